@@ -0,0 +1,115 @@
+//! the serial console the kernel starts out with, from the ACPI tables.
+//!
+//! SPCR names the console firmware itself used. DBG2 only lists debug ports, so it's the
+//! fallback.
+
+use log::{info, warn};
+use mars_acpi_driver::acpi::{
+    GenericAddress,
+    dbg2::{DBG2_PORT_SERIAL, Dbg2},
+    header::SdtHeader,
+    spcr::{SerialInterface, Spcr},
+    xsdp::{Xsdp, XsdtIter},
+};
+use protocol::{SerialConsole, SerialKind, SerialLine};
+use uefi::{system, table::cfg::ConfigTableEntry};
+use zerocopy::FromBytes;
+
+/// GAS address space of memory mapped registers
+const GAS_SYSTEM_MEMORY: u8 = 0;
+
+pub fn find_serial_console() -> SerialConsole {
+    let console = system::with_config_table(|entries| {
+        let xsdp = entries
+            .iter()
+            .find(|entry| entry.guid == ConfigTableEntry::ACPI2_GUID)?;
+        let xsdt = Xsdp::try_from_addr(xsdp.address as usize)
+            .and_then(|xsdp| xsdp.xsdt())
+            .inspect_err(|e| warn!("ACPI tables unusable: {}", e))
+            .ok()?;
+
+        let find = |sig: &[u8; 4]| {
+            XsdtIter::new(xsdt).find(|table| {
+                SdtHeader::ref_from_prefix(table)
+                    .is_ok_and(|(header, _)| &header.sig() == sig && header.check().is_ok())
+            })
+        };
+
+        find(b"SPCR")
+            .and_then(from_spcr)
+            .or_else(|| find(b"DBG2").and_then(from_dbg2))
+    });
+
+    match console {
+        Some(console) => {
+            info!("console: {:?}", console);
+            console
+        }
+        None => {
+            info!("no console in SPCR or DBG2, assuming QEMU virt");
+            SerialConsole::QEMU_VIRT
+        }
+    }
+}
+
+/// what kind of UART an interface type is, given its registers
+fn kind(interface: SerialInterface, registers: &GenericAddress) -> Option<SerialKind> {
+    if registers.address_space_id() != GAS_SYSTEM_MEMORY {
+        warn!("console registers aren't memory mapped");
+        return None;
+    }
+
+    Some(match interface {
+        SerialInterface::Pl011 => SerialKind::Pl011,
+        SerialInterface::SbsaGeneric | SerialInterface::SbsaGeneric32 => SerialKind::SbsaGeneric,
+        SerialInterface::Ns16550 | SerialInterface::Ns16550Subset => {
+            SerialKind::Ns16550 { register_width: 1 }
+        }
+        SerialInterface::Ns16550Gas => SerialKind::Ns16550 {
+            register_width: match registers.register_bit_width() {
+                32 => 4,
+                _ => 1,
+            },
+        },
+        SerialInterface::Other(other) => {
+            warn!("unsupported console interface type {:#x}", other);
+            return None;
+        }
+    })
+}
+
+fn from_spcr(table: &[u8]) -> Option<SerialConsole> {
+    let (spcr, _) = Spcr::ref_from_prefix(table).ok()?;
+    let registers = spcr.base_addr();
+
+    // SPCR can only describe 8N1. anything else is reserved, so leave the line alone.
+    let standard_format = spcr.parity() == 0 && spcr.stop_bits() == 1;
+    let line = spcr
+        .baud()
+        .zip(spcr.uart_clock())
+        .filter(|_| standard_format)
+        .map(|(baud, clock)| SerialLine { baud, clock });
+
+    Some(SerialConsole {
+        kind: kind(spcr.interface(), &registers)?,
+        address: registers.address() as usize,
+        line,
+    })
+}
+
+fn from_dbg2(table: &[u8]) -> Option<SerialConsole> {
+    let (dbg2, _) = Dbg2::ref_from_prefix(table).ok()?;
+
+    dbg2.devices(table)
+        .filter(|(info, _)| info.port_type() == DBG2_PORT_SERIAL)
+        .find_map(|(info, bytes)| {
+            let registers = info.base_addresses(bytes).next()?;
+
+            Some(SerialConsole {
+                kind: kind(SerialInterface::from(info.port_subtype()), registers)?,
+                address: registers.address() as usize,
+                // DBG2 has no line settings
+                line: None,
+            })
+        })
+}
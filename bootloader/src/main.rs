@@ -6,6 +6,7 @@
 extern crate alloc;
 
 mod allocator;
+mod console;
 mod elf;
 mod page;
 
@@ -21,18 +22,22 @@ use klib::{
     vm::{MAIR_DEVICE_INDEX, MAIR_NORMAL_INDEX, PAGE_SIZE, align_down, align_up},
 };
 use log::{debug, error, info};
-use protocol::BootInfo;
+use protocol::{BootInfo, RNG_SEED_LEN};
 use uefi::{
     CStr16, Status,
     allocator::Allocator,
     boot::{self},
     entry,
-    proto::media::file::{File, FileAttribute, FileMode},
+    proto::{
+        media::file::{File, FileAttribute, FileMode},
+        rng::Rng,
+    },
 };
 use uefi_raw::table::system::SystemTable;
 
 use crate::{
     allocator::UefiTableAlloc,
+    console::find_serial_console,
     elf::load_kernel,
     page::{UefiAddressTranslator, cpu_init, drop_to_el1, mmu_init, mmu_init_post_exit},
 };
@@ -203,6 +208,9 @@ fn main() -> Status {
 
     mmu_init(root_ttbr1.as_ptr());
 
+    let rng_seed = rng_seed();
+    let serial_console = find_serial_console();
+
     let mut boot_info = MaybeUninit::<BootInfo>::uninit();
 
     let mem_map_final = unsafe { boot::exit_boot_services(None) };
@@ -214,11 +222,30 @@ fn main() -> Status {
     boot_info.write(BootInfo {
         kernel_load_physical_address: base_phys as usize,
         kernel_size: load_size as usize,
-        serial_uart_address: 0x0900_0000,
+        serial_console,
         memory_map: mem_map_final,
         page_table_root: Some(root_ttbr0.as_ptr()),
         system_table_raw: st,
+        device_tree: None,
+        rng_seed,
     });
 
     unsafe { drop_to_el1(entry_vaddr, boot_info.as_mut_ptr() as usize) }
 }
+
+/// seed for the kernel's entropy pool, while boot services are still around
+fn rng_seed() -> Option<[u8; RNG_SEED_LEN]> {
+    let handle = boot::get_handle_for_protocol::<Rng>()
+        .inspect_err(|_| info!("no EFI_RNG_PROTOCOL"))
+        .ok()?;
+    let mut rng = boot::open_protocol_exclusive::<Rng>(handle).ok()?;
+
+    let mut seed = [0u8; RNG_SEED_LEN];
+    match rng.get_rng(None, &mut seed) {
+        Ok(()) => Some(seed),
+        Err(e) => {
+            error!("EFI_RNG_PROTOCOL failed: {:?}", e);
+            None
+        }
+    }
+}
@@ -8,6 +8,21 @@ use alloc::{
 
 use crate::parser::AmlParser;
 
+/// decode a compressed EISA ID like `EisaId ("PNP0A08")`. it's stored big endian: three 5 bit
+/// letters, then four hex digits of product number.
+pub fn eisa_id(id: u32) -> String {
+    let id = id.swap_bytes();
+    let letter = |shift: u32| (0x40 + ((id >> shift) & 0x1F) as u8) as char;
+
+    format!(
+        "{}{}{}{:04X}",
+        letter(26),
+        letter(21),
+        letter(16),
+        id & 0xFFFF
+    )
+}
+
 pub trait AmlValueExt {
     fn as_string(&self) -> Option<String>;
     fn as_u64(&self) -> Option<u64>;
@@ -30,19 +45,7 @@ impl<'a> AmlValueExt for AmlValue<'a> {
         match self {
             AmlValue::String(s) => Some(s.to_string()),
             AmlValue::NamePath(p) => Some(p.to_string()),
-            AmlValue::Integer(v) => {
-                let m1 = ((v >> 26) & 0x1F) as u8;
-                let m2 = ((v >> 21) & 0x1F) as u8;
-                let m3 = ((v >> 16) & 0x1F) as u8;
-                let p = (v & 0xFFFF) as u16;
-                Some(format!(
-                    "{}{}{}{:04X}",
-                    (0x40 + m1) as char,
-                    (0x40 + m2) as char,
-                    (0x40 + m3) as char,
-                    p
-                ))
-            }
+            AmlValue::Integer(v) => Some(eisa_id(*v as u32)),
             _ => None,
         }
     }
@@ -1,5 +1,41 @@
-use alloc::vec::Vec;
-use klib::hardware::resource::Resource;
+//! resource templates (`_CRS`), ACPI 6.5 section 6.4.
+
+use alloc::{string::String, vec::Vec};
+use klib::hardware::resource::{
+    AddressSpace, GpioConnection, GpioKind, Irq, IrqPolarity, IrqTrigger, Resource, SerialBus,
+    SerialBusConnection,
+};
+
+// small item names
+const SMALL_IRQ: u8 = 0x04;
+const SMALL_IO: u8 = 0x08;
+const SMALL_FIXED_IO: u8 = 0x09;
+
+// large item names
+const LARGE_MEMORY24: u8 = 0x01;
+const LARGE_GENERIC_REGISTER: u8 = 0x02;
+const LARGE_MEMORY32: u8 = 0x05;
+const LARGE_FIXED_MEMORY32: u8 = 0x06;
+const LARGE_DWORD_ADDRESS: u8 = 0x07;
+const LARGE_WORD_ADDRESS: u8 = 0x08;
+const LARGE_EXTENDED_IRQ: u8 = 0x09;
+const LARGE_QWORD_ADDRESS: u8 = 0x0A;
+const LARGE_EXTENDED_ADDRESS: u8 = 0x0B;
+const LARGE_GPIO: u8 = 0x0C;
+const LARGE_SERIAL_BUS: u8 = 0x0E;
+
+/// address space descriptor resource types
+const ADDRESS_MEMORY: u8 = 0;
+const ADDRESS_IO: u8 = 1;
+const ADDRESS_BUS_NUMBER: u8 = 2;
+
+/// generic register address space ids
+const GAS_SYSTEM_MEMORY: u8 = 0;
+const GAS_SYSTEM_IO: u8 = 1;
+
+const SERIAL_BUS_I2C: u8 = 1;
+const SERIAL_BUS_SPI: u8 = 2;
+const SERIAL_BUS_UART: u8 = 3;
 
 pub struct CrsIter<'a>(pub &'a [u8]);
 
@@ -36,86 +72,268 @@ pub trait DecodeCrs<'a> {
 }
 
 impl<'a> DecodeCrs<'a> for &[u8] {
+    /// decode one descriptor from `CrsIter`. malformed descriptors and the ones without a
+    /// `Resource` equivalent (DMA, vendor data, dependent function markers...) decode to nothing.
     fn into_rss(self) -> impl Iterator<Item = Resource> {
-        let mut mmio = None;
-        let mut irqs = Vec::new();
-
-        let tag = self[0];
-        let is_large = (tag & 0x80) != 0;
-        let item_type = if is_large {
-            tag & 0x7F
-        } else {
-            (tag >> 3) & 0x0F
-        };
-        let data = if is_large {
-            self.get(3..).unwrap_or(&[])
-        } else {
-            self.get(1..).unwrap_or(&[])
-        };
+        let mut out = Vec::new();
 
-        match (is_large, item_type) {
-            // IRQ mask
-            (false, 0x04) if data.len() >= 2 => {
-                let mask = u16::from_le_bytes([data[0], data[1]]);
-                irqs.extend(
-                    (0..16)
-                        .filter(|i| (mask & (1 << i)) != 0)
-                        .map(Resource::Irq),
-                );
+        if let Some(&tag) = self.first() {
+            if tag & 0x80 == 0 {
+                decode_small((tag >> 3) & 0x0F, &self[1..], &mut out);
+            } else {
+                decode_large(tag & 0x7F, self, &mut out);
             }
-            // 32-bit memory range
-            (true, 0x05) if data.len() >= 17 => {
-                let base = u32::from_le_bytes(data[1..5].try_into().unwrap()) as usize;
-                let len = u32::from_le_bytes(data[13..17].try_into().unwrap()) as usize;
-                if len > 0 {
-                    mmio = Some(Resource::Mmio {
-                        range: base..base + len,
-                    });
-                }
-            }
-            // 32-bit fixed memory range
-            (true, 0x06) if data.len() >= 9 => {
-                let base = u32::from_le_bytes(data[1..5].try_into().unwrap()) as usize;
-                let len = u32::from_le_bytes(data[5..9].try_into().unwrap()) as usize;
-                if len > 0 {
-                    mmio = Some(Resource::Mmio {
-                        range: base..base + len,
-                    });
-                }
-            }
-            // dword address space
-            (true, 0x07) if data.len() >= 23 && data[0] == 0 => {
-                let base = u32::from_le_bytes(data[7..11].try_into().unwrap()) as usize;
-                let len = u32::from_le_bytes(data[19..23].try_into().unwrap()) as usize;
-                if len > 0 {
-                    mmio = Some(Resource::Mmio {
-                        range: base..base + len,
-                    });
-                }
+        }
+
+        out.into_iter()
+    }
+}
+
+/// little endian integer of `len` bytes at `offset`
+fn le(data: &[u8], offset: usize, len: usize) -> Option<u64> {
+    let bytes = data.get(offset..offset + len)?;
+    Some(bytes.iter().rev().fold(0, |acc, &b| acc << 8 | b as u64))
+}
+
+fn mmio(base: u64, len: u64) -> Option<Resource> {
+    (len > 0).then(|| Resource::Mmio {
+        range: base as usize..(base + len) as usize,
+    })
+}
+
+fn io(base: u64, len: u64) -> Option<Resource> {
+    (len > 0).then(|| Resource::Io {
+        range: base as usize..(base + len) as usize,
+    })
+}
+
+/// null terminated resource source name at `offset`, if there's one
+fn source_name(desc: &[u8], offset: usize) -> Option<String> {
+    let bytes = desc.get(offset..)?;
+    let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    let name = core::str::from_utf8(&bytes[..end]).ok()?;
+    (!name.is_empty()).then(|| String::from(name))
+}
+
+fn decode_small(item: u8, data: &[u8], out: &mut Vec<Resource>) -> Option<()> {
+    match item {
+        SMALL_IRQ => {
+            let mask = le(data, 0, 2)?;
+            // without the information byte, it's edge triggered, active high and exclusive
+            let info = data.get(2).copied().unwrap_or(0x01);
+            let irq = Irq {
+                gsiv: 0,
+                trigger: if info & 0x01 != 0 {
+                    IrqTrigger::Edge
+                } else {
+                    IrqTrigger::Level
+                },
+                polarity: if info & 0x08 != 0 {
+                    IrqPolarity::ActiveLow
+                } else {
+                    IrqPolarity::ActiveHigh
+                },
+                shared: info & 0x10 != 0,
+            };
+
+            out.extend(
+                (0..16)
+                    .filter(|i| mask & (1 << i) != 0)
+                    .map(|gsiv| Resource::Irq(Irq { gsiv, ..irq })),
+            );
+        }
+        SMALL_IO => out.extend(io(le(data, 1, 2)?, *data.get(6)? as u64)),
+        // only 10 bits are decoded
+        SMALL_FIXED_IO => out.extend(io(le(data, 0, 2)? & 0x3FF, *data.get(2)? as u64)),
+        _ => {}
+    }
+
+    Some(())
+}
+
+/// `desc` is the whole descriptor, tag included, since GPIO and serial bus offsets count from it
+fn decode_large(item: u8, desc: &[u8], out: &mut Vec<Resource>) -> Option<()> {
+    let data = desc.get(3..)?;
+
+    match item {
+        // in 256 byte units
+        LARGE_MEMORY24 => out.extend(mmio(le(data, 1, 2)? << 8, le(data, 7, 2)? << 8)),
+        LARGE_MEMORY32 => out.extend(mmio(le(data, 1, 4)?, le(data, 13, 4)?)),
+        LARGE_FIXED_MEMORY32 => out.extend(mmio(le(data, 1, 4)?, le(data, 5, 4)?)),
+        LARGE_GENERIC_REGISTER => {
+            let space = *data.first()?;
+            let address = le(data, 4, 8)?;
+            let len = match *data.get(3)? {
+                // undefined access size, go by the bit width
+                0 => (*data.get(1)? as u64).div_ceil(8),
+                size => 1 << (size - 1),
+            };
+
+            match space {
+                GAS_SYSTEM_MEMORY => out.extend(mmio(address, len)),
+                GAS_SYSTEM_IO => out.extend(io(address, len)),
+                _ => {}
             }
-            // qword address space
-            (true, 0x0A) if data.len() >= 43 && data[0] == 0 => {
-                let base = u64::from_le_bytes(data[11..19].try_into().unwrap()) as usize;
-                let len = u64::from_le_bytes(data[35..43].try_into().unwrap()) as usize;
-                if len > 0 {
-                    mmio = Some(Resource::Mmio {
-                        range: base..base + len,
-                    });
-                }
+        }
+        LARGE_WORD_ADDRESS => out.extend(decode_address(data, 2, 3)),
+        LARGE_DWORD_ADDRESS => out.extend(decode_address(data, 4, 3)),
+        LARGE_QWORD_ADDRESS => out.extend(decode_address(data, 8, 3)),
+        // revision and a reserved byte come before the fields
+        LARGE_EXTENDED_ADDRESS => out.extend(decode_address(data, 8, 5)),
+        LARGE_EXTENDED_IRQ => {
+            let flags = *data.first()?;
+            let count = *data.get(1)? as usize;
+
+            // interrupts wired to some other controller than the GIC aren't GSIVs
+            if source_name(data, 2 + count * 4 + 1).is_some() {
+                return None;
             }
-            // extended irq
-            (true, 0x09) if data.len() >= 2 => {
-                let count = data[1] as usize;
-                let extracted = (0..count).filter_map(|i| {
-                    let offset = 2 + i * 4;
-                    let bytes = data.get(offset..offset + 4)?;
-                    Some(Resource::Irq(u32::from_le_bytes(bytes.try_into().unwrap())))
-                });
-                irqs.extend(extracted);
+
+            let irq = Irq {
+                gsiv: 0,
+                trigger: if flags & 0x02 != 0 {
+                    IrqTrigger::Edge
+                } else {
+                    IrqTrigger::Level
+                },
+                polarity: if flags & 0x04 != 0 {
+                    IrqPolarity::ActiveLow
+                } else {
+                    IrqPolarity::ActiveHigh
+                },
+                shared: flags & 0x08 != 0,
+            };
+
+            for i in 0..count {
+                let gsiv = le(data, 2 + i * 4, 4)? as u32;
+                out.push(Resource::Irq(Irq { gsiv, ..irq }));
             }
-            _ => {}
         }
+        LARGE_GPIO => out.push(Resource::Gpio(decode_gpio(desc)?)),
+        LARGE_SERIAL_BUS => out.push(Resource::SerialBus(decode_serial_bus(desc)?)),
+        _ => {}
+    }
+
+    Some(())
+}
+
+/// word, dword, qword and extended address space descriptors. they only differ in the size of
+/// their fields (`size` bytes, starting at `fields`).
+///
+/// resources a device consumes come out as plain `Mmio` or `Io`, as seen by the CPU. the ones it
+/// produces, like the windows of a PCI host bridge, come out as `Window`.
+fn decode_address(data: &[u8], size: usize, fields: usize) -> Option<Resource> {
+    let kind = *data.first()?;
+    let general_flags = *data.get(1)?;
+    let type_flags = *data.get(2)?;
 
-        mmio.into_iter().chain(irqs)
+    // granularity, minimum, maximum, translation offset, length
+    let field = |i: usize| le(data, fields + i * size, size);
+    let min = field(1)?;
+    let translation = field(3)?;
+    let len = field(4)?;
+
+    if len == 0 {
+        return None;
+    }
+
+    let consumer = general_flags & 0x01 != 0;
+    let space = match kind {
+        // memory attributes 0b11: prefetchable
+        ADDRESS_MEMORY => AddressSpace::Memory {
+            prefetchable: (type_flags >> 1) & 0b11 == 0b11,
+        },
+        ADDRESS_IO => AddressSpace::Io,
+        ADDRESS_BUS_NUMBER => AddressSpace::BusNumber,
+        // vendor defined
+        _ => return None,
+    };
+
+    match space {
+        AddressSpace::Memory { .. } if consumer => mmio(min.wrapping_add(translation), len),
+        AddressSpace::Io if consumer => io(min, len),
+        _ => Some(Resource::Window {
+            space,
+            range: min..min + len,
+            translation,
+        }),
     }
 }
+
+fn decode_gpio(desc: &[u8]) -> Option<GpioConnection> {
+    let connection_type = *desc.get(4)?;
+    let flags = le(desc, 7, 2)?;
+    let pin_table = le(desc, 14, 2)? as usize;
+    let name = le(desc, 17, 2)? as usize;
+
+    let kind = match connection_type {
+        0 => GpioKind::Interrupt {
+            trigger: if flags & 0x01 != 0 {
+                IrqTrigger::Edge
+            } else {
+                IrqTrigger::Level
+            },
+            polarity: match (flags >> 1) & 0b11 {
+                0 => IrqPolarity::ActiveHigh,
+                1 => IrqPolarity::ActiveLow,
+                _ => IrqPolarity::ActiveBoth,
+            },
+            wake: flags & 0x10 != 0,
+        },
+        1 => GpioKind::Io,
+        _ => return None,
+    };
+
+    let pins = desc
+        .get(pin_table..name)?
+        .chunks_exact(2)
+        .map(|pin| u16::from_le_bytes([pin[0], pin[1]]))
+        .collect();
+
+    Some(GpioConnection {
+        kind,
+        shared: flags & 0x08 != 0,
+        pins,
+        pin_config: *desc.get(9)?,
+        debounce_timeout: le(desc, 12, 2)? as u16,
+        controller: source_name(desc, name)?,
+    })
+}
+
+fn decode_serial_bus(desc: &[u8]) -> Option<SerialBusConnection> {
+    let bus_type = *desc.get(5)?;
+    let flags = le(desc, 7, 2)?;
+    let data_len = le(desc, 10, 2)? as usize;
+    let speed = le(desc, 12, 4)? as u32;
+
+    let bus = match bus_type {
+        SERIAL_BUS_I2C => SerialBus::I2c {
+            address: le(desc, 16, 2)? as u16,
+            ten_bit_address: flags & 0x01 != 0,
+            speed_hz: speed,
+        },
+        SERIAL_BUS_SPI => SerialBus::Spi {
+            chip_select: le(desc, 19, 2)? as u16,
+            chip_select_active_high: flags & 0x02 != 0,
+            speed_hz: speed,
+            data_bits: *desc.get(16)?,
+            clock_phase: *desc.get(17)?,
+            clock_polarity: *desc.get(18)?,
+        },
+        SERIAL_BUS_UART => SerialBus::Uart {
+            baud: speed,
+            // 0 means 5 bits, up to 4 for 9 bits
+            data_bits: ((flags >> 4) & 0b111) as u8 + 5,
+            stop_bits: ((flags >> 2) & 0b11) as u8,
+            parity: *desc.get(20)?,
+            flow_control: (flags & 0b11) as u8,
+        },
+        _ => return None,
+    };
+
+    Some(SerialBusConnection {
+        bus,
+        // the controller's name follows the type specific data
+        controller: source_name(desc, 12 + data_len)?,
+    })
+}
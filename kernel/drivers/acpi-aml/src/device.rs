@@ -1,70 +1,70 @@
-use alloc::{
-    format,
-    string::{String, ToString},
-    vec::Vec,
-};
-use hashbrown::HashMap;
+//! turns the devices in the ACPI namespace into `DeviceTree` nodes.
+
+use alloc::{string::String, vec::Vec};
 use klib::hardware::{
     device::{DeviceClass, DeviceId, DeviceInitPriority, DeviceTree},
     resource::Resource,
 };
+use log::{trace, warn};
 
 use crate::{
-    ast::{AmlTerm, AmlValue, AmlValueExt},
+    ast::eisa_id,
     crs::{CrsIter, DecodeCrs},
-    parser::AmlParser,
+    interpreter::Interpreter,
+    namespace,
+    object::{Object, ObjectType},
 };
 
+/// `_STA` bits, ACPI 6.5 section 6.3.7
+const STA_PRESENT: u64 = 1 << 0;
+const STA_ENABLED: u64 = 1 << 1;
+const STA_FUNCTIONING: u64 = 1 << 3;
+/// devices without `_STA` are present and working
+const STA_DEFAULT: u64 = 0x0F;
+
+/// `_HID` and `_CID` are either EISA IDs or strings
+fn device_id(obj: &Object) -> Option<String> {
+    match obj {
+        Object::Integer(v) => Some(eisa_id(*v as u32)),
+        Object::String(s) => Some(s.clone()),
+        _ => None,
+    }
+}
+
 #[derive(Default)]
-pub struct DeviceProperties<'a> {
+pub struct DeviceProperties {
     hid: Option<String>,
     cids: Vec<String>,
     uid: Option<u64>,
+    adr: Option<u64>,
     resources: Vec<Resource>,
-    sub_terms: Vec<AmlTerm<'a>>,
 }
 
-impl<'a> DeviceProperties<'a> {
-    fn from_aml(parser: &mut AmlParser<'a>) -> Result<Self, &'static str> {
-        let terms = core::iter::from_fn(|| parser.parse_next().transpose());
-
-        terms.fold(Ok(Self::default()), |acc, term_res| {
-            let mut acc = acc?;
-            match term_res? {
-                AmlTerm::Name { name, value } => {
-                    let name_str = name.to_string();
-                    if name_str.ends_with("_HID") {
-                        acc.hid = value.as_string();
-                    } else if name_str.ends_with("_CID") {
-                        match &value {
-                            AmlValue::Package(pkgs) => {
-                                for p in pkgs {
-                                    if let Some(s) = p.as_string() {
-                                        acc.cids.push(s);
-                                    }
-                                }
-                            }
-                            _ => {
-                                if let Some(s) = value.as_string() {
-                                    acc.cids.push(s);
-                                }
-                            }
-                        }
-                    } else if name_str.ends_with("_UID") {
-                        acc.uid = value.as_u64();
-                    } else if name_str.ends_with("_CRS") {
-                        if let AmlValue::Buffer(buf) = &value {
-                            acc.resources
-                                .extend(CrsIter(*buf).flat_map(|chunk| chunk.into_rss()));
-                        }
-                    } else {
-                        acc.sub_terms.push(AmlTerm::Name { name, value });
-                    }
-                }
-                other => acc.sub_terms.push(other),
+impl DeviceProperties {
+    fn evaluate(interpreter: &Interpreter, path: &str) -> Self {
+        let get = |name| evaluate_optional(interpreter, path, name);
+
+        let cids = match get("_CID") {
+            Some(Object::Package(pkg)) => pkg.lock().iter().filter_map(device_id).collect(),
+            Some(other) => device_id(&other).into_iter().collect(),
+            None => Vec::new(),
+        };
+
+        let resources = match get("_CRS") {
+            Some(Object::Buffer(buf)) => {
+                let buf = buf.lock();
+                CrsIter(&buf).flat_map(|chunk| chunk.into_rss()).collect()
             }
-            Ok(acc)
-        })
+            _ => Vec::new(),
+        };
+
+        Self {
+            hid: get("_HID").as_ref().and_then(device_id),
+            cids,
+            uid: get("_UID").and_then(|uid| uid.to_integer().ok()),
+            adr: get("_ADR").and_then(|adr| adr.as_integer()),
+            resources,
+        }
     }
 
     fn classify(&self) -> (DeviceClass, DeviceInitPriority) {
@@ -74,72 +74,85 @@ impl<'a> DeviceProperties<'a> {
             .or_else(|| self.cids.first().map(String::as_str))
             .unwrap_or("");
         match id {
-            "ARMH0011" => (DeviceClass::Uart, DeviceInitPriority::Regular),
+            "ARMH0011" | "PNP0501" => (DeviceClass::Uart, DeviceInitPriority::Regular),
             "PNP0A08" | "PNP0A03" => (DeviceClass::PciHostBridge, DeviceInitPriority::Regular),
             _ => (DeviceClass::Other, DeviceInitPriority::Regular),
         }
     }
 }
 
+/// the value of the child `name` of `path`, or `None` if there's no such object or it fails
+fn evaluate_optional(interpreter: &Interpreter, path: &str, name: &str) -> Option<Object> {
+    let path = namespace::child(path, name);
+    if !interpreter.contains(&path) {
+        return None;
+    }
+
+    interpreter
+        .evaluate(&path, Vec::new())
+        .inspect_err(|e| warn!("AML: evaluating {path} failed: {e}"))
+        .ok()
+}
+
 pub struct TreeBuilder<'a> {
     tree: &'a mut DeviceTree,
-    scope_map: HashMap<String, DeviceId>,
+    interpreter: &'a Interpreter,
 }
 
 impl<'a> TreeBuilder<'a> {
-    pub fn new(tree: &'a mut DeviceTree) -> Self {
-        Self {
-            tree,
-            scope_map: HashMap::new(),
-        }
+    pub fn new(tree: &'a mut DeviceTree, interpreter: &'a Interpreter) -> Self {
+        Self { tree, interpreter }
     }
 
-    pub fn join_path(parent: &str, child: &str) -> String {
-        if child.starts_with('\\') {
-            child.to_string()
-        } else if parent.is_empty() || parent == "\\" {
-            format!("\\{}", child.trim_start_matches('.'))
-        } else {
-            format!("{}.{}", parent, child.trim_start_matches('.'))
-        }
+    /// add every device below `\_SB`
+    pub fn build(&mut self) {
+        self.process_scope("\\_SB_", None);
     }
 
-    pub fn process_terms<'b>(
-        &mut self,
-        terms: impl IntoIterator<Item = AmlTerm<'b>>,
-        current_path: &str,
-        parent_id: Option<DeviceId>,
-    ) -> Result<(), &'static str> {
-        for term in terms {
-            match term {
-                AmlTerm::Scope { name, mut contents } => {
-                    let path = Self::join_path(current_path, &name.to_string());
-                    let target_parent = self.scope_map.get(&path).copied().or(parent_id);
-                    let sub_terms = core::iter::from_fn(|| contents.parse_next().transpose())
-                        .collect::<Result<Vec<_>, _>>()?;
-                    self.process_terms(sub_terms, &path, target_parent)?;
-                }
-                AmlTerm::Device { name, mut contents } => {
-                    let path = Self::join_path(current_path, &name.to_string());
-                    let props = DeviceProperties::from_aml(&mut contents)?;
-
-                    let (class, priority) = props.classify();
-                    let compatible = props.hid.into_iter().chain(props.cids).collect();
-
-                    let dev_id = self.tree.add_device(
-                        parent_id,
-                        class,
-                        compatible,
-                        props.resources,
-                        priority,
-                    );
-
-                    self.scope_map.insert(path.clone(), dev_id);
-                    self.process_terms(props.sub_terms, &path, Some(dev_id))?;
-                }
+    fn process_scope(&mut self, path: &str, parent: Option<DeviceId>) {
+        for child in self.interpreter.children(path) {
+            match self.interpreter.object_type(&child) {
+                Some(ObjectType::Device) => self.process_device(&child, parent),
+                // plain `Scope`s
+                Some(ObjectType::Uninitialized) => self.process_scope(&child, parent),
                 _ => {}
             }
         }
-        Ok(())
+    }
+
+    fn process_device(&mut self, path: &str, parent: Option<DeviceId>) {
+        let status = evaluate_optional(self.interpreter, path, "_STA")
+            .and_then(|sta| sta.as_integer())
+            .unwrap_or(STA_DEFAULT);
+
+        if status & STA_PRESENT == 0 {
+            // children of an absent device may still be there, if it says it's functioning
+            if status & STA_FUNCTIONING != 0 {
+                self.process_scope(path, parent);
+            }
+            return;
+        }
+
+        if status & STA_ENABLED == 0 {
+            trace!("AML: {path} is disabled");
+            return;
+        }
+
+        let props = DeviceProperties::evaluate(self.interpreter, path);
+        let (class, priority) = props.classify();
+        let compatible: Vec<String> = props.hid.into_iter().chain(props.cids).collect();
+
+        trace!("AML: {path} is {compatible:?}");
+
+        let id = self
+            .tree
+            .add_device(parent, class, compatible, props.resources, priority);
+        if let Some(node) = self.tree.get_mut(id) {
+            node.uid = props.uid;
+            node.bus_address = props.adr;
+            node.firmware_path = Some(String::from(path));
+        }
+
+        self.process_scope(path, Some(id));
     }
 }
@@ -0,0 +1,164 @@
+//! ACPI generic event device: interrupts whose meaning is in AML. each one runs the device's
+//! `_EVT` with the interrupt number, which usually ends in a `Notify`.
+//!
+//! AML can't run in IRQ context, so the interrupt is masked and handed to a thread, which
+//! unmasks it once `_EVT` is done.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use alloc::{
+    boxed::Box, collections::btree_map::BTreeMap, string::String, sync::Arc, vec, vec::Vec,
+};
+use klib::{
+    hardware::{
+        device::{Device, DeviceNode},
+        driver::{DriverDescriptor, DriverError},
+        resource::{Irq, Resource},
+    },
+    interrupt::{InterruptError, route_spi, singleton::get_interrupt_controller},
+    scheduler::GLOBAL_SCHEDULER,
+    stack::Stack,
+    sync::{RwLock, WaitQueue},
+    thread::Thread,
+};
+
+use crate::{interpreter::interpreter, namespace, object::Object};
+
+pub static GED_DRIVER: DriverDescriptor = DriverDescriptor {
+    name: "ged",
+    compatible: &["ACPI0013"],
+    probe,
+};
+
+const STACK_SIZE: usize = 64 * 1024;
+
+struct GedIrq {
+    /// the device's `_EVT`
+    method: String,
+    pending: AtomicBool,
+}
+
+/// by GSIV
+static IRQS: RwLock<BTreeMap<u32, GedIrq>> = RwLock::new(BTreeMap::new());
+
+static GED_WAIT: WaitQueue<'static> = WaitQueue::new();
+static STARTED: AtomicBool = AtomicBool::new(false);
+
+/// start the thread that runs `_EVT`. interrupts that came before wait for it.
+pub fn start() {
+    if IRQS.read().is_empty() || STARTED.swap(true, Ordering::AcqRel) {
+        return;
+    }
+
+    let stack = Stack::new(STACK_SIZE, 16).expect("unable to allocate GED stack");
+    let thread = Arc::new(Thread::new_kernel(stack, ged_thread as *const (), 1));
+    GLOBAL_SCHEDULER.spawn(thread);
+}
+
+fn ged_thread() -> ! {
+    use log::*;
+
+    loop {
+        let seen = GED_WAIT.generation();
+
+        let pending = IRQS
+            .read()
+            .iter()
+            .filter(|(_, irq)| irq.pending.swap(false, Ordering::AcqRel))
+            .map(|(&gsiv, irq)| (gsiv, irq.method.clone()))
+            .collect::<Vec<_>>();
+
+        for (gsiv, method) in pending {
+            trace!("ged: {method} ({gsiv})");
+
+            match interpreter() {
+                Some(interpreter) => {
+                    if let Err(e) =
+                        interpreter.evaluate(&method, vec![Object::Integer(gsiv as u64)])
+                    {
+                        warn!("ged: {method} failed: {e}");
+                    }
+                }
+                None => warn!("ged: IRQ {gsiv} without an AML interpreter"),
+            }
+
+            _ = get_interrupt_controller().enable_interrupt(gsiv);
+        }
+
+        GED_WAIT.wait(&GLOBAL_SCHEDULER, seen);
+    }
+}
+
+fn dispatch(gsiv: u32) -> Result<(), InterruptError> {
+    let irqs = IRQS.read();
+    let irq = irqs.get(&gsiv).ok_or(InterruptError::HandlerNotFound)?;
+
+    // a level triggered line stays up until `_EVT` clears it
+    get_interrupt_controller().disable_interrupt(gsiv)?;
+    irq.pending.store(true, Ordering::Release);
+    GED_WAIT.wake_all(&GLOBAL_SCHEDULER);
+
+    Ok(())
+}
+
+fn route_interrupt(irq: Irq, method: &str) -> Result<(), InterruptError> {
+    IRQS.write().insert(
+        irq.gsiv,
+        GedIrq {
+            method: String::from(method),
+            pending: AtomicBool::new(false),
+        },
+    );
+
+    route_spi(irq.gsiv, irq.trigger, dispatch)
+}
+
+struct GedDevice {
+    irqs: Vec<u32>,
+}
+
+impl Device for GedDevice {
+    fn shutdown(&self) {
+        for irq in &self.irqs {
+            _ = get_interrupt_controller().disable_interrupt(*irq);
+        }
+    }
+}
+
+fn probe(node: &DeviceNode) -> Result<Box<dyn Device>, DriverError> {
+    use log::*;
+
+    let path = node
+        .firmware_path
+        .as_deref()
+        .ok_or(DriverError::MissingResources)?;
+
+    let method = namespace::child(path, "_EVT");
+    if !interpreter().is_some_and(|interpreter| interpreter.contains(&method)) {
+        warn!("ged: {path} has no _EVT");
+        return Err(DriverError::MissingResources);
+    }
+
+    let irqs = node
+        .resources
+        .iter()
+        .filter_map(|r| match r {
+            Resource::Irq(irq) => Some(*irq),
+            _ => None,
+        })
+        .filter(|irq| {
+            route_interrupt(*irq, &method)
+                .inspect_err(|e| warn!("ged: unable to route IRQ {}: {e:?}", irq.gsiv))
+                .is_ok()
+        })
+        .map(|irq| irq.gsiv)
+        .collect::<Vec<_>>();
+
+    if irqs.is_empty() {
+        return Err(DriverError::MissingResources);
+    }
+
+    info!("ged: {path}, IRQs {irqs:?}");
+
+    Ok(Box::new(GedDevice { irqs }))
+}
@@ -0,0 +1,54 @@
+//! what the interpreter needs from the kernel: hardware access and time.
+
+use core::fmt;
+
+/// the function a `PCI_Config` region lives in, from `_SEG`, `_BBN` and `_ADR`
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PciAddress {
+    pub segment: u16,
+    pub bus: u8,
+    pub device: u8,
+    pub function: u8,
+}
+
+impl fmt::Display for PciAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:04x}:{:02x}:{:02x}.{}",
+            self.segment, self.bus, self.device, self.function
+        )
+    }
+}
+
+/// access widths are in bits: 8, 16, 32 or 64.
+///
+/// the interpreter never holds its namespace lock while calling into a handler, so handlers are
+/// free to evaluate AML themselves.
+pub trait AmlHandler: Send + Sync {
+    fn read_memory(&self, address: u64, width: u8) -> Result<u64, &'static str>;
+    fn write_memory(&self, address: u64, width: u8, value: u64) -> Result<(), &'static str>;
+
+    fn read_pci(&self, address: PciAddress, offset: u16, width: u8) -> Result<u64, &'static str>;
+    fn write_pci(
+        &self,
+        address: PciAddress,
+        offset: u16,
+        width: u8,
+        value: u64,
+    ) -> Result<(), &'static str>;
+
+    /// busy wait
+    fn stall(&self, us: u64);
+
+    /// may yield the CPU
+    fn sleep(&self, ms: u64);
+
+    /// monotonic time in 100ns units, for `Timer`
+    fn timer(&self) -> u64;
+
+    /// `Notify (path, value)`
+    fn notify(&self, path: &str, value: u64) {
+        log::debug!("AML: Notify ({path}, {value:#x}) ignored");
+    }
+}
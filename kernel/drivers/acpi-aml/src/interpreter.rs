@@ -0,0 +1,2036 @@
+//! the AML interpreter, see chapter 20 of the ACPI 6.5 specification.
+//!
+//! AML runs straight off the byte stream. loading a table executes its top level term list to
+//! populate the namespace, and [`Interpreter::evaluate`] executes method bodies. the namespace
+//! lock is only held for single lookups and stores, never across a method call or a region
+//! access, so handlers and other CPUs may evaluate while a method is running.
+
+use core::{
+    cmp::Ordering as CmpOrdering,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+};
+
+use alloc::{
+    boxed::Box,
+    format,
+    string::{String, ToString},
+    sync::Arc,
+    vec,
+    vec::Vec,
+};
+use klib::sync::{RwLock, UnfairSpinlock, UnfairSpinlockGuard};
+use log::{debug, error, warn};
+
+use crate::{
+    ast::AmlNamePath,
+    handler::{AmlHandler, PciAddress},
+    namespace::{self, Namespace, ROOT},
+    object::{
+        AmlMutex, BufferField, FieldAccess, FieldKind, FieldUnit, Method, Object, ObjectType,
+        OpRegion, Reference, RegionSpace, UpdateRule, get_bits, set_bits,
+    },
+    parser::AmlParser,
+};
+
+/// nested method invocations before evaluation is aborted
+const MAX_CALL_DEPTH: usize = 32;
+
+/// iterations a single `While` may run, so broken firmware can't hang the kernel
+const MAX_LOOP_ITERATIONS: usize = 0x10_0000;
+
+/// largest buffer or package AML may create
+const MAX_OBJECT_LEN: usize = 0x10_0000;
+
+/// what `Revision` returns
+const INTERPRETER_REVISION: u64 = 1;
+
+const SDT_HEADER_LEN: usize = 36;
+
+/// `Acquire` timeout meaning "forever"
+const WAIT_FOREVER: u64 = 0xFFFF;
+
+/// strings `\_OSI` answers true for. like every other OS we claim to be Windows, since that's
+/// what firmware is tested against.
+const OSI_SUPPORTED: &[&str] = &[
+    "Windows 2000",
+    "Windows 2001",
+    "Windows 2001 SP1",
+    "Windows 2001.1",
+    "Windows 2001 SP2",
+    "Windows 2001.1 SP1",
+    "Windows 2006",
+    "Windows 2006.1",
+    "Windows 2006 SP1",
+    "Windows 2006 SP2",
+    "Windows 2009",
+    "Windows 2012",
+    "Windows 2013",
+    "Windows 2015",
+    "Windows 2016",
+    "Windows 2017",
+    "Windows 2017.2",
+    "Windows 2018",
+    "Windows 2018.2",
+    "Windows 2019",
+    "Windows 2020",
+    "Windows 2021",
+    "Windows 2022",
+    "Module Device",
+    "Processor Device",
+    "3.0 Thermal Model",
+    "3.0 _SCP Extensions",
+    "Processor Aggregator Device",
+    "Extended Address Space Descriptor",
+];
+
+static INTERPRETER: RwLock<Option<&'static Interpreter>> = RwLock::new(None);
+
+/// make the interpreter reachable by drivers once the DSDT is loaded
+pub fn register_interpreter(interpreter: Interpreter) -> &'static Interpreter {
+    let interpreter: &'static Interpreter = Box::leak(Box::new(interpreter));
+    *INTERPRETER.write() = Some(interpreter);
+    interpreter
+}
+
+pub fn interpreter() -> Option<&'static Interpreter> {
+    *INTERPRETER.read()
+}
+
+pub struct Interpreter {
+    namespace: UnfairSpinlock<Namespace>,
+    handler: &'static dyn AmlHandler,
+    /// revision 1 DSDTs use 32 bit integers
+    int64: AtomicBool,
+    /// evaluation ids, which own AML mutexes. 0 means unowned.
+    next_owner: AtomicU64,
+}
+
+impl Interpreter {
+    pub fn new(handler: &'static dyn AmlHandler) -> Self {
+        let mut ns = Namespace::new();
+
+        let predefined = [
+            ("\\_OSI", Object::Method(Method::native(1, osi))),
+            (
+                "\\_OS_",
+                Object::String(String::from("Microsoft Windows NT")),
+            ),
+            ("\\_REV", Object::Integer(2)),
+            ("\\_GL_", Object::Mutex(Arc::new(AmlMutex::new(0)))),
+        ];
+        for (path, obj) in predefined {
+            ns.insert(String::from(path), obj)
+                .expect("predefined AML object clashes");
+        }
+
+        Self {
+            namespace: UnfairSpinlock::new(ns),
+            handler,
+            int64: AtomicBool::new(true),
+            next_owner: AtomicU64::new(1),
+        }
+    }
+
+    /// run the term list of a definition block (DSDT or SSDT), header included
+    pub fn load_table(&self, table: &[u8]) -> Result<(), &'static str> {
+        let aml = table
+            .get(SDT_HEADER_LEN..)
+            .ok_or("definition block shorter than its header")?;
+
+        if &table[0..4] == b"DSDT" && table[8] < 2 {
+            self.int64.store(false, Ordering::Relaxed);
+        }
+
+        let mut ctx = self.context();
+        let mut frame = Frame::table();
+
+        match ctx.exec_term_list(&mut frame, &mut AmlParser::new(aml))? {
+            Flow::Next => Ok(()),
+            _ => Err("control flow statement at the top level of a table"),
+        }
+    }
+
+    /// evaluate the object at `path`, e.g. `\_SB.PCI0._CRS`. methods are invoked with `args`,
+    /// fields are read, anything else is returned as is.
+    pub fn evaluate(&self, path: &str, args: Vec<Object>) -> Result<Object, &'static str> {
+        let path = namespace::normalize(path)?;
+        self.context()
+            .evaluate_path(&path, args)?
+            .ok_or("AML object not found")
+    }
+
+    pub fn contains(&self, path: &str) -> bool {
+        namespace::normalize(path).is_ok_and(|path| self.namespace.lock().contains(&path))
+    }
+
+    pub fn object_type(&self, path: &str) -> Option<ObjectType> {
+        let path = namespace::normalize(path).ok()?;
+        self.namespace
+            .lock()
+            .get(&path)
+            .map(|(_, obj)| obj.object_type())
+    }
+
+    /// absolute paths of the direct children of `path`
+    pub fn children(&self, path: &str) -> Vec<String> {
+        let Ok(path) = namespace::normalize(path) else {
+            return Vec::new();
+        };
+
+        self.namespace
+            .lock()
+            .children(&path)
+            .map(|(child, _)| child.to_string())
+            .collect()
+    }
+
+    pub fn object_count(&self) -> usize {
+        self.namespace.lock().len()
+    }
+
+    fn context(&self) -> Context<'_> {
+        Context {
+            interp: self,
+            owner: self.next_owner.fetch_add(1, Ordering::Relaxed),
+            depth: 0,
+        }
+    }
+}
+
+fn osi(args: &[Object]) -> Result<Object, &'static str> {
+    let Some(Object::String(s)) = args.first() else {
+        return Err("_OSI takes a string");
+    };
+
+    let supported = OSI_SUPPORTED.contains(&s.as_str());
+    debug!("AML: _OSI (\"{s}\") = {supported}");
+
+    Ok(Object::Integer(if supported { u64::MAX } else { 0 }))
+}
+
+/// how a term list finished
+enum Flow {
+    Next,
+    Return(Object),
+    Break,
+    Continue,
+}
+
+/// where a result goes
+enum Target {
+    Null,
+    Local(usize),
+    Arg(usize),
+    Debug,
+    Name(String),
+    Ref(Reference),
+}
+
+/// state of a running method, or of a table being loaded
+struct Frame {
+    scope: String,
+    args: [Object; 7],
+    locals: [Object; 8],
+    /// named objects the method created, removed when it returns
+    temporaries: Vec<String>,
+    in_method: bool,
+}
+
+impl Frame {
+    fn table() -> Self {
+        Self {
+            scope: String::from(ROOT),
+            args: Default::default(),
+            locals: Default::default(),
+            temporaries: Vec::new(),
+            in_method: false,
+        }
+    }
+
+    fn method(scope: String, args: Vec<Object>) -> Self {
+        let mut frame = Self {
+            scope,
+            in_method: true,
+            ..Self::table()
+        };
+
+        for (slot, arg) in frame.args.iter_mut().zip(args) {
+            *slot = arg;
+        }
+
+        frame
+    }
+}
+
+/// one top level evaluation and everything it calls
+struct Context<'i> {
+    interp: &'i Interpreter,
+    owner: u64,
+    depth: usize,
+}
+
+fn is_name_lead(b: u8) -> bool {
+    matches!(b, b'\\' | b'^' | 0x2E | 0x2F | b'A'..=b'Z' | b'_')
+}
+
+/// a run of `n` low bits
+fn low_bits(n: u64) -> u64 {
+    if n >= 64 { u64::MAX } else { (1 << n) - 1 }
+}
+
+/// the rest of a `PkgLength` delimited object that started at `start`
+fn pkg_body<'a>(p: &mut AmlParser<'a>, start: usize, len: usize) -> Result<&'a [u8], &'static str> {
+    let consumed = p.position() - start;
+    let body_len = len.checked_sub(consumed).ok_or("PkgLength underflowed")?;
+    p.take_bytes(body_len)
+}
+
+impl<'i> Context<'i> {
+    fn ns(&self) -> UnfairSpinlockGuard<'i, Namespace> {
+        self.interp.namespace.lock()
+    }
+
+    fn handler(&self) -> &'static dyn AmlHandler {
+        self.interp.handler
+    }
+
+    fn int_bytes(&self) -> usize {
+        if self.interp.int64.load(Ordering::Relaxed) {
+            8
+        } else {
+            4
+        }
+    }
+
+    fn mask(&self, v: u64) -> u64 {
+        v & low_bits(self.int_bytes() as u64 * 8)
+    }
+
+    fn ones(&self) -> u64 {
+        self.mask(u64::MAX)
+    }
+
+    fn boolean(&self, b: bool) -> Object {
+        Object::Integer(if b { self.ones() } else { 0 })
+    }
+
+    fn search(&self, frame: &Frame, name: &AmlNamePath) -> Result<Option<String>, &'static str> {
+        self.ns().search(&frame.scope, name)
+    }
+
+    /// object at `path` with aliases resolved, and the path they resolved to
+    fn get(&self, path: &str) -> Option<(String, Object)> {
+        self.ns()
+            .get(path)
+            .map(|(path, obj)| (path.to_string(), obj.clone()))
+    }
+
+    fn create(
+        &mut self,
+        frame: &mut Frame,
+        name: &AmlNamePath,
+        obj: Object,
+    ) -> Result<String, &'static str> {
+        let path = namespace::resolve(&frame.scope, name)?;
+
+        if let Err(e) = self.ns().insert(path.clone(), obj) {
+            // tables loaded after the DSDT sometimes redefine what it already has. like ACPICA,
+            // keep the first definition and carry on.
+            if !frame.in_method {
+                warn!("AML: {path} is defined twice, keeping the first definition");
+                return Ok(path);
+            }
+
+            warn!("AML: can't create {path}: {e}");
+            return Err(e);
+        }
+
+        if frame.in_method {
+            frame.temporaries.push(path.clone());
+        }
+
+        Ok(path)
+    }
+
+    // --- evaluation entry points ---
+
+    /// `None` if nothing is at `path`
+    fn evaluate_path(
+        &mut self,
+        path: &str,
+        args: Vec<Object>,
+    ) -> Result<Option<Object>, &'static str> {
+        let Some((path, obj)) = self.get(path) else {
+            return Ok(None);
+        };
+
+        let value = match obj {
+            Object::Method(method) => self.invoke(&path, &method, args)?,
+            other => self.read_value(other)?,
+        };
+
+        Ok(Some(value))
+    }
+
+    fn invoke(
+        &mut self,
+        path: &str,
+        method: &Method,
+        args: Vec<Object>,
+    ) -> Result<Object, &'static str> {
+        if let Some(native) = method.native {
+            return native(&args);
+        }
+
+        if self.depth >= MAX_CALL_DEPTH {
+            error!("AML: call depth exceeded invoking {path}");
+            return Err("AML call depth exceeded");
+        }
+
+        if let Some(mutex) = &method.serialize {
+            self.acquire(mutex, WAIT_FOREVER);
+        }
+
+        let mut frame = Frame::method(path.to_string(), args);
+
+        self.depth += 1;
+        let code = method.code.clone();
+        let res = self.exec_term_list(&mut frame, &mut AmlParser::new(&code));
+        self.depth -= 1;
+
+        {
+            let mut ns = self.ns();
+            for temp in frame.temporaries.iter().rev() {
+                ns.remove(temp);
+            }
+        }
+
+        if let Some(mutex) = &method.serialize {
+            mutex.release(self.owner)?;
+        }
+
+        match res {
+            Ok(Flow::Return(value)) => Ok(value),
+            Ok(Flow::Next) => Ok(Object::Uninitialized),
+            Ok(_) => Err("Break or Continue outside of a While"),
+            Err(e) => {
+                warn!("AML: {path} failed: {e}");
+                Err(e)
+            }
+        }
+    }
+
+    // --- statements ---
+
+    fn exec_term_list(
+        &mut self,
+        frame: &mut Frame,
+        p: &mut AmlParser,
+    ) -> Result<Flow, &'static str> {
+        while !p.is_empty() {
+            match self.exec_term(frame, p)? {
+                Flow::Next => {}
+                flow => return Ok(flow),
+            }
+        }
+
+        Ok(Flow::Next)
+    }
+
+    fn exec_term(&mut self, frame: &mut Frame, p: &mut AmlParser) -> Result<Flow, &'static str> {
+        let op = p.peek_u8().ok_or("empty term")?;
+        let ext = p.remaining_bytes().get(1).copied().unwrap_or(0);
+
+        let statement = match op {
+            0x06
+            | 0x08
+            | 0x10
+            | 0x14
+            | 0x15
+            | 0x86
+            | 0x8A..=0x8D
+            | 0x8F
+            | 0x9F
+            | 0xA0..=0xA5
+            | 0xCC => true,
+            0x5B => matches!(
+                ext,
+                0x01 | 0x02 | 0x13 | 0x21 | 0x22 | 0x24 | 0x26 | 0x27 | 0x32 | 0x80..=0x88
+            ),
+            _ => false,
+        };
+
+        if !statement {
+            self.eval(frame, p)?;
+            return Ok(Flow::Next);
+        }
+
+        p.read_u8()?;
+        if op == 0x5B {
+            p.read_u8()?;
+            return self.exec_ext_statement(frame, p, ext).map(|_| Flow::Next);
+        }
+
+        match op {
+            // AliasOp
+            0x06 => {
+                let source = p.read_name_path()?;
+                let alias = p.read_name_path()?;
+                let target = match self.search(frame, &source)? {
+                    Some(path) => path,
+                    None => namespace::resolve(&frame.scope, &source)?,
+                };
+                self.create(frame, &alias, Object::Alias(target))?;
+            }
+            // NameOp
+            0x08 => {
+                let name = p.read_name_path()?;
+                let value = self.eval(frame, p)?;
+                self.create(frame, &name, value)?;
+            }
+            // ScopeOp
+            0x10 => {
+                let start = p.position();
+                let len = p.read_pkg_length()?;
+                let name = p.read_name_path()?;
+                let body = pkg_body(p, start, len)?;
+
+                let path = namespace::resolve(&frame.scope, &name)?;
+                if !self.ns().contains(&path) {
+                    self.create(frame, &name, Object::Scope)?;
+                }
+                self.exec_scoped(frame, path, body)?;
+            }
+            // MethodOp
+            0x14 => {
+                let start = p.position();
+                let len = p.read_pkg_length()?;
+                let name = p.read_name_path()?;
+                let flags = p.read_u8()?;
+                let code = pkg_body(p, start, len)?;
+
+                let method = Method::from_flags(Arc::from(code), flags);
+                self.create(frame, &name, Object::Method(method))?;
+            }
+            // ExternalOp: only a hint for disassemblers
+            0x15 => {
+                p.read_name_path()?;
+                p.read_u8()?;
+                p.read_u8()?;
+            }
+            // NotifyOp
+            0x86 => {
+                let target = self.target(frame, p)?;
+                let value = self.eval_integer(frame, p)?;
+                match target {
+                    Target::Name(path) => self.handler().notify(&path, value),
+                    Target::Ref(Reference::Name(path)) => self.handler().notify(&path, value),
+                    _ => return Err("Notify target isn't a named object"),
+                }
+            }
+            // CreateDWordField, CreateWordField, CreateByteField, CreateBitField, CreateQWordField
+            0x8A | 0x8B | 0x8C | 0x8D | 0x8F => {
+                let buffer = self.eval(frame, p)?;
+                let index = self.eval_integer(frame, p)? as usize;
+                let name = p.read_name_path()?;
+
+                let (bit_offset, bit_len) = match op {
+                    0x8A => (index * 8, 32),
+                    0x8B => (index * 8, 16),
+                    0x8C => (index * 8, 8),
+                    0x8D => (index, 1),
+                    _ => (index * 8, 64),
+                };
+                self.create_buffer_field(frame, buffer, bit_offset, bit_len, &name)?;
+            }
+            // ContinueOp
+            0x9F => return Ok(Flow::Continue),
+            // IfOp
+            0xA0 => {
+                let start = p.position();
+                let len = p.read_pkg_length()?;
+                let body = pkg_body(p, start, len)?;
+
+                let mut inner = AmlParser::new(body);
+                let predicate = self.eval_integer(frame, &mut inner)? != 0;
+
+                let else_body = if p.peek_u8() == Some(0xA1) {
+                    p.read_u8()?;
+                    let start = p.position();
+                    let len = p.read_pkg_length()?;
+                    Some(pkg_body(p, start, len)?)
+                } else {
+                    None
+                };
+
+                if predicate {
+                    return self.exec_term_list(frame, &mut inner);
+                } else if let Some(body) = else_body {
+                    return self.exec_term_list(frame, &mut AmlParser::new(body));
+                }
+            }
+            // ElseOp without an If, skip it
+            0xA1 => {
+                let start = p.position();
+                let len = p.read_pkg_length()?;
+                pkg_body(p, start, len)?;
+            }
+            // WhileOp
+            0xA2 => {
+                let start = p.position();
+                let len = p.read_pkg_length()?;
+                let body = pkg_body(p, start, len)?;
+
+                for _ in 0..MAX_LOOP_ITERATIONS {
+                    let mut inner = AmlParser::new(body);
+                    if self.eval_integer(frame, &mut inner)? == 0 {
+                        return Ok(Flow::Next);
+                    }
+
+                    match self.exec_term_list(frame, &mut inner)? {
+                        Flow::Break => return Ok(Flow::Next),
+                        Flow::Return(value) => return Ok(Flow::Return(value)),
+                        Flow::Next | Flow::Continue => {}
+                    }
+                }
+
+                error!("AML: While in {} exceeded the iteration limit", frame.scope);
+                return Err("AML While exceeded the iteration limit");
+            }
+            // NoopOp
+            0xA3 => {}
+            // ReturnOp
+            0xA4 => {
+                let value = self.eval(frame, p)?;
+                return Ok(Flow::Return(value));
+            }
+            // BreakOp
+            0xA5 => return Ok(Flow::Break),
+            // BreakPointOp
+            0xCC => {}
+            _ => unreachable!(),
+        }
+
+        Ok(Flow::Next)
+    }
+
+    fn exec_ext_statement(
+        &mut self,
+        frame: &mut Frame,
+        p: &mut AmlParser,
+        ext: u8,
+    ) -> Result<(), &'static str> {
+        match ext {
+            // MutexOp
+            0x01 => {
+                let name = p.read_name_path()?;
+                let flags = p.read_u8()?;
+                let mutex = Arc::new(AmlMutex::new(flags & 0x0F));
+                self.create(frame, &name, Object::Mutex(mutex))?;
+            }
+            // EventOp
+            0x02 => {
+                let name = p.read_name_path()?;
+                self.create(frame, &name, Object::Event(Arc::new(AtomicU64::new(0))))?;
+            }
+            // CreateFieldOp
+            0x13 => {
+                let buffer = self.eval(frame, p)?;
+                let bit_offset = self.eval_integer(frame, p)? as usize;
+                let bit_len = self.eval_integer(frame, p)? as usize;
+                let name = p.read_name_path()?;
+                self.create_buffer_field(frame, buffer, bit_offset, bit_len, &name)?;
+            }
+            // StallOp
+            0x21 => {
+                let us = self.eval_integer(frame, p)?;
+                self.handler().stall(us);
+            }
+            // SleepOp
+            0x22 => {
+                let ms = self.eval_integer(frame, p)?;
+                self.handler().sleep(ms);
+            }
+            // SignalOp
+            0x24 => {
+                let event = self.sync_object(frame, p)?;
+                let Object::Event(count) = event else {
+                    return Err("Signal of a non-event");
+                };
+                count.fetch_add(1, Ordering::Release);
+            }
+            // ResetOp
+            0x26 => {
+                let event = self.sync_object(frame, p)?;
+                let Object::Event(count) = event else {
+                    return Err("Reset of a non-event");
+                };
+                count.store(0, Ordering::Release);
+            }
+            // ReleaseOp
+            0x27 => {
+                let mutex = self.sync_object(frame, p)?;
+                let Object::Mutex(mutex) = mutex else {
+                    return Err("Release of a non-mutex");
+                };
+                mutex.release(self.owner)?;
+            }
+            // FatalOp
+            0x32 => {
+                let ty = p.read_u8()?;
+                let code = p.read_u32_le()?;
+                let arg = self.eval_integer(frame, p)?;
+                error!(
+                    "AML: Fatal ({ty:#x}, {code:#x}, {arg:#x}) in {}",
+                    frame.scope
+                );
+                return Err("AML Fatal");
+            }
+            // OpRegionOp
+            0x80 => {
+                let name = p.read_name_path()?;
+                let space = RegionSpace::from(p.read_u8()?);
+                let offset = self.eval_integer(frame, p)?;
+                let length = self.eval_integer(frame, p)?;
+
+                let region = OpRegion {
+                    space,
+                    offset,
+                    length,
+                    parent: frame.scope.clone(),
+                };
+                self.create(frame, &name, Object::OpRegion(region))?;
+            }
+            // FieldOp
+            0x81 => {
+                let start = p.position();
+                let len = p.read_pkg_length()?;
+                let region = p.read_name_path()?;
+                let flags = p.read_u8()?;
+                let body = pkg_body(p, start, len)?;
+
+                let region = self.field_source(frame, &region)?;
+                self.create_fields(frame, body, flags, FieldKind::Normal { region })?;
+            }
+            // DeviceOp, ThermalZoneOp
+            0x82 | 0x85 => {
+                let start = p.position();
+                let len = p.read_pkg_length()?;
+                let name = p.read_name_path()?;
+                let body = pkg_body(p, start, len)?;
+
+                let obj = if ext == 0x82 {
+                    Object::Device
+                } else {
+                    Object::ThermalZone
+                };
+                let path = self.create(frame, &name, obj)?;
+                self.exec_scoped(frame, path, body)?;
+            }
+            // ProcessorOp
+            0x83 => {
+                let start = p.position();
+                let len = p.read_pkg_length()?;
+                let name = p.read_name_path()?;
+                let id = p.read_u8()?;
+                let pblk_address = p.read_u32_le()?;
+                let pblk_len = p.read_u8()?;
+                let body = pkg_body(p, start, len)?;
+
+                let obj = Object::Processor {
+                    id,
+                    pblk_address,
+                    pblk_len,
+                };
+                let path = self.create(frame, &name, obj)?;
+                self.exec_scoped(frame, path, body)?;
+            }
+            // PowerResOp
+            0x84 => {
+                let start = p.position();
+                let len = p.read_pkg_length()?;
+                let name = p.read_name_path()?;
+                let system_level = p.read_u8()?;
+                let resource_order = p.read_u16_le()?;
+                let body = pkg_body(p, start, len)?;
+
+                let obj = Object::PowerResource {
+                    system_level,
+                    resource_order,
+                };
+                let path = self.create(frame, &name, obj)?;
+                self.exec_scoped(frame, path, body)?;
+            }
+            // IndexFieldOp
+            0x86 => {
+                let start = p.position();
+                let len = p.read_pkg_length()?;
+                let index = p.read_name_path()?;
+                let data = p.read_name_path()?;
+                let flags = p.read_u8()?;
+                let body = pkg_body(p, start, len)?;
+
+                let index = self.field_source(frame, &index)?;
+                let data = self.field_source(frame, &data)?;
+                self.create_fields(frame, body, flags, FieldKind::Index { index, data })?;
+            }
+            // BankFieldOp
+            0x87 => {
+                let start = p.position();
+                let len = p.read_pkg_length()?;
+                let region = p.read_name_path()?;
+                let bank = p.read_name_path()?;
+                let value = self.eval_integer(frame, p)?;
+                let flags = p.read_u8()?;
+                let body = pkg_body(p, start, len)?;
+
+                let region = self.field_source(frame, &region)?;
+                let bank = self.field_source(frame, &bank)?;
+                let kind = FieldKind::Bank {
+                    region,
+                    bank,
+                    value,
+                };
+                self.create_fields(frame, body, flags, kind)?;
+            }
+            // DataRegionOp: regions over other ACPI tables, which nothing here reads
+            0x88 => {
+                let name = p.read_name_path()?;
+                for _ in 0..3 {
+                    self.eval(frame, p)?;
+                }
+                warn!("AML: DataTableRegion {name} isn't supported");
+                self.create(frame, &name, Object::Uninitialized)?;
+            }
+            _ => unreachable!(),
+        }
+
+        Ok(())
+    }
+
+    /// run the body of a scope-like object. while loading a table, a body that fails is skipped
+    /// so one bad device doesn't take the rest of the namespace with it.
+    fn exec_scoped(
+        &mut self,
+        frame: &mut Frame,
+        path: String,
+        body: &[u8],
+    ) -> Result<(), &'static str> {
+        let saved = core::mem::replace(&mut frame.scope, path);
+        let res = self.exec_term_list(frame, &mut AmlParser::new(body));
+        let path = core::mem::replace(&mut frame.scope, saved);
+
+        match res {
+            Ok(Flow::Next) => Ok(()),
+            Ok(_) => Err("control flow statement escaped a scope"),
+            Err(e) if !frame.in_method => {
+                warn!("AML: skipping the rest of {path}: {e}");
+                Ok(())
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// path of the region or field a field list refers to
+    fn field_source(&self, frame: &Frame, name: &AmlNamePath) -> Result<String, &'static str> {
+        match self.search(frame, name)? {
+            Some(path) => Ok(path),
+            None => namespace::resolve(&frame.scope, name),
+        }
+    }
+
+    fn create_fields(
+        &mut self,
+        frame: &mut Frame,
+        body: &[u8],
+        mut flags: u8,
+        kind: FieldKind,
+    ) -> Result<(), &'static str> {
+        let mut p = AmlParser::new(body);
+        let mut bit_offset = 0u64;
+
+        while let Some(lead) = p.peek_u8() {
+            match lead {
+                // ReservedField
+                0x00 => {
+                    p.read_u8()?;
+                    bit_offset += p.read_pkg_length()? as u64;
+                }
+                // AccessField
+                0x01 => {
+                    p.read_u8()?;
+                    let access_type = p.read_u8()?;
+                    let _access_attrib = p.read_u8()?;
+                    flags = (flags & 0xF0) | (access_type & 0x0F);
+                }
+                // ConnectField: only meaningful for GPIO and serial bus regions
+                0x02 => {
+                    p.read_u8()?;
+                    if p.peek_u8() == Some(0x11) {
+                        self.eval(frame, &mut p)?;
+                    } else {
+                        p.read_name_path()?;
+                    }
+                }
+                // ExtendedAccessField
+                0x03 => {
+                    p.read_u8()?;
+                    let access_type = p.read_u8()?;
+                    let _access_attrib = p.read_u8()?;
+                    let _access_len = p.read_u8()?;
+                    flags = (flags & 0xF0) | (access_type & 0x0F);
+                }
+                // NamedField
+                _ => {
+                    let name = AmlNamePath {
+                        raw: p.take_bytes(4)?,
+                    };
+                    let bit_len = p.read_pkg_length()? as u64;
+
+                    let field = FieldUnit {
+                        kind: kind.clone(),
+                        bit_offset,
+                        bit_len,
+                        access: FieldAccess::from_flags(flags),
+                        update: UpdateRule::from_flags(flags),
+                    };
+                    self.create(frame, &name, Object::FieldUnit(field))?;
+
+                    bit_offset += bit_len;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn create_buffer_field(
+        &mut self,
+        frame: &mut Frame,
+        buffer: Object,
+        bit_offset: usize,
+        bit_len: usize,
+        name: &AmlNamePath,
+    ) -> Result<(), &'static str> {
+        let Object::Buffer(buffer) = buffer else {
+            return Err("buffer field source isn't a buffer");
+        };
+
+        if bit_offset + bit_len > buffer.lock().len() * 8 {
+            return Err("buffer field past the end of its buffer");
+        }
+
+        let field = BufferField {
+            buffer,
+            bit_offset,
+            bit_len,
+        };
+        self.create(frame, name, Object::BufferField(field))?;
+
+        Ok(())
+    }
+
+    /// the mutex or event a `SuperName` names
+    fn sync_object(
+        &mut self,
+        frame: &mut Frame,
+        p: &mut AmlParser,
+    ) -> Result<Object, &'static str> {
+        let target = self.target(frame, p)?;
+        self.read_target(frame, &target)
+    }
+
+    fn acquire(&self, mutex: &AmlMutex, timeout_ms: u64) -> bool {
+        if mutex.try_acquire(self.owner) {
+            return true;
+        }
+
+        if timeout_ms == 0 {
+            return false;
+        }
+
+        let deadline = self.handler().timer() + timeout_ms * 10_000;
+        loop {
+            self.handler().stall(10);
+
+            if mutex.try_acquire(self.owner) {
+                return true;
+            }
+
+            if timeout_ms != WAIT_FOREVER && self.handler().timer() >= deadline {
+                return false;
+            }
+        }
+    }
+
+    // --- expressions ---
+
+    fn eval_integer(&mut self, frame: &mut Frame, p: &mut AmlParser) -> Result<u64, &'static str> {
+        self.eval(frame, p)?.to_integer()
+    }
+
+    /// evaluate one `TermArg`
+    fn eval(&mut self, frame: &mut Frame, p: &mut AmlParser) -> Result<Object, &'static str> {
+        let op = p.peek_u8().ok_or("expected a TermArg")?;
+
+        if is_name_lead(op) {
+            return self.eval_name(frame, p);
+        }
+
+        p.read_u8()?;
+        let ib = self.int_bytes();
+
+        let value = match op {
+            // ZeroOp, OneOp, OnesOp
+            0x00 => Object::Integer(0),
+            0x01 => Object::Integer(1),
+            0xFF => Object::Integer(self.ones()),
+            // BytePrefix, WordPrefix, DWordPrefix, QWordPrefix
+            0x0A => Object::Integer(p.read_u8()? as u64),
+            0x0B => Object::Integer(p.read_u16_le()? as u64),
+            0x0C => Object::Integer(p.read_u32_le()? as u64),
+            0x0E => Object::Integer(self.mask(p.read_u64_le()?)),
+            // StringPrefix
+            0x0D => {
+                let mut bytes = Vec::new();
+                loop {
+                    match p.read_u8()? {
+                        0 => break,
+                        b => bytes.push(b),
+                    }
+                }
+                Object::String(String::from_utf8_lossy(&bytes).into_owned())
+            }
+            // BufferOp
+            0x11 => {
+                let start = p.position();
+                let len = p.read_pkg_length()?;
+                let size = self.eval_integer(frame, p)? as usize;
+                let init = pkg_body(p, start, len)?;
+
+                if size > MAX_OBJECT_LEN {
+                    return Err("AML buffer too large");
+                }
+
+                let mut bytes = vec![0; size.max(init.len())];
+                bytes[..init.len()].copy_from_slice(init);
+                Object::buffer(bytes)
+            }
+            // PackageOp, VarPackageOp
+            0x12 | 0x13 => self.eval_package(frame, p, op == 0x13)?,
+            // Local0-Local7
+            0x60..=0x67 => match &frame.locals[(op - 0x60) as usize] {
+                Object::Uninitialized => return Err("read of an uninitialized local"),
+                local => local.clone(),
+            },
+            // Arg0-Arg6
+            0x68..=0x6E => match &frame.args[(op - 0x68) as usize] {
+                Object::Uninitialized => return Err("read of a missing argument"),
+                arg => arg.clone(),
+            },
+            // StoreOp
+            0x70 => {
+                let value = self.eval(frame, p)?;
+                let target = self.target(frame, p)?;
+                self.store(frame, &target, value.clone())?;
+                value
+            }
+            // RefOfOp
+            0x71 => {
+                let target = self.target(frame, p)?;
+                Object::Reference(self.reference_to(frame, target)?)
+            }
+            // AddOp, MultiplyOp, SubtractOp, ShiftLeftOp, ShiftRightOp, AndOp, NandOp, OrOp,
+            // NorOp, XorOp, ModOp
+            0x72 | 0x74 | 0x77 | 0x79..=0x7F | 0x85 => {
+                let a = self.eval_integer(frame, p)?;
+                let b = self.eval_integer(frame, p)?;
+                let target = self.target(frame, p)?;
+
+                let result = match op {
+                    0x72 => a.wrapping_add(b),
+                    0x74 => a.wrapping_sub(b),
+                    0x77 => a.wrapping_mul(b),
+                    0x79 => a.checked_shl(b as u32).filter(|_| b < 64).unwrap_or(0),
+                    0x7A => a.checked_shr(b as u32).filter(|_| b < 64).unwrap_or(0),
+                    0x7B => a & b,
+                    0x7C => !(a & b),
+                    0x7D => a | b,
+                    0x7E => !(a | b),
+                    0x7F => a ^ b,
+                    _ => a.checked_rem(b).ok_or("AML Mod by zero")?,
+                };
+
+                let result = Object::Integer(self.mask(result));
+                self.store(frame, &target, result.clone())?;
+                result
+            }
+            // ConcatOp
+            0x73 => {
+                let a = self.eval(frame, p)?;
+                let b = self.eval(frame, p)?;
+                let target = self.target(frame, p)?;
+
+                let result = match &a {
+                    Object::Integer(_) => {
+                        let mut bytes = a.to_buffer(ib)?;
+                        bytes.extend(Object::Integer(b.to_integer()?).to_buffer(ib)?);
+                        Object::buffer(bytes)
+                    }
+                    Object::String(s) => Object::String(format!("{s}{}", b.to_aml_string(ib)?)),
+                    Object::Buffer(_) => {
+                        let mut bytes = a.to_buffer(ib)?;
+                        bytes.extend(b.to_buffer(ib)?);
+                        Object::buffer(bytes)
+                    }
+                    _ => return Err("Concatenate of non-data objects"),
+                };
+
+                self.store(frame, &target, result.clone())?;
+                result
+            }
+            // IncrementOp, DecrementOp
+            0x75 | 0x76 => {
+                let target = self.target(frame, p)?;
+                let value = self.read_target(frame, &target)?.to_integer()?;
+
+                let result = if op == 0x75 {
+                    value.wrapping_add(1)
+                } else {
+                    value.wrapping_sub(1)
+                };
+
+                let result = Object::Integer(self.mask(result));
+                self.store(frame, &target, result.clone())?;
+                result
+            }
+            // DivideOp
+            0x78 => {
+                let dividend = self.eval_integer(frame, p)?;
+                let divisor = self.eval_integer(frame, p)?;
+                let remainder = self.target(frame, p)?;
+                let quotient = self.target(frame, p)?;
+
+                if divisor == 0 {
+                    return Err("AML Divide by zero");
+                }
+
+                self.store(frame, &remainder, Object::Integer(dividend % divisor))?;
+                let result = Object::Integer(dividend / divisor);
+                self.store(frame, &quotient, result.clone())?;
+                result
+            }
+            // NotOp, FindSetLeftBitOp, FindSetRightBitOp
+            0x80..=0x82 => {
+                let value = self.eval_integer(frame, p)?;
+                let target = self.target(frame, p)?;
+
+                let result = match op {
+                    0x80 => self.mask(!value),
+                    0x81 => 64 - value.leading_zeros() as u64,
+                    _ if value == 0 => 0,
+                    _ => value.trailing_zeros() as u64 + 1,
+                };
+
+                let result = Object::Integer(result);
+                self.store(frame, &target, result.clone())?;
+                result
+            }
+            // DerefOfOp
+            0x83 => {
+                let value = self.eval(frame, p)?;
+                match value {
+                    Object::Reference(r) => self.deref(&r)?,
+                    Object::String(path) => {
+                        let path = namespace::normalize(&path)?;
+                        self.evaluate_path(&path, Vec::new())?
+                            .ok_or("DerefOf an undefined name")?
+                    }
+                    _ => return Err("DerefOf a non-reference"),
+                }
+            }
+            // ConcatResOp
+            0x84 => {
+                let a = self.eval(frame, p)?.to_buffer(ib)?;
+                let b = self.eval(frame, p)?.to_buffer(ib)?;
+                let target = self.target(frame, p)?;
+
+                let result = Object::buffer(concat_resource_templates(&a, &b));
+                self.store(frame, &target, result.clone())?;
+                result
+            }
+            // SizeOfOp
+            0x87 => {
+                let target = self.target(frame, p)?;
+                let len = match self.read_target(frame, &target)? {
+                    Object::String(s) => s.len(),
+                    Object::Buffer(buf) => buf.lock().len(),
+                    Object::Package(pkg) => pkg.lock().len(),
+                    _ => return Err("SizeOf a non-data object"),
+                };
+                Object::Integer(len as u64)
+            }
+            // IndexOp
+            0x88 => {
+                let container = self.eval(frame, p)?;
+                let index = self.eval_integer(frame, p)? as usize;
+                let target = self.target(frame, p)?;
+
+                let reference = match container {
+                    Object::Package(package) if index < package.lock().len() => {
+                        Reference::PackageElement { package, index }
+                    }
+                    Object::Buffer(buffer) if index < buffer.lock().len() => {
+                        Reference::BufferByte { buffer, index }
+                    }
+                    Object::String(s) if index < s.len() => Reference::BufferByte {
+                        buffer: crate::object::shared(s.into_bytes()),
+                        index,
+                    },
+                    Object::Package(_) | Object::Buffer(_) | Object::String(_) => {
+                        return Err("AML Index out of bounds");
+                    }
+                    _ => return Err("AML Index of a non-container"),
+                };
+
+                let result = Object::Reference(reference);
+                self.store(frame, &target, result.clone())?;
+                result
+            }
+            // MatchOp
+            0x89 => {
+                let package = self.eval(frame, p)?;
+                let op1 = p.read_u8()?;
+                let operand1 = self.eval(frame, p)?;
+                let op2 = p.read_u8()?;
+                let operand2 = self.eval(frame, p)?;
+                let start = self.eval_integer(frame, p)? as usize;
+
+                let Object::Package(package) = package else {
+                    return Err("Match of a non-package");
+                };
+
+                let elements = package.lock().clone();
+                let found = elements.iter().enumerate().skip(start).find(|(_, elem)| {
+                    self.match_op(elem, op1, &operand1) && self.match_op(elem, op2, &operand2)
+                });
+
+                Object::Integer(found.map_or(self.ones(), |(i, _)| i as u64))
+            }
+            // ObjectTypeOp
+            0x8E => {
+                let target = self.target(frame, p)?;
+                let ty = match &target {
+                    Target::Debug => ObjectType::Debug,
+                    _ => self.raw_target(frame, &target)?.object_type(),
+                };
+                Object::Integer(ty as u64)
+            }
+            // LAndOp, LOrOp
+            0x90 | 0x91 => {
+                let a = self.eval_integer(frame, p)? != 0;
+                let b = self.eval_integer(frame, p)? != 0;
+                self.boolean(if op == 0x90 { a && b } else { a || b })
+            }
+            // LNotOp
+            0x92 => {
+                let value = self.eval_integer(frame, p)?;
+                self.boolean(value == 0)
+            }
+            // LEqualOp, LGreaterOp, LLessOp
+            0x93..=0x95 => {
+                let a = self.eval(frame, p)?;
+                let b = self.eval(frame, p)?;
+                let ord = self.compare(&a, &b)?;
+
+                self.boolean(match op {
+                    0x93 => ord == CmpOrdering::Equal,
+                    0x94 => ord == CmpOrdering::Greater,
+                    _ => ord == CmpOrdering::Less,
+                })
+            }
+            // ToBufferOp, ToDecimalStringOp, ToHexStringOp, ToIntegerOp
+            0x96..=0x99 => {
+                let value = self.eval(frame, p)?;
+                let target = self.target(frame, p)?;
+
+                let result = match op {
+                    0x96 => Object::buffer(value.to_buffer(ib)?),
+                    0x97 => Object::String(to_decimal_string(&value)?),
+                    0x98 => Object::String(to_hex_string(&value)?),
+                    _ => Object::Integer(self.mask(to_integer_explicit(&value)?)),
+                };
+
+                self.store(frame, &target, result.clone())?;
+                result
+            }
+            // ToStringOp
+            0x9C => {
+                let value = self.eval(frame, p)?.to_buffer(ib)?;
+                let limit = self.eval_integer(frame, p)?;
+                let target = self.target(frame, p)?;
+
+                let bytes: Vec<u8> = value
+                    .into_iter()
+                    .take(if limit == self.ones() {
+                        usize::MAX
+                    } else {
+                        limit as usize
+                    })
+                    .take_while(|&b| b != 0)
+                    .collect();
+
+                let result = Object::String(String::from_utf8_lossy(&bytes).into_owned());
+                self.store(frame, &target, result.clone())?;
+                result
+            }
+            // CopyObjectOp
+            0x9D => {
+                let value = self.eval(frame, p)?;
+                let target = self.target(frame, p)?;
+                self.copy_object(frame, &target, value.deep_copy())?;
+                value
+            }
+            // MidOp
+            0x9E => {
+                let value = self.eval(frame, p)?;
+                let index = self.eval_integer(frame, p)? as usize;
+                let len = self.eval_integer(frame, p)? as usize;
+                let target = self.target(frame, p)?;
+
+                let result = match value {
+                    Object::String(s) => {
+                        let bytes: Vec<u8> = s.bytes().skip(index).take(len).collect();
+                        Object::String(String::from_utf8_lossy(&bytes).into_owned())
+                    }
+                    Object::Buffer(buf) => {
+                        let bytes = buf.lock().iter().copied().skip(index).take(len).collect();
+                        Object::buffer(bytes)
+                    }
+                    _ => return Err("Mid of a non-string, non-buffer"),
+                };
+
+                self.store(frame, &target, result.clone())?;
+                result
+            }
+            0x5B => return self.eval_ext(frame, p),
+            other => {
+                warn!("AML: unsupported opcode {other:#04X} in {}", frame.scope);
+                return Err("unsupported AML opcode");
+            }
+        };
+
+        Ok(value)
+    }
+
+    fn eval_ext(&mut self, frame: &mut Frame, p: &mut AmlParser) -> Result<Object, &'static str> {
+        let ext = p.read_u8()?;
+
+        let value = match ext {
+            // CondRefOfOp
+            0x12 => {
+                let Some(target) = self.optional_target(frame, p)? else {
+                    self.target(frame, p)?;
+                    return Ok(Object::Integer(0));
+                };
+
+                let result = self.target(frame, p)?;
+                let reference = self.reference_to(frame, target)?;
+                self.store(frame, &result, Object::Reference(reference))?;
+                Object::Integer(self.ones())
+            }
+            // LoadTableOp, LoadOp, UnloadOp
+            0x1F | 0x20 | 0x2A => {
+                warn!(
+                    "AML: dynamic table loading in {} isn't supported",
+                    frame.scope
+                );
+                return Err("dynamic AML table loading isn't supported");
+            }
+            // AcquireOp
+            0x23 => {
+                let mutex = self.sync_object(frame, p)?;
+                let timeout = p.read_u16_le()? as u64;
+                let Object::Mutex(mutex) = mutex else {
+                    return Err("Acquire of a non-mutex");
+                };
+
+                let acquired = self.acquire(&mutex, timeout);
+                self.boolean(!acquired)
+            }
+            // WaitOp
+            0x25 => {
+                let event = self.sync_object(frame, p)?;
+                let timeout = self.eval_integer(frame, p)?;
+                let Object::Event(count) = event else {
+                    return Err("Wait on a non-event");
+                };
+
+                let take = || {
+                    count
+                        .fetch_update(Ordering::Acquire, Ordering::Relaxed, |c| c.checked_sub(1))
+                        .is_ok()
+                };
+
+                let deadline = self.handler().timer() + timeout.saturating_mul(10_000);
+                let signalled = loop {
+                    if take() {
+                        break true;
+                    }
+                    if timeout != WAIT_FOREVER && self.handler().timer() >= deadline {
+                        break false;
+                    }
+                    self.handler().stall(10);
+                };
+
+                self.boolean(!signalled)
+            }
+            // FromBCDOp, ToBCDOp
+            0x28 | 0x29 => {
+                let value = self.eval_integer(frame, p)?;
+                let target = self.target(frame, p)?;
+
+                let result = if ext == 0x28 {
+                    from_bcd(value)
+                } else {
+                    to_bcd(value)
+                };
+
+                let result = Object::Integer(self.mask(result));
+                self.store(frame, &target, result.clone())?;
+                result
+            }
+            // RevisionOp
+            0x30 => Object::Integer(INTERPRETER_REVISION),
+            // DebugOp
+            0x31 => Object::Debug,
+            // TimerOp
+            0x33 => Object::Integer(self.handler().timer()),
+            other => {
+                warn!(
+                    "AML: unsupported opcode 0x5B {other:#04X} in {}",
+                    frame.scope
+                );
+                return Err("unsupported AML opcode");
+            }
+        };
+
+        Ok(value)
+    }
+
+    /// a name in expression position: invoke methods, read fields, otherwise take the value
+    fn eval_name(&mut self, frame: &mut Frame, p: &mut AmlParser) -> Result<Object, &'static str> {
+        let name = p.read_name_path()?;
+
+        let Some(path) = self.search(frame, &name)? else {
+            warn!("AML: {name} isn't defined (from {})", frame.scope);
+            return Err("undefined AML name");
+        };
+
+        let (path, obj) = self.get(&path).ok_or("undefined AML name")?;
+
+        match obj {
+            Object::Method(method) => {
+                let mut args = Vec::with_capacity(method.arg_count as usize);
+                for _ in 0..method.arg_count {
+                    args.push(self.eval(frame, p)?);
+                }
+                self.invoke(&path, &method, args)
+            }
+            other => self.read_value(other),
+        }
+    }
+
+    fn eval_package(
+        &mut self,
+        frame: &mut Frame,
+        p: &mut AmlParser,
+        var: bool,
+    ) -> Result<Object, &'static str> {
+        let start = p.position();
+        let len = p.read_pkg_length()?;
+        let count = if var {
+            self.eval_integer(frame, p)? as usize
+        } else {
+            p.read_u8()? as usize
+        };
+        let body = pkg_body(p, start, len)?;
+
+        if count > MAX_OBJECT_LEN {
+            return Err("AML package too large");
+        }
+
+        let mut inner = AmlParser::new(body);
+        let mut elements = Vec::with_capacity(count);
+
+        while !inner.is_empty() {
+            // names in packages are references, they're not evaluated
+            if inner.peek_u8().is_some_and(is_name_lead) {
+                let name = inner.read_name_path()?;
+                let path = self.field_source(frame, &name)?;
+                elements.push(Object::Reference(Reference::Name(path)));
+            } else {
+                elements.push(self.eval(frame, &mut inner)?);
+            }
+        }
+
+        elements.resize(count, Object::Uninitialized);
+        Ok(Object::package(elements))
+    }
+
+    /// the value of a named object: fields are read, everything else is the object itself
+    fn read_value(&mut self, obj: Object) -> Result<Object, &'static str> {
+        match obj {
+            Object::FieldUnit(field) => self.read_field(&field),
+            Object::BufferField(field) => Ok(self.read_buffer_field(&field)),
+            other => Ok(other),
+        }
+    }
+
+    fn compare(&self, a: &Object, b: &Object) -> Result<CmpOrdering, &'static str> {
+        let ib = self.int_bytes();
+
+        match a {
+            Object::Integer(a) => Ok(a.cmp(&b.to_integer()?)),
+            Object::String(a) => Ok(a.as_str().cmp(b.to_aml_string(ib)?.as_str())),
+            Object::Buffer(a) => {
+                let b = b.to_buffer(ib)?;
+                Ok(a.lock().as_slice().cmp(b.as_slice()))
+            }
+            _ => Err("comparison of non-data objects"),
+        }
+    }
+
+    /// one half of a `Match` predicate
+    fn match_op(&self, elem: &Object, op: u8, operand: &Object) -> bool {
+        if op == 0 {
+            return true;
+        }
+
+        // the package element is compared against the operand, so it decides the type
+        let Ok(ord) = self.compare(elem, operand) else {
+            return false;
+        };
+
+        match op {
+            1 => ord == CmpOrdering::Equal,
+            2 => ord != CmpOrdering::Greater,
+            3 => ord == CmpOrdering::Less,
+            4 => ord != CmpOrdering::Less,
+            5 => ord == CmpOrdering::Greater,
+            _ => false,
+        }
+    }
+
+    // --- targets and references ---
+
+    /// parse a `Target` or `SuperName`
+    fn target(&mut self, frame: &mut Frame, p: &mut AmlParser) -> Result<Target, &'static str> {
+        match self.optional_target(frame, p)? {
+            Some(target) => Ok(target),
+            None => Err("undefined AML name as a target"),
+        }
+    }
+
+    /// like `target`, but an undefined name is `None` rather than an error
+    fn optional_target(
+        &mut self,
+        frame: &mut Frame,
+        p: &mut AmlParser,
+    ) -> Result<Option<Target>, &'static str> {
+        let op = p.peek_u8().ok_or("expected a target")?;
+
+        let target = match op {
+            0x00 => {
+                p.read_u8()?;
+                Target::Null
+            }
+            0x60..=0x67 => {
+                p.read_u8()?;
+                Target::Local((op - 0x60) as usize)
+            }
+            0x68..=0x6E => {
+                p.read_u8()?;
+                Target::Arg((op - 0x68) as usize)
+            }
+            0x5B if p.remaining_bytes().get(1) == Some(&0x31) => {
+                p.read_u8()?;
+                p.read_u8()?;
+                Target::Debug
+            }
+            b if is_name_lead(b) => {
+                let name = p.read_name_path()?;
+                match self.search(frame, &name)? {
+                    Some(path) => Target::Name(path),
+                    None => return Ok(None),
+                }
+            }
+            // DerefOfOp: the reference itself is the target
+            0x83 => {
+                p.read_u8()?;
+                match self.eval(frame, p)? {
+                    Object::Reference(r) => Target::Ref(r),
+                    Object::String(path) => Target::Name(namespace::normalize(&path)?),
+                    _ => return Err("DerefOf a non-reference"),
+                }
+            }
+            // RefOf, Index or a method call returning a reference
+            _ => match self.eval(frame, p)? {
+                Object::Reference(r) => Target::Ref(r),
+                _ => return Err("AML target isn't a reference"),
+            },
+        };
+
+        Ok(Some(target))
+    }
+
+    fn reference_to(&self, frame: &Frame, target: Target) -> Result<Reference, &'static str> {
+        match target {
+            Target::Name(path) => Ok(Reference::Name(path)),
+            Target::Ref(r) => Ok(r),
+            Target::Local(i) => match &frame.locals[i] {
+                Object::Reference(r) => Ok(r.clone()),
+                _ => Err("references to locals aren't supported"),
+            },
+            Target::Arg(i) => match &frame.args[i] {
+                Object::Reference(r) => Ok(r.clone()),
+                _ => Err("references to arguments aren't supported"),
+            },
+            Target::Null | Target::Debug => Err("reference to nothing"),
+        }
+    }
+
+    fn deref(&mut self, r: &Reference) -> Result<Object, &'static str> {
+        match r {
+            Reference::Name(path) => self
+                .evaluate_path(path, Vec::new())?
+                .ok_or("reference to a deleted object"),
+            Reference::PackageElement { package, index } => package
+                .lock()
+                .get(*index)
+                .cloned()
+                .ok_or("AML Index out of bounds"),
+            Reference::BufferByte { buffer, index } => buffer
+                .lock()
+                .get(*index)
+                .map(|&b| Object::Integer(b as u64))
+                .ok_or("AML Index out of bounds"),
+        }
+    }
+
+    /// the object a target names, without invoking or reading it
+    fn raw_target(&mut self, frame: &Frame, target: &Target) -> Result<Object, &'static str> {
+        match target {
+            Target::Local(i) => Ok(frame.locals[*i].clone()),
+            Target::Arg(i) => Ok(frame.args[*i].clone()),
+            Target::Name(path) => self
+                .get(path)
+                .map(|(_, obj)| obj)
+                .ok_or("undefined AML name"),
+            Target::Ref(r) => self.deref(r),
+            Target::Null | Target::Debug => Err("read of a write-only target"),
+        }
+    }
+
+    /// the current value of a target, following references in arguments
+    fn read_target(&mut self, frame: &Frame, target: &Target) -> Result<Object, &'static str> {
+        match target {
+            Target::Arg(i) => match &frame.args[*i] {
+                Object::Reference(r) => self.deref(&r.clone()),
+                arg => Ok(arg.clone()),
+            },
+            Target::Name(path) => {
+                let (_, obj) = self.get(path).ok_or("undefined AML name")?;
+                self.read_value(obj)
+            }
+            other => self.raw_target(frame, other),
+        }
+    }
+
+    fn store(
+        &mut self,
+        frame: &mut Frame,
+        target: &Target,
+        value: Object,
+    ) -> Result<(), &'static str> {
+        match target {
+            Target::Null => Ok(()),
+            Target::Debug => {
+                debug!("AML: Debug = {value}");
+                Ok(())
+            }
+            Target::Local(i) => {
+                frame.locals[*i] = value.deep_copy();
+                Ok(())
+            }
+            Target::Arg(i) => {
+                if let Object::Reference(r) = &frame.args[*i] {
+                    let r = r.clone();
+                    return self.store_ref(&r, value);
+                }
+                frame.args[*i] = value.deep_copy();
+                Ok(())
+            }
+            Target::Name(path) => self.store_name(path, value),
+            Target::Ref(r) => self.store_ref(r, value),
+        }
+    }
+
+    /// `CopyObject`: replace the target without converting to its type
+    fn copy_object(
+        &mut self,
+        frame: &mut Frame,
+        target: &Target,
+        value: Object,
+    ) -> Result<(), &'static str> {
+        match target {
+            Target::Name(path) => {
+                let (path, _) = self.get(path).ok_or("undefined AML name")?;
+                if let Some(obj) = self.ns().get_mut(&path) {
+                    *obj = value;
+                }
+                Ok(())
+            }
+            Target::Local(i) => {
+                frame.locals[*i] = value;
+                Ok(())
+            }
+            Target::Arg(i) => {
+                frame.args[*i] = value;
+                Ok(())
+            }
+            other => self.store(frame, other, value),
+        }
+    }
+
+    fn store_ref(&mut self, r: &Reference, value: Object) -> Result<(), &'static str> {
+        match r {
+            Reference::Name(path) => self.store_name(path, value),
+            Reference::PackageElement { package, index } => {
+                let value = value.deep_copy();
+                let mut package = package.lock();
+                let slot = package.get_mut(*index).ok_or("AML Index out of bounds")?;
+                *slot = value;
+                Ok(())
+            }
+            Reference::BufferByte { buffer, index } => {
+                let value = value.to_integer()?;
+                let mut buffer = buffer.lock();
+                let slot = buffer.get_mut(*index).ok_or("AML Index out of bounds")?;
+                *slot = value as u8;
+                Ok(())
+            }
+        }
+    }
+
+    /// store into a named object, converting to the type it already has
+    fn store_name(&mut self, path: &str, value: Object) -> Result<(), &'static str> {
+        let ib = self.int_bytes();
+        let (path, existing) = self.get(path).ok_or("undefined AML name")?;
+
+        let replacement = match existing {
+            Object::FieldUnit(field) => return self.write_field(&field, &value),
+            Object::BufferField(field) => return self.write_buffer_field(&field, &value),
+            Object::Integer(_) => Object::Integer(self.mask(value.to_integer()?)),
+            Object::String(_) => Object::String(value.to_aml_string(ib)?),
+            Object::Buffer(buf) => {
+                let bytes = value.to_buffer(ib)?;
+                let mut buf = buf.lock();
+                let n = bytes.len().min(buf.len());
+                buf.fill(0);
+                buf[..n].copy_from_slice(&bytes[..n]);
+                return Ok(());
+            }
+            Object::Uninitialized | Object::Package(_) | Object::Reference(_) => value.deep_copy(),
+            _ => return Err("store to a non-data object"),
+        };
+
+        if let Some(obj) = self.ns().get_mut(&path) {
+            *obj = replacement;
+        }
+
+        Ok(())
+    }
+
+    // --- fields and regions ---
+
+    fn read_buffer_field(&self, field: &BufferField) -> Object {
+        let buf = field.buffer.lock();
+
+        if field.bit_len <= self.int_bytes() * 8 {
+            return Object::Integer(get_bits(&buf, field.bit_offset, field.bit_len));
+        }
+
+        let mut out = vec![0; field.bit_len.div_ceil(8)];
+        copy_bits(&buf, field.bit_offset, &mut out, 0, field.bit_len);
+        Object::buffer(out)
+    }
+
+    fn write_buffer_field(&self, field: &BufferField, value: &Object) -> Result<(), &'static str> {
+        let mut src = value.to_buffer(self.int_bytes())?;
+        src.resize(src.len().max(field.bit_len.div_ceil(8)), 0);
+
+        let mut buf = field.buffer.lock();
+        copy_bits(&src, 0, &mut buf, field.bit_offset, field.bit_len);
+        Ok(())
+    }
+
+    fn read_field(&mut self, field: &FieldUnit) -> Result<Object, &'static str> {
+        let bit_len = field.bit_len as usize;
+        let mut out = vec![0; bit_len.div_ceil(8)];
+
+        for (unit_bit, lo, hi) in field_units(field) {
+            let value = self.access_unit(field, unit_bit / 8, None)?;
+            let bits = (value >> (lo - unit_bit)) & low_bits(hi - lo);
+            set_bits(
+                &mut out,
+                (lo - field.bit_offset) as usize,
+                (hi - lo) as usize,
+                bits,
+            );
+        }
+
+        if bit_len <= self.int_bytes() * 8 {
+            Ok(Object::Integer(get_bits(&out, 0, bit_len)))
+        } else {
+            Ok(Object::buffer(out))
+        }
+    }
+
+    fn write_field(&mut self, field: &FieldUnit, value: &Object) -> Result<(), &'static str> {
+        let src = value.to_buffer(self.int_bytes())?;
+        let width = field.access_bits();
+
+        for (unit_bit, lo, hi) in field_units(field) {
+            let bits = get_bits(&src, (lo - field.bit_offset) as usize, (hi - lo) as usize);
+            let mask = low_bits(hi - lo) << (lo - unit_bit);
+
+            let base = if hi - lo == width {
+                0
+            } else {
+                match field.update {
+                    UpdateRule::Preserve => self.access_unit(field, unit_bit / 8, None)?,
+                    UpdateRule::WriteAsOnes => low_bits(width),
+                    UpdateRule::WriteAsZeros => 0,
+                }
+            };
+
+            let unit = (base & !mask) | ((bits << (lo - unit_bit)) & mask);
+            self.access_unit(field, unit_bit / 8, Some(unit))?;
+        }
+
+        Ok(())
+    }
+
+    /// read (`write` is `None`) or write one access unit of a field at `byte_offset` into it
+    fn access_unit(
+        &mut self,
+        field: &FieldUnit,
+        byte_offset: u64,
+        write: Option<u64>,
+    ) -> Result<u64, &'static str> {
+        let width = field.access_bits() as u8;
+
+        match &field.kind {
+            FieldKind::Normal { region } => self.region_access(region, byte_offset, width, write),
+            FieldKind::Bank {
+                region,
+                bank,
+                value,
+            } => {
+                self.store_name(bank, Object::Integer(*value))?;
+                self.region_access(region, byte_offset, width, write)
+            }
+            FieldKind::Index { index, data } => {
+                self.store_name(index, Object::Integer(byte_offset))?;
+                match write {
+                    Some(value) => {
+                        self.store_name(data, Object::Integer(value))?;
+                        Ok(0)
+                    }
+                    None => {
+                        let (_, data) = self.get(data).ok_or("IndexField data field is missing")?;
+                        self.read_value(data)?.to_integer()
+                    }
+                }
+            }
+        }
+    }
+
+    fn region_access(
+        &mut self,
+        region: &str,
+        byte_offset: u64,
+        width: u8,
+        write: Option<u64>,
+    ) -> Result<u64, &'static str> {
+        let Some((_, Object::OpRegion(region))) = self.get(region) else {
+            return Err("field's OperationRegion is missing");
+        };
+
+        if byte_offset + width as u64 / 8 > region.length {
+            return Err("field access outside of its OperationRegion");
+        }
+
+        let handler = self.handler();
+
+        match region.space {
+            RegionSpace::SystemMemory => {
+                let address = region.offset + byte_offset;
+                match write {
+                    Some(value) => handler.write_memory(address, width, value).map(|_| 0),
+                    None => handler.read_memory(address, width),
+                }
+            }
+            RegionSpace::PciConfig => {
+                let pci = self.pci_address(&region.parent)?;
+                let offset = (region.offset + byte_offset) as u16;
+                match write {
+                    Some(value) => handler.write_pci(pci, offset, width, value).map(|_| 0),
+                    None => handler.read_pci(pci, offset, width),
+                }
+            }
+            space => {
+                warn!("AML: access to an unsupported {space:?} OperationRegion");
+                Err("unsupported OperationRegion space")
+            }
+        }
+    }
+
+    /// the function a `PCI_Config` region declared in `scope` belongs to. `_ADR` comes from the
+    /// enclosing device, `_SEG` and `_BBN` from the nearest ancestor that has them (the host
+    /// bridge). bridges in between aren't walked, so this is only right for root bus devices.
+    fn pci_address(&mut self, scope: &str) -> Result<PciAddress, &'static str> {
+        let adr = self
+            .optional_integer(&namespace::child(scope, "_ADR"))?
+            .unwrap_or(0);
+
+        let mut segment = None;
+        let mut bus = None;
+        let mut current = Some(scope);
+        while let Some(path) = current {
+            if segment.is_none() {
+                segment = self.optional_integer(&namespace::child(path, "_SEG"))?;
+            }
+            if bus.is_none() {
+                bus = self.optional_integer(&namespace::child(path, "_BBN"))?;
+            }
+            current = namespace::parent(path);
+        }
+
+        Ok(PciAddress {
+            segment: segment.unwrap_or(0) as u16,
+            bus: bus.unwrap_or(0) as u8,
+            device: (adr >> 16) as u8,
+            function: (adr & 0x7) as u8,
+        })
+    }
+
+    fn optional_integer(&mut self, path: &str) -> Result<Option<u64>, &'static str> {
+        self.evaluate_path(path, Vec::new())?
+            .map(|v| v.to_integer())
+            .transpose()
+    }
+}
+
+/// `(unit start bit, lo, hi)` for every access unit the field touches, `lo..hi` being the field
+/// bits inside that unit
+fn field_units(field: &FieldUnit) -> impl Iterator<Item = (u64, u64, u64)> {
+    let width = field.access_bits();
+    let start = field.bit_offset;
+    let end = field.bit_offset + field.bit_len;
+
+    let units = if field.bit_len == 0 {
+        0..0
+    } else {
+        start / width..(end - 1) / width + 1
+    };
+
+    units.map(move |unit| {
+        let unit_bit = unit * width;
+        let lo = start.max(unit_bit);
+        let hi = end.min(unit_bit + width);
+        (unit_bit, lo, hi)
+    })
+}
+
+/// copy `len` bits from `src` at `src_off` to `dst` at `dst_off`
+fn copy_bits(src: &[u8], src_off: usize, dst: &mut [u8], dst_off: usize, len: usize) {
+    let mut done = 0;
+    while done < len {
+        let chunk = (len - done).min(64);
+        let bits = get_bits(src, src_off + done, chunk);
+        set_bits(dst, dst_off + done, chunk, bits);
+        done += chunk;
+    }
+}
+
+/// `ConcatenateResTemplate`: the first template's end tag is dropped and a fresh one is added
+fn concat_resource_templates(a: &[u8], b: &[u8]) -> Vec<u8> {
+    fn strip_end_tag(t: &[u8]) -> &[u8] {
+        match t {
+            [rest @ .., 0x79, _] => rest,
+            other => other,
+        }
+    }
+
+    let mut out = strip_end_tag(a).to_vec();
+    out.extend_from_slice(strip_end_tag(b));
+    out.extend_from_slice(&[0x79, 0x00]);
+    out
+}
+
+fn to_decimal_string(value: &Object) -> Result<String, &'static str> {
+    match value {
+        Object::Integer(v) => Ok(format!("{v}")),
+        Object::String(s) => Ok(s.clone()),
+        Object::Buffer(buf) => {
+            let parts: Vec<String> = buf.lock().iter().map(|b| format!("{b}")).collect();
+            Ok(parts.join(","))
+        }
+        _ => Err("ToDecimalString of a non-data object"),
+    }
+}
+
+fn to_hex_string(value: &Object) -> Result<String, &'static str> {
+    match value {
+        Object::Integer(v) => Ok(format!("0x{v:X}")),
+        Object::String(s) => Ok(s.clone()),
+        Object::Buffer(buf) => {
+            let parts: Vec<String> = buf.lock().iter().map(|b| format!("0x{b:02X}")).collect();
+            Ok(parts.join(","))
+        }
+        _ => Err("ToHexString of a non-data object"),
+    }
+}
+
+/// `ToInteger`: unlike the implicit conversion, strings are decimal unless prefixed with `0x`
+fn to_integer_explicit(value: &Object) -> Result<u64, &'static str> {
+    let Object::String(s) = value else {
+        return value.to_integer();
+    };
+
+    let s = s.trim();
+    if s.starts_with("0x") || s.starts_with("0X") {
+        return value.to_integer();
+    }
+
+    Ok(s.chars()
+        .map_while(|c| c.to_digit(10))
+        .fold(0u64, |acc, d| acc.wrapping_mul(10).wrapping_add(d as u64)))
+}
+
+fn from_bcd(value: u64) -> u64 {
+    (0..16)
+        .rev()
+        .fold(0, |acc, i| acc * 10 + ((value >> (i * 4)) & 0xF))
+}
+
+fn to_bcd(mut value: u64) -> u64 {
+    let mut out = 0;
+    for i in 0..16 {
+        out |= (value % 10) << (i * 4);
+        value /= 10;
+    }
+    out
+}
@@ -5,4 +5,10 @@ extern crate alloc;
 pub mod ast;
 pub mod crs;
 pub mod device;
+pub mod ged;
+pub mod handler;
+pub mod interpreter;
+pub mod namespace;
+pub mod object;
 pub mod parser;
+pub mod prt;
@@ -0,0 +1,270 @@
+//! the ACPI namespace: every named object, keyed by its absolute path.
+//!
+//! paths are kept in one canonical form: `\` for the root, then full 4 character segments joined
+//! by dots, e.g. `\_SB_.PCI0._STA`.
+
+use alloc::{collections::BTreeMap, string::String, vec::Vec};
+
+use crate::{ast::AmlNamePath, object::Object};
+
+pub const ROOT: &str = "\\";
+
+/// how many aliases a lookup follows before giving up on a loop
+const MAX_ALIAS_DEPTH: usize = 8;
+
+/// a decoded `NameString`
+struct NameString {
+    rooted: bool,
+    parents: usize,
+    segs: Vec<[u8; 4]>,
+}
+
+impl NameString {
+    fn decode(raw: &[u8]) -> Result<Self, &'static str> {
+        let mut rooted = false;
+        let mut parents = 0;
+        let mut bytes = raw;
+
+        while let Some((&b, rest)) = bytes.split_first() {
+            match b {
+                b'\\' => rooted = true,
+                b'^' => parents += 1,
+                _ => break,
+            }
+            bytes = rest;
+        }
+
+        let seg_bytes = match bytes {
+            [] | [0x00, ..] => &[][..],
+            [0x2E, rest @ ..] => rest.get(..8).ok_or("truncated DualNamePath")?,
+            [0x2F, count, rest @ ..] => rest
+                .get(..*count as usize * 4)
+                .ok_or("truncated MultiNamePath")?,
+            _ => bytes.get(..4).ok_or("truncated NameSeg")?,
+        };
+
+        let segs = seg_bytes
+            .chunks_exact(4)
+            .map(|seg| [seg[0], seg[1], seg[2], seg[3]])
+            .collect();
+
+        Ok(Self {
+            rooted,
+            parents,
+            segs,
+        })
+    }
+
+    /// a lone segment without prefixes is looked up in every enclosing scope
+    fn uses_search_rules(&self) -> bool {
+        !self.rooted && self.parents == 0 && self.segs.len() == 1
+    }
+}
+
+/// parent scope of an absolute path. `None` for the root.
+pub fn parent(path: &str) -> Option<&str> {
+    if path == ROOT {
+        return None;
+    }
+
+    Some(match path.rfind('.') {
+        Some(dot) => &path[..dot],
+        None => ROOT,
+    })
+}
+
+/// last segment of an absolute path
+pub fn last_seg(path: &str) -> &str {
+    path.rsplit(['.', '\\']).next().unwrap_or(path)
+}
+
+/// absolute path of the child `seg` (a full 4 character segment) of `path`
+pub fn child(path: &str, seg: &str) -> String {
+    let mut out = String::from(path);
+    if path != ROOT {
+        out.push('.');
+    }
+    out.push_str(seg);
+    out
+}
+
+fn push_seg(path: &mut String, seg: &[u8; 4]) {
+    if path != ROOT {
+        path.push('.');
+    }
+    path.extend(seg.iter().map(|&b| b as char));
+}
+
+/// `scope` plus a relative or absolute name, without search rules
+pub fn resolve(scope: &str, name: &AmlNamePath) -> Result<String, &'static str> {
+    let name = NameString::decode(name.raw)?;
+    resolve_decoded(scope, &name)
+}
+
+fn resolve_decoded(scope: &str, name: &NameString) -> Result<String, &'static str> {
+    let mut base = if name.rooted { ROOT } else { scope };
+    for _ in 0..name.parents {
+        base = parent(base).ok_or("parent prefix above the namespace root")?;
+    }
+
+    let mut path = String::from(base);
+    for seg in &name.segs {
+        push_seg(&mut path, seg);
+    }
+
+    Ok(path)
+}
+
+/// canonical form of a user supplied absolute path like `\_SB.PCI0._STA`
+pub fn normalize(path: &str) -> Result<String, &'static str> {
+    let rest = path
+        .strip_prefix('\\')
+        .ok_or("namespace paths must start at the root")?;
+
+    let mut out = String::from(ROOT);
+    for seg in rest.split('.').filter(|s| !s.is_empty()) {
+        let valid = seg
+            .bytes()
+            .all(|b| b.is_ascii_uppercase() || b.is_ascii_digit() || b == b'_');
+        if seg.len() > 4 || !valid {
+            return Err("invalid NameSeg in path");
+        }
+
+        let mut padded = [b'_'; 4];
+        padded[..seg.len()].copy_from_slice(seg.as_bytes());
+        push_seg(&mut out, &padded);
+    }
+
+    Ok(out)
+}
+
+#[derive(Debug)]
+pub struct Namespace {
+    objects: BTreeMap<String, Object>,
+}
+
+impl Default for Namespace {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Namespace {
+    /// a namespace with the root and the predefined scopes of ACPI 6.5 section 5.3.1
+    pub fn new() -> Self {
+        let mut objects = BTreeMap::new();
+        for scope in [ROOT, "\\_GPE", "\\_PR_", "\\_SB_", "\\_SI_", "\\_TZ_"] {
+            objects.insert(String::from(scope), Object::Scope);
+        }
+
+        Self { objects }
+    }
+
+    pub fn len(&self) -> usize {
+        self.objects.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.objects.is_empty()
+    }
+
+    pub fn contains(&self, path: &str) -> bool {
+        self.objects.contains_key(path)
+    }
+
+    /// the object at `path`, following aliases. also returns the path aliases led to.
+    pub fn get(&self, path: &str) -> Option<(&str, &Object)> {
+        let (mut path, mut obj) = self.objects.get_key_value(path)?;
+
+        for _ in 0..MAX_ALIAS_DEPTH {
+            let Object::Alias(target) = obj else {
+                return Some((path, obj));
+            };
+            (path, obj) = self.objects.get_key_value(target)?;
+        }
+
+        None
+    }
+
+    pub fn get_mut(&mut self, path: &str) -> Option<&mut Object> {
+        self.objects.get_mut(path)
+    }
+
+    /// add a new object. reopening an existing scope-like node with `Object::Scope` is fine,
+    /// anything else that already exists is an error.
+    pub fn insert(&mut self, path: String, obj: Object) -> Result<(), &'static str> {
+        match self.objects.get_mut(&path) {
+            None => {
+                self.objects.insert(path, obj);
+                Ok(())
+            }
+            Some(_) if matches!(obj, Object::Scope) => Ok(()),
+            Some(existing @ Object::Scope) => {
+                *existing = obj;
+                Ok(())
+            }
+            Some(_) => Err("AML object already exists"),
+        }
+    }
+
+    /// remove `path` and everything below it
+    pub fn remove(&mut self, path: &str) {
+        self.objects.remove(path);
+
+        let prefix = child_prefix(path);
+        let doomed: Vec<String> = self
+            .objects
+            .range(prefix.clone()..)
+            .take_while(|(k, _)| k.starts_with(&prefix))
+            .map(|(k, _)| k.clone())
+            .collect();
+
+        for key in doomed {
+            self.objects.remove(&key);
+        }
+    }
+
+    /// find the absolute path `name` refers to from `scope`, applying the search rules of
+    /// ACPI 6.5 section 5.3 to single segment names
+    pub fn search(&self, scope: &str, name: &AmlNamePath) -> Result<Option<String>, &'static str> {
+        let decoded = NameString::decode(name.raw)?;
+
+        if !decoded.uses_search_rules() {
+            let path = resolve_decoded(scope, &decoded)?;
+            return Ok(self.contains(&path).then_some(path));
+        }
+
+        let mut current = Some(scope);
+        while let Some(scope) = current {
+            let path = resolve_decoded(scope, &decoded)?;
+            if self.contains(&path) {
+                return Ok(Some(path));
+            }
+            current = parent(scope);
+        }
+
+        Ok(None)
+    }
+
+    /// direct children of `path`, in path order
+    pub fn children<'n>(&'n self, path: &str) -> impl Iterator<Item = (&'n str, &'n Object)> {
+        let prefix = child_prefix(path);
+        let skip = prefix.len();
+
+        self.objects
+            .range(prefix.clone()..)
+            .take_while(move |(k, _)| k.starts_with(&prefix))
+            .filter(move |(k, _)| k.len() > skip && !k[skip..].contains('.'))
+            .map(|(k, v)| (k.as_str(), v))
+    }
+}
+
+fn child_prefix(path: &str) -> String {
+    if path == ROOT {
+        String::from(ROOT)
+    } else {
+        let mut prefix = String::from(path);
+        prefix.push('.');
+        prefix
+    }
+}
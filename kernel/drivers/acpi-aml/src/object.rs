@@ -0,0 +1,469 @@
+//! runtime objects: what the namespace holds and what AML expressions evaluate to.
+
+use core::{
+    fmt,
+    sync::atomic::{AtomicU32, AtomicU64, Ordering},
+};
+
+use alloc::{format, string::String, sync::Arc, vec::Vec};
+use klib::sync::UnfairSpinlock;
+
+/// buffers and packages are shared, so fields and `Index` references can write through to them.
+/// `Store` copies, see [`Object::deep_copy`].
+pub type Shared<T> = Arc<UnfairSpinlock<T>>;
+
+pub fn shared<T>(value: T) -> Shared<T> {
+    Arc::new(UnfairSpinlock::new(value))
+}
+
+#[derive(Debug, Clone, Default)]
+pub enum Object {
+    #[default]
+    Uninitialized,
+    Integer(u64),
+    String(String),
+    Buffer(Shared<Vec<u8>>),
+    Package(Shared<Vec<Object>>),
+    Method(Method),
+    Scope,
+    Device,
+    Processor {
+        id: u8,
+        pblk_address: u32,
+        pblk_len: u8,
+    },
+    PowerResource {
+        system_level: u8,
+        resource_order: u16,
+    },
+    ThermalZone,
+    Mutex(Arc<AmlMutex>),
+    Event(Arc<AtomicU64>),
+    OpRegion(OpRegion),
+    FieldUnit(FieldUnit),
+    BufferField(BufferField),
+    Reference(Reference),
+    /// another name for the object at this absolute path
+    Alias(String),
+    Debug,
+}
+
+/// `ObjectType` return values, ACPI 6.5 table 19.28
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ObjectType {
+    Uninitialized = 0,
+    Integer = 1,
+    String = 2,
+    Buffer = 3,
+    Package = 4,
+    FieldUnit = 5,
+    Device = 6,
+    Event = 7,
+    Method = 8,
+    Mutex = 9,
+    OpRegion = 10,
+    PowerResource = 11,
+    Processor = 12,
+    ThermalZone = 13,
+    BufferField = 14,
+    Debug = 16,
+}
+
+impl Object {
+    pub fn buffer(bytes: Vec<u8>) -> Self {
+        Object::Buffer(shared(bytes))
+    }
+
+    pub fn package(elements: Vec<Object>) -> Self {
+        Object::Package(shared(elements))
+    }
+
+    pub fn object_type(&self) -> ObjectType {
+        match self {
+            Object::Uninitialized | Object::Scope | Object::Alias(_) => ObjectType::Uninitialized,
+            Object::Integer(_) => ObjectType::Integer,
+            Object::String(_) => ObjectType::String,
+            Object::Buffer(_) => ObjectType::Buffer,
+            Object::Package(_) => ObjectType::Package,
+            Object::Method(_) => ObjectType::Method,
+            Object::Device => ObjectType::Device,
+            Object::Processor { .. } => ObjectType::Processor,
+            Object::PowerResource { .. } => ObjectType::PowerResource,
+            Object::ThermalZone => ObjectType::ThermalZone,
+            Object::Mutex(_) => ObjectType::Mutex,
+            Object::Event(_) => ObjectType::Event,
+            Object::OpRegion(_) => ObjectType::OpRegion,
+            Object::FieldUnit(_) => ObjectType::FieldUnit,
+            Object::BufferField(_) => ObjectType::BufferField,
+            Object::Reference(_) => ObjectType::Uninitialized,
+            Object::Debug => ObjectType::Debug,
+        }
+    }
+
+    /// copy for `Store`: buffers and packages get fresh storage, everything else is a value already
+    pub fn deep_copy(&self) -> Self {
+        match self {
+            Object::Buffer(buf) => Object::buffer(buf.lock().clone()),
+            Object::Package(pkg) => {
+                let elements = pkg.lock().iter().map(Object::deep_copy).collect();
+                Object::package(elements)
+            }
+            other => other.clone(),
+        }
+    }
+
+    pub fn as_integer(&self) -> Option<u64> {
+        match self {
+            Object::Integer(v) => Some(*v),
+            _ => None,
+        }
+    }
+
+    /// implicit conversion to an integer. strings are read as hex, buffers as little endian.
+    pub fn to_integer(&self) -> Result<u64, &'static str> {
+        match self {
+            Object::Integer(v) => Ok(*v),
+            Object::String(s) => Ok(parse_hex_prefix(s)),
+            Object::Buffer(buf) => {
+                let buf = buf.lock();
+                Ok(buf
+                    .iter()
+                    .take(8)
+                    .enumerate()
+                    .fold(0, |acc, (i, &b)| acc | (b as u64) << (i * 8)))
+            }
+            _ => Err("object can't be converted to an integer"),
+        }
+    }
+
+    /// implicit conversion to a buffer. `int_bytes` is 8, or 4 for revision 1 tables.
+    pub fn to_buffer(&self, int_bytes: usize) -> Result<Vec<u8>, &'static str> {
+        match self {
+            Object::Integer(v) => Ok(v.to_le_bytes()[..int_bytes].to_vec()),
+            Object::String(s) => {
+                let mut bytes = s.as_bytes().to_vec();
+                if !bytes.is_empty() {
+                    bytes.push(0);
+                }
+                Ok(bytes)
+            }
+            Object::Buffer(buf) => Ok(buf.lock().clone()),
+            _ => Err("object can't be converted to a buffer"),
+        }
+    }
+
+    /// implicit conversion to a string. integers become hex, buffers space separated hex bytes.
+    pub fn to_aml_string(&self, int_bytes: usize) -> Result<String, &'static str> {
+        match self {
+            Object::Integer(v) => Ok(format!("{:0width$X}", v, width = int_bytes * 2)),
+            Object::String(s) => Ok(s.clone()),
+            Object::Buffer(buf) => {
+                let buf = buf.lock();
+                let parts: Vec<String> = buf.iter().map(|b| format!("{b:02X}")).collect();
+                Ok(parts.join(" "))
+            }
+            _ => Err("object can't be converted to a string"),
+        }
+    }
+}
+
+/// leading hex digits of `s`, with or without a `0x` prefix
+fn parse_hex_prefix(s: &str) -> u64 {
+    let s = s.trim_start();
+    let s = s
+        .strip_prefix("0x")
+        .or_else(|| s.strip_prefix("0X"))
+        .unwrap_or(s);
+
+    s.chars()
+        .map_while(|c| c.to_digit(16))
+        .fold(0u64, |acc, d| acc.wrapping_shl(4) | d as u64)
+}
+
+#[derive(Debug, Clone)]
+pub struct Method {
+    pub code: Arc<[u8]>,
+    pub arg_count: u8,
+    /// serialized methods hold this for the duration of each invocation
+    pub serialize: Option<Arc<AmlMutex>>,
+    pub native: Option<NativeMethod>,
+}
+
+/// methods the interpreter implements itself, like `\_OSI`
+pub type NativeMethod = fn(&[Object]) -> Result<Object, &'static str>;
+
+impl Method {
+    pub fn from_flags(code: Arc<[u8]>, flags: u8) -> Self {
+        let serialize = ((flags & 0x08) != 0).then(|| Arc::new(AmlMutex::new(flags >> 4)));
+
+        Self {
+            code,
+            arg_count: flags & 0x07,
+            serialize,
+            native: None,
+        }
+    }
+
+    pub fn native(arg_count: u8, native: NativeMethod) -> Self {
+        Self {
+            code: Arc::from([]),
+            arg_count,
+            serialize: None,
+            native: Some(native),
+        }
+    }
+}
+
+/// an AML mutex. ownership is tracked per evaluation so a method may reacquire what it holds.
+#[derive(Debug)]
+pub struct AmlMutex {
+    pub sync_level: u8,
+    /// evaluation id of the owner, 0 when free
+    owner: AtomicU64,
+    depth: AtomicU32,
+}
+
+impl AmlMutex {
+    pub fn new(sync_level: u8) -> Self {
+        Self {
+            sync_level,
+            owner: AtomicU64::new(0),
+            depth: AtomicU32::new(0),
+        }
+    }
+
+    pub fn try_acquire(&self, owner: u64) -> bool {
+        match self
+            .owner
+            .compare_exchange(0, owner, Ordering::Acquire, Ordering::Relaxed)
+        {
+            Ok(_) => {
+                self.depth.store(1, Ordering::Relaxed);
+                true
+            }
+            Err(current) if current == owner => {
+                self.depth.fetch_add(1, Ordering::Relaxed);
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    pub fn release(&self, owner: u64) -> Result<(), &'static str> {
+        if self.owner.load(Ordering::Relaxed) != owner {
+            return Err("release of an AML mutex that isn't owned");
+        }
+
+        if self.depth.fetch_sub(1, Ordering::Relaxed) == 1 {
+            self.owner.store(0, Ordering::Release);
+        }
+
+        Ok(())
+    }
+}
+
+/// `RegionSpace` byte of an `OperationRegion`
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RegionSpace {
+    SystemMemory,
+    SystemIo,
+    PciConfig,
+    EmbeddedControl,
+    SmBus,
+    SystemCmos,
+    PciBarTarget,
+    Ipmi,
+    GeneralPurposeIo,
+    GenericSerialBus,
+    Pcc,
+    Oem(u8),
+}
+
+impl From<u8> for RegionSpace {
+    fn from(value: u8) -> Self {
+        match value {
+            0x00 => RegionSpace::SystemMemory,
+            0x01 => RegionSpace::SystemIo,
+            0x02 => RegionSpace::PciConfig,
+            0x03 => RegionSpace::EmbeddedControl,
+            0x04 => RegionSpace::SmBus,
+            0x05 => RegionSpace::SystemCmos,
+            0x06 => RegionSpace::PciBarTarget,
+            0x07 => RegionSpace::Ipmi,
+            0x08 => RegionSpace::GeneralPurposeIo,
+            0x09 => RegionSpace::GenericSerialBus,
+            0x0A => RegionSpace::Pcc,
+            other => RegionSpace::Oem(other),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct OpRegion {
+    pub space: RegionSpace,
+    pub offset: u64,
+    pub length: u64,
+    /// scope the region was declared in. PCI_Config regions find `_ADR`, `_SEG` and `_BBN` from here.
+    pub parent: String,
+}
+
+/// `AccessType` bits of `FieldFlags`
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FieldAccess {
+    Any,
+    Byte,
+    Word,
+    DWord,
+    QWord,
+    Buffer,
+}
+
+impl FieldAccess {
+    pub fn from_flags(flags: u8) -> Self {
+        match flags & 0x0F {
+            1 => FieldAccess::Byte,
+            2 => FieldAccess::Word,
+            3 => FieldAccess::DWord,
+            4 => FieldAccess::QWord,
+            5 => FieldAccess::Buffer,
+            _ => FieldAccess::Any,
+        }
+    }
+}
+
+/// `UpdateRule` bits of `FieldFlags`: what to do with the bits of a partially written access unit
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum UpdateRule {
+    Preserve,
+    WriteAsOnes,
+    WriteAsZeros,
+}
+
+impl UpdateRule {
+    pub fn from_flags(flags: u8) -> Self {
+        match (flags >> 5) & 0x03 {
+            1 => UpdateRule::WriteAsOnes,
+            2 => UpdateRule::WriteAsZeros,
+            _ => UpdateRule::Preserve,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum FieldKind {
+    /// a `Field` of the region at this path
+    Normal { region: String },
+    /// a `BankField`: `value` goes into the `bank` field before every access to `region`
+    Bank {
+        region: String,
+        bank: String,
+        value: u64,
+    },
+    /// an `IndexField`: the byte offset goes into the `index` field, then `data` is accessed
+    Index { index: String, data: String },
+}
+
+#[derive(Debug, Clone)]
+pub struct FieldUnit {
+    pub kind: FieldKind,
+    pub bit_offset: u64,
+    pub bit_len: u64,
+    pub access: FieldAccess,
+    pub update: UpdateRule,
+}
+
+impl FieldUnit {
+    /// width of a single access in bits
+    pub fn access_bits(&self) -> u64 {
+        match self.access {
+            FieldAccess::Byte | FieldAccess::Buffer => 8,
+            FieldAccess::Word => 16,
+            FieldAccess::DWord => 32,
+            FieldAccess::QWord => 64,
+            // the narrowest naturally aligned access covering the whole field
+            FieldAccess::Any => [8, 16, 32, 64]
+                .into_iter()
+                .find(|w| self.bit_offset / w == (self.bit_offset + self.bit_len.max(1) - 1) / w)
+                .unwrap_or(64),
+        }
+    }
+}
+
+/// a `CreateField` family window into a buffer
+#[derive(Debug, Clone)]
+pub struct BufferField {
+    pub buffer: Shared<Vec<u8>>,
+    pub bit_offset: usize,
+    pub bit_len: usize,
+}
+
+#[derive(Debug, Clone)]
+pub enum Reference {
+    /// `RefOf` a named object, or a name inside a package
+    Name(String),
+    /// element `index` of a package
+    PackageElement {
+        package: Shared<Vec<Object>>,
+        index: usize,
+    },
+    /// byte `index` of a buffer
+    BufferByte {
+        buffer: Shared<Vec<u8>>,
+        index: usize,
+    },
+}
+
+/// `len` bits of `bytes` starting at bit `offset`, little endian. `len` is at most 64.
+pub fn get_bits(bytes: &[u8], offset: usize, len: usize) -> u64 {
+    (0..len).fold(0, |acc, i| {
+        let bit = offset + i;
+        let set = bytes
+            .get(bit / 8)
+            .is_some_and(|b| (b >> (bit % 8)) & 1 != 0);
+        acc | (set as u64) << i
+    })
+}
+
+/// write the low `len` bits of `value` into `bytes` at bit `offset`. `len` is at most 64.
+pub fn set_bits(bytes: &mut [u8], offset: usize, len: usize, value: u64) {
+    for i in 0..len {
+        let bit = offset + i;
+        if let Some(b) = bytes.get_mut(bit / 8) {
+            if (value >> i) & 1 != 0 {
+                *b |= 1 << (bit % 8);
+            } else {
+                *b &= !(1 << (bit % 8));
+            }
+        }
+    }
+}
+
+impl fmt::Display for Object {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Object::Integer(v) => write!(f, "{v:#X}"),
+            Object::String(s) => write!(f, "\"{s}\""),
+            Object::Buffer(buf) => {
+                let buf = buf.lock();
+                write!(f, "Buffer ({:#X}) {{", buf.len())?;
+                for (i, b) in buf.iter().enumerate() {
+                    let sep = if i == 0 { " " } else { ", " };
+                    write!(f, "{sep}{b:#04X}")?;
+                }
+                f.write_str(" }")
+            }
+            Object::Package(pkg) => {
+                let pkg = pkg.lock();
+                write!(f, "Package ({:#X}) {{", pkg.len())?;
+                for (i, elem) in pkg.iter().enumerate() {
+                    let sep = if i == 0 { " " } else { ", " };
+                    write!(f, "{sep}{elem}")?;
+                }
+                f.write_str(" }")
+            }
+            Object::Reference(Reference::Name(path)) | Object::Alias(path) => f.write_str(path),
+            other => write!(f, "[{:?}]", other.object_type()),
+        }
+    }
+}
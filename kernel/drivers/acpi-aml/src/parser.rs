@@ -18,6 +18,11 @@ impl<'a> AmlParser<'a> {
         self.cursor >= self.bytes.len()
     }
 
+    #[inline(always)]
+    pub(crate) fn position(&self) -> usize {
+        self.cursor
+    }
+
     pub fn parse_next(&mut self) -> Result<Option<AmlTerm<'a>>, &'static str> {
         if self.is_empty() {
             return Ok(None);
@@ -262,7 +267,7 @@ impl<'a> AmlParser<'a> {
     }
 
     #[inline(always)]
-    fn read_u8(&mut self) -> Result<u8, &'static str> {
+    pub(crate) fn read_u8(&mut self) -> Result<u8, &'static str> {
         let b = self
             .bytes
             .get(self.cursor)
@@ -273,27 +278,27 @@ impl<'a> AmlParser<'a> {
     }
 
     #[inline(always)]
-    fn peek_u8(&self) -> Option<u8> {
+    pub(crate) fn peek_u8(&self) -> Option<u8> {
         self.bytes.get(self.cursor).copied()
     }
 
-    fn read_u16_le(&mut self) -> Result<u16, &'static str> {
+    pub(crate) fn read_u16_le(&mut self) -> Result<u16, &'static str> {
         let b = self.take_bytes(2)?;
         Ok(u16::from_le_bytes(b.try_into().unwrap()))
     }
 
-    fn read_u32_le(&mut self) -> Result<u32, &'static str> {
+    pub(crate) fn read_u32_le(&mut self) -> Result<u32, &'static str> {
         let b = self.take_bytes(4)?;
         Ok(u32::from_le_bytes(b.try_into().unwrap()))
     }
 
-    fn read_u64_le(&mut self) -> Result<u64, &'static str> {
+    pub(crate) fn read_u64_le(&mut self) -> Result<u64, &'static str> {
         let b = self.take_bytes(8)?;
         Ok(u64::from_le_bytes(b.try_into().unwrap()))
     }
 
     #[inline(always)]
-    fn take_bytes(&mut self, len: usize) -> Result<&'a [u8], &'static str> {
+    pub(crate) fn take_bytes(&mut self, len: usize) -> Result<&'a [u8], &'static str> {
         if self.cursor + len <= self.bytes.len() {
             let slice = &self.bytes[self.cursor..self.cursor + len];
             self.cursor += len;
@@ -303,7 +308,7 @@ impl<'a> AmlParser<'a> {
         }
     }
 
-    fn remaining_bytes(&self) -> &'a [u8] {
+    pub(crate) fn remaining_bytes(&self) -> &'a [u8] {
         &self.bytes[self.cursor..]
     }
 }
@@ -0,0 +1,103 @@
+//! PCI interrupt routing: which interrupt each INTx pin of a root bus device raises, from the
+//! host bridge's `_PRT`. ACPI 6.5 section 6.2.13.
+
+use alloc::vec::Vec;
+use klib::hardware::resource::{Irq, IrqPolarity, Resource};
+use log::warn;
+
+use crate::{
+    crs::{CrsIter, DecodeCrs},
+    interpreter::{Interpreter, interpreter},
+    namespace,
+    object::{Object, Reference},
+};
+
+#[derive(Debug, Copy, Clone)]
+pub struct PrtEntry {
+    pub device: u8,
+    /// 0 is INTA
+    pub pin: u8,
+    pub irq: Irq,
+}
+
+/// the routing table of the host bridge at `path`. empty if it has none.
+pub fn routing_table(path: &str) -> Vec<PrtEntry> {
+    let Some(interpreter) = interpreter() else {
+        return Vec::new();
+    };
+
+    let method = namespace::child(path, "_PRT");
+    if !interpreter.contains(&method) {
+        return Vec::new();
+    }
+
+    let table = match interpreter.evaluate(&method, Vec::new()) {
+        Ok(Object::Package(table)) => table,
+        Ok(_) => {
+            warn!("AML: {method} isn't a package");
+            return Vec::new();
+        }
+        Err(e) => {
+            warn!("AML: evaluating {method} failed: {e}");
+            return Vec::new();
+        }
+    };
+
+    let table = table.lock();
+    table
+        .iter()
+        .filter_map(|entry| {
+            let Object::Package(entry) = entry else {
+                return None;
+            };
+            let entry = entry.lock();
+
+            // the device in the high word, the low word is 0xFFFF for any function
+            let address = entry.first()?.as_integer()?;
+            let pin = entry.get(1)?.as_integer()?;
+            let index = entry.get(3)?.as_integer()?;
+
+            let irq = match entry.get(2)? {
+                // a hardwired GSI: level triggered, active low and shareable
+                Object::Integer(0) => Irq {
+                    polarity: IrqPolarity::ActiveLow,
+                    shared: true,
+                    ..Irq::new(index as u32)
+                },
+                Object::Reference(Reference::Name(link)) | Object::String(link) => {
+                    link_irq(interpreter, link, index as usize)?
+                }
+                _ => return None,
+            };
+
+            Some(PrtEntry {
+                device: (address >> 16) as u8,
+                pin: pin as u8,
+                irq,
+            })
+        })
+        .collect()
+}
+
+/// the interrupt an interrupt link device, PNP0C0F, is set to
+fn link_irq(interpreter: &Interpreter, link: &str, index: usize) -> Option<Irq> {
+    let crs = namespace::child(link, "_CRS");
+    let buf = match interpreter.evaluate(&crs, Vec::new()) {
+        Ok(Object::Buffer(buf)) => buf,
+        _ => {
+            warn!("AML: no interrupt from link {link}");
+            return None;
+        }
+    };
+
+    let buf = buf.lock();
+    let irqs = CrsIter(&buf)
+        .flat_map(|chunk| chunk.into_rss())
+        .filter_map(|r| match r {
+            Resource::Irq(irq) => Some(irq),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+
+    irqs.get(index).or(irqs.first()).copied()
+}
@@ -0,0 +1,91 @@
+use core::mem;
+
+use crate::acpi::AcpiTableTrait;
+use crate::impl_table;
+
+use super::FromBytes;
+use super::{GenericAddress, header::SdtHeader};
+use hax_lib::{attributes, ensures, opaque, requires};
+
+/// serial ports. their `Port Subtype` is a `SerialInterface`.
+pub const DBG2_PORT_SERIAL: u16 = 0x8000;
+
+impl_table! {
+    #[derive(Debug, Clone, Copy)]
+    pub struct Dbg2 {
+        pub header: SdtHeader,
+        pub device_info_offset: u32,
+        pub device_info_count: u32,
+    }
+}
+
+#[attributes]
+impl AcpiTableTrait for Dbg2 {
+    #[opaque]
+    #[requires(slice.len() as usize >= core::mem::size_of::<Self>())]
+    #[ensures(|result| result.is_ok())]
+    fn safe_table_cast(slice: &'static [u8]) -> Result<&'static Self, &'static str> {
+        let (reference, _) = Self::ref_from_prefix(slice).map_err(|_| "alignment/size error")?;
+        Ok(reference)
+    }
+}
+
+impl_table! {
+    #[derive(Debug, Clone, Copy)]
+    pub struct Dbg2DeviceInfo {
+        pub revision: u8,
+        pub len: u16,
+        pub address_count: u8,
+        pub namespace_len: u16,
+        pub namespace_offset: u16,
+        pub oem_data_len: u16,
+        pub oem_data_offset: u16,
+        pub port_type: u16,
+        pub port_subtype: u16,
+        pub reserved: u16,
+        pub base_address_offset: u16,
+        pub address_size_offset: u16,
+    }
+}
+
+/// a debug device, and the bytes it spans. its offsets count from the start of those.
+pub type Dbg2Device<'a> = (&'a Dbg2DeviceInfo, &'a [u8]);
+
+impl Dbg2DeviceInfo {
+    /// `address_count` registers
+    pub fn base_addresses<'a>(
+        &self,
+        bytes: &'a [u8],
+    ) -> impl Iterator<Item = &'a GenericAddress> + use<'a> {
+        let start = self.base_address_offset() as usize;
+        let gas_len = mem::size_of::<GenericAddress>();
+
+        bytes
+            .get(start..start + self.address_count() as usize * gas_len)
+            .unwrap_or(&[])
+            .chunks_exact(gas_len)
+            .filter_map(|gas| GenericAddress::ref_from_bytes(gas).ok())
+    }
+}
+
+impl Dbg2 {
+    /// `table` is this table's bytes
+    pub fn devices<'a>(&self, table: &'a [u8]) -> impl Iterator<Item = Dbg2Device<'a>> + use<'a> {
+        let mut offset = self.device_info_offset() as usize;
+        let mut remaining = self.device_info_count();
+
+        core::iter::from_fn(move || {
+            if remaining == 0 {
+                return None;
+            }
+            remaining -= 1;
+
+            let rest = table.get(offset..)?;
+            let (info, _) = Dbg2DeviceInfo::ref_from_prefix(rest).ok()?;
+            let bytes = rest.get(..info.len() as usize)?;
+
+            offset += (info.len() as usize).max(mem::size_of::<Dbg2DeviceInfo>());
+            Some((info, bytes))
+        })
+    }
+}
@@ -0,0 +1,184 @@
+//! IO remapping table: how requester IDs travel through SMMUs to the ITS.
+
+use core::mem;
+
+use crate::acpi::AcpiTableTrait;
+use crate::impl_table;
+
+use super::FromBytes;
+use super::header::SdtHeader;
+use hax_lib::{attributes, ensures, opaque, requires};
+
+pub const IORT_NODE_ITS_GROUP: u8 = 0;
+pub const IORT_NODE_NAMED_COMPONENT: u8 = 1;
+pub const IORT_NODE_ROOT_COMPLEX: u8 = 2;
+pub const IORT_NODE_SMMU_V1_V2: u8 = 3;
+pub const IORT_NODE_SMMU_V3: u8 = 4;
+
+/// ID mapping flag: the mapping is for the node's own MSIs, not for IDs coming in
+pub const IORT_ID_SINGLE_MAPPING: u32 = 1 << 0;
+
+/// SMMUv3 register space, both 64KiB pages
+pub const SMMU_V3_MMIO_SIZE: usize = 0x2_0000;
+
+impl_table! {
+    #[derive(Debug, Clone, Copy)]
+    pub struct Iort {
+        pub header: SdtHeader,
+        pub node_count: u32,
+        pub node_offset: u32,
+        pub reserved: u32,
+    }
+}
+
+#[attributes]
+impl AcpiTableTrait for Iort {
+    #[opaque]
+    #[requires(slice.len() as usize >= core::mem::size_of::<Self>())]
+    #[ensures(|result| result.is_ok())]
+    fn safe_table_cast(slice: &'static [u8]) -> Result<&'static Self, &'static str> {
+        let (reference, _) = Self::ref_from_prefix(slice).map_err(|_| "alignment/size error")?;
+        Ok(reference)
+    }
+}
+
+impl_table! {
+    #[derive(Debug, Clone, Copy)]
+    pub struct IortNodeHeader {
+        pub node_type: u8,
+        pub len: u16,
+        pub revision: u8,
+        pub identifier: u32,
+        pub id_mapping_count: u32,
+        pub id_mapping_offset: u32,
+    }
+}
+
+impl_table! {
+    #[derive(Debug, Clone, Copy)]
+    pub struct IortIdMapping {
+        pub input_base: u32,
+        /// one less than the number of IDs
+        pub id_count: u32,
+        pub output_base: u32,
+        /// offset of the node the IDs go to, from the start of the table
+        pub output_reference: u32,
+        pub flags: u32,
+    }
+}
+
+impl IortIdMapping {
+    /// the output ID of `id`, if it's in range
+    pub fn map(&self, id: u32) -> Option<u32> {
+        let offset = id.checked_sub(self.input_base())?;
+        (offset <= self.id_count()).then(|| self.output_base() + offset)
+    }
+
+    /// first and last input ID
+    pub fn inputs(&self) -> (u32, u32) {
+        (
+            self.input_base(),
+            self.input_base().saturating_add(self.id_count()),
+        )
+    }
+}
+
+impl_table! {
+    #[derive(Debug, Clone, Copy)]
+    pub struct IortRootComplex {
+        pub header: IortNodeHeader,
+        pub cache_coherent: u32,
+        pub allocation_hints: u8,
+        pub reserved: u16,
+        pub memory_access_flags: u8,
+        pub ats_attribute: u32,
+        pub pci_segment: u32,
+    }
+}
+
+impl_table! {
+    #[derive(Debug, Clone, Copy)]
+    pub struct IortSmmuV3 {
+        pub header: IortNodeHeader,
+        pub base_address: u64,
+        pub flags: u32,
+        pub reserved: u32,
+        pub vatos_address: u64,
+        pub model: u32,
+        pub event_gsiv: u32,
+        pub pri_gsiv: u32,
+        pub gerr_gsiv: u32,
+        pub sync_gsiv: u32,
+        pub proximity_domain: u32,
+        /// which ID mapping is the SMMU's own, for its MSIs
+        pub device_id_mapping_index: u32,
+    }
+}
+
+/// a node and the bytes it spans. its offsets count from the start of those.
+#[derive(Debug, Clone, Copy)]
+pub struct IortNode<'a> {
+    /// from the start of the table, which is how ID mappings refer to nodes
+    pub offset: usize,
+    pub header: &'a IortNodeHeader,
+    pub bytes: &'a [u8],
+}
+
+impl<'a> IortNode<'a> {
+    pub fn id_mappings(&self) -> impl Iterator<Item = &'a IortIdMapping> + use<'a> {
+        let start = self.header.id_mapping_offset() as usize;
+        let len = self.header.id_mapping_count() as usize * mem::size_of::<IortIdMapping>();
+
+        self.bytes
+            .get(start..start + len)
+            .unwrap_or(&[])
+            .chunks_exact(mem::size_of::<IortIdMapping>())
+            .filter_map(|mapping| IortIdMapping::ref_from_bytes(mapping).ok())
+    }
+
+    pub fn root_complex(&self) -> Option<&'a IortRootComplex> {
+        (self.header.node_type() == IORT_NODE_ROOT_COMPLEX)
+            .then(|| IortRootComplex::ref_from_prefix(self.bytes).ok())
+            .flatten()
+            .map(|(node, _)| node)
+    }
+
+    pub fn smmu_v3(&self) -> Option<&'a IortSmmuV3> {
+        (self.header.node_type() == IORT_NODE_SMMU_V3)
+            .then(|| IortSmmuV3::ref_from_prefix(self.bytes).ok())
+            .flatten()
+            .map(|(node, _)| node)
+    }
+}
+
+impl Iort {
+    /// `table` is this table's bytes
+    pub fn nodes<'a>(&self, table: &'a [u8]) -> impl Iterator<Item = IortNode<'a>> + use<'a> {
+        let mut offset = self.node_offset() as usize;
+        let mut remaining = self.node_count();
+
+        core::iter::from_fn(move || {
+            if remaining == 0 {
+                return None;
+            }
+            remaining -= 1;
+
+            let node = node_at(table, offset)?;
+            offset += (node.header.len() as usize).max(mem::size_of::<IortNodeHeader>());
+            Some(node)
+        })
+    }
+}
+
+/// the node an ID mapping's `output_reference` points at
+pub fn node_at(table: &[u8], offset: usize) -> Option<IortNode<'_>> {
+    let rest = table.get(offset..)?;
+    let (header, _) = IortNodeHeader::ref_from_prefix(rest).ok()?;
+    let bytes = rest.get(..header.len() as usize)?;
+
+    Some(IortNode {
+        offset,
+        header,
+        bytes,
+    })
+}
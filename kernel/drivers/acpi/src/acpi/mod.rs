@@ -1,9 +1,14 @@
+pub mod dbg2;
 pub mod fadt;
 pub mod gtdt;
 pub mod header;
+pub mod iort;
 pub mod madt;
 pub mod mcfg;
+pub mod pptt;
+pub mod slit;
 pub mod spcr;
+pub mod srat;
 pub mod xsdp;
 
 use hax_lib::{attributes, ensures, exclude, opaque, requires};
@@ -0,0 +1,145 @@
+//! processor properties topology table: which processors share a package, cluster or core, and
+//! the caches at each of those.
+
+use core::mem;
+
+use crate::acpi::AcpiTableTrait;
+use crate::impl_table;
+
+use super::FromBytes;
+use super::header::SdtHeader;
+use hax_lib::{attributes, ensures, opaque, requires};
+
+pub const PPTT_PROCESSOR: u8 = 0;
+pub const PPTT_CACHE: u8 = 1;
+
+/// processor flags
+pub const PPTT_PHYSICAL_PACKAGE: u32 = 1 << 0;
+pub const PPTT_ACPI_ID_VALID: u32 = 1 << 1;
+pub const PPTT_THREAD: u32 = 1 << 2;
+pub const PPTT_LEAF: u32 = 1 << 3;
+
+/// cache flags: which of the fields firmware filled in
+pub const PPTT_CACHE_SIZE_VALID: u32 = 1 << 0;
+pub const PPTT_CACHE_SETS_VALID: u32 = 1 << 1;
+pub const PPTT_CACHE_ASSOCIATIVITY_VALID: u32 = 1 << 2;
+pub const PPTT_CACHE_TYPE_VALID: u32 = 1 << 4;
+pub const PPTT_CACHE_LINE_SIZE_VALID: u32 = 1 << 6;
+
+/// cache attributes, bits 3:2
+pub const PPTT_CACHE_TYPE_SHIFT: u8 = 2;
+pub const PPTT_CACHE_TYPE_MASK: u8 = 0b11;
+pub const PPTT_CACHE_TYPE_DATA: u8 = 0b00;
+pub const PPTT_CACHE_TYPE_INSTRUCTION: u8 = 0b01;
+
+impl_table! {
+    #[derive(Debug, Clone, Copy)]
+    pub struct Pptt {
+        pub header: SdtHeader,
+    }
+}
+
+#[attributes]
+impl AcpiTableTrait for Pptt {
+    #[opaque]
+    #[requires(slice.len() as usize >= core::mem::size_of::<Self>())]
+    #[ensures(|result| result.is_ok())]
+    fn safe_table_cast(slice: &'static [u8]) -> Result<&'static Self, &'static str> {
+        let (reference, _) = Self::ref_from_prefix(slice).map_err(|_| "alignment/size error")?;
+        Ok(reference)
+    }
+}
+
+impl_table! {
+    #[derive(Debug, Clone, Copy)]
+    pub struct PpttEntryHeader {
+        pub entry_type: u8,
+        pub len: u8,
+        pub reserved: u16,
+    }
+}
+
+impl_table! {
+    #[derive(Debug, Clone, Copy)]
+    pub struct PpttProcessor {
+        pub header: PpttEntryHeader,
+        pub flags: u32,
+        /// offset of the parent node from the start of the table, 0 for the root
+        pub parent: u32,
+        pub acpi_processor_id: u32,
+        pub private_resource_count: u32,
+    }
+}
+
+impl_table! {
+    #[derive(Debug, Clone, Copy)]
+    pub struct PpttCache {
+        pub header: PpttEntryHeader,
+        pub flags: u32,
+        /// offset of the cache behind this one, 0 if it's the last
+        pub next_level: u32,
+        pub size: u32,
+        pub sets: u32,
+        pub associativity: u8,
+        pub attributes: u8,
+        pub line_size: u16,
+    }
+}
+
+impl PpttCache {
+    pub fn cache_type(&self) -> u8 {
+        (self.attributes() >> PPTT_CACHE_TYPE_SHIFT) & PPTT_CACHE_TYPE_MASK
+    }
+}
+
+/// an entry and where it is, which is how the others refer to it
+#[derive(Debug, Clone, Copy)]
+pub enum PpttEntry<'a> {
+    /// the offsets of its private resources, usually caches, follow it
+    Processor(usize, &'a PpttProcessor, &'a [u8]),
+    Cache(usize, &'a PpttCache),
+    Other(usize, u8),
+}
+
+impl PpttProcessor {
+    /// offsets of the private resources. `bytes` is the whole entry.
+    pub fn private_resources<'a>(&self, bytes: &'a [u8]) -> impl Iterator<Item = usize> + use<'a> {
+        let count = self.private_resource_count() as usize;
+
+        bytes
+            .get(mem::size_of::<Self>()..)
+            .unwrap_or(&[])
+            .chunks_exact(4)
+            .take(count)
+            .map(|offset| u32::from_le_bytes([offset[0], offset[1], offset[2], offset[3]]) as usize)
+    }
+}
+
+impl Pptt {
+    /// `table` is this table's bytes
+    pub fn entries<'a>(&self, table: &'a [u8]) -> impl Iterator<Item = PpttEntry<'a>> + use<'a> {
+        let mut offset = mem::size_of::<Self>();
+
+        core::iter::from_fn(move || {
+            let rest = table.get(offset..)?;
+            let (header, _) = PpttEntryHeader::ref_from_prefix(rest).ok()?;
+            let bytes = rest.get(..header.len() as usize)?;
+            if bytes.len() < mem::size_of::<PpttEntryHeader>() {
+                return None;
+            }
+
+            let entry = match header.entry_type() {
+                PPTT_PROCESSOR => PpttEntry::Processor(
+                    offset,
+                    PpttProcessor::ref_from_prefix(bytes).ok()?.0,
+                    bytes,
+                ),
+                PPTT_CACHE => PpttEntry::Cache(offset, PpttCache::ref_from_prefix(bytes).ok()?.0),
+                other => PpttEntry::Other(offset, other),
+            };
+
+            offset += bytes.len();
+            Some(entry)
+        })
+    }
+}
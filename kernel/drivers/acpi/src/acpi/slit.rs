@@ -0,0 +1,49 @@
+//! system locality information table: the relative distance between each pair of proximity
+//! domains.
+
+use core::mem;
+
+use crate::acpi::AcpiTableTrait;
+use crate::impl_table;
+
+use super::FromBytes;
+use super::header::SdtHeader;
+use hax_lib::{attributes, ensures, opaque, requires};
+
+/// a domain can't reach the other
+pub const SLIT_UNREACHABLE: u8 = 0xFF;
+
+impl_table! {
+    #[derive(Debug, Clone, Copy)]
+    pub struct Slit {
+        pub header: SdtHeader,
+        pub localities: u64,
+    }
+}
+
+#[attributes]
+impl AcpiTableTrait for Slit {
+    #[opaque]
+    #[requires(slice.len() as usize >= core::mem::size_of::<Self>())]
+    #[ensures(|result| result.is_ok())]
+    fn safe_table_cast(slice: &'static [u8]) -> Result<&'static Self, &'static str> {
+        let (reference, _) = Self::ref_from_prefix(slice).map_err(|_| "alignment/size error")?;
+        Ok(reference)
+    }
+}
+
+impl Slit {
+    /// from domain `from` to domain `to`. `table` is this table's bytes.
+    pub fn distance(&self, table: &[u8], from: u32, to: u32) -> Option<u8> {
+        let localities = self.localities();
+        if from as u64 >= localities || to as u64 >= localities {
+            return None;
+        }
+
+        let index = from as u64 * localities + to as u64;
+        table
+            .get(mem::size_of::<Self>()..)?
+            .get(usize::try_from(index).ok()?)
+            .copied()
+    }
+}
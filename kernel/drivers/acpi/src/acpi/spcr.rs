@@ -42,3 +42,79 @@ impl AcpiTableTrait for Spcr {
         Ok(reference)
     }
 }
+
+/// `Interface Type` in SPCR, and the `Port Subtype` of serial ports in DBG2. see the Microsoft
+/// Debug Port Table 2 specification, table 3.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SerialInterface {
+    /// fully 16550 compatible
+    Ns16550,
+    /// the 16450 subset of 16550
+    Ns16550Subset,
+    Pl011,
+    /// SBSA generic UART restricted to 32 bit accesses. deprecated.
+    SbsaGeneric32,
+    /// the PL011 subset from the Server Base System Architecture
+    SbsaGeneric,
+    /// 16550 compatible, with the register width given by the Generic Address Structure
+    Ns16550Gas,
+    Other(u16),
+}
+
+impl From<u16> for SerialInterface {
+    fn from(value: u16) -> Self {
+        match value {
+            0x00 => Self::Ns16550,
+            0x01 => Self::Ns16550Subset,
+            0x03 => Self::Pl011,
+            0x0D => Self::SbsaGeneric32,
+            0x0E => Self::SbsaGeneric,
+            0x12 => Self::Ns16550Gas,
+            other => Self::Other(other),
+        }
+    }
+}
+
+/// `UART Clock Frequency`, added in revision 3
+const SPCR_UART_CLOCK_OFFSET: usize = 80;
+/// `Precise Baud Rate`, added in revision 4
+const SPCR_PRECISE_BAUD_OFFSET: usize = 84;
+
+impl Spcr {
+    pub fn interface(&self) -> SerialInterface {
+        SerialInterface::from(self.interface_type() as u16)
+    }
+
+    /// the configured baud rate. `None` means the OS should keep what firmware programmed.
+    pub fn baud(&self) -> Option<u32> {
+        let precise = self.extension_u32(4, SPCR_PRECISE_BAUD_OFFSET);
+        if precise != 0 {
+            return Some(precise);
+        }
+
+        match self.baud_rate() {
+            3 => Some(9600),
+            4 => Some(19200),
+            6 => Some(57600),
+            7 => Some(115200),
+            _ => None,
+        }
+    }
+
+    /// input clock of the UART in Hz, if the table is new enough to say
+    pub fn uart_clock(&self) -> Option<u32> {
+        Some(self.extension_u32(3, SPCR_UART_CLOCK_OFFSET)).filter(|&clock| clock != 0)
+    }
+
+    /// a field after the revision 2 layout, or 0 when the table predates it
+    #[opaque]
+    fn extension_u32(&self, revision: u8, offset: usize) -> u32 {
+        if self.header.rev() < revision || (self.header.len() as usize) < offset + 4 {
+            return 0;
+        }
+
+        unsafe {
+            core::ptr::read_unaligned((self as *const _ as *const u8).add(offset) as *const u32)
+        }
+    }
+}
@@ -0,0 +1,103 @@
+//! system resource affinity table: which proximity domain memory ranges and processors are in.
+
+use core::mem;
+
+use crate::acpi::AcpiTableTrait;
+use crate::impl_table;
+
+use super::FromBytes;
+use super::header::SdtHeader;
+use hax_lib::{attributes, ensures, opaque, requires};
+
+pub const SRAT_MEMORY: u8 = 1;
+pub const SRAT_GICC: u8 = 3;
+
+/// memory and GICC affinity flags
+pub const SRAT_ENABLED: u32 = 1 << 0;
+pub const SRAT_MEMORY_HOT_PLUGGABLE: u32 = 1 << 1;
+pub const SRAT_MEMORY_NON_VOLATILE: u32 = 1 << 2;
+
+impl_table! {
+    #[derive(Debug, Clone, Copy)]
+    pub struct Srat {
+        pub header: SdtHeader,
+        pub reserved1: u32,
+        pub reserved2: u64,
+    }
+}
+
+#[attributes]
+impl AcpiTableTrait for Srat {
+    #[opaque]
+    #[requires(slice.len() as usize >= core::mem::size_of::<Self>())]
+    #[ensures(|result| result.is_ok())]
+    fn safe_table_cast(slice: &'static [u8]) -> Result<&'static Self, &'static str> {
+        let (reference, _) = Self::ref_from_prefix(slice).map_err(|_| "alignment/size error")?;
+        Ok(reference)
+    }
+}
+
+impl_table! {
+    #[derive(Debug, Clone, Copy)]
+    pub struct SratEntryHeader {
+        pub entry_type: u8,
+        pub len: u8,
+    }
+}
+
+impl_table! {
+    #[derive(Debug, Clone, Copy)]
+    pub struct SratMemory {
+        pub header: SratEntryHeader,
+        pub proximity_domain: u32,
+        pub reserved1: u16,
+        pub base: u64,
+        pub length: u64,
+        pub reserved2: u32,
+        pub flags: u32,
+        pub reserved3: u64,
+    }
+}
+
+impl_table! {
+    #[derive(Debug, Clone, Copy)]
+    pub struct SratGicc {
+        pub header: SratEntryHeader,
+        pub proximity_domain: u32,
+        pub acpi_processor_uid: u32,
+        pub flags: u32,
+        pub clock_domain: u32,
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum SratEntry<'a> {
+    Memory(&'a SratMemory),
+    Gicc(&'a SratGicc),
+    Other(u8),
+}
+
+impl Srat {
+    /// `table` is this table's bytes
+    pub fn entries<'a>(&self, table: &'a [u8]) -> impl Iterator<Item = SratEntry<'a>> + use<'a> {
+        let mut offset = mem::size_of::<Self>();
+
+        core::iter::from_fn(move || {
+            let rest = table.get(offset..)?;
+            let (header, _) = SratEntryHeader::ref_from_prefix(rest).ok()?;
+            let bytes = rest.get(..header.len() as usize)?;
+            if bytes.len() < mem::size_of::<SratEntryHeader>() {
+                return None;
+            }
+
+            let entry = match header.entry_type() {
+                SRAT_MEMORY => SratEntry::Memory(SratMemory::ref_from_prefix(bytes).ok()?.0),
+                SRAT_GICC => SratEntry::Gicc(SratGicc::ref_from_prefix(bytes).ok()?.0),
+                other => SratEntry::Other(other),
+            };
+
+            offset += bytes.len();
+            Some(entry)
+        })
+    }
+}
@@ -0,0 +1,353 @@
+//! flattened device trees (DTBs), see chapter 5 of the devicetree specification.
+//!
+//! the blob is parsed once into an arena of nodes borrowing from it. addresses in `reg` are
+//! translated through each parent bus's `ranges`, and `interrupts` are split into specifiers
+//! for whichever controller the node's `interrupt-parent` points to.
+
+#![no_std]
+
+extern crate alloc;
+
+mod node;
+
+use core::{error::Error, fmt};
+
+use alloc::vec::Vec;
+
+pub use node::{Node, NodeId, Property};
+
+pub const FDT_MAGIC: u32 = 0xD00D_FEED;
+
+/// every blob since version 16 is readable by a version 16 parser
+const FDT_COMPATIBLE_VERSION: u32 = 16;
+
+const HEADER_LEN: usize = 40;
+
+// structure block tokens
+const FDT_BEGIN_NODE: u32 = 1;
+const FDT_END_NODE: u32 = 2;
+const FDT_PROP: u32 = 3;
+const FDT_NOP: u32 = 4;
+const FDT_END: u32 = 9;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FdtError {
+    BadMagic(u32),
+    UnsupportedVersion(u32),
+    Truncated,
+    BadToken(u32),
+    BadStructure,
+    BadString,
+}
+
+impl fmt::Display for FdtError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FdtError::BadMagic(magic) => write!(f, "bad magic {magic:#010x}"),
+            FdtError::UnsupportedVersion(v) => write!(f, "unsupported version {v}"),
+            FdtError::Truncated => write!(f, "blob is truncated"),
+            FdtError::BadToken(token) => write!(f, "unexpected token {token:#x}"),
+            FdtError::BadStructure => write!(f, "unbalanced structure block"),
+            FdtError::BadString => write!(f, "name isn't NUL terminated UTF-8"),
+        }
+    }
+}
+
+impl Error for FdtError {}
+
+fn be32(b: &[u8], offset: usize) -> Result<u32, FdtError> {
+    b.get(offset..offset + 4)
+        .map(|v| u32::from_be_bytes([v[0], v[1], v[2], v[3]]))
+        .ok_or(FdtError::Truncated)
+}
+
+/// the NUL terminated string at the start of `b`
+fn c_str(b: &[u8]) -> Result<&str, FdtError> {
+    let len = b.iter().position(|&c| c == 0).ok_or(FdtError::BadString)?;
+    core::str::from_utf8(&b[..len]).map_err(|_| FdtError::BadString)
+}
+
+/// fold big-endian cells into one number. anything past 64 bits falls off the top.
+pub fn read_cells(cells: &[u32]) -> u64 {
+    cells
+        .iter()
+        .fold(0u64, |acc, &c| acc.wrapping_shl(32) | c as u64)
+}
+
+/// a `reg` entry, translated to a CPU physical address
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Region {
+    pub address: u64,
+    pub size: u64,
+}
+
+pub struct Fdt<'a> {
+    /// arena of all nodes. the root is the first.
+    nodes: Vec<Node<'a>>,
+    boot_cpu: u32,
+}
+
+impl<'a> Fdt<'a> {
+    /// the size of the whole blob, from the first 8 bytes of its header
+    pub fn total_size(header: &[u8]) -> Result<usize, FdtError> {
+        let magic = be32(header, 0)?;
+        if magic != FDT_MAGIC {
+            return Err(FdtError::BadMagic(magic));
+        }
+        Ok(be32(header, 4)? as usize)
+    }
+
+    pub fn parse(blob: &'a [u8]) -> Result<Self, FdtError> {
+        let total = Self::total_size(blob)?;
+        let blob = blob.get(..total).ok_or(FdtError::Truncated)?;
+        if blob.len() < HEADER_LEN {
+            return Err(FdtError::Truncated);
+        }
+
+        let version = be32(blob, 20)?;
+        let last_compatible = be32(blob, 24)?;
+        if version < FDT_COMPATIBLE_VERSION || last_compatible > FDT_COMPATIBLE_VERSION {
+            return Err(FdtError::UnsupportedVersion(version));
+        }
+
+        let struct_start = be32(blob, 8)? as usize;
+        let strings_start = be32(blob, 12)? as usize;
+        let strings_len = be32(blob, 32)? as usize;
+        let struct_len = be32(blob, 36)? as usize;
+
+        let structure = blob
+            .get(struct_start..struct_start + struct_len)
+            .ok_or(FdtError::Truncated)?;
+        let strings = blob
+            .get(strings_start..strings_start + strings_len)
+            .ok_or(FdtError::Truncated)?;
+
+        Ok(Self {
+            nodes: Self::parse_structure(structure, strings)?,
+            boot_cpu: be32(blob, 28)?,
+        })
+    }
+
+    fn parse_structure(structure: &'a [u8], strings: &'a [u8]) -> Result<Vec<Node<'a>>, FdtError> {
+        let mut nodes: Vec<Node<'a>> = Vec::new();
+        let mut stack: Vec<NodeId> = Vec::new();
+        let mut offset = 0;
+
+        loop {
+            let token = be32(structure, offset)?;
+            offset += 4;
+
+            match token {
+                FDT_BEGIN_NODE => {
+                    let name = c_str(structure.get(offset..).ok_or(FdtError::Truncated)?)?;
+                    offset += (name.len() + 1).next_multiple_of(4);
+
+                    let id = NodeId(nodes.len());
+                    let parent = stack.last().copied();
+                    if parent.is_none() && !nodes.is_empty() {
+                        // a second root
+                        return Err(FdtError::BadStructure);
+                    }
+
+                    nodes.push(Node::new(id, name, parent));
+                    if let Some(parent) = parent {
+                        nodes[parent.0].children.push(id);
+                    }
+                    stack.push(id);
+                }
+                FDT_END_NODE => {
+                    stack.pop().ok_or(FdtError::BadStructure)?;
+                }
+                FDT_PROP => {
+                    let len = be32(structure, offset)? as usize;
+                    let name_offset = be32(structure, offset + 4)? as usize;
+                    offset += 8;
+
+                    let value = structure
+                        .get(offset..offset + len)
+                        .ok_or(FdtError::Truncated)?;
+                    offset += len.next_multiple_of(4);
+
+                    let name = c_str(strings.get(name_offset..).ok_or(FdtError::Truncated)?)?;
+                    let node = stack.last().ok_or(FdtError::BadStructure)?;
+                    nodes[node.0].properties.push(Property { name, value });
+                }
+                FDT_NOP => {}
+                FDT_END => break,
+                other => return Err(FdtError::BadToken(other)),
+            }
+        }
+
+        if !stack.is_empty() || nodes.is_empty() {
+            return Err(FdtError::BadStructure);
+        }
+
+        Ok(nodes)
+    }
+
+    pub fn nodes(&self) -> &[Node<'a>] {
+        &self.nodes
+    }
+
+    pub fn node(&self, id: NodeId) -> &Node<'a> {
+        &self.nodes[id.0]
+    }
+
+    pub fn root(&self) -> &Node<'a> {
+        &self.nodes[0]
+    }
+
+    /// physical ID of the CPU we were booted on, from the header
+    pub fn boot_cpu(&self) -> u32 {
+        self.boot_cpu
+    }
+
+    pub fn parent(&self, node: &Node<'a>) -> Option<&Node<'a>> {
+        node.parent.map(|id| self.node(id))
+    }
+
+    pub fn children(&self, node: &Node<'a>) -> impl Iterator<Item = &Node<'a>> {
+        node.children.iter().map(|&id| self.node(id))
+    }
+
+    /// look a node up by its full path. components without a unit address match any.
+    pub fn find(&self, path: &str) -> Option<&Node<'a>> {
+        path.split('/')
+            .filter(|c| !c.is_empty())
+            .try_fold(self.root(), |node, component| {
+                self.children(node).find(|child| {
+                    child.name == component
+                        || (!component.contains('@') && child.base_name() == component)
+                })
+            })
+    }
+
+    pub fn by_phandle(&self, phandle: u32) -> Option<&Node<'a>> {
+        self.nodes
+            .iter()
+            .find(|node| node.phandle() == Some(phandle))
+    }
+
+    /// `#address-cells` of the children of `bus`
+    pub fn address_cells(&self, bus: &Node<'a>) -> usize {
+        bus.property_u32("#address-cells").unwrap_or(2) as usize
+    }
+
+    /// `#size-cells` of the children of `bus`
+    pub fn size_cells(&self, bus: &Node<'a>) -> usize {
+        bus.property_u32("#size-cells").unwrap_or(1) as usize
+    }
+
+    /// translate `address` on the bus `bus` up to the root. `None` if some bus on the way isn't
+    /// memory mapped.
+    pub fn translate(&self, bus: &Node<'a>, address: u64) -> Option<u64> {
+        let mut bus = bus;
+        let mut address = address;
+
+        while let Some(parent) = self.parent(bus) {
+            // no `ranges` means the children of `bus` aren't in its parent's address space
+            let ranges = bus.property("ranges")?;
+
+            if !ranges.value.is_empty() {
+                let child_cells = self.address_cells(bus);
+                let parent_cells = self.address_cells(parent);
+                let size_cells = self.size_cells(bus);
+
+                let cells: Vec<u32> = ranges.cells().collect();
+                address = cells
+                    .chunks_exact(child_cells + parent_cells + size_cells)
+                    .find_map(|entry| {
+                        let child = read_cells(&entry[..child_cells]);
+                        let parent = read_cells(&entry[child_cells..child_cells + parent_cells]);
+                        let len = read_cells(&entry[child_cells + parent_cells..]);
+
+                        let offset = address.checked_sub(child)?;
+                        (offset < len).then_some(parent + offset)
+                    })?;
+            }
+
+            bus = parent;
+        }
+
+        Some(address)
+    }
+
+    /// the node's `reg` entries that translate to CPU addresses
+    pub fn reg(&self, node: &Node<'a>) -> Vec<Region> {
+        let (Some(bus), Some(reg)) = (self.parent(node), node.property("reg")) else {
+            return Vec::new();
+        };
+
+        let address_cells = self.address_cells(bus);
+        let size_cells = self.size_cells(bus);
+        if address_cells == 0 {
+            return Vec::new();
+        }
+
+        let cells: Vec<u32> = reg.cells().collect();
+        cells
+            .chunks_exact(address_cells + size_cells)
+            .filter_map(|entry| {
+                Some(Region {
+                    address: self.translate(bus, read_cells(&entry[..address_cells]))?,
+                    size: read_cells(&entry[address_cells..]),
+                })
+            })
+            .collect()
+    }
+
+    /// the controller `node`'s interrupts go to, inherited from its ancestors
+    pub fn interrupt_parent(&self, node: &Node<'a>) -> Option<&Node<'a>> {
+        let mut node = node;
+        loop {
+            if let Some(phandle) = node.property_u32("interrupt-parent") {
+                return self.by_phandle(phandle);
+            }
+            node = self.parent(node)?;
+        }
+    }
+
+    /// the node's interrupt specifiers, each with the controller that interprets it
+    pub fn interrupts(&self, node: &Node<'a>) -> Vec<(&Node<'a>, Vec<u32>)> {
+        let interrupt_cells = |controller: &Node<'a>| {
+            controller.property_u32("#interrupt-cells").unwrap_or(1) as usize
+        };
+
+        if let Some(extended) = node.property("interrupts-extended") {
+            let cells: Vec<u32> = extended.cells().collect();
+            let mut rest = cells.as_slice();
+            let mut interrupts = Vec::new();
+
+            while let Some((&phandle, tail)) = rest.split_first() {
+                let Some(controller) = self.by_phandle(phandle) else {
+                    break;
+                };
+                let n = interrupt_cells(controller);
+                if tail.len() < n {
+                    break;
+                }
+                interrupts.push((controller, tail[..n].to_vec()));
+                rest = &tail[n..];
+            }
+
+            return interrupts;
+        }
+
+        let (Some(interrupts), Some(controller)) =
+            (node.property("interrupts"), self.interrupt_parent(node))
+        else {
+            return Vec::new();
+        };
+
+        let n = interrupt_cells(controller);
+        if n == 0 {
+            return Vec::new();
+        }
+
+        let cells: Vec<u32> = interrupts.cells().collect();
+        cells
+            .chunks_exact(n)
+            .map(|specifier| (controller, specifier.to_vec()))
+            .collect()
+    }
+}
@@ -0,0 +1,107 @@
+use alloc::vec::Vec;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[repr(transparent)]
+pub struct NodeId(pub usize);
+
+#[derive(Debug, Copy, Clone)]
+pub struct Property<'a> {
+    pub name: &'a str,
+    pub value: &'a [u8],
+}
+
+impl<'a> Property<'a> {
+    /// the value as big-endian 32-bit cells. a trailing partial cell is ignored.
+    pub fn cells(&self) -> impl Iterator<Item = u32> + use<'a> {
+        self.value
+            .chunks_exact(4)
+            .map(|c| u32::from_be_bytes([c[0], c[1], c[2], c[3]]))
+    }
+
+    pub fn as_u32(&self) -> Option<u32> {
+        let b: [u8; 4] = self.value.try_into().ok()?;
+        Some(u32::from_be_bytes(b))
+    }
+
+    /// a one or two cell value
+    pub fn as_u64(&self) -> Option<u64> {
+        match self.value.len() {
+            4 => self.as_u32().map(u64::from),
+            8 => Some(u64::from_be_bytes(self.value.try_into().ok()?)),
+            _ => None,
+        }
+    }
+
+    /// the first string of the value
+    pub fn as_str(&self) -> Option<&'a str> {
+        self.strings().next()
+    }
+
+    /// the NUL separated strings of a string list
+    pub fn strings(&self) -> impl Iterator<Item = &'a str> + use<'a> {
+        self.value
+            .split(|&b| b == 0)
+            .filter(|s| !s.is_empty())
+            .filter_map(|s| core::str::from_utf8(s).ok())
+    }
+}
+
+#[derive(Debug)]
+pub struct Node<'a> {
+    pub id: NodeId,
+    /// `name@unit-address`, empty for the root
+    pub name: &'a str,
+    pub parent: Option<NodeId>,
+    pub children: Vec<NodeId>,
+    pub properties: Vec<Property<'a>>,
+}
+
+impl<'a> Node<'a> {
+    pub(crate) fn new(id: NodeId, name: &'a str, parent: Option<NodeId>) -> Self {
+        Self {
+            id,
+            name,
+            parent,
+            children: Vec::new(),
+            properties: Vec::new(),
+        }
+    }
+
+    /// the name without its unit address
+    pub fn base_name(&self) -> &'a str {
+        self.name.split('@').next().unwrap_or(self.name)
+    }
+
+    pub fn property(&self, name: &str) -> Option<&Property<'a>> {
+        self.properties.iter().find(|p| p.name == name)
+    }
+
+    pub fn property_u32(&self, name: &str) -> Option<u32> {
+        self.property(name)?.as_u32()
+    }
+
+    pub fn property_str(&self, name: &str) -> Option<&'a str> {
+        self.property(name)?.as_str()
+    }
+
+    pub fn compatible(&self) -> impl Iterator<Item = &'a str> + use<'a> {
+        self.property("compatible")
+            .copied()
+            .into_iter()
+            .flat_map(|p| p.strings())
+    }
+
+    pub fn is_compatible(&self, compatible: &str) -> bool {
+        self.compatible().any(|c| c == compatible)
+    }
+
+    pub fn phandle(&self) -> Option<u32> {
+        self.property_u32("phandle")
+            .or_else(|| self.property_u32("linux,phandle"))
+    }
+
+    /// `status` is absent, "okay" or the older "ok"
+    pub fn is_enabled(&self) -> bool {
+        matches!(self.property_str("status"), None | Some("okay" | "ok"))
+    }
+}
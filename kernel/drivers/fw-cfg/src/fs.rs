@@ -0,0 +1,171 @@
+//! the file directory as a read-only tree of inodes. `opt/org.example/blob` becomes the file
+//! `blob` inside the directories `opt` and `org.example`.
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use alloc::{collections::btree_map::BTreeMap, string::String, sync::Arc, vec::Vec};
+use klib::vfs::{
+    FileType, Result, VfsError,
+    inode::{Dirent, Inode, InodeOperations},
+};
+
+use crate::{FwCfg, FwCfgFile};
+
+static NEXT_INODE: AtomicU64 = AtomicU64::new(1);
+
+/// directory layout, before it's turned into inodes
+enum Entry {
+    Dir(BTreeMap<String, Entry>),
+    File { select: u16, size: u32 },
+}
+
+/// build the tree for `files` and return the operations of its root directory
+pub(crate) fn build(fw: Arc<FwCfg>, files: &[FwCfgFile]) -> Arc<dyn InodeOperations + Send + Sync> {
+    use log::*;
+
+    let mut root = BTreeMap::new();
+
+    'files: for file in files {
+        let mut parts = file.name.split('/').filter(|p| !p.is_empty()).peekable();
+        let mut dir = &mut root;
+
+        while let Some(part) = parts.next() {
+            if parts.peek().is_none() {
+                if dir.contains_key(part) {
+                    warn!("fw_cfg: duplicate path {}", file.name);
+                } else {
+                    dir.insert(
+                        part.into(),
+                        Entry::File {
+                            select: file.select,
+                            size: file.size,
+                        },
+                    );
+                }
+                continue 'files;
+            }
+
+            match dir
+                .entry(part.into())
+                .or_insert_with(|| Entry::Dir(BTreeMap::new()))
+            {
+                Entry::Dir(children) => dir = children,
+                Entry::File { .. } => {
+                    warn!("fw_cfg: {} is below a file", file.name);
+                    continue 'files;
+                }
+            }
+        }
+    }
+
+    Arc::new(Directory::new(&fw, root))
+}
+
+fn make_inode(fw: &Arc<FwCfg>, entry: Entry) -> Arc<Inode> {
+    let number = NEXT_INODE.fetch_add(1, Ordering::Relaxed);
+
+    match entry {
+        Entry::Dir(children) => Arc::new(Inode {
+            number,
+            file_type: FileType::Directory,
+            size: AtomicU64::new(0),
+            operations: Arc::new(Directory::new(fw, children)),
+        }),
+        Entry::File { select, size } => Arc::new(Inode {
+            number,
+            file_type: FileType::Normal,
+            size: AtomicU64::new(size as u64),
+            operations: Arc::new(File {
+                fw: fw.clone(),
+                select,
+                size,
+            }),
+        }),
+    }
+}
+
+struct Directory {
+    children: BTreeMap<String, Arc<Inode>>,
+}
+
+impl Directory {
+    fn new(fw: &Arc<FwCfg>, children: BTreeMap<String, Entry>) -> Self {
+        Self {
+            children: children
+                .into_iter()
+                .map(|(name, entry)| (name, make_inode(fw, entry)))
+                .collect(),
+        }
+    }
+}
+
+impl InodeOperations for Directory {
+    fn read_at(&self, _offset: u64, _buffer: &mut [u8]) -> Result<u64> {
+        Err(VfsError::IsADirectory)
+    }
+
+    fn write_at(&self, _offset: u64, _buffer: &[u8]) -> Result<u64> {
+        Err(VfsError::IsADirectory)
+    }
+
+    fn lookup_child(&self, name: &str) -> Result<Arc<Inode>> {
+        self.children.get(name).cloned().ok_or(VfsError::NotFound)
+    }
+
+    fn create(&self, _name: &str, _file_type: FileType) -> Result<Arc<Inode>> {
+        Err(VfsError::PermissionDenied)
+    }
+
+    fn truncate(&self, _size: u64) -> Result<()> {
+        Err(VfsError::IsADirectory)
+    }
+
+    fn readdir(&self) -> Result<Vec<Dirent>> {
+        Ok(self
+            .children
+            .iter()
+            .map(|(name, inode)| Dirent {
+                name: name.clone(),
+                file_type: inode.file_type,
+            })
+            .collect())
+    }
+}
+
+struct File {
+    fw: Arc<FwCfg>,
+    select: u16,
+    size: u32,
+}
+
+impl InodeOperations for File {
+    fn read_at(&self, offset: u64, buffer: &mut [u8]) -> Result<u64> {
+        let size = self.size as u64;
+        if offset >= size {
+            return Ok(0);
+        }
+
+        let len = (size - offset).min(buffer.len() as u64) as usize;
+        self.fw
+            .read(self.select, offset, &mut buffer[..len])
+            .map_err(|_| VfsError::Io)?;
+
+        Ok(len as u64)
+    }
+
+    fn write_at(&self, _offset: u64, _buffer: &[u8]) -> Result<u64> {
+        Err(VfsError::PermissionDenied)
+    }
+
+    fn lookup_child(&self, _name: &str) -> Result<Arc<Inode>> {
+        Err(VfsError::NotADirectory)
+    }
+
+    fn create(&self, _name: &str, _file_type: FileType) -> Result<Arc<Inode>> {
+        Err(VfsError::NotADirectory)
+    }
+
+    fn truncate(&self, _size: u64) -> Result<()> {
+        Err(VfsError::PermissionDenied)
+    }
+}
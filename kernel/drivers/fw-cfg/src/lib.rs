@@ -0,0 +1,316 @@
+//! QEMU firmware configuration device, MMIO flavour.
+//!
+//! every named item in the file directory shows up read-only under `/dev/fw_cfg`, so blobs passed
+//! with `-fw_cfg name=opt/...,file=...` can be read like any other file. items are read through
+//! the DMA interface when the device has one, otherwise a byte at a time through the data register.
+
+#![no_std]
+
+extern crate alloc;
+
+mod fs;
+
+use core::{
+    fmt,
+    ptr::{NonNull, read_volatile, write_volatile},
+};
+
+use alloc::{boxed::Box, string::String, sync::Arc, vec, vec::Vec};
+use klib::{
+    hardware::{
+        device::{Device, DeviceNode},
+        dma::DmaBuffer,
+        driver::{DriverDescriptor, DriverError},
+        mmio::map_mmio,
+        resource::Resource,
+    },
+    scheduler::GLOBAL_SCHEDULER,
+    sync::SleepingMutex,
+    vfs::{FileType, devfs},
+};
+
+pub static FW_CFG_DRIVER: DriverDescriptor = DriverDescriptor {
+    name: "fw_cfg",
+    compatible: &["QEMU0002", "qemu,fw-cfg-mmio"],
+    probe,
+};
+
+/// name of the directory in devfs
+const DEVFS_NAME: &str = "fw_cfg";
+
+const MMIO_SIZE: usize = 0x18;
+
+/// byte stream of the selected item
+const REG_DATA: usize = 0x00;
+/// big endian item selector. selecting rewinds the item to offset 0.
+const REG_SELECTOR: usize = 0x08;
+/// big endian physical address of a `DmaAccess`. the write starts the transfer.
+const REG_DMA: usize = 0x10;
+
+/// well-known selectors
+const FW_CFG_SIGNATURE: u16 = 0x0000;
+const FW_CFG_ID: u16 = 0x0001;
+const FW_CFG_FILE_DIR: u16 = 0x0019;
+
+const SIGNATURE: &[u8; 4] = b"QEMU";
+/// FW_CFG_ID: the DMA interface is present
+const ID_DMA: u32 = 1 << 1;
+
+/// `DmaAccess::control` bits
+const DMA_ERROR: u32 = 1 << 0;
+const DMA_READ: u32 = 1 << 1;
+const DMA_SKIP: u32 = 1 << 2;
+const DMA_SELECT: u32 = 1 << 3;
+
+/// bounce buffer size. the descriptor sits at the start, data follows it.
+const DMA_BUFFER_SIZE: usize = 64 * 1024;
+const DMA_DATA_OFFSET: usize = 64;
+
+/// length of a file directory entry, and of the name inside it
+const FILE_ENTRY_SIZE: usize = 64;
+const FILE_NAME_SIZE: usize = 56;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FwCfgError {
+    /// the device flagged a DMA transfer as failed
+    Dma,
+    /// the file directory is malformed
+    InvalidDirectory,
+}
+
+impl fmt::Display for FwCfgError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Dma => write!(f, "DMA transfer failed"),
+            Self::InvalidDirectory => write!(f, "invalid file directory"),
+        }
+    }
+}
+
+pub type Result<T> = core::result::Result<T, FwCfgError>;
+
+/// one entry of the file directory
+#[derive(Debug, Clone)]
+pub struct FwCfgFile {
+    pub name: String,
+    pub select: u16,
+    pub size: u32,
+}
+
+/// the descriptor the DMA register points at. every field is big endian.
+#[repr(C)]
+struct DmaAccess {
+    control: u32,
+    length: u32,
+    address: u64,
+}
+
+pub struct FwCfg {
+    regs: NonNull<u8>,
+    /// bounce buffer for the DMA interface, `None` if there isn't one
+    dma: SleepingMutex<'static, Option<DmaBuffer>>,
+}
+
+// SAFETY: the selector and data registers are only touched with `dma` locked
+unsafe impl Send for FwCfg {}
+unsafe impl Sync for FwCfg {}
+
+impl FwCfg {
+    fn select(&self, select: u16) {
+        unsafe {
+            write_volatile(
+                self.regs.as_ptr().add(REG_SELECTOR) as *mut u16,
+                select.to_be(),
+            )
+        };
+    }
+
+    fn pio_read(&self, buffer: &mut [u8]) {
+        for byte in buffer {
+            *byte = unsafe { read_volatile(self.regs.as_ptr().add(REG_DATA)) };
+        }
+    }
+
+    /// run one DMA transfer described by `control` and `length`, into the bounce buffer
+    fn dma_transfer(&self, bounce: &mut DmaBuffer, control: u32, length: u32) -> Result<()> {
+        let access = bounce.as_ptr() as *mut DmaAccess;
+        let data = bounce.phys_addr() + DMA_DATA_OFFSET as u64;
+
+        unsafe {
+            write_volatile(
+                access,
+                DmaAccess {
+                    control: control.to_be(),
+                    length: length.to_be(),
+                    address: data.to_be(),
+                },
+            )
+        };
+        bounce.clean(0, size_of::<DmaAccess>());
+
+        unsafe {
+            write_volatile(
+                self.regs.as_ptr().add(REG_DMA) as *mut u64,
+                bounce.phys_addr().to_be(),
+            )
+        };
+
+        // QEMU completes the transfer before the register write returns, but a device is allowed
+        // to take its time. it clears everything but the error bit when done.
+        loop {
+            bounce.invalidate(0, size_of::<DmaAccess>());
+            let done = u32::from_be(unsafe { read_volatile(&raw const (*access).control) });
+            if done & DMA_ERROR != 0 {
+                return Err(FwCfgError::Dma);
+            }
+            if done == 0 {
+                break;
+            }
+            core::hint::spin_loop();
+        }
+
+        // what the device wrote may be behind stale lines
+        if control & DMA_READ != 0 {
+            bounce.invalidate(DMA_DATA_OFFSET, length as usize);
+        }
+
+        Ok(())
+    }
+
+    /// read `buffer.len()` bytes of item `select`, starting at `offset`. reading past the end of
+    /// the item yields zeros.
+    pub fn read(&self, select: u16, offset: u64, buffer: &mut [u8]) -> Result<()> {
+        let mut dma = self.dma.lock(&GLOBAL_SCHEDULER);
+
+        let Some(bounce) = dma.as_mut() else {
+            self.select(select);
+            let mut skipped = 0;
+            while skipped < offset {
+                let mut scratch = [0u8; 64];
+                let n = (offset - skipped).min(scratch.len() as u64) as usize;
+                self.pio_read(&mut scratch[..n]);
+                skipped += n as u64;
+            }
+            self.pio_read(buffer);
+            return Ok(());
+        };
+
+        let mut control = ((select as u32) << 16) | DMA_SELECT;
+        let mut offset = offset;
+        while offset > 0 {
+            let n = offset.min(u32::MAX as u64) as u32;
+            self.dma_transfer(bounce, control | DMA_SKIP, n)?;
+            control = 0;
+            offset -= n as u64;
+        }
+
+        let chunk = DMA_BUFFER_SIZE - DMA_DATA_OFFSET;
+        for piece in buffer.chunks_mut(chunk) {
+            self.dma_transfer(bounce, control | DMA_READ, piece.len() as u32)?;
+            control = 0;
+
+            let data = &bounce.as_slice()[DMA_DATA_OFFSET..DMA_DATA_OFFSET + piece.len()];
+            piece.copy_from_slice(data);
+        }
+
+        Ok(())
+    }
+
+    /// the named items
+    pub fn files(&self) -> Result<Vec<FwCfgFile>> {
+        let mut count = [0u8; 4];
+        self.read(FW_CFG_FILE_DIR, 0, &mut count)?;
+        let count = u32::from_be_bytes(count) as usize;
+
+        // QEMU caps the directory at a few thousand entries, anything larger is garbage
+        if count > u16::MAX as usize {
+            return Err(FwCfgError::InvalidDirectory);
+        }
+
+        let mut raw = vec![0u8; count * FILE_ENTRY_SIZE];
+        self.read(FW_CFG_FILE_DIR, 4, &mut raw)?;
+
+        Ok(raw
+            .chunks_exact(FILE_ENTRY_SIZE)
+            .map(|entry| {
+                let name = &entry[8..8 + FILE_NAME_SIZE];
+                let len = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+
+                FwCfgFile {
+                    name: String::from_utf8_lossy(&name[..len]).into_owned(),
+                    select: u16::from_be_bytes([entry[4], entry[5]]),
+                    size: u32::from_be_bytes([entry[0], entry[1], entry[2], entry[3]]),
+                }
+            })
+            .collect())
+    }
+}
+
+struct FwCfgDevice;
+
+impl Device for FwCfgDevice {
+    fn shutdown(&self) {
+        devfs::unregister(DEVFS_NAME);
+    }
+}
+
+fn probe(node: &DeviceNode) -> core::result::Result<Box<dyn Device>, DriverError> {
+    use log::*;
+
+    let base = node
+        .resources
+        .iter()
+        .find_map(|r| match r {
+            Resource::Mmio { range } => Some(range.start),
+            _ => None,
+        })
+        .ok_or(DriverError::MissingResources)?;
+
+    let regs = map_mmio(&(base..base + MMIO_SIZE)).ok_or(DriverError::Io)?;
+    let fw = FwCfg {
+        regs,
+        dma: SleepingMutex::new(None),
+    };
+
+    let mut signature = [0u8; 4];
+    fw.read(FW_CFG_SIGNATURE, 0, &mut signature)
+        .map_err(|_| DriverError::Io)?;
+    if &signature != SIGNATURE {
+        return Err(DriverError::Incompatible);
+    }
+
+    let mut id = [0u8; 4];
+    fw.read(FW_CFG_ID, 0, &mut id)
+        .map_err(|_| DriverError::Io)?;
+    // the only little endian item
+    if u32::from_le_bytes(id) & ID_DMA != 0 {
+        *fw.dma.lock(&GLOBAL_SCHEDULER) = DmaBuffer::new(DMA_BUFFER_SIZE);
+    }
+
+    let files = fw.files().map_err(|e| {
+        error!("fw_cfg: {e}");
+        DriverError::Io
+    })?;
+
+    info!(
+        "fw_cfg: at {:#x}, {} files, {}",
+        base,
+        files.len(),
+        if fw.dma.lock(&GLOBAL_SCHEDULER).is_some() {
+            "DMA"
+        } else {
+            "PIO"
+        }
+    );
+    for file in &files {
+        trace!(
+            "fw_cfg:     {:#06x} {:>10} {}",
+            file.select, file.size, file.name
+        );
+    }
+
+    let root = fs::build(Arc::new(fw), &files);
+    devfs::register(DEVFS_NAME, FileType::Directory, root);
+
+    Ok(Box::new(FwCfgDevice))
+}
@@ -0,0 +1,440 @@
+//! PCIe advanced error reporting. root ports collect the error messages of everything below
+//! them and raise an MSI. a thread then finds the function that sent the message, logs and
+//! clears its AER status and tells its driver, which may reset it: that can take a while, so
+//! none of it happens in IRQ context.
+
+use core::{
+    fmt,
+    ops::RangeInclusive,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use alloc::{collections::btree_map::BTreeMap, sync::Arc, vec::Vec};
+use klib::{
+    hardware::device::{DeviceClass, DeviceTree},
+    interrupt::{
+        InterruptError,
+        gicv3::{IrqHandler, IrqTarget},
+        singleton::get_interrupt_controller,
+    },
+    scheduler::GLOBAL_SCHEDULER,
+    stack::Stack,
+    sync::{RwLock, WaitQueue},
+    thread::Thread,
+};
+
+use crate::{
+    address::{Bdf, PciBridgeKind},
+    capability::{
+        extended::{AerCap, ExtendedCapabilityId},
+        find_capability, find_extended_capability,
+        standard::{PcieCap, StandardCapabilityId},
+    },
+    ecam::{Ecam, get_segment},
+    interrupt::{VectorKind, alloc_vectors},
+};
+
+const STACK_SIZE: usize = 64 * 1024;
+
+/// root error command: interrupt on correctable, non-fatal and fatal error messages
+const ROOT_COMMAND_ALL: u32 = 0x07;
+
+/// root error status bits
+const ROOT_COR_RECEIVED: u32 = 1 << 0;
+const ROOT_MULTIPLE_COR: u32 = 1 << 1;
+const ROOT_UNCOR_RECEIVED: u32 = 1 << 2;
+const ROOT_MULTIPLE_UNCOR: u32 = 1 << 3;
+const ROOT_FATAL_RECEIVED: u32 = 1 << 6;
+/// which of the port's MSI or MSI-X vectors AER uses
+const ROOT_MESSAGE_NUMBER_SHIFT: u32 = 27;
+
+/// correctable error status bits, PCIe base spec section 7.8.4.5
+const CORRECTABLE: &[(u8, &str)] = &[
+    (0, "receiver error"),
+    (6, "bad TLP"),
+    (7, "bad DLLP"),
+    (8, "replay number rollover"),
+    (12, "replay timer timeout"),
+    (13, "advisory non-fatal"),
+    (14, "corrected internal"),
+    (15, "header log overflow"),
+];
+
+/// uncorrectable error status bits, PCIe base spec section 7.8.4.2
+const UNCORRECTABLE: &[(u8, &str)] = &[
+    (4, "data link protocol"),
+    (5, "surprise down"),
+    (12, "poisoned TLP"),
+    (13, "flow control protocol"),
+    (14, "completion timeout"),
+    (15, "completer abort"),
+    (16, "unexpected completion"),
+    (17, "receiver overflow"),
+    (18, "malformed TLP"),
+    (19, "ECRC"),
+    (20, "unsupported request"),
+    (21, "ACS violation"),
+    (22, "uncorrectable internal"),
+    (23, "MC blocked TLP"),
+    (24, "AtomicOp egress blocked"),
+    (25, "TLP prefix blocked"),
+    (26, "poisoned TLP egress blocked"),
+];
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Severity {
+    Correctable,
+    NonFatal,
+    /// the link is unreliable: whatever is below the port may need resetting
+    Fatal,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct AerError {
+    /// the function that sent the error message
+    pub bdf: Bdf,
+    pub severity: Severity,
+    /// unmasked AER status bits. zero if the function has no AER capability.
+    pub status: u32,
+    /// the uncorrectable status bit that was set first
+    pub first_error: Option<u8>,
+    /// header of the TLP the first error is about
+    pub header: Option<[u32; 4]>,
+}
+
+impl fmt::Display for AerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names = match self.severity {
+            Severity::Correctable => CORRECTABLE,
+            _ => UNCORRECTABLE,
+        };
+
+        write!(f, "{}: {:?}", self.bdf, self.severity)?;
+
+        let mut sep = ":";
+        for &(bit, name) in names {
+            if self.status & (1 << bit) != 0 {
+                write!(f, "{} {}", sep, name)?;
+                if self.first_error == Some(bit) {
+                    f.write_str(" (first)")?;
+                }
+                sep = ",";
+            }
+        }
+
+        if let Some(header) = self.header {
+            write!(
+                f,
+                ", TLP header {:08x} {:08x} {:08x} {:08x}",
+                header[0], header[1], header[2], header[3]
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// what drivers hear about uncorrectable errors in their functions
+pub trait ErrorHandler: Send + Sync {
+    /// runs on the AER thread, so it may sleep, and recover with
+    /// [`crate::reset::function_level_reset`] or [`crate::reset::secondary_bus_reset`]
+    fn error_detected(&self, error: &AerError);
+}
+
+static HANDLERS: RwLock<BTreeMap<Bdf, Arc<dyn ErrorHandler>>> = RwLock::new(BTreeMap::new());
+
+/// tell `handler` about errors `bdf` reports, and fatal ones below the same root port
+pub fn register_error_handler(bdf: Bdf, handler: Arc<dyn ErrorHandler>) {
+    HANDLERS.write().insert(bdf, handler);
+}
+
+pub fn unregister_error_handler(bdf: Bdf) {
+    HANDLERS.write().remove(&bdf);
+}
+
+struct RootPort {
+    ecam: &'static Ecam,
+    bdf: Bdf,
+    aer: AerCap,
+    buses: RangeInclusive<u8>,
+    pending: AtomicBool,
+}
+
+/// by the interrupt they raise
+static ROOT_PORTS: RwLock<BTreeMap<u32, RootPort>> = RwLock::new(BTreeMap::new());
+
+static AER_WAIT: WaitQueue<'static> = WaitQueue::new();
+static STARTED: AtomicBool = AtomicBool::new(false);
+
+/// enable AER on every root port in the tree, and error reporting on everything below them
+pub fn init(dt: &DeviceTree) {
+    use log::*;
+
+    for node in &dt.nodes {
+        let DeviceClass::PciBridge {
+            bdf,
+            kind: PciBridgeKind::RootPort,
+            secondary_bus,
+            subordinate_bus,
+            ..
+        } = node.class
+        else {
+            continue;
+        };
+
+        let Some(ecam) = get_segment(bdf.segment) else {
+            continue;
+        };
+
+        match enable_root_port(ecam, bdf, secondary_bus..=subordinate_bus) {
+            Ok(irq) => info!("AER: {}: enabled, IRQ {}", bdf, irq),
+            Err(e) => debug!("AER: {}: {}", bdf, e),
+        }
+    }
+}
+
+/// start the thread that handles errors. ones that came before wait for it.
+pub fn start() {
+    if ROOT_PORTS.read().is_empty() || STARTED.swap(true, Ordering::AcqRel) {
+        return;
+    }
+
+    let stack = Stack::new(STACK_SIZE, 16).expect("unable to allocate AER stack");
+    let thread = Arc::new(Thread::new_kernel(stack, aer_thread as *const (), 1));
+    GLOBAL_SCHEDULER.spawn(thread);
+}
+
+fn enable_root_port(
+    ecam: &'static Ecam,
+    bdf: Bdf,
+    buses: RangeInclusive<u8>,
+) -> Result<u32, &'static str> {
+    let offset = find_extended_capability(ecam, bdf, ExtendedCapabilityId::AdvancedErrorReporting)
+        .ok_or("no AER capability")?;
+    let pcie = find_capability(ecam, bdf, StandardCapabilityId::PciExpress)
+        .map(|offset| PcieCap::read(ecam, bdf, offset))
+        .ok_or("not a PCIe port")?;
+
+    // MSI has at most 32 vectors, and the message number picks one of them
+    let vectors = alloc_vectors(ecam, bdf, 32)?;
+    if vectors.kind == VectorKind::Intx {
+        ecam.set_intx_disabled(bdf, true);
+        return Err("no MSI or MSI-X");
+    }
+
+    let aer = AerCap::read(ecam, bdf, offset);
+    // only meaningful once MSI or MSI-X is on
+    let (status, _) = aer.root_status(ecam, bdf);
+    let message = (status >> ROOT_MESSAGE_NUMBER_SHIFT) as usize;
+    let irq = *vectors
+        .irqs
+        .get(message)
+        .or(vectors.irqs.first())
+        .ok_or("no vectors")?;
+
+    let ic = get_interrupt_controller();
+    let handler = IrqHandler::new(IrqTarget::Redistributor, dispatch).ok_or("no IRQ handler")?;
+    ic.register_handler(irq, handler)
+        .map_err(|_| "unable to route the AER interrupt")?;
+
+    ROOT_PORTS.write().insert(
+        irq,
+        RootPort {
+            ecam,
+            bdf,
+            aer,
+            buses: buses.clone(),
+            pending: AtomicBool::new(false),
+        },
+    );
+    ic.enable_interrupt(irq)
+        .map_err(|_| "unable to enable the AER interrupt")?;
+
+    // errors from before are stale
+    aer.clear_errors(ecam, bdf);
+    aer.clear_root_status(ecam, bdf, status);
+    pcie.clear_error_status(ecam, bdf);
+
+    enable_reporting(ecam, bdf);
+    for bus in buses {
+        for device in 0..32 {
+            for function in 0..8 {
+                let below = Bdf::new(ecam.segment, bus, device, function);
+                if ecam.read_u16(below, 0x00) != !0 {
+                    enable_reporting(ecam, below);
+                }
+            }
+        }
+    }
+
+    pcie.disable_system_errors(ecam, bdf);
+    aer.set_root_command(ecam, bdf, ROOT_COMMAND_ALL);
+
+    Ok(irq)
+}
+
+/// have `bdf` send error messages, starting from a clean slate
+fn enable_reporting(ecam: &Ecam, bdf: Bdf) {
+    let Some(pcie) = find_capability(ecam, bdf, StandardCapabilityId::PciExpress)
+        .map(|offset| PcieCap::read(ecam, bdf, offset))
+    else {
+        return;
+    };
+
+    if let Some(offset) =
+        find_extended_capability(ecam, bdf, ExtendedCapabilityId::AdvancedErrorReporting)
+    {
+        AerCap::read(ecam, bdf, offset).clear_errors(ecam, bdf);
+    }
+
+    pcie.clear_error_status(ecam, bdf);
+    pcie.enable_error_reporting(ecam, bdf);
+}
+
+fn dispatch(irq: u32) -> Result<(), InterruptError> {
+    let ports = ROOT_PORTS.read();
+    let port = ports.get(&irq).ok_or(InterruptError::HandlerNotFound)?;
+
+    port.pending.store(true, Ordering::Release);
+    AER_WAIT.wake_all(&GLOBAL_SCHEDULER);
+
+    Ok(())
+}
+
+fn aer_thread() -> ! {
+    loop {
+        let seen = AER_WAIT.generation();
+
+        let pending = ROOT_PORTS
+            .read()
+            .values()
+            .filter(|port| port.pending.swap(false, Ordering::AcqRel))
+            .map(|port| (port.ecam, port.bdf, port.aer, port.buses.clone()))
+            .collect::<Vec<_>>();
+
+        for (ecam, bdf, aer, buses) in pending {
+            handle_root_port(ecam, bdf, &aer, buses);
+        }
+
+        AER_WAIT.wait(&GLOBAL_SCHEDULER, seen);
+    }
+}
+
+/// the function with requester ID `id`
+const fn source(segment: u16, id: u16) -> Bdf {
+    Bdf::new(
+        segment,
+        (id >> 8) as u8,
+        ((id >> 3) & 0x1F) as u8,
+        (id & 0x07) as u8,
+    )
+}
+
+fn handle_root_port(ecam: &Ecam, port: Bdf, aer: &AerCap, buses: RangeInclusive<u8>) {
+    use log::*;
+
+    let (status, sources) = aer.root_status(ecam, port);
+    aer.clear_root_status(ecam, port, status);
+
+    if status & ROOT_COR_RECEIVED != 0 {
+        let error = collect(ecam, source(ecam.segment, sources as u16), None);
+        warn!("AER: {}", error);
+        if status & ROOT_MULTIPLE_COR != 0 {
+            warn!("AER: {}: more correctable errors were dropped", port);
+        }
+    }
+
+    if status & ROOT_UNCOR_RECEIVED != 0 {
+        let fatal = status & ROOT_FATAL_RECEIVED != 0;
+        let error = collect(
+            ecam,
+            source(ecam.segment, (sources >> 16) as u16),
+            Some(fatal),
+        );
+        error!("AER: {}", error);
+        if status & ROOT_MULTIPLE_UNCOR != 0 {
+            error!("AER: {}: more uncorrectable errors were dropped", port);
+        }
+
+        notify(&error, buses);
+    }
+}
+
+/// read and clear the AER status of `bdf`. `fatal` is what the root port saw of an
+/// uncorrectable error, `None` for a correctable one.
+fn collect(ecam: &Ecam, bdf: Bdf, fatal: Option<bool>) -> AerError {
+    let aer = find_extended_capability(ecam, bdf, ExtendedCapabilityId::AdvancedErrorReporting)
+        .map(|offset| AerCap::read(ecam, bdf, offset));
+    if let Some(pcie) = find_capability(ecam, bdf, StandardCapabilityId::PciExpress)
+        .map(|offset| PcieCap::read(ecam, bdf, offset))
+    {
+        pcie.clear_error_status(ecam, bdf);
+    }
+
+    let severity = match fatal {
+        None => Severity::Correctable,
+        Some(true) => Severity::Fatal,
+        Some(false) => Severity::NonFatal,
+    };
+
+    let Some(aer) = aer else {
+        return AerError {
+            bdf,
+            severity,
+            status: 0,
+            first_error: None,
+            header: None,
+        };
+    };
+
+    if severity == Severity::Correctable {
+        let status = aer.correctable_status & !aer.correctable_mask;
+        aer.clear_correctable(ecam, bdf, aer.correctable_status);
+
+        return AerError {
+            bdf,
+            severity,
+            status,
+            first_error: None,
+            header: None,
+        };
+    }
+
+    let status = aer.uncorrectable_status & !aer.uncorrectable_mask;
+    let first = aer.first_error_pointer;
+    // the pointer and header log are only valid while that status bit is set
+    let logged = status & (1 << first) != 0;
+    aer.clear_uncorrectable(ecam, bdf, aer.uncorrectable_status);
+
+    AerError {
+        bdf,
+        severity: if status & aer.uncorrectable_severity != 0 {
+            Severity::Fatal
+        } else {
+            severity
+        },
+        status,
+        first_error: logged.then_some(first),
+        header: logged.then_some(aer.header_log),
+    }
+}
+
+/// tell the driver of the function that sent `error`. a fatal error takes down the link, so
+/// every driver on `buses` hears about it.
+fn notify(error: &AerError, buses: RangeInclusive<u8>) {
+    let handlers = HANDLERS
+        .read()
+        .iter()
+        .filter(|(bdf, _)| {
+            **bdf == error.bdf
+                || (error.severity == Severity::Fatal
+                    && bdf.segment == error.bdf.segment
+                    && buses.contains(&bdf.bus))
+        })
+        .map(|(_, handler)| handler.clone())
+        .collect::<Vec<_>>();
+
+    for handler in handlers {
+        handler.error_detected(error);
+    }
+}
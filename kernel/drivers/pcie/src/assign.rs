@@ -0,0 +1,755 @@
+//! bus numbers, BARs and bridge windows that firmware left unassigned.
+//!
+//! what firmware set up stays where it is as long as it's valid: bridges keep bus numbers
+//! that fit behind their parent, and BARs and windows keep addresses inside the host bridge
+//! apertures and their bridge's own windows. the rest is filled in around them. a bridge
+//! without a usable window gets a fresh one, sized bottom up from what's behind it, rounded
+//! to the window granularity, and everything behind it is placed in it top down, biggest
+//! alignment first.
+
+use core::{
+    cell::Cell,
+    ops::{Range, RangeInclusive},
+};
+
+use alloc::vec::Vec;
+use klib::hardware::resource::{AddressSpace, Resource};
+use log::*;
+
+use crate::{
+    address::Bdf,
+    bar::{BarType, probe_indexed_bars, set_bar_address},
+    ecam::Ecam,
+    scan::BusSet,
+};
+
+const CMD_REG: u16 = 0x04;
+const CMD_IO: u16 = 1 << 0;
+const CMD_MEMORY: u16 = 1 << 1;
+const CMD_BUS_MASTER: u16 = 1 << 2;
+
+/// type 1 header
+const PRIMARY_BUS: u16 = 0x18;
+const SECONDARY_BUS: u16 = 0x19;
+const SUBORDINATE_BUS: u16 = 0x1A;
+const IO_BASE: u16 = 0x1C;
+const IO_LIMIT: u16 = 0x1D;
+const MEMORY_BASE: u16 = 0x20;
+const MEMORY_LIMIT: u16 = 0x22;
+const PREFETCH_BASE: u16 = 0x24;
+const PREFETCH_LIMIT: u16 = 0x26;
+const PREFETCH_BASE_UPPER: u16 = 0x28;
+const PREFETCH_LIMIT_UPPER: u16 = 0x2C;
+const IO_BASE_UPPER: u16 = 0x30;
+const IO_LIMIT_UPPER: u16 = 0x32;
+
+/// low bits of the prefetchable base when the window is 64-bit
+const PREFETCH_64BIT: u16 = 0x1;
+
+const FOUR_GIB: u64 = 1 << 32;
+
+/// I/O ports below this are left to legacy devices
+const IO_START: u64 = 0x1000;
+
+/// the window a BAR is placed in
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Kind {
+    Io = 0,
+    Memory = 1,
+    Prefetch = 2,
+}
+
+impl Kind {
+    const ALL: [Kind; 3] = [Kind::Io, Kind::Memory, Kind::Prefetch];
+
+    /// granularity of bridge windows
+    const fn window_align(self) -> u64 {
+        match self {
+            Kind::Io => 0x1000,
+            Kind::Memory | Kind::Prefetch => 0x10_0000,
+        }
+    }
+
+    /// the kind, address and size of `bar`. 32-bit prefetchable BARs go in the
+    /// non-prefetchable window, since the prefetchable one may end up above 4GiB.
+    fn of(bar: &BarType) -> (Self, u64, u64) {
+        match *bar {
+            BarType::Io { address, size } => (Kind::Io, address as u64, size as u64),
+            BarType::Memory32 { address, size, .. } => (Kind::Memory, address as u64, size as u64),
+            BarType::Memory64 {
+                address,
+                size,
+                prefetchable: true,
+            } => (Kind::Prefetch, address, size),
+            BarType::Memory64 {
+                address,
+                size,
+                prefetchable: false,
+            } => (Kind::Memory, address, size),
+        }
+    }
+
+    fn of_space(space: AddressSpace) -> Option<Self> {
+        match space {
+            AddressSpace::Io => Some(Kind::Io),
+            AddressSpace::Memory {
+                prefetchable: false,
+            } => Some(Kind::Memory),
+            AddressSpace::Memory { prefetchable: true } => Some(Kind::Prefetch),
+            AddressSpace::BusNumber => None,
+        }
+    }
+}
+
+struct Bar {
+    bdf: Bdf,
+    index: u8,
+    kind: Kind,
+    /// as firmware left it
+    address: u64,
+    size: u64,
+    /// firmware's address is valid
+    keep: bool,
+    /// kept, or placed since
+    assigned: Cell<bool>,
+}
+
+#[derive(Debug, Default, Copy, Clone)]
+struct Need {
+    size: u64,
+    align: u64,
+}
+
+type Windows = [Option<Range<u64>>; 3];
+
+struct Bus {
+    number: u8,
+    /// the bridge in front of it, `None` for the root bus
+    bridge: Option<Bdf>,
+    functions: Vec<Bdf>,
+    bars: Vec<Bar>,
+    children: Vec<Bus>,
+    /// by `Kind`, the windows firmware gave it that are kept, or the apertures for the root
+    /// bus. `None` ones get a fresh window from the parent.
+    windows: Windows,
+    /// what the BARs and buses behind it that need placing take up, by `Kind`
+    needs: [Need; 3],
+}
+
+enum Item<'a> {
+    Bar(&'a Bar),
+    Bus(&'a Bus),
+}
+
+impl Bus {
+    /// what needs placing in this bus's `kind` window, biggest alignment first
+    fn items(&self, kind: Kind) -> Vec<(Need, Item<'_>)> {
+        let bars = self
+            .bars
+            .iter()
+            .filter(|bar| bar.kind == kind && !bar.keep)
+            .map(|bar| {
+                let need = Need {
+                    size: bar.size,
+                    align: bar.size,
+                };
+                (need, Item::Bar(bar))
+            });
+        let buses = self
+            .children
+            .iter()
+            .filter(|bus| bus.windows[kind as usize].is_none())
+            .map(|bus| (bus.needs[kind as usize], Item::Bus(bus)));
+
+        let mut items: Vec<_> = bars
+            .chain(buses)
+            .filter(|(need, _)| need.size > 0)
+            .collect();
+        items.sort_by(|(a, _), (b, _)| b.align.cmp(&a.align));
+        items
+    }
+
+    /// what's kept on this bus, in I/O or memory space
+    fn occupied(&self, io: bool) -> Vec<Range<u64>> {
+        let bars = self
+            .bars
+            .iter()
+            .filter(|bar| bar.keep && (bar.kind == Kind::Io) == io)
+            .map(|bar| bar.address..bar.address + bar.size);
+        let windows = self.children.iter().flat_map(|bus| {
+            Kind::ALL
+                .into_iter()
+                .filter(|kind| (*kind == Kind::Io) == io)
+                .filter_map(|kind| bus.windows[kind as usize].clone())
+        });
+
+        bars.chain(windows).collect()
+    }
+}
+
+/// number the buses behind the first of `buses` with the rest, and assign the BARs and bridge
+/// windows firmware didn't, or got wrong, from the host bridge apertures in `windows`
+pub fn assign_resources(ecam: &Ecam, buses: RangeInclusive<u8>, windows: &[Resource]) {
+    let mut numbering = BusSet::default();
+    numbering.insert(*buses.start()..=*buses.start());
+
+    let mut root = walk(ecam, *buses.start(), None, *buses.end(), &mut numbering);
+
+    let apertures = Apertures::new(windows);
+    let regions = apertures
+        .pick
+        .map(|pick| pick.map(|i| apertures.ranges[i].clone()));
+    validate(ecam, &mut root, regions);
+    size(&mut root);
+
+    for kind in Kind::ALL {
+        if root.windows[kind as usize].is_none() && root.needs[kind as usize].size > 0 {
+            warn!(
+                "PCIe {:04x}: no aperture for {:?}, leaving it unassigned",
+                ecam.segment, kind
+            );
+        }
+    }
+    fill(ecam, &root);
+
+    enable(ecam, &root);
+
+    let last = buses
+        .clone()
+        .rev()
+        .find(|&bus| numbering.contains(bus))
+        .unwrap_or(*buses.start());
+    let mut tally = [0; 3];
+    count(&root, &mut tally);
+    info!(
+        "PCIe {:04x}: buses {}..={}, {} BARs kept, {} assigned, {} unassigned",
+        ecam.segment,
+        buses.start(),
+        last,
+        tally[0],
+        tally[1],
+        tally[2]
+    );
+}
+
+/// BARs kept, placed, and left unassigned
+fn count(bus: &Bus, tally: &mut [usize; 3]) {
+    for bar in &bus.bars {
+        let i = match (bar.keep, bar.assigned.get()) {
+            (true, _) => 0,
+            (false, true) => 1,
+            (false, false) => 2,
+        };
+        tally[i] += 1;
+    }
+
+    for child in &bus.children {
+        count(child, tally);
+    }
+}
+
+/// which host bridge aperture each `Kind` comes out of
+struct Apertures {
+    ranges: Vec<Range<u64>>,
+    pick: [Option<usize>; 3],
+}
+
+impl Apertures {
+    /// only the first aperture of each kind is used
+    fn new(windows: &[Resource]) -> Self {
+        let mut ranges = Vec::new();
+        let mut find = |f: &dyn Fn(&AddressSpace, &Range<u64>) -> bool| {
+            let range = windows.iter().find_map(|window| match window {
+                Resource::Window { space, range, .. } if f(space, range) => Some(range.clone()),
+                _ => None,
+            })?;
+
+            Some(match ranges.iter().position(|r| *r == range) {
+                Some(i) => i,
+                None => {
+                    ranges.push(range);
+                    ranges.len() - 1
+                }
+            })
+        };
+
+        let io = find(&|space, _| *space == AddressSpace::Io);
+        let memory = find(&|space, range| {
+            matches!(space, AddressSpace::Memory { .. }) && range.end <= FOUR_GIB
+        });
+        // a 64-bit prefetchable window falls back to any 64-bit one, then to the 32-bit one
+        let prefetch = find(&|space, range| {
+            *space == AddressSpace::Memory { prefetchable: true } && range.start >= FOUR_GIB
+        })
+        .or_else(|| {
+            find(&|space, range| {
+                matches!(space, AddressSpace::Memory { .. }) && range.start >= FOUR_GIB
+            })
+        })
+        .or(memory);
+
+        Self {
+            ranges,
+            pick: [io, memory, prefetch],
+        }
+    }
+}
+
+/// the first free bus of `buses`, and the free ones right after it
+fn free_run(numbering: &BusSet, buses: RangeInclusive<u8>) -> Option<RangeInclusive<u8>> {
+    let end = *buses.end();
+    let start = buses.into_iter().find(|&bus| !numbering.contains(bus))?;
+    let last = (start..=end)
+        .take_while(|&bus| !numbering.contains(bus))
+        .last()?;
+
+    Some(start..=last)
+}
+
+/// find the functions on bus `number`, and number the buses behind its bridges out of those up
+/// to `limit`
+fn walk(ecam: &Ecam, number: u8, bridge: Option<Bdf>, limit: u8, numbering: &mut BusSet) -> Bus {
+    let mut bus = Bus {
+        number,
+        bridge,
+        functions: Vec::new(),
+        bars: Vec::new(),
+        children: Vec::new(),
+        windows: Default::default(),
+        needs: Default::default(),
+    };
+    let mut bridges = Vec::new();
+
+    for device in 0..32 {
+        for function in 0..8 {
+            let bdf = Bdf::new(ecam.segment, number, device, function);
+            if ecam.read_u16(bdf, 0x00) == !0 {
+                if function == 0 {
+                    break;
+                }
+                continue;
+            }
+
+            let header_type = ecam.read_u8(bdf, 0x0E);
+            let bar_count = match header_type & 0x7F {
+                0x00 => 6,
+                0x01 => 2,
+                _ => 0,
+            };
+
+            bus.functions.push(bdf);
+            bus.bars
+                .extend(probe_indexed_bars(ecam, bdf, bar_count).into_iter().map(
+                    |(index, bar)| {
+                        let (kind, address, size) = Kind::of(&bar);
+                        Bar {
+                            bdf,
+                            index,
+                            kind,
+                            address,
+                            size,
+                            keep: false,
+                            assigned: Cell::new(false),
+                        }
+                    },
+                ));
+
+            if header_type & 0x7F == 0x01 {
+                let secondary = ecam.read_u8(bdf, SECONDARY_BUS);
+                let subordinate = ecam.read_u8(bdf, SUBORDINATE_BUS);
+                bridges.push((bdf, secondary..=subordinate));
+            }
+
+            if function == 0 && header_type & 0x80 == 0 {
+                break;
+            }
+        }
+    }
+
+    // firmware's numbers stay if they're behind this bus and no other bridge has them. they're
+    // all claimed before any bridge is given new ones.
+    let kept = bridges
+        .iter()
+        .map(|(_, range)| {
+            let valid = *range.start() > number
+                && range.start() <= range.end()
+                && *range.end() <= limit
+                && range.clone().all(|bus| !numbering.contains(bus));
+            if valid {
+                numbering.insert(range.clone());
+            }
+            valid
+        })
+        .collect::<Vec<_>>();
+
+    for ((bdf, range), keep) in bridges.into_iter().zip(kept) {
+        let range = if keep {
+            range
+        } else {
+            let Some(free) = free_run(numbering, number.saturating_add(1)..=limit) else {
+                warn!("{}: out of bus numbers, not scanning behind it", bdf);
+                ecam.write_u8(bdf, SECONDARY_BUS, 0);
+                ecam.write_u8(bdf, SUBORDINATE_BUS, 0);
+                continue;
+            };
+            numbering.insert(*free.start()..=*free.start());
+            free
+        };
+        let secondary = *range.start();
+
+        ecam.write_u8(bdf, PRIMARY_BUS, number);
+        ecam.write_u8(bdf, SECONDARY_BUS, secondary);
+        // everything it may have, until we know how far down it goes
+        ecam.write_u8(bdf, SUBORDINATE_BUS, *range.end());
+
+        let child = if keep {
+            // the whole range is claimed already, what's behind it is numbered within it
+            let mut within = BusSet::default();
+            within.insert(secondary..=secondary);
+            walk(ecam, secondary, Some(bdf), *range.end(), &mut within)
+        } else {
+            walk(ecam, secondary, Some(bdf), *range.end(), numbering)
+        };
+
+        let subordinate = if keep {
+            *range.end()
+        } else {
+            let subordinate = range
+                .clone()
+                .rev()
+                .find(|&bus| numbering.contains(bus))
+                .unwrap_or(secondary);
+            ecam.write_u8(bdf, SUBORDINATE_BUS, subordinate);
+            subordinate
+        };
+
+        trace!(
+            "{}: buses {}..={}{}",
+            bdf,
+            secondary,
+            subordinate,
+            if keep { ", as firmware had them" } else { "" }
+        );
+        bus.children.push(child);
+    }
+
+    bus
+}
+
+/// keep the BARs and bridge windows on and below `bus` that are inside `windows`, the ones of
+/// the bus itself
+fn validate(ecam: &Ecam, bus: &mut Bus, windows: Windows) {
+    let inside = |kind: Kind, range: &Range<u64>| {
+        windows[kind as usize]
+            .as_ref()
+            .is_some_and(|window| window.start <= range.start && range.end <= window.end)
+    };
+
+    for bar in &mut bus.bars {
+        let range = bar.address..bar.address + bar.size;
+        // a prefetchable BAR works from a non-prefetchable window too
+        bar.keep = bar.address != 0
+            && (inside(bar.kind, &range)
+                || (bar.kind == Kind::Prefetch && inside(Kind::Memory, &range)));
+        bar.assigned.set(bar.keep);
+
+        if bar.keep {
+            trace!("{}: BAR {} kept at {:#x}", bar.bdf, bar.index, bar.address);
+        }
+    }
+
+    for child in &mut bus.children {
+        let Some(bridge) = child.bridge else {
+            continue;
+        };
+
+        let mut kept = Windows::default();
+        for (space, range) in bridge_windows(ecam, bridge) {
+            // as with BARs, a prefetchable window may sit in a non-prefetchable one
+            if let Some(kind) = Kind::of_space(space)
+                && (inside(kind, &range)
+                    || (kind == Kind::Prefetch && inside(Kind::Memory, &range)))
+            {
+                kept[kind as usize] = Some(range);
+            }
+        }
+
+        for kind in Kind::ALL {
+            if kept[kind as usize].is_none() {
+                disable_window(ecam, bridge, kind);
+            }
+        }
+
+        validate(ecam, child, kept);
+    }
+
+    bus.windows = windows;
+}
+
+/// fill in `needs`, children first
+fn size(bus: &mut Bus) {
+    for child in &mut bus.children {
+        size(child);
+    }
+
+    for kind in Kind::ALL {
+        let items = bus.items(kind);
+        let end = items.iter().fold(0, |cursor, (need, _)| {
+            cursor.next_multiple_of(need.align) + need.size
+        });
+        let align = items.iter().map(|(need, _)| need.align).max().unwrap_or(1);
+
+        bus.needs[kind as usize] = match bus.bridge {
+            Some(_) if end > 0 => Need {
+                size: end.next_multiple_of(kind.window_align()),
+                align: align.max(kind.window_align()),
+            },
+            _ => Need { size: end, align },
+        };
+    }
+}
+
+/// the lowest address in `region`, aligned for `need`, where it doesn't overlap `used`
+fn first_fit(region: &Range<u64>, used: &[Range<u64>], need: Need) -> Option<u64> {
+    let align = need.align.max(1);
+    let mut base = region.start.next_multiple_of(align);
+
+    loop {
+        let end = base.checked_add(need.size)?;
+        if end > region.end {
+            return None;
+        }
+
+        match used
+            .iter()
+            .filter(|range| range.start < end && base < range.end)
+            .map(|range| range.end)
+            .max()
+        {
+            Some(after) => base = after.next_multiple_of(align),
+            None => return Some(base),
+        }
+    }
+}
+
+/// place what's missing on `bus` around what's kept, then do the same behind the bridges
+/// with kept windows. memory and prefetchable memory may share an aperture, so they share
+/// what's taken.
+fn fill(ecam: &Ecam, bus: &Bus) {
+    let mut io = bus.occupied(true);
+    let mut memory = bus.occupied(false);
+
+    for kind in Kind::ALL {
+        let Some(region) = &bus.windows[kind as usize] else {
+            continue;
+        };
+        let region = match kind {
+            Kind::Io => region.start.max(IO_START)..region.end,
+            _ => region.clone(),
+        };
+        let used = match kind {
+            Kind::Io => &mut io,
+            _ => &mut memory,
+        };
+
+        for (need, item) in bus.items(kind) {
+            let Some(base) = first_fit(&region, used, need) else {
+                warn!(
+                    "PCIe bus {:#04x}: no room for {:#x} bytes of {:?} in {:#x?}",
+                    bus.number, need.size, kind, region
+                );
+                continue;
+            };
+            used.push(base..base + need.size);
+
+            match item {
+                Item::Bar(bar) => assign_bar(ecam, bar, base),
+                Item::Bus(child) => {
+                    if let Some(bridge) = child.bridge {
+                        set_window(ecam, bridge, kind, base..base + need.size);
+                    }
+                    place(ecam, child, kind, base);
+                }
+            }
+        }
+    }
+
+    for child in &bus.children {
+        if child.windows.iter().any(Option::is_some) {
+            fill(ecam, child);
+        }
+    }
+}
+
+fn assign_bar(ecam: &Ecam, bar: &Bar, address: u64) {
+    debug!("{}: BAR {} at {:#x}", bar.bdf, bar.index, address);
+
+    // nothing decodes while it moves
+    let cmd = ecam.read_u16(bar.bdf, CMD_REG);
+    ecam.write_u16(bar.bdf, CMD_REG, cmd & !(CMD_IO | CMD_MEMORY));
+
+    set_bar_address(ecam, bar.bdf, bar.index, address);
+    bar.assigned.set(true);
+}
+
+/// put what's on `bus` of `kind` at `base` on, and program the windows of the bridges below.
+/// nothing of `kind` on or behind it is kept.
+fn place(ecam: &Ecam, bus: &Bus, kind: Kind, base: u64) {
+    let mut cursor = base;
+
+    for (need, item) in bus.items(kind) {
+        cursor = cursor.next_multiple_of(need.align);
+
+        match item {
+            Item::Bar(bar) => assign_bar(ecam, bar, cursor),
+            Item::Bus(child) => {
+                if let Some(bridge) = child.bridge {
+                    set_window(ecam, bridge, kind, cursor..cursor + need.size);
+                }
+                place(ecam, child, kind, cursor);
+            }
+        }
+
+        cursor += need.size;
+    }
+
+    trace!(
+        "PCIe bus {:#04x}: {:?} up to {:#x}",
+        bus.number, kind, cursor
+    );
+}
+
+fn set_window(ecam: &Ecam, bridge: Bdf, kind: Kind, range: Range<u64>) {
+    debug!("{}: {:?} window {:#x?}", bridge, kind, range);
+
+    let last = range.end - 1;
+    match kind {
+        Kind::Io => {
+            ecam.write_u16(bridge, IO_BASE_UPPER, (range.start >> 16) as u16);
+            ecam.write_u16(bridge, IO_LIMIT_UPPER, (last >> 16) as u16);
+            ecam.write_u8(bridge, IO_BASE, (range.start >> 8) as u8 & 0xF0);
+            ecam.write_u8(bridge, IO_LIMIT, (last >> 8) as u8 & 0xF0);
+        }
+        Kind::Memory => {
+            ecam.write_u16(bridge, MEMORY_BASE, (range.start >> 16) as u16 & 0xFFF0);
+            ecam.write_u16(bridge, MEMORY_LIMIT, (last >> 16) as u16 & 0xFFF0);
+        }
+        Kind::Prefetch => {
+            let is_64bit = ecam.read_u16(bridge, PREFETCH_BASE) & 0xF == PREFETCH_64BIT;
+            if !is_64bit && last >= FOUR_GIB {
+                warn!(
+                    "{}: prefetchable window is 32-bit, but placed above 4GiB",
+                    bridge
+                );
+            }
+
+            ecam.write_u32(bridge, PREFETCH_BASE_UPPER, (range.start >> 32) as u32);
+            ecam.write_u32(bridge, PREFETCH_LIMIT_UPPER, (last >> 32) as u32);
+            ecam.write_u16(bridge, PREFETCH_BASE, (range.start >> 16) as u16 & 0xFFF0);
+            ecam.write_u16(bridge, PREFETCH_LIMIT, (last >> 16) as u16 & 0xFFF0);
+        }
+    }
+}
+
+/// a window is off when its base is above its limit
+fn disable_window(ecam: &Ecam, bridge: Bdf, kind: Kind) {
+    match kind {
+        Kind::Io => {
+            ecam.write_u16(bridge, IO_BASE_UPPER, 0);
+            ecam.write_u16(bridge, IO_LIMIT_UPPER, 0);
+            ecam.write_u8(bridge, IO_BASE, 0xF0);
+            ecam.write_u8(bridge, IO_LIMIT, 0x00);
+        }
+        Kind::Memory => {
+            ecam.write_u16(bridge, MEMORY_BASE, 0xFFF0);
+            ecam.write_u16(bridge, MEMORY_LIMIT, 0x0000);
+        }
+        Kind::Prefetch => {
+            ecam.write_u32(bridge, PREFETCH_BASE_UPPER, 0);
+            ecam.write_u32(bridge, PREFETCH_LIMIT_UPPER, 0);
+            ecam.write_u16(bridge, PREFETCH_BASE, 0xFFF0);
+            ecam.write_u16(bridge, PREFETCH_LIMIT, 0x0000);
+        }
+    }
+}
+
+/// decode on for functions whose BARs are all assigned, off for the others. bridges also
+/// forward requests upstream, for DMA and MSIs.
+fn enable(ecam: &Ecam, bus: &Bus) {
+    for &bdf in &bus.functions {
+        let mut cmd = ecam.read_u16(bdf, CMD_REG);
+
+        if bus.children.iter().any(|child| child.bridge == Some(bdf)) {
+            cmd |= CMD_IO | CMD_MEMORY | CMD_BUS_MASTER;
+        }
+
+        for (io, bit) in [(true, CMD_IO), (false, CMD_MEMORY)] {
+            let mut bars = bus
+                .bars
+                .iter()
+                .filter(|bar| bar.bdf == bdf && (bar.kind == Kind::Io) == io)
+                .peekable();
+            if bars.peek().is_none() {
+                continue;
+            }
+
+            if bars.all(|bar| bar.assigned.get()) {
+                cmd |= bit;
+            } else {
+                cmd &= !bit;
+            }
+        }
+
+        ecam.write_u16(bdf, CMD_REG, cmd);
+    }
+
+    for child in &bus.children {
+        enable(ecam, child);
+    }
+}
+
+/// the windows `bridge` forwards, in bus addresses
+pub(crate) fn bridge_windows(ecam: &Ecam, bridge: Bdf) -> Vec<(AddressSpace, Range<u64>)> {
+    let io_base = (((ecam.read_u8(bridge, IO_BASE) & 0xF0) as u64) << 8)
+        | ((ecam.read_u16(bridge, IO_BASE_UPPER) as u64) << 16);
+    let io_limit = (((ecam.read_u8(bridge, IO_LIMIT) & 0xF0) as u64) << 8)
+        | ((ecam.read_u16(bridge, IO_LIMIT_UPPER) as u64) << 16)
+        | 0xFFF;
+
+    let memory_base = ((ecam.read_u16(bridge, MEMORY_BASE) & 0xFFF0) as u64) << 16;
+    let memory_limit = (((ecam.read_u16(bridge, MEMORY_LIMIT) & 0xFFF0) as u64) << 16) | 0xF_FFFF;
+
+    let prefetch = ecam.read_u16(bridge, PREFETCH_BASE);
+    let (base_upper, limit_upper) = if prefetch & 0xF == PREFETCH_64BIT {
+        (
+            ecam.read_u32(bridge, PREFETCH_BASE_UPPER) as u64,
+            ecam.read_u32(bridge, PREFETCH_LIMIT_UPPER) as u64,
+        )
+    } else {
+        (0, 0)
+    };
+    let prefetch_base = (((prefetch & 0xFFF0) as u64) << 16) | (base_upper << 32);
+    let prefetch_limit = (((ecam.read_u16(bridge, PREFETCH_LIMIT) & 0xFFF0) as u64) << 16)
+        | (limit_upper << 32)
+        | 0xF_FFFF;
+
+    [
+        (AddressSpace::Io, io_base, io_limit),
+        (
+            AddressSpace::Memory {
+                prefetchable: false,
+            },
+            memory_base,
+            memory_limit,
+        ),
+        (
+            AddressSpace::Memory { prefetchable: true },
+            prefetch_base,
+            prefetch_limit,
+        ),
+    ]
+    .into_iter()
+    .filter(|(_, base, limit)| base < limit)
+    .map(|(space, base, limit)| (space, base..limit + 1))
+    .collect()
+}
@@ -37,6 +37,15 @@ impl Drop for CommandRegGuard<'_> {
 }
 
 pub fn probe_bars(ecam: &Ecam, bdf: Bdf) -> Vec<BarType> {
+    probe_indexed_bars(ecam, bdf, 6)
+        .into_iter()
+        .map(|(_, bar)| bar)
+        .collect()
+}
+
+/// the BARs of a function with `count` BAR registers, 6 for endpoints and 2 for bridges, each
+/// with the index of the register it starts at
+pub fn probe_indexed_bars(ecam: &Ecam, bdf: Bdf, count: u8) -> Vec<(u8, BarType)> {
     let mut bars = Vec::new();
     let mut bar_i = 0;
 
@@ -49,8 +58,8 @@ pub fn probe_bars(ecam: &Ecam, bdf: Bdf) -> Vec<BarType> {
         original_command,
     };
 
-    while bar_i < 6 {
-        let offset = 0x10 + (bar_i * 4);
+    while bar_i < count {
+        let offset = 0x10 + (bar_i as u16 * 4);
         let original_val = ecam.read_u32(bdf, offset);
 
         // probe size
@@ -68,11 +77,16 @@ pub fn probe_bars(ecam: &Ecam, bdf: Bdf) -> Vec<BarType> {
             }
 
             let addr = original_val & 0xFFFF_FFFC;
-            let size = (!(mask & 0xFFFF_FFFC)).wrapping_add(1);
-            bars.push(BarType::Io {
-                address: addr,
-                size,
-            });
+            // the lowest writable bit, since 16-bit I/O BARs read back zeroes in the upper half
+            let mask = mask & 0xFFFF_FFFC;
+            let size = mask & mask.wrapping_neg();
+            bars.push((
+                bar_i,
+                BarType::Io {
+                    address: addr,
+                    size,
+                },
+            ));
             bar_i += 1;
         } else if type_bits == 0 {
             // Memory32 BAR
@@ -84,15 +98,18 @@ pub fn probe_bars(ecam: &Ecam, bdf: Bdf) -> Vec<BarType> {
             let prefetchable = ((original_val >> 3) & 0x1) != 0;
             let addr = original_val & 0xFFFF_FFF0;
             let size = (!(mask & 0xFFFF_FFF0)).wrapping_add(1);
-            bars.push(BarType::Memory32 {
-                address: addr,
-                size,
-                prefetchable,
-            });
+            bars.push((
+                bar_i,
+                BarType::Memory32 {
+                    address: addr,
+                    size,
+                    prefetchable,
+                },
+            ));
             bar_i += 1;
         } else if type_bits == 2 {
             // Memory64 BAR
-            if bar_i >= 5 {
+            if bar_i + 1 >= count {
                 log::warn!("{}: 64-bit BAR reported at invalid index {}", bdf, bar_i);
                 bar_i += 1;
                 continue;
@@ -114,11 +131,14 @@ pub fn probe_bars(ecam: &Ecam, bdf: Bdf) -> Vec<BarType> {
             let addr = ((original_high as u64) << 32) | (original_val as u64 & 0xFFFF_FFF0);
             let size = (!raw_mask).wrapping_add(1);
 
-            bars.push(BarType::Memory64 {
-                address: addr,
-                size,
-                prefetchable,
-            });
+            bars.push((
+                bar_i,
+                BarType::Memory64 {
+                    address: addr,
+                    size,
+                    prefetchable,
+                },
+            ));
             bar_i += 2; // 64-bit BAR takes up two 32-bit regs
         } else {
             // reserved
@@ -128,3 +148,38 @@ pub fn probe_bars(ecam: &Ecam, bdf: Bdf) -> Vec<BarType> {
 
     bars
 }
+
+/// the currently programmed address of BAR `index`, without sizing it.
+pub fn bar_address(ecam: &Ecam, bdf: Bdf, index: u8) -> Option<u64> {
+    if index >= 6 {
+        return None;
+    }
+
+    let offset = 0x10 + (index as u16 * 4);
+    let low = ecam.read_u32(bdf, offset);
+
+    if (low & 0x1) != 0 {
+        return Some((low & 0xFFFF_FFFC) as u64);
+    }
+
+    match (low >> 1) & 0x3 {
+        0 => Some((low & 0xFFFF_FFF0) as u64),
+        2 if index < 5 => {
+            let high = ecam.read_u32(bdf, offset + 4);
+            Some(((high as u64) << 32) | (low & 0xFFFF_FFF0) as u64)
+        }
+        _ => None,
+    }
+}
+
+/// point BAR `index` at `address`, both halves if it's 64-bit. memory decoding should be off.
+pub fn set_bar_address(ecam: &Ecam, bdf: Bdf, index: u8, address: u64) {
+    let offset = 0x10 + (index as u16 * 4);
+    let low = ecam.read_u32(bdf, offset);
+
+    // the type bits are read only
+    ecam.write_u32(bdf, offset, address as u32);
+    if (low & 0x1) == 0 && (low >> 1) & 0x3 == 2 {
+        ecam.write_u32(bdf, offset + 4, (address >> 32) as u32);
+    }
+}
@@ -109,15 +109,37 @@ impl<'a> Iterator for ExtendedCapIter<'a> {
 pub struct AerCap {
     pub offset: u16,
     pub uncorrectable_status: u32,
+    pub uncorrectable_mask: u32,
+    /// set bits are fatal, clear ones non-fatal
+    pub uncorrectable_severity: u32,
     pub correctable_status: u32,
+    pub correctable_mask: u32,
+    /// the uncorrectable status bit that was set first
+    pub first_error_pointer: u8,
+    /// header of the TLP the first uncorrectable error is about
+    pub header_log: [u32; 4],
 }
 
 impl AerCap {
+    const ROOT_COMMAND: u16 = 0x2C;
+    const ROOT_STATUS: u16 = 0x30;
+    const ERROR_SOURCE: u16 = 0x34;
+
     pub fn read(ecam: &Ecam, bdf: Bdf, offset: u16) -> Self {
+        let mut header_log = [0; 4];
+        for (i, dw) in header_log.iter_mut().enumerate() {
+            *dw = ecam.read_u32(bdf, offset + 0x1C + i as u16 * 4);
+        }
+
         Self {
             offset,
             uncorrectable_status: ecam.read_u32(bdf, offset + 0x04),
+            uncorrectable_mask: ecam.read_u32(bdf, offset + 0x08),
+            uncorrectable_severity: ecam.read_u32(bdf, offset + 0x0C),
             correctable_status: ecam.read_u32(bdf, offset + 0x10),
+            correctable_mask: ecam.read_u32(bdf, offset + 0x14),
+            first_error_pointer: (ecam.read_u32(bdf, offset + 0x18) & 0x1F) as u8,
+            header_log,
         }
     }
 
@@ -128,6 +150,34 @@ impl AerCap {
         let corr = ecam.read_u32(bdf, self.offset + 0x10);
         ecam.write_u32(bdf, self.offset + 0x10, corr);
     }
+
+    /// write-1-to-clear `bits` of the uncorrectable status
+    pub fn clear_uncorrectable(&self, ecam: &Ecam, bdf: Bdf, bits: u32) {
+        ecam.write_u32(bdf, self.offset + 0x04, bits);
+    }
+
+    /// write-1-to-clear `bits` of the correctable status
+    pub fn clear_correctable(&self, ecam: &Ecam, bdf: Bdf, bits: u32) {
+        ecam.write_u32(bdf, self.offset + 0x10, bits);
+    }
+
+    /// root ports: which error messages raise the AER interrupt
+    pub fn set_root_command(&self, ecam: &Ecam, bdf: Bdf, command: u32) {
+        ecam.write_u32(bdf, self.offset + Self::ROOT_COMMAND, command);
+    }
+
+    /// root ports: the root error status, and the requester IDs of the last correctable (low
+    /// half) and uncorrectable (high half) error messages
+    pub fn root_status(&self, ecam: &Ecam, bdf: Bdf) -> (u32, u32) {
+        (
+            ecam.read_u32(bdf, self.offset + Self::ROOT_STATUS),
+            ecam.read_u32(bdf, self.offset + Self::ERROR_SOURCE),
+        )
+    }
+
+    pub fn clear_root_status(&self, ecam: &Ecam, bdf: Bdf, status: u32) {
+        ecam.write_u32(bdf, self.offset + Self::ROOT_STATUS, status);
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
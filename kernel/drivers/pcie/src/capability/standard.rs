@@ -146,6 +146,49 @@ impl MsiCap {
             multiple_message_capable: 1 << ((msg_ctrl >> 1) & 0x07),
         }
     }
+
+    /// where the message data is, after the address
+    const fn data_offset(&self) -> u16 {
+        self.offset as u16 + if self.is_64bit { 12 } else { 8 }
+    }
+
+    /// point every vector at `address`. vector `n` writes `data | n`.
+    pub fn configure(&self, ecam: &Ecam, bdf: Bdf, address: u64, data: u16) {
+        ecam.write_u32(bdf, self.offset as u16 + 4, address as u32);
+        if self.is_64bit {
+            ecam.write_u32(bdf, self.offset as u16 + 8, (address >> 32) as u32);
+        }
+        ecam.write_u16(bdf, self.data_offset(), data);
+    }
+
+    /// mask or unmask vector `vector`. does nothing without per vector masking.
+    pub fn set_masked(&self, ecam: &Ecam, bdf: Bdf, vector: u8, masked: bool) {
+        if !self.per_vector_masking {
+            return;
+        }
+
+        let offset = self.data_offset() + 4;
+        let mask = ecam.read_u32(bdf, offset);
+        let mask = if masked {
+            mask | (1 << vector)
+        } else {
+            mask & !(1 << vector)
+        };
+        ecam.write_u32(bdf, offset, mask);
+    }
+
+    /// enable MSI with 2^`vectors_log2` vectors
+    pub fn enable(&self, ecam: &Ecam, bdf: Bdf, vectors_log2: u8) {
+        let msg_ctrl = ecam.read_u16(bdf, self.offset as u16 + 2);
+        // multiple message enable (6:4) and MSI enable (0)
+        let msg_ctrl = (msg_ctrl & !(0x07 << 4)) | ((vectors_log2 as u16 & 0x07) << 4) | 1;
+        ecam.write_u16(bdf, self.offset as u16 + 2, msg_ctrl);
+    }
+
+    pub fn disable(&self, ecam: &Ecam, bdf: Bdf) {
+        let msg_ctrl = ecam.read_u16(bdf, self.offset as u16 + 2);
+        ecam.write_u16(bdf, self.offset as u16 + 2, msg_ctrl & !1);
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -218,4 +261,36 @@ impl PcieCap {
             current_link_width: ((link_status >> 4) & 0x3F) as u8,
         }
     }
+
+    /// send correctable, non-fatal, fatal and unsupported request error messages upstream
+    pub fn enable_error_reporting(&self, ecam: &Ecam, bdf: Bdf) {
+        let dev_ctl = ecam.read_u16(bdf, self.offset as u16 + 8);
+        ecam.write_u16(bdf, self.offset as u16 + 8, dev_ctl | 0x0F);
+    }
+
+    /// the device status error bits are write-1-to-clear
+    pub fn clear_error_status(&self, ecam: &Ecam, bdf: Bdf) {
+        ecam.write_u16(bdf, self.offset as u16 + 10, 0x0F);
+    }
+
+    /// root ports: stop turning error messages into system errors, so only AER sees them
+    pub fn disable_system_errors(&self, ecam: &Ecam, bdf: Bdf) {
+        let root_ctl = ecam.read_u16(bdf, self.offset as u16 + 28);
+        ecam.write_u16(bdf, self.offset as u16 + 28, root_ctl & !0x07);
+    }
+
+    pub fn flr_capable(&self, ecam: &Ecam, bdf: Bdf) -> bool {
+        ecam.read_u32(bdf, self.offset as u16 + 4) & (1 << 28) != 0
+    }
+
+    /// whether requests the function sent are still waiting for completions
+    pub fn transactions_pending(&self, ecam: &Ecam, bdf: Bdf) -> bool {
+        ecam.read_u16(bdf, self.offset as u16 + 10) & (1 << 5) != 0
+    }
+
+    /// start a function level reset. config space reads back as reset 100ms later.
+    pub fn initiate_flr(&self, ecam: &Ecam, bdf: Bdf) {
+        let dev_ctl = ecam.read_u16(bdf, self.offset as u16 + 8);
+        ecam.write_u16(bdf, self.offset as u16 + 8, dev_ctl | (1 << 15));
+    }
 }
@@ -1,9 +1,29 @@
 use core::ptr::{read_volatile, write_volatile};
 
-use klib::{allocator_support::KernelAddressTranslator, pm::page::mapper::AddressTranslator};
+use alloc::{boxed::Box, vec::Vec};
+use klib::{
+    allocator_support::KernelAddressTranslator, pm::page::mapper::AddressTranslator, sync::RwLock,
+};
 
 use crate::address::Bdf;
 
+static SEGMENTS: RwLock<Vec<&'static Ecam>> = RwLock::new(Vec::new());
+
+/// make a segment's config space reachable by drivers after enumeration.
+pub fn register_segment(ecam: Ecam) -> &'static Ecam {
+    let ecam: &'static Ecam = Box::leak(Box::new(ecam));
+    SEGMENTS.write().push(ecam);
+    ecam
+}
+
+pub fn get_segment(segment: u16) -> Option<&'static Ecam> {
+    SEGMENTS
+        .read()
+        .iter()
+        .find(|ecam| ecam.segment == segment)
+        .copied()
+}
+
 pub struct Ecam {
     pub phys_base: u64,
     pub segment: u16,
@@ -33,6 +53,19 @@ impl Ecam {
         self.write_u16(bdf, CMD_REG, cmd | (1 << 1));
     }
 
+    /// the command register's interrupt disable bit, which masks INTx
+    pub fn set_intx_disabled(&self, bdf: Bdf, disabled: bool) {
+        const CMD_REG: u16 = 0x04;
+        const INTX_DISABLE: u16 = 1 << 10;
+        let cmd = self.read_u16(bdf, CMD_REG);
+        let cmd = if disabled {
+            cmd | INTX_DISABLE
+        } else {
+            cmd & !INTX_DISABLE
+        };
+        self.write_u16(bdf, CMD_REG, cmd);
+    }
+
     fn offset_ptr(&self, bdf: Bdf, offset: u16) -> Option<*mut u8> {
         if offset > 0x0FFF {
             return None;
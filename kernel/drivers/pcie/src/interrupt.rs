@@ -3,20 +3,36 @@ use core::{
     ptr::{NonNull, read_volatile, write_volatile},
 };
 
-use alloc::vec::Vec;
+use alloc::{vec, vec::Vec};
 use klib::{
-    cpu_interface::CpuIdLogical, interrupt::singleton::get_interrupt_controller, per_cpu::PerCpu,
+    cpu_interface::CpuIdLogical,
+    hardware::{mmio::map_mmio, resource::Irq},
+    interrupt::{singleton::get_interrupt_controller, target_spi},
+    per_cpu::PerCpu,
+    sync::RwLock,
 };
+use log::*;
 
 use crate::{
     address::Bdf,
+    bar::bar_address,
     capability::{
         find_capability,
-        standard::{MsixCap, StandardCapabilityId},
+        standard::{MsiCap, MsixCap, StandardCapabilityId},
     },
     ecam::Ecam,
+    scan::upstream_bridge,
 };
 
+const STATUS_REG: u16 = 0x06;
+/// the function is asserting INTx
+const STATUS_INTERRUPT: u16 = 1 << 3;
+/// 1 is INTA, 0 is none
+const INTERRUPT_PIN: u16 = 0x3D;
+
+/// GSIV of the first of QEMU virt's four PCIe INTx SPIs, see `hw/arm/virt.c`
+const QEMU_VIRT_INTX_BASE: u32 = 32 + 3;
+
 /// MMIO view of the MSI-X table
 pub struct MsixTable {
     base: NonNull<u8>,
@@ -186,14 +202,34 @@ pub fn get_msix_info(ecam: &Ecam, bdf: Bdf) -> Option<MsixInfo> {
     })
 }
 
+/// map the MSI-X table of `bdf`, sized as the capability says
+pub fn map_msix_table(ecam: &Ecam, bdf: Bdf) -> Option<MsixTable> {
+    let info = get_msix_info(ecam, bdf)?;
+    let base = bar_address(ecam, bdf, info.table_bir).filter(|&base| base != 0)?;
+
+    let start = base as usize + info.table_offset as usize;
+    let len = info.table_size as usize * 16;
+    let ptr = map_mmio(&(start..start + len))?;
+
+    Some(unsafe { MsixTable::from_raw_parts(ptr, info.table_size as usize) })
+}
+
 pub fn enable_msix(ecam: &Ecam, bdf: Bdf, table: &mut MsixTable) -> Result<Vec<u32>, &'static str> {
-    use log::*;
+    enable_msix_vectors(ecam, bdf, table, usize::MAX)
+}
 
+/// enable MSI-X with at most `count` vectors unmasked
+fn enable_msix_vectors(
+    ecam: &Ecam,
+    bdf: Bdf,
+    table: &mut MsixTable,
+    count: usize,
+) -> Result<Vec<u32>, &'static str> {
     let cap_offset = find_capability(ecam, bdf, StandardCapabilityId::MsiX)
         .ok_or("MSI-X capability not found")?;
 
     let msix = MsixCap::read(ecam, bdf, cap_offset);
-    let num_vectors = (msix.table_size as usize).min(table.len());
+    let num_vectors = (msix.table_size as usize).min(table.len()).min(count);
     if num_vectors == 0 {
         return Err("MSI-X table capacity is 0");
     }
@@ -201,7 +237,7 @@ pub fn enable_msix(ecam: &Ecam, bdf: Bdf, table: &mut MsixTable) -> Result<Vec<u
     ecam.enable_memory_space(bdf);
     ecam.enable_bus_master(bdf);
 
-    let dev_id = bdf.device_id();
+    let dev_id = bdf.device_id().ok_or("MSIs don't reach an ITS")?;
     let num_events_log2 = (msix.table_size as u32)
         .next_power_of_two()
         .trailing_zeros();
@@ -258,3 +294,204 @@ pub fn enable_msix(ecam: &Ecam, bdf: Bdf, table: &mut MsixTable) -> Result<Vec<u
 
     Ok(mapped_lpis)
 }
+
+/// enable MSI with `count` vectors, rounded up to a power of two and down to what the function
+/// can do
+pub fn enable_msi(ecam: &Ecam, bdf: Bdf, count: usize) -> Result<Vec<u32>, &'static str> {
+    let cap_offset =
+        find_capability(ecam, bdf, StandardCapabilityId::Msi).ok_or("MSI capability not found")?;
+    let msi = MsiCap::read(ecam, bdf, cap_offset);
+
+    let num_vectors = count
+        .max(1)
+        .next_power_of_two()
+        .min(msi.multiple_message_capable as usize);
+    let num_vectors_log2 = num_vectors.trailing_zeros();
+
+    let dev_id = bdf.device_id().ok_or("MSIs don't reach an ITS")?;
+    let ctrl = get_interrupt_controller();
+
+    if let Err(e) = ctrl.msi_register_device(dev_id, num_vectors_log2) {
+        error!(
+            "{}: Failed to register MSI device {:#x}: {:?}",
+            bdf, dev_id, e
+        );
+        return Err("MSI device registration fail");
+    }
+
+    let doorbell = ctrl
+        .msi_get_doorbell()
+        .map_err(|_| "Failed to get MSI doorbell")?;
+
+    let num_cpus = (PerCpu::all().len() as u32).max(1);
+    let mut mapped_lpis = Vec::with_capacity(num_vectors);
+
+    // vector n writes its number as the EventID, so the vectors have to be contiguous: stop at
+    // the first one that can't be mapped
+    for event_id in 0..num_vectors as u32 {
+        let target_cpu = CpuIdLogical::new(event_id % num_cpus);
+        match ctrl.msi_map_event(dev_id, event_id, None, target_cpu) {
+            Ok(lpi) => mapped_lpis.push(lpi),
+            Err(e) => {
+                error!("{}: Failed to map EventID {}: {:?}", bdf, event_id, e);
+                break;
+            }
+        }
+    }
+
+    if mapped_lpis.is_empty() {
+        return Err("Failed to map any MSI vectors");
+    }
+
+    // only powers of two can be enabled
+    mapped_lpis.truncate(1 << mapped_lpis.len().ilog2());
+
+    ecam.enable_bus_master(bdf);
+    ecam.set_intx_disabled(bdf, true);
+
+    msi.configure(ecam, bdf, doorbell, 0);
+    for vector in 0..mapped_lpis.len() as u8 {
+        msi.set_masked(ecam, bdf, vector, false);
+    }
+    msi.enable(ecam, bdf, mapped_lpis.len().trailing_zeros() as u8);
+
+    info!(
+        "{}: Enabled {} MSI vectors across {} CPUs",
+        bdf,
+        mapped_lpis.len(),
+        num_cpus
+    );
+
+    Ok(mapped_lpis)
+}
+
+/// the interrupt INTx pin `pin` of a device on a root bus raises
+#[derive(Debug, Copy, Clone)]
+pub struct IntxRoute {
+    pub segment: u16,
+    pub bus: u8,
+    pub device: u8,
+    /// 1 is INTA
+    pub pin: u8,
+    pub irq: Irq,
+}
+
+/// from firmware, `_PRT` or a fixed platform swizzle
+static INTX_ROUTES: RwLock<Vec<IntxRoute>> = RwLock::new(Vec::new());
+
+pub fn add_intx_routes(routes: impl IntoIterator<Item = IntxRoute>) {
+    INTX_ROUTES.write().extend(routes);
+}
+
+/// QEMU virt's routing for root bus `bus`: four SPIs, rotated by the device number
+pub fn qemu_virt_intx_routes(segment: u16, bus: u8) -> impl Iterator<Item = IntxRoute> {
+    (0..32u8).flat_map(move |device| {
+        (1..=4u8).map(move |pin| IntxRoute {
+            segment,
+            bus,
+            device,
+            pin,
+            irq: Irq::new(QEMU_VIRT_INTX_BASE + (device as u32 + pin as u32 - 1) % 4),
+        })
+    })
+}
+
+/// the pin INTx `pin` of device `device` comes out of the bridge above it on, PCI-to-PCI
+/// bridge spec section 9.1
+const fn swizzle(device: u8, pin: u8) -> u8 {
+    ((pin - 1 + device) % 4) + 1
+}
+
+/// the interrupt `bdf`'s INTx pin raises, following it up through bridges to the root bus
+pub fn intx_irq(ecam: &Ecam, bdf: Bdf) -> Option<Irq> {
+    let mut pin = ecam.read_u8(bdf, INTERRUPT_PIN);
+    if !(1..=4).contains(&pin) {
+        return None;
+    }
+
+    let mut dev = bdf;
+    while let Some(bridge) = upstream_bridge(dev.segment, dev.bus) {
+        pin = swizzle(dev.device, pin);
+        dev = bridge;
+    }
+
+    INTX_ROUTES
+        .read()
+        .iter()
+        .find(|route| {
+            route.segment == dev.segment
+                && route.bus == dev.bus
+                && route.device == dev.device
+                && route.pin == pin
+        })
+        .map(|route| route.irq)
+}
+
+/// whether `bdf` is asserting INTx. lines are shared, so handlers check this first.
+pub fn intx_pending(ecam: &Ecam, bdf: Bdf) -> bool {
+    ecam.read_u16(bdf, STATUS_REG) & STATUS_INTERRUPT != 0
+}
+
+/// route and unmask `bdf`'s INTx line. the caller registers the handler and enables the SPI.
+pub fn enable_intx(ecam: &Ecam, bdf: Bdf) -> Result<u32, &'static str> {
+    let irq = intx_irq(ecam, bdf).ok_or("no INTx route")?;
+
+    target_spi(irq.gsiv, irq.trigger).map_err(|_| "Failed to route INTx")?;
+
+    ecam.enable_bus_master(bdf);
+    ecam.set_intx_disabled(bdf, false);
+
+    Ok(irq.gsiv)
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum VectorKind {
+    MsiX,
+    Msi,
+    Intx,
+}
+
+#[derive(Debug, Clone)]
+pub struct Vectors {
+    pub kind: VectorKind,
+    /// by vector: LPIs for MSI-X and MSI, the one SPI for INTx, which may be shared
+    pub irqs: Vec<u32>,
+}
+
+/// up to `count` interrupt vectors for `bdf`, preferring MSI-X, then MSI, then INTx. there can
+/// be fewer than asked for, and INTx always has one.
+pub fn alloc_vectors(ecam: &Ecam, bdf: Bdf, count: usize) -> Result<Vectors, &'static str> {
+    if count == 0 {
+        return Err("no vectors asked for");
+    }
+
+    if let Some(mut table) = map_msix_table(ecam, bdf) {
+        match enable_msix_vectors(ecam, bdf, &mut table, count) {
+            Ok(irqs) => {
+                return Ok(Vectors {
+                    kind: VectorKind::MsiX,
+                    irqs,
+                });
+            }
+            Err(e) => debug!("{}: no MSI-X: {}", bdf, e),
+        }
+    }
+
+    match enable_msi(ecam, bdf, count) {
+        Ok(irqs) => {
+            return Ok(Vectors {
+                kind: VectorKind::Msi,
+                irqs,
+            });
+        }
+        Err(e) => debug!("{}: no MSI: {}", bdf, e),
+    }
+
+    let irq = enable_intx(ecam, bdf)?;
+    info!("{}: Using INTx, IRQ {}", bdf, irq);
+
+    Ok(Vectors {
+        kind: VectorKind::Intx,
+        irqs: vec![irq],
+    })
+}
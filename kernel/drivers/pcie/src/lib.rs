@@ -3,8 +3,11 @@
 extern crate alloc;
 
 pub mod address;
+pub mod aer;
+pub mod assign;
 pub mod bar;
 pub mod capability;
 pub mod ecam;
 pub mod interrupt;
+pub mod reset;
 pub mod scan;
@@ -0,0 +1,142 @@
+//! resets for recovering from errors: a function level reset of one function, or a secondary
+//! bus reset of everything behind a bridge. both put the configuration headers back as they
+//! were, but capabilities, MSI and MSI-X included, are the driver's to set up again.
+
+use core::{hint::spin_loop, ops::RangeInclusive, time::Duration};
+
+use alloc::vec::Vec;
+use klib::time;
+
+use crate::{
+    address::Bdf,
+    capability::{
+        find_capability,
+        standard::{PcieCap, StandardCapabilityId},
+    },
+    ecam::Ecam,
+};
+
+const CMD_REG: u16 = 0x04;
+const CMD_BUS_MASTER: u16 = 1 << 2;
+
+const BRIDGE_CONTROL: u16 = 0x3E;
+const BRIDGE_SECONDARY_RESET: u16 = 1 << 6;
+
+/// how long a secondary bus reset is held
+const RESET_HOLD: Duration = Duration::from_millis(2);
+/// how long functions get after a reset before config requests, PCIe base spec section 6.6
+const RESET_RECOVERY: Duration = Duration::from_millis(100);
+/// how long outstanding requests get to complete before an FLR goes ahead anyway
+const PENDING_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// a configuration header past the IDs
+struct SavedHeader {
+    bdf: Bdf,
+    header: [u32; 15],
+}
+
+impl SavedHeader {
+    fn save(ecam: &Ecam, bdf: Bdf) -> Self {
+        let mut header = [0; 15];
+        for (i, dw) in header.iter_mut().enumerate() {
+            *dw = ecam.read_u32(bdf, CMD_REG + i as u16 * 4);
+        }
+
+        Self { bdf, header }
+    }
+
+    /// the command register goes last, once the BARs and windows it enables are back
+    fn restore(&self, ecam: &Ecam) {
+        for (i, dw) in self.header.iter().enumerate().skip(1).rev() {
+            ecam.write_u32(self.bdf, CMD_REG + i as u16 * 4, *dw);
+        }
+
+        // the status half is write-1-to-clear
+        ecam.write_u16(self.bdf, CMD_REG, self.header[0] as u16);
+    }
+}
+
+fn delay(duration: Duration) {
+    let start = time::monotonic();
+    while time::monotonic() - start < duration {
+        spin_loop();
+    }
+}
+
+/// every function on `buses`, bus by bus
+fn functions(ecam: &Ecam, buses: RangeInclusive<u8>) -> Vec<Bdf> {
+    let mut functions = Vec::new();
+
+    for bus in buses {
+        for device in 0..32 {
+            let bdf = Bdf::new(ecam.segment, bus, device, 0);
+            if ecam.read_u16(bdf, 0x00) == !0 {
+                continue;
+            }
+
+            functions.push(bdf);
+            if ecam.read_u8(bdf, 0x0E) & 0x80 == 0 {
+                continue;
+            }
+
+            functions.extend(
+                (1..8)
+                    .map(|function| Bdf::new(ecam.segment, bus, device, function))
+                    .filter(|bdf| ecam.read_u16(*bdf, 0x00) != !0),
+            );
+        }
+    }
+
+    functions
+}
+
+/// reset `bdf` alone, if it can do a function level reset
+pub fn function_level_reset(ecam: &Ecam, bdf: Bdf) -> Result<(), &'static str> {
+    let pcie = find_capability(ecam, bdf, StandardCapabilityId::PciExpress)
+        .map(|offset| PcieCap::read(ecam, bdf, offset))
+        .ok_or("not a PCIe function")?;
+    if !pcie.flr_capable(ecam, bdf) {
+        return Err("no function level reset");
+    }
+
+    let saved = SavedHeader::save(ecam, bdf);
+
+    // no new requests, and the outstanding ones get a moment to finish
+    ecam.write_u16(bdf, CMD_REG, saved.header[0] as u16 & !CMD_BUS_MASTER);
+    let start = time::monotonic();
+    while pcie.transactions_pending(ecam, bdf) && time::monotonic() - start < PENDING_TIMEOUT {
+        spin_loop();
+    }
+
+    pcie.initiate_flr(ecam, bdf);
+    delay(RESET_RECOVERY);
+
+    saved.restore(ecam);
+    Ok(())
+}
+
+/// reset everything behind `bridge`, through its bridge control register
+pub fn secondary_bus_reset(ecam: &Ecam, bridge: Bdf) -> Result<(), &'static str> {
+    if ecam.read_u8(bridge, 0x0E) & 0x7F != 0x01 {
+        return Err("not a bridge");
+    }
+
+    let buses = ecam.read_u8(bridge, 0x19)..=ecam.read_u8(bridge, 0x1A);
+    // bridges are on lower buses than what's behind them, so they're restored first
+    let saved = functions(ecam, buses)
+        .into_iter()
+        .map(|bdf| SavedHeader::save(ecam, bdf))
+        .collect::<Vec<_>>();
+
+    let ctl = ecam.read_u16(bridge, BRIDGE_CONTROL);
+    ecam.write_u16(bridge, BRIDGE_CONTROL, ctl | BRIDGE_SECONDARY_RESET);
+    delay(RESET_HOLD);
+    ecam.write_u16(bridge, BRIDGE_CONTROL, ctl & !BRIDGE_SECONDARY_RESET);
+    delay(RESET_RECOVERY);
+
+    for header in &saved {
+        header.restore(ecam);
+    }
+
+    Ok(())
+}
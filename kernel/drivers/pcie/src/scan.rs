@@ -1,26 +1,184 @@
+use core::ops::RangeInclusive;
+
 use alloc::{
+    collections::btree_map::BTreeMap,
     format,
     string::String,
     {vec, vec::Vec},
 };
-use klib::hardware::{
-    device::{DeviceClass, DeviceInitPriority, DeviceTree},
-    resource::Resource,
+use klib::{
+    hardware::{
+        device::{DeviceClass, DeviceId, DeviceInitPriority, DeviceTree},
+        resource::{AddressSpace, Resource},
+    },
+    sync::RwLock,
 };
 use log::*;
 
 use crate::{
-    address::Bdf,
-    bar::{BarType, probe_bars},
+    address::{Bdf, PciBridgeKind, PciId},
+    assign::{assign_resources, bridge_windows},
+    bar::{BarType, probe_indexed_bars},
+    capability::{
+        find_capability,
+        standard::{PcieCap, StandardCapabilityId},
+    },
     ecam::Ecam,
 };
 
-pub fn enumerate_segment(ecam: &Ecam, dt: &mut DeviceTree) {
-    // standard init
-    scan_bus(ecam, ecam.start_bus, dt);
+/// PCIe capability device/port types
+const PORT_ROOT: u8 = 0x4;
+const PORT_UPSTREAM: u8 = 0x5;
+const PORT_DOWNSTREAM: u8 = 0x6;
+
+/// the bridge in front of each secondary bus, by segment and bus
+static UPSTREAM: RwLock<BTreeMap<(u16, u8), Bdf>> = RwLock::new(BTreeMap::new());
+
+/// the bridge `bus` is behind. none for root buses.
+pub fn upstream_bridge(segment: u16, bus: u8) -> Option<Bdf> {
+    UPSTREAM.read().get(&(segment, bus)).copied()
+}
+
+/// a root bus, and the host bridge above it
+pub struct HostBridge {
+    /// the host bridge's node, if firmware described it. one is added otherwise.
+    pub node: Option<DeviceId>,
+    /// the root bus, then the ones bridges behind it may be given
+    pub buses: RangeInclusive<u8>,
+    /// the apertures. without them, BARs are left as firmware programmed them.
+    pub windows: Vec<Resource>,
+}
+
+/// a bit per bus number
+#[derive(Default)]
+pub(crate) struct BusSet([u64; 4]);
+
+impl BusSet {
+    pub(crate) fn insert(&mut self, buses: RangeInclusive<u8>) {
+        for bus in buses {
+            self.0[bus as usize / 64] |= 1 << (bus % 64);
+        }
+    }
+
+    pub(crate) fn contains(&self, bus: u8) -> bool {
+        self.0[bus as usize / 64] & (1 << (bus % 64)) != 0
+    }
+}
+
+/// add the functions of a segment to the tree, below the host bridges of `hosts`. with no
+/// hosts, the whole segment is one host bridge without apertures.
+pub fn enumerate_segment(ecam: &Ecam, hosts: &[HostBridge], dt: &mut DeviceTree) {
+    let whole = [HostBridge {
+        node: None,
+        buses: ecam.start_bus..=ecam.end_bus,
+        windows: Vec::new(),
+    }];
+    let hosts = if hosts.is_empty() { &whole[..] } else { hosts };
+
+    let mut seen = BusSet::default();
+    let mut parents = Vec::with_capacity(hosts.len());
+
+    for host in hosts {
+        if host.windows.is_empty() {
+            warn!(
+                "PCIe {:04x}: no apertures for bus {:#04x}, keeping firmware's assignments",
+                ecam.segment,
+                host.buses.start()
+            );
+        } else {
+            assign_resources(ecam, host.buses.clone(), &host.windows);
+        }
+
+        let parent = host.node.unwrap_or_else(|| add_host_bridge(host, dt));
+        scan_bus(
+            ecam,
+            *host.buses.start(),
+            &host.windows,
+            parent,
+            &mut seen,
+            dt,
+        );
+        parents.push(parent);
+    }
+
+    // root buses no bridge leads to, e.g. behind host bridges firmware didn't describe
+    for bus in ecam.start_bus..=ecam.end_bus {
+        if seen.contains(bus) || !bus_populated(ecam, bus) {
+            continue;
+        }
+
+        let host = hosts
+            .iter()
+            .position(|host| host.buses.contains(&bus))
+            .unwrap_or(0);
+
+        debug!(
+            "PCIe {:04x}: bus {:#04x} isn't behind a bridge, scanning it as a root bus",
+            ecam.segment, bus
+        );
+        scan_bus(
+            ecam,
+            bus,
+            &hosts[host].windows,
+            parents[host],
+            &mut seen,
+            dt,
+        );
+    }
+}
+
+fn add_host_bridge(host: &HostBridge, dt: &mut DeviceTree) -> DeviceId {
+    let mut resources = host.windows.clone();
+    resources.push(Resource::Window {
+        space: AddressSpace::BusNumber,
+        range: *host.buses.start() as u64..*host.buses.end() as u64 + 1,
+        translation: 0,
+    });
+
+    dt.add_device(
+        None,
+        DeviceClass::PciHostBridge,
+        vec![String::from("pci-host-ecam-generic")],
+        resources,
+        DeviceInitPriority::Regular,
+    )
+}
+
+fn bus_populated(ecam: &Ecam, bus: u8) -> bool {
+    (0..32).any(|device| ecam.read_u16(Bdf::new(ecam.segment, bus, device, 0), 0x00) != !0)
 }
 
-fn scan_bus(ecam: &Ecam, bus: u8, dt: &mut DeviceTree) {
+/// the offset from bus addresses to where the CPU sees them, by the host aperture `address`
+/// is in
+fn translation(windows: &[Resource], io: bool, address: u64) -> u64 {
+    windows
+        .iter()
+        .find_map(|window| match window {
+            Resource::Window {
+                space,
+                range,
+                translation,
+            } if *space != AddressSpace::BusNumber
+                && (*space == AddressSpace::Io) == io
+                && range.contains(&address) =>
+            {
+                Some(*translation)
+            }
+            _ => None,
+        })
+        .unwrap_or(0)
+}
+
+fn scan_bus(
+    ecam: &Ecam,
+    bus: u8,
+    windows: &[Resource],
+    parent: DeviceId,
+    seen: &mut BusSet,
+    dt: &mut DeviceTree,
+) {
+    seen.insert(bus..=bus);
+
     for device in 0..32 {
         let bdf = Bdf::new(ecam.segment, bus, device, 0);
         let vendor = ecam.read_u16(bdf, 0x00);
@@ -29,7 +187,7 @@ fn scan_bus(ecam: &Ecam, bus: u8, dt: &mut DeviceTree) {
             continue; // no device
         }
 
-        scan_function(ecam, bdf, dt);
+        scan_function(ecam, bdf, windows, parent, seen, dt);
 
         let header_type = ecam.read_u8(bdf, 0x0E);
         if (header_type & 0x80) != 0 {
@@ -39,76 +197,160 @@ fn scan_bus(ecam: &Ecam, bus: u8, dt: &mut DeviceTree) {
                 let func_vendor = ecam.read_u16(func_bdf, 0x00);
 
                 if func_vendor != !0 {
-                    scan_function(ecam, func_bdf, dt);
+                    scan_function(ecam, func_bdf, windows, parent, seen, dt);
                 }
             }
         }
     }
 }
 
-fn scan_function(ecam: &Ecam, bdf: Bdf, dt: &mut DeviceTree) {
-    let vendor_id = ecam.read_u16(bdf, 0x00);
-    let device_id = ecam.read_u16(bdf, 0x02);
-    let class_code = ecam.read_u8(bdf, 0x0B);
-    let subclass = ecam.read_u8(bdf, 0x0A);
-    let prog_if = ecam.read_u8(bdf, 0x09);
-    let header_type = ecam.read_u8(bdf, 0x0E) & 0x07;
+fn read_id(ecam: &Ecam, bdf: Bdf, header_type: u8) -> PciId {
+    let (subsystem_vendor, subsystem) = if header_type == 0x00 {
+        (ecam.read_u16(bdf, 0x2C), ecam.read_u16(bdf, 0x2E))
+    } else {
+        (0, 0)
+    };
+
+    PciId {
+        vendor: ecam.read_u16(bdf, 0x00),
+        device: ecam.read_u16(bdf, 0x02),
+        class: ecam.read_u8(bdf, 0x0B),
+        subclass: ecam.read_u8(bdf, 0x0A),
+        prog_if: ecam.read_u8(bdf, 0x09),
+        revision: ecam.read_u8(bdf, 0x08),
+        subsystem_vendor,
+        subsystem,
+    }
+}
+
+/// what the BARs decode, as the CPU sees it. unassigned BARs are left out.
+fn bar_resources(ecam: &Ecam, bdf: Bdf, count: u8, windows: &[Resource]) -> Vec<Resource> {
+    let mut resources = Vec::new();
+
+    for (_, bar) in probe_indexed_bars(ecam, bdf, count) {
+        let (io, address, size) = match bar {
+            BarType::Memory32 { address, size, .. } => (false, address as u64, size as u64),
+            BarType::Memory64 { address, size, .. } => (false, address, size),
+            BarType::Io { address, size } => (true, address as u64, size as u64),
+        };
+
+        // unassigned
+        if address == 0 {
+            continue;
+        }
+
+        if io {
+            // I/O ports are bus addresses
+            let address = address as usize;
+            resources.push(Resource::Io {
+                range: address..(address + size as usize),
+            });
+        } else {
+            let address = address.wrapping_add(translation(windows, false, address)) as usize;
+            resources.push(Resource::Mmio {
+                range: address..(address + size as usize),
+            });
+        }
+    }
+
+    resources
+}
+
+fn scan_function(
+    ecam: &Ecam,
+    bdf: Bdf,
+    windows: &[Resource],
+    parent: DeviceId,
+    seen: &mut BusSet,
+    dt: &mut DeviceTree,
+) {
+    let header_type = ecam.read_u8(bdf, 0x0E) & 0x7F;
+    let id = read_id(ecam, bdf, header_type);
 
     info!(
         "Found PCI Device {} [{:04x}:{:04x}] Class {:02x}.{:02x} ProgIf {:02x} Header {:02x}",
-        bdf, vendor_id, device_id, class_code, subclass, prog_if, header_type
+        bdf, id.vendor, id.device, id.class, id.subclass, id.prog_if, header_type
     );
 
-    if header_type == 0x00 {
-        // endpoint device
-        let bars = probe_bars(ecam, bdf);
-        let mut resources = Vec::new();
-
-        for bar in &bars {
-            match bar {
-                BarType::Memory32 { address, size, .. } => {
-                    let address = *address as usize;
-                    let size = *size as usize;
-                    resources.push(Resource::Mmio {
-                        range: address..(address + size),
-                    });
-                }
-                BarType::Memory64 { address, size, .. } => {
-                    let address = *address as usize;
-                    let size = *size as usize;
-                    resources.push(Resource::Mmio {
-                        range: address..(address + size),
-                    });
-                }
-                _ => {}
-            }
-        }
+    let mut compat = vec![
+        format!("pci{:04x},{:04x}", id.vendor, id.device),
+        format!("pci-class-{:02x}{:02x}", id.class, id.subclass),
+    ];
 
-        let compat = vec![
-            format!("pci{:04x},{:04x}", vendor_id, device_id),
-            format!("pci-class-{:02x}{:02x}", class_code, subclass),
-            String::from("pci-device"),
-        ];
+    let node = if header_type == 0x00 {
+        // endpoint device
+        compat.push(String::from("pci-device"));
+        let resources = bar_resources(ecam, bdf, 6, windows);
 
         dt.add_device(
-            None,
-            DeviceClass::Other,
+            Some(parent),
+            DeviceClass::PciEndpoint { bdf, id },
             compat,
             resources,
             DeviceInitPriority::Regular,
-        );
+        )
     } else if header_type == 0x01 {
         // PCI-to-PCI bridge
         let secondary_bus = ecam.read_u8(bdf, 0x19);
         let subordinate_bus = ecam.read_u8(bdf, 0x1A);
 
+        let kind = match find_capability(ecam, bdf, StandardCapabilityId::PciExpress)
+            .map(|offset| PcieCap::read(ecam, bdf, offset).device_type)
+        {
+            Some(PORT_ROOT) => PciBridgeKind::RootPort,
+            Some(PORT_UPSTREAM) => PciBridgeKind::SwitchUpstream,
+            Some(PORT_DOWNSTREAM) => PciBridgeKind::SwitchDownstream,
+            _ => PciBridgeKind::PciBridge,
+        };
+
         debug!(
-            "PCI: Bridge {} (Sec: {}, Sub: {})",
-            bdf, secondary_bus, subordinate_bus
+            "PCI: {:?} {} (Sec: {}, Sub: {})",
+            kind, bdf, secondary_bus, subordinate_bus
+        );
+
+        let mut resources = bar_resources(ecam, bdf, 2, windows);
+        resources.extend(bridge_windows(ecam, bdf).into_iter().map(|(space, range)| {
+            Resource::Window {
+                space,
+                translation: translation(windows, space == AddressSpace::Io, range.start),
+                range,
+            }
+        }));
+        resources.push(Resource::Window {
+            space: AddressSpace::BusNumber,
+            range: secondary_bus as u64..subordinate_bus as u64 + 1,
+            translation: 0,
+        });
+
+        compat.push(String::from("pci-bridge"));
+        let node = dt.add_device(
+            Some(parent),
+            DeviceClass::PciBridge {
+                bdf,
+                id,
+                kind,
+                secondary_bus,
+                subordinate_bus,
+            },
+            compat,
+            resources,
+            DeviceInitPriority::Regular,
         );
 
         if secondary_bus > bdf.bus && secondary_bus <= subordinate_bus {
-            scan_bus(ecam, secondary_bus, dt);
+            seen.insert(secondary_bus..=subordinate_bus);
+            UPSTREAM.write().insert((bdf.segment, secondary_bus), bdf);
+            scan_bus(ecam, secondary_bus, windows, node, seen, dt);
         }
+
+        node
+    } else {
+        debug!("PCI: {}: skipping header type {:#x}", bdf, header_type);
+        return;
+    };
+
+    if let Some(node) = dt.get_mut(node) {
+        // ACPI `_ADR` encoding
+        node.bus_address = Some(((bdf.device as u64) << 16) | bdf.function as u64);
     }
 }
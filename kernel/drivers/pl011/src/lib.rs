@@ -0,0 +1,267 @@
+//! interrupt driven PL011 UART, exposed as a TTY.
+//!
+//! the line settings programmed by firmware (or by the early console) are kept as they are. the
+//! kernel log keeps writing through the polled early console, so it still works from panics and
+//! with interrupts masked.
+
+#![no_std]
+
+extern crate alloc;
+
+pub mod ring;
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use alloc::{
+    boxed::Box,
+    collections::btree_map::BTreeMap,
+    format,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use arm_pl011_uart::{FifoLevel, Interrupts, PL011Registers, Uart, UniqueMmioPointer};
+use klib::{
+    guard::InterruptGuard,
+    hardware::{
+        device::{Device, DeviceNode},
+        driver::{DriverDescriptor, DriverError},
+        mmio::map_mmio,
+        resource::{Irq, Resource},
+    },
+    interrupt::{InterruptError, route_spi, singleton::get_interrupt_controller},
+    sync::{RwLock, UnfairSpinlock},
+    tty::{self, Tty, TtyDriver},
+};
+use ring::Ring;
+
+pub static PL011_DRIVER: DriverDescriptor = DriverDescriptor {
+    name: "pl011",
+    compatible: &["arm,pl011"],
+    probe,
+};
+
+const TX_RING_SIZE: usize = 4096;
+const RX_RING_SIZE: usize = 256;
+
+/// the register block, per the PL011 TRM
+const MMIO_SIZE: usize = 0x1000;
+
+const RX_INTERRUPTS: Interrupts = Interrupts::RXI.union(Interrupts::RTI);
+const ERROR_INTERRUPTS: Interrupts = Interrupts::OEI
+    .union(Interrupts::BEI)
+    .union(Interrupts::PEI)
+    .union(Interrupts::FEI);
+
+static PORTS: RwLock<BTreeMap<u32, Arc<Pl011>>> = RwLock::new(BTreeMap::new());
+static NEXT_INDEX: AtomicUsize = AtomicUsize::new(0);
+
+struct State {
+    uart: Uart<'static>,
+    tx: Ring<TX_RING_SIZE>,
+    rx: Ring<RX_RING_SIZE>,
+    masks: Interrupts,
+}
+
+// SAFETY: the MMIO pointer is only touched under the lock
+unsafe impl Send for State {}
+
+pub struct Pl011 {
+    state: UnfairSpinlock<State>,
+    tty: Arc<Tty>,
+    irq: u32,
+}
+
+impl State {
+    /// move bytes from the TX ring into the hardware FIFO, masking TXI once the ring is empty
+    fn fill_fifo(&mut self) {
+        while !self.uart.is_tx_fifo_full()
+            && let Some(byte) = self.tx.pop()
+        {
+            self.uart.write_word(byte);
+        }
+
+        let masks = if self.tx.is_empty() {
+            self.masks.difference(Interrupts::TXI)
+        } else {
+            self.masks.union(Interrupts::TXI)
+        };
+
+        if masks != self.masks {
+            self.masks = masks;
+            self.uart.set_interrupt_masks(masks);
+        }
+    }
+
+    /// empty the hardware FIFO into the RX ring. bytes with line errors or that don't fit are
+    /// dropped.
+    fn drain_fifo(&mut self) {
+        loop {
+            match self.uart.read_word() {
+                Ok(Some(byte)) => _ = self.rx.push(byte),
+                Ok(None) => break,
+                Err(_) => {}
+            }
+        }
+    }
+}
+
+impl Pl011 {
+    fn on_interrupt(&self) {
+        let mut received = Vec::new();
+        let tx_drained;
+
+        {
+            let _irq = InterruptGuard::new();
+            let mut state = self.state.lock();
+
+            let status = state.uart.masked_interrupt_status();
+
+            if status.intersects(RX_INTERRUPTS.union(ERROR_INTERRUPTS)) {
+                state.drain_fifo();
+            }
+
+            let tx_before = state.tx.len();
+            if status.contains(Interrupts::TXI) {
+                state.fill_fifo();
+            }
+            tx_drained = state.tx.len() < tx_before;
+
+            state.uart.clear_interrupts(status);
+
+            received.reserve(state.rx.len());
+            while let Some(byte) = state.rx.pop() {
+                received.push(byte);
+            }
+        }
+
+        if !received.is_empty() {
+            self.tty.receive(&received);
+        }
+
+        if tx_drained {
+            self.tty.write_space();
+        }
+    }
+
+    /// mask everything and drop queued output
+    fn quiesce(&self) {
+        let _irq = InterruptGuard::new();
+        let mut state = self.state.lock();
+
+        state.masks = Interrupts::empty();
+        state.uart.set_interrupt_masks(Interrupts::empty());
+        state.uart.clear_interrupts(Interrupts::all());
+        state.tx = Ring::new();
+    }
+}
+
+impl TtyDriver for Pl011 {
+    fn write(&self, bytes: &[u8]) -> usize {
+        let _irq = InterruptGuard::new();
+        let mut state = self.state.lock();
+
+        let n = state.tx.push_slice(bytes);
+        state.fill_fifo();
+        n
+    }
+}
+
+fn dispatch(irq: u32) -> Result<(), InterruptError> {
+    let port = PORTS
+        .read()
+        .get(&irq)
+        .cloned()
+        .ok_or(InterruptError::HandlerNotFound)?;
+
+    port.on_interrupt();
+    Ok(())
+}
+
+fn route_interrupt(irq: Irq, port: Arc<Pl011>) -> Result<(), InterruptError> {
+    {
+        let _irq = InterruptGuard::new();
+        PORTS.write().insert(irq.gsiv, port);
+    }
+
+    route_spi(irq.gsiv, irq.trigger, dispatch)
+}
+
+struct Pl011Device(Arc<Pl011>);
+
+impl Device for Pl011Device {
+    fn shutdown(&self) {
+        _ = get_interrupt_controller().disable_interrupt(self.0.irq);
+        self.0.quiesce();
+    }
+}
+
+fn probe(node: &DeviceNode) -> Result<Box<dyn Device>, DriverError> {
+    use log::*;
+
+    let base = node
+        .resources
+        .iter()
+        .find_map(|r| match r {
+            Resource::Mmio { range } => Some(range.start),
+            _ => None,
+        })
+        .ok_or(DriverError::MissingResources)?;
+
+    // polling works without it, but the point of this driver is the interrupts
+    let irq = node
+        .resources
+        .iter()
+        .find_map(|r| match r {
+            Resource::Irq(irq) => Some(*irq),
+            _ => None,
+        })
+        .ok_or(DriverError::MissingResources)?;
+
+    let regs = map_mmio(&(base..base + MMIO_SIZE)).ok_or(DriverError::Io)?;
+    // SAFETY: mapped above and owned by this driver from here on. the early console still writes
+    // the data register through its own mapping, which the UART tolerates.
+    let regs = unsafe { UniqueMmioPointer::new(regs.cast::<PL011Registers>()) };
+    let mut uart = Uart::new(regs);
+
+    if !uart.read_identification().is_valid() {
+        return Err(DriverError::Incompatible);
+    }
+
+    uart.set_interrupt_masks(Interrupts::empty());
+    uart.clear_interrupts(Interrupts::all());
+    uart.set_interrupt_fifo_levels(FifoLevel::Bytes16, FifoLevel::Bytes8);
+
+    let masks = RX_INTERRUPTS.union(ERROR_INTERRUPTS);
+    let name = format!("ttyS{}", NEXT_INDEX.fetch_add(1, Ordering::Relaxed));
+
+    let port = Arc::new_cyclic(|weak: &Weak<Pl011>| Pl011 {
+        state: UnfairSpinlock::new(State {
+            uart,
+            tx: Ring::new(),
+            rx: Ring::new(),
+            masks,
+        }),
+        tty: Arc::new(Tty::new(name, weak.clone())),
+        irq: irq.gsiv,
+    });
+
+    route_interrupt(irq, port.clone()).map_err(|e| {
+        error!("pl011: unable to route IRQ {}: {e:?}", irq.gsiv);
+        DriverError::Io
+    })?;
+
+    {
+        let _irq = InterruptGuard::new();
+        port.state.lock().uart.set_interrupt_masks(masks);
+    }
+
+    info!(
+        "pl011: {} at {:#x}, IRQ {}",
+        port.tty.name(),
+        base,
+        irq.gsiv
+    );
+    tty::register_tty(port.tty.clone());
+
+    Ok(Box::new(Pl011Device(port)))
+}
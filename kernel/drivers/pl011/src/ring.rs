@@ -0,0 +1,66 @@
+/// fixed size byte FIFO
+pub struct Ring<const N: usize> {
+    buf: [u8; N],
+    head: usize,
+    len: usize,
+}
+
+impl<const N: usize> Ring<N> {
+    pub const fn new() -> Self {
+        Self {
+            buf: [0; N],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn free(&self) -> usize {
+        N - self.len
+    }
+
+    /// append as much of `bytes` as fits. returns how many were taken.
+    pub fn push_slice(&mut self, bytes: &[u8]) -> usize {
+        let n = bytes.len().min(self.free());
+
+        for &byte in &bytes[..n] {
+            self.buf[(self.head + self.len) % N] = byte;
+            self.len += 1;
+        }
+
+        n
+    }
+
+    pub fn push(&mut self, byte: u8) -> bool {
+        self.push_slice(&[byte]) == 1
+    }
+
+    pub fn pop(&mut self) -> Option<u8> {
+        if self.len == 0 {
+            return None;
+        }
+
+        let byte = self.buf[self.head];
+        self.head = (self.head + 1) % N;
+        self.len -= 1;
+        Some(byte)
+    }
+
+    /// the next byte `pop` would return
+    pub fn peek(&self) -> Option<u8> {
+        (self.len > 0).then(|| self.buf[self.head])
+    }
+}
+
+impl<const N: usize> Default for Ring<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,136 @@
+//! PL031 real time clock. read once at probe to seed the kernel's wall clock.
+//!
+//! the RTC counts whole seconds since the UNIX epoch. the alarm interrupt isn't used.
+
+#![no_std]
+
+extern crate alloc;
+
+use core::{
+    ptr::{NonNull, read_volatile, write_volatile},
+    time::Duration,
+};
+
+use alloc::boxed::Box;
+use klib::{
+    hardware::{
+        device::{Device, DeviceNode},
+        driver::{DriverDescriptor, DriverError},
+        mmio::map_mmio,
+        resource::Resource,
+    },
+    time::{self, DateTime},
+};
+
+pub static PL031_DRIVER: DriverDescriptor = DriverDescriptor {
+    name: "pl031",
+    compatible: &["arm,pl031"],
+    probe,
+};
+
+/// the register block, per the PL031 TRM
+const MMIO_SIZE: usize = 0x1000;
+
+/// data register, the current count
+const RTCDR: usize = 0x000;
+/// control register
+const RTCCR: usize = 0x00C;
+/// interrupt mask set/clear
+const RTCIMSC: usize = 0x010;
+/// interrupt clear
+const RTCICR: usize = 0x01C;
+/// first of the peripheral and PrimeCell ID registers
+const RTCPERIPHID0: usize = 0xFE0;
+
+/// RTCCR: the counter is running
+const RTCCR_START: u32 = 1 << 0;
+
+/// part number 0x031, designer ARM
+const PERIPH_ID: [u8; 3] = [0x31, 0x10, 0x04];
+const PRIMECELL_ID: [u8; 4] = [0x0D, 0xF0, 0x05, 0xB1];
+
+struct Pl031 {
+    regs: NonNull<u8>,
+}
+
+// SAFETY: the registers are only touched through volatile accesses and the device has no state
+// that needs locking
+unsafe impl Send for Pl031 {}
+unsafe impl Sync for Pl031 {}
+
+impl Pl031 {
+    fn read(&self, offset: usize) -> u32 {
+        unsafe { read_volatile(self.regs.as_ptr().add(offset) as *const u32) }
+    }
+
+    fn write(&self, offset: usize, val: u32) {
+        unsafe { write_volatile(self.regs.as_ptr().add(offset) as *mut u32, val) }
+    }
+
+    /// the low byte of each ID register, in order
+    fn identification(&self) -> [u8; 8] {
+        core::array::from_fn(|i| self.read(RTCPERIPHID0 + i * 4) as u8)
+    }
+
+    fn is_pl031(&self) -> bool {
+        let id = self.identification();
+        // the top nibble of PeriphID2 is the revision
+        id[0] == PERIPH_ID[0]
+            && id[1] == PERIPH_ID[1]
+            && id[2] & 0x0F == PERIPH_ID[2]
+            && id[4..] == PRIMECELL_ID
+    }
+
+    /// seconds since the UNIX epoch
+    fn now(&self) -> u32 {
+        self.read(RTCDR)
+    }
+}
+
+impl Device for Pl031 {
+    fn shutdown(&self) {
+        // the counter keeps running, only the alarm is ours to quiet
+        self.write(RTCIMSC, 0);
+        self.write(RTCICR, 1);
+    }
+}
+
+fn probe(node: &DeviceNode) -> Result<Box<dyn Device>, DriverError> {
+    use log::*;
+
+    let base = node
+        .resources
+        .iter()
+        .find_map(|r| match r {
+            Resource::Mmio { range } => Some(range.start),
+            _ => None,
+        })
+        .ok_or(DriverError::MissingResources)?;
+
+    let regs = map_mmio(&(base..base + MMIO_SIZE)).ok_or(DriverError::Io)?;
+    let rtc = Pl031 { regs };
+
+    if !rtc.is_pl031() {
+        return Err(DriverError::Incompatible);
+    }
+
+    rtc.write(RTCIMSC, 0);
+    rtc.write(RTCICR, 1);
+
+    if rtc.read(RTCCR) & RTCCR_START == 0 {
+        // a stopped RTC reads as zero, which is still better than nothing
+        warn!("pl031: counter was stopped, starting it");
+        rtc.write(RTCCR, RTCCR_START);
+    }
+
+    let now = rtc.now();
+    time::set_realtime(Duration::from_secs(now as u64));
+
+    info!(
+        "pl031: at {:#x}, wall clock set to {}",
+        base,
+        DateTime::from_unix(now as u64)
+    );
+
+    Ok(Box::new(rtc))
+}
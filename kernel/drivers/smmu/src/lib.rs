@@ -0,0 +1,612 @@
+//! ARM SMMUv3, giving the devices that are attached to it their own stage 1 page tables.
+//!
+//! only what the IOMMU layer needs: a stream table, one context descriptor per attached stream,
+//! and commands whose completion is polled. streams nobody attaches bypass translation, the same
+//! as before the SMMU was enabled.
+
+#![no_std]
+
+extern crate alloc;
+
+mod queue;
+
+use core::{
+    hint::spin_loop,
+    ptr::{NonNull, read_volatile, write_volatile},
+    time::Duration,
+};
+
+use aarch64_cpu::{
+    asm::barrier::{self, dmb, dsb},
+    registers::{MAIR_EL1, Readable},
+};
+use alloc::{boxed::Box, collections::btree_map::BTreeMap, vec::Vec};
+use klib::{
+    hardware::{
+        device::{Device, DeviceNode},
+        dma::DmaBuffer,
+        driver::{DriverDescriptor, DriverError},
+        iommu::{self, Iommu, IommuError},
+        mmio::map_mmio,
+        resource::{Irq, Resource},
+    },
+    interrupt::{InterruptError, route_spi, singleton::get_interrupt_controller},
+    sync::{FairSpinlock, RwLock},
+    time,
+    vm::PAGE_SIZE,
+};
+use queue::{Command, EVENTQ_OVERFLOW, Queue};
+
+pub static SMMU_V3_DRIVER: DriverDescriptor = DriverDescriptor {
+    name: "smmu-v3",
+    compatible: &["arm,smmu-v3"],
+    probe,
+};
+
+/// both 64KiB register pages
+const MMIO_SIZE: usize = 0x2_0000;
+
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+// registers, page 0
+const IDR0: usize = 0x00;
+const IDR1: usize = 0x04;
+const IDR5: usize = 0x14;
+const CR0: usize = 0x20;
+const CR0ACK: usize = 0x24;
+const CR1: usize = 0x28;
+const CR2: usize = 0x2C;
+const IRQ_CTRL: usize = 0x50;
+const IRQ_CTRLACK: usize = 0x54;
+const GERROR: usize = 0x60;
+const GERRORN: usize = 0x64;
+const STRTAB_BASE: usize = 0x80;
+const STRTAB_BASE_CFG: usize = 0x88;
+const CMDQ_BASE: usize = 0x90;
+const CMDQ_PROD: usize = 0x98;
+const CMDQ_CONS: usize = 0x9C;
+const EVENTQ_BASE: usize = 0xA0;
+// page 1
+const EVENTQ_PROD: usize = 0x1_00A8;
+const EVENTQ_CONS: usize = 0x1_00AC;
+
+const IDR0_S1P: u32 = 1 << 1;
+/// AArch64 translation tables
+const IDR0_TTF_AA64: u32 = 1 << 3;
+/// table walks and queue accesses snoop the caches
+const IDR0_COHACC: u32 = 1 << 4;
+const IDR0_ASID16: u32 = 1 << 12;
+/// two level stream tables
+const IDR0_ST_LEVEL_2: u32 = 0b01 << 27;
+const IDR0_ST_LEVEL_MASK: u32 = 0b11 << 27;
+
+const IDR1_SIDSIZE_MASK: u32 = 0x3F;
+const IDR1_EVENTQS_SHIFT: u32 = 16;
+const IDR1_CMDQS_SHIFT: u32 = 21;
+const IDR1_QS_MASK: u32 = 0x1F;
+/// firmware fixed the queue or table bases, nothing this driver can work with
+const IDR1_PRESET: u32 = 0b11 << 29;
+
+const IDR5_OAS_MASK: u32 = 0b111;
+const IDR5_GRAN16K: u32 = 1 << 5;
+
+const CR0_SMMUEN: u32 = 1 << 0;
+const CR0_EVENTQEN: u32 = 1 << 2;
+const CR0_CMDQEN: u32 = 1 << 3;
+
+/// queues and tables are inner and outer write back, inner shareable
+const CR1_CACHEABLE: u32 = 0b01 | 0b01 << 2 | 0b11 << 4 | 0b01 << 6 | 0b01 << 8 | 0b11 << 10;
+/// stream IDs beyond the table are reported
+const CR2_RECINVSID: u32 = 1 << 1;
+
+const IRQ_CTRL_GERROR: u32 = 1 << 0;
+const IRQ_CTRL_EVENTQ: u32 = 1 << 2;
+
+const GERROR_CMDQ_ERR: u32 = 1 << 0;
+
+const CMDQ_CONS_ERR_SHIFT: u32 = 24;
+const CMDQ_CONS_ERR_MASK: u32 = 0x7F;
+
+/// read allocate, for both the stream table and the queues
+const BASE_RA: u64 = 1 << 62;
+const ADDR_MASK_64: u64 = 0x000F_FFFF_FFFF_FFC0;
+
+const STRTAB_FMT_2LEVEL: u32 = 0b01 << 16;
+const STRTAB_SPLIT_SHIFT: u32 = 6;
+
+/// stream IDs per second level table: one page of STEs
+const STES_PER_TABLE_LOG2: u32 = 8;
+const STE_WORDS: usize = 8;
+const CD_WORDS: usize = 8;
+
+const MAX_SID_BITS: u32 = 16;
+const MAX_CMDQ_LOG2: u32 = 8;
+const MAX_EVENTQ_LOG2: u32 = 7;
+
+const STE_VALID: u64 = 1 << 0;
+const STE_CONFIG_BYPASS: u64 = 0b100 << 1;
+const STE_CONFIG_S1: u64 = 0b101 << 1;
+/// write back, inner shareable CD fetches
+const STE_S1_CACHEABLE: u64 = 0b01 << 2 | 0b01 << 4 | 0b11 << 6;
+/// keep the shareability the device asked for
+const STE_SHCFG_INCOMING: u64 = 0b01 << 44;
+/// every access is privileged, which is what the page tables allow
+const STE_PRIVCFG_PRIVILEGED: u64 = 0b11 << 48;
+const STE_INSTCFG_DATA: u64 = 0b10 << 50;
+
+/// level 1 descriptor: the second level table covers 2^(SPAN - 1) streams
+const L1_SPAN: u64 = STES_PER_TABLE_LOG2 as u64 + 1;
+
+/// 48 bit input addresses
+const CD_T0SZ: u64 = 64 - 48;
+const CD_TG0_16K: u64 = 0b10 << 6;
+const CD_TT0_CACHEABLE: u64 = 0b01 << 8 | 0b01 << 10 | 0b11 << 12;
+/// no TTB1 walks
+const CD_EPD1: u64 = 1 << 30;
+const CD_VALID: u64 = 1 << 31;
+const CD_IPS_SHIFT: u64 = 32;
+const CD_AA64: u64 = 1 << 41;
+/// faults are recorded as events
+const CD_R: u64 = 1 << 45;
+/// and the transaction is aborted
+const CD_A: u64 = 1 << 46;
+/// ASIDs aren't shared with the CPU
+const CD_ASET: u64 = 1 << 47;
+const CD_ASID_SHIFT: u64 = 48;
+const CD_TTB_MASK: u64 = 0x000F_FFFF_FFFF_FFF0;
+
+/// 48 bit output addresses, the most a 16KiB granule reaches without LPA2
+const MAX_IPS: u32 = 0b101;
+
+static SMMUS: RwLock<BTreeMap<u32, &'static Smmu>> = RwLock::new(BTreeMap::new());
+
+struct Regs(NonNull<u8>);
+
+// SAFETY: every access is a single volatile read or write
+unsafe impl Send for Regs {}
+unsafe impl Sync for Regs {}
+
+impl Regs {
+    fn read32(&self, offset: usize) -> u32 {
+        unsafe { read_volatile(self.0.as_ptr().add(offset) as *const u32) }
+    }
+
+    fn write32(&self, offset: usize, val: u32) {
+        unsafe { write_volatile(self.0.as_ptr().add(offset) as *mut u32, val) }
+    }
+
+    fn write64(&self, offset: usize, val: u64) {
+        unsafe { write_volatile(self.0.as_ptr().add(offset) as *mut u64, val) }
+    }
+}
+
+fn spin_until(mut done: impl FnMut() -> bool) -> iommu::Result<()> {
+    let start = time::monotonic();
+    while !done() {
+        if time::monotonic() - start > TIMEOUT {
+            return Err(IommuError::Timeout);
+        }
+        spin_loop();
+    }
+    Ok(())
+}
+
+/// write a 64 byte descriptor so the SMMU never sees it half done: word 0 holds the valid bit,
+/// so it goes last
+fn write_descriptor(entry: *mut u64, words: &[u64]) {
+    unsafe {
+        for (i, word) in words.iter().enumerate().skip(1) {
+            write_volatile(entry.add(i), *word);
+        }
+        dsb(barrier::ISHST);
+        write_volatile(entry, words[0]);
+    }
+    dsb(barrier::ISHST);
+}
+
+const fn bypass_ste() -> [u64; STE_WORDS] {
+    let mut ste = [0; STE_WORDS];
+    ste[0] = STE_VALID | STE_CONFIG_BYPASS;
+    ste[1] = STE_SHCFG_INCOMING;
+    ste
+}
+
+struct Streams {
+    /// level 1 descriptors, one per table. `None` if the table is linear.
+    l1: Option<DmaBuffer>,
+    /// pages of STEs
+    tables: Vec<DmaBuffer>,
+    /// context descriptors of attached streams
+    cds: BTreeMap<u32, DmaBuffer>,
+}
+
+impl Streams {
+    /// a table covering 2^`sid_bits` streams, all bypassing
+    fn new(sid_bits: u32) -> Option<Self> {
+        let count = 1usize << sid_bits.saturating_sub(STES_PER_TABLE_LOG2);
+
+        let tables = (0..count)
+            .map(|_| {
+                let table = DmaBuffer::new(PAGE_SIZE)?;
+                let stes = table.as_ptr() as *mut u64;
+                // nothing reads the table yet, so no ordering needed within it
+                for sid in 0..1 << STES_PER_TABLE_LOG2 {
+                    for (i, word) in bypass_ste().into_iter().enumerate() {
+                        unsafe { write_volatile(stes.add(sid * STE_WORDS + i), word) };
+                    }
+                }
+                Some(table)
+            })
+            .collect::<Option<Vec<_>>>()?;
+
+        let l1 = if sid_bits > STES_PER_TABLE_LOG2 {
+            let l1 = DmaBuffer::new(count * 8)?;
+            let descriptors = l1.as_ptr() as *mut u64;
+            for (i, table) in tables.iter().enumerate() {
+                let descriptor = L1_SPAN | (table.phys_addr() & ADDR_MASK_64);
+                unsafe { write_volatile(descriptors.add(i), descriptor) };
+            }
+            dsb(barrier::ISHST);
+            Some(l1)
+        } else {
+            None
+        };
+
+        Some(Self {
+            l1,
+            tables,
+            cds: BTreeMap::new(),
+        })
+    }
+
+    fn ste(&self, stream: u32) -> *mut u64 {
+        let table = &self.tables[(stream >> STES_PER_TABLE_LOG2) as usize];
+        let index = (stream & ((1 << STES_PER_TABLE_LOG2) - 1)) as usize;
+        unsafe { (table.as_ptr() as *mut u64).add(index * STE_WORDS) }
+    }
+
+    /// the `STRTAB_BASE` and `STRTAB_BASE_CFG` register values
+    fn base(&self, sid_bits: u32) -> (u64, u32) {
+        match &self.l1 {
+            Some(l1) => (
+                BASE_RA | (l1.phys_addr() & ADDR_MASK_64),
+                STRTAB_FMT_2LEVEL | STES_PER_TABLE_LOG2 << STRTAB_SPLIT_SHIFT | sid_bits,
+            ),
+            None => (
+                BASE_RA | (self.tables[0].phys_addr() & ADDR_MASK_64),
+                sid_bits,
+            ),
+        }
+    }
+}
+
+struct Asids {
+    next: u32,
+    freed: Vec<u16>,
+    limit: u32,
+}
+
+struct Smmu {
+    regs: Regs,
+    base: usize,
+    sid_bits: u32,
+    /// output address size, in `IPS` encoding
+    ips: u32,
+    cmdq: FairSpinlock<Queue>,
+    eventq: FairSpinlock<Queue>,
+    streams: FairSpinlock<Streams>,
+    asids: FairSpinlock<Asids>,
+}
+
+impl Smmu {
+    fn write_cr0(&self, val: u32) -> iommu::Result<()> {
+        self.regs.write32(CR0, val);
+        spin_until(|| self.regs.read32(CR0ACK) == val)
+    }
+
+    /// queue `cmds` and a sync, and wait for the sync to complete
+    fn submit(&self, cmds: &[Command]) -> iommu::Result<()> {
+        use log::*;
+
+        let mut cmdq = self.cmdq.lock();
+
+        // every submission waits for the queue to drain, so it starts out empty
+        for &cmd in cmds.iter().chain(&[Command::sync()]) {
+            cmdq.push(cmd);
+        }
+        dsb(barrier::ISHST);
+        self.regs.write32(CMDQ_PROD, cmdq.index());
+
+        spin_until(|| {
+            let cons = self.regs.read32(CMDQ_CONS);
+            let errors = self.regs.read32(GERROR) ^ self.regs.read32(GERRORN);
+
+            if errors & GERROR_CMDQ_ERR != 0 {
+                // the queue stops at the bad command. skip it and let the rest run.
+                error!(
+                    "smmu: {:#x}: command refused, reason {:#x}",
+                    self.base,
+                    (cons >> CMDQ_CONS_ERR_SHIFT) & CMDQ_CONS_ERR_MASK
+                );
+                cmdq.replace(cons, Command::sync());
+                dsb(barrier::ISHST);
+                self.regs
+                    .write32(GERRORN, self.regs.read32(GERRORN) ^ GERROR_CMDQ_ERR);
+                return false;
+            }
+
+            cmdq.reached(cons, cmdq.index())
+        })
+        .inspect_err(|_| error!("smmu: {:#x}: command queue stuck", self.base))
+    }
+
+    /// log what the devices did wrong
+    fn drain_events(&self) {
+        use log::*;
+
+        let mut eventq = self.eventq.lock();
+        let prod = self.regs.read32(EVENTQ_PROD);
+        // the entries are only read after the producer index that covers them
+        dmb(barrier::SY);
+
+        if (prod ^ self.regs.read32(EVENTQ_CONS)) & EVENTQ_OVERFLOW != 0 {
+            warn!("smmu: {:#x}: event queue overflowed", self.base);
+        }
+
+        while let Some(event) = eventq.pop(prod) {
+            warn!(
+                "smmu: {:#x}: {} from stream {:#x} at {:#x}",
+                self.base,
+                event.name(),
+                event.stream(),
+                event.address()
+            );
+        }
+
+        self.regs
+            .write32(EVENTQ_CONS, eventq.index() | (prod & EVENTQ_OVERFLOW));
+    }
+
+    fn on_interrupt(&self) {
+        use log::*;
+
+        self.drain_events();
+
+        // command errors are the submitter's
+        let gerrorn = self.regs.read32(GERRORN);
+        let errors = (self.regs.read32(GERROR) ^ gerrorn) & !GERROR_CMDQ_ERR;
+        if errors != 0 {
+            error!("smmu: {:#x}: global errors {:#x}", self.base, errors);
+            self.regs.write32(GERRORN, gerrorn ^ errors);
+        }
+    }
+
+    fn init(&self) -> iommu::Result<()> {
+        self.write_cr0(0)?;
+
+        let (strtab_base, strtab_cfg) = self.streams.lock().base(self.sid_bits);
+        self.regs.write64(STRTAB_BASE, strtab_base);
+        self.regs.write32(STRTAB_BASE_CFG, strtab_cfg);
+
+        self.cmdq
+            .lock()
+            .install(&self.regs, CMDQ_BASE, CMDQ_PROD, CMDQ_CONS);
+        self.eventq
+            .lock()
+            .install(&self.regs, EVENTQ_BASE, EVENTQ_PROD, EVENTQ_CONS);
+
+        self.regs.write32(CR1, CR1_CACHEABLE);
+        self.regs.write32(CR2, CR2_RECINVSID);
+
+        self.write_cr0(CR0_CMDQEN)?;
+        // whatever firmware left cached is stale
+        self.submit(&[Command::cfgi_all(), Command::tlbi_all()])?;
+        self.write_cr0(CR0_CMDQEN | CR0_EVENTQEN)?;
+
+        self.regs
+            .write32(IRQ_CTRL, IRQ_CTRL_GERROR | IRQ_CTRL_EVENTQ);
+        spin_until(|| self.regs.read32(IRQ_CTRLACK) == IRQ_CTRL_GERROR | IRQ_CTRL_EVENTQ)?;
+
+        self.write_cr0(CR0_CMDQEN | CR0_EVENTQEN | CR0_SMMUEN)
+    }
+}
+
+impl Iommu for Smmu {
+    fn alloc_asid(&self) -> iommu::Result<u16> {
+        let mut asids = self.asids.lock();
+        if let Some(asid) = asids.freed.pop() {
+            return Ok(asid);
+        }
+
+        if asids.next >= asids.limit {
+            return Err(IommuError::OutOfResources);
+        }
+        asids.next += 1;
+        Ok((asids.next - 1) as u16)
+    }
+
+    fn free_asid(&self, asid: u16) {
+        self.asids.lock().freed.push(asid);
+    }
+
+    fn attach(&self, stream: u32, asid: u16, root: u64) -> iommu::Result<()> {
+        if stream >= 1 << self.sid_bits {
+            return Err(IommuError::InvalidStream);
+        }
+
+        let cd = DmaBuffer::new(CD_WORDS * 8).ok_or(IommuError::OutOfResources)?;
+        let mut words = [0; CD_WORDS];
+        words[0] = CD_T0SZ
+            | CD_TG0_16K
+            | CD_TT0_CACHEABLE
+            | CD_EPD1
+            | CD_VALID
+            | (self.ips as u64) << CD_IPS_SHIFT
+            | CD_AA64
+            | CD_R
+            | CD_A
+            | CD_ASET
+            | (asid as u64) << CD_ASID_SHIFT;
+        words[1] = root & CD_TTB_MASK;
+        // the same attribute indices as the CPU's tables
+        words[3] = MAIR_EL1.get();
+        write_descriptor(cd.as_ptr() as *mut u64, &words);
+
+        let mut ste = [0; STE_WORDS];
+        ste[0] = STE_VALID | STE_CONFIG_S1 | (cd.phys_addr() & ADDR_MASK_64);
+        ste[1] = STE_S1_CACHEABLE | STE_PRIVCFG_PRIVILEGED | STE_INSTCFG_DATA;
+
+        let mut streams = self.streams.lock();
+        write_descriptor(streams.ste(stream), &ste);
+        // a previous CD of the stream is only freed once the SMMU has let go of it
+        let result = self.submit(&[Command::cfgi_ste(stream), Command::cfgi_cd_all(stream)]);
+        streams.cds.insert(stream, cd);
+
+        result
+    }
+
+    fn detach(&self, stream: u32) {
+        if stream >= 1 << self.sid_bits {
+            return;
+        }
+
+        let mut streams = self.streams.lock();
+        write_descriptor(streams.ste(stream), &bypass_ste());
+        _ = self.submit(&[Command::cfgi_ste(stream), Command::cfgi_cd_all(stream)]);
+        streams.cds.remove(&stream);
+    }
+
+    fn invalidate(&self, asid: u16) {
+        _ = self.submit(&[Command::tlbi_asid(asid)]);
+    }
+}
+
+fn dispatch(irq: u32) -> Result<(), InterruptError> {
+    let smmu = SMMUS
+        .read()
+        .get(&irq)
+        .copied()
+        .ok_or(InterruptError::HandlerNotFound)?;
+
+    smmu.on_interrupt();
+    Ok(())
+}
+
+fn route_interrupt(irq: Irq, smmu: &'static Smmu) -> Result<(), InterruptError> {
+    SMMUS.write().insert(irq.gsiv, smmu);
+
+    route_spi(irq.gsiv, irq.trigger, dispatch)
+}
+
+struct SmmuDevice {
+    smmu: &'static Smmu,
+    irqs: Vec<u32>,
+}
+
+impl Device for SmmuDevice {
+    fn shutdown(&self) {
+        for irq in &self.irqs {
+            _ = get_interrupt_controller().disable_interrupt(*irq);
+        }
+        // every stream goes back to bypassing
+        _ = self.smmu.write_cr0(0);
+    }
+}
+
+fn probe(node: &DeviceNode) -> Result<Box<dyn Device>, DriverError> {
+    use log::*;
+
+    let base = node
+        .resources
+        .iter()
+        .find_map(|r| match r {
+            Resource::Mmio { range } => Some(range.start),
+            _ => None,
+        })
+        .ok_or(DriverError::MissingResources)?;
+
+    let regs = Regs(map_mmio(&(base..base + MMIO_SIZE)).ok_or(DriverError::Io)?);
+
+    let idr0 = regs.read32(IDR0);
+    let idr1 = regs.read32(IDR1);
+    let idr5 = regs.read32(IDR5);
+
+    let required = IDR0_S1P | IDR0_TTF_AA64 | IDR0_COHACC;
+    if idr0 & required != required || idr5 & IDR5_GRAN16K == 0 || idr1 & IDR1_PRESET != 0 {
+        warn!(
+            "smmu: {:#x}: unsupported (IDR0 {:#x}, IDR1 {:#x}, IDR5 {:#x})",
+            base, idr0, idr1, idr5
+        );
+        return Err(DriverError::Incompatible);
+    }
+
+    let mut sid_bits = (idr1 & IDR1_SIDSIZE_MASK).min(MAX_SID_BITS);
+    if sid_bits > STES_PER_TABLE_LOG2 && idr0 & IDR0_ST_LEVEL_MASK != IDR0_ST_LEVEL_2 {
+        // a linear table must be aligned to its size, which only a page is guaranteed to be
+        warn!(
+            "smmu: {:#x}: no two level stream tables, only the first {} streams translate",
+            base,
+            1 << STES_PER_TABLE_LOG2
+        );
+        sid_bits = STES_PER_TABLE_LOG2;
+    }
+
+    let cmdq_log2 = ((idr1 >> IDR1_CMDQS_SHIFT) & IDR1_QS_MASK).min(MAX_CMDQ_LOG2);
+    let eventq_log2 = ((idr1 >> IDR1_EVENTQS_SHIFT) & IDR1_QS_MASK).min(MAX_EVENTQ_LOG2);
+
+    let streams = Streams::new(sid_bits).ok_or(DriverError::Io)?;
+    let cmdq = Queue::new(cmdq_log2, 2).ok_or(DriverError::Io)?;
+    let eventq = Queue::new(eventq_log2, 4).ok_or(DriverError::Io)?;
+
+    let smmu: &'static Smmu = Box::leak(Box::new(Smmu {
+        regs,
+        base,
+        sid_bits,
+        ips: (idr5 & IDR5_OAS_MASK).min(MAX_IPS),
+        cmdq: FairSpinlock::new(cmdq),
+        eventq: FairSpinlock::new(eventq),
+        streams: FairSpinlock::new(streams),
+        asids: FairSpinlock::new(Asids {
+            // ASID 0 is left alone
+            next: 1,
+            freed: Vec::new(),
+            limit: if idr0 & IDR0_ASID16 != 0 {
+                1 << 16
+            } else {
+                1 << 8
+            },
+        }),
+    }));
+
+    let irqs = node
+        .resources
+        .iter()
+        .filter_map(|r| match r {
+            Resource::Irq(irq) => Some(*irq),
+            _ => None,
+        })
+        .filter(|irq| {
+            route_interrupt(*irq, smmu)
+                .inspect_err(|e| warn!("smmu: unable to route IRQ {}: {e:?}", irq.gsiv))
+                .is_ok()
+        })
+        .map(|irq| irq.gsiv)
+        .collect::<Vec<_>>();
+
+    smmu.init().map_err(|e| {
+        error!("smmu: {:#x}: unable to enable: {:?}", base, e);
+        _ = smmu.write_cr0(0);
+        DriverError::Io
+    })?;
+
+    iommu::register_iommu(base, smmu);
+
+    info!(
+        "smmu: v3 at {:#x}, {} stream ID bits, {} IRQs",
+        base,
+        sid_bits,
+        irqs.len()
+    );
+
+    Ok(Box::new(SmmuDevice { smmu, irqs }))
+}
@@ -0,0 +1,184 @@
+//! the command and event queues. both are rings the two sides chase each other around, with a
+//! wrap bit above the index so a full ring can be told apart from an empty one.
+
+use core::ptr::{read_volatile, write_volatile};
+
+use klib::hardware::dma::DmaBuffer;
+
+use crate::Regs;
+
+// command opcodes
+const CMD_CFGI_STE: u64 = 0x03;
+const CMD_CFGI_ALL: u64 = 0x04;
+const CMD_CFGI_CD_ALL: u64 = 0x06;
+const CMD_TLBI_NH_ASID: u64 = 0x11;
+const CMD_TLBI_NSNH_ALL: u64 = 0x30;
+const CMD_SYNC: u64 = 0x46;
+
+/// `Q_BASE`: read allocate
+const QUEUE_BASE_RA: u64 = 1 << 62;
+const QUEUE_BASE_ADDR_MASK: u64 = 0x000F_FFFF_FFFF_FFE0;
+
+/// `EVENTQ_PROD`: events were lost. acknowledged by copying it into `EVENTQ_CONS`.
+pub const EVENTQ_OVERFLOW: u32 = 1 << 31;
+
+#[derive(Debug, Copy, Clone)]
+pub struct Command([u64; 2]);
+
+impl Command {
+    /// forget the cached STE of `stream`
+    pub const fn cfgi_ste(stream: u32) -> Self {
+        // leaf: only the STE itself changed
+        Self([CMD_CFGI_STE | (stream as u64) << 32, 1])
+    }
+
+    /// forget the cached context descriptors of `stream`
+    pub const fn cfgi_cd_all(stream: u32) -> Self {
+        Self([CMD_CFGI_CD_ALL | (stream as u64) << 32, 0])
+    }
+
+    /// forget every cached STE and context descriptor
+    pub const fn cfgi_all() -> Self {
+        // range 31 covers every stream
+        Self([CMD_CFGI_ALL, 31])
+    }
+
+    pub const fn tlbi_asid(asid: u16) -> Self {
+        Self([CMD_TLBI_NH_ASID | (asid as u64) << 48, 0])
+    }
+
+    /// every non-secure, non-hypervisor translation
+    pub const fn tlbi_all() -> Self {
+        Self([CMD_TLBI_NSNH_ALL, 0])
+    }
+
+    /// completes once every command before it has. signals nothing, the queue is polled.
+    pub const fn sync() -> Self {
+        Self([CMD_SYNC, 0])
+    }
+}
+
+/// an event record
+#[derive(Debug, Copy, Clone)]
+pub struct Event([u64; 4]);
+
+impl Event {
+    pub const fn kind(&self) -> u8 {
+        self.0[0] as u8
+    }
+
+    pub const fn stream(&self) -> u32 {
+        (self.0[0] >> 32) as u32
+    }
+
+    /// the address the device used
+    pub const fn address(&self) -> u64 {
+        self.0[2]
+    }
+
+    pub const fn name(&self) -> &'static str {
+        match self.kind() {
+            0x01 => "unsupported upstream transaction",
+            0x02 => "bad stream ID",
+            0x03 => "STE fetch fault",
+            0x04 => "bad STE",
+            0x07 => "CD fetch fault",
+            0x08 => "bad CD",
+            0x09 => "page table walk fault",
+            0x10 => "translation fault",
+            0x11 => "address size fault",
+            0x12 => "access fault",
+            0x13 => "permission fault",
+            _ => "unknown event",
+        }
+    }
+}
+
+pub struct Queue {
+    buf: DmaBuffer,
+    /// entry size, in 64 bit words
+    words: usize,
+    log2_len: u32,
+    /// ours: the producer index of the command queue, the consumer index of the event queue
+    index: u32,
+}
+
+impl Queue {
+    /// `log2_len` entries of `words` 64 bit words each
+    pub fn new(log2_len: u32, words: usize) -> Option<Self> {
+        Some(Self {
+            buf: DmaBuffer::new((words * 8) << log2_len)?,
+            words,
+            log2_len,
+            index: 0,
+        })
+    }
+
+    /// the `Q_BASE` register value
+    pub fn base(&self) -> u64 {
+        QUEUE_BASE_RA | (self.buf.phys_addr() & QUEUE_BASE_ADDR_MASK) | self.log2_len as u64
+    }
+
+    /// mask of the index and the wrap bit above it
+    const fn mask(&self) -> u32 {
+        (2 << self.log2_len) - 1
+    }
+
+    const fn slot(&self, index: u32) -> usize {
+        (index & ((1 << self.log2_len) - 1)) as usize
+    }
+
+    fn entry(&self, index: u32) -> *mut u64 {
+        unsafe { (self.buf.as_ptr() as *mut u64).add(self.slot(index) * self.words) }
+    }
+
+    pub const fn index(&self) -> u32 {
+        self.index
+    }
+
+    /// `true` once the other side has caught up to `index`
+    pub fn reached(&self, other: u32, index: u32) -> bool {
+        other & self.mask() == index & self.mask()
+    }
+
+    /// write `cmd` at the producer index and move past it. the caller keeps it from overtaking the consumer.
+    pub fn push(&mut self, cmd: Command) {
+        let entry = self.entry(self.index);
+        for (i, word) in cmd.0.into_iter().enumerate() {
+            unsafe { write_volatile(entry.add(i), word) };
+        }
+
+        self.index = (self.index + 1) & self.mask();
+    }
+
+    /// replace the command at `index`, which the SMMU refused
+    pub fn replace(&mut self, index: u32, cmd: Command) {
+        let entry = self.entry(index);
+        for (i, word) in cmd.0.into_iter().enumerate() {
+            unsafe { write_volatile(entry.add(i), word) };
+        }
+    }
+
+    /// the event at the consumer index, if the producer is past it
+    pub fn pop(&mut self, prod: u32) -> Option<Event> {
+        if self.reached(prod, self.index) {
+            return None;
+        }
+
+        let entry = self.entry(self.index);
+        let event = Event(core::array::from_fn(|i| unsafe {
+            read_volatile(entry.add(i))
+        }));
+
+        self.index = (self.index + 1) & self.mask();
+        Some(event)
+    }
+
+    /// point the hardware at the queue, empty
+    pub fn install(&mut self, regs: &Regs, base: usize, prod: usize, cons: usize) {
+        self.index = 0;
+        regs.write64(base, self.base());
+        regs.write32(prod, 0);
+        regs.write32(cons, 0);
+    }
+}
@@ -0,0 +1,452 @@
+use core::{
+    mem::size_of,
+    ptr::{NonNull, read_volatile, write_volatile},
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+use alloc::{boxed::Box, sync::Arc};
+use klib::{
+    allocator_support::KernelAddressTranslator,
+    cache::clean_dcache_range,
+    fbcon,
+    guard::InterruptGuard,
+    hardware::{
+        device::{Device, DeviceNode},
+        dma::DmaBuffer,
+        driver::{DriverDescriptor, DriverError},
+        framebuffer::{Framebuffer, FramebufferInfo, Rect},
+    },
+    pm::page::{kernel_page_allocator, mapper::AddressTranslator},
+    sync::FairSpinlock,
+    time,
+    vm::PAGE_SIZE,
+};
+
+use crate::{
+    VIRTIO_F_VERSION_1,
+    pci::{NO_VECTOR, VirtioPci},
+    queue::{QueueBuffer, Virtqueue},
+};
+
+const CMD_GET_DISPLAY_INFO: u32 = 0x0100;
+const CMD_RESOURCE_CREATE_2D: u32 = 0x0101;
+const CMD_SET_SCANOUT: u32 = 0x0103;
+const CMD_RESOURCE_FLUSH: u32 = 0x0104;
+const CMD_TRANSFER_TO_HOST_2D: u32 = 0x0105;
+const CMD_RESOURCE_ATTACH_BACKING: u32 = 0x0106;
+
+const RESP_OK_NODATA: u32 = 0x1100;
+const RESP_OK_DISPLAY_INFO: u32 = 0x1101;
+
+/// bytes B, G, R, X in memory, which is `0x00RRGGBB` as a little endian u32
+const FORMAT_B8G8R8X8_UNORM: u32 = 2;
+
+const MAX_SCANOUTS: usize = 16;
+const CONTROL_QUEUE: u16 = 0;
+const QUEUE_SIZE: u16 = 16;
+
+const RESOURCE_ID: u32 = 1;
+
+/// used when the device doesn't report an enabled scanout
+const DEFAULT_WIDTH: u32 = 1024;
+const DEFAULT_HEIGHT: u32 = 768;
+
+/// where the device writes responses in the command buffer
+const RESPONSE_OFFSET: usize = 2048;
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(1);
+
+pub static VIRTIO_GPU_DRIVER: DriverDescriptor = DriverDescriptor {
+    name: "virtio-gpu",
+    compatible: &["pci1af4,1050"],
+    probe,
+};
+
+#[derive(Debug, Copy, Clone, Default)]
+#[repr(C)]
+struct CtrlHeader {
+    ty: u32,
+    flags: u32,
+    fence_id: u64,
+    ctx_id: u32,
+    ring_idx: u8,
+    padding: [u8; 3],
+}
+
+impl CtrlHeader {
+    fn new(ty: u32) -> Self {
+        Self {
+            ty,
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default)]
+#[repr(C)]
+struct GpuRect {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+}
+
+impl From<Rect> for GpuRect {
+    fn from(rect: Rect) -> Self {
+        Self {
+            x: rect.x as u32,
+            y: rect.y as u32,
+            width: rect.width as u32,
+            height: rect.height as u32,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+#[repr(C)]
+struct DisplayOne {
+    rect: GpuRect,
+    enabled: u32,
+    flags: u32,
+}
+
+#[derive(Debug, Copy, Clone)]
+#[repr(C)]
+struct RespDisplayInfo {
+    hdr: CtrlHeader,
+    modes: [DisplayOne; MAX_SCANOUTS],
+}
+
+#[derive(Debug, Copy, Clone)]
+#[repr(C)]
+struct ResourceCreate2d {
+    hdr: CtrlHeader,
+    resource_id: u32,
+    format: u32,
+    width: u32,
+    height: u32,
+}
+
+#[derive(Debug, Copy, Clone)]
+#[repr(C)]
+struct MemEntry {
+    addr: u64,
+    length: u32,
+    padding: u32,
+}
+
+/// with a single entry, since the backing is one contiguous block
+#[derive(Debug, Copy, Clone)]
+#[repr(C)]
+struct ResourceAttachBacking {
+    hdr: CtrlHeader,
+    resource_id: u32,
+    nr_entries: u32,
+    entry: MemEntry,
+}
+
+#[derive(Debug, Copy, Clone)]
+#[repr(C)]
+struct SetScanout {
+    hdr: CtrlHeader,
+    rect: GpuRect,
+    scanout_id: u32,
+    resource_id: u32,
+}
+
+#[derive(Debug, Copy, Clone)]
+#[repr(C)]
+struct TransferToHost2d {
+    hdr: CtrlHeader,
+    rect: GpuRect,
+    offset: u64,
+    resource_id: u32,
+    padding: u32,
+}
+
+#[derive(Debug, Copy, Clone)]
+#[repr(C)]
+struct ResourceFlush {
+    hdr: CtrlHeader,
+    rect: GpuRect,
+    resource_id: u32,
+    padding: u32,
+}
+
+struct Control {
+    queue: Virtqueue,
+    /// request at the start, response at `RESPONSE_OFFSET`
+    buf: DmaBuffer,
+    /// a command timed out and the device may still own `buf`, so nothing more is submitted
+    stuck: bool,
+}
+
+impl Control {
+    /// submit `req` and wait for a response of type `Resp`
+    fn command<Req: Copy, Resp: Copy>(&mut self, req: &Req) -> Option<Resp> {
+        debug_assert!(size_of::<Req>() <= RESPONSE_OFFSET);
+        debug_assert!(RESPONSE_OFFSET + size_of::<Resp>() <= self.buf.len());
+
+        if self.stuck {
+            return None;
+        }
+
+        let base = self.buf.as_ptr();
+        unsafe {
+            write_volatile(base as *mut Req, *req);
+            write_volatile(
+                base.add(RESPONSE_OFFSET) as *mut CtrlHeader,
+                CtrlHeader::default(),
+            );
+        }
+        self.buf.clean(0, size_of::<Req>());
+        self.buf.clean(RESPONSE_OFFSET, size_of::<CtrlHeader>());
+
+        let phys = self.buf.phys_addr();
+        let chain = [
+            QueueBuffer {
+                addr: phys,
+                len: size_of::<Req>() as u32,
+                writable: false,
+            },
+            QueueBuffer {
+                addr: phys + RESPONSE_OFFSET as u64,
+                len: size_of::<Resp>() as u32,
+                writable: true,
+            },
+        ];
+
+        self.queue.add(&chain)?;
+        self.queue.kick();
+
+        // commands are rare and quick, so there's no interrupt for the control queue
+        let deadline = time::monotonic() + COMMAND_TIMEOUT;
+        while self.queue.pop_used().is_none() {
+            if time::monotonic() > deadline {
+                self.stuck = true;
+                return None;
+            }
+            core::hint::spin_loop();
+        }
+
+        self.buf.invalidate(RESPONSE_OFFSET, size_of::<Resp>());
+        Some(unsafe { read_volatile(base.add(RESPONSE_OFFSET) as *const Resp) })
+    }
+
+    /// submit a command that answers with a bare header. true if it succeeded.
+    fn command_nodata<Req: Copy>(&mut self, req: &Req) -> bool {
+        self.command::<Req, CtrlHeader>(req)
+            .is_some_and(|resp| resp.ty == RESP_OK_NODATA)
+    }
+}
+
+pub struct VirtioGpu {
+    transport: VirtioPci,
+    control: FairSpinlock<Control>,
+    info: FramebufferInfo,
+    pixels: NonNull<u32>,
+    /// set once a command fails, so a dead device doesn't stall every flush
+    broken: AtomicBool,
+}
+
+// SAFETY: `pixels` is owned by the GPU for its whole lifetime, and commands are serialized by
+// `control`
+unsafe impl Send for VirtioGpu {}
+unsafe impl Sync for VirtioGpu {}
+
+impl Framebuffer for VirtioGpu {
+    fn info(&self) -> FramebufferInfo {
+        self.info
+    }
+
+    fn pixels(&self) -> NonNull<u32> {
+        self.pixels
+    }
+
+    fn flush(&self, rect: Rect) {
+        if self.broken.load(Ordering::Relaxed) {
+            return;
+        }
+
+        let x = rect.x.min(self.info.width);
+        let y = rect.y.min(self.info.height);
+        let rect = Rect::new(
+            x,
+            y,
+            rect.width.min(self.info.width - x),
+            rect.height.min(self.info.height - y),
+        );
+        if rect.is_empty() {
+            return;
+        }
+
+        let transfer = TransferToHost2d {
+            hdr: CtrlHeader::new(CMD_TRANSFER_TO_HOST_2D),
+            rect: rect.into(),
+            offset: ((y * self.info.stride + x) * size_of::<u32>()) as u64,
+            resource_id: RESOURCE_ID,
+            padding: 0,
+        };
+        let flush = ResourceFlush {
+            hdr: CtrlHeader::new(CMD_RESOURCE_FLUSH),
+            rect: rect.into(),
+            resource_id: RESOURCE_ID,
+            padding: 0,
+        };
+
+        // the device copies from memory, not the cache
+        for row in y..y + rect.height {
+            let start = unsafe { self.pixels.as_ptr().add(row * self.info.stride + x) };
+            unsafe { clean_dcache_range(start.cast(), rect.width * size_of::<u32>()) };
+        }
+
+        let _irq = InterruptGuard::new();
+        let mut control = self.control.lock();
+
+        if !control.command_nodata(&transfer) || !control.command_nodata(&flush) {
+            // no logging: the console would be the one calling us
+            self.broken.store(true, Ordering::Relaxed);
+
+            // a reset is the only way to get the stuck command's buffers back
+            if control.stuck {
+                self.transport.reset();
+            }
+        }
+    }
+}
+
+struct VirtioGpuDevice(Arc<VirtioGpu>);
+
+impl Device for VirtioGpuDevice {
+    fn shutdown(&self) {
+        self.0.broken.store(true, Ordering::Relaxed);
+        self.0.transport.reset();
+    }
+}
+
+/// first enabled scanout and its size
+fn display_info(control: &mut Control) -> Option<(u32, u32, u32)> {
+    let resp: RespDisplayInfo = control.command(&CtrlHeader::new(CMD_GET_DISPLAY_INFO))?;
+    if resp.hdr.ty != RESP_OK_DISPLAY_INFO {
+        return None;
+    }
+
+    resp.modes
+        .iter()
+        .enumerate()
+        .find(|(_, mode)| mode.enabled != 0 && mode.rect.width != 0 && mode.rect.height != 0)
+        .map(|(i, mode)| (i as u32, mode.rect.width, mode.rect.height))
+}
+
+fn probe(node: &DeviceNode) -> Result<Box<dyn Device>, DriverError> {
+    use log::*;
+
+    let transport = VirtioPci::new(node)?;
+    let bdf = transport.bdf;
+
+    transport.begin_init(VIRTIO_F_VERSION_1)?;
+
+    let queue = transport.setup_queue(CONTROL_QUEUE, QUEUE_SIZE, NO_VECTOR)?;
+    let buf = DmaBuffer::new_in(
+        RESPONSE_OFFSET + size_of::<RespDisplayInfo>(),
+        &transport.dma,
+    )
+    .ok_or(DriverError::Io)?;
+    let mut control = Control {
+        queue,
+        buf,
+        stuck: false,
+    };
+
+    transport.finish_init();
+
+    let (scanout, width, height) = display_info(&mut control).unwrap_or_else(|| {
+        warn!(
+            "{}: no enabled scanout, using {}x{}",
+            bdf, DEFAULT_WIDTH, DEFAULT_HEIGHT
+        );
+        (0, DEFAULT_WIDTH, DEFAULT_HEIGHT)
+    });
+
+    let bytes = width as usize * height as usize * size_of::<u32>();
+    let pages = bytes.div_ceil(PAGE_SIZE);
+    let order = pages.next_power_of_two().trailing_zeros() as usize;
+
+    let allocator = kernel_page_allocator().ok_or(DriverError::MissingResources)?;
+    let backing = allocator.alloc_dmap_pages(order).ok_or_else(|| {
+        error!(
+            "{}: no {} contiguous pages for a {}x{} framebuffer",
+            bdf,
+            1 << order,
+            width,
+            height
+        );
+        DriverError::Io
+    })?;
+    unsafe { backing.as_ptr().write_bytes(0, bytes) };
+
+    let backing_phys = KernelAddressTranslator.dmap_to_phys(backing.as_ptr());
+    let backing_range = backing_phys..backing_phys + (PAGE_SIZE << order);
+
+    if transport.dma.map(backing_range.clone()).is_err() {
+        error!("{}: can't map the framebuffer for the device", bdf);
+        allocator.free_dmap_pages(backing);
+        return Err(DriverError::Io);
+    }
+
+    let ok = control.command_nodata(&ResourceCreate2d {
+        hdr: CtrlHeader::new(CMD_RESOURCE_CREATE_2D),
+        resource_id: RESOURCE_ID,
+        format: FORMAT_B8G8R8X8_UNORM,
+        width,
+        height,
+    }) && control.command_nodata(&ResourceAttachBacking {
+        hdr: CtrlHeader::new(CMD_RESOURCE_ATTACH_BACKING),
+        resource_id: RESOURCE_ID,
+        nr_entries: 1,
+        entry: MemEntry {
+            addr: backing_phys as u64,
+            length: bytes as u32,
+            padding: 0,
+        },
+    }) && control.command_nodata(&SetScanout {
+        hdr: CtrlHeader::new(CMD_SET_SCANOUT),
+        rect: GpuRect {
+            x: 0,
+            y: 0,
+            width,
+            height,
+        },
+        scanout_id: scanout,
+        resource_id: RESOURCE_ID,
+    });
+
+    if !ok {
+        error!("{}: virtio-gpu rejected the scanout setup", bdf);
+        transport.reset();
+        transport.dma.unmap(backing_range);
+        allocator.free_dmap_pages(backing);
+        return Err(DriverError::Io);
+    }
+
+    let gpu = Arc::new(VirtioGpu {
+        transport,
+        control: FairSpinlock::new(control),
+        info: FramebufferInfo {
+            width: width as usize,
+            height: height as usize,
+            stride: width as usize,
+        },
+        pixels: backing.cast(),
+        broken: AtomicBool::new(false),
+    });
+
+    info!(
+        "{}: virtio-gpu scanout {} at {}x{}",
+        bdf, scanout, width, height
+    );
+
+    fbcon::register(gpu.clone());
+
+    Ok(Box::new(VirtioGpuDevice(gpu)))
+}
@@ -0,0 +1,219 @@
+//! virtio-input keyboards, typing into the console terminal.
+//!
+//! events arrive on the event queue as `virtio_input_event`s. key events go through the keymap
+//! and the resulting bytes are fed to the console TTY, the same line discipline the serial port
+//! uses. everything else (sync, LEDs, autorepeat reports) is dropped.
+
+use alloc::{boxed::Box, format, string::String, sync::Arc, vec::Vec};
+use klib::{
+    hardware::{
+        device::{Device, DeviceNode},
+        dma::DmaBuffer,
+        driver::{DriverDescriptor, DriverError},
+    },
+    sync::FairSpinlock,
+    tty::{self, keyboard::Keyboard},
+};
+
+use crate::{
+    VIRTIO_F_VERSION_1,
+    irq::{VirtioInterrupt, route_interrupt},
+    pci::VirtioPci,
+    queue::{QueueBuffer, Virtqueue},
+};
+
+pub static VIRTIO_INPUT_DRIVER: DriverDescriptor = DriverDescriptor {
+    name: "virtio-input",
+    compatible: &["pci1af4,1052"],
+    probe,
+};
+
+// virtio_input_config
+const CFG_SELECT: usize = 0x00;
+const CFG_SUBSEL: usize = 0x01;
+const CFG_SIZE: usize = 0x02;
+const CFG_DATA: usize = 0x08;
+const CFG_DATA_SIZE: usize = 128;
+
+// virtio_input_config_select
+const CFG_ID_NAME: u8 = 0x01;
+const CFG_EV_BITS: u8 = 0x11;
+
+const EV_KEY: u16 = 0x01;
+
+/// any keyboard has an A key, tablets and mice don't
+const KEY_A: u16 = 30;
+
+/// le16 type, le16 code, le32 value
+const EVENT_LEN: usize = 8;
+
+const EVENT_QUEUE: u16 = 0;
+const QUEUE_SIZE: u16 = 64;
+
+struct Events {
+    queue: Virtqueue,
+    bufs: DmaBuffer,
+    /// descriptor head -> event slot
+    slots: Box<[u16]>,
+    keyboard: Keyboard,
+}
+
+pub struct VirtioInput {
+    transport: VirtioPci,
+    events: FairSpinlock<Events>,
+    name: String,
+}
+
+impl VirtioInput {
+    fn post(events: &mut Events, slot: u16) {
+        let buf = QueueBuffer {
+            addr: events.bufs.phys_addr() + (slot as usize * EVENT_LEN) as u64,
+            len: EVENT_LEN as u32,
+            writable: true,
+        };
+
+        if let Some(head) = events.queue.add(&[buf]) {
+            events.slots[head as usize] = slot;
+        }
+    }
+}
+
+impl VirtioInterrupt for VirtioInput {
+    fn on_interrupt(&self, _lpi: u32) {
+        let mut typed = Vec::new();
+
+        {
+            let mut events = self.events.lock();
+            let mut reposted = false;
+
+            while let Some((head, _)) = events.queue.pop_used() {
+                let slot = events.slots[head as usize];
+                let start = slot as usize * EVENT_LEN;
+                let event = &events.bufs.as_slice()[start..start + EVENT_LEN];
+
+                let kind = u16::from_le_bytes([event[0], event[1]]);
+                let code = u16::from_le_bytes([event[2], event[3]]);
+                let value = u32::from_le_bytes([event[4], event[5], event[6], event[7]]);
+
+                if kind == EV_KEY {
+                    // 0 release, 1 press, 2 autorepeat
+                    events.keyboard.key(code, value != 0, &mut typed);
+                }
+
+                Self::post(&mut events, slot);
+                reposted = true;
+            }
+
+            if reposted {
+                events.queue.kick();
+            }
+        }
+
+        if !typed.is_empty() {
+            match tty::console() {
+                Some(console) => console.receive(&typed),
+                None => log::trace!("{}: no console, dropping input", self.name),
+            }
+        }
+    }
+}
+
+/// read a config field, `None` if the device doesn't have it
+fn config(transport: &VirtioPci, select: u8, subsel: u8) -> Option<Vec<u8>> {
+    transport.write_device_cfg_u8(CFG_SELECT, select);
+    transport.write_device_cfg_u8(CFG_SUBSEL, subsel);
+
+    let size = (transport.device_cfg_u8(CFG_SIZE) as usize).min(CFG_DATA_SIZE);
+    if size == 0 {
+        return None;
+    }
+
+    Some(
+        (0..size)
+            .map(|i| transport.device_cfg_u8(CFG_DATA + i))
+            .collect(),
+    )
+}
+
+struct VirtioInputDevice(Arc<VirtioInput>);
+
+impl Device for VirtioInputDevice {
+    fn shutdown(&self) {
+        self.0.transport.reset();
+    }
+}
+
+fn probe(node: &DeviceNode) -> Result<Box<dyn Device>, DriverError> {
+    use log::*;
+
+    let mut transport = VirtioPci::new(node)?;
+    let bdf = transport.bdf;
+
+    let has_a_key = config(&transport, CFG_EV_BITS, EV_KEY as u8)
+        .and_then(|bits| bits.get(KEY_A as usize / 8).copied())
+        .is_some_and(|byte| byte & (1 << (KEY_A % 8)) != 0);
+    if !has_a_key {
+        debug!("{}: virtio-input device isn't a keyboard", bdf);
+        return Err(DriverError::Incompatible);
+    }
+
+    let name = config(&transport, CFG_ID_NAME, 0)
+        .map(|name| String::from_utf8_lossy(&name).into_owned())
+        .unwrap_or_else(|| "keyboard".into());
+
+    transport.begin_init(VIRTIO_F_VERSION_1)?;
+
+    // there's nothing to poll from, so a keyboard without MSI-X is useless
+    let lpis = transport
+        .enable_msix()
+        .map_err(|e| {
+            warn!("{}: no MSI-X ({:?})", bdf, e);
+            DriverError::MissingResources
+        })?
+        .to_vec();
+    let vector = if lpis.len() > 1 { 1 } else { 0 };
+    transport.set_config_vector(0);
+
+    let queue = transport.setup_queue(EVENT_QUEUE, QUEUE_SIZE, vector)?;
+    let count = queue.size();
+    let bufs =
+        DmaBuffer::new_in(count as usize * EVENT_LEN, &transport.dma).ok_or(DriverError::Io)?;
+
+    let mut events = Events {
+        queue,
+        bufs,
+        slots: alloc::vec![0; count as usize].into_boxed_slice(),
+        keyboard: Keyboard::new(),
+    };
+    for slot in 0..count {
+        VirtioInput::post(&mut events, slot);
+    }
+
+    let input = Arc::new(VirtioInput {
+        transport,
+        events: FairSpinlock::new(events),
+        name: format!("virtio-input@{}", bdf),
+    });
+
+    for &lpi in &lpis {
+        if let Err(e) = route_interrupt(lpi, input.clone()) {
+            warn!("{}: failed to route LPI {}: {:?}", bdf, lpi, e);
+        }
+    }
+
+    input.transport.finish_init();
+    input.events.lock().queue.kick();
+
+    info!(
+        "{}: {} ({}), keymap {}",
+        input.name,
+        name,
+        match tty::console() {
+            Some(console) => format!("typing into {}", console.name()),
+            None => "no console yet".into(),
+        },
+        tty::keyboard::keymap().name
+    );
+
+    Ok(Box::new(VirtioInputDevice(input)))
+}
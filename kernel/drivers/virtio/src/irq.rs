@@ -0,0 +1,43 @@
+use alloc::{collections::btree_map::BTreeMap, sync::Arc};
+use klib::{
+    guard::InterruptGuard,
+    interrupt::{
+        InterruptError,
+        gicv3::{IrqHandler, IrqTarget},
+        singleton::get_interrupt_controller,
+    },
+    sync::RwLock,
+};
+
+/// a device that wants its MSI-X vectors delivered
+pub trait VirtioInterrupt: Send + Sync {
+    fn on_interrupt(&self, lpi: u32);
+}
+
+static ROUTES: RwLock<BTreeMap<u32, Arc<dyn VirtioInterrupt>>> = RwLock::new(BTreeMap::new());
+
+fn dispatch(lpi: u32) -> Result<(), InterruptError> {
+    let target = ROUTES
+        .read()
+        .get(&lpi)
+        .cloned()
+        .ok_or(InterruptError::HandlerNotFound)?;
+
+    target.on_interrupt(lpi);
+    Ok(())
+}
+
+/// deliver `lpi` to `target` and unmask it.
+pub fn route_interrupt(lpi: u32, target: Arc<dyn VirtioInterrupt>) -> Result<(), InterruptError> {
+    {
+        let _irq = InterruptGuard::new();
+        ROUTES.write().insert(lpi, target);
+    }
+
+    let ic = get_interrupt_controller();
+    let handler =
+        IrqHandler::new(IrqTarget::Redistributor, dispatch).ok_or(InterruptError::NotSupported)?;
+
+    ic.register_handler(lpi, handler)?;
+    ic.enable_interrupt(lpi)
+}
@@ -0,0 +1,30 @@
+//! virtio 1.x devices over the PCI transport.
+
+#![no_std]
+
+extern crate alloc;
+
+pub mod gpu;
+pub mod input;
+pub mod irq;
+pub mod net;
+pub mod ninep;
+pub mod pci;
+pub mod queue;
+pub mod rng;
+
+/// the device complies with virtio 1.0+ (no legacy interface)
+pub const VIRTIO_F_VERSION_1: u64 = 1 << 32;
+
+/// PCI vendor ID for all virtio devices
+pub const VIRTIO_PCI_VENDOR: u16 = 0x1AF4;
+
+/// device status bits, see virtio 1.2 section 2.1
+pub mod status {
+    pub const ACKNOWLEDGE: u8 = 1;
+    pub const DRIVER: u8 = 2;
+    pub const DRIVER_OK: u8 = 4;
+    pub const FEATURES_OK: u8 = 8;
+    pub const DEVICE_NEEDS_RESET: u8 = 64;
+    pub const FAILED: u8 = 128;
+}
@@ -0,0 +1,412 @@
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use alloc::{boxed::Box, collections::vec_deque::VecDeque, format, string::String, sync::Arc};
+use klib::{
+    guard::InterruptGuard,
+    hardware::{
+        device::{Device, DeviceNode},
+        dma::DmaBuffer,
+        driver::{DriverDescriptor, DriverError},
+    },
+    net::{
+        NetError,
+        checksum::{accumulate, fold},
+        device::{
+            ChecksumCaps, ETH_HEADER_LEN, MacAddress, NetDeviceId, NetworkDevice, RxFrame,
+            TxChecksum, notify_rx, register_device,
+        },
+    },
+    sync::FairSpinlock,
+};
+
+use crate::{
+    VIRTIO_F_VERSION_1,
+    irq::{VirtioInterrupt, route_interrupt},
+    pci::{NO_VECTOR, VirtioPci},
+    queue::{QueueBuffer, Virtqueue},
+};
+
+const VIRTIO_NET_F_CSUM: u64 = 1 << 0;
+const VIRTIO_NET_F_GUEST_CSUM: u64 = 1 << 1;
+const VIRTIO_NET_F_MTU: u64 = 1 << 3;
+const VIRTIO_NET_F_MAC: u64 = 1 << 5;
+const VIRTIO_NET_F_STATUS: u64 = 1 << 16;
+
+const HDR_F_NEEDS_CSUM: u8 = 1 << 0;
+const HDR_F_DATA_VALID: u8 = 1 << 1;
+const HDR_GSO_NONE: u8 = 0;
+
+const STATUS_LINK_UP: u16 = 1 << 0;
+
+// virtio_net_config
+const CFG_MAC: usize = 0x00;
+const CFG_STATUS: usize = 0x06;
+const CFG_MTU: usize = 0x0A;
+
+/// virtio_net_hdr with `num_buffers`, which is always present with VERSION_1
+const NET_HDR_LEN: usize = 12;
+
+const RX_QUEUE: u16 = 0;
+const TX_QUEUE: u16 = 1;
+const QUEUE_SIZE: u16 = 128;
+
+/// no mergeable RX buffers, so every buffer has to fit a whole frame
+const BUF_LEN: usize = 2048;
+const DEFAULT_MTU: usize = 1500;
+
+/// frames held for the stack before the oldest are dropped
+const RX_BACKLOG: usize = 256;
+
+const NOT_REGISTERED: usize = usize::MAX;
+
+pub static VIRTIO_NET_DRIVER: DriverDescriptor = DriverDescriptor {
+    name: "virtio-net",
+    // transitional and modern device IDs
+    compatible: &["pci1af4,1000", "pci1af4,1041"],
+    probe,
+};
+
+struct Rings {
+    rx: Virtqueue,
+    tx: Virtqueue,
+    rx_bufs: DmaBuffer,
+    tx_bufs: DmaBuffer,
+    /// descriptor head -> buffer slot
+    rx_slots: Box<[u16]>,
+    tx_slots: Box<[u16]>,
+    tx_free: VecDeque<u16>,
+    backlog: VecDeque<RxFrame>,
+}
+
+pub struct VirtioNet {
+    transport: VirtioPci,
+    rings: FairSpinlock<Rings>,
+    name: String,
+    mac: MacAddress,
+    mtu: usize,
+    features: u64,
+    id: AtomicUsize,
+}
+
+impl VirtioNet {
+    fn post_rx(rings: &mut Rings, slot: u16) -> bool {
+        let addr = rings.rx_bufs.phys_addr() + (slot as usize * BUF_LEN) as u64;
+        let buf = QueueBuffer {
+            addr,
+            len: BUF_LEN as u32,
+            writable: true,
+        };
+
+        match rings.rx.add(&[buf]) {
+            Some(head) => {
+                rings.rx_slots[head as usize] = slot;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// move completed RX buffers into the backlog and hand them back to the device.
+    fn reap_rx(&self, rings: &mut Rings) -> usize {
+        let mut count = 0;
+        let mut reposted = false;
+
+        while let Some((head, len)) = rings.rx.pop_used() {
+            let slot = rings.rx_slots[head as usize];
+            let start = slot as usize * BUF_LEN;
+            let len = (len as usize).min(BUF_LEN);
+
+            if len > NET_HDR_LEN {
+                // the device may not be coherent with the CPU
+                rings.rx_bufs.invalidate(start, len);
+                let buf = &rings.rx_bufs.as_slice()[start..start + len];
+                let guest_csum = self.features & VIRTIO_NET_F_GUEST_CSUM != 0;
+                let mut data = buf[NET_HDR_LEN..].to_vec();
+
+                // NEEDS_CSUM frames are good, only their checksum is partial. it gets finished
+                // in case the frame goes anywhere else.
+                let checksum_valid = if guest_csum && buf[0] & HDR_F_NEEDS_CSUM != 0 {
+                    let csum_start = u16::from_le_bytes([buf[6], buf[7]]);
+                    let csum_offset = u16::from_le_bytes([buf[8], buf[9]]);
+                    complete_checksum(&mut data, csum_start as usize, csum_offset as usize)
+                } else {
+                    guest_csum && buf[0] & HDR_F_DATA_VALID != 0
+                };
+
+                if rings.backlog.len() >= RX_BACKLOG {
+                    log::trace!("{}: rx backlog full, dropping oldest frame", self.name);
+                    rings.backlog.pop_front();
+                }
+
+                rings.backlog.push_back(RxFrame {
+                    data,
+                    checksum_valid,
+                });
+                count += 1;
+            }
+
+            reposted |= Self::post_rx(rings, slot);
+        }
+
+        // short frames give their buffers back too, and the device needs to hear about them
+        if reposted {
+            rings.rx.kick();
+        }
+
+        count
+    }
+
+    fn reclaim_tx(rings: &mut Rings) {
+        while let Some((head, _)) = rings.tx.pop_used() {
+            let slot = rings.tx_slots[head as usize];
+            rings.tx_free.push_back(slot);
+        }
+    }
+}
+
+/// fold everything from `start` on into the checksum at `start + offset`, which holds the
+/// pseudo-header sum. false if they're out of the frame.
+fn complete_checksum(frame: &mut [u8], start: usize, offset: usize) -> bool {
+    let field = start + offset;
+    if start >= frame.len() || field + 2 > frame.len() {
+        return false;
+    }
+
+    // 0 means no checksum to UDP, and 0xFFFF verifies the same
+    let sum = match !fold(accumulate(0, &frame[start..])) {
+        0 => 0xFFFF,
+        sum => sum,
+    };
+    frame[field..field + 2].copy_from_slice(&sum.to_be_bytes());
+    true
+}
+
+impl VirtioInterrupt for VirtioNet {
+    fn on_interrupt(&self, _lpi: u32) {
+        let received = {
+            let mut rings = self.rings.lock();
+            Self::reclaim_tx(&mut rings);
+            self.reap_rx(&mut rings)
+        };
+
+        let id = self.id.load(Ordering::Acquire);
+        if received > 0 && id != NOT_REGISTERED {
+            notify_rx(NetDeviceId(id));
+        }
+    }
+}
+
+impl NetworkDevice for VirtioNet {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn mac_address(&self) -> MacAddress {
+        self.mac
+    }
+
+    fn mtu(&self) -> usize {
+        self.mtu
+    }
+
+    fn link_up(&self) -> bool {
+        if self.features & VIRTIO_NET_F_STATUS == 0 {
+            return true;
+        }
+
+        self.transport.device_cfg_u16(CFG_STATUS) & STATUS_LINK_UP != 0
+    }
+
+    fn checksum_caps(&self) -> ChecksumCaps {
+        ChecksumCaps {
+            tx: self.features & VIRTIO_NET_F_CSUM != 0,
+            rx: self.features & VIRTIO_NET_F_GUEST_CSUM != 0,
+        }
+    }
+
+    fn transmit(&self, frame: &[u8], csum: Option<TxChecksum>) -> klib::net::Result<()> {
+        if frame.len() < ETH_HEADER_LEN {
+            return Err(NetError::FrameTooSmall);
+        }
+        if frame.len() > self.mtu + ETH_HEADER_LEN {
+            return Err(NetError::FrameTooLarge);
+        }
+        if !self.link_up() {
+            return Err(NetError::LinkDown);
+        }
+
+        let csum = csum.filter(|_| self.features & VIRTIO_NET_F_CSUM != 0);
+
+        let _irq = InterruptGuard::new();
+        let mut rings = self.rings.lock();
+
+        Self::reclaim_tx(&mut rings);
+        let slot = rings.tx_free.pop_front().ok_or(NetError::QueueFull)?;
+
+        let start = slot as usize * BUF_LEN;
+        let len = NET_HDR_LEN + frame.len();
+        {
+            let buf = &mut rings.tx_bufs.as_mut_slice()[start..start + len];
+            buf[..NET_HDR_LEN].fill(0);
+            buf[1] = HDR_GSO_NONE;
+
+            if let Some(csum) = csum {
+                buf[0] = HDR_F_NEEDS_CSUM;
+                buf[6..8].copy_from_slice(&csum.start.to_le_bytes());
+                buf[8..10].copy_from_slice(&csum.offset.to_le_bytes());
+            }
+
+            buf[NET_HDR_LEN..].copy_from_slice(frame);
+        }
+        rings.tx_bufs.clean(start, len);
+
+        let addr = rings.tx_bufs.phys_addr() + start as u64;
+        let desc = QueueBuffer {
+            addr,
+            len: len as u32,
+            writable: false,
+        };
+
+        match rings.tx.add(&[desc]) {
+            Some(head) => {
+                rings.tx_slots[head as usize] = slot;
+                rings.tx.kick();
+                Ok(())
+            }
+            None => {
+                rings.tx_free.push_front(slot);
+                Err(NetError::QueueFull)
+            }
+        }
+    }
+
+    fn receive(&self) -> Option<RxFrame> {
+        let _irq = InterruptGuard::new();
+        let mut rings = self.rings.lock();
+
+        // covers devices running without MSI-X
+        if rings.backlog.is_empty() {
+            self.reap_rx(&mut rings);
+        }
+
+        rings.backlog.pop_front()
+    }
+}
+
+struct VirtioNetDevice(Arc<VirtioNet>);
+
+impl Device for VirtioNetDevice {
+    fn shutdown(&self) {
+        self.0.transport.reset();
+    }
+}
+
+fn probe(node: &DeviceNode) -> Result<Box<dyn Device>, DriverError> {
+    use log::*;
+
+    let mut transport = VirtioPci::new(node)?;
+    let bdf = transport.bdf;
+
+    let wanted = VIRTIO_F_VERSION_1
+        | VIRTIO_NET_F_CSUM
+        | VIRTIO_NET_F_GUEST_CSUM
+        | VIRTIO_NET_F_MTU
+        | VIRTIO_NET_F_MAC
+        | VIRTIO_NET_F_STATUS;
+    let features = transport.begin_init(wanted)?;
+
+    // vector 0 for config changes, 1 for RX. TX completions are reaped lazily.
+    let lpis = match transport.enable_msix() {
+        Ok(lpis) => lpis.to_vec(),
+        Err(e) => {
+            warn!("{}: no MSI-X ({:?}), falling back to polling", bdf, e);
+            alloc::vec::Vec::new()
+        }
+    };
+    let rx_vector = match lpis.len() {
+        0 => NO_VECTOR,
+        1 => 0,
+        _ => 1,
+    };
+    if !lpis.is_empty() {
+        transport.set_config_vector(0);
+    }
+
+    let rx = transport.setup_queue(RX_QUEUE, QUEUE_SIZE, rx_vector)?;
+    let mut tx = transport.setup_queue(TX_QUEUE, QUEUE_SIZE, NO_VECTOR)?;
+    tx.set_interrupts(false);
+
+    let rx_count = rx.size();
+    let tx_count = tx.size();
+
+    let rx_bufs =
+        DmaBuffer::new_in(rx_count as usize * BUF_LEN, &transport.dma).ok_or(DriverError::Io)?;
+    let tx_bufs =
+        DmaBuffer::new_in(tx_count as usize * BUF_LEN, &transport.dma).ok_or(DriverError::Io)?;
+
+    let mut rings = Rings {
+        rx,
+        tx,
+        rx_bufs,
+        tx_bufs,
+        rx_slots: alloc::vec![0; rx_count as usize].into_boxed_slice(),
+        tx_slots: alloc::vec![0; tx_count as usize].into_boxed_slice(),
+        tx_free: (0..tx_count).collect(),
+        backlog: VecDeque::new(),
+    };
+
+    for slot in 0..rx_count {
+        VirtioNet::post_rx(&mut rings, slot);
+    }
+
+    let mac = if features & VIRTIO_NET_F_MAC != 0 {
+        let mut mac = [0u8; 6];
+        for (i, byte) in mac.iter_mut().enumerate() {
+            *byte = transport.device_cfg_u8(CFG_MAC + i);
+        }
+        MacAddress(mac)
+    } else {
+        // locally administered, derived from the BDF
+        MacAddress([0x02, 0x00, 0x00, bdf.bus, bdf.device, bdf.function])
+    };
+
+    let mtu = if features & VIRTIO_NET_F_MTU != 0 {
+        (transport.device_cfg_u16(CFG_MTU) as usize).min(DEFAULT_MTU)
+    } else {
+        DEFAULT_MTU
+    };
+
+    let net = Arc::new(VirtioNet {
+        transport,
+        rings: FairSpinlock::new(rings),
+        name: format!("virtio-net@{}", bdf),
+        mac,
+        mtu,
+        features,
+        id: AtomicUsize::new(NOT_REGISTERED),
+    });
+
+    for &lpi in &lpis {
+        if let Err(e) = route_interrupt(lpi, net.clone()) {
+            warn!("{}: failed to route LPI {}: {:?}", bdf, lpi, e);
+        }
+    }
+
+    net.transport.finish_init();
+    net.rings.lock().rx.kick();
+
+    info!(
+        "{}: virtio-net up, mac {}, mtu {}, link {}, csum offload tx={} rx={}",
+        bdf,
+        net.mac,
+        net.mtu,
+        if net.link_up() { "up" } else { "down" },
+        features & VIRTIO_NET_F_CSUM != 0,
+        features & VIRTIO_NET_F_GUEST_CSUM != 0,
+    );
+
+    let id = register_device(net.clone());
+    net.id.store(id.0, Ordering::Release);
+
+    Ok(Box::new(VirtioNetDevice(net)))
+}
@@ -0,0 +1,207 @@
+//! the 9P2000.L operations the filesystem needs, one request at a time.
+
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use alloc::{sync::Arc, vec::Vec};
+
+use super::{
+    Channel,
+    proto::{self, Attr, Message, NinePError, Qid, ReaddirEntry, Reader, Result},
+};
+
+/// every request but Tversion, since only one is ever in flight
+const TAG: u16 = 1;
+
+pub struct Client {
+    channel: Arc<Channel>,
+    msize: u32,
+    next_fid: AtomicU32,
+}
+
+impl Client {
+    /// negotiate the protocol version and message size
+    pub fn connect(channel: Arc<Channel>, msize: u32) -> Result<Self> {
+        let mut msg = Message::new(proto::TVERSION, proto::NOTAG);
+        msg.u32(msize).str(proto::VERSION);
+        let reply = channel.rpc(msg)?;
+
+        let mut r = Reader::new(&reply);
+        let server_msize = r.u32()?;
+        let version = r.str()?;
+        if version != proto::VERSION {
+            return Err(NinePError::Malformed);
+        }
+
+        Ok(Self {
+            channel,
+            msize: msize.min(server_msize),
+            next_fid: AtomicU32::new(0),
+        })
+    }
+
+    pub fn msize(&self) -> u32 {
+        self.msize
+    }
+
+    /// the most a single Tread or Twrite can move
+    pub fn max_io(&self) -> usize {
+        self.msize as usize - proto::IO_HEADER_LEN
+    }
+
+    pub fn alloc_fid(&self) -> u32 {
+        self.next_fid.fetch_add(1, Ordering::Relaxed)
+    }
+
+    fn rpc(&self, msg: Message) -> Result<Vec<u8>> {
+        self.channel.rpc(msg)
+    }
+
+    pub fn attach(&self, fid: u32, uname: &str, aname: &str) -> Result<Qid> {
+        let mut msg = Message::new(proto::TATTACH, TAG);
+        msg.u32(fid)
+            .u32(proto::NOFID)
+            .str(uname)
+            .str(aname)
+            // n_uname: root
+            .u32(0);
+        Reader::new(&self.rpc(msg)?).qid()
+    }
+
+    /// walk from `fid` along `names` to a new fid. no names clones `fid`.
+    pub fn walk(&self, fid: u32, names: &[&str]) -> Result<(u32, Vec<Qid>)> {
+        if names.len() > proto::MAX_WALK_NAMES {
+            return Err(NinePError::TooLarge);
+        }
+
+        let newfid = self.alloc_fid();
+        let mut msg = Message::new(proto::TWALK, TAG);
+        msg.u32(fid).u32(newfid).u16(names.len() as u16);
+        for name in names {
+            msg.str(name);
+        }
+
+        let reply = self.rpc(msg)?;
+        let mut r = Reader::new(&reply);
+        let count = r.u16()? as usize;
+        let qids = (0..count).map(|_| r.qid()).collect::<Result<Vec<_>>>()?;
+
+        // a partial walk doesn't create `newfid`
+        if qids.len() < names.len() {
+            return Err(NinePError::Remote(proto::ENOENT));
+        }
+
+        Ok((newfid, qids))
+    }
+
+    /// returns the qid and the iounit, 0 if the server doesn't say
+    pub fn lopen(&self, fid: u32, flags: u32) -> Result<(Qid, u32)> {
+        let mut msg = Message::new(proto::TLOPEN, TAG);
+        msg.u32(fid).u32(flags);
+
+        let reply = self.rpc(msg)?;
+        let mut r = Reader::new(&reply);
+        Ok((r.qid()?, r.u32()?))
+    }
+
+    /// create `name` in the directory `fid`, which then refers to the new file, opened
+    pub fn lcreate(&self, fid: u32, name: &str, flags: u32, mode: u32) -> Result<(Qid, u32)> {
+        let mut msg = Message::new(proto::TLCREATE, TAG);
+        msg.u32(fid).str(name).u32(flags).u32(mode).u32(0);
+
+        let reply = self.rpc(msg)?;
+        let mut r = Reader::new(&reply);
+        Ok((r.qid()?, r.u32()?))
+    }
+
+    pub fn mkdir(&self, dfid: u32, name: &str, mode: u32) -> Result<Qid> {
+        let mut msg = Message::new(proto::TMKDIR, TAG);
+        msg.u32(dfid).str(name).u32(mode).u32(0);
+        Reader::new(&self.rpc(msg)?).qid()
+    }
+
+    /// read at most `max_io` bytes. returns how many were read, 0 at end of file.
+    pub fn read(&self, fid: u32, offset: u64, buf: &mut [u8]) -> Result<usize> {
+        let count = buf.len().min(self.max_io());
+        let mut msg = Message::new(proto::TREAD, TAG);
+        msg.u32(fid).u64(offset).u32(count as u32);
+
+        let reply = self.rpc(msg)?;
+        let mut r = Reader::new(&reply);
+        let n = (r.u32()? as usize).min(count);
+        buf[..n].copy_from_slice(r.bytes(n)?);
+        Ok(n)
+    }
+
+    /// write at most `max_io` bytes. returns how many were written.
+    pub fn write(&self, fid: u32, offset: u64, data: &[u8]) -> Result<usize> {
+        let data = &data[..data.len().min(self.max_io())];
+        let mut msg = Message::new(proto::TWRITE, TAG);
+        msg.u32(fid).u64(offset).u32(data.len() as u32).bytes(data);
+
+        let reply = self.rpc(msg)?;
+        Ok(Reader::new(&reply).u32()? as usize)
+    }
+
+    /// one batch of entries of the open directory `fid`, starting after `offset`. empty at the end.
+    pub fn readdir(&self, fid: u32, offset: u64) -> Result<Vec<ReaddirEntry>> {
+        let mut msg = Message::new(proto::TREADDIR, TAG);
+        msg.u32(fid).u64(offset).u32(self.max_io() as u32);
+
+        let reply = self.rpc(msg)?;
+        let mut r = Reader::new(&reply);
+        let count = r.u32()? as usize;
+        let mut data = Reader::new(r.bytes(count)?);
+
+        let mut entries = Vec::new();
+        while !data.is_empty() {
+            let qid = data.qid()?;
+            let offset = data.u64()?;
+            let _ty = data.u8()?;
+            let name = data.str()?;
+            entries.push(ReaddirEntry { qid, offset, name });
+        }
+
+        Ok(entries)
+    }
+
+    pub fn getattr(&self, fid: u32) -> Result<Attr> {
+        let mut msg = Message::new(proto::TGETATTR, TAG);
+        msg.u32(fid).u64(proto::GETATTR_BASIC);
+
+        let reply = self.rpc(msg)?;
+        let mut r = Reader::new(&reply);
+        let _valid = r.u64()?;
+        let qid = r.qid()?;
+        let _mode = r.u32()?;
+        let _uid = r.u32()?;
+        let _gid = r.u32()?;
+        let _nlink = r.u64()?;
+        let _rdev = r.u64()?;
+        let size = r.u64()?;
+
+        Ok(Attr { qid, size })
+    }
+
+    pub fn truncate(&self, fid: u32, size: u64) -> Result<()> {
+        let mut msg = Message::new(proto::TSETATTR, TAG);
+        msg.u32(fid)
+            .u32(proto::SETATTR_SIZE)
+            // mode, uid, gid
+            .u32(0)
+            .u32(0)
+            .u32(0)
+            .u64(size)
+            // atime and mtime, seconds and nanoseconds
+            .u64(0)
+            .u64(0)
+            .u64(0)
+            .u64(0);
+        self.rpc(msg).map(|_| ())
+    }
+
+    pub fn clunk(&self, fid: u32) -> Result<()> {
+        let mut msg = Message::new(proto::TCLUNK, TAG);
+        msg.u32(fid);
+        self.rpc(msg).map(|_| ())
+    }
+}
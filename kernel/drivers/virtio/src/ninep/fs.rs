@@ -0,0 +1,266 @@
+//! files on the server as inodes. every inode holds a fid walked to its file, and opens a second
+//! one for I/O the first time it's read or written. both are clunked when the inode goes away.
+
+use core::sync::atomic::AtomicU64;
+
+use alloc::{sync::Arc, vec::Vec};
+use klib::{
+    scheduler::GLOBAL_SCHEDULER,
+    sync::SleepingMutex,
+    vfs::{
+        FileType, Result, VfsError,
+        inode::{Dirent, Inode, InodeOperations},
+    },
+};
+
+use super::{
+    client::Client,
+    proto::{self, Qid},
+};
+
+const FILE_MODE: u32 = 0o644;
+const DIR_MODE: u32 = 0o755;
+
+/// an open fid and whether it can be written through
+#[derive(Copy, Clone)]
+struct OpenFid {
+    fid: u32,
+    writable: bool,
+}
+
+struct Node {
+    client: Arc<Client>,
+    fid: u32,
+    qid: Qid,
+    io: SleepingMutex<'static, Option<OpenFid>>,
+}
+
+/// the inode for the attached root, `fid`
+pub(super) fn root(client: Arc<Client>, fid: u32, qid: Qid) -> Arc<Inode> {
+    make_inode(client, fid, qid, 0, None)
+}
+
+fn make_inode(
+    client: Arc<Client>,
+    fid: u32,
+    qid: Qid,
+    size: u64,
+    open: Option<OpenFid>,
+) -> Arc<Inode> {
+    Arc::new(Inode {
+        number: qid.path,
+        file_type: if qid.is_dir() {
+            FileType::Directory
+        } else {
+            FileType::Normal
+        },
+        size: AtomicU64::new(size),
+        operations: Arc::new(Node {
+            client,
+            fid,
+            qid,
+            io: SleepingMutex::new(open),
+        }),
+    })
+}
+
+impl Node {
+    /// an inode for `name` in this directory
+    fn walk_to(&self, name: &str, open: Option<OpenFid>) -> Result<Arc<Inode>> {
+        let (fid, qids) = self.client.walk(self.fid, &[name])?;
+
+        let attr = match self.client.getattr(fid) {
+            Ok(attr) => attr,
+            Err(e) => {
+                _ = self.client.clunk(fid);
+                return Err(e.into());
+            }
+        };
+        let qid = qids.last().copied().unwrap_or(attr.qid);
+
+        Ok(make_inode(self.client.clone(), fid, qid, attr.size, open))
+    }
+
+    /// the fid to do I/O through, opened on first use. read-write if the server allows it.
+    fn open(&self, write: bool) -> Result<u32> {
+        let mut io = self.io.lock(&GLOBAL_SCHEDULER);
+
+        let open = match *io {
+            Some(open) => open,
+            None => {
+                let (fid, _) = self.client.walk(self.fid, &[])?;
+                let open = match self.client.lopen(fid, proto::O_RDWR) {
+                    Ok(_) => OpenFid {
+                        fid,
+                        writable: true,
+                    },
+                    Err(_) => match self.client.lopen(fid, proto::O_RDONLY) {
+                        Ok(_) => OpenFid {
+                            fid,
+                            writable: false,
+                        },
+                        Err(e) => {
+                            _ = self.client.clunk(fid);
+                            return Err(e.into());
+                        }
+                    },
+                };
+
+                *io = Some(open);
+                open
+            }
+        };
+
+        if write && !open.writable {
+            return Err(VfsError::PermissionDenied);
+        }
+
+        Ok(open.fid)
+    }
+}
+
+impl InodeOperations for Node {
+    fn read_at(&self, offset: u64, buffer: &mut [u8]) -> Result<u64> {
+        if self.qid.is_dir() {
+            return Err(VfsError::IsADirectory);
+        }
+
+        let fid = self.open(false)?;
+        let mut done = 0;
+
+        while done < buffer.len() {
+            let n = self
+                .client
+                .read(fid, offset + done as u64, &mut buffer[done..])?;
+            if n == 0 {
+                break;
+            }
+            done += n;
+        }
+
+        Ok(done as u64)
+    }
+
+    fn write_at(&self, offset: u64, buffer: &[u8]) -> Result<u64> {
+        if self.qid.is_dir() {
+            return Err(VfsError::IsADirectory);
+        }
+
+        let fid = self.open(true)?;
+        let mut done = 0;
+
+        while done < buffer.len() {
+            let n = self
+                .client
+                .write(fid, offset + done as u64, &buffer[done..])?;
+            if n == 0 {
+                return Err(VfsError::OutOfSpace);
+            }
+            done += n;
+        }
+
+        Ok(done as u64)
+    }
+
+    fn lookup_child(&self, name: &str) -> Result<Arc<Inode>> {
+        if !self.qid.is_dir() {
+            return Err(VfsError::NotADirectory);
+        }
+
+        self.walk_to(name, None)
+    }
+
+    fn create(&self, name: &str, file_type: FileType) -> Result<Arc<Inode>> {
+        if !self.qid.is_dir() {
+            return Err(VfsError::NotADirectory);
+        }
+
+        match file_type {
+            FileType::Normal => {
+                // Tlcreate turns the directory fid it's given into an open fid for the new file,
+                // which is kept for I/O
+                let (fid, _) = self.client.walk(self.fid, &[])?;
+                if let Err(e) =
+                    self.client
+                        .lcreate(fid, name, proto::O_RDWR | proto::O_EXCL, FILE_MODE)
+                {
+                    _ = self.client.clunk(fid);
+                    return Err(e.into());
+                }
+
+                let open = OpenFid {
+                    fid,
+                    writable: true,
+                };
+                self.walk_to(name, Some(open)).inspect_err(|_| {
+                    // no inode to clunk it when it goes away
+                    _ = self.client.clunk(fid);
+                })
+            }
+            FileType::Directory => {
+                self.client.mkdir(self.fid, name, DIR_MODE)?;
+                self.walk_to(name, None)
+            }
+        }
+    }
+
+    fn truncate(&self, size: u64) -> Result<()> {
+        if self.qid.is_dir() {
+            return Err(VfsError::IsADirectory);
+        }
+
+        Ok(self.client.truncate(self.fid, size)?)
+    }
+
+    fn readdir(&self) -> Result<Vec<Dirent>> {
+        if !self.qid.is_dir() {
+            return Err(VfsError::NotADirectory);
+        }
+
+        let (fid, _) = self.client.walk(self.fid, &[])?;
+
+        let list = || -> proto::Result<Vec<Dirent>> {
+            self.client
+                .lopen(fid, proto::O_RDONLY | proto::O_DIRECTORY)?;
+
+            let mut entries = Vec::new();
+            let mut offset = 0;
+            loop {
+                let batch = self.client.readdir(fid, offset)?;
+                let Some(last) = batch.last() else {
+                    break;
+                };
+                offset = last.offset;
+
+                entries.extend(
+                    batch
+                        .into_iter()
+                        .filter(|e| e.name != "." && e.name != "..")
+                        .map(|e| Dirent {
+                            file_type: if e.qid.is_dir() {
+                                FileType::Directory
+                            } else {
+                                FileType::Normal
+                            },
+                            name: e.name,
+                        }),
+                );
+            }
+
+            Ok(entries)
+        };
+
+        let result = list();
+        _ = self.client.clunk(fid);
+        Ok(result?)
+    }
+}
+
+impl Drop for Node {
+    fn drop(&mut self) {
+        if let Some(open) = *self.io.lock(&GLOBAL_SCHEDULER) {
+            _ = self.client.clunk(open.fid);
+        }
+        _ = self.client.clunk(self.fid);
+    }
+}
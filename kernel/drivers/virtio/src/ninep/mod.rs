@@ -0,0 +1,233 @@
+//! virtio-9p: host directories shared with QEMU `-virtfs`, mounted at `/<mount tag>`.
+//!
+//! the transport carries one 9P2000.L request at a time, as a device-readable buffer followed by a
+//! device-writable one for the reply. callers sleep until the completion interrupt, or spin when
+//! there's no thread to put to sleep yet.
+
+mod client;
+mod fs;
+mod proto;
+
+use alloc::{boxed::Box, string::String, sync::Arc, vec::Vec};
+use klib::{
+    hardware::{
+        device::{Device, DeviceNode},
+        dma::DmaBuffer,
+        driver::{DriverDescriptor, DriverError},
+    },
+    scheduler::GLOBAL_SCHEDULER,
+    sync::{SleepingMutex, WaitQueue},
+    vfs::mount,
+};
+
+use crate::{
+    VIRTIO_F_VERSION_1,
+    irq::{VirtioInterrupt, route_interrupt},
+    pci::{NO_VECTOR, VirtioPci},
+    queue::{QueueBuffer, Virtqueue},
+};
+use client::Client;
+use proto::{Message, NinePError, Reader};
+
+pub static VIRTIO_9P_DRIVER: DriverDescriptor = DriverDescriptor {
+    name: "virtio-9p",
+    // transitional and modern device IDs
+    compatible: &["pci1af4,1009", "pci1af4,1049"],
+    probe,
+};
+
+/// the device has a mount tag in its config space
+const VIRTIO_9P_F_MOUNT_TAG: u64 = 1 << 0;
+
+// virtio_9p_config
+const CFG_TAG_LEN: usize = 0x00;
+const CFG_TAG: usize = 0x02;
+
+const REQUEST_QUEUE: u16 = 0;
+const QUEUE_SIZE: u16 = 8;
+
+/// largest message in either direction
+const MSIZE: u32 = 64 * 1024;
+
+const ROOT_FID: u32 = 0;
+
+struct Ring {
+    queue: Virtqueue,
+    request: DmaBuffer,
+    reply: DmaBuffer,
+}
+
+pub struct Channel {
+    transport: VirtioPci,
+    ring: SleepingMutex<'static, Ring>,
+    done: WaitQueue<'static>,
+    has_interrupts: bool,
+}
+
+impl Channel {
+    /// send a T-message and return the body of its R-message
+    fn rpc(&self, msg: Message) -> proto::Result<Vec<u8>> {
+        let ty = msg.ty();
+        let msg = msg.finish();
+        if msg.len() > MSIZE as usize {
+            return Err(NinePError::TooLarge);
+        }
+
+        let mut ring = self.ring.lock(&GLOBAL_SCHEDULER);
+        ring.request.as_mut_slice()[..msg.len()].copy_from_slice(&msg);
+        ring.request.clean(0, msg.len());
+
+        let bufs = [
+            QueueBuffer {
+                addr: ring.request.phys_addr(),
+                len: msg.len() as u32,
+                writable: false,
+            },
+            QueueBuffer {
+                addr: ring.reply.phys_addr(),
+                len: MSIZE,
+                writable: true,
+            },
+        ];
+        ring.queue.add(&bufs).ok_or(NinePError::TooLarge)?;
+        ring.queue.kick();
+
+        let len = loop {
+            let seen = self.done.generation();
+            if let Some((_, len)) = ring.queue.pop_used() {
+                break len as usize;
+            }
+
+            if self.has_interrupts && GLOBAL_SCHEDULER.current_thread().is_some() {
+                self.done.wait(&GLOBAL_SCHEDULER, seen);
+            } else {
+                core::hint::spin_loop();
+            }
+        };
+
+        let reply = &ring.reply.as_slice()[..len.min(MSIZE as usize)];
+        let mut r = Reader::new(reply);
+        let size = r.u32()? as usize;
+        let reply_ty = r.u8()?;
+        let _tag = r.u16()?;
+        if size < proto::HEADER_LEN || size > reply.len() {
+            return Err(NinePError::Malformed);
+        }
+        let body = &reply[proto::HEADER_LEN..size];
+
+        if reply_ty == proto::RLERROR {
+            return Err(NinePError::Remote(Reader::new(body).u32()?));
+        }
+        if reply_ty != ty + 1 {
+            return Err(NinePError::UnexpectedReply(reply_ty));
+        }
+
+        Ok(body.to_vec())
+    }
+}
+
+impl VirtioInterrupt for Channel {
+    fn on_interrupt(&self, _lpi: u32) {
+        self.done.wake_all(&GLOBAL_SCHEDULER);
+    }
+}
+
+struct Virtio9pDevice {
+    channel: Arc<Channel>,
+    tag: String,
+}
+
+impl Device for Virtio9pDevice {
+    fn shutdown(&self) {
+        _ = mount::unmount(&self.tag);
+        self.channel.transport.reset();
+    }
+}
+
+fn probe(node: &DeviceNode) -> Result<Box<dyn Device>, DriverError> {
+    use log::*;
+
+    let mut transport = VirtioPci::new(node)?;
+    let bdf = transport.bdf;
+
+    let features = transport.begin_init(VIRTIO_F_VERSION_1 | VIRTIO_9P_F_MOUNT_TAG)?;
+
+    let tag = if features & VIRTIO_9P_F_MOUNT_TAG != 0 {
+        let len = transport.device_cfg_u16(CFG_TAG_LEN) as usize;
+        let bytes = (0..len)
+            .map(|i| transport.device_cfg_u8(CFG_TAG + i))
+            .collect::<Vec<_>>();
+        String::from_utf8_lossy(&bytes).into_owned()
+    } else {
+        "9p".into()
+    };
+
+    let lpis = match transport.enable_msix() {
+        Ok(lpis) => lpis.to_vec(),
+        Err(e) => {
+            warn!("{}: no MSI-X ({:?}), falling back to polling", bdf, e);
+            Vec::new()
+        }
+    };
+    let vector = match lpis.len() {
+        0 => NO_VECTOR,
+        1 => 0,
+        _ => 1,
+    };
+    if !lpis.is_empty() {
+        transport.set_config_vector(0);
+    }
+
+    let queue = transport.setup_queue(REQUEST_QUEUE, QUEUE_SIZE, vector)?;
+    let request = DmaBuffer::new_in(MSIZE as usize, &transport.dma).ok_or(DriverError::Io)?;
+    let reply = DmaBuffer::new_in(MSIZE as usize, &transport.dma).ok_or(DriverError::Io)?;
+
+    let channel = Arc::new(Channel {
+        transport,
+        ring: SleepingMutex::new(Ring {
+            queue,
+            request,
+            reply,
+        }),
+        done: WaitQueue::new(),
+        has_interrupts: !lpis.is_empty(),
+    });
+
+    for &lpi in &lpis {
+        if let Err(e) = route_interrupt(lpi, channel.clone()) {
+            warn!("{}: failed to route LPI {}: {:?}", bdf, lpi, e);
+        }
+    }
+
+    channel.transport.finish_init();
+
+    let connect = || -> proto::Result<_> {
+        let client = Client::connect(channel.clone(), MSIZE)?;
+        let root_fid = client.alloc_fid();
+        debug_assert_eq!(root_fid, ROOT_FID);
+        let qid = client.attach(root_fid, "root", "")?;
+        Ok((client, qid))
+    };
+    let (client, qid) = connect().map_err(|e| {
+        error!("{}: 9P: {}", bdf, e);
+        channel.transport.reset();
+        DriverError::Io
+    })?;
+
+    info!(
+        "{}: virtio-9p share \"{}\", {}, msize {}",
+        bdf,
+        tag,
+        proto::VERSION,
+        client.msize()
+    );
+
+    let root = fs::root(Arc::new(client), ROOT_FID, qid);
+    if let Err(e) = mount::mount(&tag, root) {
+        error!("{}: can't mount /{}: {}", bdf, tag, e);
+        channel.transport.reset();
+        return Err(DriverError::Io);
+    }
+
+    Ok(Box::new(Virtio9pDevice { channel, tag }))
+}
@@ -0,0 +1,243 @@
+//! 9P2000.L message encoding. every field is little endian, strings are a u16 length followed by
+//! UTF-8 bytes.
+
+use core::fmt;
+
+use alloc::{string::String, vec::Vec};
+use klib::vfs::VfsError;
+
+pub const VERSION: &str = "9P2000.L";
+
+/// `size[4] type[1] tag[2]`
+pub const HEADER_LEN: usize = 7;
+/// room a Tread/Twrite header takes out of `msize`
+pub const IO_HEADER_LEN: usize = 24;
+
+pub const NOTAG: u16 = 0xFFFF;
+pub const NOFID: u32 = 0xFFFF_FFFF;
+
+// message types. the R-message is always the T-message plus one.
+pub const RLERROR: u8 = 7;
+pub const TLOPEN: u8 = 12;
+pub const TLCREATE: u8 = 14;
+pub const TGETATTR: u8 = 24;
+pub const TSETATTR: u8 = 26;
+pub const TREADDIR: u8 = 40;
+pub const TMKDIR: u8 = 72;
+pub const TVERSION: u8 = 100;
+pub const TATTACH: u8 = 104;
+pub const TWALK: u8 = 110;
+pub const TREAD: u8 = 116;
+pub const TWRITE: u8 = 118;
+pub const TCLUNK: u8 = 120;
+
+/// Linux open flags, which 9P2000.L uses as is
+pub const O_RDONLY: u32 = 0o0;
+pub const O_RDWR: u32 = 0o2;
+pub const O_EXCL: u32 = 0o200;
+pub const O_DIRECTORY: u32 = 0o200000;
+
+/// Tgetattr: everything up to and including the block count
+pub const GETATTR_BASIC: u64 = 0x0000_07FF;
+/// Tsetattr: the size field is valid
+pub const SETATTR_SIZE: u32 = 1 << 3;
+
+/// the longest name Twalk takes per message
+pub const MAX_WALK_NAMES: usize = 16;
+
+const QT_DIR: u8 = 0x80;
+
+// the errnos that mean something to the VFS
+const EPERM: u32 = 1;
+pub const ENOENT: u32 = 2;
+const EACCES: u32 = 13;
+const EEXIST: u32 = 17;
+const ENOTDIR: u32 = 20;
+const EISDIR: u32 = 21;
+const ENOSPC: u32 = 28;
+const EROFS: u32 = 30;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum NinePError {
+    /// the server answered with Rlerror
+    Remote(u32),
+    /// the reply doesn't parse
+    Malformed,
+    /// the reply isn't the one the request calls for
+    UnexpectedReply(u8),
+    /// the message doesn't fit in `msize`
+    TooLarge,
+}
+
+impl fmt::Display for NinePError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Remote(errno) => write!(f, "server error {errno}"),
+            Self::Malformed => write!(f, "malformed reply"),
+            Self::UnexpectedReply(ty) => write!(f, "unexpected reply type {ty}"),
+            Self::TooLarge => write!(f, "message larger than msize"),
+        }
+    }
+}
+
+impl From<NinePError> for VfsError {
+    fn from(e: NinePError) -> Self {
+        match e {
+            NinePError::Remote(ENOENT) => VfsError::NotFound,
+            NinePError::Remote(EEXIST) => VfsError::ExistsAlready,
+            NinePError::Remote(ENOTDIR) => VfsError::NotADirectory,
+            NinePError::Remote(EISDIR) => VfsError::IsADirectory,
+            NinePError::Remote(EPERM | EACCES | EROFS) => VfsError::PermissionDenied,
+            NinePError::Remote(ENOSPC) => VfsError::OutOfSpace,
+            _ => VfsError::Io,
+        }
+    }
+}
+
+pub type Result<T> = core::result::Result<T, NinePError>;
+
+/// unique identifier of a file on the server
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Qid {
+    pub ty: u8,
+    pub version: u32,
+    pub path: u64,
+}
+
+impl Qid {
+    pub fn is_dir(&self) -> bool {
+        self.ty & QT_DIR != 0
+    }
+}
+
+/// the Rgetattr fields we use
+#[derive(Debug, Copy, Clone)]
+pub struct Attr {
+    pub qid: Qid,
+    pub size: u64,
+}
+
+/// one Rreaddir entry
+#[derive(Debug, Clone)]
+pub struct ReaddirEntry {
+    pub qid: Qid,
+    /// pass to the next Treaddir to continue after this entry
+    pub offset: u64,
+    pub name: String,
+}
+
+/// builds one T-message
+pub struct Message {
+    buf: Vec<u8>,
+}
+
+impl Message {
+    pub fn new(ty: u8, tag: u16) -> Self {
+        let mut msg = Self {
+            buf: Vec::with_capacity(64),
+        };
+        msg.u32(0).u8(ty).u16(tag);
+        msg
+    }
+
+    pub fn u8(&mut self, v: u8) -> &mut Self {
+        self.buf.push(v);
+        self
+    }
+
+    pub fn u16(&mut self, v: u16) -> &mut Self {
+        self.buf.extend_from_slice(&v.to_le_bytes());
+        self
+    }
+
+    pub fn u32(&mut self, v: u32) -> &mut Self {
+        self.buf.extend_from_slice(&v.to_le_bytes());
+        self
+    }
+
+    pub fn u64(&mut self, v: u64) -> &mut Self {
+        self.buf.extend_from_slice(&v.to_le_bytes());
+        self
+    }
+
+    pub fn str(&mut self, s: &str) -> &mut Self {
+        self.u16(s.len() as u16);
+        self.buf.extend_from_slice(s.as_bytes());
+        self
+    }
+
+    pub fn bytes(&mut self, b: &[u8]) -> &mut Self {
+        self.buf.extend_from_slice(b);
+        self
+    }
+
+    pub fn ty(&self) -> u8 {
+        self.buf[4]
+    }
+
+    /// the finished message, with its size filled in
+    pub fn finish(mut self) -> Vec<u8> {
+        let len = self.buf.len() as u32;
+        self.buf[..4].copy_from_slice(&len.to_le_bytes());
+        self.buf
+    }
+}
+
+/// walks the body of an R-message
+pub struct Reader<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(buf: &'a [u8]) -> Self {
+        Self { buf }
+    }
+
+    pub fn bytes(&mut self, n: usize) -> Result<&'a [u8]> {
+        if self.buf.len() < n {
+            return Err(NinePError::Malformed);
+        }
+        let (head, rest) = self.buf.split_at(n);
+        self.buf = rest;
+        Ok(head)
+    }
+
+    pub fn u8(&mut self) -> Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub fn u16(&mut self) -> Result<u16> {
+        let b = self.bytes(2)?;
+        Ok(u16::from_le_bytes([b[0], b[1]]))
+    }
+
+    pub fn u32(&mut self) -> Result<u32> {
+        let b = self.bytes(4)?;
+        Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    pub fn u64(&mut self) -> Result<u64> {
+        let b = self.bytes(8)?;
+        let mut v = [0u8; 8];
+        v.copy_from_slice(b);
+        Ok(u64::from_le_bytes(v))
+    }
+
+    pub fn str(&mut self) -> Result<String> {
+        let len = self.u16()? as usize;
+        let b = self.bytes(len)?;
+        Ok(String::from_utf8_lossy(b).into_owned())
+    }
+
+    pub fn qid(&mut self) -> Result<Qid> {
+        Ok(Qid {
+            ty: self.u8()?,
+            version: self.u32()?,
+            path: self.u64()?,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+}
@@ -0,0 +1,317 @@
+use core::ptr::{NonNull, read_volatile, write_volatile};
+
+use alloc::vec::Vec;
+use klib::hardware::{device::DeviceNode, driver::DriverError, iommu::DmaSpace, mmio::map_mmio};
+use mars_pcie_driver::{
+    address::Bdf,
+    bar::bar_address,
+    capability::standard::{StandardCapIter, StandardCapabilityId},
+    ecam::{Ecam, get_segment},
+    interrupt::{enable_msix, map_msix_table},
+};
+
+use crate::{queue::Virtqueue, status};
+
+/// `queue_msix_vector`/`config_msix_vector` value for "no interrupt"
+pub const NO_VECTOR: u16 = 0xFFFF;
+
+const CFG_TYPE_COMMON: u8 = 1;
+const CFG_TYPE_NOTIFY: u8 = 2;
+const CFG_TYPE_ISR: u8 = 3;
+const CFG_TYPE_DEVICE: u8 = 4;
+
+// virtio_pci_common_cfg
+const DEVICE_FEATURE_SELECT: usize = 0x00;
+const DEVICE_FEATURE: usize = 0x04;
+const DRIVER_FEATURE_SELECT: usize = 0x08;
+const DRIVER_FEATURE: usize = 0x0C;
+const CONFIG_MSIX_VECTOR: usize = 0x10;
+const NUM_QUEUES: usize = 0x12;
+const DEVICE_STATUS: usize = 0x14;
+const CONFIG_GENERATION: usize = 0x15;
+const QUEUE_SELECT: usize = 0x16;
+const QUEUE_SIZE: usize = 0x18;
+const QUEUE_MSIX_VECTOR: usize = 0x1A;
+const QUEUE_ENABLE: usize = 0x1C;
+const QUEUE_NOTIFY_OFF: usize = 0x1E;
+const QUEUE_DESC: usize = 0x20;
+const QUEUE_DRIVER: usize = 0x28;
+const QUEUE_DEVICE: usize = 0x30;
+
+/// a modern virtio-pci function
+pub struct VirtioPci {
+    pub bdf: Bdf,
+    /// where the function's DMA buffers go
+    pub dma: DmaSpace,
+    ecam: &'static Ecam,
+    common: NonNull<u8>,
+    notify: NonNull<u8>,
+    notify_multiplier: u32,
+    isr: Option<NonNull<u8>>,
+    device_cfg: Option<NonNull<u8>>,
+    msix_lpis: Vec<u32>,
+}
+
+// SAFETY: every pointer is MMIO that's only touched through volatile accesses
+unsafe impl Send for VirtioPci {}
+unsafe impl Sync for VirtioPci {}
+
+impl VirtioPci {
+    pub fn new(node: &DeviceNode) -> Result<Self, DriverError> {
+        use log::*;
+
+        let bdf = Bdf::try_from(&node.class).map_err(|_| DriverError::Incompatible)?;
+        let ecam = get_segment(bdf.segment).ok_or(DriverError::MissingResources)?;
+
+        let mut common = None;
+        let mut notify = None;
+        let mut notify_multiplier = 0;
+        let mut isr = None;
+        let mut device_cfg = None;
+
+        for (id, cap) in StandardCapIter::new(ecam, bdf) {
+            if id != StandardCapabilityId::VendorSpecific {
+                continue;
+            }
+
+            let cap = cap as u16;
+            let cfg_type = ecam.read_u8(bdf, cap + 3);
+            let bar = ecam.read_u8(bdf, cap + 4);
+            let offset = ecam.read_u32(bdf, cap + 8) as usize;
+            let length = ecam.read_u32(bdf, cap + 12) as usize;
+
+            let slot = match cfg_type {
+                CFG_TYPE_COMMON => &mut common,
+                CFG_TYPE_NOTIFY => &mut notify,
+                CFG_TYPE_ISR => &mut isr,
+                CFG_TYPE_DEVICE => &mut device_cfg,
+                _ => continue,
+            };
+
+            // the first capability of each type is the preferred one
+            if slot.is_some() {
+                continue;
+            }
+
+            let Some(base) = bar_address(ecam, bdf, bar) else {
+                continue;
+            };
+            if base == 0 {
+                warn!(
+                    "{}: virtio cfg {} lives in unassigned BAR {}",
+                    bdf, cfg_type, bar
+                );
+                continue;
+            }
+
+            let start = base as usize + offset;
+            let ptr = map_mmio(&(start..start + length.max(1))).ok_or(DriverError::Io)?;
+
+            *slot = Some(ptr);
+
+            if cfg_type == CFG_TYPE_NOTIFY {
+                notify_multiplier = ecam.read_u32(bdf, cap + 16);
+            }
+        }
+
+        let (Some(common), Some(notify)) = (common, notify) else {
+            // legacy-only device
+            return Err(DriverError::Incompatible);
+        };
+
+        let dma = bdf.dma_space();
+
+        ecam.enable_memory_space(bdf);
+        ecam.enable_bus_master(bdf);
+
+        Ok(Self {
+            bdf,
+            dma,
+            ecam,
+            common,
+            notify,
+            notify_multiplier,
+            isr,
+            device_cfg,
+            msix_lpis: Vec::new(),
+        })
+    }
+
+    fn common_ptr<T>(&self, offset: usize) -> *mut T {
+        unsafe { self.common.as_ptr().add(offset) as *mut T }
+    }
+
+    fn read_common<T: Copy>(&self, offset: usize) -> T {
+        unsafe { read_volatile(self.common_ptr(offset)) }
+    }
+
+    fn write_common<T: Copy>(&self, offset: usize, val: T) {
+        unsafe { write_volatile(self.common_ptr(offset), val) }
+    }
+
+    pub fn status(&self) -> u8 {
+        self.read_common(DEVICE_STATUS)
+    }
+
+    /// OR `bits` into the device status
+    pub fn add_status(&self, bits: u8) {
+        self.write_common(DEVICE_STATUS, self.status() | bits);
+    }
+
+    pub fn reset(&self) {
+        self.write_common(DEVICE_STATUS, 0u8);
+        while self.status() != 0 {
+            core::hint::spin_loop();
+        }
+    }
+
+    pub fn device_features(&self) -> u64 {
+        self.write_common(DEVICE_FEATURE_SELECT, 0u32);
+        let low = self.read_common::<u32>(DEVICE_FEATURE) as u64;
+        self.write_common(DEVICE_FEATURE_SELECT, 1u32);
+        let high = self.read_common::<u32>(DEVICE_FEATURE) as u64;
+
+        (high << 32) | low
+    }
+
+    /// reset the device and run feature negotiation up to FEATURES_OK.
+    /// returns the accepted subset of `wanted`.
+    pub fn begin_init(&self, wanted: u64) -> Result<u64, DriverError> {
+        self.reset();
+        self.add_status(status::ACKNOWLEDGE);
+        self.add_status(status::DRIVER);
+
+        let features = self.device_features() & wanted;
+        if features & crate::VIRTIO_F_VERSION_1 == 0 {
+            self.add_status(status::FAILED);
+            return Err(DriverError::Incompatible);
+        }
+
+        self.write_common(DRIVER_FEATURE_SELECT, 0u32);
+        self.write_common(DRIVER_FEATURE, features as u32);
+        self.write_common(DRIVER_FEATURE_SELECT, 1u32);
+        self.write_common(DRIVER_FEATURE, (features >> 32) as u32);
+
+        self.add_status(status::FEATURES_OK);
+        if self.status() & status::FEATURES_OK == 0 {
+            self.add_status(status::FAILED);
+            return Err(DriverError::Incompatible);
+        }
+
+        Ok(features)
+    }
+
+    pub fn finish_init(&self) {
+        self.add_status(status::DRIVER_OK);
+    }
+
+    pub fn num_queues(&self) -> u16 {
+        self.read_common(NUM_QUEUES)
+    }
+
+    pub fn config_generation(&self) -> u8 {
+        self.read_common(CONFIG_GENERATION)
+    }
+
+    /// set up and enable queue `index` with at most `max_size` entries.
+    pub fn setup_queue(
+        &self,
+        index: u16,
+        max_size: u16,
+        msix_vector: u16,
+    ) -> Result<Virtqueue, DriverError> {
+        self.write_common(QUEUE_SELECT, index);
+
+        let device_max = self.read_common::<u16>(QUEUE_SIZE);
+        if device_max == 0 {
+            return Err(DriverError::MissingResources);
+        }
+
+        // sizes don't have to be powers of two in virtio 1.x, but ours are
+        let size = max_size.min(device_max);
+        let size = 1 << (u16::BITS - 1 - size.leading_zeros());
+
+        let notify_off = self.read_common::<u16>(QUEUE_NOTIFY_OFF) as usize;
+        let notify = unsafe {
+            self.notify
+                .add(notify_off * self.notify_multiplier as usize)
+                .cast::<u16>()
+        };
+
+        let queue = Virtqueue::new(index, size, notify, &self.dma).ok_or(DriverError::Io)?;
+
+        self.write_common(QUEUE_SIZE, size);
+        self.write_common(QUEUE_DESC, queue.desc_phys());
+        self.write_common(QUEUE_DRIVER, queue.avail_phys());
+        self.write_common(QUEUE_DEVICE, queue.used_phys());
+
+        if msix_vector != NO_VECTOR {
+            self.write_common(QUEUE_MSIX_VECTOR, msix_vector);
+            if self.read_common::<u16>(QUEUE_MSIX_VECTOR) != msix_vector {
+                log::warn!("{}: queue {} refused MSI-X vector", self.bdf, index);
+            }
+        }
+
+        self.write_common(QUEUE_ENABLE, 1u16);
+
+        Ok(queue)
+    }
+
+    pub fn set_config_vector(&self, vector: u16) {
+        self.write_common(CONFIG_MSIX_VECTOR, vector);
+    }
+
+    /// map every MSI-X vector of the function. returned LPIs are indexed by vector.
+    pub fn enable_msix(&mut self) -> Result<&[u32], DriverError> {
+        use log::*;
+
+        let mut table = map_msix_table(self.ecam, self.bdf).ok_or(DriverError::MissingResources)?;
+        self.msix_lpis = enable_msix(self.ecam, self.bdf, &mut table).map_err(|e| {
+            warn!("{}: {}", self.bdf, e);
+            DriverError::Io
+        })?;
+
+        Ok(&self.msix_lpis)
+    }
+
+    pub fn msix_lpis(&self) -> &[u32] {
+        &self.msix_lpis
+    }
+
+    /// read and clear the legacy ISR status
+    pub fn read_isr(&self) -> u8 {
+        self.isr
+            .map(|isr| unsafe { read_volatile(isr.as_ptr()) })
+            .unwrap_or(0)
+    }
+
+    pub fn device_cfg_u8(&self, offset: usize) -> u8 {
+        self.device_cfg
+            .map(|cfg| unsafe { read_volatile(cfg.as_ptr().add(offset)) })
+            .unwrap_or(0)
+    }
+
+    pub fn device_cfg_u16(&self, offset: usize) -> u16 {
+        self.device_cfg
+            .map(|cfg| unsafe { read_volatile(cfg.as_ptr().add(offset) as *const u16) })
+            .unwrap_or(0)
+    }
+
+    pub fn device_cfg_u32(&self, offset: usize) -> u32 {
+        self.device_cfg
+            .map(|cfg| unsafe { read_volatile(cfg.as_ptr().add(offset) as *const u32) })
+            .unwrap_or(0)
+    }
+
+    pub fn write_device_cfg_u8(&self, offset: usize, val: u8) {
+        if let Some(cfg) = self.device_cfg {
+            unsafe { write_volatile(cfg.as_ptr().add(offset), val) };
+        }
+    }
+
+    pub fn write_device_cfg_u32(&self, offset: usize, val: u32) {
+        if let Some(cfg) = self.device_cfg {
+            unsafe { write_volatile(cfg.as_ptr().add(offset) as *mut u32, val) };
+        }
+    }
+}
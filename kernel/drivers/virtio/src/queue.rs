@@ -0,0 +1,232 @@
+use core::{
+    mem::size_of,
+    ptr::{NonNull, read_volatile, write_volatile},
+};
+
+use aarch64_cpu::asm::barrier::{self, dmb};
+use klib::hardware::{dma::DmaBuffer, iommu::DmaSpace};
+
+const DESC_F_NEXT: u16 = 1 << 0;
+const DESC_F_WRITE: u16 = 1 << 1;
+
+/// tell the device not to interrupt when it consumes buffers from this queue
+const AVAIL_F_NO_INTERRUPT: u16 = 1 << 0;
+
+#[derive(Debug, Copy, Clone, Default)]
+#[repr(C)]
+struct Descriptor {
+    addr: u64,
+    len: u32,
+    flags: u16,
+    next: u16,
+}
+
+#[derive(Debug, Copy, Clone, Default)]
+#[repr(C)]
+struct UsedElem {
+    id: u32,
+    len: u32,
+}
+
+/// one element of a descriptor chain
+#[derive(Debug, Copy, Clone)]
+pub struct QueueBuffer {
+    pub addr: u64,
+    pub len: u32,
+    /// the device writes into this buffer
+    pub writable: bool,
+}
+
+/// a split virtqueue
+pub struct Virtqueue {
+    index: u16,
+    size: u16,
+    mem: DmaBuffer,
+    avail_offset: usize,
+    used_offset: usize,
+    notify: NonNull<u16>,
+
+    free_head: u16,
+    num_free: u16,
+    avail_idx: u16,
+    last_used: u16,
+}
+
+// SAFETY: the ring memory is owned by the queue and `notify` is MMIO
+unsafe impl Send for Virtqueue {}
+unsafe impl Sync for Virtqueue {}
+
+impl Virtqueue {
+    /// `size` must be a power of two. `notify` is this queue's doorbell.
+    pub fn new(index: u16, size: u16, notify: NonNull<u16>, dma: &DmaSpace) -> Option<Self> {
+        if size == 0 || !size.is_power_of_two() {
+            return None;
+        }
+
+        let n = size as usize;
+        let avail_offset = n * size_of::<Descriptor>();
+        let used_offset = (avail_offset + 6 + 2 * n).next_multiple_of(4);
+        let total = used_offset + 6 + n * size_of::<UsedElem>();
+
+        let mem = DmaBuffer::new_in(total, dma)?;
+
+        let queue = Self {
+            index,
+            size,
+            mem,
+            avail_offset,
+            used_offset,
+            notify,
+            free_head: 0,
+            num_free: size,
+            avail_idx: 0,
+            last_used: 0,
+        };
+
+        for i in 0..size {
+            let desc = queue.desc(i);
+            unsafe { (*desc).next = (i + 1) % size };
+        }
+
+        Some(queue)
+    }
+
+    #[inline]
+    pub const fn index(&self) -> u16 {
+        self.index
+    }
+
+    #[inline]
+    pub const fn size(&self) -> u16 {
+        self.size
+    }
+
+    #[inline]
+    pub const fn num_free(&self) -> u16 {
+        self.num_free
+    }
+
+    pub fn desc_phys(&self) -> u64 {
+        self.mem.phys_addr()
+    }
+
+    pub fn avail_phys(&self) -> u64 {
+        self.mem.phys_addr() + self.avail_offset as u64
+    }
+
+    pub fn used_phys(&self) -> u64 {
+        self.mem.phys_addr() + self.used_offset as u64
+    }
+
+    fn desc(&self, i: u16) -> *mut Descriptor {
+        unsafe { (self.mem.as_ptr() as *mut Descriptor).add(i as usize) }
+    }
+
+    fn avail_field(&self, i: usize) -> *mut u16 {
+        unsafe { (self.mem.as_ptr().add(self.avail_offset) as *mut u16).add(i) }
+    }
+
+    fn used_idx(&self) -> u16 {
+        unsafe { read_volatile(self.mem.as_ptr().add(self.used_offset + 2) as *const u16) }
+    }
+
+    fn used_elem(&self, i: u16) -> UsedElem {
+        unsafe {
+            let ring = self.mem.as_ptr().add(self.used_offset + 4) as *const UsedElem;
+            read_volatile(ring.add((i % self.size) as usize))
+        }
+    }
+
+    /// suppress (or re-enable) used-buffer interrupts for this queue
+    pub fn set_interrupts(&mut self, enabled: bool) {
+        let flags = if enabled { 0 } else { AVAIL_F_NO_INTERRUPT };
+        unsafe { write_volatile(self.avail_field(0), flags) };
+    }
+
+    /// publish a descriptor chain. returns the head descriptor id, which `pop_used` hands back.
+    pub fn add(&mut self, bufs: &[QueueBuffer]) -> Option<u16> {
+        if bufs.is_empty() || bufs.len() > self.num_free as usize {
+            return None;
+        }
+
+        let head = self.free_head;
+        let mut cur = head;
+
+        for (i, buf) in bufs.iter().enumerate() {
+            let desc = self.desc(cur);
+            let next = unsafe { (*desc).next };
+
+            let mut flags = if buf.writable { DESC_F_WRITE } else { 0 };
+            if i + 1 < bufs.len() {
+                flags |= DESC_F_NEXT;
+            }
+
+            unsafe {
+                write_volatile(
+                    desc,
+                    Descriptor {
+                        addr: buf.addr,
+                        len: buf.len,
+                        flags,
+                        next,
+                    },
+                )
+            };
+
+            cur = next;
+        }
+
+        self.free_head = cur;
+        self.num_free -= bufs.len() as u16;
+
+        let slot = 2 + (self.avail_idx % self.size) as usize;
+        unsafe { write_volatile(self.avail_field(slot), head) };
+
+        // descriptors and ring entry must be visible before the index moves
+        dmb(barrier::SY);
+        self.avail_idx = self.avail_idx.wrapping_add(1);
+        unsafe { write_volatile(self.avail_field(1), self.avail_idx) };
+
+        Some(head)
+    }
+
+    /// ring the doorbell
+    pub fn kick(&self) {
+        dmb(barrier::SY);
+        unsafe { write_volatile(self.notify.as_ptr(), self.index) };
+    }
+
+    /// take one completed chain off the used ring: `(head, bytes written by the device)`.
+    pub fn pop_used(&mut self) -> Option<(u16, u32)> {
+        if self.last_used == self.used_idx() {
+            return None;
+        }
+
+        dmb(barrier::SY);
+        let elem = self.used_elem(self.last_used);
+        self.last_used = self.last_used.wrapping_add(1);
+
+        let head = elem.id as u16;
+        self.free_chain(head);
+
+        Some((head, elem.len))
+    }
+
+    fn free_chain(&mut self, head: u16) {
+        let mut cur = head;
+        let mut count = 1;
+
+        loop {
+            let desc = unsafe { read_volatile(self.desc(cur)) };
+            if desc.flags & DESC_F_NEXT == 0 {
+                break;
+            }
+            cur = desc.next;
+            count += 1;
+        }
+
+        unsafe { (*self.desc(cur)).next = self.free_head };
+        self.free_head = head;
+        self.num_free += count;
+    }
+}
@@ -0,0 +1,154 @@
+//! virtio-rng, a source for the kernel entropy pool.
+//!
+//! the device fills whatever buffers it's given with random bytes. one request is in flight at a
+//! time: the pool asks for more after it reseeds, and until it's seeded every completion asks
+//! again right away.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use alloc::{boxed::Box, format, string::String, sync::Arc};
+use klib::{
+    guard::InterruptGuard,
+    hardware::{
+        device::{Device, DeviceNode},
+        dma::DmaBuffer,
+        driver::{DriverDescriptor, DriverError},
+    },
+    random::{self, EntropySource},
+    sync::FairSpinlock,
+};
+
+use crate::{
+    VIRTIO_F_VERSION_1,
+    irq::{VirtioInterrupt, route_interrupt},
+    pci::VirtioPci,
+    queue::{QueueBuffer, Virtqueue},
+};
+
+pub static VIRTIO_RNG_DRIVER: DriverDescriptor = DriverDescriptor {
+    name: "virtio-rng",
+    // transitional and modern device IDs
+    compatible: &["pci1af4,1005", "pci1af4,1044"],
+    probe,
+};
+
+const REQUEST_QUEUE: u16 = 0;
+const QUEUE_SIZE: u16 = 8;
+
+/// bytes asked for per request
+const REQUEST_LEN: usize = 64;
+
+struct Request {
+    queue: Virtqueue,
+    buf: DmaBuffer,
+}
+
+pub struct VirtioRng {
+    transport: VirtioPci,
+    request: FairSpinlock<Request>,
+    pending: AtomicBool,
+    name: String,
+}
+
+impl EntropySource for VirtioRng {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn request(&self) {
+        if self.pending.swap(true, Ordering::AcqRel) {
+            return;
+        }
+
+        let _irq = InterruptGuard::new();
+        let mut request = self.request.lock();
+
+        let buf = QueueBuffer {
+            addr: request.buf.phys_addr(),
+            len: REQUEST_LEN as u32,
+            writable: true,
+        };
+        if request.queue.add(&[buf]).is_some() {
+            request.queue.kick();
+        } else {
+            self.pending.store(false, Ordering::Release);
+        }
+    }
+}
+
+impl VirtioInterrupt for VirtioRng {
+    fn on_interrupt(&self, _lpi: u32) {
+        let mut bytes = [0u8; REQUEST_LEN];
+        let mut len = 0;
+
+        {
+            let mut request = self.request.lock();
+            while let Some((_, used)) = request.queue.pop_used() {
+                len = (used as usize).min(REQUEST_LEN);
+                bytes[..len].copy_from_slice(&request.buf.as_slice()[..len]);
+            }
+        }
+
+        if len == 0 {
+            return;
+        }
+
+        self.pending.store(false, Ordering::Release);
+        random::add_entropy(&bytes[..len], len * 8);
+
+        if !random::is_seeded() {
+            self.request();
+        }
+    }
+}
+
+struct VirtioRngDevice(Arc<VirtioRng>);
+
+impl Device for VirtioRngDevice {
+    fn shutdown(&self) {
+        self.0.transport.reset();
+    }
+}
+
+fn probe(node: &DeviceNode) -> Result<Box<dyn Device>, DriverError> {
+    use log::*;
+
+    let mut transport = VirtioPci::new(node)?;
+    let bdf = transport.bdf;
+
+    transport.begin_init(VIRTIO_F_VERSION_1)?;
+
+    // requests are only ever answered by interrupt
+    let lpis = transport
+        .enable_msix()
+        .map_err(|e| {
+            warn!("{}: no MSI-X ({:?})", bdf, e);
+            DriverError::MissingResources
+        })?
+        .to_vec();
+    let vector = if lpis.len() > 1 { 1 } else { 0 };
+    transport.set_config_vector(0);
+
+    let queue = transport.setup_queue(REQUEST_QUEUE, QUEUE_SIZE, vector)?;
+    let buf = DmaBuffer::new_in(REQUEST_LEN, &transport.dma).ok_or(DriverError::Io)?;
+
+    let rng = Arc::new(VirtioRng {
+        transport,
+        request: FairSpinlock::new(Request { queue, buf }),
+        pending: AtomicBool::new(false),
+        name: format!("virtio-rng@{}", bdf),
+    });
+
+    for &lpi in &lpis {
+        if let Err(e) = route_interrupt(lpi, rng.clone()) {
+            warn!("{}: failed to route LPI {}: {:?}", bdf, lpi, e);
+        }
+    }
+
+    rng.transport.finish_init();
+    info!("{}: virtio-rng up", bdf);
+
+    random::register_source(rng.clone());
+
+    Ok(Box::new(VirtioRngDevice(rng)))
+}
@@ -0,0 +1,121 @@
+//! device and input contexts, see xHCI 1.2 section 6.2.
+//!
+//! a device context is a slot context followed by 31 endpoint contexts, indexed by DCI. an input
+//! context puts an input control context in front, saying which of the others a command should
+//! look at. every context is 32 or 64 bytes depending on HCCPARAMS1.CSZ, of which only the first
+//! 32 are used.
+
+use klib::hardware::{dma::DmaBuffer, iommu::DmaSpace};
+
+/// slot + 31 endpoints
+const DEVICE_CONTEXTS: usize = 32;
+
+// endpoint types
+pub const EP_BULK_OUT: u32 = 2;
+pub const EP_INTERRUPT_OUT: u32 = 3;
+pub const EP_CONTROL: u32 = 4;
+pub const EP_BULK_IN: u32 = 6;
+pub const EP_INTERRUPT_IN: u32 = 7;
+
+/// retries on transaction errors before the endpoint halts
+const CERR: u32 = 3;
+
+/// the fields of an endpoint context we set
+pub struct EndpointContext {
+    pub ty: u32,
+    pub max_packet: u16,
+    pub max_burst: u8,
+    pub interval: u8,
+    pub average_trb: u16,
+    pub max_esit_payload: u16,
+    /// from `Ring::dequeue_pointer`, cycle state included
+    pub dequeue: u64,
+}
+
+pub struct InputContext {
+    buf: DmaBuffer,
+    context_size: usize,
+}
+
+impl InputContext {
+    pub fn new(context_size: usize, dma: &DmaSpace) -> Option<Self> {
+        Some(Self {
+            buf: DmaBuffer::new_in((DEVICE_CONTEXTS + 1) * context_size, dma)?,
+            context_size,
+        })
+    }
+
+    pub fn phys_addr(&self) -> u64 {
+        self.buf.phys_addr()
+    }
+
+    /// dword `dword` of context `index`. 0 is the input control context, 1 the slot context.
+    fn set(&mut self, index: usize, dword: usize, val: u32) {
+        let offset = index * self.context_size + dword * 4;
+        self.buf.as_mut_slice()[offset..offset + 4].copy_from_slice(&val.to_le_bytes());
+    }
+
+    /// start over, with only the contexts in `add` (bit n = DCI n, bit 0 the slot) marked valid
+    pub fn reset(&mut self, add: u32) {
+        self.buf.as_mut_slice().fill(0);
+        self.set(0, 1, add);
+    }
+
+    /// `entries` is the highest DCI in use
+    pub fn set_slot(&mut self, speed: u8, port: u8, entries: u8) {
+        self.set(1, 0, (speed as u32) << 20 | (entries as u32) << 27);
+        self.set(1, 1, (port as u32) << 16);
+    }
+
+    pub fn set_endpoint(&mut self, dci: u8, ep: &EndpointContext) {
+        let index = 1 + dci as usize;
+        self.set(index, 0, (ep.interval as u32) << 16);
+        self.set(
+            index,
+            1,
+            CERR << 1 | ep.ty << 3 | (ep.max_burst as u32) << 8 | (ep.max_packet as u32) << 16,
+        );
+        self.set(index, 2, ep.dequeue as u32);
+        self.set(index, 3, (ep.dequeue >> 32) as u32);
+        self.set(
+            index,
+            4,
+            ep.average_trb as u32 | (ep.max_esit_payload as u32) << 16,
+        );
+    }
+
+    /// write the whole context back before a command reads it
+    pub fn clean(&self) {
+        self.buf.clean(0, self.buf.len());
+    }
+}
+
+/// the output context the controller keeps a device's state in
+pub struct DeviceContext {
+    buf: DmaBuffer,
+}
+
+impl DeviceContext {
+    pub fn new(context_size: usize, dma: &DmaSpace) -> Option<Self> {
+        Some(Self {
+            buf: DmaBuffer::new_in(DEVICE_CONTEXTS * context_size, dma)?,
+        })
+    }
+
+    pub fn phys_addr(&self) -> u64 {
+        self.buf.phys_addr()
+    }
+}
+
+/// just the control endpoint
+pub fn control_endpoint(max_packet: u16, dequeue: u64) -> EndpointContext {
+    EndpointContext {
+        ty: EP_CONTROL,
+        max_packet,
+        max_burst: 0,
+        interval: 0,
+        average_trb: 8,
+        max_esit_payload: 0,
+        dequeue,
+    }
+}
@@ -0,0 +1,519 @@
+//! the host controller: bring-up, the command ring, the event ring and the root hub ports.
+//!
+//! commands and transfers are synchronous. the submitter writes its TRBs, rings the doorbell and
+//! waits for the completion events, which whoever drains the event ring (the interrupt handler,
+//! or the waiter itself when it can't sleep) files by the address of the TRB they complete.
+//! interrupt endpoints register a listener instead and get their events straight from the drain.
+
+use core::{
+    fmt,
+    hint::spin_loop,
+    ptr::write_volatile,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+use aarch64_cpu::asm::barrier::{self, dmb};
+use alloc::{collections::btree_map::BTreeMap, string::String, sync::Arc, vec::Vec};
+use klib::{
+    guard::InterruptGuard,
+    hardware::{dma::DmaBuffer, iommu::DmaSpace},
+    interrupt::{
+        InterruptError,
+        gicv3::{IrqHandler, IrqTarget},
+        singleton::get_interrupt_controller,
+    },
+    scheduler::GLOBAL_SCHEDULER,
+    sync::{FairSpinlock, RwLock, SleepingMutex, WaitQueue},
+    time,
+    vm::PAGE_SIZE,
+};
+
+use crate::{
+    context::{DeviceContext, InputContext},
+    regs::*,
+    ring::{self, EventRing, Ring, Trb},
+};
+
+/// TRBs per producer ring, link included
+pub const RING_LEN: usize = 256;
+const EVENT_RING_LEN: usize = 256;
+
+/// how long to spin for anything before giving up on the controller
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum XhciError {
+    /// the controller completed the request with this code
+    Completion(u8),
+    /// the endpoint stalled
+    Stall,
+    /// the controller didn't respond in time
+    Timeout,
+    /// nothing is connected to the port
+    NotConnected,
+    /// a descriptor doesn't parse
+    BadDescriptor,
+    /// the device wants something we don't support
+    Unsupported,
+    NoMemory,
+}
+
+impl fmt::Display for XhciError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Completion(code) => write!(f, "completion code {code}"),
+            Self::Stall => write!(f, "endpoint stalled"),
+            Self::Timeout => write!(f, "timed out"),
+            Self::NotConnected => write!(f, "nothing connected"),
+            Self::BadDescriptor => write!(f, "malformed descriptor"),
+            Self::Unsupported => write!(f, "unsupported device"),
+            Self::NoMemory => write!(f, "out of DMA memory"),
+        }
+    }
+}
+
+impl core::error::Error for XhciError {}
+
+pub type Result<T> = core::result::Result<T, XhciError>;
+
+/// gets the transfer events of an endpoint instead of a waiting submitter
+pub trait TransferListener: Send + Sync {
+    fn on_transfer(&self, xhci: &Xhci, event: Trb);
+}
+
+struct Events {
+    ring: EventRing,
+    /// completions nobody has collected yet, by the address of the TRB they complete
+    completions: BTreeMap<u64, Trb>,
+    /// by slot and DCI
+    listeners: BTreeMap<(u8, u8), Arc<dyn TransferListener>>,
+}
+
+pub struct Xhci {
+    pub name: String,
+    op: Regs,
+    interrupter: Regs,
+    doorbells: Regs,
+    max_slots: u8,
+    max_ports: u8,
+    context_size: usize,
+    /// where the controller's DMA buffers go
+    pub dma: DmaSpace,
+    dcbaa: DmaBuffer,
+    /// the output context of every enabled slot
+    contexts: SleepingMutex<'static, BTreeMap<u8, DeviceContext>>,
+    _scratchpad: Vec<DmaBuffer>,
+    commands: SleepingMutex<'static, Ring>,
+    events: FairSpinlock<Events>,
+    done: WaitQueue<'static>,
+    has_interrupts: AtomicBool,
+}
+
+/// spin until `done` or the timeout
+fn spin_until(mut done: impl FnMut() -> bool) -> Result<()> {
+    let start = time::monotonic();
+    while !done() {
+        if time::monotonic() - start > TIMEOUT {
+            return Err(XhciError::Timeout);
+        }
+        spin_loop();
+    }
+    Ok(())
+}
+
+/// success and short packets are both fine, the caller looks at the residual
+fn check(event: &Trb) -> Result<()> {
+    match event.completion_code() {
+        ring::SUCCESS | ring::SHORT_PACKET => Ok(()),
+        ring::STALL => Err(XhciError::Stall),
+        code => Err(XhciError::Completion(code)),
+    }
+}
+
+impl Xhci {
+    /// reset the controller behind `base` and set up its rings, without starting it
+    pub fn new(name: String, base: Regs, dma: DmaSpace) -> Result<Self> {
+        let caplength = base.read8(CAPLENGTH) as usize;
+        let op = base.at(caplength);
+        let interrupter = base
+            .at(base.read32(RTSOFF) as usize & !0x1F)
+            .at(INTERRUPTER_0);
+        let doorbells = base.at(base.read32(DBOFF) as usize & !0x3);
+
+        let hcs1 = base.read32(HCSPARAMS1);
+        let hcs2 = base.read32(HCSPARAMS2);
+        let hcc1 = base.read32(HCCPARAMS1);
+        let max_slots = hcs1 as u8;
+        let max_ports = (hcs1 >> 24) as u8;
+        let scratchpads = (((hcs2 >> 21) & 0x1F) << 5 | (hcs2 >> 27)) as usize;
+        let context_size = if hcc1 & HCC_CSZ != 0 { 64 } else { 32 };
+
+        log::debug!(
+            "{}: xHCI {:x}, {} slots, {} ports, {} scratchpad buffers",
+            name,
+            base.read16(HCIVERSION),
+            max_slots,
+            max_ports,
+            scratchpads
+        );
+
+        spin_until(|| op.read32(USBSTS) & STS_CNR == 0)?;
+
+        if op.read32(USBSTS) & STS_HCH == 0 {
+            op.write32(USBCMD, op.read32(USBCMD) & !CMD_RUN);
+            spin_until(|| op.read32(USBSTS) & STS_HCH != 0)?;
+        }
+
+        op.write32(USBCMD, CMD_HCRST);
+        spin_until(|| op.read32(USBCMD) & CMD_HCRST == 0 && op.read32(USBSTS) & STS_CNR == 0)?;
+
+        op.write32(CONFIG, max_slots as u32);
+
+        let mut dcbaa =
+            DmaBuffer::new_in((max_slots as usize + 1) * 8, &dma).ok_or(XhciError::NoMemory)?;
+
+        // our pages are at least as big and as aligned as any page size the controller can ask for
+        let mut scratchpad = Vec::new();
+        if scratchpads > 0 {
+            let mut array = DmaBuffer::new_in(scratchpads * 8, &dma).ok_or(XhciError::NoMemory)?;
+            for i in 0..scratchpads {
+                let page = DmaBuffer::new_in(PAGE_SIZE, &dma).ok_or(XhciError::NoMemory)?;
+                array.as_mut_slice()[i * 8..i * 8 + 8]
+                    .copy_from_slice(&page.phys_addr().to_le_bytes());
+                scratchpad.push(page);
+            }
+            array.clean(0, scratchpads * 8);
+
+            dcbaa.as_mut_slice()[..8].copy_from_slice(&array.phys_addr().to_le_bytes());
+            scratchpad.push(array);
+        }
+        dcbaa.clean(0, dcbaa.len());
+        op.write64(DCBAAP, dcbaa.phys_addr());
+
+        let commands = Ring::new(RING_LEN, &dma).ok_or(XhciError::NoMemory)?;
+        op.write64(CRCR, commands.dequeue_pointer());
+
+        let events = EventRing::new(EVENT_RING_LEN, &dma).ok_or(XhciError::NoMemory)?;
+        interrupter.write32(ERSTSZ, 1);
+        interrupter.write64(ERDP, events.dequeue_pointer());
+        interrupter.write64(ERSTBA, events.table_addr());
+        // no moderation, keystrokes are rare and disk I/O is synchronous anyway
+        interrupter.write32(IMOD, 0);
+
+        Ok(Self {
+            name,
+            op,
+            interrupter,
+            doorbells,
+            max_slots,
+            max_ports,
+            context_size,
+            dma,
+            dcbaa,
+            contexts: SleepingMutex::new(BTreeMap::new()),
+            _scratchpad: scratchpad,
+            commands: SleepingMutex::new(commands),
+            events: FairSpinlock::new(Events {
+                ring: events,
+                completions: BTreeMap::new(),
+                listeners: BTreeMap::new(),
+            }),
+            done: WaitQueue::new(),
+            has_interrupts: AtomicBool::new(false),
+        })
+    }
+
+    /// let interrupter 0 interrupt, once its vector is routed to us
+    pub fn enable_interrupts(&self) {
+        self.interrupter.write32(IMAN, IMAN_IE | IMAN_IP);
+        self.op.write32(USBCMD, self.op.read32(USBCMD) | CMD_INTE);
+        self.has_interrupts.store(true, Ordering::Release);
+    }
+
+    pub fn start(&self) -> Result<()> {
+        self.op.write32(USBCMD, self.op.read32(USBCMD) | CMD_RUN);
+        spin_until(|| self.op.read32(USBSTS) & STS_HCH == 0)
+    }
+
+    pub fn halt(&self) {
+        self.op
+            .write32(USBCMD, self.op.read32(USBCMD) & !(CMD_RUN | CMD_INTE));
+        _ = spin_until(|| self.op.read32(USBSTS) & STS_HCH != 0);
+    }
+
+    pub fn max_ports(&self) -> u8 {
+        self.max_ports
+    }
+
+    pub fn context_size(&self) -> usize {
+        self.context_size
+    }
+
+    /// `target` is the DCI for device slots, 0 for the command ring
+    pub fn ring_doorbell(&self, slot: u8, target: u8) {
+        dmb(barrier::SY);
+        self.doorbells.write32(slot as usize * 4, target as u32);
+    }
+
+    /// drain the event ring
+    pub fn poll(&self) {
+        let mut deliveries = Vec::new();
+
+        {
+            let _irq = InterruptGuard::new();
+            let mut events = self.events.lock();
+            let mut drained = false;
+
+            while let Some(event) = events.ring.pop() {
+                drained = true;
+
+                match event.ty() {
+                    ring::COMMAND_COMPLETION => {
+                        events.completions.insert(event.parameter, event);
+                    }
+                    ring::TRANSFER_EVENT => {
+                        let key = (event.slot_id(), event.endpoint_id());
+                        match events.listeners.get(&key) {
+                            Some(listener) => deliveries.push((listener.clone(), event)),
+                            None => _ = events.completions.insert(event.parameter, event),
+                        }
+                    }
+                    ring::PORT_STATUS_CHANGE => {
+                        // ports are only looked at during probe
+                        log::debug!(
+                            "{}: port {} changed, hotplug isn't supported",
+                            self.name,
+                            event.parameter >> 24
+                        );
+                    }
+                    ty => log::trace!("{}: ignoring event type {}", self.name, ty),
+                }
+            }
+
+            if drained {
+                let dequeue = events.ring.dequeue_pointer();
+                self.interrupter.write64(ERDP, dequeue | ERDP_EHB);
+            }
+        }
+
+        for (listener, event) in deliveries {
+            listener.on_transfer(self, event);
+        }
+    }
+
+    fn take(&self, trbs: &[u64]) -> Option<Vec<Trb>> {
+        let _irq = InterruptGuard::new();
+        let mut events = self.events.lock();
+
+        let finished = events.completions.contains_key(trbs.last()?);
+        let failed = trbs
+            .iter()
+            .filter_map(|addr| events.completions.get(addr))
+            .any(|event| check(event).is_err());
+        if !finished && !failed {
+            return None;
+        }
+
+        Some(
+            trbs.iter()
+                .filter_map(|addr| events.completions.remove(addr))
+                .collect(),
+        )
+    }
+
+    fn forget(&self, trbs: &[u64]) {
+        let _irq = InterruptGuard::new();
+        let mut events = self.events.lock();
+        for addr in trbs {
+            events.completions.remove(addr);
+        }
+    }
+
+    /// wait for the TD made of `trbs` to complete: the last one, or an earlier one failing.
+    /// returns the events that arrived for them, in order.
+    pub fn wait(&self, trbs: &[u64]) -> Result<Vec<Trb>> {
+        let start = time::monotonic();
+
+        loop {
+            let seen = self.done.generation();
+            let sleep = self.has_interrupts.load(Ordering::Acquire)
+                && GLOBAL_SCHEDULER.current_thread().is_some();
+
+            if !sleep {
+                self.poll();
+            }
+
+            if let Some(events) = self.take(trbs) {
+                for event in &events {
+                    check(event)?;
+                }
+                return Ok(events);
+            }
+
+            if time::monotonic() - start > TIMEOUT {
+                self.forget(trbs);
+                return Err(XhciError::Timeout);
+            }
+
+            if sleep {
+                self.done
+                    .wait_until(&GLOBAL_SCHEDULER, seen, start + TIMEOUT);
+            } else {
+                spin_loop();
+            }
+        }
+    }
+
+    pub fn add_listener(&self, slot: u8, dci: u8, listener: Arc<dyn TransferListener>) {
+        let _irq = InterruptGuard::new();
+        self.events.lock().listeners.insert((slot, dci), listener);
+    }
+
+    /// run a command and return its completion event
+    pub fn command(&self, trb: Trb) -> Result<Trb> {
+        let addr = self.commands.lock(&GLOBAL_SCHEDULER).push(trb);
+        self.ring_doorbell(0, 0);
+
+        self.wait(&[addr])?.pop().ok_or(XhciError::Timeout)
+    }
+
+    pub fn enable_slot(&self) -> Result<u8> {
+        let event = self.command(Trb::new(ring::ENABLE_SLOT, 0, 0, 0))?;
+        match event.slot_id() {
+            slot if slot == 0 || slot > self.max_slots => Err(XhciError::Completion(0)),
+            slot => Ok(slot),
+        }
+    }
+
+    pub fn disable_slot(&self, slot: u8) -> Result<()> {
+        self.command(Trb::new(ring::DISABLE_SLOT, 0, 0, (slot as u32) << 24))?;
+        self.write_dcbaa(slot, 0);
+        self.contexts.lock(&GLOBAL_SCHEDULER).remove(&slot);
+        Ok(())
+    }
+
+    /// give `slot` an output context, kept until the slot is disabled
+    pub fn set_device_context(&self, slot: u8) -> Result<()> {
+        let context =
+            DeviceContext::new(self.context_size, &self.dma).ok_or(XhciError::NoMemory)?;
+        self.write_dcbaa(slot, context.phys_addr());
+        self.contexts.lock(&GLOBAL_SCHEDULER).insert(slot, context);
+        Ok(())
+    }
+
+    fn write_dcbaa(&self, slot: u8, phys: u64) {
+        let offset = slot as usize * 8;
+        unsafe { write_volatile(self.dcbaa.as_ptr().add(offset) as *mut u64, phys) };
+        self.dcbaa.clean(offset, 8);
+    }
+
+    fn context_command(&self, ty: u8, slot: u8, input: &InputContext) -> Result<()> {
+        input.clean();
+        self.command(Trb::new(ty, input.phys_addr(), 0, (slot as u32) << 24))
+            .map(|_| ())
+    }
+
+    pub fn address_device(&self, slot: u8, input: &InputContext) -> Result<()> {
+        self.context_command(ring::ADDRESS_DEVICE, slot, input)
+    }
+
+    pub fn configure_endpoint(&self, slot: u8, input: &InputContext) -> Result<()> {
+        self.context_command(ring::CONFIGURE_ENDPOINT, slot, input)
+    }
+
+    pub fn evaluate_context(&self, slot: u8, input: &InputContext) -> Result<()> {
+        self.context_command(ring::EVALUATE_CONTEXT, slot, input)
+    }
+
+    /// take a halted endpoint back to stopped and move it on to `dequeue`
+    pub fn reset_endpoint(&self, slot: u8, dci: u8, dequeue: u64) -> Result<()> {
+        let target = (slot as u32) << 24 | (dci as u32) << 16;
+        self.command(Trb::new(ring::RESET_ENDPOINT, 0, 0, target))?;
+        self.command(Trb::new(ring::SET_TR_DEQUEUE, dequeue, 0, target))?;
+        Ok(())
+    }
+
+    fn portsc(&self, port: u8) -> u32 {
+        self.op
+            .read32(PORTSC_BASE + (port as usize - 1) * PORTSC_STRIDE)
+    }
+
+    fn write_portsc(&self, port: u8, val: u32) {
+        self.op
+            .write32(PORTSC_BASE + (port as usize - 1) * PORTSC_STRIDE, val);
+    }
+
+    pub fn port_connected(&self, port: u8) -> bool {
+        self.portsc(port) & PORT_CCS != 0
+    }
+
+    /// get `port` enabled and return the speed of the device on it
+    pub fn reset_port(&self, port: u8) -> Result<u8> {
+        let sc = self.portsc(port);
+        if sc & PORT_CCS == 0 {
+            return Err(XhciError::NotConnected);
+        }
+
+        // USB3 ports enable themselves once the link is up, USB2 ones need a reset
+        if sc & PORT_PED == 0 {
+            self.write_portsc(port, (sc & PORT_PP) | PORT_PR);
+            spin_until(|| {
+                let sc = self.portsc(port);
+                sc & PORT_PR == 0 && sc & PORT_PED != 0
+            })?;
+        }
+
+        // acknowledge the changes so new ones get reported
+        let sc = self.portsc(port);
+        self.write_portsc(port, (sc & PORT_PP) | (sc & PORT_CHANGE));
+
+        Ok(((sc >> PORT_SPEED_SHIFT) & PORT_SPEED_MASK) as u8)
+    }
+
+    fn on_interrupt(&self) {
+        // both are write-1-to-clear
+        self.interrupter.write32(IMAN, IMAN_IE | IMAN_IP);
+        self.op.write32(USBSTS, STS_EINT);
+
+        self.poll();
+        self.done.wake_all(&GLOBAL_SCHEDULER);
+    }
+}
+
+static ROUTES: RwLock<BTreeMap<u32, Arc<Xhci>>> = RwLock::new(BTreeMap::new());
+
+fn dispatch(irq: u32) -> core::result::Result<(), InterruptError> {
+    let xhci = ROUTES
+        .read()
+        .get(&irq)
+        .cloned()
+        .ok_or(InterruptError::HandlerNotFound)?;
+
+    xhci.on_interrupt();
+    Ok(())
+}
+
+/// deliver `irq` to `xhci` and unmask it. `intx` for a shared SPI rather than an LPI.
+pub fn route_interrupt(
+    irq: u32,
+    intx: bool,
+    xhci: Arc<Xhci>,
+) -> core::result::Result<(), InterruptError> {
+    {
+        let _irq = InterruptGuard::new();
+        ROUTES.write().insert(irq, xhci);
+    }
+
+    let target = if intx {
+        IrqTarget::Distributor
+    } else {
+        IrqTarget::Redistributor
+    };
+
+    let ic = get_interrupt_controller();
+    let handler = IrqHandler::new(target, dispatch).ok_or(InterruptError::NotSupported)?;
+
+    ic.register_handler(irq, handler)?;
+    ic.enable_interrupt(irq)
+}
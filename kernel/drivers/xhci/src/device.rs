@@ -0,0 +1,358 @@
+//! one device on a root hub port: its slot, its default control endpoint and the endpoints its
+//! class driver configures.
+
+use alloc::vec::Vec;
+use klib::{
+    hardware::dma::DmaBuffer, scheduler::GLOBAL_SCHEDULER, sync::SleepingMutex, vm::PAGE_SIZE,
+};
+
+use crate::{
+    context::{
+        EP_BULK_IN, EP_BULK_OUT, EP_INTERRUPT_IN, EP_INTERRUPT_OUT, EndpointContext, InputContext,
+        control_endpoint,
+    },
+    controller::{RING_LEN, Result, Xhci, XhciError},
+    regs::{SPEED_FULL, SPEED_HIGH, SPEED_LOW, SPEED_SUPER},
+    ring::{self, Ring, Trb},
+    usb::{
+        CLEAR_FEATURE, CONFIGURATION_DESCRIPTOR_LEN, Configuration, DESC_CONFIGURATION,
+        DESC_DEVICE, DEVICE_DESCRIPTOR_LEN, DeviceDescriptor, ENDPOINT_HALT, EndpointDescriptor,
+        REQ_ENDPOINT, SET_CONFIGURATION, SetupPacket, TransferType,
+    },
+};
+
+/// DCI of the default control endpoint
+const EP0: u8 = 1;
+
+/// the recommended average TRB lengths, see xHCI 1.2 section 4.14.1.1
+const AVERAGE_TRB_INTERRUPT: u16 = 1024;
+const AVERAGE_TRB_BULK: u16 = 3072;
+
+pub fn speed_name(speed: u8) -> &'static str {
+    match speed {
+        SPEED_FULL => "full speed",
+        SPEED_LOW => "low speed",
+        SPEED_HIGH => "high speed",
+        SPEED_SUPER => "SuperSpeed",
+        _ => "SuperSpeedPlus",
+    }
+}
+
+/// the control endpoint's max packet size before the device descriptor says otherwise
+fn default_max_packet(speed: u8) -> u16 {
+    match speed {
+        SPEED_FULL | SPEED_LOW => 8,
+        SPEED_HIGH => 64,
+        _ => 512,
+    }
+}
+
+/// a bulk or interrupt endpoint and its transfer ring
+pub struct Endpoint {
+    pub dci: u8,
+    pub address: u8,
+    ring: Ring,
+}
+
+impl Endpoint {
+    /// queue one transfer of `len` bytes at `phys` without waiting for it. returns the TRB's
+    /// address.
+    pub fn queue(&mut self, xhci: &Xhci, slot: u8, phys: u64, len: usize) -> u64 {
+        let addr = self.ring.push(Trb::new(
+            ring::NORMAL,
+            phys,
+            len as u32,
+            ring::ISP | ring::IOC,
+        ));
+        xhci.ring_doorbell(slot, self.dci);
+        addr
+    }
+
+    /// move `len` bytes at `phys`, in whichever direction the endpoint goes. returns how many
+    /// actually moved.
+    pub fn transfer(&mut self, xhci: &Xhci, slot: u8, phys: u64, len: usize) -> Result<usize> {
+        let addr = self.queue(xhci, slot, phys, len);
+        let event = xhci.wait(&[addr])?.pop().ok_or(XhciError::Timeout)?;
+        Ok(len.saturating_sub(event.residual() as usize))
+    }
+}
+
+/// the default control endpoint, with a buffer for data stages
+struct Control {
+    ring: Ring,
+    buf: DmaBuffer,
+}
+
+impl Control {
+    /// run a control transfer whose data stage, if any, goes through `buf` in the direction the
+    /// setup packet says. returns how many bytes the data stage moved.
+    fn transfer(&mut self, xhci: &Xhci, slot: u8, setup: SetupPacket) -> Result<usize> {
+        let len = setup.length as usize;
+        let data_in = setup.request_type & crate::usb::REQ_IN != 0;
+        if len > self.buf.len() {
+            return Err(XhciError::Unsupported);
+        }
+
+        let trt = match (len, data_in) {
+            (0, _) => 0,
+            (_, true) => ring::TRT_IN,
+            (_, false) => ring::TRT_OUT,
+        };
+        let dir = if data_in { ring::DIR_IN } else { 0 };
+
+        let mut trbs = Vec::with_capacity(3);
+        trbs.push(self.ring.push(Trb::new(
+            ring::SETUP_STAGE,
+            setup.to_u64(),
+            8,
+            ring::IDT | trt,
+        )));
+
+        let data = (len > 0).then(|| {
+            self.buf.clean(0, len);
+            self.ring.push(Trb::new(
+                ring::DATA_STAGE,
+                self.buf.phys_addr(),
+                len as u32,
+                ring::ISP | dir,
+            ))
+        });
+        trbs.extend(data);
+
+        // the status stage goes the other way, and in when there's no data
+        let status_dir = if len == 0 || !data_in {
+            ring::DIR_IN
+        } else {
+            0
+        };
+        trbs.push(
+            self.ring
+                .push(Trb::new(ring::STATUS_STAGE, 0, 0, ring::IOC | status_dir)),
+        );
+
+        xhci.ring_doorbell(slot, EP0);
+
+        let events = match xhci.wait(&trbs) {
+            Ok(events) => events,
+            Err(XhciError::Stall) => {
+                // a stalled control endpoint only needs moving past the failed transfer
+                xhci.reset_endpoint(slot, EP0, self.ring.dequeue_pointer())?;
+                return Err(XhciError::Stall);
+            }
+            Err(e) => return Err(e),
+        };
+
+        let residual = events
+            .iter()
+            .find(|e| Some(e.parameter) == data && e.completion_code() == ring::SHORT_PACKET)
+            .map_or(0, |e| e.residual() as usize);
+
+        Ok(len.saturating_sub(residual))
+    }
+}
+
+pub struct UsbDevice {
+    pub slot: u8,
+    pub port: u8,
+    pub speed: u8,
+    pub descriptor: DeviceDescriptor,
+    input: InputContext,
+    control: SleepingMutex<'static, Control>,
+}
+
+impl UsbDevice {
+    /// reset `port`, give what's on it an address and read its device descriptor. the slot stays
+    /// enabled until someone disables it.
+    pub fn enumerate(xhci: &Xhci, port: u8) -> Result<Self> {
+        let speed = xhci.reset_port(port)?;
+        let slot = xhci.enable_slot()?;
+
+        Self::address(xhci, slot, port, speed).inspect_err(|_| {
+            _ = xhci.disable_slot(slot);
+        })
+    }
+
+    fn address(xhci: &Xhci, slot: u8, port: u8, speed: u8) -> Result<Self> {
+        let mut input =
+            InputContext::new(xhci.context_size(), &xhci.dma).ok_or(XhciError::NoMemory)?;
+        let mut control = Control {
+            ring: Ring::new(RING_LEN, &xhci.dma).ok_or(XhciError::NoMemory)?,
+            buf: DmaBuffer::new_in(PAGE_SIZE, &xhci.dma).ok_or(XhciError::NoMemory)?,
+        };
+
+        xhci.set_device_context(slot)?;
+
+        let max_packet = default_max_packet(speed);
+        input.reset(1 << 0 | 1 << EP0);
+        input.set_slot(speed, port, EP0);
+        input.set_endpoint(
+            EP0,
+            &control_endpoint(max_packet, control.ring.dequeue_pointer()),
+        );
+        xhci.address_device(slot, &input)?;
+
+        // the first 8 bytes say how big control packets really are
+        control.transfer(xhci, slot, SetupPacket::get_descriptor(DESC_DEVICE, 0, 8))?;
+        let partial = DeviceDescriptor::parse(&control.buf.as_slice()[..8])
+            .ok_or(XhciError::BadDescriptor)?;
+
+        let actual = if speed >= SPEED_SUPER {
+            1u16 << partial.max_packet0.min(15)
+        } else {
+            partial.max_packet0 as u16
+        };
+        if actual != max_packet && actual != 0 {
+            input.reset(1 << EP0);
+            input.set_endpoint(
+                EP0,
+                &control_endpoint(actual, control.ring.dequeue_pointer()),
+            );
+            xhci.evaluate_context(slot, &input)?;
+        }
+
+        let len = control.transfer(
+            xhci,
+            slot,
+            SetupPacket::get_descriptor(DESC_DEVICE, 0, DEVICE_DESCRIPTOR_LEN as u16),
+        )?;
+        let descriptor = DeviceDescriptor::parse(&control.buf.as_slice()[..len])
+            .ok_or(XhciError::BadDescriptor)?;
+
+        Ok(Self {
+            slot,
+            port,
+            speed,
+            descriptor,
+            input,
+            control: SleepingMutex::new(control),
+        })
+    }
+
+    /// run a control transfer that reads into `buf`. returns how much was read.
+    pub fn control_in(&self, xhci: &Xhci, setup: SetupPacket, buf: &mut [u8]) -> Result<usize> {
+        let mut control = self.control.lock(&GLOBAL_SCHEDULER);
+        let len = control.transfer(xhci, self.slot, setup)?.min(buf.len());
+        buf[..len].copy_from_slice(&control.buf.as_slice()[..len]);
+        Ok(len)
+    }
+
+    /// run a control transfer that writes `data`
+    pub fn control_out(&self, xhci: &Xhci, setup: SetupPacket, data: &[u8]) -> Result<()> {
+        let mut control = self.control.lock(&GLOBAL_SCHEDULER);
+        let len = data.len().min(control.buf.len());
+        control.buf.as_mut_slice()[..len].copy_from_slice(&data[..len]);
+        control.transfer(xhci, self.slot, setup).map(|_| ())
+    }
+
+    /// read and parse configuration `index`
+    pub fn configuration(&self, xhci: &Xhci, index: u8) -> Result<Configuration> {
+        let mut header = [0u8; CONFIGURATION_DESCRIPTOR_LEN];
+        let setup = SetupPacket::get_descriptor(
+            DESC_CONFIGURATION,
+            index,
+            CONFIGURATION_DESCRIPTOR_LEN as u16,
+        );
+        self.control_in(xhci, setup, &mut header)?;
+
+        let total = Configuration::total_length(&header).ok_or(XhciError::BadDescriptor)?;
+        let mut full = alloc::vec![0u8; (total as usize).min(PAGE_SIZE)];
+        let setup = SetupPacket::get_descriptor(DESC_CONFIGURATION, index, full.len() as u16);
+        let len = self.control_in(xhci, setup, &mut full)?;
+
+        Configuration::parse(&full[..len]).ok_or(XhciError::BadDescriptor)
+    }
+
+    pub fn set_configuration(&self, xhci: &Xhci, value: u8) -> Result<()> {
+        let setup = SetupPacket {
+            request_type: 0,
+            request: SET_CONFIGURATION,
+            value: value as u16,
+            index: 0,
+            length: 0,
+        };
+        self.control_out(xhci, setup, &[])
+    }
+
+    /// tell the controller about `endpoints` and give each one a transfer ring
+    pub fn configure_endpoints(
+        &mut self,
+        xhci: &Xhci,
+        endpoints: &[EndpointDescriptor],
+    ) -> Result<Vec<Endpoint>> {
+        let mut configured = Vec::with_capacity(endpoints.len());
+        let mut add = 1 << 0;
+        let mut entries = EP0;
+
+        for desc in endpoints {
+            let ring = Ring::new(RING_LEN, &xhci.dma).ok_or(XhciError::NoMemory)?;
+            add |= 1 << desc.dci();
+            entries = entries.max(desc.dci());
+            configured.push(Endpoint {
+                dci: desc.dci(),
+                address: desc.address,
+                ring,
+            });
+        }
+
+        self.input.reset(add);
+        self.input.set_slot(self.speed, self.port, entries);
+        for (desc, endpoint) in endpoints.iter().zip(&configured) {
+            let context = self.endpoint_context(desc, endpoint.ring.dequeue_pointer())?;
+            self.input.set_endpoint(endpoint.dci, &context);
+        }
+        xhci.configure_endpoint(self.slot, &self.input)?;
+
+        Ok(configured)
+    }
+
+    fn endpoint_context(&self, desc: &EndpointDescriptor, dequeue: u64) -> Result<EndpointContext> {
+        let kind = desc.transfer_type();
+        let ty = match (kind, desc.is_in()) {
+            (TransferType::Bulk, false) => EP_BULK_OUT,
+            (TransferType::Bulk, true) => EP_BULK_IN,
+            (TransferType::Interrupt, false) => EP_INTERRUPT_OUT,
+            (TransferType::Interrupt, true) => EP_INTERRUPT_IN,
+            _ => return Err(XhciError::Unsupported),
+        };
+        let max_packet = desc.packet_size();
+
+        let (interval, average_trb, max_esit_payload) = if kind == TransferType::Interrupt {
+            // high speed and up count bInterval as an exponent of 125 us, slower devices in
+            // frames, which the controller wants rounded down to a power of two microframes
+            let interval = if self.speed >= SPEED_HIGH {
+                desc.interval.clamp(1, 16) - 1
+            } else {
+                let microframes = desc.interval.max(1) as u32 * 8;
+                (u32::BITS - 1 - microframes.leading_zeros()) as u8
+            };
+            let payload = max_packet * (desc.max_burst as u16 + 1);
+            (interval, AVERAGE_TRB_INTERRUPT.min(payload), payload)
+        } else {
+            (0, AVERAGE_TRB_BULK, 0)
+        };
+
+        Ok(EndpointContext {
+            ty,
+            max_packet,
+            max_burst: desc.max_burst,
+            interval,
+            average_trb,
+            max_esit_payload,
+            dequeue,
+        })
+    }
+
+    /// recover `endpoint` after it stalled
+    pub fn clear_halt(&self, xhci: &Xhci, endpoint: &mut Endpoint) -> Result<()> {
+        xhci.reset_endpoint(self.slot, endpoint.dci, endpoint.ring.dequeue_pointer())?;
+
+        let setup = SetupPacket {
+            request_type: REQ_ENDPOINT,
+            request: CLEAR_FEATURE,
+            value: ENDPOINT_HALT,
+            index: endpoint.address as u16,
+            length: 0,
+        };
+        self.control_out(xhci, setup, &[])
+    }
+}
@@ -0,0 +1,199 @@
+//! HID boot protocol keyboards, typing into the console terminal.
+//!
+//! a boot report is 8 bytes: modifier bits, a reserved byte, then up to six pressed keys as HID
+//! usage codes. each report is compared with the last one to find what was pressed and released,
+//! and those go through the keymap like any other keyboard's keys. there's no autorepeat.
+
+use alloc::{format, string::String, sync::Arc, vec::Vec};
+use klib::{
+    guard::InterruptGuard,
+    hardware::dma::DmaBuffer,
+    sync::FairSpinlock,
+    tty::{self, keyboard::Keyboard},
+};
+
+use crate::{
+    controller::{Result, TransferListener, Xhci, XhciError},
+    device::{Endpoint, UsbDevice, speed_name},
+    ring::{self, Trb},
+    usb::{Interface, REQ_CLASS, REQ_INTERFACE, TransferType},
+};
+
+pub const CLASS_HID: u8 = 3;
+pub const SUBCLASS_BOOT: u8 = 1;
+pub const PROTOCOL_KEYBOARD: u8 = 1;
+
+// class requests
+const SET_IDLE: u8 = 0x0A;
+const SET_PROTOCOL: u8 = 0x0B;
+const PROTOCOL_BOOT: u16 = 0;
+
+const REPORT_LEN: usize = 8;
+
+/// every key slot reads this when more keys are down than a report can carry
+const ERROR_ROLL_OVER: u8 = 0x01;
+
+/// Linux keycodes for HID keyboard usages 0x00-0x6F
+#[rustfmt::skip]
+const USAGE_TO_KEY: [u8; 112] = [
+      0,   0,   0,   0,  30,  48,  46,  32,  18,  33,  34,  35,  23,  36,  37,  38,
+     50,  49,  24,  25,  16,  19,  31,  20,  22,  47,  17,  45,  21,  44,   2,   3,
+      4,   5,   6,   7,   8,   9,  10,  11,  28,   1,  14,  15,  57,  12,  13,  26,
+     27,  43,  43,  39,  40,  41,  51,  52,  53,  58,  59,  60,  61,  62,  63,  64,
+     65,  66,  67,  68,  87,  88,  99,  70, 119, 110, 102, 104, 111, 107, 109, 106,
+    105, 108, 103,  69,  98,  55,  74,  78,  96,  79,  80,  81,  75,  76,  77,  71,
+     72,  73,  82,  83,  86, 127, 116, 117, 183, 184, 185, 186, 187, 188, 189, 190,
+];
+
+/// Linux keycodes for the modifier bits, left ctrl first
+const MODIFIER_KEYS: [u16; 8] = [29, 42, 56, 125, 97, 54, 100, 126];
+
+struct State {
+    endpoint: Endpoint,
+    report: DmaBuffer,
+    last: [u8; REPORT_LEN],
+    keyboard: Keyboard,
+}
+
+pub struct HidKeyboard {
+    name: String,
+    device: UsbDevice,
+    state: FairSpinlock<State>,
+}
+
+impl HidKeyboard {
+    /// the boot keyboard interface among `interfaces`, if there is one
+    pub fn find(interfaces: &[Interface]) -> Option<&Interface> {
+        interfaces.iter().find(|i| {
+            i.alternate == 0
+                && i.class == CLASS_HID
+                && i.subclass == SUBCLASS_BOOT
+                && i.protocol == PROTOCOL_KEYBOARD
+                && i.endpoint(TransferType::Interrupt, true).is_some()
+        })
+    }
+
+    /// switch `interface` to boot reports and start polling it
+    pub fn attach(xhci: &Xhci, mut device: UsbDevice, interface: &Interface) -> Result<()> {
+        use log::*;
+
+        let desc = *interface
+            .endpoint(TransferType::Interrupt, true)
+            .ok_or(XhciError::Unsupported)?;
+        let endpoint = device
+            .configure_endpoints(xhci, &[desc])?
+            .pop()
+            .ok_or(XhciError::Unsupported)?;
+        let dci = endpoint.dci;
+
+        let class_request = |request, value| crate::usb::SetupPacket {
+            request_type: REQ_CLASS | REQ_INTERFACE,
+            request,
+            value,
+            index: interface.number as u16,
+            length: 0,
+        };
+        device.control_out(xhci, class_request(SET_PROTOCOL, PROTOCOL_BOOT), &[])?;
+        // reports only on change. some keyboards don't take it, which is fine.
+        if let Err(e) = device.control_out(xhci, class_request(SET_IDLE, 0), &[]) {
+            debug!("{}: SET_IDLE: {}", xhci.name, e);
+        }
+
+        let keyboard = Arc::new(Self {
+            name: format!("{}-{}", xhci.name, device.port),
+            state: FairSpinlock::new(State {
+                endpoint,
+                report: DmaBuffer::new_in(REPORT_LEN, &xhci.dma).ok_or(XhciError::NoMemory)?,
+                last: [0; REPORT_LEN],
+                keyboard: Keyboard::new(),
+            }),
+            device,
+        });
+
+        info!(
+            "{}: {:04x}:{:04x} boot keyboard, {} ({})",
+            keyboard.name,
+            keyboard.device.descriptor.vendor,
+            keyboard.device.descriptor.product,
+            speed_name(keyboard.device.speed),
+            match tty::console() {
+                Some(console) => format!("typing into {}", console.name()),
+                None => "no console yet".into(),
+            },
+        );
+
+        xhci.add_listener(keyboard.device.slot, dci, keyboard.clone());
+        keyboard.poll(xhci);
+
+        Ok(())
+    }
+
+    /// ask for the next report
+    fn poll(&self, xhci: &Xhci) {
+        let _irq = InterruptGuard::new();
+        let mut state = self.state.lock();
+        let State {
+            endpoint, report, ..
+        } = &mut *state;
+        endpoint.queue(xhci, self.device.slot, report.phys_addr(), REPORT_LEN);
+    }
+
+    fn report(state: &mut State, report: [u8; REPORT_LEN], typed: &mut Vec<u8>) {
+        if report[2..].contains(&ERROR_ROLL_OVER) {
+            return;
+        }
+
+        let last = state.last;
+        let changed = last[0] ^ report[0];
+        for (bit, &key) in MODIFIER_KEYS.iter().enumerate() {
+            if changed & (1 << bit) != 0 {
+                state.keyboard.key(key, report[0] & (1 << bit) != 0, typed);
+            }
+        }
+
+        let keycode = |usage: u8| USAGE_TO_KEY.get(usage as usize).copied().unwrap_or(0);
+        for &usage in &last[2..] {
+            if usage != 0 && !report[2..].contains(&usage) {
+                state.keyboard.key(keycode(usage) as u16, false, typed);
+            }
+        }
+        for &usage in &report[2..] {
+            if usage != 0 && !last[2..].contains(&usage) {
+                state.keyboard.key(keycode(usage) as u16, true, typed);
+            }
+        }
+
+        state.last = report;
+    }
+}
+
+impl TransferListener for HidKeyboard {
+    fn on_transfer(&self, xhci: &Xhci, event: Trb) {
+        if !matches!(event.completion_code(), ring::SUCCESS | ring::SHORT_PACKET) {
+            // the endpoint is halted now, and we stop polling it
+            log::warn!(
+                "{}: interrupt transfer failed with code {}",
+                self.name,
+                event.completion_code()
+            );
+            return;
+        }
+
+        let mut typed = Vec::new();
+        {
+            let _irq = InterruptGuard::new();
+            let mut state = self.state.lock();
+            let mut report = [0u8; REPORT_LEN];
+            report.copy_from_slice(&state.report.as_slice()[..REPORT_LEN]);
+            Self::report(&mut state, report, &mut typed);
+        }
+        self.poll(xhci);
+
+        if !typed.is_empty() {
+            match tty::console() {
+                Some(console) => console.receive(&typed),
+                None => log::trace!("{}: no console, dropping input", self.name),
+            }
+        }
+    }
+}
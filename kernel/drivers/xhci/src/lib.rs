@@ -0,0 +1,190 @@
+//! xHCI USB host controllers, and the USB devices on their root ports.
+//!
+//! devices are enumerated once, at probe. boot protocol keyboards type into the console, and
+//! SCSI bulk-only mass storage devices are registered as block devices. anything else gets an
+//! address and is otherwise left alone.
+
+#![no_std]
+
+extern crate alloc;
+
+mod context;
+mod controller;
+mod device;
+mod hid;
+mod regs;
+mod ring;
+mod storage;
+mod usb;
+
+use alloc::{boxed::Box, format, sync::Arc};
+use klib::hardware::{
+    device::{Device, DeviceNode},
+    driver::{DriverDescriptor, DriverError},
+    mmio::map_mmio,
+};
+use mars_pcie_driver::{
+    address::Bdf,
+    bar::{BarType, bar_address, probe_bars},
+    ecam::{Ecam, get_segment},
+    interrupt::{VectorKind, alloc_vectors},
+};
+
+use controller::{Xhci, route_interrupt};
+use device::UsbDevice;
+use hid::HidKeyboard;
+use regs::Regs;
+use storage::MassStorage;
+
+pub static XHCI_DRIVER: DriverDescriptor = DriverDescriptor {
+    name: "xhci",
+    // serial bus controller, USB
+    compatible: &["pci-class-0c03"],
+    probe,
+};
+
+/// programming interface of USB controllers that are xHCI
+const PROG_IF_XHCI: u8 = 0x30;
+const PCI_PROG_IF: u16 = 0x09;
+
+struct XhciDevice(Arc<Xhci>);
+
+impl Device for XhciDevice {
+    fn shutdown(&self) {
+        self.0.halt();
+    }
+}
+
+/// enumerate whatever is on `port` and hand it to a class driver
+fn attach(xhci: &Arc<Xhci>, port: u8) {
+    use log::*;
+
+    let device = match UsbDevice::enumerate(xhci, port) {
+        Ok(device) => device,
+        Err(e) => {
+            warn!("{}: port {}: enumeration failed: {}", xhci.name, port, e);
+            return;
+        }
+    };
+    let slot = device.slot;
+
+    let config = match device.configuration(xhci, 0) {
+        Ok(config) => config,
+        Err(e) => {
+            warn!("{}-{}: no configuration: {}", xhci.name, port, e);
+            _ = xhci.disable_slot(slot);
+            return;
+        }
+    };
+
+    let keyboard = HidKeyboard::find(&config.interfaces);
+    let disk = MassStorage::find(&config.interfaces);
+    if keyboard.is_none() && disk.is_none() {
+        let d = &device.descriptor;
+        info!(
+            "{}-{}: {:04x}:{:04x} class {:02x}/{:02x}/{:02x} USB {:x}.{:02x}, no driver",
+            xhci.name,
+            port,
+            d.vendor,
+            d.product,
+            d.class,
+            d.subclass,
+            d.protocol,
+            d.usb >> 8,
+            d.usb & 0xFF
+        );
+        _ = xhci.disable_slot(slot);
+        return;
+    }
+
+    if let Err(e) = device.set_configuration(xhci, config.value) {
+        warn!("{}-{}: SET_CONFIGURATION: {}", xhci.name, port, e);
+        _ = xhci.disable_slot(slot);
+        return;
+    }
+
+    match (keyboard, disk) {
+        (Some(interface), _) => {
+            if let Err(e) = HidKeyboard::attach(xhci, device, interface) {
+                warn!("{}-{}: keyboard: {}", xhci.name, port, e);
+                _ = xhci.disable_slot(slot);
+            }
+        }
+        (None, Some(interface)) => {
+            if let Err(e) = MassStorage::attach(xhci, device, interface) {
+                warn!("{}-{}: mass storage: {}", xhci.name, port, e);
+                _ = xhci.disable_slot(slot);
+            }
+        }
+        (None, None) => {}
+    }
+}
+
+fn probe(node: &DeviceNode) -> Result<Box<dyn Device>, DriverError> {
+    use log::*;
+
+    let bdf = Bdf::try_from(&node.class).map_err(|_| DriverError::Incompatible)?;
+    let ecam = get_segment(bdf.segment).ok_or(DriverError::MissingResources)?;
+
+    if ecam.read_u8(bdf, PCI_PROG_IF) != PROG_IF_XHCI {
+        // UHCI, OHCI or EHCI
+        return Err(DriverError::Incompatible);
+    }
+
+    let size = match probe_bars(ecam, bdf).first() {
+        Some(BarType::Memory32 { size, .. }) => *size as usize,
+        Some(BarType::Memory64 { size, .. }) => *size as usize,
+        _ => return Err(DriverError::MissingResources),
+    };
+    let base = bar_address(ecam, bdf, 0)
+        .filter(|&base| base != 0)
+        .ok_or(DriverError::MissingResources)? as usize;
+    let regs = map_mmio(&(base..base + size)).ok_or(DriverError::Io)?;
+
+    let dma = bdf.dma_space();
+
+    ecam.enable_memory_space(bdf);
+    ecam.enable_bus_master(bdf);
+
+    let xhci = Xhci::new(format!("xhci@{}", bdf), Regs::new(regs), dma).map_err(|e| {
+        error!("{}: reset failed: {}", bdf, e);
+        DriverError::Io
+    })?;
+    let xhci = Arc::new(xhci);
+
+    // interrupter 0 is the only one we use, and it signals through vector 0
+    let routed = alloc_vectors(ecam, bdf, 1)
+        .map_err(|e| {
+            warn!("{}: {}", bdf, e);
+            DriverError::MissingResources
+        })
+        .and_then(|vectors| {
+            let irq = vectors.irqs[0];
+            route_interrupt(irq, vectors.kind == VectorKind::Intx, xhci.clone()).map_err(|e| {
+                warn!("{}: failed to route IRQ {}: {:?}", bdf, irq, e);
+                DriverError::Io
+            })
+        });
+    match routed {
+        Ok(()) => xhci.enable_interrupts(),
+        Err(e) => warn!(
+            "{}: no interrupts ({:?}), polling, and keyboards won't work",
+            bdf, e
+        ),
+    }
+
+    xhci.start().map_err(|e| {
+        error!("{}: failed to start: {}", xhci.name, e);
+        DriverError::Io
+    })?;
+
+    info!("{}: running, {} ports", xhci.name, xhci.max_ports());
+
+    for port in 1..=xhci.max_ports() {
+        if xhci.port_connected(port) {
+            attach(&xhci, port);
+        }
+    }
+
+    Ok(Box::new(XhciDevice(xhci)))
+}
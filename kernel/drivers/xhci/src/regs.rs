@@ -0,0 +1,110 @@
+//! register layout, see xHCI 1.2 chapter 5.
+
+use core::ptr::{NonNull, read_volatile, write_volatile};
+
+// capability registers
+pub const CAPLENGTH: usize = 0x00;
+pub const HCIVERSION: usize = 0x02;
+pub const HCSPARAMS1: usize = 0x04;
+pub const HCSPARAMS2: usize = 0x08;
+pub const HCCPARAMS1: usize = 0x10;
+pub const DBOFF: usize = 0x14;
+pub const RTSOFF: usize = 0x18;
+
+/// HCCPARAMS1: contexts are 64 bytes instead of 32
+pub const HCC_CSZ: u32 = 1 << 2;
+
+// operational registers
+pub const USBCMD: usize = 0x00;
+pub const USBSTS: usize = 0x04;
+pub const CRCR: usize = 0x18;
+pub const DCBAAP: usize = 0x30;
+pub const CONFIG: usize = 0x38;
+pub const PORTSC_BASE: usize = 0x400;
+pub const PORTSC_STRIDE: usize = 0x10;
+
+// USBCMD
+pub const CMD_RUN: u32 = 1 << 0;
+pub const CMD_HCRST: u32 = 1 << 1;
+pub const CMD_INTE: u32 = 1 << 2;
+
+// USBSTS
+pub const STS_HCH: u32 = 1 << 0;
+pub const STS_EINT: u32 = 1 << 3;
+pub const STS_CNR: u32 = 1 << 11;
+
+// PORTSC
+pub const PORT_CCS: u32 = 1 << 0;
+pub const PORT_PED: u32 = 1 << 1;
+pub const PORT_PR: u32 = 1 << 4;
+pub const PORT_PP: u32 = 1 << 9;
+pub const PORT_SPEED_SHIFT: u32 = 10;
+pub const PORT_SPEED_MASK: u32 = 0xF;
+/// the RW1C change bits, CSC through CEC
+pub const PORT_CHANGE: u32 = 0x7F << 17;
+
+/// interrupter 0 in the runtime registers
+pub const INTERRUPTER_0: usize = 0x20;
+
+// interrupter registers
+pub const IMAN: usize = 0x00;
+pub const IMOD: usize = 0x04;
+pub const ERSTSZ: usize = 0x08;
+pub const ERSTBA: usize = 0x10;
+pub const ERDP: usize = 0x18;
+
+pub const IMAN_IP: u32 = 1 << 0;
+pub const IMAN_IE: u32 = 1 << 1;
+/// ERDP: event handler busy, RW1C
+pub const ERDP_EHB: u64 = 1 << 3;
+
+// PORTSC speed IDs, the default protocol speed ID mapping
+pub const SPEED_FULL: u8 = 1;
+pub const SPEED_LOW: u8 = 2;
+pub const SPEED_HIGH: u8 = 3;
+pub const SPEED_SUPER: u8 = 4;
+
+/// a block of 32-bit registers
+#[derive(Copy, Clone)]
+pub struct Regs {
+    base: NonNull<u8>,
+}
+
+// SAFETY: MMIO, only touched through volatile accesses
+unsafe impl Send for Regs {}
+unsafe impl Sync for Regs {}
+
+impl Regs {
+    pub fn new(base: NonNull<u8>) -> Self {
+        Self { base }
+    }
+
+    /// the registers starting `offset` bytes in
+    pub fn at(self, offset: usize) -> Self {
+        Self {
+            base: unsafe { self.base.add(offset) },
+        }
+    }
+
+    pub fn read8(&self, offset: usize) -> u8 {
+        unsafe { read_volatile(self.base.as_ptr().add(offset)) }
+    }
+
+    pub fn read16(&self, offset: usize) -> u16 {
+        unsafe { read_volatile(self.base.as_ptr().add(offset) as *const u16) }
+    }
+
+    pub fn read32(&self, offset: usize) -> u32 {
+        unsafe { read_volatile(self.base.as_ptr().add(offset) as *const u32) }
+    }
+
+    pub fn write32(&self, offset: usize, val: u32) {
+        unsafe { write_volatile(self.base.as_ptr().add(offset) as *mut u32, val) }
+    }
+
+    /// 64-bit registers are written as two halves, low first, which every controller accepts
+    pub fn write64(&self, offset: usize, val: u64) {
+        self.write32(offset, val as u32);
+        self.write32(offset + 4, (val >> 32) as u32);
+    }
+}
@@ -0,0 +1,238 @@
+//! transfer request blocks and the rings they travel on, see xHCI 1.2 sections 4.9 and 6.4.
+//!
+//! producer rings (the command ring and one transfer ring per endpoint) end in a link TRB back to
+//! the start that flips the cycle bit, so the controller can tell new TRBs from ones it's already
+//! done. the event ring works the other way round: the controller produces, we consume.
+
+use core::ptr::{read_volatile, write_volatile};
+
+use aarch64_cpu::asm::barrier::{self, dmb};
+use klib::hardware::{dma::DmaBuffer, iommu::DmaSpace};
+
+pub const TRB_LEN: usize = 16;
+
+// TRB types
+pub const NORMAL: u8 = 1;
+pub const SETUP_STAGE: u8 = 2;
+pub const DATA_STAGE: u8 = 3;
+pub const STATUS_STAGE: u8 = 4;
+pub const LINK: u8 = 6;
+pub const ENABLE_SLOT: u8 = 9;
+pub const DISABLE_SLOT: u8 = 10;
+pub const ADDRESS_DEVICE: u8 = 11;
+pub const CONFIGURE_ENDPOINT: u8 = 12;
+pub const EVALUATE_CONTEXT: u8 = 13;
+pub const RESET_ENDPOINT: u8 = 14;
+pub const SET_TR_DEQUEUE: u8 = 16;
+pub const TRANSFER_EVENT: u8 = 32;
+pub const COMMAND_COMPLETION: u8 = 33;
+pub const PORT_STATUS_CHANGE: u8 = 34;
+
+// control field bits
+pub const CYCLE: u32 = 1 << 0;
+/// link TRBs: flip the cycle state when following the link
+pub const TOGGLE_CYCLE: u32 = 1 << 1;
+/// interrupt on short packet
+pub const ISP: u32 = 1 << 2;
+pub const CHAIN: u32 = 1 << 4;
+/// interrupt on completion
+pub const IOC: u32 = 1 << 5;
+/// immediate data: the parameter is the data, not a pointer to it
+pub const IDT: u32 = 1 << 6;
+/// data and status stages: device to host
+pub const DIR_IN: u32 = 1 << 16;
+// setup stages: transfer type
+pub const TRT_OUT: u32 = 2 << 16;
+pub const TRT_IN: u32 = 3 << 16;
+
+// completion codes
+pub const SUCCESS: u8 = 1;
+pub const STALL: u8 = 6;
+pub const SHORT_PACKET: u8 = 13;
+
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Trb {
+    pub parameter: u64,
+    pub status: u32,
+    pub control: u32,
+}
+
+impl Trb {
+    pub fn new(ty: u8, parameter: u64, status: u32, flags: u32) -> Self {
+        Self {
+            parameter,
+            status,
+            control: ((ty as u32) << 10) | flags,
+        }
+    }
+
+    pub fn ty(&self) -> u8 {
+        ((self.control >> 10) & 0x3F) as u8
+    }
+
+    /// event TRBs
+    pub fn completion_code(&self) -> u8 {
+        (self.status >> 24) as u8
+    }
+
+    /// transfer events: bytes left untransferred
+    pub fn residual(&self) -> u32 {
+        self.status & 0xFF_FFFF
+    }
+
+    /// commands and events that concern a device slot
+    pub fn slot_id(&self) -> u8 {
+        (self.control >> 24) as u8
+    }
+
+    /// transfer events: the endpoint's device context index
+    pub fn endpoint_id(&self) -> u8 {
+        ((self.control >> 16) & 0x1F) as u8
+    }
+}
+
+fn write_trb(buf: &DmaBuffer, index: usize, trb: Trb) {
+    let ptr = unsafe { buf.as_ptr().add(index * TRB_LEN) };
+    unsafe {
+        write_volatile(ptr as *mut u64, trb.parameter);
+        write_volatile(ptr.add(8) as *mut u32, trb.status);
+        // the cycle bit hands the TRB over, so it goes last
+        dmb(barrier::SY);
+        write_volatile(ptr.add(12) as *mut u32, trb.control);
+    }
+    buf.clean(index * TRB_LEN, TRB_LEN);
+}
+
+fn read_trb(buf: &DmaBuffer, index: usize) -> Trb {
+    let ptr = unsafe { buf.as_ptr().add(index * TRB_LEN) };
+    unsafe {
+        Trb {
+            parameter: read_volatile(ptr as *const u64),
+            status: read_volatile(ptr.add(8) as *const u32),
+            control: read_volatile(ptr.add(12) as *const u32),
+        }
+    }
+}
+
+/// a ring we produce TRBs on
+pub struct Ring {
+    buf: DmaBuffer,
+    /// TRBs, counting the link
+    len: usize,
+    enqueue: usize,
+    cycle: bool,
+}
+
+impl Ring {
+    pub fn new(len: usize, dma: &DmaSpace) -> Option<Self> {
+        let buf = DmaBuffer::new_in(len * TRB_LEN, dma)?;
+        let link = Trb::new(LINK, buf.phys_addr(), 0, TOGGLE_CYCLE);
+        // the link's cycle bit stays clear until the ring reaches it
+        write_trb(&buf, len - 1, link);
+
+        Some(Self {
+            buf,
+            len,
+            enqueue: 0,
+            cycle: true,
+        })
+    }
+
+    /// where the controller starts: the next TRB we'll write, with our cycle state in bit 0
+    pub fn dequeue_pointer(&self) -> u64 {
+        self.trb_addr(self.enqueue) | self.cycle as u64
+    }
+
+    fn trb_addr(&self, index: usize) -> u64 {
+        self.buf.phys_addr() + (index * TRB_LEN) as u64
+    }
+
+    /// hand `trb` to the controller. returns its bus address, which events refer to it by.
+    pub fn push(&mut self, mut trb: Trb) -> u64 {
+        let addr = self.trb_addr(self.enqueue);
+        let chain = trb.control & CHAIN;
+
+        trb.control = (trb.control & !CYCLE) | self.cycle as u32;
+        write_trb(&self.buf, self.enqueue, trb);
+
+        self.enqueue += 1;
+        if self.enqueue == self.len - 1 {
+            // the link keeps a TD that spans it together
+            let link = Trb::new(
+                LINK,
+                self.buf.phys_addr(),
+                0,
+                TOGGLE_CYCLE | chain | self.cycle as u32,
+            );
+            write_trb(&self.buf, self.enqueue, link);
+
+            self.enqueue = 0;
+            self.cycle = !self.cycle;
+        }
+
+        addr
+    }
+}
+
+/// the ring the controller reports events on, a single segment
+pub struct EventRing {
+    buf: DmaBuffer,
+    /// the event ring segment table, one entry
+    table: DmaBuffer,
+    len: usize,
+    dequeue: usize,
+    cycle: bool,
+}
+
+impl EventRing {
+    pub fn new(len: usize, dma: &DmaSpace) -> Option<Self> {
+        let buf = DmaBuffer::new_in(len * TRB_LEN, dma)?;
+        let mut table = DmaBuffer::new_in(16, dma)?;
+
+        let entry = table.as_mut_slice();
+        entry[..8].copy_from_slice(&buf.phys_addr().to_le_bytes());
+        entry[8..12].copy_from_slice(&(len as u32).to_le_bytes());
+        table.clean(0, 16);
+
+        Some(Self {
+            buf,
+            table,
+            len,
+            dequeue: 0,
+            cycle: true,
+        })
+    }
+
+    pub fn table_addr(&self) -> u64 {
+        self.table.phys_addr()
+    }
+
+    /// the next event to read, for ERDP
+    pub fn dequeue_pointer(&self) -> u64 {
+        self.buf.phys_addr() + (self.dequeue * TRB_LEN) as u64
+    }
+
+    /// the next event, if the controller has written one
+    pub fn pop(&mut self) -> Option<Trb> {
+        let offset = self.dequeue * TRB_LEN;
+
+        // the controller may not be coherent with the CPU, so the line can be stale
+        self.buf.invalidate(offset, TRB_LEN);
+        let control = unsafe { read_volatile(self.buf.as_ptr().add(offset + 12) as *const u32) };
+        if (control & CYCLE != 0) != self.cycle {
+            return None;
+        }
+
+        dmb(barrier::SY);
+        self.buf.invalidate(offset, TRB_LEN);
+        let trb = read_trb(&self.buf, self.dequeue);
+
+        self.dequeue += 1;
+        if self.dequeue == self.len {
+            self.dequeue = 0;
+            self.cycle = !self.cycle;
+        }
+
+        Some(trb)
+    }
+}
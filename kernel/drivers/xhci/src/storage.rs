@@ -0,0 +1,421 @@
+//! USB mass storage: SCSI commands over bulk-only transport, registered as a block device.
+//!
+//! every command is a command block wrapper sent to bulk OUT, an optional data stage on either
+//! bulk endpoint, then a command status wrapper read from bulk IN. commands run one at a time
+//! through a page-sized bounce buffer, so big reads and writes are split up.
+
+use core::{
+    fmt,
+    ops::Range,
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+use alloc::{boxed::Box, format, string::String, sync::Arc};
+use klib::{
+    block::{self, BlockDevice, BlockError, HardwareAdapter, SharedProvider},
+    hardware::dma::DmaBuffer,
+    sync::SleepingMutex,
+    vm::PAGE_SIZE,
+};
+
+use crate::{
+    controller::{Xhci, XhciError},
+    device::{Endpoint, UsbDevice, speed_name},
+    usb::{Interface, REQ_CLASS, REQ_INTERFACE, SetupPacket, TransferType},
+};
+
+pub const CLASS_MASS_STORAGE: u8 = 8;
+pub const SUBCLASS_SCSI: u8 = 6;
+pub const PROTOCOL_BULK_ONLY: u8 = 0x50;
+
+/// class request that gets a confused device back to waiting for a CBW
+const BULK_ONLY_RESET: u8 = 0xFF;
+
+const CBW_SIGNATURE: u32 = 0x4342_5355;
+const CSW_SIGNATURE: u32 = 0x5342_5355;
+const CBW_LEN: usize = 31;
+const CSW_LEN: usize = 13;
+const CBW_DATA_IN: u8 = 0x80;
+
+// CSW status
+const STATUS_PASSED: u8 = 0;
+const STATUS_FAILED: u8 = 1;
+
+// SCSI operation codes
+const TEST_UNIT_READY: u8 = 0x00;
+const REQUEST_SENSE: u8 = 0x03;
+const INQUIRY: u8 = 0x12;
+const READ_CAPACITY_10: u8 = 0x25;
+const READ_10: u8 = 0x28;
+const WRITE_10: u8 = 0x2A;
+const SYNCHRONIZE_CACHE_10: u8 = 0x35;
+
+const INQUIRY_LEN: usize = 36;
+const SENSE_LEN: usize = 18;
+
+/// a device that was just plugged in answers the first few TEST UNIT READYs with a unit attention
+const READY_ATTEMPTS: usize = 5;
+
+static NEXT_DISK: AtomicU32 = AtomicU32::new(0);
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum StorageError {
+    Transport(XhciError),
+    /// the device ran the command and it failed
+    CommandFailed,
+    /// the device lost track of the protocol and had to be reset
+    PhaseError,
+    /// a status wrapper with the wrong signature or tag
+    BadStatus,
+    /// a block size we can't do I/O in
+    BadBlockSize(u32),
+}
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Transport(e) => write!(f, "{e}"),
+            Self::CommandFailed => write!(f, "command failed"),
+            Self::PhaseError => write!(f, "phase error"),
+            Self::BadStatus => write!(f, "malformed status wrapper"),
+            Self::BadBlockSize(size) => write!(f, "unsupported block size {size}"),
+        }
+    }
+}
+
+impl core::error::Error for StorageError {}
+
+impl From<XhciError> for StorageError {
+    fn from(e: XhciError) -> Self {
+        Self::Transport(e)
+    }
+}
+
+impl From<StorageError> for BlockError {
+    fn from(_: StorageError) -> Self {
+        BlockError::HardwareError
+    }
+}
+
+type Result<T> = core::result::Result<T, StorageError>;
+
+/// where a command's data stage goes
+enum Data<'a> {
+    None,
+    In(&'a mut [u8]),
+    Out(&'a [u8]),
+}
+
+pub struct MassStorage {
+    xhci: Arc<Xhci>,
+    device: UsbDevice,
+    interface: u8,
+    bulk_in: Endpoint,
+    bulk_out: Endpoint,
+    /// CBWs and CSWs
+    wrapper: DmaBuffer,
+    data: DmaBuffer,
+    tag: u32,
+    block_size: usize,
+    block_count: u64,
+}
+
+impl MassStorage {
+    /// the SCSI bulk-only interface among `interfaces`, if there is one
+    pub fn find(interfaces: &[Interface]) -> Option<&Interface> {
+        interfaces.iter().find(|i| {
+            i.alternate == 0
+                && i.class == CLASS_MASS_STORAGE
+                && i.subclass == SUBCLASS_SCSI
+                && i.protocol == PROTOCOL_BULK_ONLY
+                && i.endpoint(TransferType::Bulk, true).is_some()
+                && i.endpoint(TransferType::Bulk, false).is_some()
+        })
+    }
+
+    /// bring up the disk on `interface` and register it
+    pub fn attach(xhci: &Arc<Xhci>, mut device: UsbDevice, interface: &Interface) -> Result<()> {
+        use log::*;
+
+        let descs = [
+            *interface
+                .endpoint(TransferType::Bulk, true)
+                .ok_or(XhciError::Unsupported)?,
+            *interface
+                .endpoint(TransferType::Bulk, false)
+                .ok_or(XhciError::Unsupported)?,
+        ];
+        let mut endpoints = device.configure_endpoints(xhci, &descs)?;
+        let bulk_out = endpoints.pop().ok_or(XhciError::Unsupported)?;
+        let bulk_in = endpoints.pop().ok_or(XhciError::Unsupported)?;
+
+        let mut disk = Self {
+            xhci: xhci.clone(),
+            device,
+            interface: interface.number,
+            bulk_in,
+            bulk_out,
+            wrapper: DmaBuffer::new_in(CBW_LEN, &xhci.dma).ok_or(XhciError::NoMemory)?,
+            data: DmaBuffer::new_in(PAGE_SIZE, &xhci.dma).ok_or(XhciError::NoMemory)?,
+            tag: 0,
+            block_size: 0,
+            block_count: 0,
+        };
+
+        let mut inquiry = [0u8; INQUIRY_LEN];
+        disk.command(
+            &[INQUIRY, 0, 0, 0, INQUIRY_LEN as u8, 0],
+            Data::In(&mut inquiry),
+        )?;
+        let text = |range: Range<usize>| String::from_utf8_lossy(&inquiry[range]).trim().into();
+        let (vendor, product): (String, String) = (text(8..16), text(16..32));
+
+        disk.wait_ready()?;
+
+        let mut capacity = [0u8; 8];
+        disk.command(
+            &[READ_CAPACITY_10, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+            Data::In(&mut capacity),
+        )?;
+        let last_lba = u32::from_be_bytes([capacity[0], capacity[1], capacity[2], capacity[3]]);
+        let block_size = u32::from_be_bytes([capacity[4], capacity[5], capacity[6], capacity[7]]);
+        if block_size == 0 || block_size as usize > PAGE_SIZE || !block_size.is_power_of_two() {
+            return Err(StorageError::BadBlockSize(block_size));
+        }
+
+        // READ(10) can't go past 2^32 blocks, so neither do we
+        disk.block_size = block_size as usize;
+        disk.block_count = last_lba as u64 + 1;
+
+        let name = format!("usb{}", NEXT_DISK.fetch_add(1, Ordering::Relaxed));
+        info!(
+            "{}-{}: {} {} ({}), {} MiB as {}",
+            xhci.name,
+            disk.device.port,
+            vendor,
+            product,
+            speed_name(disk.device.speed),
+            (disk.block_count * disk.block_size as u64) >> 20,
+            name
+        );
+
+        let provider: SharedProvider = Arc::new(SleepingMutex::new(HardwareAdapter::new(
+            name,
+            Box::new(disk),
+        )));
+        block::register(provider);
+
+        Ok(())
+    }
+
+    fn wait_ready(&mut self) -> Result<()> {
+        let mut result = Ok(());
+
+        for _ in 0..READY_ATTEMPTS {
+            result = self.command(&[TEST_UNIT_READY, 0, 0, 0, 0, 0], Data::None);
+            if result != Err(StorageError::CommandFailed) {
+                break;
+            }
+
+            // reading the sense data clears the unit attention
+            let mut sense = [0u8; SENSE_LEN];
+            self.command(
+                &[REQUEST_SENSE, 0, 0, 0, SENSE_LEN as u8, 0],
+                Data::In(&mut sense),
+            )?;
+            log::debug!(
+                "{}: not ready, sense key {:#x} asc {:#x}",
+                self.xhci.name,
+                sense[2] & 0xF,
+                sense[12]
+            );
+        }
+
+        result
+    }
+
+    /// run one SCSI command
+    fn command(&mut self, cb: &[u8], mut data: Data<'_>) -> Result<()> {
+        let slot = self.device.slot;
+        let (len, data_in) = match &data {
+            Data::None => (0, false),
+            Data::In(buf) => (buf.len(), true),
+            Data::Out(buf) => (buf.len(), false),
+        };
+        debug_assert!(len <= self.data.len() && cb.len() <= 16);
+
+        self.tag = self.tag.wrapping_add(1);
+        let cbw = &mut self.wrapper.as_mut_slice()[..CBW_LEN];
+        cbw.fill(0);
+        cbw[0..4].copy_from_slice(&CBW_SIGNATURE.to_le_bytes());
+        cbw[4..8].copy_from_slice(&self.tag.to_le_bytes());
+        cbw[8..12].copy_from_slice(&(len as u32).to_le_bytes());
+        cbw[12] = if data_in { CBW_DATA_IN } else { 0 };
+        cbw[14] = cb.len() as u8;
+        cbw[15..15 + cb.len()].copy_from_slice(cb);
+        self.wrapper.clean(0, CBW_LEN);
+
+        if let Err(e) = self
+            .bulk_out
+            .transfer(&self.xhci, slot, self.wrapper.phys_addr(), CBW_LEN)
+        {
+            // the device didn't take the command at all
+            self.reset_recovery();
+            return Err(e.into());
+        }
+
+        if len > 0 {
+            if let Data::Out(buf) = &data {
+                self.data.as_mut_slice()[..len].copy_from_slice(buf);
+                self.data.clean(0, len);
+            }
+
+            let endpoint = if data_in {
+                &mut self.bulk_in
+            } else {
+                &mut self.bulk_out
+            };
+            match endpoint.transfer(&self.xhci, slot, self.data.phys_addr(), len) {
+                Ok(done) => {
+                    if let Data::In(buf) = &mut data {
+                        buf[..done].copy_from_slice(&self.data.as_slice()[..done]);
+                    }
+                }
+                // a stalled data stage still ends with a status wrapper
+                Err(XhciError::Stall) => self.device.clear_halt(&self.xhci, endpoint)?,
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        let csw = match self.read_status() {
+            Err(StorageError::Transport(XhciError::Stall)) => {
+                self.device.clear_halt(&self.xhci, &mut self.bulk_in)?;
+                self.read_status()
+            }
+            result => result,
+        }?;
+
+        let signature = u32::from_le_bytes([csw[0], csw[1], csw[2], csw[3]]);
+        let tag = u32::from_le_bytes([csw[4], csw[5], csw[6], csw[7]]);
+        if signature != CSW_SIGNATURE || tag != self.tag {
+            self.reset_recovery();
+            return Err(StorageError::BadStatus);
+        }
+
+        match csw[12] {
+            STATUS_PASSED => Ok(()),
+            STATUS_FAILED => Err(StorageError::CommandFailed),
+            _ => {
+                self.reset_recovery();
+                Err(StorageError::PhaseError)
+            }
+        }
+    }
+
+    fn read_status(&mut self) -> Result<[u8; CSW_LEN]> {
+        let len = self.bulk_in.transfer(
+            &self.xhci,
+            self.device.slot,
+            self.wrapper.phys_addr(),
+            CSW_LEN,
+        )?;
+        if len < CSW_LEN {
+            return Err(StorageError::BadStatus);
+        }
+
+        let mut csw = [0u8; CSW_LEN];
+        csw.copy_from_slice(&self.wrapper.as_slice()[..CSW_LEN]);
+        Ok(csw)
+    }
+
+    /// reset the interface and both endpoints, see the bulk-only transport spec section 5.3.4
+    fn reset_recovery(&mut self) {
+        let setup = SetupPacket {
+            request_type: REQ_CLASS | REQ_INTERFACE,
+            request: BULK_ONLY_RESET,
+            value: 0,
+            index: self.interface as u16,
+            length: 0,
+        };
+
+        let result = self
+            .device
+            .control_out(&self.xhci, setup, &[])
+            .and_then(|_| self.device.clear_halt(&self.xhci, &mut self.bulk_in))
+            .and_then(|_| self.device.clear_halt(&self.xhci, &mut self.bulk_out));
+        if let Err(e) = result {
+            log::warn!("{}: reset recovery failed: {}", self.xhci.name, e);
+        }
+    }
+
+    /// split a transfer of `len` bytes at `lba` into pieces that fit the bounce buffer:
+    /// `(lba, byte range)` pairs
+    fn chunks(
+        &self,
+        lba: u64,
+        len: usize,
+    ) -> block::Result<impl Iterator<Item = (u64, Range<usize>)> + use<>> {
+        if !len.is_multiple_of(self.block_size) {
+            return Err(BlockError::UnalignedBuffer);
+        }
+        let blocks = (len / self.block_size) as u64;
+        if lba
+            .checked_add(blocks)
+            .is_none_or(|end| end > self.block_count)
+        {
+            return Err(BlockError::OutOfBounds);
+        }
+
+        let block_size = self.block_size;
+        let chunk = PAGE_SIZE / block_size * block_size;
+        Ok((0..len).step_by(chunk).map(move |start| {
+            (
+                lba + (start / block_size) as u64,
+                start..(start + chunk).min(len),
+            )
+        }))
+    }
+
+    fn rw10(&self, op: u8, lba: u64, len: usize) -> [u8; 10] {
+        let lba = (lba as u32).to_be_bytes();
+        let blocks = ((len / self.block_size) as u16).to_be_bytes();
+        [
+            op, 0, lba[0], lba[1], lba[2], lba[3], 0, blocks[0], blocks[1], 0,
+        ]
+    }
+}
+
+impl BlockDevice for MassStorage {
+    fn flush(&mut self) -> block::Result<()> {
+        Ok(self.command(
+            &[SYNCHRONIZE_CACHE_10, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+            Data::None,
+        )?)
+    }
+
+    fn read_blocks(&mut self, lba: u64, buf: &mut [u8]) -> block::Result<()> {
+        for (lba, range) in self.chunks(lba, buf.len())? {
+            let buf = &mut buf[range];
+            let cb = self.rw10(READ_10, lba, buf.len());
+            self.command(&cb, Data::In(buf))?;
+        }
+        Ok(())
+    }
+
+    fn write_blocks(&mut self, lba: u64, buf: &[u8]) -> block::Result<()> {
+        for (lba, range) in self.chunks(lba, buf.len())? {
+            let buf = &buf[range];
+            let cb = self.rw10(WRITE_10, lba, buf.len());
+            self.command(&cb, Data::Out(buf))?;
+        }
+        Ok(())
+    }
+
+    fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    fn block_count(&self) -> u64 {
+        self.block_count
+    }
+}
@@ -0,0 +1,221 @@
+//! standard requests and descriptors, see USB 2.0 chapter 9.
+
+use alloc::vec::Vec;
+
+// bmRequestType
+pub const REQ_IN: u8 = 0x80;
+pub const REQ_CLASS: u8 = 0x20;
+pub const REQ_INTERFACE: u8 = 0x01;
+pub const REQ_ENDPOINT: u8 = 0x02;
+
+// standard bRequest
+pub const CLEAR_FEATURE: u8 = 1;
+pub const GET_DESCRIPTOR: u8 = 6;
+pub const SET_CONFIGURATION: u8 = 9;
+
+/// CLEAR_FEATURE selector for endpoints
+pub const ENDPOINT_HALT: u16 = 0;
+
+// descriptor types
+pub const DESC_DEVICE: u8 = 1;
+pub const DESC_CONFIGURATION: u8 = 2;
+pub const DESC_INTERFACE: u8 = 4;
+pub const DESC_ENDPOINT: u8 = 5;
+pub const DESC_SS_ENDPOINT_COMPANION: u8 = 0x30;
+
+pub const DEVICE_DESCRIPTOR_LEN: usize = 18;
+pub const CONFIGURATION_DESCRIPTOR_LEN: usize = 9;
+
+/// the 8 bytes of a control transfer's setup stage
+#[derive(Debug, Copy, Clone)]
+pub struct SetupPacket {
+    pub request_type: u8,
+    pub request: u8,
+    pub value: u16,
+    pub index: u16,
+    pub length: u16,
+}
+
+impl SetupPacket {
+    pub fn get_descriptor(ty: u8, index: u8, length: u16) -> Self {
+        Self {
+            request_type: REQ_IN,
+            request: GET_DESCRIPTOR,
+            value: ((ty as u16) << 8) | index as u16,
+            index: 0,
+            length,
+        }
+    }
+
+    /// the setup stage TRB carries the packet as its parameter
+    pub fn to_u64(self) -> u64 {
+        self.request_type as u64
+            | (self.request as u64) << 8
+            | (self.value as u64) << 16
+            | (self.index as u64) << 32
+            | (self.length as u64) << 48
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct DeviceDescriptor {
+    pub usb: u16,
+    pub class: u8,
+    pub subclass: u8,
+    pub protocol: u8,
+    pub max_packet0: u8,
+    pub vendor: u16,
+    pub product: u16,
+}
+
+impl DeviceDescriptor {
+    /// the first 8 bytes are enough for `max_packet0`
+    pub fn parse(b: &[u8]) -> Option<Self> {
+        if b.len() < 8 || b[1] != DESC_DEVICE {
+            return None;
+        }
+        let u16_at = |i: usize| b.get(i..i + 2).map(|v| u16::from_le_bytes([v[0], v[1]]));
+
+        Some(Self {
+            usb: u16_at(2)?,
+            class: b[4],
+            subclass: b[5],
+            protocol: b[6],
+            max_packet0: b[7],
+            vendor: u16_at(8).unwrap_or(0),
+            product: u16_at(10).unwrap_or(0),
+        })
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TransferType {
+    Control,
+    Isochronous,
+    Bulk,
+    Interrupt,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct EndpointDescriptor {
+    pub address: u8,
+    pub attributes: u8,
+    pub max_packet: u16,
+    pub interval: u8,
+    /// from the SuperSpeed companion descriptor, 0 otherwise
+    pub max_burst: u8,
+}
+
+impl EndpointDescriptor {
+    pub fn is_in(&self) -> bool {
+        self.address & 0x80 != 0
+    }
+
+    pub fn transfer_type(&self) -> TransferType {
+        match self.attributes & 0x3 {
+            0 => TransferType::Control,
+            1 => TransferType::Isochronous,
+            2 => TransferType::Bulk,
+            _ => TransferType::Interrupt,
+        }
+    }
+
+    /// the endpoint's index in the device context
+    pub fn dci(&self) -> u8 {
+        (self.address & 0xF) * 2 + self.is_in() as u8
+    }
+
+    /// bits 10:0 of wMaxPacketSize. the rest is the high-bandwidth multiplier.
+    pub fn packet_size(&self) -> u16 {
+        self.max_packet & 0x7FF
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Interface {
+    pub number: u8,
+    /// the configuration starts out with alternate setting 0 of every interface
+    pub alternate: u8,
+    pub class: u8,
+    pub subclass: u8,
+    pub protocol: u8,
+    pub endpoints: Vec<EndpointDescriptor>,
+}
+
+impl Interface {
+    /// the first endpoint of `kind` in direction `is_in`
+    pub fn endpoint(&self, kind: TransferType, is_in: bool) -> Option<&EndpointDescriptor> {
+        self.endpoints
+            .iter()
+            .find(|e| e.transfer_type() == kind && e.is_in() == is_in)
+    }
+}
+
+/// a configuration descriptor and everything that follows it
+#[derive(Debug, Clone)]
+pub struct Configuration {
+    pub value: u8,
+    pub interfaces: Vec<Interface>,
+}
+
+impl Configuration {
+    /// the total length of the configuration, from its first 9 bytes
+    pub fn total_length(b: &[u8]) -> Option<u16> {
+        if b.len() < CONFIGURATION_DESCRIPTOR_LEN || b[1] != DESC_CONFIGURATION {
+            return None;
+        }
+        Some(u16::from_le_bytes([b[2], b[3]]))
+    }
+
+    /// walk the descriptors, keeping the interfaces and their endpoints. class-specific
+    /// descriptors (HID, hub, ...) are skipped.
+    pub fn parse(b: &[u8]) -> Option<Self> {
+        Self::total_length(b)?;
+        let value = b[5];
+
+        let mut interfaces: Vec<Interface> = Vec::new();
+        let mut rest = &b[b[0] as usize..];
+
+        while rest.len() >= 2 {
+            let len = rest[0] as usize;
+            if len < 2 || len > rest.len() {
+                return None;
+            }
+            let d = &rest[..len];
+
+            match d[1] {
+                DESC_INTERFACE if len >= 9 => interfaces.push(Interface {
+                    number: d[2],
+                    alternate: d[3],
+                    class: d[5],
+                    subclass: d[6],
+                    protocol: d[7],
+                    endpoints: Vec::new(),
+                }),
+                DESC_ENDPOINT if len >= 7 => {
+                    if let Some(interface) = interfaces.last_mut() {
+                        interface.endpoints.push(EndpointDescriptor {
+                            address: d[2],
+                            attributes: d[3],
+                            max_packet: u16::from_le_bytes([d[4], d[5]]),
+                            interval: d[6],
+                            max_burst: 0,
+                        });
+                    }
+                }
+                DESC_SS_ENDPOINT_COMPANION if len >= 2 => {
+                    if let Some(endpoint) =
+                        interfaces.last_mut().and_then(|i| i.endpoints.last_mut())
+                    {
+                        endpoint.max_burst = d.get(2).copied().unwrap_or(0);
+                    }
+                }
+                _ => {}
+            }
+
+            rest = &rest[len..];
+        }
+
+        Some(Self { value, interfaces })
+    }
+}
@@ -9,7 +9,8 @@ use klib::{
     cpu_interface::CpuTopologyId,
     hardware::{
         device::{DeviceClass, DeviceInitPriority, DeviceTree},
-        resource::Resource,
+        iommu::{RidMap, add_rid_map},
+        resource::{AddressSpace, Irq, IrqPolarity, IrqTrigger, Resource},
     },
     interrupt::{GicdRegisters, GicrRegisters, GitsRegisters, gicv3::registers::gic::GicrTyper},
     per_cpu::PerCpu,
@@ -19,25 +20,41 @@ use klib::{
 };
 use mars_acpi_aml_driver::{
     ast::{AmlTerm, AmlValue},
+    device::TreeBuilder,
+    interpreter::{Interpreter, register_interpreter},
     parser::AmlParser,
+    prt::routing_table,
 };
 use mars_acpi_driver::acpi::{
     fadt::Fadt,
     gtdt::Gtdt,
     header::SdtHeader,
+    iort::{
+        IORT_ID_SINGLE_MAPPING, IORT_NODE_ITS_GROUP, IORT_NODE_NAMED_COMPONENT,
+        IORT_NODE_SMMU_V1_V2, IORT_NODE_SMMU_V3, Iort, IortIdMapping, IortSmmuV3,
+        SMMU_V3_MMIO_SIZE, node_at,
+    },
     madt::{GicCpuInterface, GicDistributor, GicIts, GicRedistributor, Madt, MadtIter},
     mcfg::Mcfg,
+    spcr::{SerialInterface, Spcr},
     xsdp::{Xsdp, XsdtIter},
 };
 use mars_models::memory::registers::volatile::PureReadable;
-use mars_pcie_driver::{ecam::Ecam, scan::enumerate_segment};
+use mars_pcie_driver::{
+    ecam::{Ecam, register_segment},
+    interrupt::{IntxRoute, add_intx_routes, qemu_virt_intx_routes},
+    scan::{HostBridge, enumerate_segment},
+};
 use uefi::table::cfg::ConfigTableEntry;
 use uefi_raw::table::{configuration::ConfigurationTable, system::SystemTable};
 use zerocopy::FromBytes;
 
-use crate::{DEVICE_TREE, KERNEL_ADDRESS_SPACE, earlyinit::platform::BootInfoToken};
+use crate::{
+    DEVICE_TREE, KERNEL_ADDRESS_SPACE,
+    earlyinit::{aml::AML_HANDLER, numa, platform::BootInfoToken, topology},
+};
 
-fn config_table(st: NonNull<SystemTable>) -> &'static [ConfigTableEntry] {
+pub(super) fn config_table(st: NonNull<SystemTable>) -> &'static [ConfigTableEntry] {
     let st = KernelAddressTranslator.phys_to_dmap(st.as_ptr() as _) as *const SystemTable;
     let st = unsafe { &*st };
 
@@ -54,8 +71,9 @@ fn config_table(st: NonNull<SystemTable>) -> &'static [ConfigTableEntry] {
     unsafe { core::slice::from_raw_parts(ct, len) }
 }
 
+/// discover hardware from the ACPI tables. `false` if the firmware didn't provide any.
 #[allow(static_mut_refs, reason = "singlethreaded")]
-pub fn acpi_init(token: &BootInfoToken) {
+pub fn acpi_init(token: &BootInfoToken) -> bool {
     use log::*;
 
     let bi = token.get();
@@ -70,7 +88,11 @@ pub fn acpi_init(token: &BootInfoToken) {
         .iter()
         .filter(|t| t.guid == ConfigTableEntry::ACPI2_GUID);
 
-    let xsdp = iter.next().expect("no ACPI2 table").address as *const Xsdp;
+    let Some(xsdp) = iter.next() else {
+        info!("UEFI: no ACPI 2.0 tables");
+        return false;
+    };
+    let xsdp = xsdp.address as *const Xsdp;
 
     assert_eq!(iter.next(), None, "more than one ACPI2 table?");
 
@@ -84,6 +106,17 @@ pub fn acpi_init(token: &BootInfoToken) {
 
     trace!("sdt: {:?}", xsdt);
 
+    // AML is loaded once every table has been seen: SSDTs can come before the FADT, and they
+    // extend scopes the DSDT defines
+    let mut dsdt = None;
+    let mut ssdts = Vec::new();
+    // needs the CPUs from the MADT
+    let mut pptt = None;
+    let mut srat = None;
+    let mut slit = None;
+    // enumerated once AML has described the host bridges' apertures
+    let mut segments = Vec::new();
+
     let xsdt_iter = XsdtIter::new(xsdt);
     for phys_table_bytes in xsdt_iter {
         let table_bytes: &[u8] = {
@@ -111,79 +144,464 @@ pub fn acpi_init(token: &BootInfoToken) {
             b"FACP" => {
                 trace!("    fadt found");
 
-                handle_fadt(table_bytes);
+                dsdt = Some(handle_fadt(table_bytes));
             }
             b"MCFG" => {
                 trace!("    mcfg found");
-                handle_mcfg(table_bytes);
+                segments.extend(handle_mcfg(table_bytes));
+            }
+            b"IORT" => {
+                trace!("    iort found");
+                handle_iort(table_bytes);
+            }
+            b"SPCR" => {
+                trace!("    spcr found");
+                handle_spcr(table_bytes);
+            }
+            b"SSDT" => {
+                trace!("    ssdt found");
+                ssdts.push(table_bytes);
+            }
+            b"PPTT" => {
+                trace!("    pptt found");
+                pptt = Some(table_bytes);
+            }
+            b"SRAT" => {
+                trace!("    srat found");
+                srat = Some(table_bytes);
+            }
+            b"SLIT" => {
+                trace!("    slit found");
+                slit = Some(table_bytes);
             }
             _ => trace!("unrecognized ACPI table: {}", header.signature()),
         }
     }
+
+    if let Some(pptt) = pptt {
+        topology::init_from_pptt(pptt, &DEVICE_TREE.borrow());
+    }
+
+    if let Some(srat) = srat {
+        numa::init_from_acpi(srat, slit, &DEVICE_TREE.borrow());
+    }
+
+    load_aml(dsdt, &ssdts);
+
+    let qemu = &xsdt.oem_id() == QEMU_OEM_ID;
+
+    {
+        let mut dt = DEVICE_TREE.borrow_mut();
+        for ecam in segments {
+            let hosts = host_bridges(&dt, ecam);
+            enumerate_segment(ecam, &hosts, &mut dt);
+            route_intx(&dt, ecam, &hosts, qemu);
+        }
+    }
+
+    if qemu {
+        add_qemu_virt_devices();
+    }
+
+    true
 }
 
-fn handle_mcfg(table: &'static [u8]) {
+/// OEM ID QEMU puts in the tables it generates
+const QEMU_OEM_ID: &[u8; 6] = b"BOCHS ";
+
+/// a fixed device of QEMU's virt machine. see `hw/arm/virt.c`.
+struct QemuVirtDevice {
+    class: DeviceClass,
+    compatible: &'static str,
+    base: usize,
+    size: usize,
+    gsiv: Option<u32>,
+}
+
+/// devices the DSDT doesn't always describe
+const QEMU_VIRT_DEVICES: &[QemuVirtDevice] = &[
+    QemuVirtDevice {
+        class: DeviceClass::Rtc,
+        compatible: "arm,pl031",
+        base: 0x0901_0000,
+        size: 0x1000,
+        gsiv: Some(32 + 2),
+    },
+    QemuVirtDevice {
+        class: DeviceClass::Other,
+        compatible: "QEMU0002",
+        base: 0x0902_0000,
+        size: 0x18,
+        gsiv: None,
+    },
+];
+
+/// add the fixed devices of QEMU's virt machine that aren't in the device tree yet
+fn add_qemu_virt_devices() {
     use log::*;
 
-    let (mcfg, _) = Mcfg::ref_from_prefix(table).expect("invalid mcfg size");
     let mut dt = DEVICE_TREE.borrow_mut();
 
-    for alloc in mcfg.allocations() {
-        let bus_count = (alloc.end_bus_num() as usize - alloc.start_bus_num() as usize) + 1;
-        let ecam_size = bus_count * (1024 * 1024); // 32 dev * 8 func * 4KiB
-        let phys_base = alloc.base_addr();
-        let va_start = KernelAddressTranslator.phys_to_dmap(phys_base as usize) as usize;
-        let va_end = va_start + ecam_size;
+    for dev in QEMU_VIRT_DEVICES {
+        let described = dt
+            .nodes
+            .iter()
+            .any(|node| node.compatible.iter().any(|c| c == dev.compatible));
+        if described {
+            continue;
+        }
+
+        trace!("QEMU virt: adding {} at {:#x}", dev.compatible, dev.base);
+
+        let mut resources = vec![Resource::Mmio {
+            range: dev.base..dev.base + dev.size,
+        }];
+        resources.extend(dev.gsiv.map(|gsiv| Resource::Irq(Irq::new(gsiv))));
+
+        dt.add_device(
+            None,
+            dev.class.clone(),
+            vec![String::from(dev.compatible)],
+            resources,
+            Default::default(),
+        );
+    }
+}
+
+fn handle_mcfg(table: &'static [u8]) -> Vec<&'static Ecam> {
+    let (mcfg, _) = Mcfg::ref_from_prefix(table).expect("invalid mcfg size");
 
-        {
-            trace!(
-                "ACPI: Mapping PCIe ECAM Segment {} [Phys: {:#018x}..{:#018x}] -> [Vir: {:#018x}..{:#018x}] [{} MiB]",
+    mcfg.allocations()
+        .map(|alloc| {
+            add_ecam_segment(
+                alloc.base_addr(),
                 alloc.pci_segment_group(),
-                phys_base,
-                phys_base + (ecam_size as u64),
-                va_start,
-                va_end,
-                ecam_size / (1024 * 1024)
-            );
+                alloc.start_bus_num(),
+                alloc.end_bus_num(),
+            )
+        })
+        .collect()
+}
 
-            // global AddressSpace unusable:
-            // this memory region most likely won't have been included in the firmware memory map.
-            // therefore they must be added via `map_page`
-            // maybe add PCIe regions to page descriptors in the future, if userspace needs them. currently unnecessary.
-            let root = unsafe { KERNEL_ADDRESS_SPACE.root_mut() };
-
-            for offset in (0..ecam_size).step_by(PAGE_SIZE) {
-                map_page(
-                    root,
-                    phys_base as usize + offset,
-                    va_start + offset,
-                    AccessPermission::PrivilegedReadWrite,
-                    Shareability::OuterShareable,
-                    true,
-                    true,
-                    MAIR_DEVICE_INDEX,
-                    &KERNEL_ADDRESS_SPACE.allocator,
-                    &KernelAddressTranslator,
-                );
-            }
+/// the ACPI host bridges whose root buses are in `ecam`
+fn host_bridges(dt: &DeviceTree, ecam: &Ecam) -> Vec<HostBridge> {
+    let mut hosts: Vec<HostBridge> = dt
+        .nodes
+        .iter()
+        .filter(|node| node.class == DeviceClass::PciHostBridge)
+        .filter_map(|node| {
+            let buses = node.resources.iter().find_map(|r| match r {
+                Resource::Window {
+                    space: AddressSpace::BusNumber,
+                    range,
+                    ..
+                } if (ecam.start_bus as u64..=ecam.end_bus as u64).contains(&range.start) => {
+                    Some(range.start as u8..=(range.end - 1).min(ecam.end_bus as u64) as u8)
+                }
+                _ => None,
+            })?;
+
+            let windows = node
+                .resources
+                .iter()
+                .filter(|r| {
+                    matches!(r, Resource::Window { space, .. } if *space != AddressSpace::BusNumber)
+                })
+                .cloned()
+                .collect();
+
+            Some(HostBridge {
+                node: Some(node.id),
+                buses,
+                windows,
+            })
+        })
+        .collect();
+
+    hosts.sort_by_key(|host| *host.buses.start());
+    hosts
+}
+
+/// INTx routing of the root buses of `hosts`, from each host bridge's `_PRT`. QEMU virt's
+/// fixed swizzle stands in where there's none.
+fn route_intx(dt: &DeviceTree, ecam: &Ecam, hosts: &[HostBridge], qemu: bool) {
+    use log::*;
+
+    let roots = match hosts {
+        [] => vec![(ecam.start_bus, None)],
+        hosts => hosts
+            .iter()
+            .map(|host| (*host.buses.start(), host.node))
+            .collect(),
+    };
+
+    for (bus, node) in roots {
+        let prt = node
+            .and_then(|id| dt.get(id))
+            .and_then(|node| node.firmware_path.as_deref())
+            .map(routing_table)
+            .unwrap_or_default();
+
+        if !prt.is_empty() {
+            add_intx_routes(prt.into_iter().map(|entry| IntxRoute {
+                segment: ecam.segment,
+                bus,
+                device: entry.device,
+                // `_PRT` counts from 0
+                pin: entry.pin + 1,
+                irq: entry.irq,
+            }));
+        } else if qemu {
+            add_intx_routes(qemu_virt_intx_routes(ecam.segment, bus));
+        } else {
+            debug!(
+                "PCIe {:04x}: no INTx routing for bus {:#04x}",
+                ecam.segment, bus
+            );
         }
+    }
+}
 
-        debug!(
-            "ACPI: Found PCIe Segment {} Base {:#018X} Bus {}..={}",
-            alloc.pci_segment_group(),
-            alloc.base_addr(),
-            alloc.start_bus_num(),
-            alloc.end_bus_num()
-        );
+/// map a PCIe ECAM region and register it as a segment
+pub(super) fn add_ecam_segment(
+    phys_base: u64,
+    segment: u16,
+    start_bus: u8,
+    end_bus: u8,
+) -> &'static Ecam {
+    use log::*;
+
+    let bus_count = (end_bus as usize - start_bus as usize) + 1;
+    let ecam_size = bus_count * (1024 * 1024); // 32 dev * 8 func * 4KiB
+    let va_start = KernelAddressTranslator.phys_to_dmap(phys_base as usize) as usize;
+    let va_end = va_start + ecam_size;
 
-        let ecam = Ecam::new(
+    {
+        trace!(
+            "PCIe: Mapping ECAM Segment {} [Phys: {:#018x}..{:#018x}] -> [Vir: {:#018x}..{:#018x}] [{} MiB]",
+            segment,
             phys_base,
-            alloc.pci_segment_group(),
-            alloc.start_bus_num(),
-            alloc.end_bus_num(),
+            phys_base + (ecam_size as u64),
+            va_start,
+            va_end,
+            ecam_size / (1024 * 1024)
         );
 
-        enumerate_segment(&ecam, &mut dt);
+        // global AddressSpace unusable:
+        // this memory region most likely won't have been included in the firmware memory map.
+        // therefore they must be added via `map_page`
+        // maybe add PCIe regions to page descriptors in the future, if userspace needs them. currently unnecessary.
+        let root = unsafe { KERNEL_ADDRESS_SPACE.root_mut() };
+
+        for offset in (0..ecam_size).step_by(PAGE_SIZE) {
+            map_page(
+                root,
+                phys_base as usize + offset,
+                va_start + offset,
+                AccessPermission::PrivilegedReadWrite,
+                Shareability::OuterShareable,
+                true,
+                true,
+                MAIR_DEVICE_INDEX,
+                &KERNEL_ADDRESS_SPACE.allocator,
+                &KernelAddressTranslator,
+            );
+        }
+    }
+
+    debug!(
+        "PCIe: Found Segment {} Base {:#018X} Bus {}..={}",
+        segment, phys_base, start_bus, end_bus
+    );
+
+    register_segment(Ecam::new(phys_base, segment, start_bus, end_bus))
+}
+
+fn handle_iort(table: &[u8]) {
+    use log::*;
+
+    let (iort, _) = Iort::ref_from_prefix(table).expect("invalid iort size");
+    let mut dt = DEVICE_TREE.borrow_mut();
+
+    for node in iort.nodes(table) {
+        if let Some(smmu) = node.smmu_v3() {
+            add_smmu_v3(&mut dt, smmu);
+        } else if let Some(rc) = node.root_complex() {
+            let segment = rc.pci_segment() as u16;
+
+            for mapping in node.id_mappings() {
+                for map in rid_maps(table, segment, mapping) {
+                    trace!("IORT: {:x?}", map);
+                    add_rid_map(map);
+                }
+            }
+        } else {
+            match node.header.node_type() {
+                IORT_NODE_SMMU_V1_V2 => warn!("IORT: SMMUv1/v2 is unsupported"),
+                IORT_NODE_NAMED_COMPONENT => debug!("IORT: skipping named component"),
+                _ => {}
+            }
+        }
+    }
+}
+
+/// an SMMUv3 node, as a device. its wired interrupts are edge triggered.
+fn add_smmu_v3(dt: &mut DeviceTree, smmu: &IortSmmuV3) {
+    use log::*;
+
+    let base = smmu.base_address() as usize;
+    let mut resources = vec![Resource::Mmio {
+        range: base..base + SMMU_V3_MMIO_SIZE,
+    }];
+
+    // the rest signal through MSIs, or not at all
+    resources.extend(
+        [smmu.event_gsiv(), smmu.gerr_gsiv()]
+            .into_iter()
+            .filter(|&gsiv| gsiv != 0)
+            .map(|gsiv| {
+                Resource::Irq(Irq {
+                    trigger: IrqTrigger::Edge,
+                    ..Irq::new(gsiv)
+                })
+            }),
+    );
+
+    debug!("IORT: SMMUv3 at {:#x}", base);
+
+    // devices behind it can't be attached before it's up
+    dt.add_device(
+        None,
+        DeviceClass::Iommu,
+        vec![String::from("arm,smmu-v3")],
+        resources,
+        DeviceInitPriority::Fundamental,
+    );
+}
+
+/// follow one root complex ID mapping to the ITS, through an SMMU if there's one in between
+fn rid_maps(table: &[u8], segment: u16, mapping: &IortIdMapping) -> Vec<RidMap> {
+    use log::*;
+
+    let (first, last) = mapping.inputs();
+    let Some(target) = node_at(table, mapping.output_reference() as usize) else {
+        warn!("IORT: ID mapping points outside the table");
+        return Vec::new();
+    };
+
+    match target.header.node_type() {
+        IORT_NODE_ITS_GROUP => vec![RidMap {
+            segment,
+            rids: first..last.saturating_add(1),
+            stream: None,
+            device_id: Some(mapping.output_base()),
+        }],
+        IORT_NODE_SMMU_V3 => {
+            let Some(smmu) = target.smmu_v3() else {
+                return Vec::new();
+            };
+            let base = smmu.base_address() as usize;
+            let streams = mapping.output_base()..=mapping.output_base() + mapping.id_count();
+
+            // where the SMMU sends the stream IDs this mapping produces
+            let mut maps: Vec<RidMap> = target
+                .id_mappings()
+                .filter(|its| its.flags() & IORT_ID_SINGLE_MAPPING == 0)
+                .filter(|its| {
+                    node_at(table, its.output_reference() as usize)
+                        .is_some_and(|node| node.header.node_type() == IORT_NODE_ITS_GROUP)
+                })
+                .filter_map(|its| {
+                    let (its_first, its_last) = its.inputs();
+                    let low = its_first.max(*streams.start());
+                    let high = its_last.min(*streams.end());
+
+                    (low <= high).then(|| RidMap {
+                        segment,
+                        rids: first + (low - streams.start())..first + (high - streams.start()) + 1,
+                        stream: Some((base, low)),
+                        device_id: its.map(low),
+                    })
+                })
+                .collect();
+
+            if maps.is_empty() {
+                maps.push(RidMap {
+                    segment,
+                    rids: first..last.saturating_add(1),
+                    stream: Some((base, mapping.output_base())),
+                    device_id: None,
+                });
+            }
+
+            maps
+        }
+        other => {
+            warn!("IORT: root complex maps to unsupported node type {}", other);
+            Vec::new()
+        }
+    }
+}
+
+/// SPCR interrupt type bit for a GIC interrupt in `global_system_interrupt`
+const SPCR_IRQ_GIC: u8 = 1 << 3;
+
+fn handle_spcr(table: &[u8]) {
+    use log::*;
+
+    let (spcr, _) = Spcr::ref_from_prefix(table).expect("invalid spcr size");
+    let registers = spcr.base_addr();
+
+    // the same names a device tree would use
+    let (compatible, size) = match spcr.interface() {
+        SerialInterface::Pl011 => ("arm,pl011", 0x1000),
+        SerialInterface::SbsaGeneric | SerialInterface::SbsaGeneric32 => ("arm,sbsa-uart", 0x1000),
+        SerialInterface::Ns16550 | SerialInterface::Ns16550Subset => ("ns16550a", 8),
+        SerialInterface::Ns16550Gas => (
+            "ns16550a",
+            8 * (registers.register_bit_width() as usize / 8).max(1),
+        ),
+        SerialInterface::Other(other) => {
+            warn!("SPCR: unsupported console interface type {other:#x}");
+            return;
+        }
+    };
+
+    let base = registers.address() as usize;
+
+    let mut resources = vec![Resource::Mmio {
+        range: base..base + size,
+    }];
+    if spcr.interrupt_type() & SPCR_IRQ_GIC != 0 {
+        resources.push(Resource::Irq(Irq::new(spcr.global_system_interrupt())));
+    }
+
+    trace!("SPCR: {} console {:?}", compatible, resources);
+
+    let mut dt = DEVICE_TREE.borrow_mut();
+    dt.add_device(
+        None,
+        DeviceClass::Uart,
+        vec![String::from(compatible)],
+        resources,
+        Default::default(),
+    );
+}
+
+/// GTDT timer flags: bit 0 set for edge triggered, bit 1 for active low
+fn gtdt_irq(gsiv: u32, flags: u32) -> Irq {
+    Irq {
+        trigger: if flags & 0b01 != 0 {
+            IrqTrigger::Edge
+        } else {
+            IrqTrigger::Level
+        },
+        polarity: if flags & 0b10 != 0 {
+            IrqPolarity::ActiveLow
+        } else {
+            IrqPolarity::ActiveHigh
+        },
+        ..Irq::new(gsiv)
     }
 }
 
@@ -209,7 +627,10 @@ fn handle_gtdt(table: &[u8]) {
         None,
         DeviceClass::Timer,
         vec![String::from("arm,armv8-timer")],
-        vec![Resource::Irq(gtdt.virt_el1_gsiv())],
+        vec![Resource::Irq(gtdt_irq(
+            gtdt.virt_el1_gsiv(),
+            gtdt.virt_el1_flags(),
+        ))],
         Default::default(),
     );
 }
@@ -226,7 +647,8 @@ fn handle_madt(table: &[u8]) {
     }
 }
 
-fn handle_fadt(table: &[u8]) {
+/// returns the DSDT
+fn handle_fadt(table: &[u8]) -> &'static [u8] {
     use log::*;
 
     let (fadt, _) = Fadt::ref_from_prefix(table)
@@ -255,10 +677,46 @@ fn handle_fadt(table: &[u8]) {
         core::slice::from_raw_parts(dsdt_addr, len)
     };
 
-    handle_dsdt(dsdt_bytes);
+    dsdt_bytes
 }
 
-fn handle_dsdt(table: &[u8]) {
+/// load the DSDT and then every SSDT into one namespace
+fn load_aml(dsdt: Option<&[u8]>, ssdts: &[&[u8]]) {
+    use log::*;
+
+    let interpreter = register_interpreter(Interpreter::new(&AML_HANDLER));
+
+    match dsdt {
+        Some(dsdt) => handle_dsdt(interpreter, dsdt),
+        None => warn!("ACPI: no FADT, so no DSDT"),
+    }
+
+    for &ssdt in ssdts {
+        let (header, _) = SdtHeader::ref_from_prefix(ssdt).expect("table impossibly small");
+        let table_id = header.oem_table_id();
+        let table_id = core::str::from_utf8(&table_id).unwrap_or("????????");
+
+        if let Err(e) = header.check() {
+            error!("ACPI: skipping SSDT \"{}\": {}", table_id, e);
+            continue;
+        }
+
+        match interpreter.load_table(ssdt) {
+            Ok(()) => debug!("ACPI: SSDT \"{}\" loaded", table_id),
+            Err(e) => error!("ACPI: failed to load SSDT \"{}\": {}", table_id, e),
+        }
+    }
+
+    info!(
+        "ACPI: AML loaded from {} table(s), {} namespace objects",
+        dsdt.is_some() as usize + ssdts.len(),
+        interpreter.object_count()
+    );
+
+    TreeBuilder::new(&mut DEVICE_TREE.borrow_mut(), interpreter).build();
+}
+
+fn handle_dsdt(interpreter: &Interpreter, table: &[u8]) {
     use log::*;
 
     let (header, aml_bytes) = match SdtHeader::ref_from_prefix(table) {
@@ -284,6 +742,10 @@ fn handle_dsdt(table: &[u8]) {
     let mut output = String::new();
     format_aml_stream(&mut root_parser, 0, &mut output).unwrap();
     debug!("{}", output);
+
+    if let Err(e) = interpreter.load_table(table) {
+        error!("ACPI: failed to load the DSDT: {}", e);
+    }
 }
 
 fn aml_stream(parser: &mut AmlParser, depth: usize) -> Result<(), &'static str> {
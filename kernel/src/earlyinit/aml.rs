@@ -0,0 +1,145 @@
+use core::time::Duration;
+
+use klib::{hardware::mmio::map_mmio, time::monotonic};
+use mars_acpi_aml_driver::handler::{AmlHandler, PciAddress};
+use mars_pcie_driver::{
+    address::Bdf,
+    ecam::{Ecam, get_segment},
+};
+
+use crate::{
+    DEVICE_TREE,
+    power::{self, PowerAction},
+};
+
+/// the control method power button
+const POWER_BUTTON_HID: &str = "PNP0C0C";
+/// `Notify` value for a press, ACPI 6.5 section 5.6.6
+const NOTIFY_POWER_BUTTON_PRESSED: u64 = 0x80;
+
+/// hardware access for the AML interpreter
+pub struct KernelAmlHandler;
+
+pub static AML_HANDLER: KernelAmlHandler = KernelAmlHandler;
+
+fn spin_for(duration: Duration) {
+    let deadline = monotonic() + duration;
+    while monotonic() < deadline {
+        core::hint::spin_loop();
+    }
+}
+
+fn ecam_for(address: PciAddress) -> Result<(&'static Ecam, Bdf), &'static str> {
+    let ecam = get_segment(address.segment).ok_or("AML: no ECAM for PCI segment")?;
+    let bdf = Bdf::new(
+        address.segment,
+        address.bus,
+        address.device,
+        address.function,
+    );
+    Ok((ecam, bdf))
+}
+
+impl AmlHandler for KernelAmlHandler {
+    fn read_memory(&self, address: u64, width: u8) -> Result<u64, &'static str> {
+        let len = width as usize / 8;
+        let ptr = map_mmio(&(address as usize..address as usize + len))
+            .ok_or("AML: can't map SystemMemory region")?;
+
+        // SAFETY: the firmware described this range as a SystemMemory OperationRegion
+        unsafe {
+            Ok(match width {
+                8 => ptr.cast::<u8>().read_volatile() as u64,
+                16 => ptr.cast::<u16>().read_volatile() as u64,
+                32 => ptr.cast::<u32>().read_volatile() as u64,
+                64 => ptr.cast::<u64>().read_volatile(),
+                _ => return Err("AML: bad access width"),
+            })
+        }
+    }
+
+    fn write_memory(&self, address: u64, width: u8, value: u64) -> Result<(), &'static str> {
+        let len = width as usize / 8;
+        let ptr = map_mmio(&(address as usize..address as usize + len))
+            .ok_or("AML: can't map SystemMemory region")?;
+
+        // SAFETY: see `read_memory`
+        unsafe {
+            match width {
+                8 => ptr.cast::<u8>().write_volatile(value as u8),
+                16 => ptr.cast::<u16>().write_volatile(value as u16),
+                32 => ptr.cast::<u32>().write_volatile(value as u32),
+                64 => ptr.cast::<u64>().write_volatile(value),
+                _ => return Err("AML: bad access width"),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn read_pci(&self, address: PciAddress, offset: u16, width: u8) -> Result<u64, &'static str> {
+        let (ecam, bdf) = ecam_for(address)?;
+
+        Ok(match width {
+            8 => ecam.read_u8(bdf, offset) as u64,
+            16 => ecam.read_u16(bdf, offset) as u64,
+            32 => ecam.read_u32(bdf, offset) as u64,
+            64 => ecam.read_u32(bdf, offset) as u64 | (ecam.read_u32(bdf, offset + 4) as u64) << 32,
+            _ => return Err("AML: bad access width"),
+        })
+    }
+
+    fn write_pci(
+        &self,
+        address: PciAddress,
+        offset: u16,
+        width: u8,
+        value: u64,
+    ) -> Result<(), &'static str> {
+        let (ecam, bdf) = ecam_for(address)?;
+
+        match width {
+            8 => ecam.write_u8(bdf, offset, value as u8),
+            16 => ecam.write_u16(bdf, offset, value as u16),
+            32 => ecam.write_u32(bdf, offset, value as u32),
+            64 => {
+                ecam.write_u32(bdf, offset, value as u32);
+                ecam.write_u32(bdf, offset + 4, (value >> 32) as u32);
+            }
+            _ => return Err("AML: bad access width"),
+        }
+
+        Ok(())
+    }
+
+    fn stall(&self, us: u64) {
+        spin_for(Duration::from_micros(us));
+    }
+
+    // AML runs during early init, before there's a scheduler to yield to
+    fn sleep(&self, ms: u64) {
+        spin_for(Duration::from_millis(ms));
+    }
+
+    fn timer(&self) -> u64 {
+        (monotonic().as_nanos() / 100) as u64
+    }
+
+    fn notify(&self, path: &str, value: u64) {
+        use log::*;
+
+        let is_power_button = DEVICE_TREE.try_borrow().is_ok_and(|dt| {
+            dt.nodes.iter().any(|node| {
+                node.firmware_path.as_deref() == Some(path)
+                    && node.compatible.iter().any(|id| id == POWER_BUTTON_HID)
+            })
+        });
+
+        if is_power_button && value == NOTIFY_POWER_BUTTON_PRESSED {
+            info!("AML: power button pressed");
+            power::shutdown(PowerAction::Off);
+        }
+
+        debug!("AML: Notify ({path}, {value:#x}) ignored");
+    }
+}
@@ -1,6 +1,7 @@
 use arm_pl011_uart::{LineConfig, PL011Registers, Uart, UniqueMmioPointer};
-use core::{fmt::Write, ptr::NonNull};
+use core::{fmt, fmt::Write, ptr::NonNull};
 use klib::sync::FairSpinlock;
+use protocol::{SerialConsole, SerialKind, SerialLine};
 
 pub static EARLYCON: FairSpinlock<Option<EarlyCon>> = FairSpinlock::new(None);
 
@@ -9,7 +10,7 @@ macro_rules! earlycon_write {
     ($($arg:tt)*) => {{
         use core::fmt::Write;
         if let Some(uart) = crate::earlyinit::earlycon::EARLYCON.lock().as_mut() {
-            let _ = core::write!(uart.uart, $($arg)*);
+            let _ = core::write!(uart, $($arg)*);
         }
     }};
 }
@@ -19,7 +20,7 @@ macro_rules! earlycon_writeln {
     ($($arg:tt)*) => {{
         use core::fmt::Write;
         if let Some(uart) = crate::earlyinit::earlycon::EARLYCON.lock().as_mut() {
-            let _ = core::writeln!(uart.uart, $($arg)*);
+            let _ = core::writeln!(uart, $($arg)*);
         }
     }};
 }
@@ -34,32 +35,127 @@ macro_rules! earlycon_writeln_debug {
     }};
 }
 
-pub struct EarlyCon<'a> {
-    pub uart: Uart<'a>,
+/// polled UART the kernel logs through until (and after) drivers are up
+pub enum EarlyCon<'a> {
+    /// also drives the SBSA generic UART, which is a subset of it
+    Pl011(Uart<'a>),
+    Ns16550(Ns16550),
 }
 
 impl<'a> EarlyCon<'a> {
-    pub fn new(serial_uart_addr: usize) -> Self {
-        let uart_ptr = unsafe {
-            UniqueMmioPointer::new(NonNull::new(serial_uart_addr as *mut PL011Registers).unwrap())
-        };
-        let mut uart = Uart::new(uart_ptr);
+    pub fn new(console: &SerialConsole) -> Self {
+        let mut con = match console.kind {
+            SerialKind::Pl011 | SerialKind::SbsaGeneric => {
+                let uart_ptr = unsafe {
+                    UniqueMmioPointer::new(
+                        NonNull::new(console.address as *mut PL011Registers).unwrap(),
+                    )
+                };
+                let mut uart = Uart::new(uart_ptr);
+
+                // the SBSA UART has no line control, firmware's settings are all there is
+                if let (SerialKind::Pl011, Some(line)) = (console.kind, console.line) {
+                    let line_conf = LineConfig {
+                        data_bits: arm_pl011_uart::DataBits::Bits8,
+                        parity: arm_pl011_uart::Parity::None,
+                        stop_bits: arm_pl011_uart::StopBits::One,
+                    };
+                    _ = uart.enable(line_conf, line.baud, line.clock);
+                }
 
-        let line_conf = LineConfig {
-            data_bits: arm_pl011_uart::DataBits::Bits8,
-            parity: arm_pl011_uart::Parity::None,
-            stop_bits: arm_pl011_uart::StopBits::One,
+                Self::Pl011(uart)
+            }
+            SerialKind::Ns16550 { register_width } => {
+                let uart = Ns16550 {
+                    base: NonNull::new(console.address as *mut u8).unwrap(),
+                    register_width,
+                };
+                if let Some(line) = console.line {
+                    uart.configure(line);
+                }
+
+                Self::Ns16550(uart)
+            }
         };
-        _ = uart.enable(line_conf, 115_200, 16_000_000);
-        _ = writeln!(uart, "UART {:#x} enabled", serial_uart_addr);
 
-        Self { uart }
+        _ = writeln!(
+            con,
+            "UART {:#x} ({:?}) enabled",
+            console.address, console.kind
+        );
+
+        con
     }
+}
 
-    pub fn switch(&mut self, serial_uart_addr: usize) {
-        let uart_ptr = unsafe {
-            UniqueMmioPointer::new(NonNull::new(serial_uart_addr as *mut PL011Registers).unwrap())
-        };
-        self.uart = Uart::new(uart_ptr);
+impl fmt::Write for EarlyCon<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        match self {
+            Self::Pl011(uart) => uart.write_str(s),
+            Self::Ns16550(uart) => {
+                s.bytes().for_each(|b| uart.write_byte(b));
+                Ok(())
+            }
+        }
+    }
+}
+
+// 16550 registers, in units of the register width
+const NS16550_THR: usize = 0;
+const NS16550_DLL: usize = 0;
+const NS16550_DLM: usize = 1;
+const NS16550_LCR: usize = 3;
+const NS16550_LSR: usize = 5;
+
+const NS16550_LCR_8N1: u8 = 0b11;
+const NS16550_LCR_DLAB: u8 = 1 << 7;
+const NS16550_LSR_THRE: u8 = 1 << 5;
+
+pub struct Ns16550 {
+    base: NonNull<u8>,
+    /// 1 for byte wide registers, 4 for 32 bit ones
+    register_width: u8,
+}
+
+// SAFETY: only used under the `EARLYCON` lock
+unsafe impl Send for Ns16550 {}
+
+impl Ns16550 {
+    fn read(&self, reg: usize) -> u8 {
+        // SAFETY: `base` points at the UART's registers, as firmware described them
+        unsafe {
+            let ptr = self.base.as_ptr().add(reg * self.register_width as usize);
+            match self.register_width {
+                4 => ptr.cast::<u32>().read_volatile() as u8,
+                _ => ptr.read_volatile(),
+            }
+        }
+    }
+
+    fn write(&self, reg: usize, value: u8) {
+        // SAFETY: see `read`
+        unsafe {
+            let ptr = self.base.as_ptr().add(reg * self.register_width as usize);
+            match self.register_width {
+                4 => ptr.cast::<u32>().write_volatile(value as u32),
+                _ => ptr.write_volatile(value),
+            }
+        }
+    }
+
+    fn configure(&self, line: SerialLine) {
+        let divisor = line.clock / (16 * line.baud);
+
+        self.write(NS16550_LCR, NS16550_LCR_8N1 | NS16550_LCR_DLAB);
+        self.write(NS16550_DLL, divisor as u8);
+        self.write(NS16550_DLM, (divisor >> 8) as u8);
+        self.write(NS16550_LCR, NS16550_LCR_8N1);
+    }
+
+    fn write_byte(&self, byte: u8) {
+        while self.read(NS16550_LSR) & NS16550_LSR_THRE == 0 {
+            core::hint::spin_loop();
+        }
+        self.write(NS16550_THR, byte);
     }
 }
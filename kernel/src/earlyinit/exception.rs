@@ -66,6 +66,8 @@ impl ExceptionHandler for Exceptions {
 
             let regs = match ack {
                 Some(int) => {
+                    klib::random::add_interrupt_timing(int);
+
                     timer_disarm();
                     timer_rearm();
 
@@ -92,8 +94,14 @@ impl ExceptionHandler for Exceptions {
                     };
 
                     let regs = if is_timer {
+                        klib::net::tick();
+                        klib::sync::tick();
                         GLOBAL_SCHEDULER.schedule(register_file)
                     } else {
+                        if let Err(e) = gic.on_interrupt(int) {
+                            use log::warn;
+                            warn!("unhandled IRQ {}: {:?}", int, e);
+                        }
                         register_file
                     };
 
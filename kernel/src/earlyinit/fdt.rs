@@ -0,0 +1,437 @@
+//! hardware discovery from a flattened device tree, for boards without ACPI.
+//!
+//! produces the same `DeviceTree` that `acpi_init` does, so everything past this point doesn't
+//! care which one the firmware gave us.
+
+use core::sync::atomic::Ordering;
+
+use aarch64_cpu::registers::{MPIDR_EL1, Readable};
+use alloc::{string::String, vec, vec::Vec};
+use klib::{
+    allocator_support::KernelAddressTranslator,
+    cpu_interface::CpuTopologyId,
+    hardware::{
+        device::{DeviceClass, DeviceInitPriority, DeviceTree},
+        mmio::map_mmio,
+        resource::{AddressSpace, Irq, IrqPolarity, IrqTrigger, Resource},
+    },
+    interrupt::{GicdRegisters, GicrRegisters, GitsRegisters, gicv3::registers::gic::GicrTyper},
+    per_cpu::PerCpu,
+    pm::page::mapper::AddressTranslator,
+    smccc::USE_HVC,
+};
+use mars_fdt_driver::{Fdt, Node, NodeId, read_cells};
+use mars_models::memory::registers::volatile::PureReadable;
+use mars_pcie_driver::{
+    interrupt::{add_intx_routes, qemu_virt_intx_routes},
+    scan::{HostBridge, enumerate_segment},
+};
+use uefi::{Guid, guid};
+
+use crate::{
+    DEVICE_TREE,
+    earlyinit::{
+        acpi::{add_ecam_segment, config_table},
+        platform::BootInfoToken,
+    },
+};
+
+/// `EFI_DTB_TABLE_GUID`
+const DTB_GUID: Guid = guid!("b1b621d5-f19c-41a5-830b-d9152c69aae0");
+
+/// distance between GICv3 redistributors when `redistributor-stride` isn't given
+const GICR_STRIDE: usize = 0x2_0000;
+
+// GIC interrupt specifier types
+const GIC_SPI: u32 = 0;
+const GIC_PPI: u32 = 1;
+const GIC_ESPI: u32 = 2;
+const GIC_EPPI: u32 = 3;
+
+/// index of the virtual timer in the timer node's `interrupts`
+const TIMER_VIRT_INDEX: usize = 2;
+
+/// discover hardware from the device tree. panics if there isn't one.
+pub fn fdt_init(token: &BootInfoToken) {
+    use log::*;
+
+    let bi = token.get();
+
+    // EBBR has firmware put the blob in ACPI reclaim memory, which we haven't touched
+    let phys = bi
+        .device_tree
+        .or_else(|| {
+            config_table(bi.system_table_raw)
+                .iter()
+                .find(|t| t.guid == DTB_GUID)
+                .map(|t| t.address as usize)
+        })
+        .expect("no ACPI tables or device tree");
+
+    let header = KernelAddressTranslator.phys_to_dmap(phys) as *const u8;
+    let header = unsafe { core::slice::from_raw_parts(header, 8) };
+    let blob = Fdt::total_size(header)
+        .map(|len| unsafe { core::slice::from_raw_parts(header.as_ptr(), len) })
+        .unwrap_or_else(|e| panic!("DTB err: {}", e));
+    let fdt = Fdt::parse(blob).unwrap_or_else(|e| panic!("DTB err: {}", e));
+
+    info!(
+        "FDT: {} nodes at {:#x}, model \"{}\"",
+        fdt.nodes().len(),
+        phys,
+        fdt.root().property_str("model").unwrap_or("unknown")
+    );
+
+    let mut dt = DEVICE_TREE.borrow_mut();
+
+    handle_psci(&fdt);
+    handle_cpus(&fdt, &mut dt);
+
+    let gic = fdt
+        .nodes()
+        .iter()
+        .find(|n| n.is_compatible("arm,gic-v3") && n.is_enabled());
+    match gic {
+        Some(gic) => handle_gicv3(&fdt, gic, &mut dt),
+        None => {
+            error!("FDT: no GICv3 (unsupported)");
+            unimplemented!();
+        }
+    }
+    let gic = gic.map(|n| n.id);
+
+    for node in fdt.nodes().iter().skip(1) {
+        if !node.is_enabled() || node.compatible().next().is_none() || !on_platform_bus(&fdt, node)
+        {
+            continue;
+        }
+
+        if node.is_compatible("arm,gic-v3") || node.compatible().any(|c| c.starts_with("arm,psci"))
+        {
+            continue;
+        } else if node.is_compatible("arm,armv8-timer") {
+            handle_timer(&fdt, node, gic, &mut dt);
+        } else if node.is_compatible("pci-host-ecam-generic") {
+            handle_pcie(&fdt, node, &mut dt);
+        } else {
+            let resources = resources(&fdt, node, gic);
+            if resources.is_empty() {
+                continue;
+            }
+
+            trace!("FDT: {} {:?}", node.name, resources);
+
+            dt.add_device(
+                None,
+                class(node),
+                node.compatible().map(String::from).collect(),
+                resources,
+                Default::default(),
+            );
+        }
+    }
+}
+
+/// the node sits on the root or on simple buses, so its `reg` is CPU addresses
+fn on_platform_bus(fdt: &Fdt, node: &Node) -> bool {
+    let mut parent = fdt.parent(node);
+    while let Some(bus) = parent {
+        if bus.parent.is_some() && !bus.is_compatible("simple-bus") {
+            return false;
+        }
+        parent = fdt.parent(bus);
+    }
+    true
+}
+
+fn class(node: &Node) -> DeviceClass {
+    if node
+        .compatible()
+        .any(|c| matches!(c, "arm,pl011" | "arm,sbsa-uart" | "ns16550" | "ns16550a"))
+    {
+        DeviceClass::Uart
+    } else if node.is_compatible("arm,pl031") {
+        DeviceClass::Rtc
+    } else {
+        DeviceClass::Other
+    }
+}
+
+/// a GIC interrupt specifier: type, number, flags
+fn gic_irq(specifier: &[u32]) -> Option<Irq> {
+    let (&ty, &number) = (specifier.first()?, specifier.get(1)?);
+    let gsiv = match ty {
+        GIC_SPI => 32 + number,
+        GIC_PPI => 16 + number,
+        GIC_ESPI => 4096 + number,
+        GIC_EPPI => 1056 + number,
+        _ => return None,
+    };
+
+    // IRQ_TYPE_* in the low byte: 1 rising edge, 2 falling edge, 4 high level, 8 low level
+    let flags = specifier.get(2).copied().unwrap_or(0);
+    Some(Irq {
+        trigger: if flags & 0b0011 != 0 {
+            IrqTrigger::Edge
+        } else {
+            IrqTrigger::Level
+        },
+        polarity: if flags & 0b1010 != 0 {
+            IrqPolarity::ActiveLow
+        } else {
+            IrqPolarity::ActiveHigh
+        },
+        ..Irq::new(gsiv)
+    })
+}
+
+/// interrupts of `node` that go to `gic`
+fn gic_interrupts(fdt: &Fdt, node: &Node, gic: Option<NodeId>) -> Vec<Irq> {
+    fdt.interrupts(node)
+        .into_iter()
+        .filter(|(controller, _)| Some(controller.id) == gic)
+        .filter_map(|(_, specifier)| gic_irq(&specifier))
+        .collect()
+}
+
+fn resources(fdt: &Fdt, node: &Node, gic: Option<NodeId>) -> Vec<Resource> {
+    let mut resources: Vec<Resource> = fdt
+        .reg(node)
+        .into_iter()
+        .map(|r| Resource::Mmio {
+            range: r.address as usize..(r.address + r.size) as usize,
+        })
+        .collect();
+    resources.extend(
+        gic_interrupts(fdt, node, gic)
+            .into_iter()
+            .map(Resource::Irq),
+    );
+    resources
+}
+
+fn handle_psci(fdt: &Fdt) {
+    use log::*;
+
+    let Some(psci) = fdt
+        .nodes()
+        .iter()
+        .find(|n| n.compatible().any(|c| c.starts_with("arm,psci")))
+    else {
+        warn!("FDT: no PSCI, secondary CPUs won't come up");
+        return;
+    };
+
+    let hvc = psci.property_str("method") == Some("hvc");
+
+    trace!("    use HVC for PSCI?: {}", hvc);
+
+    USE_HVC.store(hvc, Ordering::Relaxed);
+}
+
+fn handle_cpus(fdt: &Fdt, dt: &mut DeviceTree) {
+    let cpus = fdt.find("/cpus").expect("device tree has no /cpus");
+    let address_cells = fdt.address_cells(cpus);
+
+    let cpu_topologies: Vec<CpuTopologyId> = fdt
+        .children(cpus)
+        .filter(|n| n.property_str("device_type") == Some("cpu") && n.is_enabled())
+        .filter_map(|n| {
+            let reg: Vec<u32> = n.property("reg")?.cells().take(address_cells).collect();
+            Some(CpuTopologyId::from_mpidr(read_cells(&reg)))
+        })
+        .collect();
+
+    PerCpu::init(cpu_topologies.len());
+
+    let current_topo = CpuTopologyId::from_mpidr(MPIDR_EL1.get());
+    for (i, &topo) in cpu_topologies.iter().enumerate() {
+        if topo == current_topo {
+            PerCpu::register_local(i).expect("invalid index");
+            break;
+        }
+    }
+
+    for (i, &id) in cpu_topologies.iter().enumerate() {
+        dt.add_device(
+            None,
+            // there's no ACPI UID, so the position in /cpus stands in for it
+            DeviceClass::Cpu {
+                id,
+                acpi_uid: i as u32,
+            },
+            Vec::new(),
+            Vec::new(),
+            DeviceInitPriority::Fundamental,
+        );
+    }
+}
+
+/// the distributor, then every redistributor, then the ITSes. same layout as the MADT gives.
+fn handle_gicv3(fdt: &Fdt, gic: &Node, dt: &mut DeviceTree) {
+    use log::*;
+
+    let regions = fdt.reg(gic);
+    let gicd = regions.first().expect("GICv3 node has no distributor");
+
+    let mut gic_resources = vec![Resource::Mmio {
+        range: gicd.address as usize..gicd.address as usize + size_of::<GicdRegisters>(),
+    }];
+    let mut redistributor_count = 0;
+
+    let region_count = gic.property_u32("#redistributor-regions").unwrap_or(1) as usize;
+    let stride = gic
+        .property("redistributor-stride")
+        .and_then(|p| p.as_u64())
+        .map_or(GICR_STRIDE, |s| s as usize);
+
+    for region in regions.iter().skip(1).take(region_count) {
+        let base = region.address as usize;
+        let size = region.size as usize;
+        let Some(frames) = map_mmio(&(base..base + size)) else {
+            error!("FDT: can't map redistributors at {:#x}", base);
+            continue;
+        };
+
+        // the region may be sized for more CPUs than there are
+        for offset in (0..size).step_by(stride) {
+            if offset + size_of::<GicrRegisters>() > size {
+                break;
+            }
+
+            let gicr_regs = unsafe { &*(frames.as_ptr().add(offset) as *const GicrRegisters) };
+            let last = gicr_regs
+                .type_
+                .read_field_pure(GicrTyper::LastRedistributor);
+
+            gic_resources.push(Resource::Mmio {
+                range: (base + offset)..(base + offset + size_of::<GicrRegisters>()),
+            });
+
+            redistributor_count += 1;
+
+            if last {
+                break;
+            }
+        }
+    }
+
+    for its in fdt
+        .children(gic)
+        .filter(|n| n.is_compatible("arm,gic-v3-its") && n.is_enabled())
+    {
+        if let Some(region) = fdt.reg(its).first() {
+            let base = region.address as usize;
+            gic_resources.push(Resource::Mmio {
+                range: base..base + size_of::<GitsRegisters>(),
+            });
+        }
+    }
+
+    dt.add_device(
+        None,
+        DeviceClass::GicV3 {
+            redistributor_count,
+        },
+        vec![String::from("arm,gic-v3")],
+        gic_resources,
+        DeviceInitPriority::Fundamental,
+    );
+}
+
+fn handle_timer(fdt: &Fdt, node: &Node, gic: Option<NodeId>, dt: &mut DeviceTree) {
+    use log::*;
+
+    let Some(&virt) = gic_interrupts(fdt, node, gic).get(TIMER_VIRT_INDEX) else {
+        error!("FDT: timer has no virtual timer interrupt");
+        return;
+    };
+
+    dt.add_device(
+        None,
+        DeviceClass::Timer,
+        vec![String::from("arm,armv8-timer")],
+        vec![Resource::Irq(virt)],
+        Default::default(),
+    );
+}
+
+fn handle_pcie(fdt: &Fdt, node: &Node, dt: &mut DeviceTree) {
+    use log::*;
+
+    let Some(ecam) = fdt.reg(node).first().copied() else {
+        error!("FDT: {} has no ECAM", node.name);
+        return;
+    };
+
+    let (start_bus, end_bus) = match node.property("bus-range") {
+        Some(range) => {
+            let mut cells = range.cells();
+            (
+                cells.next().unwrap_or(0) as u8,
+                cells.next().unwrap_or(0xFF) as u8,
+            )
+        }
+        // 1 MiB of ECAM per bus
+        None => (0, ((ecam.size >> 20).clamp(1, 256) - 1) as u8),
+    };
+    let segment = node.property_u32("linux,pci-domain").unwrap_or(0) as u16;
+
+    let ecam = add_ecam_segment(ecam.address, segment, start_bus, end_bus);
+    let host = HostBridge {
+        node: None,
+        buses: start_bus..=end_bus,
+        windows: pcie_windows(fdt, node),
+    };
+    enumerate_segment(ecam, &[host], dt);
+
+    // QEMU virt's `interrupt-map` is a fixed swizzle onto four SPIs
+    if fdt.root().is_compatible("linux,dummy-virt") {
+        add_intx_routes(qemu_virt_intx_routes(segment, start_bus));
+    } else {
+        debug!("FDT: {}: no INTx routing", node.name);
+    }
+}
+
+/// the host bridge's apertures, from `ranges`. see the PCI bus binding: the first cell of the
+/// PCI address says which space it's in.
+fn pcie_windows(fdt: &Fdt, node: &Node) -> Vec<Resource> {
+    let (Some(parent), Some(ranges)) = (fdt.parent(node), node.property("ranges")) else {
+        return Vec::new();
+    };
+
+    let child_cells = fdt.address_cells(node);
+    let parent_cells = fdt.address_cells(parent);
+    let size_cells = fdt.size_cells(node);
+    if child_cells != 3 {
+        return Vec::new();
+    }
+
+    let cells: Vec<u32> = ranges.cells().collect();
+    cells
+        .chunks_exact(child_cells + parent_cells + size_cells)
+        .filter_map(|entry| {
+            let flags = entry[0];
+            let pci = read_cells(&entry[1..child_cells]);
+            let cpu = fdt.translate(
+                parent,
+                read_cells(&entry[child_cells..child_cells + parent_cells]),
+            )?;
+            let len = read_cells(&entry[child_cells + parent_cells..]);
+
+            let space = match (flags >> 24) & 0b11 {
+                0b01 => AddressSpace::Io,
+                0b10 | 0b11 => AddressSpace::Memory {
+                    prefetchable: flags & (1 << 30) != 0,
+                },
+                _ => return None,
+            };
+
+            Some(Resource::Window {
+                space,
+                range: pci..pci + len,
+                translation: cpu.wrapping_sub(pci),
+            })
+        })
+        .collect()
+}
@@ -1,10 +1,14 @@
 pub mod acpi;
+pub mod aml;
 pub mod earlycon;
 pub mod exception;
+pub mod fdt;
 pub mod gicv3;
 pub mod idle;
 pub mod mem;
 pub mod mmu;
+pub mod numa;
 pub mod platform;
 pub mod smp;
+pub mod topology;
 pub mod uefi;
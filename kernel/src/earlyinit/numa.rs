@@ -0,0 +1,113 @@
+//! NUMA nodes from the SRAT, and the distances between them from the SLIT.
+
+use alloc::{collections::btree_map::BTreeMap, vec::Vec};
+use klib::{
+    cpu_interface::CpuTopologyId,
+    hardware::device::{DeviceClass, DeviceTree},
+    pm::{
+        numa::{self, MAX_NODES, NodeId},
+        page::kernel_page_allocator,
+    },
+    vm::PAGE_SIZE,
+};
+use mars_acpi_driver::acpi::{
+    slit::{SLIT_UNREACHABLE, Slit},
+    srat::{SRAT_ENABLED, Srat, SratEntry},
+};
+use zerocopy::FromBytes;
+
+/// record the nodes and hand them to the page allocator. GICC entries are matched up by the
+/// ACPI processor UIDs the MADT gave the CPUs, so the MADT has to have been handled.
+pub fn init_from_acpi(srat: &[u8], slit: Option<&[u8]>, dt: &DeviceTree) {
+    use log::*;
+
+    let Ok((table, _)) = Srat::ref_from_prefix(srat) else {
+        warn!("SRAT: too short");
+        return;
+    };
+
+    let uids: BTreeMap<u32, CpuTopologyId> = dt
+        .nodes
+        .iter()
+        .filter_map(|node| match node.class {
+            DeviceClass::Cpu { id, acpi_uid } => Some((acpi_uid, id)),
+            _ => None,
+        })
+        .collect();
+
+    for entry in table.entries(srat) {
+        match entry {
+            SratEntry::Memory(memory) if memory.flags() & SRAT_ENABLED != 0 => {
+                let base = memory.base() as usize;
+                let end = base + memory.length() as usize;
+                trace!(
+                    "SRAT: {:#x}..{:#x} in domain {}",
+                    base,
+                    end,
+                    memory.proximity_domain()
+                );
+                numa::add_memory(base..end, memory.proximity_domain());
+            }
+            SratEntry::Gicc(gicc) if gicc.flags() & SRAT_ENABLED != 0 => {
+                let Some(&cpu) = uids.get(&gicc.acpi_processor_uid()) else {
+                    warn!("SRAT: no CPU with ACPI UID {}", gicc.acpi_processor_uid());
+                    continue;
+                };
+                numa::add_cpu(cpu, gicc.proximity_domain());
+            }
+            SratEntry::Memory(_) | SratEntry::Gicc(_) => {}
+            SratEntry::Other(ty) => debug!("SRAT: skipping entry type {}", ty),
+        }
+    }
+
+    if let Some(slit) = slit {
+        handle_slit(slit);
+    }
+
+    let node_count = numa::node_count();
+    let Some(allocator) = kernel_page_allocator() else {
+        warn!("NUMA: no page allocator to tell about {} nodes", node_count);
+        return;
+    };
+
+    allocator.set_nodes(node_count, &numa::memory(), &numa::distances());
+
+    for node in 0..node_count as NodeId {
+        let stats = allocator.node_stats(node);
+        info!(
+            "NUMA: node {} (domain {}): {} MiB",
+            node,
+            numa::domain_of(node).unwrap_or(0),
+            stats.total_pages * PAGE_SIZE / (1024 * 1024)
+        );
+    }
+}
+
+fn handle_slit(bytes: &[u8]) {
+    use log::*;
+
+    let Ok((slit, _)) = Slit::ref_from_prefix(bytes) else {
+        warn!("SLIT: too short");
+        return;
+    };
+
+    let nodes = (0..numa::node_count().min(MAX_NODES) as NodeId)
+        .filter_map(|node| Some((node, numa::domain_of(node)?)))
+        .collect::<Vec<_>>();
+
+    for &(from, from_domain) in &nodes {
+        for &(to, to_domain) in &nodes {
+            let Some(distance) = slit.distance(bytes, from_domain, to_domain) else {
+                warn!(
+                    "SLIT: no distance from domain {} to {}",
+                    from_domain, to_domain
+                );
+                continue;
+            };
+
+            if distance != SLIT_UNREACHABLE {
+                numa::set_distance(from, to, distance);
+            }
+        }
+    }
+}
@@ -1,32 +1,43 @@
-use core::{borrow::Borrow, mem::MaybeUninit};
+use core::{borrow::Borrow, mem::MaybeUninit, ops::Range, ptr::NonNull};
 
+use aarch64_cpu_ext::structures::tte::{AccessPermission, Shareability};
 use klib::{
+    allocator_support::KernelAddressTranslator,
     cpu_interface::{CpuTopologyId, init_cpu_maps},
     hardware::{
         device::{DeviceClass, DeviceInitPriority, DeviceNode, IrqFn},
         irq::CallbackError,
+        mmio::set_mmio_mapper,
         resource::Resource,
     },
     interrupt::singleton::get_interrupt_controller,
+    pm::page::{
+        mapper::{AddressTranslator, map_page},
+        set_kernel_page_allocator,
+    },
     scheduler::GLOBAL_SCHEDULER,
     stack::Stack,
+    sync::FairSpinlock,
     this_cpu,
-    vm::{PAGE_SIZE, user::PAGE_DESCRIPTORS},
+    vm::{MAIR_DEVICE_INDEX, PAGE_SIZE, align_down, align_up, user::PAGE_DESCRIPTORS},
 };
 use protocol::BootInfo;
 use uefi::mem::memory_map::{MemoryMap, MemoryMapMut};
 
 use crate::{
-    __KBASE, DEVICE_TREE, KALLOCATOR, KERNEL_ADDRESS_SPACE,
+    __KBASE, DEVICE_TREE, DRIVER_MANAGER, KALLOCATOR, KERNEL_ADDRESS_SPACE,
     earlyinit::{
         acpi::acpi_init,
         earlycon::{EARLYCON, EarlyCon},
+        fdt::fdt_init,
         mem::{
             clone_and_process_mmap, create_page_descriptors, populate_alloc_stage0,
             populate_alloc_stage1, switch_to_new_page_tables,
         },
         mmu::init_mmu,
         smp::boot_secondary,
+        topology,
+        uefi::init_runtime_services,
     },
     log::LOGGER,
     lut::{DEVICE_TABLE, DeviceCallback},
@@ -147,10 +158,12 @@ pub fn uefi_arm64_bootstrap(mut boot_info_token: BootInfoToken) {
 
     let boot_info = boot_info_token.get_mut();
     let load_addr = boot_info.kernel_load_physical_address;
+    // taken so the seed doesn't linger in the boot info
+    let rng_seed = boot_info.rng_seed.take();
 
     {
         let mut lock = EARLYCON.lock();
-        *lock = Some(EarlyCon::new(boot_info.serial_uart_address));
+        *lock = Some(EarlyCon::new(&boot_info.serial_console));
     }
 
     LOGGER
@@ -195,8 +208,17 @@ pub fn uefi_arm64_bootstrap(mut boot_info_token: BootInfoToken) {
     PAGE_DESCRIPTORS.init(page_descriptors, range);
 
     KERNEL_ADDRESS_SPACE.init_from_table(new_pt);
+    set_mmio_mapper(map_device_mmio);
+    set_kernel_page_allocator(KALLOCATOR.page_alloc());
+
+    init_runtime_services(&uefi_mmap, boot_info_token.get().system_table_raw);
 
-    acpi_init(&boot_info_token);
+    klib::vfs::mount::mount("dev", klib::vfs::devfs::root()).expect("can't mount devfs");
+    klib::random::init(rng_seed.as_ref().map(|seed| seed.as_slice()));
+
+    if !acpi_init(&boot_info_token) {
+        fdt_init(&boot_info_token);
+    }
 
     {
         let dt = DEVICE_TREE.borrow();
@@ -230,6 +252,8 @@ pub fn uefi_arm64_bootstrap(mut boot_info_token: BootInfoToken) {
         };
 
         init_cpu_maps(create_cpu_iter());
+        topology::init_from_registers(create_cpu_iter());
+        klib::pm::numa::init_cpu_nodes();
 
         info!("Waking up secondary CPUs.");
         for cpu in create_cpu_iter() {
@@ -248,9 +272,54 @@ pub fn uefi_arm64_bootstrap(mut boot_info_token: BootInfoToken) {
                 .expect("error booting secondary core")
             };
         }
+
+        // drivers may spread MSIs across every core, so bind once they're all up
+        mars_pcie_driver::aer::init(&dt);
+        DRIVER_MANAGER.borrow_mut().bind_drivers(&dt);
     }
 
     GLOBAL_SCHEDULER.register_cpu(this_cpu!().id);
+
+    // these spawn threads, so the scheduler has to know about this core first
+    klib::net::init();
+    mars_acpi_aml_driver::ged::start();
+    mars_pcie_driver::aer::start();
+    crate::shell::spawn();
+}
+
+/// serializes edits to the kernel root table made through `map_device_mmio`
+static MMIO_MAP_LOCK: FairSpinlock<()> = FairSpinlock::new(());
+
+/// map device MMIO into the direct map. see `handle_mcfg` for why this bypasses `AddressSpace`.
+fn map_device_mmio(range: &Range<usize>) -> Option<NonNull<u8>> {
+    if range.is_empty() {
+        return None;
+    }
+
+    let start = align_down(range.start, PAGE_SIZE);
+    let end = align_up(range.end, PAGE_SIZE);
+
+    {
+        let _lock = MMIO_MAP_LOCK.lock();
+        let root = unsafe { KERNEL_ADDRESS_SPACE.root_mut() };
+
+        for pa in (start..end).step_by(PAGE_SIZE) {
+            map_page(
+                root,
+                pa,
+                KernelAddressTranslator.phys_to_dmap(pa) as usize,
+                AccessPermission::PrivilegedReadWrite,
+                Shareability::OuterShareable,
+                true,
+                true,
+                MAIR_DEVICE_INDEX,
+                &KERNEL_ADDRESS_SPACE.allocator,
+                &KernelAddressTranslator,
+            );
+        }
+    }
+
+    NonNull::new(KernelAddressTranslator.phys_to_dmap(range.start))
 }
 
 pub fn filter_fundamental<T>(n: &T) -> bool
@@ -274,9 +343,11 @@ pub const ENABLE_IRQ: IrqFn = |resource| {
 
     let result = match resource {
         Resource::Irq(irq) => {
-            ic.enable_interrupt(*irq)
+            ic.set_trigger(irq.gsiv, irq.trigger)
+                .map_err(|_| CallbackError::FailedToEnable)?;
+            ic.enable_interrupt(irq.gsiv)
                 .map_err(|_| CallbackError::FailedToEnable)?;
-            trace!("device driver enabled IRQ {}", irq);
+            trace!("device driver enabled IRQ {} ({:?})", irq.gsiv, irq.trigger);
 
             Ok(())
         }
@@ -296,9 +367,9 @@ pub const DISABLE_IRQ: IrqFn = |resource| {
 
     let result = match resource {
         Resource::Irq(irq) => {
-            ic.disable_interrupt(*irq)
+            ic.disable_interrupt(irq.gsiv)
                 .map_err(|_| CallbackError::FailedToDisable)?;
-            trace!("device driver disabled IRQ {}", irq);
+            trace!("device driver disabled IRQ {}", irq.gsiv);
 
             Ok(())
         }
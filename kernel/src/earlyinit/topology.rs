@@ -0,0 +1,323 @@
+//! the CPU topology from the PPTT, or from the CPUs' own registers without one.
+
+use alloc::{
+    collections::{btree_map::BTreeMap, btree_set::BTreeSet},
+    vec::Vec,
+};
+use klib::{
+    cpu_interface::{
+        CacheInfo, CacheKind, CpuTopology, CpuTopologyId, TopologyLevel, cpu_topology,
+        init_cpu_topology,
+    },
+    hardware::device::{DeviceClass, DeviceTree},
+};
+use mars_acpi_driver::acpi::pptt::{
+    PPTT_ACPI_ID_VALID, PPTT_CACHE_ASSOCIATIVITY_VALID, PPTT_CACHE_LINE_SIZE_VALID,
+    PPTT_CACHE_SETS_VALID, PPTT_CACHE_SIZE_VALID, PPTT_CACHE_TYPE_DATA,
+    PPTT_CACHE_TYPE_INSTRUCTION, PPTT_CACHE_TYPE_VALID, PPTT_LEAF, PPTT_PHYSICAL_PACKAGE,
+    PPTT_THREAD, Pptt, PpttCache, PpttEntry, PpttProcessor,
+};
+use zerocopy::FromBytes;
+
+/// deeper cache chains than this are taken as firmware bugs
+const MAX_CACHE_LEVEL: u8 = 8;
+
+struct Processor<'a> {
+    entry: &'a PpttProcessor,
+    resources: Vec<usize>,
+}
+
+/// build the topology from the PPTT. the CPUs are matched up by the ACPI processor UIDs the
+/// MADT gave them, so the MADT has to have been handled.
+pub fn init_from_pptt(table: &[u8], dt: &DeviceTree) {
+    use log::*;
+
+    let Ok((pptt, _)) = Pptt::ref_from_prefix(table) else {
+        warn!("PPTT: too short");
+        return;
+    };
+
+    let mut processors = BTreeMap::new();
+    let mut caches = BTreeMap::new();
+    for entry in pptt.entries(table) {
+        match entry {
+            PpttEntry::Processor(offset, entry, bytes) => {
+                let resources = entry.private_resources(bytes).collect();
+                processors.insert(offset, Processor { entry, resources });
+            }
+            PpttEntry::Cache(offset, cache) => {
+                caches.insert(offset, cache);
+            }
+            PpttEntry::Other(offset, ty) => {
+                debug!("PPTT: skipping entry type {} at {:#x}", ty, offset)
+            }
+        }
+    }
+
+    let uids: BTreeMap<u32, CpuTopologyId> = dt
+        .nodes
+        .iter()
+        .filter_map(|node| match node.class {
+            DeviceClass::Cpu { id, acpi_uid } => Some((acpi_uid, id)),
+            _ => None,
+        })
+        .collect();
+
+    let topology = build(&processors, &caches, &uids);
+    info!(
+        "PPTT: {} topology nodes, {} of them CPUs",
+        topology.nodes().len(),
+        topology.nodes().iter().filter(|n| n.cpu.is_some()).count()
+    );
+    init_cpu_topology(topology);
+}
+
+/// the fallback without a PPTT. a no-op if one was found.
+pub fn init_from_registers(cpus: impl IntoIterator<Item = CpuTopologyId>) {
+    use log::*;
+
+    if !cpu_topology().is_empty() {
+        return;
+    }
+
+    let topology = CpuTopology::from_registers(cpus);
+    debug!(
+        "topology: {} nodes from MPIDR_EL1 and CLIDR_EL1",
+        topology.nodes().len()
+    );
+    init_cpu_topology(topology);
+}
+
+fn build(
+    processors: &BTreeMap<usize, Processor>,
+    caches: &BTreeMap<usize, &PpttCache>,
+    uids: &BTreeMap<u32, CpuTopologyId>,
+) -> CpuTopology {
+    let parent_of = |offset: usize| {
+        let parent = processors.get(&offset)?.entry.parent() as usize;
+        processors.contains_key(&parent).then_some(parent)
+    };
+
+    // anything a parent points at isn't a leaf, whatever its flags say. the value is whether
+    // its children are threads, which makes it a core.
+    let mut parents = BTreeMap::new();
+    for (&offset, processor) in processors {
+        if let Some(parent) = parent_of(offset) {
+            *parents.entry(parent).or_insert(false) |= processor.entry.flags() & PPTT_THREAD != 0;
+        }
+    }
+
+    // nodes above a package group packages
+    let mut above_package = BTreeSet::new();
+    for (&offset, processor) in processors {
+        if processor.entry.flags() & PPTT_PHYSICAL_PACKAGE != 0 {
+            let mut parent = parent_of(offset);
+            while let Some(p) = parent
+                && above_package.insert(p)
+            {
+                parent = parent_of(p);
+            }
+        }
+    }
+
+    // and whether it's a leaf, which only CPUs are
+    let level_of = |offset: usize, flags: u32| {
+        let leaf = flags & PPTT_LEAF != 0 || !parents.contains_key(&offset);
+        let level = if leaf && flags & PPTT_THREAD != 0 {
+            TopologyLevel::Thread
+        } else if leaf || parents.get(&offset) == Some(&true) {
+            TopologyLevel::Core
+        } else if above_package.contains(&offset) {
+            TopologyLevel::System
+        } else if flags & PPTT_PHYSICAL_PACKAGE != 0 {
+            TopologyLevel::Package
+        } else {
+            TopologyLevel::Cluster
+        };
+        (level, leaf)
+    };
+
+    // which node each cache belongs to: the one listing it, or else the node of the cache in
+    // front of it
+    let mut owners = BTreeMap::new();
+    for (&offset, processor) in processors {
+        for resource in processor
+            .resources
+            .iter()
+            .filter(|r| caches.contains_key(r))
+        {
+            owners.insert(*resource, offset);
+        }
+    }
+    for _ in 0..MAX_CACHE_LEVEL {
+        for (&offset, cache) in caches {
+            let next = cache.next_level() as usize;
+            if caches.contains_key(&next)
+                && !owners.contains_key(&next)
+                && let Some(&owner) = owners.get(&offset)
+            {
+                owners.insert(next, owner);
+            }
+        }
+    }
+
+    let mut levels = BTreeMap::new();
+    for &offset in caches.keys() {
+        cache_level(offset, caches, processors, &owners, &mut levels, 0);
+    }
+
+    let mut topology = CpuTopology::new();
+    let mut indices = BTreeMap::new();
+    for &offset in processors.keys() {
+        add_node(
+            offset,
+            processors,
+            &mut topology,
+            &mut indices,
+            &level_of,
+            uids,
+        );
+    }
+
+    for (&cache, &owner) in &owners {
+        let (Some(&index), Some(&level)) = (indices.get(&owner), levels.get(&cache)) else {
+            continue;
+        };
+        topology
+            .node_mut(index)
+            .caches
+            .push(cache_info(caches[&cache], level));
+    }
+
+    for index in 0..topology.nodes().len() {
+        topology.node_mut(index).caches.sort_by_key(|c| c.level);
+    }
+
+    topology
+}
+
+/// add the node at `offset`, and its parents before it
+fn add_node(
+    offset: usize,
+    processors: &BTreeMap<usize, Processor>,
+    topology: &mut CpuTopology,
+    indices: &mut BTreeMap<usize, usize>,
+    level_of: &impl Fn(usize, u32) -> (TopologyLevel, bool),
+    uids: &BTreeMap<u32, CpuTopologyId>,
+) -> usize {
+    if let Some(&index) = indices.get(&offset) {
+        return index;
+    }
+
+    // a loop of parents would recurse forever, so claim the offset before going up
+    indices.insert(offset, usize::MAX);
+
+    let entry = processors[&offset].entry;
+    let parent = entry.parent() as usize;
+    let parent = if processors.contains_key(&parent) {
+        Some(add_node(
+            parent, processors, topology, indices, level_of, uids,
+        ))
+        .filter(|&p| p != usize::MAX)
+    } else {
+        None
+    };
+
+    // inner nodes' IDs name containers, they can clash with processor UIDs
+    let (level, leaf) = level_of(offset, entry.flags());
+    let cpu = (leaf && entry.flags() & PPTT_ACPI_ID_VALID != 0)
+        .then(|| uids.get(&entry.acpi_processor_id()).copied())
+        .flatten();
+
+    let index = topology.add(parent, level, Vec::new(), cpu);
+    indices.insert(offset, index);
+    index
+}
+
+/// a cache is one level past the caches that point at it. one nothing points at is level 1
+/// at a leaf, and one past the caches below its node otherwise.
+fn cache_level(
+    offset: usize,
+    caches: &BTreeMap<usize, &PpttCache>,
+    processors: &BTreeMap<usize, Processor>,
+    owners: &BTreeMap<usize, usize>,
+    levels: &mut BTreeMap<usize, u8>,
+    depth: u8,
+) -> u8 {
+    if let Some(&level) = levels.get(&offset) {
+        return level;
+    }
+    if depth >= MAX_CACHE_LEVEL {
+        return MAX_CACHE_LEVEL;
+    }
+
+    let mut before = caches
+        .iter()
+        .filter(|(_, cache)| cache.next_level() as usize == offset)
+        .map(|(&c, _)| c)
+        .collect::<Vec<_>>();
+
+    if before.is_empty() {
+        // the caches of the nodes below this one's
+        let owner = owners.get(&offset).copied();
+        before = owners
+            .iter()
+            .filter(|&(_, &o)| is_below(o, owner, processors))
+            .map(|(&c, _)| c)
+            .collect();
+    }
+
+    let level = before
+        .into_iter()
+        .map(|c| cache_level(c, caches, processors, owners, levels, depth + 1))
+        .max()
+        .unwrap_or(0)
+        + 1;
+
+    levels.insert(offset, level);
+    level
+}
+
+/// whether processor node `node` is somewhere below `ancestor`
+fn is_below(node: usize, ancestor: Option<usize>, processors: &BTreeMap<usize, Processor>) -> bool {
+    let Some(ancestor) = ancestor else {
+        return false;
+    };
+
+    let mut current = node;
+    for _ in 0..processors.len() {
+        let Some(processor) = processors.get(&current) else {
+            return false;
+        };
+        let parent = processor.entry.parent() as usize;
+        if parent == ancestor {
+            return true;
+        }
+        current = parent;
+    }
+    false
+}
+
+fn cache_info(cache: &PpttCache, level: u8) -> CacheInfo {
+    let flags = cache.flags();
+    let valid = |flag: u32| flags & flag != 0;
+
+    let kind = match cache.cache_type() {
+        _ if !valid(PPTT_CACHE_TYPE_VALID) => CacheKind::Unified,
+        PPTT_CACHE_TYPE_DATA => CacheKind::Data,
+        PPTT_CACHE_TYPE_INSTRUCTION => CacheKind::Instruction,
+        _ => CacheKind::Unified,
+    };
+
+    // fields firmware didn't fill in read as 0
+    let field = |flag: u32, value: u32| if valid(flag) { value } else { 0 };
+
+    CacheInfo {
+        level,
+        kind,
+        size: field(PPTT_CACHE_SIZE_VALID, cache.size()) as usize,
+        line_size: field(PPTT_CACHE_LINE_SIZE_VALID, cache.line_size() as u32),
+        sets: field(PPTT_CACHE_SETS_VALID, cache.sets()),
+        associativity: field(PPTT_CACHE_ASSOCIATIVITY_VALID, cache.associativity() as u32),
+    }
+}
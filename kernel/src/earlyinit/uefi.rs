@@ -1 +1,102 @@
+//! UEFI runtime services: map the firmware's runtime regions 1:1 in an address space of their
+//! own and tell the firmware with `SetVirtualAddressMap`.
 
+use core::ptr::NonNull;
+
+use aarch64_cpu_ext::structures::tte::{AccessPermission, Shareability};
+use alloc::vec::Vec;
+use klib::{
+    allocator_support::KernelAddressTranslator,
+    efi,
+    pm::page::mapper::{AddressTranslator, map_region},
+    time,
+    vm::{
+        MAIR_DEVICE_INDEX, MAIR_NORMAL_INDEX, PAGE_SIZE, align_down, align_up,
+        user::address_space::AddressSpace,
+    },
+};
+use uefi::{
+    boot::{MemoryAttribute, MemoryDescriptor, MemoryType, PAGE_SIZE as UEFI_PS},
+    mem::memory_map::MemoryMap,
+};
+use uefi_raw::table::system::SystemTable;
+
+use crate::{KPAGE_ALLOCATOR, KPT_ALLOCATOR};
+
+/// `map` has to still describe the runtime regions as the firmware reported them. nothing
+/// but their virtual addresses is looked at once boot services are gone.
+pub fn init_runtime_services<M: MemoryMap>(map: &M, st: NonNull<SystemTable>) {
+    use log::*;
+
+    let st = KernelAddressTranslator.phys_to_dmap(st.as_ptr() as _) as *const SystemTable;
+    let Some(services) = NonNull::new(unsafe { (*st).runtime_services } as *mut u8) else {
+        info!("UEFI: no runtime services");
+        return;
+    };
+
+    let space = AddressSpace::new(
+        None,
+        &KPT_ALLOCATOR,
+        &KPAGE_ALLOCATOR,
+        &KernelAddressTranslator,
+    );
+    let root = unsafe { space.root_mut() };
+
+    let mut runtime = Vec::new();
+    for desc in map
+        .entries()
+        .filter(|desc| desc.att.contains(MemoryAttribute::RUNTIME))
+    {
+        let start = desc.phys_start as usize;
+        let end = start + desc.page_count as usize * UEFI_PS;
+
+        // firmware has to align runtime regions to 64KiB on arm64, so this never takes in a
+        // neighbour
+        let (share, attr_index) = if desc.att.contains(MemoryAttribute::WRITE_BACK) {
+            (Shareability::InnerShareable, MAIR_NORMAL_INDEX)
+        } else {
+            (Shareability::OuterShareable, MAIR_DEVICE_INDEX)
+        };
+        let pxn = desc.ty != MemoryType::RUNTIME_SERVICES_CODE;
+
+        let map_start = align_down(start, PAGE_SIZE);
+        map_region(
+            root,
+            map_start,
+            map_start,
+            align_up(end, PAGE_SIZE) - map_start,
+            // the firmware relocates its code in place in `SetVirtualAddressMap`
+            AccessPermission::PrivilegedReadWrite,
+            share,
+            true,
+            pxn,
+            attr_index,
+            &space.allocator,
+            &KernelAddressTranslator,
+        );
+
+        trace!("UEFI: runtime {:?} at {:#x}..{:#x}", desc.ty, start, end);
+        runtime.push(MemoryDescriptor {
+            virt_start: desc.phys_start,
+            ..*desc
+        });
+    }
+
+    if runtime.is_empty() {
+        warn!("UEFI: runtime services, but no runtime regions");
+        return;
+    }
+
+    match efi::install(services, space, &runtime) {
+        Ok(()) => info!("UEFI: runtime services in {} regions", runtime.len()),
+        Err(e) => {
+            warn!("UEFI: SetVirtualAddressMap failed: {}", e);
+            return;
+        }
+    }
+
+    match efi::realtime() {
+        Ok(now) => time::set_realtime(now),
+        Err(e) => debug!("UEFI: no time: {}", e),
+    }
+}
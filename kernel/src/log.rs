@@ -1,3 +1,4 @@
+use klib::fbcon;
 use log::{Level, LevelFilter, SetLoggerError};
 
 use crate::earlycon_writeln;
@@ -46,9 +47,25 @@ impl log::Log for Logger {
                         line,
                         record.args()
                     );
+                    fbcon::write_fmt(format_args!(
+                        "{}[{:>5}]{} {}:{}: {}\n",
+                        color,
+                        level,
+                        RESET_CODE,
+                        file,
+                        line,
+                        record.args()
+                    ));
                 }
                 _ => {
                     earlycon_writeln!("{}[{:>5}]{} {}", color, level, RESET_CODE, record.args());
+                    fbcon::write_fmt(format_args!(
+                        "{}[{:>5}]{} {}\n",
+                        color,
+                        level,
+                        RESET_CODE,
+                        record.args()
+                    ));
                 }
             }
         }
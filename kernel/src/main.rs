@@ -8,6 +8,8 @@ mod allocator;
 mod earlyinit;
 mod log;
 mod lut;
+mod power;
+mod shell;
 
 use aarch64_cpu::asm::wfe;
 use atomic_refcell::AtomicRefCell;
@@ -18,7 +20,7 @@ use core::{
 use klib::{
     allocator_support::KernelAddressTranslator,
     cpu_interface::{CpuIdLogical, CpuTopologyId},
-    hardware::device::DeviceTree,
+    hardware::{device::DeviceTree, driver::DriverManager},
     pm::page::PageAllocator,
     register_drivers,
     vm::{slab::SlabAllocator, user::address_space::AddressSpace},
@@ -70,7 +72,22 @@ fn panic(info: &PanicInfo) -> ! {
     busy_loop()
 }
 
-register_drivers!([]);
+register_drivers!([
+    mars_acpi_aml_driver::ged::GED_DRIVER,
+    mars_fw_cfg_driver::FW_CFG_DRIVER,
+    mars_pl011_driver::PL011_DRIVER,
+    mars_pl031_driver::PL031_DRIVER,
+    mars_smmu_driver::SMMU_V3_DRIVER,
+    mars_virtio_driver::gpu::VIRTIO_GPU_DRIVER,
+    mars_virtio_driver::input::VIRTIO_INPUT_DRIVER,
+    mars_virtio_driver::net::VIRTIO_NET_DRIVER,
+    mars_virtio_driver::ninep::VIRTIO_9P_DRIVER,
+    mars_virtio_driver::rng::VIRTIO_RNG_DRIVER,
+    mars_xhci_driver::XHCI_DRIVER,
+]);
+
+static DRIVER_MANAGER: AtomicRefCell<DriverManager> =
+    AtomicRefCell::new(DriverManager::new(DRIVERS));
 
 #[allow(dead_code)]
 fn busy_loop() -> ! {
@@ -0,0 +1,80 @@
+//! orderly shutdown and reboot
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use aarch64_cpu::asm::wfe;
+use klib::{
+    block::{Command, IoRequest, devices},
+    efi::{self, ResetKind},
+    scheduler::GLOBAL_SCHEDULER,
+    smccc,
+};
+
+use crate::DRIVER_MANAGER;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PowerAction {
+    Off,
+    Reboot,
+}
+
+static SHUTTING_DOWN: AtomicBool = AtomicBool::new(false);
+
+/// stop user processes, flush the disks, quiet every bound driver, then power off or reboot.
+/// whoever calls it second waits for the first.
+pub fn shutdown(action: PowerAction) -> ! {
+    use log::*;
+
+    if SHUTTING_DOWN.swap(true, Ordering::AcqRel) {
+        loop {
+            wfe();
+        }
+    }
+
+    info!("power: {:?} requested", action);
+
+    let stopped = GLOBAL_SCHEDULER.stop_user_threads();
+    debug!("power: stopped {} user threads", stopped);
+
+    for provider in devices() {
+        let mut provider = provider.lock(&GLOBAL_SCHEDULER);
+        if let Err(e) = provider.request(IoRequest {
+            cmd: Command::Flush,
+            lba: 0,
+        }) {
+            warn!("power: flushing {} failed: {}", provider.name(), e);
+        }
+    }
+
+    match DRIVER_MANAGER.try_borrow() {
+        Ok(drivers) => {
+            for device in drivers.instances() {
+                device.shutdown();
+            }
+        }
+        Err(_) => warn!("power: drivers are busy, not shutting them down"),
+    }
+
+    let e = match action {
+        PowerAction::Off => {
+            info!("power: off");
+            smccc::system_off()
+        }
+        PowerAction::Reboot => {
+            info!("power: rebooting");
+            smccc::reboot()
+        }
+    };
+
+    warn!("power: PSCI refused {:?}: {:?}", action, e);
+
+    let e = efi::reset(match action {
+        PowerAction::Off => ResetKind::Shutdown,
+        PowerAction::Reboot => ResetKind::Cold,
+    });
+
+    error!("power: UEFI refused {:?}: {}", action, e);
+    loop {
+        wfe();
+    }
+}
@@ -0,0 +1,304 @@
+//! a small command shell on the console TTY
+
+use core::fmt::Write;
+
+use alloc::{string::String, sync::Arc, vec::Vec};
+use klib::{
+    block, efi,
+    net::{self, Ipv4Address},
+    pm::{numa::NodeId, page::kernel_page_allocator},
+    scheduler::GLOBAL_SCHEDULER,
+    stack::Stack,
+    thread::Thread,
+    time::{self, DateTime},
+    tty::{self, Tty, TtyError},
+    vfs::{FileType, Vfs, mount},
+    vm::PAGE_SIZE,
+};
+
+use crate::power::{self, PowerAction};
+
+const STACK_SIZE: usize = 64 * 1024;
+const PROMPT: &str = "mars> ";
+
+const PING_TIMEOUT_MS: u64 = 2_000;
+
+/// how much of a file `cat` reads at once
+const CAT_CHUNK: usize = 4096;
+
+/// start the shell on the console, if there is one
+pub fn spawn() {
+    use log::*;
+
+    if tty::console().is_none() {
+        warn!("shell: no console TTY");
+        return;
+    }
+
+    let stack = Stack::new(STACK_SIZE, 16).expect("unable to allocate shell stack");
+    let thread = Arc::new(Thread::new_kernel(stack, shell_entry as *const (), 1));
+    GLOBAL_SCHEDULER.spawn(thread);
+}
+
+/// read one line, without the trailing newline. `None` on end of file.
+fn read_line(tty: &Tty) -> Result<Option<String>, TtyError> {
+    let mut line = String::new();
+    let mut buf = [0u8; 128];
+
+    loop {
+        let n = tty.read(&mut buf)?;
+        if n == 0 {
+            return Ok((!line.is_empty()).then_some(line));
+        }
+
+        line.push_str(&String::from_utf8_lossy(&buf[..n]));
+        if line.ends_with('\n') {
+            line.pop();
+            return Ok(Some(line));
+        }
+    }
+}
+
+/// a dotted quad, e.g. `10.0.2.2`
+fn parse_address(s: &str) -> Option<Ipv4Address> {
+    let mut octets = [0u8; 4];
+    let mut parts = s.split('.');
+
+    for octet in &mut octets {
+        *octet = parts.next()?.parse().ok()?;
+    }
+
+    parts.next().is_none().then_some(Ipv4Address(octets))
+}
+
+fn shell_entry() -> ! {
+    let tty = tty::console().expect("console went away");
+    let mut out = &*tty;
+    let vfs = Vfs::new(mount::root());
+
+    _ = writeln!(
+        out,
+        "\nmars shell on {}. type `help` for commands.",
+        tty.name()
+    );
+
+    loop {
+        _ = write!(out, "{PROMPT}");
+
+        match read_line(&tty) {
+            Ok(Some(line)) => run(&mut out, &vfs, line.trim()),
+            Ok(None) => _ = writeln!(out),
+            // ^C already echoed
+            Err(TtyError::Interrupted) => {}
+            Err(e) => _ = writeln!(out, "shell: {e}"),
+        }
+    }
+}
+
+fn run(out: &mut impl Write, vfs: &Vfs, line: &str) {
+    let mut args = line.split_whitespace();
+    let Some(command) = args.next() else {
+        return;
+    };
+
+    match command {
+        "help" => {
+            _ = writeln!(out, "help               this text");
+            _ = writeln!(out, "echo <text>        print text");
+            _ = writeln!(out, "date               show the wall clock and uptime");
+            _ = writeln!(out, "ifconfig           list network interfaces");
+            _ = writeln!(out, "ping <ip> [count]  send ICMP echo requests");
+            _ = writeln!(out, "ls [path]          list a directory");
+            _ = writeln!(out, "cat <path>         print a file");
+            _ = writeln!(out, "lsblk              list block devices");
+            _ = writeln!(out, "free               show memory use, per NUMA node");
+            _ = writeln!(out, "efivar [var [hex]] list, read or set UEFI variables");
+            _ = writeln!(out, "poweroff           shut down and power off");
+            _ = writeln!(out, "reboot             shut down and reboot");
+        }
+        "echo" => {
+            let mut first = true;
+            for arg in args {
+                _ = write!(out, "{}{arg}", if first { "" } else { " " });
+                first = false;
+            }
+            _ = writeln!(out);
+        }
+        "date" => {
+            if time::realtime_is_set() {
+                _ = writeln!(out, "{}", DateTime::now());
+            } else {
+                _ = writeln!(out, "wall clock not set");
+            }
+            let up = time::monotonic();
+            _ = writeln!(out, "up {}.{:03} s", up.as_secs(), up.subsec_millis());
+        }
+        "ifconfig" => {
+            for iface in net::interfaces() {
+                _ = writeln!(
+                    out,
+                    "{}: mac {} mtu {} link {}",
+                    iface.name,
+                    iface.mac,
+                    iface.mtu,
+                    if iface.link_up { "up" } else { "down" }
+                );
+
+                match iface.config {
+                    Some(config) => {
+                        _ = write!(
+                            out,
+                            "    inet {}/{}",
+                            config.addr,
+                            Ipv4Address::prefix_len(config.netmask)
+                        );
+                        if let Some(gateway) = config.gateway {
+                            _ = write!(out, " gateway {gateway}");
+                        }
+                        _ = writeln!(out);
+                    }
+                    None => _ = writeln!(out, "    unconfigured"),
+                }
+            }
+        }
+        "ping" => {
+            let Some(dst) = args.next().and_then(parse_address) else {
+                _ = writeln!(out, "usage: ping <ip> [count]");
+                return;
+            };
+            let count = args.next().and_then(|c| c.parse().ok()).unwrap_or(4u32);
+
+            for seq in 0..count {
+                match net::ping(dst, PING_TIMEOUT_MS) {
+                    Ok(rtt) => _ = writeln!(out, "reply from {dst}: seq={seq} time={rtt} ms"),
+                    Err(e) => _ = writeln!(out, "{dst}: seq={seq} {e}"),
+                }
+            }
+        }
+        "ls" => {
+            let path = args.next().unwrap_or("/");
+            match vfs.open(path, false, true, false).and_then(|f| f.readdir()) {
+                Ok(mut entries) => {
+                    entries.sort_by(|a, b| a.name.cmp(&b.name));
+                    for entry in entries {
+                        let suffix = if entry.file_type == FileType::Directory {
+                            "/"
+                        } else {
+                            ""
+                        };
+                        _ = writeln!(out, "{}{suffix}", entry.name);
+                    }
+                }
+                Err(e) => _ = writeln!(out, "ls: {path}: {e}"),
+            }
+        }
+        "cat" => {
+            let Some(path) = args.next() else {
+                _ = writeln!(out, "usage: cat <path>");
+                return;
+            };
+            let file = match vfs.open(path, false, true, false) {
+                Ok(file) => file,
+                Err(e) => {
+                    _ = writeln!(out, "cat: {path}: {e}");
+                    return;
+                }
+            };
+
+            let mut buf = [0u8; CAT_CHUNK];
+            loop {
+                match file.read(&mut buf) {
+                    Ok(0) => break,
+                    Ok(n) => _ = write!(out, "{}", String::from_utf8_lossy(&buf[..n as usize])),
+                    Err(e) => {
+                        _ = writeln!(out, "cat: {path}: {e}");
+                        break;
+                    }
+                }
+            }
+        }
+        "lsblk" => {
+            for device in block::devices() {
+                let device = device.lock(&GLOBAL_SCHEDULER);
+                let bytes = device.block_count() * device.block_size() as u64;
+                _ = writeln!(
+                    out,
+                    "{}: {} blocks of {} bytes, {} MiB",
+                    device.name(),
+                    device.block_count(),
+                    device.block_size(),
+                    bytes >> 20
+                );
+            }
+        }
+        "free" => {
+            let Some(allocator) = kernel_page_allocator() else {
+                _ = writeln!(out, "free: no page allocator");
+                return;
+            };
+
+            let mib = |pages: usize| (pages * PAGE_SIZE) >> 20;
+            _ = writeln!(
+                out,
+                "total: {} MiB, {} MiB used",
+                mib(allocator.total_pages()),
+                mib(allocator.allocated_pages())
+            );
+
+            if allocator.node_count() > 1 {
+                for node in 0..allocator.node_count() as NodeId {
+                    let stats = allocator.node_stats(node);
+                    _ = writeln!(
+                        out,
+                        "node {node}: {} MiB, {} MiB used",
+                        mib(stats.total_pages),
+                        mib(stats.allocated_pages)
+                    );
+                }
+            }
+        }
+        "efivar" => match (args.next(), args.next()) {
+            (None, _) => match efi::variable_names() {
+                Ok(names) => {
+                    for (name, vendor) in names {
+                        _ = writeln!(out, "{vendor} {name}");
+                    }
+                }
+                Err(e) => _ = writeln!(out, "efivar: {e}"),
+            },
+            (Some(name), None) => match efi::variable(name, &efi::GLOBAL_VARIABLE) {
+                Ok((attributes, data)) => {
+                    _ = write!(out, "{name} ({attributes:#x}):");
+                    for byte in data {
+                        _ = write!(out, " {byte:02x}");
+                    }
+                    _ = writeln!(out);
+                }
+                Err(e) => _ = writeln!(out, "efivar: {name}: {e}"),
+            },
+            (Some(name), Some(hex)) => {
+                let data = (0..hex.len())
+                    .step_by(2)
+                    .map(|i| {
+                        hex.get(i..i + 2)
+                            .and_then(|b| u8::from_str_radix(b, 16).ok())
+                    })
+                    .collect::<Option<Vec<_>>>();
+                let Some(data) = data else {
+                    _ = writeln!(out, "usage: efivar <name> <hex bytes>");
+                    return;
+                };
+
+                let attributes = efi::VARIABLE_NON_VOLATILE
+                    | efi::VARIABLE_BOOTSERVICE_ACCESS
+                    | efi::VARIABLE_RUNTIME_ACCESS;
+                if let Err(e) = efi::set_variable(name, &efi::GLOBAL_VARIABLE, attributes, &data) {
+                    _ = writeln!(out, "efivar: {name}: {e}");
+                }
+            }
+        },
+        "poweroff" => power::shutdown(PowerAction::Off),
+        "reboot" => power::shutdown(PowerAction::Reboot),
+        _ => _ = writeln!(out, "{command}: unknown command"),
+    }
+}
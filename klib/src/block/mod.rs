@@ -1,8 +1,11 @@
 use core::fmt::Display;
 
-use alloc::{boxed::Box, string::String, sync::Arc};
+use alloc::{boxed::Box, string::String, sync::Arc, vec::Vec};
 
-use crate::{scheduler::GLOBAL_SCHEDULER, sync::SleepingMutex};
+use crate::{
+    scheduler::GLOBAL_SCHEDULER,
+    sync::{RwLock, SleepingMutex},
+};
 
 /// errors that can occur during block I/O
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
@@ -49,6 +52,48 @@ impl core::error::Error for BlockError {}
 
 pub type Result<T> = core::result::Result<T, BlockError>;
 
+/// a shared `Provider`, what consumers attach to
+pub type SharedProvider = Arc<SleepingMutex<'static, dyn Provider>>;
+
+/// every disk a driver has found, by name, so finding one doesn't take its lock
+static DEVICES: RwLock<Vec<(String, SharedProvider)>> = RwLock::new(Vec::new());
+
+/// make a disk available to whatever looks for one
+pub fn register(provider: SharedProvider) {
+    let name = {
+        let p = provider.lock(&GLOBAL_SCHEDULER);
+        log::info!(
+            "block: {}, {} blocks of {} bytes",
+            p.name(),
+            p.block_count(),
+            p.block_size()
+        );
+        String::from(p.name())
+    };
+
+    DEVICES.write().push((name, provider));
+}
+
+/// forget the disk called `name`. consumers already attached keep their reference.
+pub fn unregister(name: &str) {
+    let removed: Vec<_> = {
+        let mut devices = DEVICES.write();
+        let (removed, kept) = core::mem::take(&mut *devices)
+            .into_iter()
+            .partition(|(n, _)| n == name);
+        *devices = kept;
+        removed
+    };
+
+    // the last reference may go here, and dropping a device can sleep
+    drop(removed);
+}
+
+/// every registered disk
+pub fn devices() -> Vec<SharedProvider> {
+    DEVICES.read().iter().map(|(_, p)| p.clone()).collect()
+}
+
 pub trait BlockDevice: Send {
     /// flush any caches to the backing device.
     fn flush(&mut self) -> self::Result<()> {
@@ -132,14 +177,14 @@ pub trait Provider: Send {
 /// attaches to a `Provider`.
 /// sends requests down, tracks accesses, and does bounds checking.
 pub struct Consumer {
-    provider: Arc<SleepingMutex<'static, dyn Provider>>,
+    provider: SharedProvider,
     read_count: isize,
     write_count: isize,
     exclusive_count: isize,
 }
 
 impl Consumer {
-    pub fn attach(provider: Arc<SleepingMutex<'static, dyn Provider>>) -> Self {
+    pub fn attach(provider: SharedProvider) -> Self {
         Self {
             provider,
             read_count: 0,
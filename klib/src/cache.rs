@@ -38,3 +38,25 @@ pub unsafe fn clean_dcache_range(addr: *const u8, len: usize) {
     dsb(barrier::SY);
     isb(barrier::SY);
 }
+
+/// clean and invalidate, so the next read comes from memory. cleaning first keeps lines
+/// shared with other data intact.
+pub unsafe fn invalidate_dcache_range(addr: *const u8, len: usize) {
+    let cache_line_size = get_dcache_line_size();
+
+    let start = align_down(addr as usize, cache_line_size);
+    let end = align_up(addr as usize + len, cache_line_size);
+
+    for ptr in (start..end).step_by(cache_line_size) {
+        unsafe {
+            core::arch::asm!(
+                "dc civac, {}",
+                in(reg) ptr,
+                options(nomem, nostack, preserves_flags)
+            );
+        }
+    }
+
+    dsb(barrier::SY);
+    isb(barrier::SY);
+}
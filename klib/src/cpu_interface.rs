@@ -6,7 +6,7 @@ use core::{
 
 use aarch64_cpu::registers::{MPIDR_EL1, Readable, TPIDR_EL1};
 use alloc::vec::Vec;
-use atomic_refcell::AtomicRefCell;
+use atomic_refcell::{AtomicRef, AtomicRefCell};
 use hashbrown::HashMap;
 use log::trace;
 use rustc_hash::FxHasher;
@@ -211,3 +211,317 @@ pub const fn mpidr_affinities(mpidr: u32) -> (u8, u8, u8, u8) {
 
     (aff3 as u8, aff2 as u8, aff1 as u8, aff0 as u8)
 }
+
+/// how far up the topology a node sits, innermost first
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum TopologyLevel {
+    Thread,
+    Core,
+    Cluster,
+    Package,
+    /// above the packages, e.g. the whole machine
+    System,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum CacheKind {
+    Data,
+    Instruction,
+    Unified,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheInfo {
+    /// 1 for the caches closest to the core
+    pub level: u8,
+    pub kind: CacheKind,
+    /// in bytes. 0 if firmware didn't say, as for the other fields.
+    pub size: usize,
+    pub line_size: u32,
+    pub sets: u32,
+    pub associativity: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct TopologyNode {
+    pub level: TopologyLevel,
+    pub parent: Option<usize>,
+    pub children: Vec<usize>,
+    /// shared by every CPU below the node
+    pub caches: Vec<CacheInfo>,
+    /// the CPU a leaf is. `None` for inner nodes and CPUs the MADT doesn't list.
+    pub cpu: Option<CpuTopologyId>,
+}
+
+/// packages, clusters, cores and threads, as a tree of nodes indexed by position
+#[derive(Debug, Clone, Default)]
+pub struct CpuTopology {
+    nodes: Vec<TopologyNode>,
+}
+
+impl CpuTopology {
+    pub const fn new() -> Self {
+        Self { nodes: Vec::new() }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// add a node below `parent`, returning its index
+    pub fn add(
+        &mut self,
+        parent: Option<usize>,
+        level: TopologyLevel,
+        caches: Vec<CacheInfo>,
+        cpu: Option<CpuTopologyId>,
+    ) -> usize {
+        let index = self.nodes.len();
+        self.nodes.push(TopologyNode {
+            level,
+            parent,
+            children: Vec::new(),
+            caches,
+            cpu,
+        });
+
+        if let Some(parent) = parent {
+            self.nodes[parent].children.push(index);
+        }
+        index
+    }
+
+    pub fn nodes(&self) -> &[TopologyNode] {
+        &self.nodes
+    }
+
+    pub fn node(&self, index: usize) -> &TopologyNode {
+        &self.nodes[index]
+    }
+
+    pub fn node_mut(&mut self, index: usize) -> &mut TopologyNode {
+        &mut self.nodes[index]
+    }
+
+    pub fn roots(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.nodes.len()).filter(|&i| self.nodes[i].parent.is_none())
+    }
+
+    /// the leaf of `cpu`
+    pub fn leaf(&self, cpu: CpuTopologyId) -> Option<usize> {
+        self.nodes.iter().position(|node| node.cpu == Some(cpu))
+    }
+
+    /// `node` and everything above it, innermost first
+    pub fn ancestors(&self, node: usize) -> impl Iterator<Item = usize> + '_ {
+        core::iter::successors(Some(node), |&i| self.nodes[i].parent)
+    }
+
+    /// the innermost node at or above `level` that `cpu` is in
+    pub fn ancestor(&self, cpu: CpuTopologyId, level: TopologyLevel) -> Option<usize> {
+        self.ancestors(self.leaf(cpu)?)
+            .find(|&i| self.nodes[i].level >= level)
+    }
+
+    /// the innermost level `a` and `b` are both in. `None` if they share nothing, not even a
+    /// root.
+    pub fn shared_level(&self, a: CpuTopologyId, b: CpuTopologyId) -> Option<TopologyLevel> {
+        let b = self.leaf(b)?;
+        self.ancestors(self.leaf(a)?)
+            .find(|&i| self.ancestors(b).any(|j| j == i))
+            .map(|i| self.nodes[i].level)
+    }
+
+    /// the CPUs below `node`
+    pub fn cpus_below(&self, node: usize) -> Vec<CpuTopologyId> {
+        let mut cpus = Vec::new();
+        let mut pending = alloc::vec![node];
+
+        while let Some(i) = pending.pop() {
+            cpus.extend(self.nodes[i].cpu);
+            pending.extend(self.nodes[i].children.iter().rev());
+        }
+        cpus
+    }
+
+    /// every cache `cpu` goes through, with the node it belongs to, innermost first
+    pub fn caches(&self, cpu: CpuTopologyId) -> Vec<(usize, &CacheInfo)> {
+        let mut caches = self
+            .leaf(cpu)
+            .into_iter()
+            .flat_map(|leaf| self.ancestors(leaf))
+            .flat_map(|i| self.nodes[i].caches.iter().map(move |cache| (i, cache)))
+            .collect::<Vec<_>>();
+
+        caches.sort_by_key(|(_, cache)| cache.level);
+        caches
+    }
+
+    /// the outermost cache of `cpu`, and the CPUs that share it
+    pub fn last_level_cache(&self, cpu: CpuTopologyId) -> Option<(&CacheInfo, Vec<CpuTopologyId>)> {
+        let (node, cache) = self.caches(cpu).pop()?;
+        Some((cache, self.cpus_below(node)))
+    }
+
+    /// a topology from the affinity fields of `cpus`, with the boot CPU's caches copied to every
+    /// core. without firmware tables there's no saying who shares a cache: the first two levels
+    /// are taken as per core, the rest as per cluster.
+    pub fn from_registers(cpus: impl IntoIterator<Item = CpuTopologyId>) -> Self {
+        // every core is assumed to be multithreaded or not alike
+        let multithreaded = MPIDR_EL1.get() & MPIDR_MT != 0;
+        let caches = cache_registers::read();
+
+        let mut topology = Self::new();
+        let mut packages = Map::default();
+        let mut clusters = Map::default();
+        let mut cores = Map::default();
+
+        for cpu in cpus {
+            let (aff3, aff2, _, _) = mpidr_affinities(cpu.0);
+            // (package, cluster, core), each including the levels above it
+            let keys = if multithreaded {
+                (aff3 as u32, (aff3 as u32) << 8 | aff2 as u32, cpu.0 >> 8)
+            } else {
+                ((aff3 as u32) << 8 | aff2 as u32, cpu.0 >> 8, cpu.0)
+            };
+
+            let package = *packages
+                .entry(keys.0)
+                .or_insert_with(|| topology.add(None, TopologyLevel::Package, Vec::new(), None));
+
+            let cluster = *clusters.entry(keys.1).or_insert_with(|| {
+                let shared = caches
+                    .iter()
+                    .filter(|c| c.level > 2)
+                    .cloned()
+                    .collect::<Vec<_>>();
+                topology.add(Some(package), TopologyLevel::Cluster, shared, None)
+            });
+
+            let private = || {
+                caches
+                    .iter()
+                    .filter(|c| c.level <= 2)
+                    .cloned()
+                    .collect::<Vec<_>>()
+            };
+            if multithreaded {
+                let core = *cores.entry(keys.2).or_insert_with(|| {
+                    topology.add(Some(cluster), TopologyLevel::Core, private(), None)
+                });
+                topology.add(Some(core), TopologyLevel::Thread, Vec::new(), Some(cpu));
+            } else {
+                topology.add(Some(cluster), TopologyLevel::Core, private(), Some(cpu));
+            }
+        }
+
+        topology
+    }
+}
+
+/// MPIDR_EL1: the lowest affinity level is threads of a core
+const MPIDR_MT: u64 = 1 << 24;
+
+mod cache_registers {
+    use core::arch::asm;
+
+    use alloc::vec::Vec;
+
+    use super::{CacheInfo, CacheKind};
+
+    /// levels CLIDR_EL1 can describe
+    const MAX_LEVELS: u64 = 7;
+
+    const CLIDR_CTYPE_INSTRUCTION: u64 = 0b001;
+    const CLIDR_CTYPE_DATA: u64 = 0b010;
+    const CLIDR_CTYPE_SEPARATE: u64 = 0b011;
+    const CLIDR_CTYPE_UNIFIED: u64 = 0b100;
+
+    /// ID_AA64MMFR2_EL1.CCIDX: CCSIDR_EL1 has the 64 bit layout
+    const MMFR2_CCIDX_SHIFT: u64 = 20;
+
+    fn clidr() -> u64 {
+        let val: u64;
+        unsafe { asm!("mrs {}, clidr_el1", out(reg) val, options(nomem, nostack)) };
+        val
+    }
+
+    fn ccidx() -> bool {
+        let val: u64;
+        unsafe { asm!("mrs {}, id_aa64mmfr2_el1", out(reg) val, options(nomem, nostack)) };
+        (val >> MMFR2_CCIDX_SHIFT) & 0xF != 0
+    }
+
+    /// CCSIDR_EL1 of the cache `level` (from 1), instruction or not
+    fn ccsidr(level: u64, instruction: bool) -> u64 {
+        let select = (level - 1) << 1 | instruction as u64;
+        let val: u64;
+        unsafe {
+            asm!(
+                "msr csselr_el1, {}",
+                "isb",
+                "mrs {}, ccsidr_el1",
+                in(reg) select,
+                out(reg) val,
+                options(nostack),
+            )
+        };
+        val
+    }
+
+    fn describe(level: u64, kind: CacheKind, ccidx: bool) -> CacheInfo {
+        let ccsidr = ccsidr(level, kind == CacheKind::Instruction);
+
+        let line_size = 1 << ((ccsidr & 0b111) + 4);
+        let (associativity, sets) = if ccidx {
+            (
+                ((ccsidr >> 3) & 0x1F_FFFF) + 1,
+                ((ccsidr >> 32) & 0xFF_FFFF) + 1,
+            )
+        } else {
+            (((ccsidr >> 3) & 0x3FF) + 1, ((ccsidr >> 13) & 0x7FFF) + 1)
+        };
+
+        CacheInfo {
+            level: level as u8,
+            kind,
+            size: (line_size * associativity * sets) as usize,
+            line_size: line_size as u32,
+            sets: sets as u32,
+            associativity: associativity as u32,
+        }
+    }
+
+    /// the caches of the running CPU, innermost first
+    pub fn read() -> Vec<CacheInfo> {
+        let clidr = clidr();
+        let ccidx = ccidx();
+        let mut caches = Vec::new();
+
+        for level in 1..=MAX_LEVELS {
+            let kinds: &[CacheKind] = match (clidr >> ((level - 1) * 3)) & 0b111 {
+                CLIDR_CTYPE_INSTRUCTION => &[CacheKind::Instruction],
+                CLIDR_CTYPE_DATA => &[CacheKind::Data],
+                CLIDR_CTYPE_SEPARATE => &[CacheKind::Instruction, CacheKind::Data],
+                CLIDR_CTYPE_UNIFIED => &[CacheKind::Unified],
+                // the first level without a cache ends them
+                _ => break,
+            };
+
+            caches.extend(kinds.iter().map(|&kind| describe(level, kind, ccidx)));
+        }
+
+        caches
+    }
+}
+
+static CPU_TOPOLOGY: AtomicRefCell<CpuTopology> = AtomicRefCell::new(CpuTopology::new());
+
+/// set once, before the secondary CPUs start
+pub fn init_cpu_topology(topology: CpuTopology) {
+    *CPU_TOPOLOGY.borrow_mut() = topology;
+}
+
+pub fn cpu_topology() -> AtomicRef<'static, CpuTopology> {
+    CPU_TOPOLOGY.borrow()
+}
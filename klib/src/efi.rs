@@ -0,0 +1,344 @@
+//! UEFI runtime services, after `ExitBootServices`.
+//!
+//! the firmware's runtime regions are mapped 1:1 in an address space of their own, which is in
+//! TTBR0 only for the length of a call. the firmware isn't reentrant, so calls are serialized
+//! and run with interrupts masked.
+
+use core::{
+    fmt::Display,
+    ptr::{self, NonNull},
+    time::Duration,
+};
+
+use aarch64_cpu::{
+    asm::barrier::{self, dsb, isb},
+    registers::{Readable, TTBR0_EL1, Writeable},
+};
+use aarch64_cpu_ext::asm::tlb::{VMALLE1, tlbi};
+use alloc::{string::String, vec, vec::Vec};
+use uefi::boot::MemoryDescriptor;
+use uefi_raw::Guid;
+
+use crate::{
+    allocator_support::KernelAddressTranslator, guard::InterruptGuard,
+    pm::page::mapper::AddressTranslator, sync::FairSpinlock, time::DateTime,
+    vm::user::address_space::AddressSpace,
+};
+
+/// `EFI_GLOBAL_VARIABLE`, where `BootOrder` and `Boot####` live
+pub const GLOBAL_VARIABLE: Guid = uefi_raw::guid!("8be4df61-93ca-11d2-aa0d-00e098032b8c");
+
+/// variable attributes
+pub const VARIABLE_NON_VOLATILE: u32 = 1 << 0;
+pub const VARIABLE_BOOTSERVICE_ACCESS: u32 = 1 << 1;
+pub const VARIABLE_RUNTIME_ACCESS: u32 = 1 << 2;
+
+/// `EFI_TIME.TimeZone` when the time is local with no known offset
+const UNSPECIFIED_TIMEZONE: i16 = 0x07FF;
+
+/// variable data is read into a buffer this big first
+const VARIABLE_BUF: usize = 256;
+
+const ERROR_BIT: usize = 1 << (usize::BITS - 1);
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum EfiError {
+    /// there are no runtime services, or they haven't been set up
+    Unavailable,
+    InvalidParameter,
+    Unsupported,
+    BufferTooSmall,
+    NotReady,
+    DeviceError,
+    WriteProtected,
+    OutOfResources,
+    NotFound,
+    SecurityViolation,
+    Other(usize),
+}
+
+impl Display for EfiError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Unavailable => f.write_str("no UEFI runtime services"),
+            Self::InvalidParameter => f.write_str("invalid parameter"),
+            Self::Unsupported => f.write_str("unsupported"),
+            Self::BufferTooSmall => f.write_str("buffer too small"),
+            Self::NotReady => f.write_str("not ready"),
+            Self::DeviceError => f.write_str("device error"),
+            Self::WriteProtected => f.write_str("write protected"),
+            Self::OutOfResources => f.write_str("out of resources"),
+            Self::NotFound => f.write_str("not found"),
+            Self::SecurityViolation => f.write_str("security violation"),
+            Self::Other(status) => write!(f, "status {:#x}", status),
+        }
+    }
+}
+
+impl core::error::Error for EfiError {}
+
+pub type Result<T> = core::result::Result<T, EfiError>;
+
+fn check(status: usize) -> Result<()> {
+    if status & ERROR_BIT == 0 {
+        // warnings
+        return Ok(());
+    }
+
+    Err(match status & !ERROR_BIT {
+        2 => EfiError::InvalidParameter,
+        3 => EfiError::Unsupported,
+        5 => EfiError::BufferTooSmall,
+        6 => EfiError::NotReady,
+        7 => EfiError::DeviceError,
+        8 => EfiError::WriteProtected,
+        9 => EfiError::OutOfResources,
+        14 => EfiError::NotFound,
+        26 => EfiError::SecurityViolation,
+        _ => EfiError::Other(status),
+    })
+}
+
+/// `EFI_TIME`
+#[allow(dead_code, reason = "firmware layout")]
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default)]
+struct Time {
+    year: u16,
+    month: u8,
+    day: u8,
+    hour: u8,
+    minute: u8,
+    second: u8,
+    pad1: u8,
+    nanosecond: u32,
+    /// minutes: local time is UTC minus this
+    time_zone: i16,
+    daylight: u8,
+    pad2: u8,
+}
+
+/// `EFI_RUNTIME_SERVICES`, UEFI 2.10 section 4.5
+#[allow(dead_code, reason = "firmware layout")]
+#[repr(C)]
+struct RuntimeServices {
+    header: [u8; 24],
+    get_time: unsafe extern "efiapi" fn(time: *mut Time, capabilities: *mut u8) -> usize,
+    set_time: usize,
+    get_wakeup_time: usize,
+    set_wakeup_time: usize,
+    set_virtual_address_map: unsafe extern "efiapi" fn(
+        map_size: usize,
+        desc_size: usize,
+        desc_version: u32,
+        map: *const MemoryDescriptor,
+    ) -> usize,
+    convert_pointer: usize,
+    get_variable: unsafe extern "efiapi" fn(
+        name: *const u16,
+        vendor: *const Guid,
+        attributes: *mut u32,
+        data_size: *mut usize,
+        data: *mut u8,
+    ) -> usize,
+    get_next_variable_name: unsafe extern "efiapi" fn(
+        name_size: *mut usize,
+        name: *mut u16,
+        vendor: *mut Guid,
+    ) -> usize,
+    set_variable: unsafe extern "efiapi" fn(
+        name: *const u16,
+        vendor: *const Guid,
+        attributes: u32,
+        data_size: usize,
+        data: *const u8,
+    ) -> usize,
+    get_next_high_monotonic_count: usize,
+    reset_system:
+        unsafe extern "efiapi" fn(kind: u32, status: usize, data_size: usize, data: *const u8),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(u32)]
+pub enum ResetKind {
+    Cold = 0,
+    Warm = 1,
+    Shutdown = 2,
+}
+
+struct Runtime {
+    /// physical, which is also where it's mapped
+    services: *const RuntimeServices,
+    space: AddressSpace<'static>,
+}
+
+// SAFETY: only touched with `RUNTIME` held
+unsafe impl Send for Runtime {}
+
+static RUNTIME: FairSpinlock<Option<Runtime>> = FairSpinlock::new(None);
+
+fn switch_ttbr0(value: u64) {
+    TTBR0_EL1.set(value);
+
+    dsb(barrier::ISHST);
+    tlbi(VMALLE1);
+    dsb(barrier::SY);
+    isb(barrier::SY);
+}
+
+/// run `f` with the firmware's address space loaded
+fn call<R>(f: impl FnOnce(&RuntimeServices) -> R) -> Result<R> {
+    let _irq = InterruptGuard::new();
+    let runtime = RUNTIME.lock();
+    let runtime = runtime.as_ref().ok_or(EfiError::Unavailable)?;
+
+    let old = TTBR0_EL1.get();
+    let root = KernelAddressTranslator.dmap_to_phys(runtime.space.root() as *const _ as *mut u8);
+    switch_ttbr0(root as u64);
+    let result = f(unsafe { &*runtime.services });
+    switch_ttbr0(old);
+
+    Ok(result)
+}
+
+/// hand over the runtime services at physical address `services`, with `space` mapping every
+/// runtime region 1:1. `map` is those regions, for `SetVirtualAddressMap`, which can only be
+/// called once.
+pub fn install(
+    services: NonNull<u8>,
+    space: AddressSpace<'static>,
+    map: &[MemoryDescriptor],
+) -> Result<()> {
+    *RUNTIME.lock() = Some(Runtime {
+        services: services.as_ptr() as *const RuntimeServices,
+        space,
+    });
+
+    let status = call(|rt| unsafe {
+        (rt.set_virtual_address_map)(
+            size_of_val(map),
+            size_of::<MemoryDescriptor>(),
+            MemoryDescriptor::VERSION,
+            map.as_ptr(),
+        )
+    })?;
+
+    check(status).inspect_err(|_| *RUNTIME.lock() = None)
+}
+
+/// whether `install` worked
+pub fn available() -> bool {
+    RUNTIME.lock().is_some()
+}
+
+/// seconds since the UNIX epoch, by the firmware's clock
+fn unix_time() -> Result<u64> {
+    let mut time = Time::default();
+    check(call(|rt| unsafe {
+        (rt.get_time)(&mut time, ptr::null_mut())
+    })?)?;
+
+    let local = DateTime {
+        year: time.year as u32,
+        month: time.month,
+        day: time.day,
+        hour: time.hour,
+        minute: time.minute,
+        second: time.second,
+    }
+    .to_unix()
+    .ok_or(EfiError::DeviceError)?;
+
+    Ok(match time.time_zone {
+        UNSPECIFIED_TIMEZONE => local,
+        zone => local.saturating_add_signed(zone as i64 * 60),
+    })
+}
+
+/// the firmware's clock, in UTC
+pub fn time() -> Result<DateTime> {
+    unix_time().map(DateTime::from_unix)
+}
+
+/// the firmware's clock as a duration since the UNIX epoch, for `time::set_realtime`
+pub fn realtime() -> Result<Duration> {
+    unix_time().map(Duration::from_secs)
+}
+
+fn to_ucs2(name: &str) -> Vec<u16> {
+    name.encode_utf16().chain([0]).collect()
+}
+
+/// the attributes and contents of variable `name`
+pub fn variable(name: &str, vendor: &Guid) -> Result<(u32, Vec<u8>)> {
+    let name = to_ucs2(name);
+    let mut data = vec![0u8; VARIABLE_BUF];
+
+    loop {
+        let mut attributes = 0;
+        let mut size = data.len();
+
+        let status = call(|rt| unsafe {
+            (rt.get_variable)(
+                name.as_ptr(),
+                vendor,
+                &mut attributes,
+                &mut size,
+                data.as_mut_ptr(),
+            )
+        })?;
+
+        match check(status) {
+            Ok(()) => {
+                data.truncate(size);
+                return Ok((attributes, data));
+            }
+            // `size` is what it needs
+            Err(EfiError::BufferTooSmall) if size > data.len() => data.resize(size, 0),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// write variable `name`. no data deletes it.
+pub fn set_variable(name: &str, vendor: &Guid, attributes: u32, data: &[u8]) -> Result<()> {
+    let name = to_ucs2(name);
+
+    check(call(|rt| unsafe {
+        (rt.set_variable)(name.as_ptr(), vendor, attributes, data.len(), data.as_ptr())
+    })?)
+}
+
+/// the name and vendor of every variable
+pub fn variable_names() -> Result<Vec<(String, Guid)>> {
+    let mut names = Vec::new();
+    // starts empty, and each call replaces it with the next
+    let mut name = vec![0u16; VARIABLE_BUF / 2];
+    let mut vendor = Guid::ZERO;
+
+    loop {
+        let mut size = name.len() * 2;
+
+        let status = call(|rt| unsafe {
+            (rt.get_next_variable_name)(&mut size, name.as_mut_ptr(), &mut vendor)
+        })?;
+
+        match check(status) {
+            Ok(()) => {
+                let len = name.iter().position(|&c| c == 0).unwrap_or(name.len());
+                names.push((String::from_utf16_lossy(&name[..len]), vendor));
+            }
+            Err(EfiError::NotFound) => return Ok(names),
+            Err(EfiError::BufferTooSmall) if size / 2 > name.len() => name.resize(size / 2, 0),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// reset or power off through the firmware. only returns if there are no runtime services.
+pub fn reset(kind: ResetKind) -> EfiError {
+    match call(|rt| unsafe { (rt.reset_system)(kind as u32, 0, 0, ptr::null()) }) {
+        Ok(()) => EfiError::DeviceError,
+        Err(e) => e,
+    }
+}
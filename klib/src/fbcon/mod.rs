@@ -0,0 +1,325 @@
+//! a text console drawn on a framebuffer, so there's something on screen without serial.
+//!
+//! understands the handful of escape sequences the kernel log uses for colours. output goes to
+//! the first framebuffer that's registered.
+
+mod font;
+
+use core::{
+    fmt::{self, Write},
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+};
+
+use aarch64_cpu::registers::{MPIDR_EL1, Readable};
+use alloc::sync::Arc;
+
+use crate::{
+    guard::InterruptGuard,
+    hardware::framebuffer::{Framebuffer, FramebufferInfo, Rect},
+    sync::UnfairSpinlock,
+};
+
+/// glyph rows are drawn twice, for the usual 8x16 cell
+const CELL_WIDTH: usize = font::WIDTH;
+const CELL_HEIGHT: usize = font::HEIGHT * 2;
+
+const TAB_WIDTH: usize = 8;
+
+const BACKGROUND: u32 = 0x0000_0000;
+const DEFAULT_FOREGROUND: u32 = 0x00AA_AAAA;
+
+/// SGR colours 30-37, then their bright versions 90-97
+const PALETTE: [u32; 8] = [
+    0x0000_0000,
+    0x00AA_0000,
+    0x0000_AA00,
+    0x00AA_5500,
+    0x0000_00AA,
+    0x00AA_00AA,
+    0x0000_AAAA,
+    0x00AA_AAAA,
+];
+const BRIGHT_PALETTE: [u32; 8] = [
+    0x0055_5555,
+    0x00FF_5555,
+    0x0055_FF55,
+    0x00FF_FF55,
+    0x0055_55FF,
+    0x00FF_55FF,
+    0x0055_FFFF,
+    0x00FF_FFFF,
+];
+
+const MAX_PARAMS: usize = 4;
+
+const NO_OWNER: u64 = u64::MAX;
+
+static CONSOLE: UnfairSpinlock<Option<Console>> = UnfairSpinlock::new(None);
+/// cheap check for the common case of nothing registered yet
+static REGISTERED: AtomicBool = AtomicBool::new(false);
+/// MPIDR of the core holding `CONSOLE`. a framebuffer driver that logs from inside `flush` would
+/// otherwise deadlock on it.
+static OWNER: AtomicU64 = AtomicU64::new(NO_OWNER);
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Escape {
+    None,
+    /// saw ESC
+    Start,
+    /// inside `ESC [`
+    Csi,
+}
+
+struct Console {
+    fb: Arc<dyn Framebuffer>,
+    info: FramebufferInfo,
+    columns: usize,
+    rows: usize,
+    column: usize,
+    row: usize,
+    foreground: u32,
+    escape: Escape,
+    params: [u16; MAX_PARAMS],
+    param_count: usize,
+    /// drawn since the last flush
+    dirty: Rect,
+}
+
+impl Console {
+    fn new(fb: Arc<dyn Framebuffer>) -> Option<Self> {
+        let info = fb.info();
+        let columns = info.width / CELL_WIDTH;
+        let rows = info.height / CELL_HEIGHT;
+        if columns == 0 || rows == 0 {
+            return None;
+        }
+
+        let mut console = Self {
+            fb,
+            info,
+            columns,
+            rows,
+            column: 0,
+            row: 0,
+            foreground: DEFAULT_FOREGROUND,
+            escape: Escape::None,
+            params: [0; MAX_PARAMS],
+            param_count: 0,
+            dirty: Rect::new(0, 0, 0, 0),
+        };
+
+        console.pixels().fill(BACKGROUND);
+        console.dirty = Rect::new(0, 0, info.width, info.height);
+
+        Some(console)
+    }
+
+    fn pixels(&mut self) -> &mut [u32] {
+        let len = self.info.stride * self.info.height;
+        // SAFETY: the framebuffer owns `len` pixels for as long as it lives, and we hold it
+        unsafe { core::slice::from_raw_parts_mut(self.fb.pixels().as_ptr(), len) }
+    }
+
+    fn draw_glyph(&mut self, c: u8) {
+        let glyph = font::glyph(c);
+        let x = self.column * CELL_WIDTH;
+        let y = self.row * CELL_HEIGHT;
+        let stride = self.info.stride;
+        let foreground = self.foreground;
+
+        let pixels = self.pixels();
+        for (dy, line) in pixels[y * stride..]
+            .chunks_mut(stride)
+            .take(CELL_HEIGHT)
+            .enumerate()
+        {
+            let bits = glyph[dy / 2];
+            for (dx, pixel) in line[x..x + CELL_WIDTH].iter_mut().enumerate() {
+                *pixel = if bits & (1 << dx) != 0 {
+                    foreground
+                } else {
+                    BACKGROUND
+                };
+            }
+        }
+
+        self.dirty = self.dirty.union(&Rect::new(x, y, CELL_WIDTH, CELL_HEIGHT));
+    }
+
+    fn scroll(&mut self) {
+        let stride = self.info.stride;
+        let row_pixels = CELL_HEIGHT * stride;
+        let text_pixels = self.rows * row_pixels;
+
+        let pixels = self.pixels();
+        pixels.copy_within(row_pixels..text_pixels, 0);
+        pixels[text_pixels - row_pixels..text_pixels].fill(BACKGROUND);
+
+        self.dirty = Rect::new(0, 0, self.info.width, self.rows * CELL_HEIGHT);
+    }
+
+    fn newline(&mut self) {
+        self.column = 0;
+        if self.row + 1 < self.rows {
+            self.row += 1;
+        } else {
+            self.scroll();
+        }
+    }
+
+    fn sgr(&mut self) {
+        // `ESC [ m` is a reset too
+        let count = self.param_count.max(1);
+        for i in 0..count {
+            match self.params[i] {
+                0 | 39 => self.foreground = DEFAULT_FOREGROUND,
+                p @ 30..=37 => self.foreground = PALETTE[(p - 30) as usize],
+                p @ 90..=97 => self.foreground = BRIGHT_PALETTE[(p - 90) as usize],
+                _ => {}
+            }
+        }
+    }
+
+    fn put(&mut self, byte: u8) {
+        match self.escape {
+            Escape::Start => {
+                self.escape = if byte == b'[' {
+                    self.params = [0; MAX_PARAMS];
+                    self.param_count = 0;
+                    Escape::Csi
+                } else {
+                    Escape::None
+                };
+                return;
+            }
+            Escape::Csi => {
+                match byte {
+                    b'0'..=b'9' => {
+                        if self.param_count == 0 {
+                            self.param_count = 1;
+                        }
+                        if let Some(param) = self.params.get_mut(self.param_count - 1) {
+                            *param = param
+                                .saturating_mul(10)
+                                .saturating_add((byte - b'0') as u16);
+                        }
+                    }
+                    b';' => self.param_count = (self.param_count.max(1) + 1).min(MAX_PARAMS),
+                    b'm' => {
+                        self.sgr();
+                        self.escape = Escape::None;
+                    }
+                    // anything else ends a sequence we don't implement
+                    0x40..=0x7E => self.escape = Escape::None,
+                    _ => {}
+                }
+                return;
+            }
+            Escape::None => {}
+        }
+
+        match byte {
+            0x1B => self.escape = Escape::Start,
+            b'\n' => self.newline(),
+            b'\r' => self.column = 0,
+            b'\t' => {
+                let next = (self.column / TAB_WIDTH + 1) * TAB_WIDTH;
+                if next >= self.columns {
+                    self.newline();
+                } else {
+                    self.column = next;
+                }
+            }
+            0x08 => self.column = self.column.saturating_sub(1),
+            0x00..=0x1F => {}
+            // one `?` per UTF-8 sequence, not per byte
+            0x80..=0xBF => {}
+            _ => {
+                if self.column >= self.columns {
+                    self.newline();
+                }
+                self.draw_glyph(byte);
+                self.column += 1;
+            }
+        }
+    }
+
+    fn flush(&mut self) {
+        if !self.dirty.is_empty() {
+            self.fb.flush(self.dirty);
+            self.dirty = Rect::new(0, 0, 0, 0);
+        }
+    }
+}
+
+impl Write for Console {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            self.put(byte);
+        }
+        Ok(())
+    }
+}
+
+fn with_console(f: impl FnOnce(&mut Console)) {
+    if !REGISTERED.load(Ordering::Acquire) {
+        return;
+    }
+
+    let _irq = InterruptGuard::new();
+    let me = MPIDR_EL1.get();
+    if OWNER.load(Ordering::Acquire) == me {
+        return;
+    }
+
+    let mut console = CONSOLE.lock();
+    OWNER.store(me, Ordering::Release);
+
+    if let Some(console) = console.as_mut() {
+        f(console);
+        console.flush();
+    }
+
+    OWNER.store(NO_OWNER, Ordering::Release);
+}
+
+/// draw the console on `fb`, unless one is already registered
+pub fn register(fb: Arc<dyn Framebuffer>) {
+    use log::*;
+
+    {
+        let _irq = InterruptGuard::new();
+        let mut console = CONSOLE.lock();
+        if console.is_some() {
+            return;
+        }
+
+        let Some(new) = Console::new(fb) else {
+            warn!("fbcon: framebuffer too small for a console");
+            return;
+        };
+
+        info!("fbcon: {}x{} text console", new.columns, new.rows);
+        *console = Some(new);
+    }
+
+    REGISTERED.store(true, Ordering::Release);
+    with_console(|_| {});
+}
+
+pub fn write_str(s: &str) {
+    with_console(|console| _ = console.write_str(s));
+}
+
+/// raw terminal output, which doesn't have to be UTF-8
+pub fn write_bytes(bytes: &[u8]) {
+    with_console(|console| {
+        for &byte in bytes {
+            console.put(byte);
+        }
+    });
+}
+
+pub fn write_fmt(args: fmt::Arguments) {
+    with_console(|console| _ = console.write_fmt(args));
+}
@@ -1,6 +1,7 @@
 use core::mem::discriminant;
 
 use super::irq::CallbackError;
+use super::pci::{Bdf, PciBridgeKind, PciId};
 use super::resource::Resource;
 use crate::cpu_interface::CpuTopologyId;
 use alloc::string::String;
@@ -46,6 +47,9 @@ impl DeviceTree {
             class,
             compatible,
             resources,
+            uid: None,
+            bus_address: None,
+            firmware_path: None,
             children: Vec::new(),
         };
 
@@ -64,6 +68,10 @@ impl DeviceTree {
         self.nodes.get(id.0)
     }
 
+    pub fn get_mut(&mut self, id: DeviceId) -> Option<&mut DeviceNode> {
+        self.nodes.get_mut(id.0)
+    }
+
     pub fn iter_class(&self, class: DeviceClass) -> impl Iterator<Item = &DeviceNode> {
         self.nodes
             .iter()
@@ -89,6 +97,14 @@ pub struct DeviceNode {
     pub compatible: Vec<String>,
 
     pub resources: Vec<Resource>,
+
+    /// tells apart devices with the same identifiers, e.g. ACPI `_UID`
+    pub uid: Option<u64>,
+    /// location on the parent bus, e.g. ACPI `_ADR`
+    pub bus_address: Option<u64>,
+    /// where firmware describes it, e.g. the ACPI namespace path, for running its methods
+    pub firmware_path: Option<String>,
+
     pub children: Vec<DeviceId>,
 }
 
@@ -97,9 +113,24 @@ pub enum DeviceClass {
     Cpu { id: CpuTopologyId, acpi_uid: u32 },
     Uart,
     Timer,
+    Rtc,
     // hopefully you have less than 4 billion redistributors
     GicV3 { redistributor_count: u32 },
     PciHostBridge,
+    /// remaps the DMA of the devices behind it
+    Iommu,
+    /// a root port, switch port or PCI-to-PCI bridge. the functions behind it are its children.
+    PciBridge {
+        bdf: Bdf,
+        id: PciId,
+        kind: PciBridgeKind,
+        secondary_bus: u8,
+        subordinate_bus: u8,
+    },
+    PciEndpoint {
+        bdf: Bdf,
+        id: PciId,
+    },
     Other,
 }
 
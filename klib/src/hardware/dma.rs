@@ -0,0 +1,111 @@
+use core::{alloc::Layout, fmt::Debug, ptr::NonNull};
+
+use alloc::alloc::{alloc_zeroed, dealloc};
+
+use crate::{
+    allocator_support::KernelAddressTranslator,
+    cache::{clean_dcache_range, invalidate_dcache_range},
+    hardware::iommu::DmaSpace,
+    pm::page::mapper::AddressTranslator,
+    vm::PAGE_SIZE,
+};
+
+/// physically contiguous, zeroed, page aligned memory that a device can access.
+pub struct DmaBuffer {
+    ptr: NonNull<u8>,
+    layout: Layout,
+    /// where the buffer is mapped for the device
+    space: DmaSpace,
+}
+
+// SAFETY: the buffer is uniquely owned
+unsafe impl Send for DmaBuffer {}
+unsafe impl Sync for DmaBuffer {}
+
+impl Debug for DmaBuffer {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("DmaBuffer")
+            .field("phys", &format_args!("{:#x}", self.phys_addr()))
+            .field("len", &self.len())
+            .finish()
+    }
+}
+
+impl DmaBuffer {
+    /// allocations above the slab size come straight from the buddy allocator, so they're
+    /// physically contiguous.
+    pub fn new(size: usize) -> Option<Self> {
+        Self::new_in(size, &DmaSpace::Direct)
+    }
+
+    /// a buffer mapped into `space`, so only the devices using it can reach it
+    pub fn new_in(size: usize, space: &DmaSpace) -> Option<Self> {
+        let layout =
+            Layout::from_size_align(size.max(1).next_multiple_of(PAGE_SIZE), PAGE_SIZE).ok()?;
+        let ptr = NonNull::new(unsafe { alloc_zeroed(layout) })?;
+
+        let buffer = Self {
+            ptr,
+            layout,
+            space: space.clone(),
+        };
+        let phys = buffer.phys_addr() as usize;
+        space.map(phys..phys + layout.size()).ok()?;
+
+        Some(buffer)
+    }
+
+    #[inline]
+    pub const fn as_ptr(&self) -> *mut u8 {
+        self.ptr.as_ptr()
+    }
+
+    #[inline]
+    pub const fn len(&self) -> usize {
+        self.layout.size()
+    }
+
+    #[inline]
+    pub const fn is_empty(&self) -> bool {
+        self.layout.size() == 0
+    }
+
+    /// bus address of the start of the buffer
+    #[inline]
+    pub fn phys_addr(&self) -> u64 {
+        KernelAddressTranslator.dmap_to_phys(self.ptr.as_ptr()) as u64
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self.ptr.as_ptr(), self.len()) }
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        unsafe { core::slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len()) }
+    }
+
+    /// write back `len` bytes at `offset` so a non-coherent device sees them.
+    pub fn clean(&self, offset: usize, len: usize) {
+        let end = (offset + len).min(self.len());
+        if offset < end {
+            unsafe { clean_dcache_range(self.ptr.as_ptr().add(offset), end - offset) };
+        }
+    }
+
+    /// drop cached copies of `len` bytes at `offset`, to read what a non-coherent device wrote.
+    pub fn invalidate(&self, offset: usize, len: usize) {
+        let end = (offset + len).min(self.len());
+        if offset < end {
+            unsafe { invalidate_dcache_range(self.ptr.as_ptr().add(offset), end - offset) };
+        }
+    }
+}
+
+impl Drop for DmaBuffer {
+    fn drop(&mut self) {
+        let phys = self.phys_addr() as usize;
+        self.space.unmap(phys..phys + self.len());
+
+        unsafe { dealloc(self.ptr.as_ptr(), self.layout) };
+    }
+}
@@ -1,6 +1,6 @@
 use alloc::{boxed::Box, string::String, vec::Vec};
 
-use crate::hardware::device::DeviceTree;
+use crate::hardware::device::{DeviceInitPriority, DeviceTree};
 
 use super::device::{Device, DeviceNode};
 
@@ -18,20 +18,32 @@ pub struct DriverDescriptor {
 }
 
 pub struct DriverManager {
-    drivers: &'static [DriverDescriptor],
+    drivers: &'static [&'static DriverDescriptor],
     instances: Vec<Box<dyn Device>>,
 }
 
 impl DriverManager {
-    pub const fn new(drivers: &'static [DriverDescriptor]) -> Self {
+    pub const fn new(drivers: &'static [&'static DriverDescriptor]) -> Self {
         Self {
             drivers,
             instances: Vec::new(),
         }
     }
 
+    pub fn instances(&self) -> &[Box<dyn Device>] {
+        &self.instances
+    }
+
+    /// fundamental devices, like IOMMUs, go first: the others may depend on them
     pub fn bind_drivers(&mut self, dt: &DeviceTree) {
-        for node in &dt.nodes {
+        use log::*;
+
+        let (fundamental, regular): (Vec<_>, Vec<_>) = dt
+            .nodes
+            .iter()
+            .partition(|node| node.priority == DeviceInitPriority::Fundamental);
+
+        for node in fundamental.into_iter().chain(regular) {
             let driver = self.drivers.iter().find(|drv| {
                 drv.compatible
                     .iter()
@@ -40,8 +52,11 @@ impl DriverManager {
 
             if let Some(drv) = driver {
                 match (drv.probe)(node) {
-                    Ok(device) => self.instances.push(device),
-                    Err(_) => panic!("probe fail"),
+                    Ok(device) => {
+                        info!("{}: bound to {:?}", drv.name, node.compatible.first());
+                        self.instances.push(device);
+                    }
+                    Err(e) => warn!("{}: probe failed: {:?}", drv.name, e),
                 }
             }
         }
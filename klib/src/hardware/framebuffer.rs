@@ -0,0 +1,62 @@
+//! linear framebuffers, as provided by display drivers.
+
+use core::ptr::NonNull;
+
+/// geometry of a 32 bits per pixel, `0x00RRGGBB` framebuffer
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct FramebufferInfo {
+    pub width: usize,
+    pub height: usize,
+    /// pixels from the start of one row to the next
+    pub stride: usize,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Rect {
+    pub const fn new(x: usize, y: usize, width: usize, height: usize) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    pub const fn is_empty(&self) -> bool {
+        self.width == 0 || self.height == 0
+    }
+
+    /// smallest rectangle covering both
+    pub fn union(&self, other: &Rect) -> Rect {
+        if self.is_empty() {
+            return *other;
+        }
+        if other.is_empty() {
+            return *self;
+        }
+
+        let x = self.x.min(other.x);
+        let y = self.y.min(other.y);
+        let right = (self.x + self.width).max(other.x + other.width);
+        let bottom = (self.y + self.height).max(other.y + other.height);
+
+        Rect::new(x, y, right - x, bottom - y)
+    }
+}
+
+pub trait Framebuffer: Send + Sync {
+    fn info(&self) -> FramebufferInfo;
+
+    /// the first pixel. the memory stays valid for as long as the framebuffer lives.
+    fn pixels(&self) -> NonNull<u32>;
+
+    /// make changes inside `rect` visible. may be called from any context, including IRQs.
+    fn flush(&self, rect: Rect);
+}
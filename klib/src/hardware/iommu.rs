@@ -0,0 +1,333 @@
+//! IOMMUs, and the ID maps that say which requesters sit behind them.
+//!
+//! firmware describes where a PCI requester ID ends up: as a stream ID at an IOMMU, as a
+//! DeviceID at the ITS, or both. IOMMU drivers register themselves when they bind, and devices
+//! behind one get their own I/O page tables. devices nobody attaches keep bypassing translation.
+
+use core::{
+    cell::Cell,
+    ops::Range,
+    ptr::{self, NonNull},
+};
+
+use aarch64_cpu_ext::{
+    asm::barrier::{self, dsb},
+    structures::tte::{AccessPermission, Shareability},
+};
+use alloc::{collections::btree_map::BTreeMap, sync::Arc, vec::Vec};
+
+use crate::{
+    allocator_support::KernelAddressTranslator,
+    interrupt::singleton::get_interrupt_controller,
+    pm::page::{
+        kernel_page_allocator,
+        mapper::{AddressTranslator, TableAllocator, free_tables, map_page, unmap_page},
+    },
+    sync::{FairSpinlock, RwLock},
+    vm::{
+        MAIR_DEVICE_INDEX, MAIR_NORMAL_INDEX, PAGE_SIZE, TABLE_ENTRIES, TTable, align_down,
+        align_up,
+    },
+};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum IommuError {
+    /// no ASIDs or memory left for another address space
+    OutOfResources,
+    /// the stream ID is beyond what the IOMMU handles
+    InvalidStream,
+    /// the IOMMU didn't complete a command
+    Timeout,
+}
+
+pub type Result<T> = core::result::Result<T, IommuError>;
+
+pub trait Iommu: Send + Sync {
+    /// a free address space tag
+    fn alloc_asid(&self) -> Result<u16>;
+
+    fn free_asid(&self, asid: u16);
+
+    /// translate `stream` through the 48 bit, 16KiB granule page tables at `root` (physical),
+    /// tagged `asid`
+    fn attach(&self, stream: u32, asid: u16, root: u64) -> Result<()>;
+
+    /// let `stream` bypass translation again
+    fn detach(&self, stream: u32);
+
+    /// drop the cached translations of `asid`. done after every unmap.
+    fn invalidate(&self, asid: u16);
+}
+
+/// a run of requester IDs of one PCI segment, and what they turn into
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RidMap {
+    pub segment: u16,
+    pub rids: Range<u32>,
+    /// register base of the IOMMU and the stream ID of `rids.start`, if there's an IOMMU
+    pub stream: Option<(usize, u32)>,
+    /// ITS DeviceID of `rids.start`. `None` if their MSIs don't reach an ITS.
+    pub device_id: Option<u32>,
+}
+
+static RID_MAPS: RwLock<Vec<RidMap>> = RwLock::new(Vec::new());
+
+/// IOMMUs that have a driver, by register base
+static IOMMUS: RwLock<BTreeMap<usize, &'static dyn Iommu>> = RwLock::new(BTreeMap::new());
+
+pub fn add_rid_map(map: RidMap) {
+    RID_MAPS.write().push(map);
+}
+
+pub fn register_iommu(base: usize, iommu: &'static dyn Iommu) {
+    IOMMUS.write().insert(base, iommu);
+}
+
+fn find_rid_map(segment: u16, rid: u32) -> Option<RidMap> {
+    RID_MAPS
+        .read()
+        .iter()
+        .find(|map| map.segment == segment && map.rids.contains(&rid))
+        .cloned()
+}
+
+/// ITS DeviceID of a PCI requester. without firmware ID maps, the segment and requester ID.
+pub fn msi_device_id(segment: u16, rid: u32) -> Option<u32> {
+    if RID_MAPS.read().is_empty() {
+        return Some((segment as u32) << 16 | rid);
+    }
+
+    let map = find_rid_map(segment, rid)?;
+    map.device_id.map(|base| base + (rid - map.rids.start))
+}
+
+/// give a PCI requester its own DMA address space if it's behind an IOMMU with a driver
+pub fn pci_dma_space(segment: u16, rid: u32) -> DmaSpace {
+    use log::*;
+
+    let Some((base, stream)) = find_rid_map(segment, rid).and_then(|map| {
+        map.stream
+            .map(|(base, first)| (base, first + (rid - map.rids.start)))
+    }) else {
+        return DmaSpace::Direct;
+    };
+
+    let Some(iommu) = IOMMUS.read().get(&base).copied() else {
+        debug!(
+            "IOMMU: {:#x} has no driver, stream {:#x} bypasses it",
+            base, stream
+        );
+        return DmaSpace::Direct;
+    };
+
+    match DmaDomain::new(iommu, stream) {
+        Ok(domain) => DmaSpace::Translated(Arc::new(domain)),
+        Err(e) => {
+            warn!(
+                "IOMMU: can't attach stream {:#x}, it bypasses: {:?}",
+                stream, e
+            );
+            DmaSpace::Direct
+        }
+    }
+}
+
+/// how a device sees memory
+#[derive(Clone, Default)]
+pub enum DmaSpace {
+    /// physical memory as is, nothing stops it
+    #[default]
+    Direct,
+    /// only what's been mapped for it
+    Translated(Arc<DmaDomain>),
+}
+
+impl DmaSpace {
+    /// let the device reach `range` (physical) at the same bus address
+    pub fn map(&self, range: Range<usize>) -> Result<()> {
+        match self {
+            Self::Direct => Ok(()),
+            Self::Translated(domain) => domain.map(range, MAIR_NORMAL_INDEX),
+        }
+    }
+
+    pub fn unmap(&self, range: Range<usize>) {
+        if let Self::Translated(domain) = self {
+            domain.unmap(range);
+        }
+    }
+}
+
+/// I/O page tables for one stream. they map bus addresses to the same physical addresses, the
+/// point is what's left out.
+pub struct DmaDomain {
+    iommu: &'static dyn Iommu,
+    stream: u32,
+    asid: u16,
+    root: FairSpinlock<NonNull<TTable<TABLE_ENTRIES>>>,
+}
+
+// SAFETY: the tables are only touched under the `root` lock
+unsafe impl Send for DmaDomain {}
+unsafe impl Sync for DmaDomain {}
+
+/// a zeroed page table page, straight from the kernel page allocator
+fn new_table() -> Option<NonNull<TTable<TABLE_ENTRIES>>> {
+    let table = kernel_page_allocator()?
+        .alloc_dmap_pages(0)?
+        .cast::<TTable<TABLE_ENTRIES>>();
+
+    unsafe { ptr::write_bytes(table.as_ptr(), 0, 1) };
+    Some(table)
+}
+
+/// one more page can take a new table at each level below the root
+const TABLES_PER_PAGE: usize = 3;
+
+/// hands out tables allocated ahead of time by `refill`, so running out of memory is an error
+/// from `map` rather than a panic in the middle of a table walk
+struct IoTableAllocator {
+    spare: Cell<[Option<NonNull<TTable<TABLE_ENTRIES>>>; TABLES_PER_PAGE]>,
+}
+
+impl IoTableAllocator {
+    const fn new() -> Self {
+        Self {
+            spare: Cell::new([None; TABLES_PER_PAGE]),
+        }
+    }
+
+    /// make sure the next page can be mapped
+    fn refill(&self) -> Result<()> {
+        let mut spare = self.spare.get();
+        for slot in spare.iter_mut().filter(|slot| slot.is_none()) {
+            *slot = new_table();
+            if slot.is_none() {
+                self.spare.set(spare);
+                return Err(IommuError::OutOfResources);
+            }
+        }
+
+        self.spare.set(spare);
+        Ok(())
+    }
+}
+
+impl TableAllocator for IoTableAllocator {
+    fn alloc_table(&self) -> NonNull<TTable<TABLE_ENTRIES>> {
+        let mut spare = self.spare.get();
+        let table = spare
+            .iter_mut()
+            .find_map(Option::take)
+            .expect("IOMMU: more tables than one page needs");
+
+        self.spare.set(spare);
+        table
+    }
+
+    fn free_table(&self, table: NonNull<TTable<TABLE_ENTRIES>>) {
+        if let Some(allocator) = kernel_page_allocator() {
+            allocator.free_dmap_pages(table.cast());
+        }
+    }
+}
+
+impl Drop for IoTableAllocator {
+    fn drop(&mut self) {
+        for table in self.spare.get().into_iter().flatten() {
+            self.free_table(table);
+        }
+    }
+}
+
+impl DmaDomain {
+    fn new(iommu: &'static dyn Iommu, stream: u32) -> Result<Self> {
+        let asid = iommu.alloc_asid()?;
+        let Some(root) = new_table() else {
+            iommu.free_asid(asid);
+            return Err(IommuError::OutOfResources);
+        };
+
+        let domain = Self {
+            iommu,
+            stream,
+            asid,
+            root: FairSpinlock::new(root),
+        };
+
+        // MSI writes are DMA too, so the ITS doorbell has to stay reachable
+        if let Ok(doorbell) = get_interrupt_controller().msi_get_doorbell() {
+            let page = align_down(doorbell as usize, PAGE_SIZE);
+            domain.map(page..page + PAGE_SIZE, MAIR_DEVICE_INDEX)?;
+        }
+
+        let root_phys = KernelAddressTranslator.dmap_to_phys(root.as_ptr() as _) as u64;
+        iommu.attach(stream, asid, root_phys)?;
+
+        Ok(domain)
+    }
+
+    /// out of resources if there's no memory for the tables. pages mapped before that stay
+    /// mapped.
+    fn map(&self, range: Range<usize>, attr_index: u64) -> Result<()> {
+        let tables = IoTableAllocator::new();
+        let mut root = self.root.lock();
+        let root = unsafe { root.as_mut() };
+
+        for pa in
+            (align_down(range.start, PAGE_SIZE)..align_up(range.end, PAGE_SIZE)).step_by(PAGE_SIZE)
+        {
+            if let Err(e) = tables.refill() {
+                dsb(barrier::ISHST);
+                return Err(e);
+            }
+
+            map_page(
+                root,
+                pa,
+                pa,
+                AccessPermission::PrivilegedReadWrite,
+                Shareability::InnerShareable,
+                true,
+                true,
+                attr_index,
+                &tables,
+                &KernelAddressTranslator,
+            );
+        }
+
+        // the IOMMU walks the tables itself
+        dsb(barrier::ISHST);
+        Ok(())
+    }
+
+    fn unmap(&self, range: Range<usize>) {
+        {
+            let mut root = self.root.lock();
+            let root = unsafe { root.as_mut() };
+
+            for pa in (align_down(range.start, PAGE_SIZE)..align_up(range.end, PAGE_SIZE))
+                .step_by(PAGE_SIZE)
+            {
+                unmap_page(root, pa, &IoTableAllocator::new(), &KernelAddressTranslator);
+            }
+        }
+
+        dsb(barrier::ISHST);
+        self.iommu.invalidate(self.asid);
+    }
+}
+
+impl Drop for DmaDomain {
+    fn drop(&mut self) {
+        self.iommu.detach(self.stream);
+        self.iommu.invalidate(self.asid);
+        self.iommu.free_asid(self.asid);
+
+        free_tables(
+            *self.root.lock(),
+            &IoTableAllocator::new(),
+            &KernelAddressTranslator,
+        );
+    }
+}
@@ -0,0 +1,23 @@
+//! mapping of device MMIO into the kernel address space.
+//!
+//! drivers live outside the kernel crate and can't reach the kernel page tables, so the kernel
+//! registers a mapper here during early init.
+
+use core::{ops::Range, ptr::NonNull};
+
+use atomic_refcell::AtomicRefCell;
+
+/// maps `range` (physical) as device memory. returns the virtual address of `range.start`.
+pub type MmioMapFn = fn(range: &Range<usize>) -> Option<NonNull<u8>>;
+
+static MMIO_MAPPER: AtomicRefCell<Option<MmioMapFn>> = AtomicRefCell::new(None);
+
+pub fn set_mmio_mapper(mapper: MmioMapFn) {
+    *MMIO_MAPPER.borrow_mut() = Some(mapper);
+}
+
+/// map a physical MMIO range. `None` if no mapper is registered or mapping failed.
+pub fn map_mmio(range: &Range<usize>) -> Option<NonNull<u8>> {
+    let mapper = (*MMIO_MAPPER.borrow())?;
+    mapper(range)
+}
@@ -1,6 +1,11 @@
 //! a collection of tools for managing hardware, agnostic of how hardware is discovered (ie ACPI/DTB)
 
 pub mod device;
+pub mod dma;
 pub mod driver;
+pub mod framebuffer;
+pub mod iommu;
 pub mod irq;
+pub mod mmio;
+pub mod pci;
 pub mod resource;
@@ -0,0 +1,87 @@
+//! PCI functions, as the device tree describes them
+
+use core::fmt;
+
+use super::{
+    device::DeviceClass,
+    iommu::{self, DmaSpace},
+};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Bdf {
+    pub segment: u16,
+    pub bus: u8,
+    pub device: u8,
+    pub function: u8,
+}
+
+impl Bdf {
+    pub const fn new(segment: u16, bus: u8, device: u8, function: u8) -> Self {
+        Self {
+            segment,
+            bus,
+            device,
+            function,
+        }
+    }
+
+    /// the PCIe requester ID used for TLP routing
+    pub const fn requester_id(&self) -> u32 {
+        ((self.bus as u32) << 8) | ((self.device as u32) << 3) | (self.function as u32)
+    }
+
+    /// the ITS DeviceID its MSIs carry, as firmware maps it. `None` if they don't reach an ITS.
+    pub fn device_id(&self) -> Option<u32> {
+        iommu::msi_device_id(self.segment, self.requester_id())
+    }
+
+    /// the function's own DMA address space if it's behind an IOMMU. attaches it, so call this
+    /// once, before the function does any DMA.
+    pub fn dma_space(&self) -> DmaSpace {
+        iommu::pci_dma_space(self.segment, self.requester_id())
+    }
+}
+
+impl TryFrom<&DeviceClass> for Bdf {
+    type Error = ();
+
+    fn try_from(class: &DeviceClass) -> Result<Self, Self::Error> {
+        match *class {
+            DeviceClass::PciEndpoint { bdf, .. } | DeviceClass::PciBridge { bdf, .. } => Ok(bdf),
+            _ => Err(()),
+        }
+    }
+}
+
+impl fmt::Display for Bdf {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_fmt(format_args!(
+            "{:04x}:{:02x}:{:02x}.{}",
+            self.segment, self.bus, self.device, self.function
+        ))
+    }
+}
+
+/// what a function's configuration header says it is
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PciId {
+    pub vendor: u16,
+    pub device: u16,
+    pub class: u8,
+    pub subclass: u8,
+    pub prog_if: u8,
+    pub revision: u8,
+    /// zero for bridges, whose header doesn't have them
+    pub subsystem_vendor: u16,
+    pub subsystem: u16,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PciBridgeKind {
+    RootPort,
+    /// a switch's port towards the root
+    SwitchUpstream,
+    SwitchDownstream,
+    /// conventional PCI-to-PCI, or between PCI and PCIe
+    PciBridge,
+}
@@ -1,14 +1,127 @@
 use core::ops::Range;
 
+use alloc::{string::String, vec::Vec};
+
 /// descriptor of a hardware resource
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Resource {
-    Mmio { range: Range<usize> },
-    Irq(u32),
+    Mmio {
+        range: Range<usize>,
+    },
+    /// I/O ports
+    Io {
+        range: Range<usize>,
+    },
+    Irq(Irq),
+    /// an address range a bridge forwards to the bus below it. `range` is what that bus sees,
+    /// the CPU sees it at `range` plus `translation`.
+    Window {
+        space: AddressSpace,
+        range: Range<u64>,
+        translation: u64,
+    },
+    Gpio(GpioConnection),
+    SerialBus(SerialBusConnection),
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressSpace {
+    Memory { prefetchable: bool },
+    Io,
+    BusNumber,
+}
+
+/// an interrupt line and how it signals
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Irq {
+    pub gsiv: u32,
+    pub trigger: IrqTrigger,
+    pub polarity: IrqPolarity,
+    pub shared: bool,
+}
+
+impl Irq {
+    /// level triggered, active high and not shared, which is what GIC SPIs are unless the
+    /// firmware says otherwise
+    pub const fn new(gsiv: u32) -> Self {
+        Self {
+            gsiv,
+            trigger: IrqTrigger::Level,
+            polarity: IrqPolarity::ActiveHigh,
+            shared: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IrqTrigger {
+    Level,
+    Edge,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum IrqPolarity {
     ActiveHigh,
     ActiveLow,
+    /// both edges. only GPIO interrupts do this.
+    ActiveBoth,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GpioKind {
+    Interrupt {
+        trigger: IrqTrigger,
+        polarity: IrqPolarity,
+        wake: bool,
+    },
+    Io,
+}
+
+/// pins of a GPIO controller a device is wired to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GpioConnection {
+    pub kind: GpioKind,
+    pub shared: bool,
+    pub pins: Vec<u16>,
+    /// pull up/down, ACPI 6.5 section 6.4.3.8.1
+    pub pin_config: u8,
+    /// in hundredths of milliseconds
+    pub debounce_timeout: u16,
+    /// firmware path of the GPIO controller
+    pub controller: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SerialBus {
+    I2c {
+        address: u16,
+        ten_bit_address: bool,
+        speed_hz: u32,
+    },
+    Spi {
+        chip_select: u16,
+        chip_select_active_high: bool,
+        speed_hz: u32,
+        data_bits: u8,
+        clock_phase: u8,
+        clock_polarity: u8,
+    },
+    Uart {
+        baud: u32,
+        data_bits: u8,
+        /// ACPI encoding: 0 none, 1 one, 2 one and a half, 3 two
+        stop_bits: u8,
+        /// ACPI encoding: 0 none, 1 even, 2 odd, 3 mark, 4 space
+        parity: u8,
+        /// ACPI encoding: 0 none, 1 hardware, 2 XON/XOFF
+        flow_control: u8,
+    },
+}
+
+/// a device on an I2C, SPI or UART bus
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SerialBusConnection {
+    pub bus: SerialBus,
+    /// firmware path of the bus controller
+    pub controller: String,
 }
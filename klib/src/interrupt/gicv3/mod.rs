@@ -27,6 +27,7 @@ use crate::{
     cache::clean_dcache_range,
     cpu_interface::CpuIdLogical,
     guard::InterruptGuard,
+    hardware::resource::IrqTrigger,
     interrupt::{
         GicrRegisters, GitsRegisters,
         gicv3::{
@@ -77,6 +78,15 @@ pub struct IrqHandler {
     dispatch_fn: IrqHandlerFnPtr,
 }
 
+impl IrqHandler {
+    pub fn new(target: IrqTarget, dispatch_fn: fn(u32) -> Result<()>) -> Option<Self> {
+        Some(Self {
+            target,
+            dispatch_fn: IrqHandlerFnPtr::new(dispatch_fn).ok()?,
+        })
+    }
+}
+
 struct ItsCmdQueue {
     base: NonNull<u8>,
     write_offset: usize,
@@ -735,6 +745,33 @@ impl<'a, I: InterruptInterface + Send + Sync> InterruptController for GicV3<'a,
         Ok(())
     }
 
+    fn set_trigger(&self, int_id: u32, trigger: IrqTrigger) -> Result<()> {
+        // 2 bits per interrupt, the upper one set for edge triggered
+        let shift = (int_id % 16) * 2;
+        let edge = match trigger {
+            IrqTrigger::Level => 0b00,
+            IrqTrigger::Edge => 0b10,
+        };
+        let update = |cfg: u32| (cfg & !(0b11 << shift)) | (edge << shift);
+
+        if int_id < 16 || (LPI_START..=MAX_LPI_ID).contains(&int_id) {
+            // SGIs and LPIs are always edge triggered
+            if trigger != IrqTrigger::Edge {
+                return Err(InterruptError::NotSupported);
+            }
+        } else if int_id < 32 {
+            let redist = self.redistributor_mut();
+            let cfg = redist.icfg1.read_pure();
+            redist.icfg1.write(update(cfg));
+        } else if int_id < 1020 {
+            let icfg = &self.distributor.icfg[(int_id / 16) as usize];
+            icfg.write_pure(update(icfg.read_pure()));
+        } else {
+            return Err(InterruptError::InvalidInterruptId);
+        }
+        Ok(())
+    }
+
     fn register_handler(&self, int_id: u32, handler: IrqHandler) -> Result<()> {
         if int_id < 1020 {
             let mut handle = self
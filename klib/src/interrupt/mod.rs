@@ -1,6 +1,7 @@
 pub mod gicv3;
 pub mod singleton;
 
+use aarch64_cpu::registers::{MPIDR_EL1, Readable};
 use mars_models::memory::registers::volatile::{
     RPureReadOnly, RPureReadPureWrite, RPureReadWrite, RWriteOnly,
 };
@@ -14,8 +15,9 @@ use gicv3::registers::gic::{
 };
 
 use crate::cpu_interface::CpuIdLogical;
-use crate::interrupt::gicv3::IrqHandler;
+use crate::hardware::resource::IrqTrigger;
 use crate::interrupt::gicv3::registers::gic::GicBitfield8;
+use crate::interrupt::gicv3::{IrqHandler, IrqTarget};
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum InterruptError {
@@ -44,6 +46,26 @@ pub enum InterruptError {
 
 type Result<T> = core::result::Result<T, InterruptError>;
 
+/// affinity fields of MPIDR_EL1, in GICD_IROUTER layout
+pub const MPIDR_AFFINITY_MASK: u64 = 0xFF_00FF_FFFF;
+
+/// point SPI `gsiv` at this core with `trigger`. it stays masked.
+pub fn target_spi(gsiv: u32, trigger: IrqTrigger) -> Result<()> {
+    let ic = singleton::get_interrupt_controller();
+    ic.set_affinity(gsiv, MPIDR_EL1.get() & MPIDR_AFFINITY_MASK)?;
+    ic.set_trigger(gsiv, trigger)
+}
+
+/// deliver SPI `gsiv` to `handler` on this core, and unmask it
+pub fn route_spi(gsiv: u32, trigger: IrqTrigger, handler: fn(u32) -> Result<()>) -> Result<()> {
+    let handler =
+        IrqHandler::new(IrqTarget::Distributor, handler).ok_or(InterruptError::NotSupported)?;
+
+    singleton::get_interrupt_controller().register_handler(gsiv, handler)?;
+    target_spi(gsiv, trigger)?;
+    singleton::get_interrupt_controller().enable_interrupt(gsiv)
+}
+
 pub trait InterruptController: Send + Sync {
     /// initializes the controller. call on every core once.
     fn init(&self) -> Result<()>;
@@ -67,6 +89,10 @@ pub trait InterruptController: Send + Sync {
     /// routes interrupt to a specific CPU.
     fn set_affinity(&self, int_id: u32, affinity: u64) -> Result<()>;
 
+    /// sets whether an interrupt is edge triggered or level sensitive. only change this while the
+    /// interrupt is disabled.
+    fn set_trigger(&self, int_id: u32, trigger: IrqTrigger) -> Result<()>;
+
     /// handles an interrupt, as the name suggests.
     fn on_interrupt(&self, int_id: u32) -> Result<()>;
 
@@ -17,19 +17,25 @@ pub mod block;
 pub mod cache;
 pub mod context;
 pub mod cpu_interface;
+pub mod efi;
 pub mod exception;
+pub mod fbcon;
 pub mod guard;
 pub mod hardware;
 pub mod interrupt;
+pub mod net;
 pub mod per_cpu;
 pub mod pm;
 pub mod process;
+pub mod random;
 pub mod scheduler;
 pub mod smccc;
 pub mod stack;
 pub mod strange;
 pub mod sync;
 pub mod thread;
+pub mod time;
+pub mod tty;
 pub mod vcpu;
 pub mod vfs;
 pub mod vm;
@@ -0,0 +1,243 @@
+//! address resolution (RFC 826)
+
+use alloc::{collections::btree_map::BTreeMap, vec::Vec};
+
+use super::{
+    Ipv4Address,
+    device::{MacAddress, TxChecksum},
+    ethernet::{ETHERTYPE_ARP, EthernetHeader},
+    stack::NetStack,
+};
+
+const HTYPE_ETHERNET: u16 = 1;
+const OP_REQUEST: u16 = 1;
+const OP_REPLY: u16 = 2;
+const PACKET_LEN: usize = 28;
+
+/// how long a learned mapping stays valid
+const ENTRY_LIFETIME_MS: u64 = 120_000;
+const RETRY_INTERVAL_MS: u64 = 1_000;
+const MAX_REQUESTS: u32 = 3;
+/// frames held per unresolved address
+const MAX_PENDING: usize = 16;
+
+#[derive(Debug, Copy, Clone)]
+struct Entry {
+    mac: MacAddress,
+    expires: u64,
+}
+
+#[derive(Debug)]
+struct Resolution {
+    requests: u32,
+    next_request: u64,
+    /// complete frames whose destination MAC is still zero
+    frames: Vec<(Vec<u8>, Option<TxChecksum>)>,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct ArpCache {
+    entries: BTreeMap<(usize, Ipv4Address), Entry>,
+    pending: BTreeMap<(usize, Ipv4Address), Resolution>,
+}
+
+impl ArpCache {
+    pub const fn new() -> Self {
+        Self {
+            entries: BTreeMap::new(),
+            pending: BTreeMap::new(),
+        }
+    }
+
+    pub fn lookup(&self, iface: usize, ip: Ipv4Address, now: u64) -> Option<MacAddress> {
+        self.entries
+            .get(&(iface, ip))
+            .filter(|e| e.expires > now)
+            .map(|e| e.mac)
+    }
+
+    pub fn next_deadline(&self) -> Option<u64> {
+        self.pending.values().map(|r| r.next_request).min()
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+struct ArpPacket {
+    op: u16,
+    sender_mac: MacAddress,
+    sender_ip: Ipv4Address,
+    target_mac: MacAddress,
+    target_ip: Ipv4Address,
+}
+
+impl ArpPacket {
+    fn parse(data: &[u8]) -> Option<Self> {
+        if data.len() < PACKET_LEN {
+            return None;
+        }
+
+        let htype = u16::from_be_bytes([data[0], data[1]]);
+        let ptype = u16::from_be_bytes([data[2], data[3]]);
+        if htype != HTYPE_ETHERNET || ptype != super::ethernet::ETHERTYPE_IPV4 {
+            return None;
+        }
+        if data[4] != 6 || data[5] != 4 {
+            return None;
+        }
+
+        Some(Self {
+            op: u16::from_be_bytes([data[6], data[7]]),
+            sender_mac: MacAddress(data[8..14].try_into().ok()?),
+            sender_ip: Ipv4Address(data[14..18].try_into().ok()?),
+            target_mac: MacAddress(data[18..24].try_into().ok()?),
+            target_ip: Ipv4Address(data[24..28].try_into().ok()?),
+        })
+    }
+
+    fn emit(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&HTYPE_ETHERNET.to_be_bytes());
+        buf.extend_from_slice(&super::ethernet::ETHERTYPE_IPV4.to_be_bytes());
+        buf.extend_from_slice(&[6, 4]);
+        buf.extend_from_slice(&self.op.to_be_bytes());
+        buf.extend_from_slice(&self.sender_mac.0);
+        buf.extend_from_slice(&self.sender_ip.0);
+        buf.extend_from_slice(&self.target_mac.0);
+        buf.extend_from_slice(&self.target_ip.0);
+    }
+}
+
+fn send_packet(stack: &mut NetStack, iface: usize, dst: MacAddress, packet: ArpPacket) {
+    let mut frame = Vec::with_capacity(14 + PACKET_LEN);
+    EthernetHeader {
+        dst,
+        src: stack.interfaces[iface].mac(),
+        ethertype: ETHERTYPE_ARP,
+    }
+    .emit(&mut frame);
+    packet.emit(&mut frame);
+
+    stack.transmit(iface, &frame, None);
+}
+
+fn send_request(stack: &mut NetStack, iface: usize, target: Ipv4Address) {
+    let packet = ArpPacket {
+        op: OP_REQUEST,
+        sender_mac: stack.interfaces[iface].mac(),
+        sender_ip: stack.interfaces[iface]
+            .addr()
+            .unwrap_or(Ipv4Address::UNSPECIFIED),
+        target_mac: MacAddress::ZERO,
+        target_ip: target,
+    };
+
+    send_packet(stack, iface, MacAddress::BROADCAST, packet);
+}
+
+/// hold `frame` until `next_hop` resolves, asking for it if this is the first frame.
+pub(crate) fn enqueue(
+    stack: &mut NetStack,
+    iface: usize,
+    next_hop: Ipv4Address,
+    frame: Vec<u8>,
+    csum: Option<TxChecksum>,
+    now: u64,
+) {
+    let key = (iface, next_hop);
+
+    if let Some(resolution) = stack.arp.pending.get_mut(&key) {
+        if resolution.frames.len() < MAX_PENDING {
+            resolution.frames.push((frame, csum));
+        }
+        return;
+    }
+
+    stack.arp.pending.insert(
+        key,
+        Resolution {
+            requests: 1,
+            next_request: now + RETRY_INTERVAL_MS,
+            frames: alloc::vec![(frame, csum)],
+        },
+    );
+    send_request(stack, iface, next_hop);
+}
+
+fn learn(stack: &mut NetStack, iface: usize, ip: Ipv4Address, mac: MacAddress, now: u64) {
+    stack.arp.entries.insert(
+        (iface, ip),
+        Entry {
+            mac,
+            expires: now + ENTRY_LIFETIME_MS,
+        },
+    );
+
+    if let Some(resolution) = stack.arp.pending.remove(&(iface, ip)) {
+        for (mut frame, csum) in resolution.frames {
+            frame[0..6].copy_from_slice(&mac.0);
+            stack.transmit(iface, &frame, csum);
+        }
+    }
+}
+
+pub(crate) fn input(stack: &mut NetStack, iface: usize, data: &[u8], now: u64) {
+    let Some(packet) = ArpPacket::parse(data) else {
+        return;
+    };
+
+    let Some(our_ip) = stack.interfaces[iface].addr() else {
+        return;
+    };
+
+    let for_us = packet.target_ip == our_ip;
+    let known = stack.arp.entries.contains_key(&(iface, packet.sender_ip))
+        || stack.arp.pending.contains_key(&(iface, packet.sender_ip));
+
+    if !packet.sender_ip.is_unspecified() && (for_us || known) {
+        learn(stack, iface, packet.sender_ip, packet.sender_mac, now);
+    }
+
+    if packet.op == OP_REQUEST && for_us {
+        let reply = ArpPacket {
+            op: OP_REPLY,
+            sender_mac: stack.interfaces[iface].mac(),
+            sender_ip: our_ip,
+            target_mac: packet.sender_mac,
+            target_ip: packet.sender_ip,
+        };
+        send_packet(stack, iface, packet.sender_mac, reply);
+    }
+}
+
+pub(crate) fn on_timer(stack: &mut NetStack, now: u64) {
+    use log::*;
+
+    stack.arp.entries.retain(|_, e| e.expires > now);
+
+    let due: Vec<_> = stack
+        .arp
+        .pending
+        .iter()
+        .filter(|(_, r)| r.next_request <= now)
+        .map(|(k, _)| *k)
+        .collect();
+
+    for (iface, ip) in due {
+        let Some(resolution) = stack.arp.pending.get_mut(&(iface, ip)) else {
+            continue;
+        };
+
+        if resolution.requests >= MAX_REQUESTS {
+            debug!(
+                "arp: {} unreachable, dropping {} frames",
+                ip,
+                resolution.frames.len()
+            );
+            stack.arp.pending.remove(&(iface, ip));
+            continue;
+        }
+
+        resolution.requests += 1;
+        resolution.next_request = now + RETRY_INTERVAL_MS;
+        send_request(stack, iface, ip);
+    }
+}
@@ -0,0 +1,46 @@
+//! the internet checksum (RFC 1071)
+
+use super::Ipv4Address;
+
+/// add `data` to a running one's complement sum
+pub fn accumulate(mut sum: u32, data: &[u8]) -> u32 {
+    let mut chunks = data.chunks_exact(2);
+    for pair in &mut chunks {
+        sum += u16::from_be_bytes([pair[0], pair[1]]) as u32;
+    }
+
+    if let [last] = chunks.remainder() {
+        sum += (*last as u32) << 8;
+    }
+
+    sum
+}
+
+/// fold carries back into 16 bits, without inverting
+pub fn fold(mut sum: u32) -> u16 {
+    while sum > 0xFFFF {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+
+    sum as u16
+}
+
+pub fn checksum(data: &[u8]) -> u16 {
+    !fold(accumulate(0, data))
+}
+
+/// sum of the TCP/UDP pseudo-header
+pub fn pseudo_header(src: Ipv4Address, dst: Ipv4Address, protocol: u8, len: u16) -> u32 {
+    let mut sum = accumulate(0, &src.0);
+    sum = accumulate(sum, &dst.0);
+    sum + protocol as u32 + len as u32
+}
+
+/// verify a received L4 segment (checksum field included in `segment`)
+pub fn verify_l4(src: Ipv4Address, dst: Ipv4Address, protocol: u8, segment: &[u8]) -> bool {
+    let sum = accumulate(
+        pseudo_header(src, dst, protocol, segment.len() as u16),
+        segment,
+    );
+    fold(sum) == 0xFFFF
+}
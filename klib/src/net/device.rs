@@ -0,0 +1,124 @@
+use core::fmt::{self, Display};
+
+use alloc::{sync::Arc, vec::Vec};
+use atomic_refcell::AtomicRefCell;
+
+use crate::sync::RwLock;
+
+/// length of an ethernet II header (dst, src, ethertype)
+pub const ETH_HEADER_LEN: usize = 14;
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[repr(transparent)]
+pub struct MacAddress(pub [u8; 6]);
+
+impl MacAddress {
+    pub const BROADCAST: Self = Self([0xFF; 6]);
+    pub const ZERO: Self = Self([0; 6]);
+
+    #[inline]
+    pub const fn is_broadcast(&self) -> bool {
+        matches!(self.0, [0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF])
+    }
+
+    #[inline]
+    pub const fn is_multicast(&self) -> bool {
+        self.0[0] & 0x01 != 0
+    }
+}
+
+impl Display for MacAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [a, b, c, d, e, g] = self.0;
+        write!(f, "{a:02x}:{b:02x}:{c:02x}:{d:02x}:{e:02x}:{g:02x}")
+    }
+}
+
+/// checksum work the device can do on behalf of the stack
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct ChecksumCaps {
+    /// the device can finish a partial L4 checksum on transmit
+    pub tx: bool,
+    /// the device validates L4 checksums on receive
+    pub rx: bool,
+}
+
+/// partial checksum request for a transmitted frame. the stack stores the pseudo-header sum at
+/// `start + offset`; the device sums from `start` to the end of the frame and writes it there.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct TxChecksum {
+    pub start: u16,
+    pub offset: u16,
+}
+
+#[derive(Debug, Clone)]
+pub struct RxFrame {
+    /// the frame, starting at the ethernet header
+    pub data: Vec<u8>,
+    /// the device already verified the L4 checksum
+    pub checksum_valid: bool,
+}
+
+pub trait NetworkDevice: Send + Sync {
+    /// short interface name for logging
+    fn name(&self) -> &str;
+    fn mac_address(&self) -> MacAddress;
+    /// maximum L3 payload, not counting the ethernet header.
+    fn mtu(&self) -> usize;
+    fn link_up(&self) -> bool;
+    fn checksum_caps(&self) -> ChecksumCaps {
+        ChecksumCaps::default()
+    }
+    /// queue a frame for transmission. `frame` starts at the ethernet header.
+    fn transmit(&self, frame: &[u8], csum: Option<TxChecksum>) -> super::Result<()>;
+    /// pop the next received frame, if any.
+    fn receive(&self) -> Option<RxFrame>;
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[repr(transparent)]
+pub struct NetDeviceId(pub usize);
+
+pub type RxListener = fn(NetDeviceId);
+
+static DEVICES: RwLock<Vec<Arc<dyn NetworkDevice>>> = RwLock::new(Vec::new());
+static RX_LISTENER: AtomicRefCell<Option<RxListener>> = AtomicRefCell::new(None);
+
+pub fn register_device(device: Arc<dyn NetworkDevice>) -> NetDeviceId {
+    use log::*;
+
+    let mut devices = DEVICES.write();
+    let id = NetDeviceId(devices.len());
+
+    info!(
+        "net: registered {} (mac {}, mtu {})",
+        device.name(),
+        device.mac_address(),
+        device.mtu()
+    );
+
+    devices.push(device);
+    id
+}
+
+pub fn get_device(id: NetDeviceId) -> Option<Arc<dyn NetworkDevice>> {
+    DEVICES.read().get(id.0).cloned()
+}
+
+pub fn device_count() -> usize {
+    DEVICES.read().len()
+}
+
+/// set the function called when a device has frames waiting.
+pub fn set_rx_listener(listener: RxListener) {
+    *RX_LISTENER.borrow_mut() = Some(listener);
+}
+
+/// called by drivers (usually from their RX interrupt) once frames are queued.
+pub fn notify_rx(id: NetDeviceId) {
+    if let Ok(listener) = RX_LISTENER.try_borrow()
+        && let Some(listener) = *listener
+    {
+        listener(id);
+    }
+}
@@ -0,0 +1,439 @@
+//! DHCP client (RFC 2131), one per ethernet interface.
+
+use alloc::vec::Vec;
+
+use super::{
+    Ipv4Address, SocketAddrV4,
+    device::MacAddress,
+    iface::InterfaceConfig,
+    stack::{NetStack, Route},
+    udp,
+};
+
+pub const CLIENT_PORT: u16 = 68;
+const SERVER_PORT: u16 = 67;
+
+const OP_REQUEST: u8 = 1;
+const OP_REPLY: u8 = 2;
+const HTYPE_ETHERNET: u8 = 1;
+/// ask for broadcast replies, we can't receive unicast before we have an address
+const FLAG_BROADCAST: u16 = 0x8000;
+const MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+/// fixed BOOTP part, up to and including the magic cookie
+const BOOTP_LEN: usize = 240;
+
+const OPT_PAD: u8 = 0;
+const OPT_SUBNET_MASK: u8 = 1;
+const OPT_ROUTER: u8 = 3;
+const OPT_DNS: u8 = 6;
+const OPT_REQUESTED_IP: u8 = 50;
+const OPT_LEASE_TIME: u8 = 51;
+const OPT_MESSAGE_TYPE: u8 = 53;
+const OPT_SERVER_ID: u8 = 54;
+const OPT_PARAMETER_LIST: u8 = 55;
+const OPT_RENEWAL_TIME: u8 = 58;
+const OPT_REBINDING_TIME: u8 = 59;
+const OPT_END: u8 = 255;
+
+const DHCPDISCOVER: u8 = 1;
+const DHCPOFFER: u8 = 2;
+const DHCPREQUEST: u8 = 3;
+const DHCPACK: u8 = 5;
+const DHCPNAK: u8 = 6;
+
+const INITIAL_BACKOFF_MS: u64 = 4_000;
+const MAX_BACKOFF_MS: u64 = 32_000;
+const MAX_REQUESTS: u32 = 4;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct Lease {
+    config: InterfaceConfig,
+    server: Ipv4Address,
+    duration_ms: u64,
+    renew_at: u64,
+    rebind_at: u64,
+    expires: u64,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum State {
+    Init,
+    Selecting,
+    Requesting {
+        server: Ipv4Address,
+        offered: Ipv4Address,
+        sent: u32,
+    },
+    Bound,
+    /// T1 passed, asking the server that gave us the lease
+    Renewing,
+    /// T2 passed, asking anyone
+    Rebinding,
+}
+
+/// what the stack should do after the client handled an event
+enum Effect {
+    Send {
+        msg: Vec<u8>,
+        /// unicast to the leasing server, otherwise broadcast
+        server: Option<Ipv4Address>,
+    },
+    Bind(Lease),
+    Unbind,
+}
+
+#[derive(Debug)]
+pub(crate) struct DhcpClient {
+    mac: MacAddress,
+    xid: u32,
+    state: State,
+    lease: Option<Lease>,
+    deadline: u64,
+    backoff: u64,
+}
+
+/// options we care about from a server reply
+#[derive(Debug, Default)]
+struct Reply {
+    message_type: u8,
+    yiaddr: Ipv4Address,
+    netmask: Option<Ipv4Address>,
+    router: Option<Ipv4Address>,
+    dns: Option<Ipv4Address>,
+    server: Option<Ipv4Address>,
+    lease_secs: Option<u32>,
+    renewal_secs: Option<u32>,
+    rebinding_secs: Option<u32>,
+}
+
+fn ip_option(value: &[u8]) -> Option<Ipv4Address> {
+    let bytes: [u8; 4] = value.get(..4)?.try_into().ok()?;
+    Some(Ipv4Address(bytes))
+}
+
+fn u32_option(value: &[u8]) -> Option<u32> {
+    Some(u32::from_be_bytes(value.get(..4)?.try_into().ok()?))
+}
+
+impl DhcpClient {
+    pub fn new(mac: MacAddress, xid: u32) -> Self {
+        Self {
+            mac,
+            xid,
+            state: State::Init,
+            lease: None,
+            deadline: 0,
+            backoff: INITIAL_BACKOFF_MS,
+        }
+    }
+
+    pub fn next_deadline(&self) -> Option<u64> {
+        Some(self.deadline)
+    }
+
+    fn message(&self, ty: u8, ciaddr: Ipv4Address, options: &[(u8, &[u8])]) -> Vec<u8> {
+        let mut msg = Vec::with_capacity(BOOTP_LEN + 64);
+
+        msg.extend_from_slice(&[OP_REQUEST, HTYPE_ETHERNET, 6, 0]);
+        msg.extend_from_slice(&self.xid.to_be_bytes());
+        // secs
+        msg.extend_from_slice(&[0, 0]);
+        let flags = if ciaddr.is_unspecified() {
+            FLAG_BROADCAST
+        } else {
+            0
+        };
+        msg.extend_from_slice(&flags.to_be_bytes());
+        msg.extend_from_slice(&ciaddr.0);
+        // yiaddr, siaddr, giaddr
+        msg.extend_from_slice(&[0; 12]);
+        msg.extend_from_slice(&self.mac.0);
+        // rest of chaddr, sname, file
+        msg.resize(BOOTP_LEN - MAGIC_COOKIE.len(), 0);
+        msg.extend_from_slice(&MAGIC_COOKIE);
+
+        msg.extend_from_slice(&[OPT_MESSAGE_TYPE, 1, ty]);
+        for (code, value) in options {
+            msg.push(*code);
+            msg.push(value.len() as u8);
+            msg.extend_from_slice(value);
+        }
+        msg.extend_from_slice(&[
+            OPT_PARAMETER_LIST,
+            4,
+            OPT_SUBNET_MASK,
+            OPT_ROUTER,
+            OPT_DNS,
+            OPT_LEASE_TIME,
+        ]);
+        msg.push(OPT_END);
+
+        msg
+    }
+
+    fn parse(&self, data: &[u8]) -> Option<Reply> {
+        if data.len() < BOOTP_LEN
+            || data[0] != OP_REPLY
+            || data[4..8] != self.xid.to_be_bytes()
+            || data[28..34] != self.mac.0
+            || data[236..240] != MAGIC_COOKIE
+        {
+            return None;
+        }
+
+        let mut reply = Reply {
+            yiaddr: ip_option(&data[16..20])?,
+            ..Default::default()
+        };
+
+        let mut options = &data[BOOTP_LEN..];
+        while let Some(&code) = options.first() {
+            match code {
+                OPT_PAD => {
+                    options = &options[1..];
+                    continue;
+                }
+                OPT_END => break,
+                _ => {}
+            }
+
+            let len = *options.get(1)? as usize;
+            let value = options.get(2..2 + len)?;
+            options = &options[2 + len..];
+
+            match code {
+                OPT_MESSAGE_TYPE => reply.message_type = *value.first()?,
+                OPT_SUBNET_MASK => reply.netmask = ip_option(value),
+                OPT_ROUTER => reply.router = ip_option(value),
+                OPT_DNS => reply.dns = ip_option(value),
+                OPT_SERVER_ID => reply.server = ip_option(value),
+                OPT_LEASE_TIME => reply.lease_secs = u32_option(value),
+                OPT_RENEWAL_TIME => reply.renewal_secs = u32_option(value),
+                OPT_REBINDING_TIME => reply.rebinding_secs = u32_option(value),
+                _ => {}
+            }
+        }
+
+        Some(reply)
+    }
+
+    fn discover(&mut self, now: u64) -> Effect {
+        self.state = State::Selecting;
+        self.deadline = now + self.backoff;
+        self.backoff = (self.backoff * 2).min(MAX_BACKOFF_MS);
+
+        Effect::Send {
+            msg: self.message(DHCPDISCOVER, Ipv4Address::UNSPECIFIED, &[]),
+            server: None,
+        }
+    }
+
+    fn request(
+        &mut self,
+        server: Ipv4Address,
+        offered: Ipv4Address,
+        sent: u32,
+        now: u64,
+    ) -> Effect {
+        self.state = State::Requesting {
+            server,
+            offered,
+            sent: sent + 1,
+        };
+        self.deadline = now + INITIAL_BACKOFF_MS;
+
+        Effect::Send {
+            msg: self.message(
+                DHCPREQUEST,
+                Ipv4Address::UNSPECIFIED,
+                &[(OPT_REQUESTED_IP, &offered.0), (OPT_SERVER_ID, &server.0)],
+            ),
+            server: None,
+        }
+    }
+
+    /// REQUEST for the current lease. unicast while renewing, broadcast while rebinding.
+    fn extend(&mut self, lease: Lease, now: u64) -> Effect {
+        let limit = match self.state {
+            State::Renewing => lease.rebind_at,
+            _ => lease.expires,
+        };
+        // RFC 2131 4.4.5: half the remaining time, but at least a minute
+        self.deadline = (now + (limit.saturating_sub(now) / 2).max(60_000)).min(limit);
+
+        Effect::Send {
+            msg: self.message(DHCPREQUEST, lease.config.addr, &[]),
+            server: (self.state == State::Renewing).then_some(lease.server),
+        }
+    }
+
+    fn restart(&mut self, now: u64) -> Option<Effect> {
+        self.state = State::Init;
+        self.backoff = INITIAL_BACKOFF_MS;
+        self.deadline = now;
+        self.xid = self.xid.wrapping_add(1);
+        self.lease.take().map(|_| Effect::Unbind)
+    }
+
+    fn on_timer(&mut self, now: u64) -> Option<Effect> {
+        if now < self.deadline {
+            return None;
+        }
+
+        match self.state {
+            State::Init | State::Selecting => Some(self.discover(now)),
+            State::Requesting { sent, .. } if sent >= MAX_REQUESTS => {
+                self.restart(now);
+                Some(self.discover(now))
+            }
+            State::Requesting {
+                server,
+                offered,
+                sent,
+            } => Some(self.request(server, offered, sent, now)),
+            State::Bound | State::Renewing | State::Rebinding => {
+                let lease = self.lease?;
+
+                if now >= lease.expires {
+                    return self.restart(now);
+                }
+
+                self.state = if now >= lease.rebind_at {
+                    State::Rebinding
+                } else {
+                    State::Renewing
+                };
+                Some(self.extend(lease, now))
+            }
+        }
+    }
+
+    fn on_reply(&mut self, reply: Reply, now: u64) -> Option<Effect> {
+        match (self.state, reply.message_type) {
+            (State::Selecting, DHCPOFFER) => {
+                let server = reply.server?;
+                Some(self.request(server, reply.yiaddr, 0, now))
+            }
+            (State::Requesting { .. } | State::Renewing | State::Rebinding, DHCPACK) => {
+                let server = match self.state {
+                    State::Requesting { server, .. } => server,
+                    _ => self.lease.map(|l| l.server)?,
+                };
+
+                let duration_ms = reply.lease_secs.unwrap_or(u32::MAX) as u64 * 1000;
+                let renew_ms = reply
+                    .renewal_secs
+                    .map_or(duration_ms / 2, |s| s as u64 * 1000);
+                let rebind_ms = reply
+                    .rebinding_secs
+                    .map_or(duration_ms / 8 * 7, |s| s as u64 * 1000);
+
+                let lease = Lease {
+                    config: InterfaceConfig {
+                        addr: reply.yiaddr,
+                        netmask: reply.netmask.unwrap_or(Ipv4Address::new(255, 255, 255, 0)),
+                        gateway: reply.router,
+                        dns: reply.dns,
+                    },
+                    server: reply.server.unwrap_or(server),
+                    duration_ms,
+                    renew_at: now + renew_ms,
+                    rebind_at: now + rebind_ms,
+                    expires: now + duration_ms,
+                };
+
+                self.state = State::Bound;
+                self.lease = Some(lease);
+                self.deadline = lease.renew_at;
+                self.backoff = INITIAL_BACKOFF_MS;
+                Some(Effect::Bind(lease))
+            }
+            (State::Requesting { .. } | State::Renewing | State::Rebinding, DHCPNAK) => {
+                self.restart(now)
+            }
+            _ => None,
+        }
+    }
+}
+
+fn apply(stack: &mut NetStack, iface: usize, effect: Effect) {
+    use log::*;
+
+    match effect {
+        Effect::Send { msg, server } => {
+            let addr = stack.interfaces[iface].addr();
+            let unicast = server
+                .filter(|_| addr.is_some())
+                .and_then(|server| Some((stack.route(server).ok()?, server)));
+
+            let (route, dst) = unicast.unwrap_or((
+                Route {
+                    iface,
+                    src: addr.unwrap_or(Ipv4Address::UNSPECIFIED),
+                    next_hop: Ipv4Address::BROADCAST,
+                },
+                Ipv4Address::BROADCAST,
+            ));
+
+            _ = udp::send_on(
+                stack,
+                &route,
+                CLIENT_PORT,
+                SocketAddrV4::new(dst, SERVER_PORT),
+                &msg,
+            );
+        }
+        Effect::Bind(lease) => {
+            let interface = &mut stack.interfaces[iface];
+            let config = lease.config;
+            if interface.config != Some(config) {
+                info!(
+                    "net: {} bound to {}/{} gateway {} dns {} lease {}s",
+                    interface.device.name(),
+                    config.addr,
+                    Ipv4Address::prefix_len(config.netmask),
+                    config.gateway.unwrap_or(Ipv4Address::UNSPECIFIED),
+                    config.dns.unwrap_or(Ipv4Address::UNSPECIFIED),
+                    lease.duration_ms / 1000,
+                );
+            }
+            interface.config = Some(config);
+        }
+        Effect::Unbind => {
+            let interface = &mut stack.interfaces[iface];
+            warn!("net: {} lost its DHCP lease", interface.device.name());
+            interface.config = None;
+        }
+    }
+}
+
+pub(crate) fn input(stack: &mut NetStack, iface: usize, payload: &[u8], now: u64) {
+    let Some(client) = stack.interfaces[iface].dhcp.as_mut() else {
+        return;
+    };
+
+    let Some(reply) = client.parse(payload) else {
+        return;
+    };
+
+    if let Some(effect) = client.on_reply(reply, now) {
+        apply(stack, iface, effect);
+    }
+}
+
+pub(crate) fn on_timer(stack: &mut NetStack, now: u64) {
+    for iface in 0..stack.interfaces.len() {
+        if !stack.interfaces[iface].device.link_up() {
+            continue;
+        }
+
+        let effect = stack.interfaces[iface]
+            .dhcp
+            .as_mut()
+            .and_then(|client| client.on_timer(now));
+
+        if let Some(effect) = effect {
+            apply(stack, iface, effect);
+        }
+    }
+}
@@ -0,0 +1,35 @@
+use alloc::vec::Vec;
+
+use super::device::{ETH_HEADER_LEN, MacAddress};
+
+pub const ETHERTYPE_IPV4: u16 = 0x0800;
+pub const ETHERTYPE_ARP: u16 = 0x0806;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct EthernetHeader {
+    pub dst: MacAddress,
+    pub src: MacAddress,
+    pub ethertype: u16,
+}
+
+impl EthernetHeader {
+    pub fn parse(frame: &[u8]) -> Option<(Self, &[u8])> {
+        if frame.len() < ETH_HEADER_LEN {
+            return None;
+        }
+
+        let header = Self {
+            dst: MacAddress(frame[0..6].try_into().ok()?),
+            src: MacAddress(frame[6..12].try_into().ok()?),
+            ethertype: u16::from_be_bytes([frame[12], frame[13]]),
+        };
+
+        Some((header, &frame[ETH_HEADER_LEN..]))
+    }
+
+    pub fn emit(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.dst.0);
+        buf.extend_from_slice(&self.src.0);
+        buf.extend_from_slice(&self.ethertype.to_be_bytes());
+    }
+}
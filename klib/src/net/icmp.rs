@@ -0,0 +1,157 @@
+//! ICMP echo (RFC 792): replies to pings and a blocking [`ping`] for the kernel.
+
+use alloc::{collections::btree_map::BTreeMap, sync::Arc, vec::Vec};
+
+use super::{
+    Ipv4Address, NetError, Result,
+    checksum::checksum,
+    ipv4::{Ipv4Header, PROTO_ICMP},
+    stack::{self, NetStack, now_ms, with_stack},
+};
+use crate::{scheduler::GLOBAL_SCHEDULER, sync::WaitQueue};
+
+const TYPE_ECHO_REPLY: u8 = 0;
+const TYPE_ECHO_REQUEST: u8 = 8;
+
+const HEADER_LEN: usize = 8;
+const PING_PAYLOAD_LEN: usize = 32;
+
+struct Ping {
+    sent_at: u64,
+    deadline: u64,
+    reply_at: Option<u64>,
+    wait: Arc<WaitQueue<'static>>,
+}
+
+/// outstanding echo requests, keyed by (identifier, sequence)
+pub(crate) struct PingTable {
+    pending: BTreeMap<(u16, u16), Ping>,
+    next_ident: u16,
+}
+
+impl PingTable {
+    pub const fn new() -> Self {
+        Self {
+            pending: BTreeMap::new(),
+            next_ident: 1,
+        }
+    }
+
+    pub fn next_deadline(&self) -> Option<u64> {
+        self.pending
+            .values()
+            .filter(|p| p.reply_at.is_none())
+            .map(|p| p.deadline)
+            .min()
+    }
+}
+
+fn emit(ty: u8, ident: u16, seq: u16, payload: &[u8]) -> Vec<u8> {
+    let mut msg = Vec::with_capacity(HEADER_LEN + payload.len());
+    msg.extend_from_slice(&[ty, 0, 0, 0]);
+    msg.extend_from_slice(&ident.to_be_bytes());
+    msg.extend_from_slice(&seq.to_be_bytes());
+    msg.extend_from_slice(payload);
+
+    let csum = checksum(&msg);
+    msg[2..4].copy_from_slice(&csum.to_be_bytes());
+    msg
+}
+
+pub(crate) fn input(stack: &mut NetStack, _iface: usize, ip: &Ipv4Header, data: &[u8]) {
+    if data.len() < HEADER_LEN || checksum(data) != 0 {
+        return;
+    }
+
+    let ident = u16::from_be_bytes([data[4], data[5]]);
+    let seq = u16::from_be_bytes([data[6], data[7]]);
+
+    match data[0] {
+        TYPE_ECHO_REQUEST if !ip.dst.is_broadcast() => {
+            let Ok(route) = stack.route(ip.src) else {
+                return;
+            };
+
+            let reply = emit(TYPE_ECHO_REPLY, ident, seq, &data[HEADER_LEN..]);
+            _ = stack.send_ip(&route, ip.src, PROTO_ICMP, reply, None);
+        }
+        TYPE_ECHO_REPLY => {
+            if let Some(ping) = stack.pings.pending.get_mut(&(ident, seq))
+                && ping.reply_at.is_none()
+            {
+                ping.reply_at = Some(now_ms());
+                ping.wait.wake_all(&GLOBAL_SCHEDULER);
+            }
+        }
+        _ => {}
+    }
+}
+
+pub(crate) fn on_timer(stack: &mut NetStack, now: u64) {
+    for ping in stack.pings.pending.values() {
+        if ping.reply_at.is_none() && ping.deadline <= now {
+            ping.wait.wake_all(&GLOBAL_SCHEDULER);
+        }
+    }
+}
+
+/// send an echo request to `dst` and wait for the reply. returns the round trip in milliseconds.
+pub fn ping(dst: Ipv4Address, timeout_ms: u64) -> Result<u64> {
+    let wait = Arc::new(WaitQueue::new());
+
+    let key = with_stack(|s| {
+        let route = s.route(dst)?;
+
+        let ident = s.pings.next_ident;
+        s.pings.next_ident = ident.wrapping_add(1);
+        let key = (ident, 1);
+
+        let payload: Vec<u8> = (0..PING_PAYLOAD_LEN as u8).collect();
+        let msg = emit(TYPE_ECHO_REQUEST, key.0, key.1, &payload);
+
+        let now = now_ms();
+        s.pings.pending.insert(
+            key,
+            Ping {
+                sent_at: now,
+                deadline: now + timeout_ms,
+                reply_at: None,
+                wait: wait.clone(),
+            },
+        );
+
+        if let Err(e) = s.send_ip(&route, dst, PROTO_ICMP, msg, None) {
+            s.pings.pending.remove(&key);
+            return Err(e);
+        }
+
+        Ok(key)
+    })?;
+
+    // netd has to learn about the new deadline
+    stack::kick();
+
+    loop {
+        let seen = wait.generation();
+
+        let result = with_stack(|s| {
+            let Some(ping) = s.pings.pending.get(&key) else {
+                return Some(Err(NetError::TimedOut));
+            };
+            let result = match ping.reply_at {
+                Some(at) => Ok(at - ping.sent_at),
+                None if now_ms() >= ping.deadline => Err(NetError::TimedOut),
+                None => return None,
+            };
+
+            s.pings.pending.remove(&key);
+            Some(result)
+        });
+
+        if let Some(result) = result {
+            return result;
+        }
+
+        wait.wait(&GLOBAL_SCHEDULER, seen);
+    }
+}
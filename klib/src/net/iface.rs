@@ -0,0 +1,61 @@
+use alloc::{string::String, sync::Arc};
+
+use super::{
+    Ipv4Address,
+    device::{MacAddress, NetDeviceId, NetworkDevice},
+    dhcp::DhcpClient,
+};
+
+/// layer 3 configuration of an interface
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct InterfaceConfig {
+    pub addr: Ipv4Address,
+    pub netmask: Ipv4Address,
+    pub gateway: Option<Ipv4Address>,
+    pub dns: Option<Ipv4Address>,
+}
+
+impl InterfaceConfig {
+    pub const fn broadcast(&self) -> Ipv4Address {
+        Ipv4Address::from_u32(self.addr.to_u32() | !self.netmask.to_u32())
+    }
+}
+
+pub(crate) struct Interface {
+    pub id: NetDeviceId,
+    pub device: Arc<dyn NetworkDevice>,
+    pub config: Option<InterfaceConfig>,
+    pub dhcp: Option<DhcpClient>,
+    pub loopback: bool,
+}
+
+impl Interface {
+    pub fn mac(&self) -> MacAddress {
+        self.device.mac_address()
+    }
+
+    pub fn addr(&self) -> Option<Ipv4Address> {
+        self.config.map(|c| c.addr)
+    }
+
+    /// true if `dst` is addressed to this interface
+    pub fn accepts(&self, dst: Ipv4Address) -> bool {
+        match self.config {
+            _ if self.loopback || dst.is_broadcast() => true,
+            Some(config) => dst == config.addr || dst == config.broadcast(),
+            // still configuring, let DHCP see everything
+            None => true,
+        }
+    }
+}
+
+/// snapshot of an interface for callers outside the stack
+#[derive(Debug, Clone)]
+pub struct InterfaceInfo {
+    pub id: NetDeviceId,
+    pub name: String,
+    pub mac: MacAddress,
+    pub mtu: usize,
+    pub link_up: bool,
+    pub config: Option<InterfaceConfig>,
+}
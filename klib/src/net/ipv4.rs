@@ -0,0 +1,165 @@
+use core::fmt::{self, Display};
+
+use alloc::vec::Vec;
+
+use super::checksum::checksum;
+
+pub const PROTO_ICMP: u8 = 1;
+pub const PROTO_TCP: u8 = 6;
+pub const PROTO_UDP: u8 = 17;
+
+pub const HEADER_LEN: usize = 20;
+pub const DEFAULT_TTL: u8 = 64;
+
+const FLAG_DF: u16 = 0x4000;
+const FLAG_MF: u16 = 0x2000;
+const FRAG_OFFSET_MASK: u16 = 0x1FFF;
+
+#[derive(Copy, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[repr(transparent)]
+pub struct Ipv4Address(pub [u8; 4]);
+
+impl Ipv4Address {
+    pub const UNSPECIFIED: Self = Self([0, 0, 0, 0]);
+    pub const BROADCAST: Self = Self([255, 255, 255, 255]);
+    pub const LOOPBACK: Self = Self([127, 0, 0, 1]);
+
+    pub const fn new(a: u8, b: u8, c: u8, d: u8) -> Self {
+        Self([a, b, c, d])
+    }
+
+    pub const fn from_u32(v: u32) -> Self {
+        Self(v.to_be_bytes())
+    }
+
+    pub const fn to_u32(self) -> u32 {
+        u32::from_be_bytes(self.0)
+    }
+
+    pub const fn is_unspecified(&self) -> bool {
+        self.to_u32() == 0
+    }
+
+    pub const fn is_broadcast(&self) -> bool {
+        self.to_u32() == u32::MAX
+    }
+
+    pub const fn is_loopback(&self) -> bool {
+        self.0[0] == 127
+    }
+
+    pub const fn is_multicast(&self) -> bool {
+        self.0[0] & 0xF0 == 0xE0
+    }
+
+    /// true if `self` and `other` share the network given by `mask`
+    pub const fn same_subnet(&self, other: Ipv4Address, mask: Ipv4Address) -> bool {
+        self.to_u32() & mask.to_u32() == other.to_u32() & mask.to_u32()
+    }
+
+    pub const fn prefix_len(mask: Ipv4Address) -> u32 {
+        mask.to_u32().leading_ones()
+    }
+}
+
+impl Display for Ipv4Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [a, b, c, d] = self.0;
+        write!(f, "{a}.{b}.{c}.{d}")
+    }
+}
+
+impl fmt::Debug for Ipv4Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Display::fmt(self, f)
+    }
+}
+
+#[derive(Copy, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SocketAddrV4 {
+    pub addr: Ipv4Address,
+    pub port: u16,
+}
+
+impl SocketAddrV4 {
+    pub const UNSPECIFIED: Self = Self::new(Ipv4Address::UNSPECIFIED, 0);
+
+    pub const fn new(addr: Ipv4Address, port: u16) -> Self {
+        Self { addr, port }
+    }
+}
+
+impl Display for SocketAddrV4 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.addr, self.port)
+    }
+}
+
+impl fmt::Debug for SocketAddrV4 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Display::fmt(self, f)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Ipv4Header {
+    pub src: Ipv4Address,
+    pub dst: Ipv4Address,
+    pub protocol: u8,
+    pub ttl: u8,
+    pub ident: u16,
+}
+
+impl Ipv4Header {
+    /// validate a packet and split off its payload. fragments are dropped, there's no reassembly.
+    pub fn parse(packet: &[u8]) -> Option<(Self, &[u8])> {
+        if packet.len() < HEADER_LEN || packet[0] >> 4 != 4 {
+            return None;
+        }
+
+        let ihl = (packet[0] & 0x0F) as usize * 4;
+        let total_len = u16::from_be_bytes([packet[2], packet[3]]) as usize;
+        if ihl < HEADER_LEN || total_len < ihl || total_len > packet.len() {
+            return None;
+        }
+
+        if checksum(&packet[..ihl]) != 0 {
+            return None;
+        }
+
+        let flags = u16::from_be_bytes([packet[6], packet[7]]);
+        if flags & FLAG_MF != 0 || flags & FRAG_OFFSET_MASK != 0 {
+            return None;
+        }
+
+        let header = Self {
+            ident: u16::from_be_bytes([packet[4], packet[5]]),
+            ttl: packet[8],
+            protocol: packet[9],
+            src: Ipv4Address(packet[12..16].try_into().ok()?),
+            dst: Ipv4Address(packet[16..20].try_into().ok()?),
+        };
+
+        Some((header, &packet[ihl..total_len]))
+    }
+
+    /// append a 20 byte header for a payload of `payload_len` bytes
+    pub fn emit(&self, payload_len: usize, buf: &mut Vec<u8>) {
+        let start = buf.len();
+        let total_len = (HEADER_LEN + payload_len) as u16;
+
+        buf.push(0x45);
+        buf.push(0);
+        buf.extend_from_slice(&total_len.to_be_bytes());
+        buf.extend_from_slice(&self.ident.to_be_bytes());
+        buf.extend_from_slice(&FLAG_DF.to_be_bytes());
+        buf.push(self.ttl);
+        buf.push(self.protocol);
+        buf.extend_from_slice(&[0, 0]);
+        buf.extend_from_slice(&self.src.0);
+        buf.extend_from_slice(&self.dst.0);
+
+        let csum = checksum(&buf[start..start + HEADER_LEN]);
+        buf[start + 10..start + 12].copy_from_slice(&csum.to_be_bytes());
+    }
+}
@@ -0,0 +1,94 @@
+//! networking: link-layer devices and an IPv4 stack (ARP, ICMP, UDP, TCP, DHCP).
+//!
+//! protocol processing happens on the `netd` kernel thread, which drains every device when a
+//! driver signals RX and runs protocol timers. the sockets API in [`socket`] sleeps on per-socket
+//! wait queues.
+
+pub mod arp;
+pub mod checksum;
+pub mod device;
+pub mod dhcp;
+pub mod ethernet;
+pub mod icmp;
+pub mod iface;
+pub mod ipv4;
+pub mod loopback;
+pub mod socket;
+pub mod stack;
+pub mod tcp;
+pub mod udp;
+
+use core::fmt::Display;
+
+pub use icmp::ping;
+pub use ipv4::{Ipv4Address, SocketAddrV4};
+pub use socket::{Socket, SocketKind};
+pub use stack::{init, interfaces, now_ms, tick};
+
+/// errors that can occur on a network device or socket
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum NetError {
+    /// carrier is down
+    LinkDown,
+    /// frame is larger than the device MTU allows
+    FrameTooLarge,
+    /// frame is shorter than an ethernet header
+    FrameTooSmall,
+    /// no free TX descriptors
+    QueueFull,
+    HardwareError,
+    /// this device does not support the operation
+    NotSupported,
+    /// no interface can reach the destination
+    NoRoute,
+    /// the local address/port is already bound
+    AddressInUse,
+    /// the local address isn't assigned to any interface
+    AddressNotAvailable,
+    /// no free ephemeral ports
+    PortsExhausted,
+    /// the peer refused the connection
+    ConnectionRefused,
+    /// the peer reset the connection
+    ConnectionReset,
+    /// retransmissions gave up
+    TimedOut,
+    /// socket isn't connected
+    NotConnected,
+    /// socket is already connected or listening
+    AlreadyConnected,
+    /// operation isn't valid in the socket's current state
+    InvalidState,
+    InvalidArgument,
+    /// no such socket
+    BadHandle,
+}
+
+impl Display for NetError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::LinkDown => f.write_str("link is down"),
+            Self::FrameTooLarge => f.write_str("frame exceeds device MTU"),
+            Self::FrameTooSmall => f.write_str("frame is shorter than an ethernet header"),
+            Self::QueueFull => f.write_str("transmit queue is full"),
+            Self::HardwareError => f.write_str("hardware error occurred during I/O"),
+            Self::NotSupported => f.write_str("operation not supported by this device"),
+            Self::NoRoute => f.write_str("no route to host"),
+            Self::AddressInUse => f.write_str("address already in use"),
+            Self::AddressNotAvailable => f.write_str("address not available"),
+            Self::PortsExhausted => f.write_str("no free ephemeral ports"),
+            Self::ConnectionRefused => f.write_str("connection refused"),
+            Self::ConnectionReset => f.write_str("connection reset by peer"),
+            Self::TimedOut => f.write_str("operation timed out"),
+            Self::NotConnected => f.write_str("socket is not connected"),
+            Self::AlreadyConnected => f.write_str("socket is already connected"),
+            Self::InvalidState => f.write_str("invalid operation for socket state"),
+            Self::InvalidArgument => f.write_str("invalid argument"),
+            Self::BadHandle => f.write_str("no such socket"),
+        }
+    }
+}
+
+impl core::error::Error for NetError {}
+
+pub type Result<T> = core::result::Result<T, NetError>;
@@ -0,0 +1,471 @@
+//! kernel sockets: BSD-style TCP and UDP endpoints that sleep until the stack makes progress.
+
+use alloc::{boxed::Box, collections::vec_deque::VecDeque, sync::Arc};
+use core::task::Poll;
+
+use super::{
+    Ipv4Address, NetError, Result, SocketAddrV4, ipv4,
+    stack::{self, NetStack, now_ms, with_stack},
+    tcp::{self, Tcb, TcpState},
+    udp::{self, UdpSocket},
+};
+use crate::{scheduler::GLOBAL_SCHEDULER, sync::WaitQueue};
+
+/// listen backlog when the caller passes 0
+const DEFAULT_BACKLOG: usize = 16;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SocketHandle(pub(crate) u32);
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SocketKind {
+    /// TCP
+    Stream,
+    /// UDP
+    Datagram,
+}
+
+#[derive(Debug)]
+pub(crate) enum SocketState {
+    /// a stream socket that hasn't connected or listened yet
+    TcpIdle {
+        local: Option<SocketAddrV4>,
+    },
+    TcpListen {
+        local: SocketAddrV4,
+        backlog: usize,
+        /// established connections waiting for `accept`
+        ready: VecDeque<SocketHandle>,
+    },
+    Tcp(Box<Tcb>),
+    Udp(UdpSocket),
+}
+
+impl SocketState {
+    pub fn kind(&self) -> SocketKind {
+        match self {
+            Self::Udp(_) => SocketKind::Datagram,
+            _ => SocketKind::Stream,
+        }
+    }
+
+    pub fn local_addr(&self) -> Option<SocketAddrV4> {
+        match self {
+            Self::TcpIdle { local } => *local,
+            Self::TcpListen { local, .. } => Some(*local),
+            Self::Tcp(tcb) => Some(tcb.local),
+            Self::Udp(udp) => udp.local,
+        }
+    }
+}
+
+pub(crate) struct SocketEntry {
+    pub wait: Arc<WaitQueue<'static>>,
+    pub state: SocketState,
+}
+
+/// a socket owned by kernel code. closed on drop.
+#[derive(Debug)]
+pub struct Socket {
+    handle: SocketHandle,
+    kind: SocketKind,
+}
+
+/// run `f` against the socket's state until it stops returning `Pending`, sleeping in between
+fn block_on<R>(
+    handle: SocketHandle,
+    mut f: impl FnMut(&mut NetStack, SocketHandle) -> Poll<Result<R>>,
+) -> Result<R> {
+    loop {
+        let polled = with_stack(|s| {
+            let wait = s
+                .sockets
+                .get(&handle)
+                .ok_or(NetError::BadHandle)?
+                .wait
+                .clone();
+            let seen = wait.generation();
+            Ok((f(s, handle), wait, seen))
+        });
+
+        match polled {
+            Err(e) => return Err(e),
+            Ok((Poll::Ready(result), _, _)) => return result,
+            Ok((Poll::Pending, wait, seen)) => wait.wait(&GLOBAL_SCHEDULER, seen),
+        }
+    }
+}
+
+fn tcb_mut(s: &mut NetStack, handle: SocketHandle) -> Result<&mut Tcb> {
+    match s.sockets.get_mut(&handle).map(|e| &mut e.state) {
+        Some(SocketState::Tcp(tcb)) => Ok(tcb),
+        Some(_) => Err(NetError::NotConnected),
+        None => Err(NetError::BadHandle),
+    }
+}
+
+/// the address a socket bound to `addr` will use as source, checking it's one of ours
+fn check_local(s: &NetStack, addr: Ipv4Address) -> Result<()> {
+    let ours = addr.is_unspecified()
+        || addr.is_loopback()
+        || s.interfaces.iter().any(|i| i.addr() == Some(addr));
+
+    if ours {
+        Ok(())
+    } else {
+        Err(NetError::AddressNotAvailable)
+    }
+}
+
+impl Socket {
+    pub fn new(kind: SocketKind) -> Self {
+        let state = match kind {
+            SocketKind::Stream => SocketState::TcpIdle { local: None },
+            SocketKind::Datagram => SocketState::Udp(UdpSocket::default()),
+        };
+
+        let handle = with_stack(|s| s.insert_socket(state));
+        Self { handle, kind }
+    }
+
+    pub fn kind(&self) -> SocketKind {
+        self.kind
+    }
+
+    /// bind to a local address. port 0 picks an ephemeral port.
+    pub fn bind(&self, local: SocketAddrV4) -> Result<()> {
+        with_stack(|s| {
+            check_local(s, local.addr)?;
+
+            let current = s.sockets.get(&self.handle).ok_or(NetError::BadHandle)?;
+            if current.state.local_addr().is_some() {
+                return Err(NetError::InvalidState);
+            }
+
+            let port = match local.port {
+                0 => s.ephemeral_port(self.kind, local.addr)?,
+                _ if s.port_in_use(self.kind, local) => return Err(NetError::AddressInUse),
+                port => port,
+            };
+            let local = SocketAddrV4::new(local.addr, port);
+
+            match &mut s
+                .sockets
+                .get_mut(&self.handle)
+                .ok_or(NetError::BadHandle)?
+                .state
+            {
+                SocketState::TcpIdle { local: bound } => *bound = Some(local),
+                SocketState::Udp(udp) => udp.local = Some(local),
+                _ => return Err(NetError::InvalidState),
+            }
+            Ok(())
+        })
+    }
+
+    /// start accepting connections. binds an ephemeral port if unbound.
+    pub fn listen(&self, backlog: usize) -> Result<()> {
+        if self.kind != SocketKind::Stream {
+            return Err(NetError::NotSupported);
+        }
+
+        with_stack(|s| {
+            let entry = s.sockets.get_mut(&self.handle).ok_or(NetError::BadHandle)?;
+            let SocketState::TcpIdle { local } = entry.state else {
+                return Err(NetError::AlreadyConnected);
+            };
+
+            let local = match local {
+                Some(local) => local,
+                None => SocketAddrV4::new(
+                    Ipv4Address::UNSPECIFIED,
+                    s.ephemeral_port(self.kind, Ipv4Address::UNSPECIFIED)?,
+                ),
+            };
+
+            let entry = s.sockets.get_mut(&self.handle).ok_or(NetError::BadHandle)?;
+            entry.state = SocketState::TcpListen {
+                local,
+                backlog: if backlog == 0 {
+                    DEFAULT_BACKLOG
+                } else {
+                    backlog
+                },
+                ready: VecDeque::new(),
+            };
+            Ok(())
+        })
+    }
+
+    /// wait for an incoming connection
+    pub fn accept(&self) -> Result<(Socket, SocketAddrV4)> {
+        block_on(self.handle, |s, handle| {
+            if !matches!(
+                s.sockets.get(&handle).map(|e| &e.state),
+                Some(SocketState::TcpListen { .. })
+            ) {
+                return Poll::Ready(Err(NetError::InvalidState));
+            }
+
+            match s.take_accepted(handle) {
+                Some(child) => {
+                    let remote = tcb_mut(s, child).map(|tcb| tcb.remote);
+                    Poll::Ready(remote.map(|remote| {
+                        let socket = Socket {
+                            handle: child,
+                            kind: SocketKind::Stream,
+                        };
+                        (socket, remote)
+                    }))
+                }
+                None => Poll::Pending,
+            }
+        })
+    }
+
+    /// connect a stream socket, or set the default peer of a datagram socket
+    pub fn connect(&self, remote: SocketAddrV4) -> Result<()> {
+        if remote.addr.is_unspecified() || remote.port == 0 {
+            return Err(NetError::InvalidArgument);
+        }
+
+        match self.kind {
+            SocketKind::Datagram => with_stack(|s| {
+                let route = s.route(remote.addr)?;
+                let port = match s.sockets.get(&self.handle).map(|e| e.state.local_addr()) {
+                    Some(Some(local)) => local.port,
+                    Some(None) => s.ephemeral_port(self.kind, route.src)?,
+                    None => return Err(NetError::BadHandle),
+                };
+
+                let Some(SocketState::Udp(udp)) =
+                    s.sockets.get_mut(&self.handle).map(|e| &mut e.state)
+                else {
+                    return Err(NetError::BadHandle);
+                };
+
+                udp.local
+                    .get_or_insert(SocketAddrV4::new(Ipv4Address::UNSPECIFIED, port));
+                udp.remote = Some(remote);
+                Ok(())
+            }),
+            SocketKind::Stream => {
+                with_stack(|s| {
+                    let route = s.route(remote.addr)?;
+                    let entry = s.sockets.get(&self.handle).ok_or(NetError::BadHandle)?;
+                    let SocketState::TcpIdle { local } = entry.state else {
+                        return Err(NetError::AlreadyConnected);
+                    };
+
+                    let local = match local {
+                        Some(l) if l.addr.is_unspecified() => SocketAddrV4::new(route.src, l.port),
+                        Some(l) => l,
+                        None => {
+                            SocketAddrV4::new(route.src, s.ephemeral_port(self.kind, route.src)?)
+                        }
+                    };
+
+                    let mss = s.interfaces[route.iface]
+                        .device
+                        .mtu()
+                        .saturating_sub(ipv4::HEADER_LEN + 20);
+                    let iss = s.new_iss();
+                    let tcb = Tcb::connect(local, remote, iss, mss, now_ms());
+
+                    s.sockets
+                        .get_mut(&self.handle)
+                        .ok_or(NetError::BadHandle)?
+                        .state = SocketState::Tcp(Box::new(tcb));
+                    tcp::flush(s, self.handle);
+                    Ok(())
+                })?;
+
+                // retransmit timer
+                stack::kick();
+
+                block_on(self.handle, |s, handle| {
+                    let tcb = match tcb_mut(s, handle) {
+                        Ok(tcb) => tcb,
+                        Err(e) => return Poll::Ready(Err(e)),
+                    };
+
+                    match tcb.state {
+                        TcpState::SynSent | TcpState::SynReceived => Poll::Pending,
+                        TcpState::Closed => {
+                            Poll::Ready(Err(tcb.error.unwrap_or(NetError::ConnectionRefused)))
+                        }
+                        _ => Poll::Ready(Ok(())),
+                    }
+                })
+            }
+        }
+    }
+
+    /// send on a connected socket. blocks until all of `data` is queued.
+    pub fn send(&self, data: &[u8]) -> Result<usize> {
+        if self.kind == SocketKind::Datagram {
+            return with_stack(|s| {
+                let Some(SocketState::Udp(udp)) = s.sockets.get(&self.handle).map(|e| &e.state)
+                else {
+                    return Err(NetError::BadHandle);
+                };
+
+                let remote = udp.remote.ok_or(NetError::NotConnected)?;
+                let local = udp.local.ok_or(NetError::NotConnected)?;
+                udp::send(s, local, remote, data)?;
+                Ok(data.len())
+            });
+        }
+
+        let mut sent = 0;
+        let result = block_on(self.handle, |s, handle| {
+            let tcb = match tcb_mut(s, handle) {
+                Ok(tcb) => tcb,
+                Err(e) => return Poll::Ready(Err(e)),
+            };
+
+            if let Some(e) = tcb.error {
+                return Poll::Ready(Err(e));
+            }
+            if !tcb.can_send() {
+                return Poll::Ready(Err(NetError::NotConnected));
+            }
+
+            sent += tcb.write(&data[sent..], now_ms());
+            tcp::flush(s, handle);
+
+            if sent == data.len() {
+                Poll::Ready(Ok(sent))
+            } else {
+                Poll::Pending
+            }
+        });
+
+        stack::kick();
+        result
+    }
+
+    /// receive on a connected socket. returns 0 at end of stream.
+    pub fn recv(&self, buf: &mut [u8]) -> Result<usize> {
+        if self.kind == SocketKind::Datagram {
+            return self.recv_from(buf).map(|(n, _)| n);
+        }
+
+        block_on(self.handle, |s, handle| {
+            let tcb = match tcb_mut(s, handle) {
+                Ok(tcb) => tcb,
+                Err(e) => return Poll::Ready(Err(e)),
+            };
+
+            if buf.is_empty() || !tcb.recv_buf.is_empty() {
+                let n = tcb.read(buf);
+                tcp::flush(s, handle);
+                return Poll::Ready(Ok(n));
+            }
+
+            if let Some(e) = tcb.error {
+                return Poll::Ready(Err(e));
+            }
+            if tcb.peer_fin || tcb.is_finished() {
+                return Poll::Ready(Ok(0));
+            }
+
+            Poll::Pending
+        })
+    }
+
+    /// send a datagram to `remote`, binding an ephemeral port if needed
+    pub fn send_to(&self, data: &[u8], remote: SocketAddrV4) -> Result<usize> {
+        if self.kind != SocketKind::Datagram {
+            return Err(NetError::NotSupported);
+        }
+
+        with_stack(|s| {
+            let route = s.route(remote.addr)?;
+
+            let local = match s.sockets.get(&self.handle).map(|e| e.state.local_addr()) {
+                Some(Some(local)) => local,
+                Some(None) => {
+                    let port = s.ephemeral_port(self.kind, route.src)?;
+                    let local = SocketAddrV4::new(Ipv4Address::UNSPECIFIED, port);
+                    if let Some(SocketState::Udp(udp)) =
+                        s.sockets.get_mut(&self.handle).map(|e| &mut e.state)
+                    {
+                        udp.local = Some(local);
+                    }
+                    local
+                }
+                None => return Err(NetError::BadHandle),
+            };
+
+            udp::send(s, local, remote, data)?;
+            Ok(data.len())
+        })
+    }
+
+    /// wait for a datagram. excess bytes past `buf` are discarded.
+    pub fn recv_from(&self, buf: &mut [u8]) -> Result<(usize, SocketAddrV4)> {
+        if self.kind != SocketKind::Datagram {
+            return Err(NetError::NotSupported);
+        }
+
+        block_on(self.handle, |s, handle| {
+            let Some(SocketState::Udp(udp)) = s.sockets.get_mut(&handle).map(|e| &mut e.state)
+            else {
+                return Poll::Ready(Err(NetError::BadHandle));
+            };
+
+            if udp.local.is_none() {
+                return Poll::Ready(Err(NetError::InvalidState));
+            }
+
+            match udp.rx.pop_front() {
+                Some((from, datagram)) => {
+                    let n = datagram.len().min(buf.len());
+                    buf[..n].copy_from_slice(&datagram[..n]);
+                    Poll::Ready(Ok((n, from)))
+                }
+                None => Poll::Pending,
+            }
+        })
+    }
+
+    /// stop sending. the peer sees end of stream once queued data drains.
+    pub fn shutdown(&self) -> Result<()> {
+        with_stack(|s| {
+            tcb_mut(s, self.handle)?.close(now_ms());
+            tcp::flush(s, self.handle);
+            Ok(())
+        })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddrV4> {
+        with_stack(|s| {
+            s.sockets
+                .get(&self.handle)
+                .ok_or(NetError::BadHandle)?
+                .state
+                .local_addr()
+                .ok_or(NetError::InvalidState)
+        })
+    }
+
+    pub fn peer_addr(&self) -> Result<SocketAddrV4> {
+        with_stack(|s| match s.sockets.get(&self.handle).map(|e| &e.state) {
+            Some(SocketState::Tcp(tcb)) => Ok(tcb.remote),
+            Some(SocketState::Udp(UdpSocket {
+                remote: Some(remote),
+                ..
+            })) => Ok(*remote),
+            Some(_) => Err(NetError::NotConnected),
+            None => Err(NetError::BadHandle),
+        })
+    }
+}
+
+impl Drop for Socket {
+    fn drop(&mut self) {
+        with_stack(|s| s.remove_socket(self.handle, now_ms()));
+        stack::kick();
+    }
+}
@@ -0,0 +1,587 @@
+//! the stack proper: interface list, routing, frame dispatch and the `netd` thread.
+
+use alloc::{collections::btree_map::BTreeMap, sync::Arc, vec::Vec};
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use super::{
+    Ipv4Address, NetError, Result, SocketAddrV4,
+    arp::{self, ArpCache},
+    checksum::{accumulate, fold, pseudo_header},
+    device::{self, MacAddress, NetDeviceId, RxFrame, TxChecksum, get_device, register_device},
+    dhcp::{self, DhcpClient},
+    ethernet::{ETHERTYPE_ARP, ETHERTYPE_IPV4, EthernetHeader},
+    icmp::{self, PingTable},
+    iface::{Interface, InterfaceConfig, InterfaceInfo},
+    ipv4::{self, Ipv4Header, PROTO_ICMP, PROTO_TCP, PROTO_UDP},
+    loopback::Loopback,
+    socket::{SocketEntry, SocketHandle, SocketKind, SocketState},
+    tcp, udp,
+};
+use crate::{
+    guard::InterruptGuard,
+    random,
+    scheduler::{GLOBAL_SCHEDULER, Scheduler},
+    stack::Stack,
+    sync::{FairSpinlock, WaitQueue},
+    thread::Thread,
+};
+
+/// frames taken from one device per poll before the others get a turn
+const RX_BUDGET: usize = 64;
+const NETD_STACK_SIZE: usize = 64 * 1024;
+const EPHEMERAL_PORTS: core::ops::RangeInclusive<u16> = 49152..=65535;
+
+/// where a packet leaves the host
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) struct Route {
+    pub iface: usize,
+    pub src: Ipv4Address,
+    pub next_hop: Ipv4Address,
+}
+
+pub(crate) struct NetStack {
+    pub interfaces: Vec<Interface>,
+    pub arp: ArpCache,
+    pub sockets: BTreeMap<SocketHandle, SocketEntry>,
+    pub pings: PingTable,
+    next_handle: u32,
+    next_port: u16,
+    ip_ident: u16,
+}
+
+static STACK: FairSpinlock<NetStack> = FairSpinlock::new(NetStack::new());
+static STARTED: AtomicBool = AtomicBool::new(false);
+
+static NETD_WAIT: WaitQueue<'static> = WaitQueue::new();
+/// earliest protocol timer, checked from the scheduler tick
+static NETD_DEADLINE: AtomicU64 = AtomicU64::new(u64::MAX);
+
+/// run `f` with the stack locked. never call from IRQ context.
+pub(crate) fn with_stack<R>(f: impl FnOnce(&mut NetStack) -> R) -> R {
+    let _irq = InterruptGuard::new();
+    f(&mut STACK.lock())
+}
+
+/// milliseconds since boot
+pub fn now_ms() -> u64 {
+    crate::time::monotonic().as_millis() as u64
+}
+
+/// bring up loopback and start `netd`. devices registered later are picked up as they appear.
+pub fn init() {
+    use log::*;
+
+    if STARTED.swap(true, Ordering::AcqRel) {
+        return;
+    }
+
+    let lo = Arc::new(Loopback::new());
+    let id = register_device(lo.clone());
+    lo.set_id(id);
+
+    with_stack(|stack| {
+        stack.interfaces.push(Interface {
+            id,
+            device: lo,
+            config: Some(InterfaceConfig {
+                addr: Ipv4Address::LOOPBACK,
+                netmask: Ipv4Address::new(255, 0, 0, 0),
+                gateway: None,
+                dns: None,
+            }),
+            dhcp: None,
+            loopback: true,
+        });
+    });
+
+    device::set_rx_listener(|_| kick());
+
+    let stack = Stack::new(NETD_STACK_SIZE, 16).expect("unable to allocate netd stack");
+    let thread = Arc::new(Thread::new_kernel(stack, netd as *const (), 1));
+    GLOBAL_SCHEDULER.spawn(thread);
+
+    info!("net: stack started");
+}
+
+/// wake `netd` to process whatever is pending
+pub fn kick() {
+    NETD_WAIT.wake_all(&GLOBAL_SCHEDULER);
+}
+
+/// scheduler tick hook. cheap enough for IRQ context.
+pub fn tick() {
+    if STARTED.load(Ordering::Acquire) && now_ms() >= NETD_DEADLINE.load(Ordering::Acquire) {
+        NETD_DEADLINE.store(u64::MAX, Ordering::Release);
+        kick();
+    }
+}
+
+fn netd() -> ! {
+    loop {
+        let seen = NETD_WAIT.generation();
+        let deadline = with_stack(|stack| stack.poll(now_ms()));
+
+        if deadline <= now_ms() {
+            // more work queued than one poll's budget, let everything else run first
+            Scheduler::yield_now();
+            continue;
+        }
+
+        NETD_DEADLINE.store(deadline, Ordering::Release);
+        NETD_WAIT.wait(&GLOBAL_SCHEDULER, seen);
+    }
+}
+
+/// snapshot of every interface
+pub fn interfaces() -> Vec<InterfaceInfo> {
+    with_stack(|stack| {
+        stack
+            .interfaces
+            .iter()
+            .map(|i| InterfaceInfo {
+                id: i.id,
+                name: i.device.name().into(),
+                mac: i.mac(),
+                mtu: i.device.mtu(),
+                link_up: i.device.link_up(),
+                config: i.config,
+            })
+            .collect()
+    })
+}
+
+impl NetStack {
+    const fn new() -> Self {
+        Self {
+            interfaces: Vec::new(),
+            arp: ArpCache::new(),
+            sockets: BTreeMap::new(),
+            pings: PingTable::new(),
+            next_handle: 1,
+            next_port: *EPHEMERAL_PORTS.start(),
+            ip_ident: 1,
+        }
+    }
+
+    /// process received frames and due timers. returns the next deadline.
+    fn poll(&mut self, now: u64) -> u64 {
+        self.sync_interfaces();
+
+        let mut backlogged = false;
+        for iface in 0..self.interfaces.len() {
+            let device = self.interfaces[iface].device.clone();
+
+            let mut budget = RX_BUDGET;
+            while budget > 0
+                && let Some(frame) = device.receive()
+            {
+                self.input(iface, frame, now);
+                budget -= 1;
+            }
+            backlogged |= budget == 0;
+        }
+
+        arp::on_timer(self, now);
+        dhcp::on_timer(self, now);
+        icmp::on_timer(self, now);
+        tcp::on_timer(self, now);
+
+        if backlogged {
+            return now;
+        }
+
+        [
+            self.arp.next_deadline(),
+            self.interfaces
+                .iter()
+                .filter_map(|i| i.dhcp.as_ref().and_then(DhcpClient::next_deadline))
+                .min(),
+            self.pings.next_deadline(),
+            tcp::next_deadline(self),
+        ]
+        .into_iter()
+        .flatten()
+        .min()
+        .unwrap_or(u64::MAX)
+    }
+
+    /// pick up devices registered since the last poll
+    fn sync_interfaces(&mut self) {
+        use log::*;
+
+        for index in 0..device::device_count() {
+            let id = NetDeviceId(index);
+            if self.interfaces.iter().any(|i| i.id == id) {
+                continue;
+            }
+
+            let Some(device) = get_device(id) else {
+                continue;
+            };
+
+            info!(
+                "net: {} ({}) attached, starting DHCP",
+                device.name(),
+                device.mac_address()
+            );
+
+            let xid = self.new_iss();
+            self.interfaces.push(Interface {
+                id,
+                dhcp: Some(DhcpClient::new(device.mac_address(), xid)),
+                device,
+                config: None,
+                loopback: false,
+            });
+        }
+    }
+
+    fn input(&mut self, iface: usize, frame: RxFrame, now: u64) {
+        let Some((eth, payload)) = EthernetHeader::parse(&frame.data) else {
+            return;
+        };
+
+        let mac = self.interfaces[iface].mac();
+        if eth.dst != mac && !eth.dst.is_broadcast() && !eth.dst.is_multicast() {
+            return;
+        }
+
+        match eth.ethertype {
+            ETHERTYPE_ARP => arp::input(self, iface, payload, now),
+            ETHERTYPE_IPV4 => self.ipv4_input(iface, payload, frame.checksum_valid, now),
+            _ => {}
+        }
+    }
+
+    fn ipv4_input(&mut self, iface: usize, packet: &[u8], csum_ok: bool, now: u64) {
+        let Some((ip, payload)) = Ipv4Header::parse(packet) else {
+            return;
+        };
+
+        if !self.interfaces[iface].accepts(ip.dst) {
+            return;
+        }
+
+        match ip.protocol {
+            PROTO_ICMP => icmp::input(self, iface, &ip, payload),
+            PROTO_UDP => udp::input(self, iface, &ip, payload, csum_ok, now),
+            PROTO_TCP => tcp::input(self, iface, &ip, payload, csum_ok, now),
+            _ => {}
+        }
+    }
+
+    pub fn transmit(&self, iface: usize, frame: &[u8], csum: Option<TxChecksum>) {
+        use log::*;
+
+        let device = &self.interfaces[iface].device;
+        if let Err(e) = device.transmit(frame, csum) {
+            trace!("net: {} dropped frame: {e}", device.name());
+        }
+    }
+
+    fn loopback_iface(&self) -> Option<usize> {
+        self.interfaces.iter().position(|i| i.loopback)
+    }
+
+    pub fn route(&self, dst: Ipv4Address) -> Result<Route> {
+        let is_ours = self.interfaces.iter().any(|i| i.addr() == Some(dst));
+
+        if dst.is_loopback() || is_ours {
+            let iface = self.loopback_iface().ok_or(NetError::NoRoute)?;
+            return Ok(Route {
+                iface,
+                src: dst,
+                next_hop: dst,
+            });
+        }
+
+        let candidates = || {
+            self.interfaces.iter().enumerate().filter_map(|(n, i)| {
+                let config = i.config?;
+                (!i.loopback && i.device.link_up()).then_some((n, config))
+            })
+        };
+
+        if let Some((iface, config)) = candidates().find(|(_, c)| {
+            dst.same_subnet(c.addr, c.netmask) || dst == c.broadcast() || dst.is_broadcast()
+        }) {
+            return Ok(Route {
+                iface,
+                src: config.addr,
+                next_hop: dst,
+            });
+        }
+
+        candidates()
+            .find_map(|(iface, c)| {
+                c.gateway.map(|gateway| Route {
+                    iface,
+                    src: c.addr,
+                    next_hop: gateway,
+                })
+            })
+            .ok_or(NetError::NoRoute)
+    }
+
+    fn next_ident(&mut self) -> u16 {
+        let ident = self.ip_ident;
+        self.ip_ident = self.ip_ident.wrapping_add(1);
+        ident
+    }
+
+    /// wrap `payload` in IPv4 + ethernet and send it along `route`.
+    /// `csum_offset` is where the L4 checksum lives in `payload`, if it has one.
+    pub fn send_ip(
+        &mut self,
+        route: &Route,
+        dst: Ipv4Address,
+        protocol: u8,
+        payload: Vec<u8>,
+        csum_offset: Option<usize>,
+    ) -> Result<()> {
+        let iface = &self.interfaces[route.iface];
+        if payload.len() + ipv4::HEADER_LEN > iface.device.mtu() {
+            return Err(NetError::FrameTooLarge);
+        }
+
+        let mut frame =
+            Vec::with_capacity(device::ETH_HEADER_LEN + ipv4::HEADER_LEN + payload.len());
+        EthernetHeader {
+            dst: MacAddress::ZERO,
+            src: iface.mac(),
+            ethertype: ETHERTYPE_IPV4,
+        }
+        .emit(&mut frame);
+
+        let header = Ipv4Header {
+            src: route.src,
+            dst,
+            protocol,
+            ttl: ipv4::DEFAULT_TTL,
+            ident: self.next_ident(),
+        };
+        header.emit(payload.len(), &mut frame);
+
+        let l4_start = frame.len();
+        frame.extend_from_slice(&payload);
+
+        let iface = &self.interfaces[route.iface];
+        let mut tx_csum = None;
+        if let Some(offset) = csum_offset {
+            let at = l4_start + offset;
+            let pseudo = pseudo_header(route.src, dst, protocol, payload.len() as u16);
+
+            if iface.device.checksum_caps().tx {
+                frame[at..at + 2].copy_from_slice(&fold(pseudo).to_be_bytes());
+                tx_csum = Some(TxChecksum {
+                    start: l4_start as u16,
+                    offset: offset as u16,
+                });
+            } else {
+                let mut csum = !fold(accumulate(pseudo, &frame[l4_start..]));
+                if protocol == PROTO_UDP && csum == 0 {
+                    csum = 0xFFFF;
+                }
+                frame[at..at + 2].copy_from_slice(&csum.to_be_bytes());
+            }
+        }
+
+        let broadcast = dst.is_broadcast() || iface.config.is_some_and(|c| c.broadcast() == dst);
+        let dst_mac = if iface.loopback {
+            MacAddress::ZERO
+        } else if broadcast {
+            MacAddress::BROADCAST
+        } else if let Some(mac) = self.arp.lookup(route.iface, route.next_hop, now_ms()) {
+            mac
+        } else {
+            arp::enqueue(self, route.iface, route.next_hop, frame, tx_csum, now_ms());
+            return Ok(());
+        };
+
+        frame[0..6].copy_from_slice(&dst_mac.0);
+        self.transmit(route.iface, &frame, tx_csum);
+        Ok(())
+    }
+
+    pub fn insert_socket(&mut self, state: SocketState) -> SocketHandle {
+        let handle = SocketHandle(self.next_handle);
+        self.next_handle = self.next_handle.wrapping_add(1).max(1);
+
+        self.sockets.insert(
+            handle,
+            SocketEntry {
+                wait: Arc::new(WaitQueue::new()),
+                state,
+            },
+        );
+        handle
+    }
+
+    pub fn wake(&self, handle: SocketHandle) {
+        if let Some(entry) = self.sockets.get(&handle) {
+            entry.wait.wake_all(&GLOBAL_SCHEDULER);
+        }
+    }
+
+    pub fn new_iss(&mut self) -> u32 {
+        // unpredictable rather than RFC 6528's clock plus keyed hash, which is just as good here
+        // since nothing relies on ISNs increasing across connections
+        random::get_random_u32()
+    }
+
+    pub fn port_in_use(&self, kind: SocketKind, local: SocketAddrV4) -> bool {
+        self.sockets.values().any(|e| {
+            let Some(bound) = e.state.local_addr() else {
+                return false;
+            };
+
+            e.state.kind() == kind
+                && bound.port == local.port
+                && (bound.addr.is_unspecified()
+                    || local.addr.is_unspecified()
+                    || bound.addr == local.addr)
+                // accepted connections share the listener's port
+                && !matches!(&e.state, SocketState::Tcp(tcb) if tcb.parent.is_some())
+        })
+    }
+
+    pub fn ephemeral_port(&mut self, kind: SocketKind, addr: Ipv4Address) -> Result<u16> {
+        let count = EPHEMERAL_PORTS.len();
+
+        for _ in 0..count {
+            let port = self.next_port;
+            self.next_port = if port == *EPHEMERAL_PORTS.end() {
+                *EPHEMERAL_PORTS.start()
+            } else {
+                port + 1
+            };
+
+            let in_use = self.port_in_use(kind, SocketAddrV4::new(addr, port))
+                || self
+                    .sockets
+                    .values()
+                    .any(|e| matches!(&e.state, SocketState::Tcp(tcb) if tcb.local.port == port));
+            if !in_use {
+                return Ok(port);
+            }
+        }
+
+        Err(NetError::PortsExhausted)
+    }
+
+    pub fn find_connection(
+        &self,
+        local: SocketAddrV4,
+        remote: SocketAddrV4,
+    ) -> Option<SocketHandle> {
+        self.sockets.iter().find_map(|(h, e)| match &e.state {
+            SocketState::Tcp(tcb)
+                if tcb.local == local && tcb.remote == remote && !tcb.is_finished() =>
+            {
+                Some(*h)
+            }
+            _ => None,
+        })
+    }
+
+    pub fn find_listener(&self, local: SocketAddrV4) -> Option<SocketHandle> {
+        self.sockets.iter().find_map(|(h, e)| match &e.state {
+            SocketState::TcpListen { local: bound, .. }
+                if bound.port == local.port
+                    && (bound.addr.is_unspecified() || bound.addr == local.addr) =>
+            {
+                Some(*h)
+            }
+            _ => None,
+        })
+    }
+
+    /// pending (handshaking or unaccepted) connections are under the backlog
+    pub fn listener_has_room(&self, listener: SocketHandle) -> bool {
+        let Some(SocketState::TcpListen { backlog, .. }) =
+            self.sockets.get(&listener).map(|e| &e.state)
+        else {
+            return false;
+        };
+
+        let pending = self
+            .sockets
+            .values()
+            .filter(|e| matches!(&e.state, SocketState::Tcp(tcb) if tcb.parent == Some(listener)))
+            .count();
+
+        pending < *backlog
+    }
+
+    pub fn enqueue_accept(&mut self, listener: SocketHandle, child: SocketHandle) {
+        if let Some(SocketState::TcpListen { ready, .. }) =
+            self.sockets.get_mut(&listener).map(|e| &mut e.state)
+        {
+            ready.push_back(child);
+            self.wake(listener);
+        }
+    }
+
+    /// drop a socket, resetting any half-open children of a listener
+    pub fn remove_socket(&mut self, handle: SocketHandle, now: u64) {
+        let Some(entry) = self.sockets.get_mut(&handle) else {
+            return;
+        };
+
+        match &mut entry.state {
+            SocketState::Tcp(tcb) => {
+                tcb.close(now);
+                tcb.orphaned = true;
+                let finished = tcb.is_finished();
+
+                tcp::flush(self, handle);
+                if finished {
+                    self.sockets.remove(&handle);
+                }
+            }
+            SocketState::TcpListen { .. } => {
+                self.sockets.remove(&handle);
+
+                let children: Vec<SocketHandle> = self
+                    .sockets
+                    .iter()
+                    .filter(|(_, e)| {
+                        matches!(&e.state, SocketState::Tcp(tcb) if tcb.parent == Some(handle))
+                    })
+                    .map(|(h, _)| *h)
+                    .collect();
+
+                for child in children {
+                    if let Some(SocketState::Tcp(tcb)) =
+                        self.sockets.get_mut(&child).map(|e| &mut e.state)
+                    {
+                        tcb.reset();
+                    }
+                    tcp::flush(self, child);
+                    self.sockets.remove(&child);
+                }
+            }
+            _ => {
+                self.sockets.remove(&handle);
+            }
+        }
+    }
+
+    /// take ownership of a connection the listener queued
+    pub fn take_accepted(&mut self, listener: SocketHandle) -> Option<SocketHandle> {
+        loop {
+            let Some(SocketState::TcpListen { ready, .. }) =
+                self.sockets.get_mut(&listener).map(|e| &mut e.state)
+            else {
+                return None;
+            };
+
+            let child = ready.pop_front()?;
+            if let Some(SocketState::Tcp(tcb)) = self.sockets.get_mut(&child).map(|e| &mut e.state)
+            {
+                tcb.parent = None;
+                return Some(child);
+            }
+        }
+    }
+}
@@ -0,0 +1,908 @@
+//! TCP (RFC 793/9293) with RFC 6298 retransmission timing.
+//!
+//! no congestion control, SACK, window scaling or out-of-order reassembly: segments past
+//! `rcv_nxt` are dropped and re-requested with a duplicate ACK.
+
+use alloc::{boxed::Box, collections::vec_deque::VecDeque, vec::Vec};
+
+use super::{
+    NetError, SocketAddrV4,
+    checksum::verify_l4,
+    ipv4::{self, Ipv4Header, PROTO_TCP},
+    socket::{SocketHandle, SocketState},
+    stack::NetStack,
+};
+
+pub const FIN: u8 = 1 << 0;
+pub const SYN: u8 = 1 << 1;
+pub const RST: u8 = 1 << 2;
+pub const PSH: u8 = 1 << 3;
+pub const ACK: u8 = 1 << 4;
+
+const HEADER_LEN: usize = 20;
+const OPT_END: u8 = 0;
+const OPT_NOP: u8 = 1;
+const OPT_MSS: u8 = 2;
+
+/// RFC 9293 default when the peer sends no MSS option
+const DEFAULT_MSS: usize = 536;
+
+pub const SEND_BUFFER: usize = 32 * 1024;
+pub const RECV_BUFFER: usize = 32 * 1024;
+
+const INITIAL_RTO_MS: u64 = 1_000;
+const MIN_RTO_MS: u64 = 200;
+const MAX_RTO_MS: u64 = 60_000;
+const MAX_RETRIES: u32 = 8;
+/// 2 * MSL
+const TIME_WAIT_MS: u64 = 30_000;
+
+#[inline]
+fn seq_lt(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) < 0
+}
+
+#[inline]
+fn seq_le(a: u32, b: u32) -> bool {
+    a == b || seq_lt(a, b)
+}
+
+#[inline]
+fn seq_gt(a: u32, b: u32) -> bool {
+    seq_lt(b, a)
+}
+
+#[inline]
+fn seq_ge(a: u32, b: u32) -> bool {
+    seq_le(b, a)
+}
+
+/// `start <= seq < end`
+#[inline]
+fn seq_in(seq: u32, start: u32, end: u32) -> bool {
+    seq_le(start, seq) && seq_lt(seq, end)
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct TcpHeader {
+    pub src_port: u16,
+    pub dst_port: u16,
+    pub seq: u32,
+    pub ack: u32,
+    pub flags: u8,
+    pub window: u16,
+    pub mss: Option<u16>,
+}
+
+impl TcpHeader {
+    pub fn parse(segment: &[u8]) -> Option<(Self, &[u8])> {
+        if segment.len() < HEADER_LEN {
+            return None;
+        }
+
+        let data_offset = (segment[12] >> 4) as usize * 4;
+        if data_offset < HEADER_LEN || data_offset > segment.len() {
+            return None;
+        }
+
+        let mut mss = None;
+        let mut options = &segment[HEADER_LEN..data_offset];
+        while let Some(&kind) = options.first() {
+            match kind {
+                OPT_END => break,
+                OPT_NOP => options = &options[1..],
+                _ => {
+                    let len = *options.get(1)? as usize;
+                    if len < 2 || len > options.len() {
+                        break;
+                    }
+                    if kind == OPT_MSS && len == 4 {
+                        mss = Some(u16::from_be_bytes([options[2], options[3]]));
+                    }
+                    options = &options[len..];
+                }
+            }
+        }
+
+        let header = Self {
+            src_port: u16::from_be_bytes([segment[0], segment[1]]),
+            dst_port: u16::from_be_bytes([segment[2], segment[3]]),
+            seq: u32::from_be_bytes(segment[4..8].try_into().ok()?),
+            ack: u32::from_be_bytes(segment[8..12].try_into().ok()?),
+            flags: segment[13],
+            window: u16::from_be_bytes([segment[14], segment[15]]),
+            mss,
+        };
+
+        Some((header, &segment[data_offset..]))
+    }
+
+    /// append the header (checksum zeroed) followed by `payload`
+    pub fn emit(&self, payload: &[u8], buf: &mut Vec<u8>) {
+        let header_len = HEADER_LEN + if self.mss.is_some() { 4 } else { 0 };
+
+        buf.extend_from_slice(&self.src_port.to_be_bytes());
+        buf.extend_from_slice(&self.dst_port.to_be_bytes());
+        buf.extend_from_slice(&self.seq.to_be_bytes());
+        buf.extend_from_slice(&self.ack.to_be_bytes());
+        buf.push(((header_len / 4) as u8) << 4);
+        buf.push(self.flags);
+        buf.extend_from_slice(&self.window.to_be_bytes());
+        buf.extend_from_slice(&[0, 0, 0, 0]);
+
+        if let Some(mss) = self.mss {
+            buf.extend_from_slice(&[OPT_MSS, 4]);
+            buf.extend_from_slice(&mss.to_be_bytes());
+        }
+
+        buf.extend_from_slice(payload);
+    }
+
+    /// sequence space the segment occupies
+    fn seq_len(&self, payload_len: usize) -> u32 {
+        payload_len as u32 + (self.flags & SYN != 0) as u32 + (self.flags & FIN != 0) as u32
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TcpState {
+    Closed,
+    SynSent,
+    SynReceived,
+    Established,
+    FinWait1,
+    FinWait2,
+    CloseWait,
+    Closing,
+    LastAck,
+    TimeWait,
+}
+
+impl TcpState {
+    /// a SYN has been exchanged in both directions
+    pub const fn is_synchronized(&self) -> bool {
+        !matches!(self, Self::Closed | Self::SynSent | Self::SynReceived)
+    }
+}
+
+/// a segment waiting to be handed to IP
+#[derive(Debug, Clone)]
+pub(crate) struct Segment {
+    pub header: TcpHeader,
+    pub payload: Vec<u8>,
+}
+
+/// transmission control block
+#[derive(Debug)]
+pub(crate) struct Tcb {
+    pub state: TcpState,
+    pub local: SocketAddrV4,
+    pub remote: SocketAddrV4,
+
+    iss: u32,
+    snd_una: u32,
+    snd_nxt: u32,
+    /// highest sequence number sent, `snd_nxt` rewinds on retransmit
+    snd_max: u32,
+    snd_wnd: u32,
+    snd_wl1: u32,
+    snd_wl2: u32,
+    rcv_nxt: u32,
+    mss: usize,
+
+    /// unacknowledged and unsent data, starting at `snd_una`
+    send_buf: VecDeque<u8>,
+    pub recv_buf: VecDeque<u8>,
+
+    /// the user is done sending, FIN follows the last byte
+    fin_queued: bool,
+    fin_seq: Option<u32>,
+    pub peer_fin: bool,
+    pub error: Option<NetError>,
+
+    rto: u64,
+    srtt: Option<u64>,
+    rttvar: u64,
+    /// (sequence that acks the timed segment, time it was sent)
+    rtt_probe: Option<(u32, u64)>,
+    retransmit_at: Option<u64>,
+    retries: u32,
+    time_wait_until: Option<u64>,
+
+    /// the listener that created this connection, until it's accepted
+    pub parent: Option<SocketHandle>,
+    /// the owning socket was closed; reap once the connection finishes
+    pub orphaned: bool,
+
+    pub outbox: Vec<Segment>,
+}
+
+impl Tcb {
+    fn new(local: SocketAddrV4, remote: SocketAddrV4, iss: u32, mss: usize) -> Self {
+        Self {
+            state: TcpState::Closed,
+            local,
+            remote,
+            iss,
+            snd_una: iss,
+            snd_nxt: iss,
+            snd_max: iss,
+            snd_wnd: 0,
+            snd_wl1: 0,
+            snd_wl2: 0,
+            rcv_nxt: 0,
+            mss,
+            send_buf: VecDeque::new(),
+            recv_buf: VecDeque::new(),
+            fin_queued: false,
+            fin_seq: None,
+            peer_fin: false,
+            error: None,
+            rto: INITIAL_RTO_MS,
+            srtt: None,
+            rttvar: 0,
+            rtt_probe: None,
+            retransmit_at: None,
+            retries: 0,
+            time_wait_until: None,
+            parent: None,
+            orphaned: false,
+            outbox: Vec::new(),
+        }
+    }
+
+    /// active open: queue a SYN
+    pub fn connect(
+        local: SocketAddrV4,
+        remote: SocketAddrV4,
+        iss: u32,
+        mss: usize,
+        now: u64,
+    ) -> Self {
+        let mut tcb = Self::new(local, remote, iss, mss);
+        tcb.state = TcpState::SynSent;
+        tcb.send_syn(now);
+        tcb
+    }
+
+    /// passive open from a listener that received `syn`
+    pub fn accept_syn(
+        local: SocketAddrV4,
+        remote: SocketAddrV4,
+        syn: &TcpHeader,
+        iss: u32,
+        mss: usize,
+        parent: SocketHandle,
+        now: u64,
+    ) -> Self {
+        let mss = syn.mss.map_or(DEFAULT_MSS, |m| m as usize).min(mss);
+
+        let mut tcb = Self::new(local, remote, iss, mss);
+        tcb.state = TcpState::SynReceived;
+        tcb.rcv_nxt = syn.seq.wrapping_add(1);
+        tcb.snd_wnd = syn.window as u32;
+        tcb.snd_wl1 = syn.seq;
+        tcb.parent = Some(parent);
+        tcb.send_syn(now);
+        tcb
+    }
+
+    pub fn rcv_wnd(&self) -> u32 {
+        (RECV_BUFFER - self.recv_buf.len()).min(u16::MAX as usize) as u32
+    }
+
+    pub fn send_space(&self) -> usize {
+        SEND_BUFFER.saturating_sub(self.send_buf.len())
+    }
+
+    /// the user may still queue data
+    pub fn can_send(&self) -> bool {
+        matches!(self.state, TcpState::Established | TcpState::CloseWait) && !self.fin_queued
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.state == TcpState::Closed
+    }
+
+    pub fn next_deadline(&self) -> Option<u64> {
+        match (self.retransmit_at, self.time_wait_until) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    fn push(&mut self, flags: u8, seq: u32, payload: Vec<u8>) {
+        let ack = if flags & ACK != 0 { self.rcv_nxt } else { 0 };
+        let mss = (flags & SYN != 0).then_some(self.mss as u16);
+
+        self.outbox.push(Segment {
+            header: TcpHeader {
+                src_port: self.local.port,
+                dst_port: self.remote.port,
+                seq,
+                ack,
+                flags,
+                window: self.rcv_wnd() as u16,
+                mss,
+            },
+            payload,
+        });
+    }
+
+    fn push_ack(&mut self) {
+        self.push(ACK, self.snd_nxt, Vec::new());
+    }
+
+    fn send_syn(&mut self, now: u64) {
+        let flags = match self.state {
+            TcpState::SynReceived => SYN | ACK,
+            _ => SYN,
+        };
+
+        self.push(flags, self.iss, Vec::new());
+        self.snd_nxt = self.iss.wrapping_add(1);
+        self.snd_max = self.snd_nxt;
+        self.retransmit_at = Some(now + self.rto);
+    }
+
+    fn fin_acked(&self) -> bool {
+        self.fin_seq.is_some_and(|fin| seq_gt(self.snd_una, fin))
+    }
+
+    fn enter_time_wait(&mut self, now: u64) {
+        self.state = TcpState::TimeWait;
+        self.retransmit_at = None;
+        self.time_wait_until = Some(now + TIME_WAIT_MS);
+    }
+
+    fn abort(&mut self, error: Option<NetError>) {
+        self.state = TcpState::Closed;
+        self.error = self.error.or(error);
+        self.send_buf.clear();
+        self.retransmit_at = None;
+        self.time_wait_until = None;
+    }
+
+    /// send a RST and drop the connection, e.g. when the owner goes away mid-handshake
+    pub fn reset(&mut self) {
+        if self.state != TcpState::Closed {
+            self.push(RST | ACK, self.snd_nxt, Vec::new());
+        }
+        self.abort(None);
+    }
+
+    fn update_rtt(&mut self, sample: u64) {
+        match self.srtt {
+            None => {
+                self.srtt = Some(sample);
+                self.rttvar = sample / 2;
+            }
+            Some(srtt) => {
+                self.rttvar = (3 * self.rttvar + srtt.abs_diff(sample)) / 4;
+                self.srtt = Some((7 * srtt + sample) / 8);
+            }
+        }
+
+        let srtt = self.srtt.unwrap_or(sample);
+        self.rto = (srtt + (4 * self.rttvar).max(1)).clamp(MIN_RTO_MS, MAX_RTO_MS);
+    }
+
+    /// queue user data. returns how much fit.
+    pub fn write(&mut self, data: &[u8], now: u64) -> usize {
+        let n = data.len().min(self.send_space());
+        self.send_buf.extend(&data[..n]);
+        self.output(now, false);
+        n
+    }
+
+    /// copy out received data, opening the window back up if it was nearly shut
+    pub fn read(&mut self, buf: &mut [u8]) -> usize {
+        let was_small = (self.rcv_wnd() as usize) < self.mss;
+
+        let n = buf.len().min(self.recv_buf.len());
+        for (dst, src) in buf.iter_mut().zip(self.recv_buf.drain(..n)) {
+            *dst = src;
+        }
+
+        if was_small && self.rcv_wnd() as usize >= self.mss && self.state.is_synchronized() {
+            self.push_ack();
+        }
+
+        n
+    }
+
+    /// the user closed its side, send FIN once the buffer drains
+    pub fn close(&mut self, now: u64) {
+        match self.state {
+            TcpState::SynSent => self.abort(None),
+            TcpState::SynReceived | TcpState::Established | TcpState::CloseWait => {
+                self.fin_queued = true;
+                self.output(now, false);
+            }
+            _ => {}
+        }
+    }
+
+    /// send whatever the window allows, then a FIN if one is due
+    fn output(&mut self, now: u64, probe: bool) {
+        if !self.state.is_synchronized() {
+            return;
+        }
+
+        let mut in_flight = self.snd_nxt.wrapping_sub(self.snd_una);
+        // bytes of `send_buf` already covered by `snd_nxt`
+        let mut offset = (in_flight as usize).min(self.send_buf.len());
+        let window = if probe {
+            self.snd_wnd.max(1)
+        } else {
+            self.snd_wnd
+        };
+
+        while offset < self.send_buf.len() {
+            let available = window.saturating_sub(in_flight) as usize;
+            if available == 0 {
+                break;
+            }
+
+            let len = self.mss.min(self.send_buf.len() - offset).min(available);
+            let payload: Vec<u8> = self.send_buf.range(offset..offset + len).copied().collect();
+            let last = offset + len == self.send_buf.len();
+
+            let seq = self.snd_nxt;
+            self.push(ACK | if last { PSH } else { 0 }, seq, payload);
+
+            self.snd_nxt = self.snd_nxt.wrapping_add(len as u32);
+            offset += len;
+            in_flight += len as u32;
+
+            if self.rtt_probe.is_none() && seq_ge(seq, self.snd_max) {
+                self.rtt_probe = Some((self.snd_nxt, now));
+            }
+        }
+
+        let fin_due = self.fin_queued
+            && offset == self.send_buf.len()
+            && self.fin_seq.is_none_or(|fin| fin == self.snd_nxt);
+
+        if fin_due {
+            self.push(FIN | ACK, self.snd_nxt, Vec::new());
+
+            if self.fin_seq.is_none() {
+                self.fin_seq = Some(self.snd_nxt);
+                self.state = match self.state {
+                    TcpState::CloseWait => TcpState::LastAck,
+                    _ => TcpState::FinWait1,
+                };
+            }
+            self.snd_nxt = self.snd_nxt.wrapping_add(1);
+        }
+
+        if seq_gt(self.snd_nxt, self.snd_max) {
+            self.snd_max = self.snd_nxt;
+        }
+
+        let stalled = self.snd_nxt == self.snd_una && offset < self.send_buf.len();
+        if (self.snd_nxt != self.snd_una || stalled) && self.retransmit_at.is_none() {
+            self.retransmit_at = Some(now + self.rto);
+        }
+    }
+
+    pub fn on_timer(&mut self, now: u64) {
+        if self.time_wait_until.is_some_and(|t| t <= now) {
+            self.time_wait_until = None;
+            self.state = TcpState::Closed;
+            return;
+        }
+
+        let Some(deadline) = self.retransmit_at else {
+            return;
+        };
+        if deadline > now {
+            return;
+        }
+
+        // zero window probes don't count against the connection
+        let probing = self.snd_wnd == 0 && self.state.is_synchronized();
+        if !probing {
+            self.retries += 1;
+        }
+
+        if self.retries > MAX_RETRIES {
+            self.abort(Some(NetError::TimedOut));
+            return;
+        }
+
+        self.rto = (self.rto * 2).min(MAX_RTO_MS);
+        self.rtt_probe = None;
+        self.retransmit_at = None;
+
+        match self.state {
+            TcpState::SynSent | TcpState::SynReceived => self.send_syn(now),
+            _ => {
+                // go back N
+                self.snd_nxt = self.snd_una;
+                self.output(now, probing);
+            }
+        }
+    }
+
+    fn on_syn_sent(&mut self, seg: &TcpHeader, now: u64) {
+        let has_ack = seg.flags & ACK != 0;
+
+        if has_ack && !(seq_gt(seg.ack, self.iss) && seq_le(seg.ack, self.snd_max)) {
+            if seg.flags & RST == 0 {
+                self.push(RST, seg.ack, Vec::new());
+            }
+            return;
+        }
+
+        if seg.flags & RST != 0 {
+            if has_ack {
+                self.abort(Some(NetError::ConnectionRefused));
+            }
+            return;
+        }
+
+        if seg.flags & SYN == 0 {
+            return;
+        }
+
+        self.rcv_nxt = seg.seq.wrapping_add(1);
+        self.mss = seg.mss.map_or(DEFAULT_MSS, |m| m as usize).min(self.mss);
+
+        if has_ack {
+            self.snd_una = seg.ack;
+            self.snd_wnd = seg.window as u32;
+            self.snd_wl1 = seg.seq;
+            self.snd_wl2 = seg.ack;
+            self.state = TcpState::Established;
+            self.retransmit_at = None;
+            self.retries = 0;
+            self.push_ack();
+            self.output(now, false);
+        } else {
+            // simultaneous open
+            self.state = TcpState::SynReceived;
+            self.send_syn(now);
+        }
+    }
+
+    fn on_ack(&mut self, seg: &TcpHeader, now: u64) {
+        if seq_gt(seg.ack, self.snd_max) {
+            self.push_ack();
+            return;
+        }
+
+        if seq_gt(seg.ack, self.snd_una) {
+            let mut acked = seg.ack.wrapping_sub(self.snd_una);
+            if self.fin_seq.is_some_and(|fin| seq_gt(seg.ack, fin)) {
+                acked -= 1;
+            }
+
+            let drained = (acked as usize).min(self.send_buf.len());
+            self.send_buf.drain(..drained);
+            self.snd_una = seg.ack;
+            if seq_lt(self.snd_nxt, self.snd_una) {
+                self.snd_nxt = self.snd_una;
+            }
+
+            if let Some((until, sent)) = self.rtt_probe
+                && seq_ge(seg.ack, until)
+            {
+                self.update_rtt(now.saturating_sub(sent));
+                self.rtt_probe = None;
+            }
+
+            self.retries = 0;
+            self.retransmit_at = (self.snd_una != self.snd_max).then_some(now + self.rto);
+        }
+
+        if seq_lt(self.snd_wl1, seg.seq)
+            || (self.snd_wl1 == seg.seq && seq_le(self.snd_wl2, seg.ack))
+        {
+            self.snd_wnd = seg.window as u32;
+            self.snd_wl1 = seg.seq;
+            self.snd_wl2 = seg.ack;
+        }
+
+        if self.fin_acked() {
+            match self.state {
+                TcpState::FinWait1 => self.state = TcpState::FinWait2,
+                TcpState::Closing => self.enter_time_wait(now),
+                TcpState::LastAck => self.state = TcpState::Closed,
+                _ => {}
+            }
+        }
+    }
+
+    pub fn on_segment(&mut self, seg: &TcpHeader, payload: &[u8], now: u64) {
+        match self.state {
+            TcpState::Closed => return,
+            TcpState::SynSent => return self.on_syn_sent(seg, now),
+            _ => {}
+        }
+
+        let seg_len = seg.seq_len(payload.len());
+        let wnd = self.rcv_wnd();
+        let wnd_end = self.rcv_nxt.wrapping_add(wnd);
+        let acceptable = match (seg_len, wnd) {
+            (0, 0) => seg.seq == self.rcv_nxt,
+            (0, _) => seq_in(seg.seq, self.rcv_nxt, wnd_end),
+            (_, 0) => false,
+            (len, _) => {
+                seq_in(seg.seq, self.rcv_nxt, wnd_end)
+                    || seq_in(seg.seq.wrapping_add(len - 1), self.rcv_nxt, wnd_end)
+            }
+        };
+
+        if !acceptable {
+            if seg.flags & RST == 0 {
+                self.push_ack();
+            }
+            return;
+        }
+
+        if seg.flags & RST != 0 {
+            let error = match self.state {
+                TcpState::SynReceived if self.parent.is_some() => None,
+                TcpState::SynReceived => Some(NetError::ConnectionRefused),
+                TcpState::Closing | TcpState::LastAck | TcpState::TimeWait => None,
+                _ => Some(NetError::ConnectionReset),
+            };
+            self.abort(error);
+            return;
+        }
+
+        if seg.flags & SYN != 0 {
+            self.reset();
+            self.error = Some(NetError::ConnectionReset);
+            return;
+        }
+
+        if seg.flags & ACK == 0 {
+            return;
+        }
+
+        if self.state == TcpState::SynReceived {
+            if !(seq_gt(seg.ack, self.snd_una) && seq_le(seg.ack, self.snd_max)) {
+                self.push(RST, seg.ack, Vec::new());
+                return;
+            }
+
+            self.state = TcpState::Established;
+            self.snd_una = self.iss.wrapping_add(1);
+            self.snd_wl1 = seg.seq;
+            self.snd_wl2 = seg.ack;
+            self.retransmit_at = None;
+            self.retries = 0;
+        }
+
+        self.on_ack(seg, now);
+        if self.state == TcpState::Closed {
+            return;
+        }
+
+        let mut need_ack = false;
+        let mut seq = seg.seq;
+        let mut data = payload;
+
+        // drop the part we already have
+        if seq_lt(seq, self.rcv_nxt) {
+            let skip = self.rcv_nxt.wrapping_sub(seq) as usize;
+            data = data.get(skip..).unwrap_or(&[]);
+            seq = self.rcv_nxt;
+        }
+
+        let mut in_order = true;
+        if !data.is_empty() {
+            match self.state {
+                TcpState::Established | TcpState::FinWait1 | TcpState::FinWait2
+                    if seq == self.rcv_nxt =>
+                {
+                    let n = data.len().min(self.rcv_wnd() as usize);
+                    self.recv_buf.extend(&data[..n]);
+                    self.rcv_nxt = self.rcv_nxt.wrapping_add(n as u32);
+                    in_order = n == data.len();
+                }
+                _ => in_order = false,
+            }
+            need_ack = true;
+        }
+
+        let fin_seq = seg.seq.wrapping_add(payload.len() as u32);
+        if seg.flags & FIN != 0 && in_order && fin_seq == self.rcv_nxt && !self.peer_fin {
+            self.peer_fin = true;
+            self.rcv_nxt = self.rcv_nxt.wrapping_add(1);
+            need_ack = true;
+
+            match self.state {
+                TcpState::Established => self.state = TcpState::CloseWait,
+                TcpState::FinWait1 if self.fin_acked() => self.enter_time_wait(now),
+                TcpState::FinWait1 => self.state = TcpState::Closing,
+                TcpState::FinWait2 => self.enter_time_wait(now),
+                _ => {}
+            }
+        }
+
+        if need_ack {
+            self.push_ack();
+        }
+
+        self.output(now, false);
+    }
+}
+
+/// hand every queued segment of `handle` to IP
+pub(crate) fn flush(stack: &mut NetStack, handle: SocketHandle) {
+    let Some(SocketState::Tcp(tcb)) = stack.sockets.get_mut(&handle).map(|e| &mut e.state) else {
+        return;
+    };
+
+    let local = tcb.local;
+    let remote = tcb.remote;
+    let outbox = core::mem::take(&mut tcb.outbox);
+
+    for segment in outbox {
+        send_segment(stack, local, remote, &segment.header, &segment.payload);
+    }
+}
+
+fn send_segment(
+    stack: &mut NetStack,
+    local: SocketAddrV4,
+    remote: SocketAddrV4,
+    header: &TcpHeader,
+    payload: &[u8],
+) {
+    let mut segment = Vec::with_capacity(HEADER_LEN + 4 + payload.len());
+    header.emit(payload, &mut segment);
+
+    let Ok(mut route) = stack.route(remote.addr) else {
+        return;
+    };
+    route.src = local.addr;
+
+    _ = stack.send_ip(&route, remote.addr, PROTO_TCP, segment, Some(16));
+}
+
+/// RST for a segment that matches no connection
+fn reply_reset(stack: &mut NetStack, ip: &Ipv4Header, seg: &TcpHeader, payload_len: usize) {
+    if seg.flags & RST != 0 {
+        return;
+    }
+
+    let (seq, ack, flags) = if seg.flags & ACK != 0 {
+        (seg.ack, 0, RST)
+    } else {
+        (0, seg.seq.wrapping_add(seg.seq_len(payload_len)), RST | ACK)
+    };
+
+    let header = TcpHeader {
+        src_port: seg.dst_port,
+        dst_port: seg.src_port,
+        seq,
+        ack,
+        flags,
+        window: 0,
+        mss: None,
+    };
+
+    send_segment(
+        stack,
+        SocketAddrV4::new(ip.dst, seg.dst_port),
+        SocketAddrV4::new(ip.src, seg.src_port),
+        &header,
+        &[],
+    );
+}
+
+pub(crate) fn input(
+    stack: &mut NetStack,
+    iface: usize,
+    ip: &Ipv4Header,
+    data: &[u8],
+    csum_ok: bool,
+    now: u64,
+) {
+    if !csum_ok && !verify_l4(ip.src, ip.dst, PROTO_TCP, data) {
+        return;
+    }
+
+    let Some((seg, payload)) = TcpHeader::parse(data) else {
+        return;
+    };
+
+    if ip.dst.is_broadcast() || ip.dst.is_multicast() {
+        return;
+    }
+
+    let local = SocketAddrV4::new(ip.dst, seg.dst_port);
+    let remote = SocketAddrV4::new(ip.src, seg.src_port);
+
+    if let Some(handle) = stack.find_connection(local, remote) {
+        let (established, parent) = {
+            let Some(SocketState::Tcp(tcb)) = stack.sockets.get_mut(&handle).map(|e| &mut e.state)
+            else {
+                return;
+            };
+
+            let before = tcb.state;
+            tcb.on_segment(&seg, payload, now);
+
+            let established = before == TcpState::SynReceived && tcb.state.is_synchronized();
+            (established, tcb.parent)
+        };
+
+        flush(stack, handle);
+        stack.wake(handle);
+
+        if established && let Some(parent) = parent {
+            stack.enqueue_accept(parent, handle);
+        }
+        return;
+    }
+
+    let Some(listener) = stack.find_listener(local) else {
+        reply_reset(stack, ip, &seg, payload.len());
+        return;
+    };
+
+    if seg.flags & RST != 0 {
+        return;
+    }
+    if seg.flags & ACK != 0 || seg.flags & SYN == 0 {
+        reply_reset(stack, ip, &seg, payload.len());
+        return;
+    }
+
+    if !stack.listener_has_room(listener) {
+        return;
+    }
+
+    let mss = stack.interfaces[iface]
+        .device
+        .mtu()
+        .saturating_sub(ipv4::HEADER_LEN + HEADER_LEN);
+    let iss = stack.new_iss();
+    let tcb = Tcb::accept_syn(local, remote, &seg, iss, mss, listener, now);
+
+    let handle = stack.insert_socket(SocketState::Tcp(Box::new(tcb)));
+    flush(stack, handle);
+}
+
+pub(crate) fn on_timer(stack: &mut NetStack, now: u64) {
+    let handles: Vec<SocketHandle> = stack
+        .sockets
+        .iter()
+        .filter_map(|(h, e)| match &e.state {
+            SocketState::Tcp(tcb) if tcb.next_deadline().is_some_and(|t| t <= now) => Some(*h),
+            _ => None,
+        })
+        .collect();
+
+    for handle in handles {
+        if let Some(SocketState::Tcp(tcb)) = stack.sockets.get_mut(&handle).map(|e| &mut e.state) {
+            tcb.on_timer(now);
+        }
+
+        flush(stack, handle);
+        stack.wake(handle);
+    }
+
+    // connections nobody will ever look at again
+    stack.sockets.retain(|_, e| match &e.state {
+        SocketState::Tcp(tcb) => !(tcb.is_finished() && (tcb.orphaned || tcb.parent.is_some())),
+        _ => true,
+    });
+}
+
+pub(crate) fn next_deadline(stack: &NetStack) -> Option<u64> {
+    stack
+        .sockets
+        .values()
+        .filter_map(|e| match &e.state {
+            SocketState::Tcp(tcb) => tcb.next_deadline(),
+            _ => None,
+        })
+        .min()
+}
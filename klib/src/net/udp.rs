@@ -0,0 +1,128 @@
+//! UDP (RFC 768)
+
+use alloc::{collections::vec_deque::VecDeque, vec::Vec};
+
+use super::{
+    NetError, Result, SocketAddrV4,
+    checksum::verify_l4,
+    dhcp,
+    ipv4::{Ipv4Header, PROTO_UDP},
+    socket::SocketState,
+    stack::{NetStack, Route},
+};
+
+pub const HEADER_LEN: usize = 8;
+/// datagrams held per socket before new ones are dropped
+const RX_QUEUE_LIMIT: usize = 64;
+
+#[derive(Debug, Default)]
+pub(crate) struct UdpSocket {
+    pub local: Option<SocketAddrV4>,
+    /// set by `connect`: only this peer is received from and sent to by default
+    pub remote: Option<SocketAddrV4>,
+    pub rx: VecDeque<(SocketAddrV4, Vec<u8>)>,
+}
+
+impl UdpSocket {
+    fn matches(&self, local: SocketAddrV4, remote: SocketAddrV4) -> bool {
+        let Some(bound) = self.local else {
+            return false;
+        };
+
+        bound.port == local.port
+            && (bound.addr.is_unspecified()
+                || bound.addr == local.addr
+                || local.addr.is_broadcast())
+            && self.remote.is_none_or(|r| r == remote)
+    }
+}
+
+fn emit(src_port: u16, dst_port: u16, payload: &[u8]) -> Result<Vec<u8>> {
+    let len = u16::try_from(HEADER_LEN + payload.len()).map_err(|_| NetError::FrameTooLarge)?;
+
+    let mut datagram = Vec::with_capacity(len as usize);
+    datagram.extend_from_slice(&src_port.to_be_bytes());
+    datagram.extend_from_slice(&dst_port.to_be_bytes());
+    datagram.extend_from_slice(&len.to_be_bytes());
+    datagram.extend_from_slice(&[0, 0]);
+    datagram.extend_from_slice(payload);
+    Ok(datagram)
+}
+
+/// send from `src` to `dst`, picking the interface by route
+pub(crate) fn send(
+    stack: &mut NetStack,
+    src: SocketAddrV4,
+    dst: SocketAddrV4,
+    payload: &[u8],
+) -> Result<()> {
+    let mut route = stack.route(dst.addr)?;
+    if !src.addr.is_unspecified() {
+        route.src = src.addr;
+    }
+
+    send_on(stack, &route, src.port, dst, payload)
+}
+
+/// send along an explicit route, e.g. broadcasts before the interface has an address
+pub(crate) fn send_on(
+    stack: &mut NetStack,
+    route: &Route,
+    src_port: u16,
+    dst: SocketAddrV4,
+    payload: &[u8],
+) -> Result<()> {
+    let datagram = emit(src_port, dst.port, payload)?;
+    stack.send_ip(route, dst.addr, PROTO_UDP, datagram, Some(6))
+}
+
+pub(crate) fn input(
+    stack: &mut NetStack,
+    iface: usize,
+    ip: &Ipv4Header,
+    data: &[u8],
+    csum_ok: bool,
+    now: u64,
+) {
+    if data.len() < HEADER_LEN {
+        return;
+    }
+
+    let len = u16::from_be_bytes([data[4], data[5]]) as usize;
+    if len < HEADER_LEN || len > data.len() {
+        return;
+    }
+
+    let datagram = &data[..len];
+    let has_csum = datagram[6..8] != [0, 0];
+    if has_csum && !csum_ok && !verify_l4(ip.src, ip.dst, PROTO_UDP, datagram) {
+        return;
+    }
+
+    let src_port = u16::from_be_bytes([datagram[0], datagram[1]]);
+    let dst_port = u16::from_be_bytes([datagram[2], datagram[3]]);
+    let payload = &datagram[HEADER_LEN..];
+
+    if dst_port == dhcp::CLIENT_PORT && stack.interfaces[iface].dhcp.is_some() {
+        dhcp::input(stack, iface, payload, now);
+        return;
+    }
+
+    let local = SocketAddrV4::new(ip.dst, dst_port);
+    let remote = SocketAddrV4::new(ip.src, src_port);
+
+    let target = stack
+        .sockets
+        .iter_mut()
+        .find_map(|(handle, e)| match &mut e.state {
+            SocketState::Udp(udp) if udp.matches(local, remote) => Some((*handle, udp)),
+            _ => None,
+        });
+
+    if let Some((handle, udp)) = target {
+        if udp.rx.len() < RX_QUEUE_LIMIT {
+            udp.rx.push_back((remote, payload.into()));
+        }
+        stack.wake(handle);
+    }
+}
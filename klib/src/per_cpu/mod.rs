@@ -13,6 +13,8 @@ pub struct PerCpuData {
     pub id: CpuIdLogical,
     /// for bootstrap only. owning core must set to true before BSP can continue.
     pub ready: AtomicBool,
+    /// NUMA node, 0 until the firmware tables say otherwise
+    pub node: AtomicU8,
 }
 
 #[macro_export]
@@ -36,6 +38,7 @@ impl PerCpu {
             cpus.push(PerCpuData {
                 id: CpuIdLogical::new(i as _),
                 ready: AtomicBool::new(false),
+                node: AtomicU8::new(0),
             });
         }
 
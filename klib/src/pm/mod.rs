@@ -1 +1,2 @@
+pub mod numa;
 pub mod page;
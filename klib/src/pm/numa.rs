@@ -0,0 +1,150 @@
+//! NUMA nodes: which memory and CPUs sit close together, and how far apart the nodes are.
+//!
+//! firmware numbers proximity domains sparsely, nodes are dense: the first domain seen is node
+//! 0, and so on. without firmware tables everything is node 0.
+
+use core::{ops::Range, sync::atomic::Ordering};
+
+use aarch64_cpu::registers::{Readable, TPIDR_EL1};
+use alloc::vec::Vec;
+
+use crate::{cpu_interface::CpuTopologyId, per_cpu::PerCpu, sync::RwLock, this_cpu};
+
+/// nodes the page allocator keeps apart. domains past these share the last node.
+pub const MAX_NODES: usize = 8;
+
+/// SLIT distance of a node to itself
+pub const LOCAL_DISTANCE: u8 = 10;
+/// assumed between nodes when there's no SLIT
+pub const REMOTE_DISTANCE: u8 = 20;
+
+pub type NodeId = u8;
+
+struct Numa {
+    /// proximity domain of each node
+    domains: Vec<u32>,
+    memory: Vec<(Range<usize>, NodeId)>,
+    cpus: Vec<(CpuTopologyId, NodeId)>,
+    distances: [[u8; MAX_NODES]; MAX_NODES],
+}
+
+static NUMA: RwLock<Numa> = RwLock::new(Numa {
+    domains: Vec::new(),
+    memory: Vec::new(),
+    cpus: Vec::new(),
+    distances: default_distances(),
+});
+
+const fn default_distances() -> [[u8; MAX_NODES]; MAX_NODES] {
+    let mut distances = [[REMOTE_DISTANCE; MAX_NODES]; MAX_NODES];
+    let mut i = 0;
+    while i < MAX_NODES {
+        distances[i][i] = LOCAL_DISTANCE;
+        i += 1;
+    }
+    distances
+}
+
+impl Numa {
+    fn node(&mut self, domain: u32) -> NodeId {
+        use log::*;
+
+        if let Some(node) = self.domains.iter().position(|&d| d == domain) {
+            return node as NodeId;
+        }
+
+        if self.domains.len() == MAX_NODES {
+            warn!(
+                "NUMA: more than {} nodes, domain {} shares the last",
+                MAX_NODES, domain
+            );
+            return (MAX_NODES - 1) as NodeId;
+        }
+
+        self.domains.push(domain);
+        (self.domains.len() - 1) as NodeId
+    }
+}
+
+/// the node of proximity domain `domain`, making one if it's new
+pub fn node_of_domain(domain: u32) -> NodeId {
+    NUMA.write().node(domain)
+}
+
+/// the node of `domain`, if it's been seen
+pub fn find_domain(domain: u32) -> Option<NodeId> {
+    NUMA.read()
+        .domains
+        .iter()
+        .position(|&d| d == domain)
+        .map(|node| node as NodeId)
+}
+
+pub fn add_memory(range: Range<usize>, domain: u32) {
+    let mut numa = NUMA.write();
+    let node = numa.node(domain);
+    numa.memory.push((range, node));
+}
+
+pub fn add_cpu(cpu: CpuTopologyId, domain: u32) {
+    let mut numa = NUMA.write();
+    let node = numa.node(domain);
+    numa.cpus.push((cpu, node));
+}
+
+/// distance between the nodes of two domains, as the SLIT has it
+pub fn set_distance(from: NodeId, to: NodeId, distance: u8) {
+    NUMA.write().distances[from as usize][to as usize] = distance;
+}
+
+/// at least 1, even without firmware tables
+pub fn node_count() -> usize {
+    NUMA.read().domains.len().max(1)
+}
+
+pub fn domain_of(node: NodeId) -> Option<u32> {
+    NUMA.read().domains.get(node as usize).copied()
+}
+
+pub fn distance(from: NodeId, to: NodeId) -> u8 {
+    NUMA.read().distances[from as usize][to as usize]
+}
+
+pub fn distances() -> [[u8; MAX_NODES]; MAX_NODES] {
+    NUMA.read().distances
+}
+
+/// the physical memory ranges and their nodes
+pub fn memory() -> Vec<(Range<usize>, NodeId)> {
+    NUMA.read().memory.clone()
+}
+
+pub fn node_of_cpu(cpu: CpuTopologyId) -> NodeId {
+    NUMA.read()
+        .cpus
+        .iter()
+        .find(|(c, _)| *c == cpu)
+        .map_or(0, |(_, node)| *node)
+}
+
+/// the node of the running CPU. 0 before the per CPU data is set up.
+pub fn local_node() -> NodeId {
+    if TPIDR_EL1.get() == 0 {
+        return 0;
+    }
+    this_cpu!().node.load(Ordering::Relaxed)
+}
+
+/// tell every CPU its node. needs the logical CPU IDs.
+pub fn init_cpu_nodes() {
+    let numa = NUMA.read();
+
+    for &(cpu, node) in &numa.cpus {
+        if let Some(pcpu) = cpu
+            .to_logical()
+            .and_then(|logical| PerCpu::get(logical.to_usize()))
+        {
+            pcpu.node.store(node, Ordering::Relaxed);
+        }
+    }
+}
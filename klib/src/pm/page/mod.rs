@@ -2,15 +2,18 @@ use core::{
     cell::UnsafeCell,
     fmt::Debug,
     ops::Range,
-    ptr::{self},
-    sync::atomic::{AtomicUsize, Ordering},
+    ptr::{self, NonNull},
+    sync::atomic::{AtomicPtr, AtomicUsize, Ordering},
     usize,
 };
 
 use derivative::Derivative;
 
 use crate::{
-    pm::page::mapper::AddressTranslator,
+    pm::{
+        numa::{MAX_NODES, NodeId, local_node},
+        page::mapper::AddressTranslator,
+    },
     vm::{
         is_kernel_address,
         page_allocator::{DmapPageAllocator, PhysicalPageAllocator},
@@ -27,6 +30,19 @@ pub mod mapper;
 const MAX_ORDER: usize = 11;
 const FREE_FLAG: u8 = 1 << 7;
 
+/// the kernel's page allocator, for drivers that want whole pages rather than heap memory
+static KERNEL_PAGE_ALLOCATOR: AtomicPtr<PageAllocator<'static>> = AtomicPtr::new(ptr::null_mut());
+
+/// register the kernel's page allocator. it has to hand out direct map addresses by now.
+pub fn set_kernel_page_allocator(allocator: &'static PageAllocator<'static>) {
+    KERNEL_PAGE_ALLOCATOR.store(allocator as *const _ as *mut _, Ordering::Release);
+}
+
+/// `None` before early init registers it
+pub fn kernel_page_allocator() -> Option<&'static PageAllocator<'static>> {
+    unsafe { KERNEL_PAGE_ALLOCATOR.load(Ordering::Acquire).as_ref() }
+}
+
 #[repr(C)]
 #[derive(Debug)]
 pub struct FreeBlock {
@@ -36,12 +52,56 @@ pub struct FreeBlock {
     page_index: usize,
 }
 
+/// pages of a zone from `start` (a page index) up to the next run, all on one node
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+struct ZoneRun {
+    start: usize,
+    node: NodeId,
+}
+
 #[repr(C)]
 pub struct Zone {
     meta_array: *mut u8,
     data_base: usize,
     total_pages: usize,
     next: *mut Zone,
+    /// the first always starts at 0
+    runs: [ZoneRun; MAX_NODES],
+    run_count: usize,
+}
+
+impl Zone {
+    fn node_of(&self, index: usize) -> NodeId {
+        self.runs[..self.run_count]
+            .iter()
+            .rev()
+            .find(|run| run.start <= index)
+            .map_or(0, |run| run.node)
+    }
+
+    /// whether the block at `index` crosses into another run. free blocks never do.
+    fn straddles(&self, index: usize, order: usize) -> bool {
+        self.runs[1..self.run_count]
+            .iter()
+            .any(|run| index < run.start && run.start < index + (1 << order))
+    }
+
+    /// one past the last page of run `i`
+    fn run_end(&self, i: usize) -> usize {
+        if i + 1 < self.run_count {
+            self.runs[i + 1].start
+        } else {
+            self.total_pages
+        }
+    }
+}
+
+/// pages on one NUMA node
+#[derive(Debug, Copy, Clone, Default)]
+pub struct NodeStats {
+    pub total_pages: usize,
+    pub allocated_pages: usize,
 }
 
 #[repr(C)]
@@ -50,9 +110,15 @@ pub struct Zone {
 pub struct PageAllocator<'a> {
     lock: TicketLock,
     zone_head: *mut Zone,
-    free_area: UnsafeCell<[*mut FreeBlock; MAX_ORDER]>,
+    /// per node
+    free_area: UnsafeCell<[[*mut FreeBlock; MAX_ORDER]; MAX_NODES]>,
     total_pages: AtomicUsize,
     allocated_pages: AtomicUsize,
+    node_count: AtomicUsize,
+    /// for each node, the nodes to allocate from, nearest first
+    fallback: UnsafeCell<[[NodeId; MAX_NODES]; MAX_NODES]>,
+    node_pages: [AtomicUsize; MAX_NODES],
+    node_allocated: [AtomicUsize; MAX_NODES],
     lowest_address: usize,
     is_dmap: bool,
 
@@ -69,9 +135,13 @@ impl<'a> PageAllocator<'a> {
         let pa = Self {
             lock: TicketLock::new(),
             zone_head: ptr::null_mut(),
-            free_area: UnsafeCell::new([ptr::null_mut(); MAX_ORDER]),
+            free_area: UnsafeCell::new([[ptr::null_mut(); MAX_ORDER]; MAX_NODES]),
             total_pages: AtomicUsize::new(0),
             allocated_pages: AtomicUsize::new(0),
+            node_count: AtomicUsize::new(1),
+            fallback: UnsafeCell::new(default_fallback()),
+            node_pages: [const { AtomicUsize::new(0) }; MAX_NODES],
+            node_allocated: [const { AtomicUsize::new(0) }; MAX_NODES],
             lowest_address: 0,
             is_dmap: false,
 
@@ -81,7 +151,28 @@ impl<'a> PageAllocator<'a> {
     }
 }
 
+/// every node falls back to the others in order
+const fn default_fallback() -> [[NodeId; MAX_NODES]; MAX_NODES] {
+    let mut fallback = [[0; MAX_NODES]; MAX_NODES];
+    let mut node = 0;
+    while node < MAX_NODES {
+        fallback[node][0] = node as NodeId;
+        let mut i = 1;
+        let mut other = 0;
+        while other < MAX_NODES {
+            if other != node {
+                fallback[node][i] = other as NodeId;
+                i += 1;
+            }
+            other += 1;
+        }
+        node += 1;
+    }
+    fallback
+}
+
 impl PageAllocator<'_> {
+    /// ranges land on node 0 until `set_nodes` says otherwise
     pub fn add_range(&mut self, region: &Range<usize>) {
         debug_assert_eq!(
             is_kernel_address(region.start),
@@ -141,13 +232,15 @@ impl PageAllocator<'_> {
                     data_base,
                     total_pages: usable_pages,
                     next: self.zone_head,
+                    runs: [ZoneRun { start: 0, node: 0 }; MAX_NODES],
+                    run_count: 1,
                 },
             );
 
             self.zone_head = zone_ptr;
             self.total_pages.fetch_add(usable_pages, Ordering::Relaxed);
+            self.node_pages[0].fetch_add(usable_pages, Ordering::Relaxed);
 
-            let free_area = &mut *self.free_area.get();
             let mut current_index = 0;
 
             while current_index < usable_pages {
@@ -163,24 +256,7 @@ impl PageAllocator<'_> {
                 let size_order = remaining.ilog2() as usize;
                 let order = align_order.min(size_order).min(MAX_ORDER - 1);
 
-                let pages = 1 << order;
-                for p in 0..pages {
-                    *meta_ptr.add(current_index + p) = (order as u8) | FREE_FLAG;
-                }
-
-                let block = (data_base + current_index * PAGE_SIZE) as *mut FreeBlock;
-                (*block).zone = zone_ptr;
-                (*block).page_index = current_index;
-
-                let old_head = free_area[order];
-                (*block).next = old_head;
-                (*block).prev = ptr::null_mut();
-
-                if !old_head.is_null() {
-                    (*old_head).prev = block;
-                }
-
-                free_area[order] = block;
+                self.push_free(zone_ptr, current_index, order);
                 current_index += 1 << order;
             }
         }
@@ -253,9 +329,9 @@ impl PageAllocator<'_> {
         }
 
         let free_area = unsafe { &mut *self.free_area.get() };
-        for order in 0..MAX_ORDER {
-            let mut current_block_phys = free_area[order];
-            free_area[order] = Self::to_dmap(free_area[order], self.translator);
+        for list in free_area.iter_mut().flatten() {
+            let mut current_block_phys = *list;
+            *list = Self::to_dmap(*list, self.translator);
 
             while !current_block_phys.is_null() {
                 let current_block_dmap = Self::to_dmap(current_block_phys, self.translator);
@@ -286,13 +362,14 @@ impl PageAllocator<'_> {
             (*this).zone_head = ptr::null_mut();
             (*this).lowest_address = 0;
 
-            let free_area = &mut *self.free_area.get();
-            for i in 0..MAX_ORDER {
-                free_area[i] = ptr::null_mut();
-            }
+            *self.free_area.get() = [[ptr::null_mut(); MAX_ORDER]; MAX_NODES];
 
             self.total_pages.store(0, Ordering::SeqCst);
             self.allocated_pages.store(0, Ordering::SeqCst);
+            for node in 0..MAX_NODES {
+                self.node_pages[node].store(0, Ordering::SeqCst);
+                self.node_allocated[node].store(0, Ordering::SeqCst);
+            }
         }
 
         self.lock.unlock();
@@ -302,74 +379,133 @@ impl PageAllocator<'_> {
         self.alloc_pages(0)
     }
 
-    // allocate 2^order pages (contiguous)
+    /// allocate 2^order pages (contiguous), near the running CPU
     pub fn alloc_pages(&self, target_order: usize) -> *mut u8 {
+        self.alloc_pages_near(target_order, local_node())
+    }
+
+    /// allocate 2^order pages (contiguous) from `node`, or the nearest node that has them
+    pub fn alloc_pages_near(&self, target_order: usize, node: NodeId) -> *mut u8 {
         if target_order >= MAX_ORDER {
             return ptr::null_mut();
         }
 
         self.lock.lock();
 
-        unsafe {
-            let free_area = &mut *self.free_area.get();
+        let node_count = self.node_count.load(Ordering::Relaxed);
+        let fallback = unsafe { (*self.fallback.get())[(node as usize).min(node_count - 1)] };
+
+        for &node in &fallback[..node_count] {
+            if let Some(pages) = unsafe { self.take_block(node, target_order) } {
+                self.lock.unlock();
+                return pages;
+            }
+        }
+
+        self.lock.unlock();
+        ptr::null_mut()
+    }
 
+    /// the lock must be held
+    unsafe fn take_block(&self, node: NodeId, target_order: usize) -> Option<*mut u8> {
+        let free_area = self.free_area.get();
+
+        unsafe {
             for current_order in target_order..MAX_ORDER {
-                let head = free_area[current_order];
+                let head = (*free_area)[node as usize][current_order];
+                if head.is_null() {
+                    continue;
+                }
 
-                if !head.is_null() {
-                    let next = (*head).next;
-                    free_area[current_order] = next;
+                let next = (*head).next;
+                (*free_area)[node as usize][current_order] = next;
 
-                    if !next.is_null() {
-                        (*next).prev = ptr::null_mut();
-                    }
+                if !next.is_null() {
+                    (*next).prev = ptr::null_mut();
+                }
 
-                    let zone = (*head).zone;
-                    let page_i = (*head).page_index;
-                    let meta_array = (*zone).meta_array;
+                let zone = (*head).zone;
+                let page_i = (*head).page_index;
+                let meta_array = (*zone).meta_array;
 
-                    let pages = 1 << target_order;
-                    for p in 0..pages {
-                        *meta_array.add(page_i + p) = target_order as u8;
-                    }
+                let pages = 1 << target_order;
+                for p in 0..pages {
+                    *meta_array.add(page_i + p) = target_order as u8;
+                }
 
-                    // split
-                    let mut split_order = current_order;
-                    while split_order > target_order {
-                        split_order -= 1;
+                // split
+                let mut split_order = current_order;
+                while split_order > target_order {
+                    split_order -= 1;
+                    self.push_free(zone, page_i + (1 << split_order), split_order);
+                }
 
-                        let split_i = page_i + (1 << split_order);
-                        let split_pages = 1 << split_order;
-                        for p in 0..split_pages {
-                            *meta_array.add(split_i + p) = (split_order as u8) | FREE_FLAG;
-                        }
+                self.allocated_pages
+                    .fetch_add(1 << target_order, Ordering::Relaxed);
+                self.node_allocated[node as usize].fetch_add(1 << target_order, Ordering::Relaxed);
 
-                        let block = ((*zone).data_base + split_i * PAGE_SIZE) as *mut FreeBlock;
-                        (*block).zone = zone;
-                        (*block).page_index = split_i;
+                return Some(((*zone).data_base + page_i * PAGE_SIZE) as *mut u8);
+            }
+        }
 
-                        let old_head = free_area[split_order];
-                        (*block).next = old_head;
-                        (*block).prev = ptr::null_mut();
+        None
+    }
 
-                        if !old_head.is_null() {
-                            (*old_head).prev = block;
-                        }
+    /// mark a block free and put it on its node's list. the lock must be held.
+    unsafe fn push_free(&self, zone_ptr: *mut Zone, page_index: usize, order: usize) {
+        unsafe {
+            let zone = &*zone_ptr;
+            let list = &mut (*self.free_area.get())[zone.node_of(page_index) as usize][order];
 
-                        free_area[split_order] = block;
-                    }
+            for p in 0..1 << order {
+                *zone.meta_array.add(page_index + p) = (order as u8) | FREE_FLAG;
+            }
 
-                    self.allocated_pages
-                        .fetch_add(1 << target_order, Ordering::Relaxed);
-                    self.lock.unlock();
+            let block = (zone.data_base + page_index * PAGE_SIZE) as *mut FreeBlock;
+            (*block).zone = zone_ptr;
+            (*block).page_index = page_index;
 
-                    return ((*zone).data_base + page_i * PAGE_SIZE) as *mut u8;
-                }
+            let old_head = *list;
+            (*block).next = old_head;
+            (*block).prev = ptr::null_mut();
+
+            if !old_head.is_null() {
+                (*old_head).prev = block;
             }
+
+            *list = block;
         }
+    }
 
-        self.lock.unlock();
-        ptr::null_mut()
+    /// like `push_free`, but in pieces if the block crosses runs
+    unsafe fn push_free_split(&self, zone_ptr: *mut Zone, page_index: usize, order: usize) {
+        unsafe {
+            if order > 0 && (*zone_ptr).straddles(page_index, order) {
+                self.push_free_split(zone_ptr, page_index, order - 1);
+                self.push_free_split(zone_ptr, page_index + (1 << (order - 1)), order - 1);
+            } else {
+                self.push_free(zone_ptr, page_index, order);
+            }
+        }
+    }
+
+    /// allocate 2^order contiguous pages, returned as a direct map address
+    pub fn alloc_dmap_pages(&self, order: usize) -> Option<NonNull<u8>> {
+        let pages = NonNull::new(self.alloc_pages(order))?;
+        if self.is_dmap {
+            Some(pages)
+        } else {
+            NonNull::new(self.translator.phys_to_dmap(pages.as_ptr() as usize))
+        }
+    }
+
+    /// free a block from `alloc_dmap_pages`
+    pub fn free_dmap_pages(&self, pages: NonNull<u8>) {
+        if self.is_dmap {
+            self.free_pages(pages.as_ptr());
+        } else {
+            self.free_pages(self.translator.dmap_to_phys(pages.as_ptr()) as *mut u8);
+        }
     }
 
     pub fn free_pages(&self, pages_ptr: *mut u8) {
@@ -403,6 +539,8 @@ impl PageAllocator<'_> {
                     let pages = 1 << order;
 
                     self.allocated_pages.fetch_sub(pages, Ordering::Relaxed);
+                    self.node_allocated[zone.node_of(index) as usize]
+                        .fetch_sub(pages, Ordering::Relaxed);
                     self.free_block_in_zone(zone_ptr, index, order);
 
                     handled = true;
@@ -424,7 +562,7 @@ impl PageAllocator<'_> {
         mut order: usize,
     ) {
         let zone = unsafe { &mut *zone_ptr };
-        let free_area = unsafe { &mut *self.free_area.get() };
+        let free_area = self.free_area.get();
 
         while order < MAX_ORDER - 1 {
             let index = page_index ^ (1 << order);
@@ -432,6 +570,11 @@ impl PageAllocator<'_> {
                 break;
             }
 
+            // buddies on different nodes stay apart
+            if zone.straddles(page_index & !(1 << order), order + 1) {
+                break;
+            }
+
             let meta_val = unsafe { *zone.meta_array.add(index) };
 
             if (meta_val & FREE_FLAG) == 0 || (meta_val & !FREE_FLAG) != order as u8 {
@@ -446,7 +589,7 @@ impl PageAllocator<'_> {
             if !prev.is_null() {
                 unsafe { (*prev).next = next };
             } else {
-                free_area[order] = next;
+                unsafe { (*free_area)[zone.node_of(index) as usize][order] = next };
             }
 
             if !next.is_null() {
@@ -458,25 +601,8 @@ impl PageAllocator<'_> {
             order += 1;
         }
 
-        let pages = 1 << order;
-        for p in 0..pages {
-            unsafe { *zone.meta_array.add(page_index + p) = (order as u8) | FREE_FLAG };
-        }
-
-        let block = (zone.data_base + page_index * PAGE_SIZE) as *mut FreeBlock;
-
-        unsafe { (*block).zone = zone_ptr };
-        unsafe { (*block).page_index = page_index };
-
-        let old_head = free_area[order];
-        unsafe { (*block).next = old_head };
-        unsafe { (*block).prev = ptr::null_mut() };
-
-        if !old_head.is_null() {
-            unsafe { (*old_head).prev = block };
-        }
-
-        free_area[order] = block;
+        // a block allocated before `set_nodes` can still cross runs
+        unsafe { self.push_free_split(zone_ptr, page_index, order) };
     }
 
     pub fn max_address(&self) -> usize {
@@ -510,6 +636,177 @@ impl PageAllocator<'_> {
     pub fn allocated_pages(&self) -> usize {
         self.allocated_pages.load(Ordering::Relaxed)
     }
+
+    pub fn node_count(&self) -> usize {
+        self.node_count.load(Ordering::Relaxed)
+    }
+
+    pub fn node_stats(&self, node: NodeId) -> NodeStats {
+        let Some(total) = self.node_pages.get(node as usize) else {
+            return NodeStats::default();
+        };
+
+        NodeStats {
+            total_pages: total.load(Ordering::Relaxed),
+            allocated_pages: self.node_allocated[node as usize].load(Ordering::Relaxed),
+        }
+    }
+
+    /// spread the zones over NUMA nodes. `memory` is physical ranges and their nodes, pages
+    /// outside all of them stay on node 0. blocks allocated before count against the node they
+    /// start in.
+    ///
+    /// the heap allocates from here, so nothing under the lock may allocate, free or log.
+    pub fn set_nodes(
+        &self,
+        node_count: usize,
+        memory: &[(Range<usize>, NodeId)],
+        distances: &[[u8; MAX_NODES]; MAX_NODES],
+    ) {
+        let node_count = node_count.clamp(1, MAX_NODES);
+
+        // the first zone that crosses too many nodes, to warn about once unlocked
+        let mut crowded = None;
+
+        self.lock.lock();
+
+        unsafe {
+            let detached = ptr::replace(
+                self.free_area.get(),
+                [[ptr::null_mut(); MAX_ORDER]; MAX_NODES],
+            );
+
+            for node in 0..MAX_NODES {
+                self.node_pages[node].store(0, Ordering::Relaxed);
+                self.node_allocated[node].store(0, Ordering::Relaxed);
+            }
+
+            let mut head = self.zone_head;
+            while !head.is_null() {
+                let zone = &mut *head;
+                if !self.set_runs(zone, memory) && crowded.is_none() {
+                    crowded = Some((zone.data_base, zone.runs[MAX_NODES - 1].node));
+                }
+
+                for i in 0..zone.run_count {
+                    self.node_pages[zone.runs[i].node as usize]
+                        .fetch_add(zone.run_end(i) - zone.runs[i].start, Ordering::Relaxed);
+                }
+
+                head = zone.next;
+            }
+
+            for lists in &detached {
+                for (order, &list) in lists.iter().enumerate() {
+                    let mut block = list;
+                    while !block.is_null() {
+                        let next = (*block).next;
+                        self.push_free_split((*block).zone, (*block).page_index, order);
+                        block = next;
+                    }
+                }
+            }
+
+            // whatever isn't free is allocated
+            let mut head = self.zone_head;
+            while !head.is_null() {
+                let zone = &*head;
+
+                let mut index = 0;
+                while index < zone.total_pages {
+                    let meta_val = *zone.meta_array.add(index);
+                    let pages = 1 << (meta_val & !FREE_FLAG);
+                    if meta_val & FREE_FLAG == 0 {
+                        self.node_allocated[zone.node_of(index) as usize]
+                            .fetch_add(pages, Ordering::Relaxed);
+                    }
+                    index += pages;
+                }
+
+                head = zone.next;
+            }
+
+            let fallback = &mut *self.fallback.get();
+            for (node, order) in fallback.iter_mut().enumerate().take(node_count) {
+                for (i, other) in order.iter_mut().enumerate() {
+                    *other = i as NodeId;
+                }
+                order[..node_count].sort_unstable_by_key(|&other| {
+                    (
+                        distances[node][other as usize],
+                        other as usize != node,
+                        other,
+                    )
+                });
+            }
+        }
+
+        self.node_count.store(node_count, Ordering::Relaxed);
+        self.lock.unlock();
+
+        if let Some((base, node)) = crowded {
+            use log::*;
+            warn!(
+                "page allocator: zone at {:#x} crosses too many nodes, the rest stays on node {}",
+                base, node
+            );
+        }
+    }
+
+    /// split `zone` into runs, from where the ranges of `memory` start and end. false if it
+    /// crosses more nodes than it has runs for. doesn't allocate.
+    fn set_runs(&self, zone: &mut Zone, memory: &[(Range<usize>, NodeId)]) -> bool {
+        let base = if self.is_dmap {
+            self.translator.dmap_to_phys(zone.data_base as _)
+        } else {
+            zone.data_base
+        };
+
+        let node_at = |index: usize| {
+            let phys = base + index * PAGE_SIZE;
+            memory
+                .iter()
+                .find(|(range, _)| range.contains(&phys))
+                .map_or(0, |(_, node)| *node)
+        };
+
+        // the first page index past `index` where a range of `memory` starts or ends
+        let total_pages = zone.total_pages;
+        let next_boundary = |index: usize| {
+            memory
+                .iter()
+                .flat_map(|(range, _)| [range.start, range.end])
+                .filter(|&phys| phys > base)
+                .map(|phys| (phys - base).div_ceil(PAGE_SIZE))
+                .filter(|&boundary| boundary > index && boundary < total_pages)
+                .min()
+        };
+
+        zone.runs[0] = ZoneRun {
+            start: 0,
+            node: node_at(0),
+        };
+        zone.run_count = 1;
+
+        let mut index = 0;
+        while let Some(boundary) = next_boundary(index) {
+            index = boundary;
+
+            let node = node_at(index);
+            if node == zone.runs[zone.run_count - 1].node {
+                continue;
+            }
+
+            if zone.run_count == MAX_NODES {
+                return false;
+            }
+
+            zone.runs[zone.run_count] = ZoneRun { start: index, node };
+            zone.run_count += 1;
+        }
+
+        true
+    }
 }
 
 impl PhysicalPageAllocator for PageAllocator<'_> {
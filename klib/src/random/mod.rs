@@ -0,0 +1,403 @@
+//! kernel randomness.
+//!
+//! sources mix what they collect into an input pool. the pool is a running BLAKE2s hash plus an
+//! estimate of how many bits of entropy went into it. random bytes come from a ChaCha20 generator
+//! whose key is derived from the pool once it has seen [`SEED_BITS`], and rekeyed from the pool
+//! every [`RESEED_INTERVAL`] after that. every output overwrites the key it was made with, so a
+//! later compromise doesn't reveal earlier output.
+//!
+//! sources are:
+//! - the `RNDR` instruction, on cores with FEAT_RNG
+//! - a seed the bootloader got from `EFI_RNG_PROTOCOL`
+//! - hardware RNGs, through [`EntropySource`]
+//! - interrupt timing jitter, credited at a bit per [`INTERRUPTS_PER_BIT`] interrupts
+
+mod blake2s;
+mod chacha;
+
+use core::{
+    arch::asm,
+    fmt::{self, Display},
+    sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use aarch64_cpu::registers::{CNTPCT_EL0, ID_AA64ISAR0_EL1, Readable};
+use alloc::{sync::Arc, vec::Vec};
+
+use crate::{
+    guard::InterruptGuard,
+    scheduler::GLOBAL_SCHEDULER,
+    sync::{RwLock, UnfairSpinlock, WaitQueue},
+    time,
+    vfs::{self, FileType, VfsError, inode::InodeOperations},
+};
+use blake2s::Blake2s;
+
+/// entropy needed before output counts as secure
+pub const SEED_BITS: usize = 256;
+/// the pool doesn't count past this
+const POOL_BITS: usize = 512;
+
+pub const RESEED_INTERVAL: Duration = Duration::from_secs(60);
+
+pub const INTERRUPTS_PER_BIT: usize = 64;
+
+/// bytes generated per lock hold, so a huge read doesn't keep interrupts off for long
+const CHUNK: usize = 256;
+
+/// `getrandom` flags, same values as Linux
+pub const GRND_NONBLOCK: u32 = 1 << 0;
+/// accepted and ignored, there's only one pool
+pub const GRND_RANDOM: u32 = 1 << 1;
+/// don't wait for the pool to be seeded
+pub const GRND_INSECURE: u32 = 1 << 2;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RandomError {
+    /// not seeded yet and the caller asked not to block
+    WouldBlock,
+    InvalidFlags,
+}
+
+impl Display for RandomError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::WouldBlock => f.write_str("entropy pool not seeded yet"),
+            Self::InvalidFlags => f.write_str("invalid flags"),
+        }
+    }
+}
+
+impl core::error::Error for RandomError {}
+
+pub type Result<T> = core::result::Result<T, RandomError>;
+
+/// a hardware RNG. the pool asks for more when it has just been drained; the source answers
+/// whenever it likes by calling [`add_entropy`].
+pub trait EntropySource: Send + Sync {
+    fn name(&self) -> &str;
+
+    /// start fetching some entropy. must not block, may be called from IRQ context.
+    fn request(&self);
+}
+
+struct Pool {
+    input: Blake2s,
+    /// estimated entropy in `input`, in bits
+    entropy: usize,
+    key: [u8; chacha::KEY_LEN],
+    seeded: bool,
+    last_reseed: Duration,
+}
+
+impl Pool {
+    const fn new() -> Self {
+        Self {
+            input: Blake2s::new(),
+            entropy: 0,
+            key: [0; chacha::KEY_LEN],
+            seeded: false,
+            last_reseed: Duration::ZERO,
+        }
+    }
+
+    /// returns true if this made the pool seeded
+    fn add(&mut self, bytes: &[u8], bits: usize) -> bool {
+        self.input.update(bytes);
+        self.entropy = (self.entropy + bits).min(POOL_BITS);
+
+        if !self.seeded && self.entropy >= SEED_BITS {
+            self.reseed();
+            self.seeded = true;
+            return true;
+        }
+
+        false
+    }
+
+    /// hash the input pool into a new key. the pool restarts from its own digest, so whatever
+    /// went in before still counts towards later keys.
+    fn reseed(&mut self) {
+        let digest = core::mem::take(&mut self.input).finalize();
+        self.input.update(&digest);
+
+        self.key = self.derive_key(&digest);
+        self.entropy = 0;
+        self.last_reseed = time::monotonic();
+    }
+
+    fn derive_key(&self, digest: &[u8]) -> [u8; chacha::KEY_LEN] {
+        let mut h = Blake2s::new();
+        h.update(&self.key);
+        h.update(digest);
+        h.update(&CNTPCT_EL0.get().to_le_bytes());
+        if let Some(r) = rndr() {
+            h.update(&r.to_le_bytes());
+        }
+        h.finalize()
+    }
+
+    /// returns true if the pool was drained for a reseed, and sources should be asked for more
+    fn generate(&mut self, out: &mut [u8]) -> bool {
+        let mut drained = false;
+
+        if self.seeded {
+            if self.entropy >= SEED_BITS
+                && time::monotonic().saturating_sub(self.last_reseed) >= RESEED_INTERVAL
+            {
+                self.reseed();
+                drained = true;
+            }
+        } else {
+            // best effort until seeded: fold in whatever the pool has so far
+            let digest = self.input.clone().finalize();
+            self.key = self.derive_key(&digest);
+        }
+
+        // fast key erasure: the first half block becomes the next key
+        let nonce = [0u8; 12];
+        let first = chacha::block(&self.key, 0, &nonce);
+        let (next_key, rest) = first.split_at(chacha::KEY_LEN);
+
+        let n = rest.len().min(out.len());
+        out[..n].copy_from_slice(&rest[..n]);
+
+        for (i, chunk) in out[n..].chunks_mut(chacha::BLOCK_LEN).enumerate() {
+            let block = chacha::block(&self.key, i as u32 + 1, &nonce);
+            chunk.copy_from_slice(&block[..chunk.len()]);
+        }
+
+        self.key.copy_from_slice(next_key);
+        drained
+    }
+}
+
+static POOL: UnfairSpinlock<Pool> = UnfairSpinlock::new(Pool::new());
+static SEEDED: AtomicBool = AtomicBool::new(false);
+static SEEDED_WAIT: WaitQueue<'static> = WaitQueue::new();
+
+static SOURCES: RwLock<Vec<Arc<dyn EntropySource>>> = RwLock::new(Vec::new());
+
+static HAS_RNDR: AtomicBool = AtomicBool::new(false);
+
+/// interrupt samples not yet folded into the pool
+static FAST_POOL: [AtomicU64; 4] = [const { AtomicU64::new(0) }; 4];
+static FAST_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+fn with_pool<R>(f: impl FnOnce(&mut Pool) -> R) -> R {
+    let _irq = InterruptGuard::new();
+    f(&mut POOL.lock())
+}
+
+fn became_seeded() {
+    use log::*;
+
+    SEEDED.store(true, Ordering::Release);
+    SEEDED_WAIT.wake_all(&GLOBAL_SCHEDULER);
+    info!("random: pool seeded");
+}
+
+fn request_entropy() {
+    for source in SOURCES.read().iter() {
+        source.request();
+    }
+}
+
+/// one value from the RNDR register, if the core has one and it didn't fail
+fn rndr() -> Option<u64> {
+    if !HAS_RNDR.load(Ordering::Relaxed) {
+        return None;
+    }
+
+    let value: u64;
+    let ok: u64;
+    // RNDR sets NZCV to 0b0100 when it couldn't produce a number in reasonable time
+    unsafe {
+        asm!(
+            "mrs {value}, s3_3_c2_c4_0",
+            "cset {ok}, ne",
+            value = out(reg) value,
+            ok = out(reg) ok,
+            options(nomem, nostack),
+        )
+    };
+
+    (ok != 0).then_some(value)
+}
+
+/// mix in entropy, crediting `bits` of it. safe to call from IRQ context.
+pub fn add_entropy(bytes: &[u8], bits: usize) {
+    if with_pool(|pool| pool.add(bytes, bits)) {
+        became_seeded();
+    }
+}
+
+/// mix in data that might be predictable (serial numbers, MACs, user writes), without credit
+pub fn add_device_randomness(bytes: &[u8]) {
+    add_entropy(bytes, 0);
+}
+
+/// sample the cycle counter on an interrupt. meant to be called for every interrupt taken.
+pub fn add_interrupt_timing(int_id: u32) {
+    let count = FAST_COUNT.fetch_add(1, Ordering::Relaxed);
+    let sample = CNTPCT_EL0.get() ^ ((int_id as u64) << 48);
+
+    let slot = &FAST_POOL[count % FAST_POOL.len()];
+    let mixed = (slot.load(Ordering::Relaxed) ^ sample)
+        .rotate_left(17)
+        .wrapping_mul(0x9E37_79B9_7F4A_7C15);
+    slot.store(mixed, Ordering::Relaxed);
+
+    if (count + 1).is_multiple_of(INTERRUPTS_PER_BIT) {
+        let mut bytes = [0u8; 32];
+        for (chunk, slot) in bytes.chunks_exact_mut(8).zip(&FAST_POOL) {
+            chunk.copy_from_slice(&slot.load(Ordering::Relaxed).to_le_bytes());
+        }
+        add_entropy(&bytes, 1);
+    }
+}
+
+/// a hardware RNG is ready. it's asked for entropy right away.
+pub fn register_source(source: Arc<dyn EntropySource>) {
+    use log::*;
+
+    info!("random: entropy source {}", source.name());
+    {
+        let _irq = InterruptGuard::new();
+        SOURCES.write().push(source.clone());
+    }
+    source.request();
+}
+
+pub fn is_seeded() -> bool {
+    SEEDED.load(Ordering::Acquire)
+}
+
+/// block until the pool is seeded
+pub fn wait_for_seed() {
+    while !is_seeded() {
+        let seen = SEEDED_WAIT.generation();
+        if is_seeded() {
+            break;
+        }
+        SEEDED_WAIT.wait(&GLOBAL_SCHEDULER, seen);
+    }
+}
+
+/// fill `buf` without waiting for the pool to be seeded. fine for hash seeds and the like, not
+/// for keys. safe to call from IRQ context.
+pub fn fill_bytes(buf: &mut [u8]) {
+    let mut drained = false;
+    for chunk in buf.chunks_mut(CHUNK) {
+        drained |= with_pool(|pool| pool.generate(chunk));
+    }
+
+    if drained {
+        request_entropy();
+    }
+}
+
+pub fn get_random_u32() -> u32 {
+    let mut bytes = [0u8; 4];
+    fill_bytes(&mut bytes);
+    u32::from_le_bytes(bytes)
+}
+
+pub fn get_random_u64() -> u64 {
+    let mut bytes = [0u8; 8];
+    fill_bytes(&mut bytes);
+    u64::from_le_bytes(bytes)
+}
+
+/// fill `buf` with random bytes. waits for the pool to be seeded unless `GRND_NONBLOCK` or
+/// `GRND_INSECURE` is given. returns how many bytes were written.
+pub fn getrandom(buf: &mut [u8], flags: u32) -> Result<usize> {
+    if flags & !(GRND_NONBLOCK | GRND_RANDOM | GRND_INSECURE) != 0
+        || flags & (GRND_RANDOM | GRND_INSECURE) == GRND_RANDOM | GRND_INSECURE
+    {
+        return Err(RandomError::InvalidFlags);
+    }
+
+    if flags & GRND_INSECURE == 0 && !is_seeded() {
+        if flags & GRND_NONBLOCK != 0 {
+            return Err(RandomError::WouldBlock);
+        }
+        wait_for_seed();
+    }
+
+    fill_bytes(buf);
+    Ok(buf.len())
+}
+
+/// seed from the bootloader and the CPU, and publish the device nodes
+pub fn init(boot_seed: Option<&[u8]>) {
+    use log::*;
+
+    let has_rndr = ID_AA64ISAR0_EL1.read(ID_AA64ISAR0_EL1::RNDR) != 0;
+    HAS_RNDR.store(has_rndr, Ordering::Relaxed);
+
+    if let Some(seed) = boot_seed {
+        info!("random: {} byte seed from the bootloader", seed.len());
+        add_entropy(seed, seed.len() * 8);
+    }
+
+    if has_rndr {
+        let mut words = 0;
+        for _ in 0..SEED_BITS / 64 {
+            if let Some(r) = rndr() {
+                add_entropy(&r.to_le_bytes(), 64);
+                words += 1;
+            }
+        }
+        info!("random: RNDR available, {} bits", words * 64);
+    }
+
+    if !is_seeded() {
+        info!("random: waiting for entropy sources");
+    }
+
+    vfs::devfs::register(
+        "random",
+        FileType::Normal,
+        Arc::new(RandomInode { flags: 0 }),
+    );
+    vfs::devfs::register(
+        "urandom",
+        FileType::Normal,
+        Arc::new(RandomInode {
+            flags: GRND_INSECURE,
+        }),
+    );
+}
+
+/// `/dev/random` blocks until seeded, `/dev/urandom` never does. writes are mixed into the pool
+/// without being credited.
+struct RandomInode {
+    flags: u32,
+}
+
+impl InodeOperations for RandomInode {
+    fn read_at(&self, _offset: u64, buffer: &mut [u8]) -> vfs::Result<u64> {
+        getrandom(buffer, self.flags)
+            .map(|n| n as u64)
+            .map_err(|_| VfsError::Io)
+    }
+
+    fn write_at(&self, _offset: u64, buffer: &[u8]) -> vfs::Result<u64> {
+        add_device_randomness(buffer);
+        Ok(buffer.len() as u64)
+    }
+
+    fn lookup_child(&self, _name: &str) -> vfs::Result<Arc<vfs::inode::Inode>> {
+        Err(VfsError::NotADirectory)
+    }
+
+    fn create(&self, _name: &str, _file_type: FileType) -> vfs::Result<Arc<vfs::inode::Inode>> {
+        Err(VfsError::NotADirectory)
+    }
+
+    fn truncate(&self, _size: u64) -> vfs::Result<()> {
+        Err(VfsError::PermissionDenied)
+    }
+}
@@ -1,5 +1,5 @@
 use core::{
-    sync::atomic::{AtomicU8, Ordering},
+    sync::atomic::{AtomicBool, AtomicU8, Ordering},
     usize,
 };
 
@@ -38,6 +38,8 @@ pub static GLOBAL_SCHEDULER: Scheduler = Scheduler::new();
 pub struct Scheduler<'a> {
     queues: RwLock<Vec<UnfairSpinlock<LocalScheduler<'a>>>>,
     spawn_counter: AtomicU8,
+    /// user threads don't run again once set
+    users_stopped: AtomicBool,
 }
 
 unsafe impl Send for Scheduler<'_> {}
@@ -48,6 +50,7 @@ impl<'a> Scheduler<'a> {
         Self {
             queues: RwLock::new(Vec::new()),
             spawn_counter: AtomicU8::new(0),
+            users_stopped: AtomicBool::new(false),
         }
     }
 
@@ -83,10 +86,11 @@ impl<'a> Scheduler<'a> {
         }
     }
 
+    /// `None` outside a thread, including on a core the scheduler doesn't know about yet
     pub fn current_thread(&self) -> Option<Arc<Thread<'a>>> {
         let cpu_id = CpuIdLogical::current();
         let queues = self.queues.read();
-        let local = queues[cpu_id.to_usize()].lock();
+        let local = queues.get(cpu_id.to_usize())?.lock();
         local.current_thread.clone()
     }
 
@@ -103,6 +107,11 @@ impl<'a> Scheduler<'a> {
     }
 
     pub fn spawn(&self, thread: Arc<Thread<'a>>) {
+        if !thread.is_kernel() && self.users_stopped.load(Ordering::Acquire) {
+            thread.set_state(ThreadState::Dead);
+            return;
+        }
+
         let queues = self.queues.read();
         assert!(!queues.is_empty(), "scheduler has no CPUs");
 
@@ -114,6 +123,35 @@ impl<'a> Scheduler<'a> {
         target_queue.lock().thread_queue.push_back(thread);
     }
 
+    /// stop every user thread for good, for shutdown. running ones stop at their next switch,
+    /// blocked ones when they're woken. returns how many were queued or running.
+    pub fn stop_user_threads(&self) -> usize {
+        self.users_stopped.store(true, Ordering::Release);
+
+        let mut stopped = 0;
+        for queue in self.queues.read().iter() {
+            let mut local = queue.lock();
+
+            local.thread_queue.retain(|thread| {
+                if thread.is_kernel() {
+                    return true;
+                }
+                thread.set_state(ThreadState::Dead);
+                stopped += 1;
+                false
+            });
+
+            if let Some(current) = &local.current_thread
+                && !current.is_kernel()
+            {
+                current.set_state(ThreadState::Dead);
+                stopped += 1;
+            }
+        }
+
+        stopped
+    }
+
     pub fn schedule<'ctx>(&self, ctx: RegisterFileRef<'ctx>) -> RegisterFileRef<'ctx> {
         let cpu_id = CpuIdLogical::current();
         let queues_guard = self.queues.read();
@@ -5,7 +5,15 @@ use core::{
 
 use super::cpu_interface::CpuTopologyId;
 
+pub const PSCI_0_2_FN_CPU_OFF: u32 = 0x8400_0002;
 pub const PSCI_0_2_FN64_CPU_ON: u32 = 0xC400_0003;
+pub const PSCI_0_2_FN_SYSTEM_OFF: u32 = 0x8400_0008;
+pub const PSCI_0_2_FN_SYSTEM_RESET: u32 = 0x8400_0009;
+pub const PSCI_1_0_FN_PSCI_FEATURES: u32 = 0x8400_000A;
+pub const PSCI_1_1_FN64_SYSTEM_RESET2: u32 = 0xC400_0012;
+
+/// `SYSTEM_RESET2` reset type: like `SYSTEM_RESET`, but memory may survive it
+pub const PSCI_SYSTEM_WARM_RESET: u32 = 0;
 
 #[repr(i64)]
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
@@ -84,28 +92,76 @@ unsafe fn smccc_call_smc(fid: u32, arg1: u64, arg2: u64, arg3: u64) -> i64 {
 
 pub static USE_HVC: AtomicBool = AtomicBool::new(false);
 
+fn psci_call(fid: u32, arg1: u64, arg2: u64, arg3: u64) -> i64 {
+    unsafe {
+        match USE_HVC.load(Ordering::Relaxed) {
+            true => smccc_call_hvc(fid, arg1, arg2, arg3),
+            false => smccc_call_smc(fid, arg1, arg2, arg3),
+        }
+    }
+}
+
 /// power on a CPU by its MPIDR using PSCI.
 pub fn cpu_on(
     target_cpu: CpuTopologyId,
     entry_point_paddr: u64,
     context_id: u64,
 ) -> Result<(), PsciError> {
-    let res = unsafe {
-        match USE_HVC.load(Ordering::Relaxed) {
-            true => smccc_call_hvc(
-                PSCI_0_2_FN64_CPU_ON,
-                target_cpu.to_mpidr(),
-                entry_point_paddr,
-                context_id,
-            ),
-            false => smccc_call_smc(
-                PSCI_0_2_FN64_CPU_ON,
-                target_cpu.to_mpidr(),
-                entry_point_paddr,
-                context_id,
-            ),
-        }
-    };
+    let res = psci_call(
+        PSCI_0_2_FN64_CPU_ON,
+        target_cpu.to_mpidr(),
+        entry_point_paddr,
+        context_id,
+    );
 
     PsciError::from_i64(res)
 }
+
+/// power off the calling CPU. only returns on failure.
+pub fn cpu_off() -> PsciError {
+    match PsciError::from_i64(psci_call(PSCI_0_2_FN_CPU_OFF, 0, 0, 0)) {
+        Ok(()) => PsciError::Unknown,
+        Err(e) => e,
+    }
+}
+
+/// power off the machine. only returns on failure.
+pub fn system_off() -> PsciError {
+    match PsciError::from_i64(psci_call(PSCI_0_2_FN_SYSTEM_OFF, 0, 0, 0)) {
+        Ok(()) => PsciError::Unknown,
+        Err(e) => e,
+    }
+}
+
+/// cold reset the machine. only returns on failure.
+pub fn system_reset() -> PsciError {
+    match PsciError::from_i64(psci_call(PSCI_0_2_FN_SYSTEM_RESET, 0, 0, 0)) {
+        Ok(()) => PsciError::Unknown,
+        Err(e) => e,
+    }
+}
+
+/// reset the machine the way `reset_type` says. only returns on failure.
+pub fn system_reset2(reset_type: u32, cookie: u64) -> PsciError {
+    let res = psci_call(PSCI_1_1_FN64_SYSTEM_RESET2, reset_type as u64, cookie, 0);
+    match PsciError::from_i64(res) {
+        Ok(()) => PsciError::Unknown,
+        Err(e) => e,
+    }
+}
+
+/// whether the firmware implements the PSCI function `fid`. PSCI 0.2 can't say, so anything
+/// past it reads as missing there.
+pub fn has_feature(fid: u32) -> bool {
+    psci_call(PSCI_1_0_FN_PSCI_FEATURES, fid as u64, 0, 0) >= 0
+}
+
+/// warm reset if the firmware can, cold otherwise. only returns on failure.
+pub fn reboot() -> PsciError {
+    if has_feature(PSCI_1_1_FN64_SYSTEM_RESET2) {
+        let e = system_reset2(PSCI_SYSTEM_WARM_RESET, 0);
+        log::warn!("PSCI: SYSTEM_RESET2 failed: {:?}", e);
+    }
+
+    system_reset()
+}
@@ -3,12 +3,18 @@ use core::{
     fmt::Debug,
     ops::{Deref, DerefMut},
     sync::atomic::{Atomic, AtomicBool, AtomicUsize, Ordering},
+    time::Duration,
 };
 
 use aarch64_cpu::asm::{sev, wfe};
-use alloc::{collections::vec_deque::VecDeque, sync::Arc};
+use alloc::{collections::vec_deque::VecDeque, sync::Arc, vec::Vec};
 
-use crate::{scheduler::Scheduler, thread::Thread};
+use crate::{
+    guard::InterruptGuard,
+    scheduler::{GLOBAL_SCHEDULER, Scheduler},
+    thread::{Thread, ThreadState},
+    time,
+};
 
 pub struct SleepingMutex<'a, T: ?Sized> {
     locked: AtomicBool,
@@ -106,6 +112,111 @@ impl<'m, 'a, T: ?Sized> Drop for SleepingMutexGuard<'m, 'a, T> {
     }
 }
 
+/// threads sleeping until some condition changes.
+/// wakers bump `generation` first, so a waiter that raced a wake-up doesn't go to sleep.
+/// `wake_all` is safe to call from IRQ context.
+pub struct WaitQueue<'a> {
+    generation: AtomicUsize,
+    waiters: UnfairSpinlock<VecDeque<Arc<Thread<'a>>>>,
+}
+
+unsafe impl<'a> Sync for WaitQueue<'a> {}
+unsafe impl<'a> Send for WaitQueue<'a> {}
+
+impl<'a> WaitQueue<'a> {
+    pub const fn new() -> Self {
+        Self {
+            generation: AtomicUsize::new(0),
+            waiters: UnfairSpinlock::new(VecDeque::new()),
+        }
+    }
+
+    /// read before checking the condition, then pass to `wait`.
+    pub fn generation(&self) -> usize {
+        self.generation.load(Ordering::Acquire)
+    }
+
+    /// block until woken. returns immediately if a wake-up happened since `seen`.
+    pub fn wait(&self, scheduler: &Scheduler<'a>, seen: usize) {
+        // masked until we've switched out, so an IRQ wake on this core can't race the yield
+        let _irq = InterruptGuard::new();
+
+        let current = scheduler
+            .current_thread()
+            .expect("can't wait without a running thread!!");
+
+        {
+            let mut waiters = self.waiters.lock();
+            if self.generation.load(Ordering::Acquire) != seen {
+                return;
+            }
+
+            current.set_state(ThreadState::Blocked);
+            waiters.push_back(current);
+        }
+
+        Scheduler::yield_now();
+    }
+
+    pub fn wake_all(&self, scheduler: &Scheduler<'a>) {
+        let _irq = InterruptGuard::new();
+        self.generation.fetch_add(1, Ordering::AcqRel);
+
+        let mut waiters = self.waiters.lock();
+        while let Some(thread) = waiters.pop_front() {
+            scheduler.unblock(thread);
+        }
+    }
+}
+
+/// queues with a thread in `wait_until`, by address, and when to wake them. an entry is
+/// removed before its waiter returns, so the queue outlives it.
+static TIMED: UnfairSpinlock<Vec<(Duration, usize)>> = UnfairSpinlock::new(Vec::new());
+
+impl WaitQueue<'static> {
+    /// `wait`, but woken at `deadline` on the monotonic clock at the latest. other waiters on
+    /// the queue may be woken with it.
+    pub fn wait_until(&self, scheduler: &Scheduler<'static>, seen: usize, deadline: Duration) {
+        let entry = (deadline, self as *const Self as usize);
+        {
+            let _irq = InterruptGuard::new();
+            TIMED.lock().push(entry);
+        }
+
+        self.wait(scheduler, seen);
+
+        let _irq = InterruptGuard::new();
+        let mut timed = TIMED.lock();
+        if let Some(i) = timed.iter().position(|e| *e == entry) {
+            timed.swap_remove(i);
+        }
+    }
+}
+
+/// scheduler tick hook: wake the queues whose deadline passed. cheap enough for IRQ context.
+pub fn tick() {
+    let now = time::monotonic();
+    let mut timed = TIMED.lock();
+
+    let mut i = 0;
+    while i < timed.len() {
+        if timed[i].0 > now {
+            i += 1;
+            continue;
+        }
+
+        let (_, queue) = timed.swap_remove(i);
+        // SAFETY: the waiter is still in `wait_until`, it removes the entry before returning
+        unsafe { &*(queue as *const WaitQueue<'static>) }.wake_all(&GLOBAL_SCHEDULER);
+    }
+}
+
+impl Default for WaitQueue<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[repr(C, align(64))]
 #[derive(Debug)]
 pub struct TicketLock {
@@ -0,0 +1,132 @@
+//! monotonic and wall-clock time.
+//!
+//! the monotonic clock is the architected counter and starts near zero at boot. the realtime clock
+//! is the monotonic clock plus an offset, seeded by an RTC driver (or anyone else who knows the
+//! date) through [`set_realtime`].
+
+use core::{
+    fmt,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    time::Duration,
+};
+
+use aarch64_cpu::registers::{CNTFRQ_EL0, CNTPCT_EL0, Readable};
+
+const NANOS_PER_SEC: u128 = 1_000_000_000;
+const SECS_PER_DAY: u64 = 86_400;
+
+/// realtime minus monotonic, in nanoseconds
+static REALTIME_OFFSET: AtomicU64 = AtomicU64::new(0);
+static REALTIME_SET: AtomicBool = AtomicBool::new(false);
+
+/// time since the counter started, which is roughly boot
+pub fn monotonic() -> Duration {
+    let ticks = CNTPCT_EL0.get() as u128;
+    let freq = CNTFRQ_EL0.get().max(1) as u128;
+    let nanos = ticks * NANOS_PER_SEC / freq;
+
+    Duration::new(
+        (nanos / NANOS_PER_SEC) as u64,
+        (nanos % NANOS_PER_SEC) as u32,
+    )
+}
+
+/// time since the UNIX epoch. counts from the epoch at boot if nothing has set the clock.
+pub fn realtime() -> Duration {
+    monotonic() + Duration::from_nanos(REALTIME_OFFSET.load(Ordering::Acquire))
+}
+
+/// whether [`realtime`] has been set from a real clock source
+pub fn realtime_is_set() -> bool {
+    REALTIME_SET.load(Ordering::Acquire)
+}
+
+/// set the wall clock to `now`, as time since the UNIX epoch
+pub fn set_realtime(now: Duration) {
+    let offset = now.saturating_sub(monotonic());
+    REALTIME_OFFSET.store(offset.as_nanos() as u64, Ordering::Release);
+    REALTIME_SET.store(true, Ordering::Release);
+}
+
+/// a broken-down UTC date and time
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DateTime {
+    pub year: u32,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl DateTime {
+    /// split seconds since the UNIX epoch into a civil date
+    pub fn from_unix(secs: u64) -> Self {
+        let days = secs / SECS_PER_DAY;
+        let rem = secs % SECS_PER_DAY;
+
+        // Howard Hinnant's civil_from_days. eras are 400 year cycles of the gregorian calendar.
+        let z = days + 719_468;
+        let era = z / 146_097;
+        let doe = z % 146_097;
+        let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = (doy - (153 * mp + 2) / 5 + 1) as u8;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 } as u8;
+        let year = (yoe + era * 400 + (month <= 2) as u64) as u32;
+
+        Self {
+            year,
+            month,
+            day,
+            hour: (rem / 3600) as u8,
+            minute: (rem / 60 % 60) as u8,
+            second: (rem % 60) as u8,
+        }
+    }
+
+    /// seconds since the UNIX epoch. `None` before it, or if a field is out of range.
+    pub fn to_unix(&self) -> Option<u64> {
+        if !(1..=12).contains(&self.month)
+            || !(1..=31).contains(&self.day)
+            || self.hour > 23
+            || self.minute > 59
+            || self.second > 59
+            || self.year < 1970
+        {
+            return None;
+        }
+
+        // days_from_civil, the inverse of the above
+        let year = self.year as u64 - (self.month <= 2) as u64;
+        let era = year / 400;
+        let yoe = year % 400;
+        let mp = (self.month as u64 + 9) % 12;
+        let doy = (153 * mp + 2) / 5 + self.day as u64 - 1;
+        let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+        let days = (era * 146_097 + doe).checked_sub(719_468)?;
+
+        Some(
+            days * SECS_PER_DAY
+                + self.hour as u64 * 3600
+                + self.minute as u64 * 60
+                + self.second as u64,
+        )
+    }
+
+    /// the current wall-clock time
+    pub fn now() -> Self {
+        Self::from_unix(realtime().as_secs())
+    }
+}
+
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
@@ -0,0 +1,280 @@
+//! turning key presses into terminal input.
+//!
+//! keyboard drivers report Linux input keycodes (what virtio-input uses, and what HID usages are
+//! translated to) and [`Keyboard`] turns them into bytes for the line discipline, using the
+//! current [`Keymap`].
+
+use alloc::vec::Vec;
+
+use crate::{guard::InterruptGuard, sync::UnfairSpinlock};
+
+/// Linux input keycodes, the ones with special meaning here
+pub mod keys {
+    pub const KEY_ESC: u16 = 1;
+    pub const KEY_BACKSPACE: u16 = 14;
+    pub const KEY_ENTER: u16 = 28;
+    pub const KEY_LEFTCTRL: u16 = 29;
+    pub const KEY_LEFTSHIFT: u16 = 42;
+    pub const KEY_RIGHTSHIFT: u16 = 54;
+    pub const KEY_LEFTALT: u16 = 56;
+    pub const KEY_CAPSLOCK: u16 = 58;
+    pub const KEY_KPENTER: u16 = 96;
+    pub const KEY_RIGHTCTRL: u16 = 97;
+    pub const KEY_RIGHTALT: u16 = 100;
+    pub const KEY_HOME: u16 = 102;
+    pub const KEY_UP: u16 = 103;
+    pub const KEY_PAGEUP: u16 = 104;
+    pub const KEY_LEFT: u16 = 105;
+    pub const KEY_RIGHT: u16 = 106;
+    pub const KEY_END: u16 = 107;
+    pub const KEY_DOWN: u16 = 108;
+    pub const KEY_PAGEDOWN: u16 = 109;
+    pub const KEY_INSERT: u16 = 110;
+    pub const KEY_DELETE: u16 = 111;
+}
+
+use keys::*;
+
+/// keycodes a keymap covers
+pub const KEYMAP_SIZE: usize = 128;
+
+/// characters per keycode, without and with shift. 0 means the key doesn't type anything.
+pub struct Keymap {
+    pub name: &'static str,
+    pub normal: [u8; KEYMAP_SIZE],
+    pub shifted: [u8; KEYMAP_SIZE],
+}
+
+const fn us_keymap() -> Keymap {
+    let mut normal = [0u8; KEYMAP_SIZE];
+    let mut shifted = [0u8; KEYMAP_SIZE];
+
+    // keycode, unshifted, shifted
+    let table: &[(usize, u8, u8)] = &[
+        (1, 0x1B, 0x1B),
+        (2, b'1', b'!'),
+        (3, b'2', b'@'),
+        (4, b'3', b'#'),
+        (5, b'4', b'$'),
+        (6, b'5', b'%'),
+        (7, b'6', b'^'),
+        (8, b'7', b'&'),
+        (9, b'8', b'*'),
+        (10, b'9', b'('),
+        (11, b'0', b')'),
+        (12, b'-', b'_'),
+        (13, b'=', b'+'),
+        (14, 0x7F, 0x7F),
+        (15, b'\t', b'\t'),
+        (16, b'q', b'Q'),
+        (17, b'w', b'W'),
+        (18, b'e', b'E'),
+        (19, b'r', b'R'),
+        (20, b't', b'T'),
+        (21, b'y', b'Y'),
+        (22, b'u', b'U'),
+        (23, b'i', b'I'),
+        (24, b'o', b'O'),
+        (25, b'p', b'P'),
+        (26, b'[', b'{'),
+        (27, b']', b'}'),
+        (28, b'\r', b'\r'),
+        (30, b'a', b'A'),
+        (31, b's', b'S'),
+        (32, b'd', b'D'),
+        (33, b'f', b'F'),
+        (34, b'g', b'G'),
+        (35, b'h', b'H'),
+        (36, b'j', b'J'),
+        (37, b'k', b'K'),
+        (38, b'l', b'L'),
+        (39, b';', b':'),
+        (40, b'\'', b'"'),
+        (41, b'`', b'~'),
+        (43, b'\\', b'|'),
+        (44, b'z', b'Z'),
+        (45, b'x', b'X'),
+        (46, b'c', b'C'),
+        (47, b'v', b'V'),
+        (48, b'b', b'B'),
+        (49, b'n', b'N'),
+        (50, b'm', b'M'),
+        (51, b',', b'<'),
+        (52, b'.', b'>'),
+        (53, b'/', b'?'),
+        (55, b'*', b'*'),
+        (57, b' ', b' '),
+        (71, b'7', b'7'),
+        (72, b'8', b'8'),
+        (73, b'9', b'9'),
+        (74, b'-', b'-'),
+        (75, b'4', b'4'),
+        (76, b'5', b'5'),
+        (77, b'6', b'6'),
+        (78, b'+', b'+'),
+        (79, b'1', b'1'),
+        (80, b'2', b'2'),
+        (81, b'3', b'3'),
+        (82, b'0', b'0'),
+        (83, b'.', b'.'),
+        (96, b'\r', b'\r'),
+        (98, b'/', b'/'),
+    ];
+
+    let mut i = 0;
+    while i < table.len() {
+        let (code, n, s) = table[i];
+        normal[code] = n;
+        shifted[code] = s;
+        i += 1;
+    }
+
+    Keymap {
+        name: "us",
+        normal,
+        shifted,
+    }
+}
+
+pub static US: Keymap = us_keymap();
+
+/// taken from keyboard IRQs too, hence the interrupt guards
+static KEYMAP: UnfairSpinlock<&'static Keymap> = UnfairSpinlock::new(&US);
+
+/// switch every keyboard to `keymap`
+pub fn set_keymap(keymap: &'static Keymap) {
+    let _irq = InterruptGuard::new();
+    *KEYMAP.lock() = keymap;
+}
+
+pub fn keymap() -> &'static Keymap {
+    let _irq = InterruptGuard::new();
+    *KEYMAP.lock()
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct Modifiers {
+    pub left_shift: bool,
+    pub right_shift: bool,
+    pub left_ctrl: bool,
+    pub right_ctrl: bool,
+    pub left_alt: bool,
+    pub right_alt: bool,
+    pub caps_lock: bool,
+}
+
+impl Modifiers {
+    pub fn shift(&self) -> bool {
+        self.left_shift || self.right_shift
+    }
+
+    pub fn ctrl(&self) -> bool {
+        self.left_ctrl || self.right_ctrl
+    }
+
+    pub fn alt(&self) -> bool {
+        self.left_alt || self.right_alt
+    }
+}
+
+/// modifier state of one keyboard
+#[derive(Debug, Default)]
+pub struct Keyboard {
+    modifiers: Modifiers,
+}
+
+impl Keyboard {
+    pub const fn new() -> Self {
+        Self {
+            modifiers: Modifiers {
+                left_shift: false,
+                right_shift: false,
+                left_ctrl: false,
+                right_ctrl: false,
+                left_alt: false,
+                right_alt: false,
+                caps_lock: false,
+            },
+        }
+    }
+
+    pub fn modifiers(&self) -> Modifiers {
+        self.modifiers
+    }
+
+    /// handle one key event. `pressed` is false on release, auto-repeat counts as a press.
+    /// whatever the key types is appended to `out`.
+    pub fn key(&mut self, code: u16, pressed: bool, out: &mut Vec<u8>) {
+        let m = &mut self.modifiers;
+        match code {
+            KEY_LEFTSHIFT => m.left_shift = pressed,
+            KEY_RIGHTSHIFT => m.right_shift = pressed,
+            KEY_LEFTCTRL => m.left_ctrl = pressed,
+            KEY_RIGHTCTRL => m.right_ctrl = pressed,
+            KEY_LEFTALT => m.left_alt = pressed,
+            KEY_RIGHTALT => m.right_alt = pressed,
+            KEY_CAPSLOCK if pressed => m.caps_lock = !m.caps_lock,
+            _ if pressed => self.press(code, out),
+            _ => {}
+        }
+    }
+
+    fn press(&self, code: u16, out: &mut Vec<u8>) {
+        // the VT100 sequences for the editing keys
+        let sequence: &[u8] = match code {
+            KEY_UP => b"\x1b[A",
+            KEY_DOWN => b"\x1b[B",
+            KEY_RIGHT => b"\x1b[C",
+            KEY_LEFT => b"\x1b[D",
+            KEY_HOME => b"\x1b[H",
+            KEY_END => b"\x1b[F",
+            KEY_INSERT => b"\x1b[2~",
+            KEY_DELETE => b"\x1b[3~",
+            KEY_PAGEUP => b"\x1b[5~",
+            KEY_PAGEDOWN => b"\x1b[6~",
+            _ => b"",
+        };
+        if !sequence.is_empty() {
+            out.extend_from_slice(sequence);
+            return;
+        }
+
+        // one keymap for the whole key, even if it's switched meanwhile
+        let keymap = keymap();
+        let Some(&normal) = keymap.normal.get(code as usize) else {
+            return;
+        };
+        if normal == 0 {
+            return;
+        }
+
+        let m = self.modifiers;
+        let letter = normal.is_ascii_lowercase();
+        let shifted = m.shift() != (letter && m.caps_lock);
+        let mut c = if shifted {
+            keymap.shifted[code as usize]
+        } else {
+            normal
+        };
+
+        if m.ctrl() {
+            c = match c {
+                b'a'..=b'z' | b'A'..=b'Z' => c & 0x1F,
+                b'@' | b' ' | b'2' => 0,
+                b'[' => 0x1B,
+                b'\\' => 0x1C,
+                b']' => 0x1D,
+                b'^' | b'6' => 0x1E,
+                b'_' | b'-' => 0x1F,
+                b'?' | b'/' => 0x7F,
+                _ => c,
+            };
+        }
+
+        // meta sends an escape prefix
+        if m.alt() {
+            out.push(0x1B);
+        }
+        out.push(c);
+    }
+}
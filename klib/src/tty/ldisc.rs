@@ -0,0 +1,266 @@
+//! the line discipline: cooks raw input into lines and decides what gets echoed.
+
+use alloc::{collections::vec_deque::VecDeque, vec::Vec};
+
+/// ^C
+pub const VINTR: u8 = 0x03;
+/// ^D
+pub const VEOF: u8 = 0x04;
+/// ^H
+pub const VERASE_BS: u8 = 0x08;
+/// ^U
+pub const VKILL: u8 = 0x15;
+/// ^W
+pub const VWERASE: u8 = 0x17;
+/// DEL, what most terminals send for backspace
+pub const VERASE: u8 = 0x7F;
+
+/// longest line canonical mode will hold, further input is dropped
+const MAX_LINE: usize = 4096;
+/// input buffered for readers before new input is dropped
+const MAX_PENDING: usize = 16 * 1024;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Termios {
+    /// hand input to readers a line at a time, with line editing
+    pub canonical: bool,
+    pub echo: bool,
+    /// ^C raises an interrupt instead of being passed through
+    pub isig: bool,
+    /// translate CR to NL on input
+    pub icrnl: bool,
+    /// translate NL to CR NL on output
+    pub onlcr: bool,
+}
+
+impl Default for Termios {
+    fn default() -> Self {
+        Self {
+            canonical: true,
+            echo: true,
+            isig: true,
+            icrnl: true,
+            onlcr: true,
+        }
+    }
+}
+
+/// what the terminal should do after a byte was processed
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Event {
+    /// nothing for readers yet
+    None,
+    /// input became readable
+    Readable,
+    /// ^C: the line was discarded
+    Interrupt,
+}
+
+#[derive(Debug)]
+pub struct LineDiscipline {
+    termios: Termios,
+    /// the line being edited
+    line: Vec<u8>,
+    /// finished lines. an empty one is end of file.
+    lines: VecDeque<Vec<u8>>,
+    /// raw-mode input
+    raw: VecDeque<u8>,
+    /// bytes buffered across `lines` and `raw`
+    pending: usize,
+}
+
+fn echo_char(byte: u8, echo: &mut Vec<u8>) {
+    match byte {
+        b'\n' => echo.extend_from_slice(b"\r\n"),
+        b'\t' => echo.push(b'\t'),
+        0..0x20 => echo.extend_from_slice(&[b'^', byte + b'@']),
+        VERASE => echo.extend_from_slice(b"^?"),
+        _ => echo.push(byte),
+    }
+}
+
+/// columns `byte` took when it was echoed
+fn echo_width(byte: u8) -> usize {
+    match byte {
+        0..0x20 | VERASE => 2,
+        _ => 1,
+    }
+}
+
+impl LineDiscipline {
+    pub const fn new() -> Self {
+        Self {
+            termios: Termios {
+                canonical: true,
+                echo: true,
+                isig: true,
+                icrnl: true,
+                onlcr: true,
+            },
+            line: Vec::new(),
+            lines: VecDeque::new(),
+            raw: VecDeque::new(),
+            pending: 0,
+        }
+    }
+
+    pub fn termios(&self) -> Termios {
+        self.termios
+    }
+
+    /// switching out of canonical mode hands over the partial line as raw input
+    pub fn set_termios(&mut self, termios: Termios) {
+        if self.termios.canonical && !termios.canonical {
+            for line in self.lines.drain(..) {
+                self.raw.extend(line);
+            }
+            // finished lines are already counted, the partial one isn't
+            self.pending += self.line.len();
+            self.raw.extend(self.line.drain(..));
+        }
+
+        self.termios = termios;
+    }
+
+    pub fn is_readable(&self) -> bool {
+        !self.lines.is_empty() || !self.raw.is_empty()
+    }
+
+    /// drop everything typed but not yet read
+    pub fn flush_input(&mut self) {
+        self.line.clear();
+        self.lines.clear();
+        self.raw.clear();
+        self.pending = 0;
+    }
+
+    fn erase(&mut self, echo: &mut Vec<u8>) -> bool {
+        let Some(byte) = self.line.pop() else {
+            return false;
+        };
+
+        if self.termios.echo {
+            for _ in 0..echo_width(byte) {
+                echo.extend_from_slice(b"\x08 \x08");
+            }
+        }
+        true
+    }
+
+    fn finish_line(&mut self) {
+        let line = core::mem::take(&mut self.line);
+        self.pending += line.len();
+        self.lines.push_back(line);
+    }
+
+    /// feed one received byte, collecting whatever should be echoed into `echo`
+    pub fn input(&mut self, mut byte: u8, echo: &mut Vec<u8>) -> Event {
+        if byte == b'\r' && self.termios.icrnl {
+            byte = b'\n';
+        }
+
+        if byte == VINTR && self.termios.isig {
+            self.flush_input();
+            if self.termios.echo {
+                echo.extend_from_slice(b"^C\r\n");
+            }
+            return Event::Interrupt;
+        }
+
+        if !self.termios.canonical {
+            if self.pending >= MAX_PENDING {
+                return Event::None;
+            }
+
+            self.raw.push_back(byte);
+            self.pending += 1;
+            if self.termios.echo {
+                echo_char(byte, echo);
+            }
+            return Event::Readable;
+        }
+
+        match byte {
+            VERASE | VERASE_BS => {
+                self.erase(echo);
+                Event::None
+            }
+            VKILL => {
+                while self.erase(echo) {}
+                Event::None
+            }
+            VWERASE => {
+                while self.line.last().is_some_and(|b| b.is_ascii_whitespace()) {
+                    self.erase(echo);
+                }
+                while self.line.last().is_some_and(|b| !b.is_ascii_whitespace()) {
+                    self.erase(echo);
+                }
+                Event::None
+            }
+            VEOF => {
+                // an empty line reads as end of file
+                self.finish_line();
+                Event::Readable
+            }
+            b'\n' => {
+                if self.termios.echo {
+                    echo_char(byte, echo);
+                }
+                self.line.push(byte);
+                self.finish_line();
+                Event::Readable
+            }
+            _ => {
+                if self.line.len() >= MAX_LINE || self.pending + self.line.len() >= MAX_PENDING {
+                    return Event::None;
+                }
+
+                if self.termios.echo {
+                    echo_char(byte, echo);
+                }
+                self.line.push(byte);
+                Event::None
+            }
+        }
+    }
+
+    /// copy out pending input. canonical reads stop at the end of a line.
+    /// `Some(0)` is end of file, `None` means nothing is available yet.
+    pub fn read(&mut self, buf: &mut [u8]) -> Option<usize> {
+        if !self.termios.canonical {
+            if self.raw.is_empty() {
+                return None;
+            }
+
+            let n = buf.len().min(self.raw.len());
+            for (dst, src) in buf.iter_mut().zip(self.raw.drain(..n)) {
+                *dst = src;
+            }
+            self.pending -= n;
+            return Some(n);
+        }
+
+        let line = self.lines.front_mut()?;
+        if line.is_empty() {
+            self.lines.pop_front();
+            return Some(0);
+        }
+
+        let n = buf.len().min(line.len());
+        buf[..n].copy_from_slice(&line[..n]);
+        line.drain(..n);
+        self.pending -= n;
+
+        if line.is_empty() {
+            self.lines.pop_front();
+        }
+        Some(n)
+    }
+}
+
+impl Default for LineDiscipline {
+    fn default() -> Self {
+        Self::new()
+    }
+}
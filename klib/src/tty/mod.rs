@@ -0,0 +1,298 @@
+//! terminals: a line discipline between a character device and whoever reads it.
+//!
+//! drivers implement [`TtyDriver`] for output and push received bytes into [`Tty::receive`],
+//! usually from their RX interrupt. readers block in [`Tty::read`] until a line is ready.
+//! keyboards feed the console the same way, through [`keyboard::Keyboard`].
+
+pub mod keyboard;
+pub mod ldisc;
+
+use core::{
+    fmt::{self, Display},
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+
+use alloc::{
+    string::String,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+
+use crate::{
+    fbcon,
+    guard::InterruptGuard,
+    scheduler::GLOBAL_SCHEDULER,
+    sync::{RwLock, UnfairSpinlock, WaitQueue},
+    vfs::{self, FileType, VfsError, inode::InodeOperations},
+};
+use ldisc::{Event, LineDiscipline, Termios};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TtyError {
+    /// the read was cut short by ^C
+    Interrupted,
+    /// the device behind the terminal went away
+    Hangup,
+}
+
+impl Display for TtyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Interrupted => f.write_str("interrupted"),
+            Self::Hangup => f.write_str("terminal hung up"),
+        }
+    }
+}
+
+impl core::error::Error for TtyError {}
+
+pub type Result<T> = core::result::Result<T, TtyError>;
+
+/// the hardware side of a terminal
+pub trait TtyDriver: Send + Sync {
+    /// queue output without blocking. returns how many bytes were accepted.
+    fn write(&self, bytes: &[u8]) -> usize;
+}
+
+pub struct Tty {
+    name: String,
+    driver: Weak<dyn TtyDriver>,
+    ldisc: UnfairSpinlock<LineDiscipline>,
+    readers: WaitQueue<'static>,
+    writers: WaitQueue<'static>,
+    /// bumped on every ^C so blocked readers can tell they were interrupted
+    interrupts: AtomicUsize,
+    /// output is also drawn on the framebuffer console. set while this is the console, so what's
+    /// typed on a keyboard shows up on screen.
+    on_screen: AtomicBool,
+}
+
+impl Tty {
+    pub fn new(name: impl Into<String>, driver: Weak<dyn TtyDriver>) -> Self {
+        Self {
+            name: name.into(),
+            driver,
+            ldisc: UnfairSpinlock::new(LineDiscipline::new()),
+            readers: WaitQueue::new(),
+            writers: WaitQueue::new(),
+            interrupts: AtomicUsize::new(0),
+            on_screen: AtomicBool::new(false),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn termios(&self) -> Termios {
+        let _irq = InterruptGuard::new();
+        self.ldisc.lock().termios()
+    }
+
+    pub fn set_termios(&self, termios: Termios) {
+        {
+            let _irq = InterruptGuard::new();
+            self.ldisc.lock().set_termios(termios);
+        }
+        self.readers.wake_all(&GLOBAL_SCHEDULER);
+    }
+
+    /// feed received bytes through the line discipline. safe to call from IRQ context.
+    pub fn receive(&self, bytes: &[u8]) {
+        let mut echo = Vec::new();
+        let mut readable = false;
+        let mut interrupted = false;
+
+        {
+            let _irq = InterruptGuard::new();
+            let mut ldisc = self.ldisc.lock();
+
+            for &byte in bytes {
+                match ldisc.input(byte, &mut echo) {
+                    Event::None => {}
+                    Event::Readable => readable = true,
+                    Event::Interrupt => interrupted = true,
+                }
+            }
+        }
+
+        if !echo.is_empty() {
+            self.mirror(&echo);
+            if let Some(driver) = self.driver.upgrade() {
+                // echo is best effort, it's dropped if the output buffer is full
+                driver.write(&echo);
+            }
+        }
+
+        if interrupted {
+            self.interrupts.fetch_add(1, Ordering::AcqRel);
+        }
+
+        if readable || interrupted {
+            self.readers.wake_all(&GLOBAL_SCHEDULER);
+        }
+    }
+
+    /// the driver drained some output, let blocked writers continue
+    pub fn write_space(&self) {
+        self.writers.wake_all(&GLOBAL_SCHEDULER);
+    }
+
+    pub fn is_readable(&self) -> bool {
+        let _irq = InterruptGuard::new();
+        self.ldisc.lock().is_readable()
+    }
+
+    /// wait for input. returns 0 at end of file (^D on an empty line).
+    pub fn read(&self, buf: &mut [u8]) -> Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let interrupts = self.interrupts.load(Ordering::Acquire);
+
+        loop {
+            let seen = self.readers.generation();
+
+            let read = {
+                let _irq = InterruptGuard::new();
+                self.ldisc.lock().read(buf)
+            };
+
+            if let Some(n) = read {
+                return Ok(n);
+            }
+
+            if self.interrupts.load(Ordering::Acquire) != interrupts {
+                return Err(TtyError::Interrupted);
+            }
+
+            if self.driver.strong_count() == 0 {
+                return Err(TtyError::Hangup);
+            }
+
+            self.readers.wait(&GLOBAL_SCHEDULER, seen);
+        }
+    }
+
+    /// write all of `buf`, sleeping while the driver's buffer is full
+    pub fn write(&self, buf: &[u8]) -> Result<usize> {
+        let onlcr = self.termios().onlcr;
+
+        for chunk in buf.split_inclusive(|&b| onlcr && b == b'\n') {
+            match chunk.split_last() {
+                Some((b'\n', text)) if onlcr => {
+                    self.write_all(text)?;
+                    self.write_all(b"\r\n")?;
+                }
+                _ => self.write_all(chunk)?,
+            }
+        }
+
+        Ok(buf.len())
+    }
+
+    fn mirror(&self, bytes: &[u8]) {
+        if self.on_screen.load(Ordering::Acquire) {
+            fbcon::write_bytes(bytes);
+        }
+    }
+
+    fn write_all(&self, mut bytes: &[u8]) -> Result<()> {
+        self.mirror(bytes);
+
+        while !bytes.is_empty() {
+            let driver = self.driver.upgrade().ok_or(TtyError::Hangup)?;
+            let seen = self.writers.generation();
+
+            let written = driver.write(bytes);
+            bytes = &bytes[written..];
+
+            if written == 0 {
+                self.writers.wait(&GLOBAL_SCHEDULER, seen);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Write for &Tty {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        Tty::write(self, s.as_bytes())
+            .map(|_| ())
+            .map_err(|_| fmt::Error)
+    }
+}
+
+static TTYS: RwLock<Vec<Arc<Tty>>> = RwLock::new(Vec::new());
+static CONSOLE: RwLock<Option<Arc<Tty>>> = RwLock::new(None);
+
+/// make a terminal visible by name and in devfs. the first one becomes the console.
+pub fn register_tty(tty: Arc<Tty>) {
+    use log::*;
+
+    info!("tty: registered {}", tty.name());
+
+    vfs::devfs::register(
+        tty.name(),
+        FileType::Normal,
+        Arc::new(TtyInode(tty.clone())),
+    );
+    TTYS.write().push(tty.clone());
+
+    let mut console = CONSOLE.write();
+    if console.is_none() {
+        tty.on_screen.store(true, Ordering::Release);
+        *console = Some(tty);
+    }
+}
+
+pub fn get_tty(name: &str) -> Option<Arc<Tty>> {
+    TTYS.read().iter().find(|t| t.name() == name).cloned()
+}
+
+pub fn console() -> Option<Arc<Tty>> {
+    CONSOLE.read().clone()
+}
+
+pub fn set_console(tty: Arc<Tty>) {
+    let mut console = CONSOLE.write();
+    if let Some(old) = console.as_ref() {
+        old.on_screen.store(false, Ordering::Release);
+    }
+    tty.on_screen.store(true, Ordering::Release);
+    *console = Some(tty);
+}
+
+/// a terminal as a file. offsets are meaningless and ignored.
+pub struct TtyInode(pub Arc<Tty>);
+
+impl InodeOperations for TtyInode {
+    fn read_at(&self, _offset: u64, buffer: &mut [u8]) -> vfs::Result<u64> {
+        match self.0.read(buffer) {
+            Ok(n) => Ok(n as u64),
+            Err(TtyError::Interrupted) => Err(VfsError::Interrupted),
+            Err(TtyError::Hangup) => Err(VfsError::Io),
+        }
+    }
+
+    fn write_at(&self, _offset: u64, buffer: &[u8]) -> vfs::Result<u64> {
+        self.0
+            .write(buffer)
+            .map(|n| n as u64)
+            .map_err(|_| VfsError::Io)
+    }
+
+    fn lookup_child(&self, _name: &str) -> vfs::Result<Arc<vfs::inode::Inode>> {
+        Err(VfsError::NotADirectory)
+    }
+
+    fn create(&self, _name: &str, _file_type: FileType) -> vfs::Result<Arc<vfs::inode::Inode>> {
+        Err(VfsError::NotADirectory)
+    }
+
+    fn truncate(&self, _size: u64) -> vfs::Result<()> {
+        Err(VfsError::PermissionDenied)
+    }
+}
@@ -0,0 +1,84 @@
+//! a flat directory of device nodes, for mounting at `/dev`.
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use alloc::{collections::btree_map::BTreeMap, string::String, sync::Arc, vec::Vec};
+
+use super::{
+    FileType, Result, VfsError,
+    inode::{Dirent, Inode, InodeOperations},
+};
+use crate::sync::RwLock;
+
+static NODES: RwLock<BTreeMap<String, Arc<Inode>>> = RwLock::new(BTreeMap::new());
+/// 1 is the directory itself
+static NEXT_INODE: AtomicU64 = AtomicU64::new(2);
+
+/// publish a device under `name`. replaces any previous node with that name.
+pub fn register(
+    name: &str,
+    file_type: FileType,
+    operations: Arc<dyn InodeOperations + Send + Sync>,
+) {
+    let inode = Arc::new(Inode {
+        number: NEXT_INODE.fetch_add(1, Ordering::Relaxed),
+        file_type,
+        size: AtomicU64::new(0),
+        operations,
+    });
+
+    NODES.write().insert(name.into(), inode);
+}
+
+pub fn unregister(name: &str) {
+    NODES.write().remove(name);
+}
+
+pub fn get(name: &str) -> Option<Arc<Inode>> {
+    NODES.read().get(name).cloned()
+}
+
+/// the devfs directory inode
+pub fn root() -> Arc<Inode> {
+    Arc::new(Inode {
+        number: 1,
+        file_type: FileType::Directory,
+        size: AtomicU64::new(0),
+        operations: Arc::new(DevFsRoot),
+    })
+}
+
+struct DevFsRoot;
+
+impl InodeOperations for DevFsRoot {
+    fn read_at(&self, _offset: u64, _buffer: &mut [u8]) -> Result<u64> {
+        Err(VfsError::IsADirectory)
+    }
+
+    fn write_at(&self, _offset: u64, _buffer: &[u8]) -> Result<u64> {
+        Err(VfsError::IsADirectory)
+    }
+
+    fn lookup_child(&self, name: &str) -> Result<Arc<Inode>> {
+        get(name).ok_or(VfsError::NotFound)
+    }
+
+    fn create(&self, _name: &str, _file_type: FileType) -> Result<Arc<Inode>> {
+        Err(VfsError::PermissionDenied)
+    }
+
+    fn truncate(&self, _size: u64) -> Result<()> {
+        Err(VfsError::IsADirectory)
+    }
+
+    fn readdir(&self) -> Result<Vec<Dirent>> {
+        Ok(NODES
+            .read()
+            .iter()
+            .map(|(name, inode)| Dirent {
+                name: name.clone(),
+                file_type: inode.file_type,
+            })
+            .collect())
+    }
+}
@@ -1,8 +1,11 @@
 use core::sync::atomic::{AtomicU64, Ordering};
 
-use alloc::sync::Arc;
+use alloc::{sync::Arc, vec::Vec};
 
-use super::{Result, VfsError, inode::DirEntry};
+use super::{
+    Result, VfsError,
+    inode::{DirEntry, Dirent},
+};
 use crate::sync::RwLock;
 
 pub struct File {
@@ -63,4 +66,12 @@ impl File {
 
         Ok(bytes_count)
     }
+
+    pub fn readdir(&self) -> Result<Vec<Dirent>> {
+        if !self.readable {
+            return Err(VfsError::PermissionDenied);
+        }
+
+        self.dir_entry.read().inode.operations.readdir()
+    }
 }
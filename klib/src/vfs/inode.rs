@@ -4,11 +4,12 @@ use alloc::{
     collections::btree_map::BTreeMap,
     string::String,
     sync::{Arc, Weak},
+    vec::Vec,
 };
 
 use crate::sync::RwLock;
 
-use super::{FileType, Result};
+use super::{FileType, Result, VfsError};
 
 pub struct Inode {
     pub number: u64,
@@ -23,6 +24,18 @@ pub trait InodeOperations {
     fn lookup_child(&self, name: &str) -> Result<Arc<Inode>>;
     fn create(&self, name: &str, file_type: FileType) -> Result<Arc<Inode>>;
     fn truncate(&self, size: u64) -> Result<()>;
+
+    /// list a directory, without `.` and `..`
+    fn readdir(&self) -> Result<Vec<Dirent>> {
+        Err(VfsError::NotADirectory)
+    }
+}
+
+/// one entry of a directory listing
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Dirent {
+    pub name: String,
+    pub file_type: FileType,
 }
 
 /// cache for lookups
@@ -1,11 +1,18 @@
-use alloc::{string::ToString, sync::Arc};
+use core::fmt::{self, Display};
+
+use alloc::{
+    string::ToString,
+    sync::{Arc, Weak},
+};
 
 use crate::sync::RwLock;
 use file::File;
-use inode::DirEntry;
+use inode::{DirEntry, Inode};
 
+pub mod devfs;
 pub mod file;
 pub mod inode;
+pub mod mount;
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum VfsError {
@@ -16,8 +23,27 @@ pub enum VfsError {
     PermissionDenied,
     Io,
     OutOfSpace,
+    /// a blocking operation was interrupted, e.g. by ^C on a terminal
+    Interrupted,
+}
+
+impl Display for VfsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotFound => f.write_str("no such file or directory"),
+            Self::ExistsAlready => f.write_str("file exists"),
+            Self::NotADirectory => f.write_str("not a directory"),
+            Self::IsADirectory => f.write_str("is a directory"),
+            Self::PermissionDenied => f.write_str("permission denied"),
+            Self::Io => f.write_str("I/O error"),
+            Self::OutOfSpace => f.write_str("no space left on device"),
+            Self::Interrupted => f.write_str("interrupted"),
+        }
+    }
 }
 
+impl core::error::Error for VfsError {}
+
 pub type Result<T> = core::result::Result<T, VfsError>;
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
@@ -31,6 +57,12 @@ pub struct Vfs {
 }
 
 impl Vfs {
+    pub fn new(root: Arc<Inode>) -> Self {
+        Self {
+            root: Arc::new(RwLock::new(DirEntry::new("/".into(), root, Weak::new()))),
+        }
+    }
+
     pub fn lookup(
         &self,
         path: &str,
@@ -0,0 +1,91 @@
+//! the root directory, made of whatever filesystems are mounted on it.
+//!
+//! mount points are single names directly below `/`, e.g. `dev` for devfs. a filesystem is just
+//! the inode of its root directory.
+
+use core::sync::atomic::AtomicU64;
+
+use alloc::{collections::btree_map::BTreeMap, string::String, sync::Arc, vec::Vec};
+
+use super::{
+    FileType, Result, VfsError,
+    inode::{Dirent, Inode, InodeOperations},
+};
+use crate::sync::RwLock;
+
+static MOUNTS: RwLock<BTreeMap<String, Arc<Inode>>> = RwLock::new(BTreeMap::new());
+
+/// make the directory `root` appear as `/<name>`
+pub fn mount(name: &str, root: Arc<Inode>) -> Result<()> {
+    use log::*;
+
+    if name.is_empty() || name.contains('/') || name == "." || name == ".." {
+        return Err(VfsError::NotFound);
+    }
+    if root.file_type != FileType::Directory {
+        return Err(VfsError::NotADirectory);
+    }
+
+    let mut mounts = MOUNTS.write();
+    if mounts.contains_key(name) {
+        return Err(VfsError::ExistsAlready);
+    }
+
+    info!("vfs: mounted /{name}");
+    mounts.insert(name.into(), root);
+    Ok(())
+}
+
+/// lookups already cached by a `Vfs` keep working until they're dropped
+pub fn unmount(name: &str) -> Result<()> {
+    MOUNTS
+        .write()
+        .remove(name)
+        .map(|_| ())
+        .ok_or(VfsError::NotFound)
+}
+
+/// the root directory inode
+pub fn root() -> Arc<Inode> {
+    Arc::new(Inode {
+        number: 1,
+        file_type: FileType::Directory,
+        size: AtomicU64::new(0),
+        operations: Arc::new(MountRoot),
+    })
+}
+
+struct MountRoot;
+
+impl InodeOperations for MountRoot {
+    fn read_at(&self, _offset: u64, _buffer: &mut [u8]) -> Result<u64> {
+        Err(VfsError::IsADirectory)
+    }
+
+    fn write_at(&self, _offset: u64, _buffer: &[u8]) -> Result<u64> {
+        Err(VfsError::IsADirectory)
+    }
+
+    fn lookup_child(&self, name: &str) -> Result<Arc<Inode>> {
+        MOUNTS.read().get(name).cloned().ok_or(VfsError::NotFound)
+    }
+
+    fn create(&self, _name: &str, _file_type: FileType) -> Result<Arc<Inode>> {
+        Err(VfsError::PermissionDenied)
+    }
+
+    fn truncate(&self, _size: u64) -> Result<()> {
+        Err(VfsError::IsADirectory)
+    }
+
+    fn readdir(&self) -> Result<Vec<Dirent>> {
+        Ok(MOUNTS
+            .read()
+            .keys()
+            .map(|name| Dirent {
+                name: name.clone(),
+                file_type: FileType::Directory,
+            })
+            .collect())
+    }
+}
@@ -17,12 +17,59 @@ pub struct BootInfo {
     /// the TTBR0 that the kernel should load, if any
     pub page_table_root: Option<*const TTable<TABLE_ENTRIES>>,
 
-    /// serial uart
-    pub serial_uart_address: usize,
+    /// serial port for the early console
+    pub serial_console: SerialConsole,
 
     /// UEFI memory map
     pub memory_map: MemoryMapOwned,
 
     /// UEFI system table
     pub system_table_raw: NonNull<SystemTable>,
+
+    /// physical address of a flattened device tree handed over in a register, if we weren't
+    /// loaded by UEFI. otherwise it's found through the configuration table.
+    pub device_tree: Option<usize>,
+
+    /// bytes from `EFI_RNG_PROTOCOL`, if the firmware has it
+    pub rng_seed: Option<[u8; RNG_SEED_LEN]>,
+}
+
+pub const RNG_SEED_LEN: usize = 32;
+
+/// the firmware's console UART, from SPCR or DBG2
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SerialConsole {
+    pub kind: SerialKind,
+    /// physical address of the registers
+    pub address: usize,
+    /// line settings to program, or `None` to keep what firmware set up
+    pub line: Option<SerialLine>,
+}
+
+impl SerialConsole {
+    /// the PL011 of QEMU's virt machine, for when firmware doesn't say
+    pub const QEMU_VIRT: Self = Self {
+        kind: SerialKind::Pl011,
+        address: 0x0900_0000,
+        line: None,
+    };
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SerialKind {
+    Pl011,
+    /// the PL011 subset from the Server Base System Architecture. it can't be configured.
+    SbsaGeneric,
+    /// 16550 compatible, with registers `register_width` bytes apart
+    Ns16550 {
+        register_width: u8,
+    },
+}
+
+/// always 8 data bits, no parity and 1 stop bit: the only format SPCR can describe
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SerialLine {
+    pub baud: u32,
+    /// input clock in Hz, to derive the divisor from
+    pub clock: u32,
 }
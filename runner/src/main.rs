@@ -68,6 +68,7 @@ fn main() -> Result<()> {
     let qemu_status = Command::new("qemu-system-aarch64")
         .args([
             "-M",
+            // add ",acpi=off" to boot from the device tree instead
             "virt,gic-version=3,its=on",
             "-accel",
             "tcg",
@@ -87,6 +88,24 @@ fn main() -> Result<()> {
             //"stdio",
             "-device",
             "virtio-gpu-pci",
+            "-device",
+            "virtio-keyboard-pci",
+            "-device",
+            "virtio-rng-pci",
+            "-device",
+            "qemu-xhci,id=xhci",
+            "-device",
+            "usb-kbd,bus=xhci.0",
+            //"-drive",
+            //"if=none,id=usbdisk,format=raw,file=usb.img",
+            //"-device",
+            //"usb-storage,bus=xhci.0,drive=usbdisk",
+            "-virtfs",
+            "local,path=.,mount_tag=host,security_model=none,readonly=on",
+            "-netdev",
+            "user,id=net0",
+            "-device",
+            "virtio-net-pci,netdev=net0",
             "-smp",
             "8",
             //"-D",